@@ -1,7 +1,7 @@
 //! Configuration structures and parsing for avalon
 
 use serde::{Deserialize, Serialize};
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
 use std::path::{Path, PathBuf};
 use thiserror::Error;
 
@@ -16,6 +16,9 @@ pub enum ConfigError {
 
     #[error("Validation error: {0}")]
     Validation(String),
+
+    #[error("Serialization error: {0}")]
+    Serialize(String),
 }
 
 /// Root configuration structure
@@ -111,7 +114,7 @@ impl Config {
     /// This allows users to use either:
     /// - Standard format: [[servers]] with routes
     /// - Simplified format: [http] with [[routes]]
-    fn normalize(&mut self) {
+    pub(crate) fn normalize(&mut self) {
         // If simplified http config is present and no servers defined
         if let Some(http_config) = &self.http {
             if self.servers.is_empty() && !self.routes.is_empty() {
@@ -134,8 +137,19 @@ impl Config {
                             host: None,
                             method: None,
                             header: None,
+                            path_not: None,
+                            user_agent: None,
+                            client_cert: None,
+                            client_cert_organization: None,
+                            any_of: None,
                         },
                         handle: simple.handler.clone(),
+                        trailing_slash: TrailingSlash::Strict,
+                        access_log: None,
+                        fault_injection: None,
+                        response_hints: Vec::new(),
+                        block_user_agents: Vec::new(),
+                        block_user_agents_status: 403,
                     }
                 }).collect();
 
@@ -145,6 +159,9 @@ impl Config {
                     listen: vec![http_config.bind.clone()],
                     routes,
                     https_redirect: false,
+                    http2: Http2Config::default(),
+                    access_control: None,
+                    unix_socket_mode: None,
                 };
 
                 self.servers.push(server);
@@ -154,6 +171,92 @@ impl Config {
 
     /// Validate the configuration
     pub fn validate(&self) -> Result<(), ConfigError> {
+        // Guard against pathological (usually generated) configs
+        if self.servers.len() > self.global.max_servers {
+            return Err(ConfigError::Validation(format!(
+                "Config has {} servers, more than global.max_servers ({})",
+                self.servers.len(),
+                self.global.max_servers
+            )));
+        }
+        let route_count: usize = self.servers.iter().map(|s| s.routes.len()).sum();
+        if route_count > self.global.max_routes {
+            return Err(ConfigError::Validation(format!(
+                "Config has {} routes, more than global.max_routes ({})",
+                route_count, self.global.max_routes
+            )));
+        }
+
+        for hint in &self.global.response_hints {
+            validate_link_hint(hint)?;
+        }
+
+        let status_header = &self.global.cache.status_header;
+        if !status_header.is_empty() && !is_header_name(status_header) {
+            return Err(ConfigError::Validation(format!(
+                "global.cache.status_header {:?} is not a valid header name",
+                status_header
+            )));
+        }
+
+        for name in &self.global.correlation_headers {
+            if !is_header_name(name) {
+                return Err(ConfigError::Validation(format!(
+                    "global.correlation_headers entry {:?} is not a valid header name",
+                    name
+                )));
+            }
+        }
+
+        if let Some(drain_timeout) = self.global.drain_timeout {
+            if drain_timeout < self.global.grace_period {
+                return Err(ConfigError::Validation(format!(
+                    "global.drain_timeout ({}) must not be shorter than grace_period ({})",
+                    drain_timeout, self.global.grace_period
+                )));
+            }
+        }
+
+        let compression = &self.global.compression;
+        for (name, level) in [
+            ("brotli_dynamic_level", compression.brotli_dynamic_level),
+            ("brotli_static_level", compression.brotli_static_level),
+        ] {
+            if level.is_some_and(|level| level > 11) {
+                return Err(ConfigError::Validation(format!(
+                    "global.compression.{} must be between 0 and 11",
+                    name
+                )));
+            }
+        }
+
+        let security_headers = &self.global.security_headers;
+        if security_headers.csp_nonce.is_some()
+            && !security_headers.content_security_policy.as_ref().is_some_and(|csp| csp.contains("{nonce}"))
+        {
+            return Err(ConfigError::Validation(
+                "security_headers.csp_nonce needs a content_security_policy containing {nonce}".to_string(),
+            ));
+        }
+
+        let metrics = &self.global.metrics;
+        if let Some(addr) = &metrics.statsd_addr {
+            let has_port = addr
+                .rsplit_once(':')
+                .is_some_and(|(host, port)| !host.is_empty() && port.parse::<u16>().is_ok());
+            if !has_port {
+                return Err(ConfigError::Validation(format!(
+                    "metrics.statsd_addr {:?} must be host:port",
+                    addr
+                )));
+            }
+            if metrics.statsd_flush_interval == 0 {
+                return Err(ConfigError::Validation(
+                    "metrics.statsd_flush_interval must be at least 1 second".to_string(),
+                ));
+            }
+        }
+
         // Check that servers have listen addresses
         for server in &self.servers {
             if server.listen.is_empty() {
@@ -162,15 +265,227 @@ impl Config {
                     server.name
                 )));
             }
+            for listen in &server.listen {
+                let Some(path) = unix_socket_path(listen) else {
+                    continue;
+                };
+                if path.as_os_str().is_empty() || path.to_string_lossy().starts_with('@') {
+                    return Err(ConfigError::Validation(format!(
+                        "Server '{}' listen address {:?} must name a socket file; abstract sockets aren't supported",
+                        server.name, listen
+                    )));
+                }
+            }
+            if server.unix_socket_mode.is_some_and(|mode| mode > 0o777) {
+                return Err(ConfigError::Validation(format!(
+                    "Server '{}' unix_socket_mode must be a permission mode such as 0o660",
+                    server.name
+                )));
+            }
+
+            // RFC 9113 Section 6.5.2 bounds
+            let http2 = &server.http2;
+            if http2.max_concurrent_streams == 0 {
+                return Err(ConfigError::Validation(format!(
+                    "Server '{}' http2.max_concurrent_streams must be at least 1",
+                    server.name
+                )));
+            }
+            if http2.max_frame_size.is_some_and(|size| !(16_384..=16_777_215).contains(&size)) {
+                return Err(ConfigError::Validation(format!(
+                    "Server '{}' http2.max_frame_size must be between 16384 and 16777215",
+                    server.name
+                )));
+            }
+            if http2.initial_window_size.is_some_and(|size| size > i32::MAX as u32) {
+                return Err(ConfigError::Validation(format!(
+                    "Server '{}' http2.initial_window_size must be at most 2147483647",
+                    server.name
+                )));
+            }
+
+            if let Some(access) = &server.access_control {
+                validate_access_control(&server.name, access)?;
+            }
 
             // Check that reverse_proxy routes have upstreams
             for route in &server.routes {
+                if let HandlerConfig::StaticResponse(static_config) = &route.handle {
+                    if static_config.body_size.is_some_and(|size| size > MAX_STATIC_BODY_SIZE) {
+                        return Err(ConfigError::Validation(format!(
+                            "Server '{}' static_response body_size exceeds {} bytes",
+                            server.name, MAX_STATIC_BODY_SIZE
+                        )));
+                    }
+                    if static_config.delay_ms > MAX_STATIC_DELAY_MS {
+                        return Err(ConfigError::Validation(format!(
+                            "Server '{}' static_response delay_ms exceeds {} ms",
+                            server.name, MAX_STATIC_DELAY_MS
+                        )));
+                    }
+                }
+
                 if let HandlerConfig::ReverseProxy(proxy_config) = &route.handle {
                     if proxy_config.upstreams.is_empty() {
                         return Err(ConfigError::Validation(
                             "Reverse proxy handler has no upstreams".to_string(),
                         ));
                     }
+
+                    if let Some(canary) = &proxy_config.canary {
+                        if canary.upstreams.is_empty() {
+                            return Err(ConfigError::Validation(format!(
+                                "Canary '{}' has no upstreams",
+                                canary.flag
+                            )));
+                        }
+                        if canary.default_weight > 100 {
+                            return Err(ConfigError::Validation(format!(
+                                "Canary '{}' default_weight must be between 0 and 100",
+                                canary.flag
+                            )));
+                        }
+                    }
+
+                    if proxy_config.request_buffer_limit > MAX_REQUEST_BUFFER_LIMIT {
+                        return Err(ConfigError::Validation(format!(
+                            "request_buffer_limit ({}) exceeds {} bytes, the most a body can be replayed",
+                            proxy_config.request_buffer_limit, MAX_REQUEST_BUFFER_LIMIT
+                        )));
+                    }
+
+                    if proxy_config.max_request_body_size > 0
+                        && proxy_config.request_buffer_limit > proxy_config.max_request_body_size
+                    {
+                        return Err(ConfigError::Validation(format!(
+                            "request_buffer_limit ({}) exceeds max_request_body_size ({})",
+                            proxy_config.request_buffer_limit, proxy_config.max_request_body_size
+                        )));
+                    }
+
+                    if let HostHeader::Literal(host) = &proxy_config.host_header {
+                        if host.is_empty() || host.chars().any(|c| c.is_control() || c.is_whitespace()) {
+                            return Err(ConfigError::Validation(format!(
+                                "host_header {:?} must be \"preserve\", \"upstream\" or a host name",
+                                host
+                            )));
+                        }
+                    }
+
+                    if !(0.0..=1.0).contains(&proxy_config.lb_try_jitter) {
+                        return Err(ConfigError::Validation(format!(
+                            "lb_try_jitter {} must be between 0.0 and 1.0",
+                            proxy_config.lb_try_jitter
+                        )));
+                    }
+
+                    let canary_rewrite = proxy_config.canary.as_ref().and_then(|c| c.rewrite.as_ref());
+                    for rewrite in proxy_config.rewrite.iter().chain(canary_rewrite) {
+                        validate_status_map(&rewrite.status_map)?;
+                        validate_body_replace(rewrite)?;
+                    }
+
+                    if let Some(affinity) = proxy_config.session_affinity.as_ref().filter(|a| a.affinity_type == "jwt_claim") {
+                        if affinity.claim.as_deref().is_none_or(str::is_empty) {
+                            return Err(ConfigError::Validation(
+                                "session_affinity jwt_claim requires a claim name".to_string(),
+                            ));
+                        }
+                        // Only claims of a verified token may steer requests
+                        let jwt_secret = proxy_config.auth.as_ref().and_then(|a| a.jwt.as_ref()?.secret.as_ref());
+                        if jwt_secret.is_none() {
+                            return Err(ConfigError::Validation(
+                                "session_affinity jwt_claim requires auth.jwt with a secret on the route".to_string(),
+                            ));
+                        }
+                    }
+
+                    if let Some(rate_limit) = &proxy_config.rate_limit {
+                        if rate_limit.max_requests == 0 || rate_limit.window == 0 || rate_limit.max_tracked_clients == 0 {
+                            return Err(ConfigError::Validation(
+                                "rate_limit max_requests, window and max_tracked_clients must be greater than 0".to_string(),
+                            ));
+                        }
+                        if !(400..=599).contains(&rate_limit.reject_status) {
+                            return Err(ConfigError::Validation(format!(
+                                "rate_limit reject_status {} must be a 4xx or 5xx status",
+                                rate_limit.reject_status
+                            )));
+                        }
+                    }
+
+                    if let Some(secret) = proxy_config.auth.as_ref().and_then(|a| a.jwt.as_ref()?.secret.as_ref()) {
+                        expand_env(secret)?;
+                    }
+
+                    if let Some(upstream_auth) = &proxy_config.upstream_auth {
+                        match (&upstream_auth.basic, &upstream_auth.bearer_token) {
+                            (Some(basic), None) => {
+                                expand_env(&basic.username)?;
+                                expand_env(&basic.password)?;
+                            }
+                            (None, Some(token)) => {
+                                expand_env(token)?;
+                            }
+                            _ => {
+                                return Err(ConfigError::Validation(
+                                    "upstream_auth needs exactly one of basic or bearer_token".to_string(),
+                                ));
+                            }
+                        }
+                    }
+
+                    if let Some(passive) = proxy_config.health_check.as_ref().and_then(|h| h.passive.as_ref()) {
+                        if !(0.0..=1.0).contains(&passive.max_error_rate) {
+                            return Err(ConfigError::Validation(
+                                "passive health check max_error_rate must be between 0.0 and 1.0".to_string(),
+                            ));
+                        }
+                    }
+
+                    if let Some(maintenance) = &proxy_config.maintenance {
+                        if !(100..=599).contains(&maintenance.status) {
+                            return Err(ConfigError::Validation(format!(
+                                "maintenance status {} is not a valid HTTP status",
+                                maintenance.status
+                            )));
+                        }
+                        if maintenance.bypass_secret.as_ref().is_some_and(|secret| secret.is_empty()) {
+                            return Err(ConfigError::Validation(
+                                "maintenance bypass_secret must not be empty".to_string(),
+                            ));
+                        }
+                    }
+                }
+
+                if let Some(fault) = &route.fault_injection {
+                    if !(0.0..=1.0).contains(&fault.probability) {
+                        return Err(ConfigError::Validation(
+                            "fault_injection probability must be between 0.0 and 1.0".to_string(),
+                        ));
+                    }
+                    if let Some(status) = fault.abort_status {
+                        if !(100..=599).contains(&status) {
+                            return Err(ConfigError::Validation(format!(
+                                "fault_injection abort_status {} is not a valid HTTP status",
+                                status
+                            )));
+                        }
+                    }
+                }
+
+                for hint in &route.response_hints {
+                    validate_link_hint(hint)?;
+                }
+
+                validate_any_of(&route.match_rule)?;
+                validate_client_cert_match(&route.match_rule, self.tls.client_ca.is_some())?;
+
+                if !matches!(route.block_user_agents_status, 403 | 429) {
+                    return Err(ConfigError::Validation(format!(
+                        "block_user_agents_status {} must be 403 or 429",
+                        route.block_user_agents_status
+                    )));
                 }
             }
         }
@@ -187,37 +502,232 @@ impl Config {
 
     /// Get all domains that need TLS certificates
     pub fn get_tls_domains(&self) -> Vec<String> {
-        let mut domains = Vec::new();
+        tls_domains(&self.servers)
+    }
 
-        for server in &self.servers {
-            // Check if server has HTTPS listeners
-            let has_https = server.listen.iter().any(|addr| {
-                addr.contains(":443") || addr.starts_with("https://")
-            });
+    /// Whether the global or any route access log is exported over OTLP
+    pub fn uses_otlp_access_log(&self) -> bool {
+        let global = self.global.access_log.as_deref() == Some(OTLP_ACCESS_LOG);
+        let route = self.servers.iter().flat_map(|s| &s.routes).any(|route| {
+            matches!(&route.access_log, Some(RouteAccessLog::Custom(custom)) if custom.path.as_deref() == Some(OTLP_ACCESS_LOG))
+        });
+        global || route
+    }
+}
 
-            if !has_https {
-                continue;
-            }
+/// Socket file of a `unix:/path/to.sock` listen address
+pub fn unix_socket_path(listen: &str) -> Option<&Path> {
+    listen.strip_prefix("unix:").map(Path::new)
+}
 
-            // Collect domains from routes
-            for route in &server.routes {
-                if let Some(hosts) = &route.match_rule.host {
-                    for host in hosts {
-                        // Skip wildcards and localhost
-                        if !host.starts_with('*') && host != "localhost" {
-                            domains.push(host.clone());
-                        }
+/// Route hosts of servers with HTTPS listeners, which need TLS certificates
+pub fn tls_domains(servers: &[ServerConfig]) -> Vec<String> {
+    let mut domains = Vec::new();
+
+    for server in servers {
+        // Check if server has HTTPS listeners
+        let has_https = server.listen.iter().any(|addr| {
+            addr.contains(":443") || addr.starts_with("https://")
+        });
+
+        if !has_https {
+            continue;
+        }
+
+        // Collect domains from routes
+        for route in &server.routes {
+            if let Some(hosts) = route.match_rule.candidate_hosts() {
+                for host in hosts {
+                    // Skip wildcards and localhost
+                    if !host.starts_with('*') && host != "localhost" {
+                        domains.push(host.clone());
                     }
                 }
             }
         }
+    }
+
+    domains.sort();
+    domains.dedup();
+    domains
+}
+
+/// Check that a resource hint is a `Link` value with a target and a `rel`
+fn validate_link_hint(hint: &str) -> Result<(), ConfigError> {
+    let valid = hint.starts_with('<')
+        && hint.find('>').is_some_and(|end| {
+            hint[end + 1..]
+                .split(';')
+                .skip(1)
+                .any(|param| param.trim().to_ascii_lowercase().starts_with("rel="))
+        })
+        && !hint.chars().any(|c| c.is_control());
+    if !valid {
+        return Err(ConfigError::Validation(format!(
+            "response_hints entry {:?} must look like `<url>; rel=preconnect`",
+            hint
+        )));
+    }
+    Ok(())
+}
+
+/// Whether `name` is a valid HTTP header field name (an RFC 9110 token)
+fn is_header_name(name: &str) -> bool {
+    !name.is_empty()
+        && name
+            .bytes()
+            .all(|b| b.is_ascii_alphanumeric() || b"!#$%&'*+-.^_`|~".contains(&b))
+}
+
+/// Check that access rules parse and that `require_auth` has credentials to check
+fn validate_access_control(server: &str, access: &AccessControlConfig) -> Result<(), ConfigError> {
+    let has_auth = access
+        .auth
+        .as_ref()
+        .is_some_and(|auth| !auth.basic.is_empty() || !auth.api_keys.is_empty() || auth.jwt.is_some());
+    if let Some(secret) = access.auth.as_ref().and_then(|a| a.jwt.as_ref()?.secret.as_ref()) {
+        expand_env(secret)?;
+    }
+    for rule in &access.rules {
+        if let Some(ip) = rule.ip.iter().find(|ip| !is_ip_or_cidr(ip)) {
+            return Err(ConfigError::Validation(format!(
+                "Server '{}' access_control ip {:?} is not an IP address or CIDR range",
+                server, ip
+            )));
+        }
+        if rule.action == AccessAction::RequireAuth && !has_auth {
+            return Err(ConfigError::Validation(format!(
+                "Server '{}' access_control uses require_auth but auth has no basic, api_keys or jwt",
+                server
+            )));
+        }
+    }
+    Ok(())
+}
+
+fn is_ip_or_cidr(value: &str) -> bool {
+    let (addr, prefix) = match value.split_once('/') {
+        Some((addr, prefix)) => (addr, Some(prefix)),
+        None => (value, None),
+    };
+    let Ok(addr) = addr.parse::<std::net::IpAddr>() else {
+        return false;
+    };
+    let max_prefix = if addr.is_ipv4() { 32 } else { 128 };
+    prefix.is_none_or(|prefix| prefix.parse::<u8>().is_ok_and(|len| len <= max_prefix))
+}
+
+/// Check that `any_of` groups are non-empty and don't use `user_agent` or
+/// client certificate conditions
+fn validate_any_of(matcher: &MatchConfig) -> Result<(), ConfigError> {
+    let Some(groups) = &matcher.any_of else {
+        return Ok(());
+    };
+    if groups.is_empty() {
+        return Err(ConfigError::Validation("match.any_of must not be empty".to_string()));
+    }
+    for group in groups {
+        if group.user_agent.is_some() {
+            return Err(ConfigError::Validation(
+                "match.any_of groups can't use user_agent; set it on the route's match".to_string(),
+            ));
+        }
+        if group.client_cert.is_some() || group.client_cert_organization.is_some() {
+            return Err(ConfigError::Validation(
+                "match.any_of groups can't use client_cert; set it on the route's match".to_string(),
+            ));
+        }
+        validate_any_of(group)?;
+    }
+    Ok(())
+}
+
+/// Check that client certificate conditions are consistent and can be met
+fn validate_client_cert_match(matcher: &MatchConfig, client_ca: bool) -> Result<(), ConfigError> {
+    if let Some(organizations) = &matcher.client_cert_organization {
+        if organizations.is_empty() {
+            return Err(ConfigError::Validation("match.client_cert_organization must not be empty".to_string()));
+        }
+        if matcher.client_cert == Some(false) {
+            return Err(ConfigError::Validation(
+                "match.client_cert_organization can't be combined with client_cert = false".to_string(),
+            ));
+        }
+    }
+    let requires_cert = matcher.client_cert == Some(true) || matcher.client_cert_organization.is_some();
+    if requires_cert && !client_ca {
+        return Err(ConfigError::Validation(
+            "match.client_cert requires tls.client_ca to verify client certificates".to_string(),
+        ));
+    }
+    Ok(())
+}
+
+/// Check that status_map entries are valid, unique, and don't give bodies to bodiless statuses
+fn validate_status_map(status_map: &[StatusMapping]) -> Result<(), ConfigError> {
+    let mut seen = HashSet::new();
+    for mapping in status_map {
+        for status in [mapping.from, mapping.to] {
+            if !(100..=599).contains(&status) {
+                return Err(ConfigError::Validation(format!(
+                    "status_map status {} is not a valid HTTP status",
+                    status
+                )));
+            }
+        }
+        if !seen.insert(mapping.from) {
+            return Err(ConfigError::Validation(format!(
+                "status_map maps upstream status {} more than once",
+                mapping.from
+            )));
+        }
+        if mapping.body.is_some() && (mapping.to < 200 || matches!(mapping.to, 204 | 304)) {
+            return Err(ConfigError::Validation(format!(
+                "status_map body can't be sent with status {}",
+                mapping.to
+            )));
+        }
+    }
+    Ok(())
+}
 
-        domains.sort();
-        domains.dedup();
-        domains
+/// Check that body substitutions have something to find and room to buffer
+fn validate_body_replace(rewrite: &RewriteConfig) -> Result<(), ConfigError> {
+    if rewrite.response_body_replace.iter().any(|r| r.from.is_empty()) {
+        return Err(ConfigError::Validation(
+            "response_body_replace entries need a non-empty `from`".to_string(),
+        ));
+    }
+    if rewrite.max_rewrite_body_size == Some(0) {
+        return Err(ConfigError::Validation(
+            "max_rewrite_body_size must be greater than 0".to_string(),
+        ));
     }
+    Ok(())
 }
 
+/// Replace `${NAME}` references with environment variables
+///
+/// Used for secrets such as upstream credentials that shouldn't be written
+/// into the config file. A reference to an unset variable is an error.
+pub fn expand_env(value: &str) -> Result<String, ConfigError> {
+    let mut expanded = String::with_capacity(value.len());
+    let mut rest = value;
+    while let Some(start) = rest.find("${") {
+        expanded.push_str(&rest[..start]);
+        let end = rest[start..].find('}').ok_or_else(|| {
+            ConfigError::Validation(format!("unterminated ${{...}} in {:?}", value))
+        })?;
+        let name = &rest[start + 2..start + end];
+        let var = std::env::var(name).map_err(|_| {
+            ConfigError::Validation(format!("environment variable {:?} is not set", name))
+        })?;
+        expanded.push_str(&var);
+        rest = &rest[start + end + 1..];
+    }
+    expanded.push_str(rest);
+    Ok(expanded)
+}
 
 /// Plugin system configuration
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -358,7 +868,7 @@ pub struct GlobalConfig {
     #[serde(default = "default_log_level")]
     pub log_level: String,
 
-    /// Access log file path (optional)
+    /// Access log file path, or "otlp" to export records over OTLP (optional)
     #[serde(default)]
     pub access_log: Option<String>,
 
@@ -378,13 +888,105 @@ pub struct GlobalConfig {
     #[serde(default = "default_grace_period")]
     pub grace_period: u64,
 
+    /// Hard limit in seconds for draining on shutdown; connections still open
+    /// are then closed forcibly (default: 60, or `grace_period` when longer)
+    #[serde(default)]
+    pub drain_timeout: Option<u64>,
+
+    /// Seconds to keep serving after the shutdown signal with `/ready`
+    /// answering 503, before draining starts (default: 0)
+    #[serde(default)]
+    pub pre_drain_delay: u64,
+
+    /// Overall time limit per request in seconds, answered with 504 (default: 0 = none)
+    #[serde(default)]
+    pub request_timeout: u64,
+
+    /// Time allowed to receive a complete request header in seconds (default: 60, 0 = none)
+    #[serde(default = "default_header_read_timeout")]
+    pub header_read_timeout: u64,
+
+    /// Time a script handler or plugin hook may run in seconds (default: 30, 0 = none)
+    #[serde(default = "default_handler_timeout")]
+    pub handler_timeout: u64,
+
+    /// Minimum rate a request header must arrive at in bytes per second (default: 0 = off)
+    #[serde(default)]
+    pub min_header_rate: u64,
+
+    /// Maximum open client connections across all listeners (default: 0 = unlimited)
+    #[serde(default)]
+    pub max_connections: usize,
+
     /// OpenTelemetry tracing configuration
     #[serde(default)]
     pub tracing: TracingConfig,
 
+    /// Metrics export besides the Prometheus `/metrics` endpoint
+    #[serde(default)]
+    pub metrics: MetricsConfig,
+
     /// Security headers configuration
     #[serde(default)]
     pub security_headers: SecurityHeadersConfig,
+
+    /// Apply route fault injection to all clients; keep off in production (default: false)
+    #[serde(default)]
+    pub fault_injection: bool,
+
+    /// Request path normalization applied before route matching
+    #[serde(default)]
+    pub path_normalization: PathNormalizationConfig,
+
+    /// Answer OPTIONS for routes without CORS with 204 and an `Allow` header (default: false)
+    #[serde(default)]
+    pub options_response: bool,
+
+    /// Handling of header values with control characters or invalid UTF-8 (default: off)
+    #[serde(default)]
+    pub strict_header_validation: HeaderValidation,
+
+    /// Maximum number of servers (default: 1000)
+    #[serde(default = "default_max_servers")]
+    pub max_servers: usize,
+
+    /// Maximum number of routes across all servers (default: 50000)
+    #[serde(default = "default_max_routes")]
+    pub max_routes: usize,
+
+    /// `Link` header values added to every proxied HTML response,
+    /// e.g. `<https://cdn.example.com>; rel=preconnect`
+    #[serde(default)]
+    pub response_hints: Vec<String>,
+
+    /// Correlation headers, e.g. `["X-Request-Id", "traceparent"]`, kept
+    /// from the client or generated when missing, and forwarded upstream
+    /// (default: none)
+    #[serde(default)]
+    pub correlation_headers: Vec<String>,
+}
+
+/// Request path normalization
+///
+/// Paths like `//api///users` or `/api/%2e/users` can slip past prefix
+/// matchers; normalizing them first makes matching see the canonical path.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct PathNormalizationConfig {
+    /// Collapse repeated slashes (default: false)
+    #[serde(default)]
+    pub merge_slashes: bool,
+
+    /// Resolve `.` and `..` segments (default: false)
+    #[serde(default)]
+    pub resolve_dot_segments: bool,
+
+    /// Percent-decode unreserved characters such as `%2e` and `%41` (default: false)
+    #[serde(default)]
+    pub decode_unreserved: bool,
+
+    /// Reject non-canonical paths with 400 instead of rewriting them (default: false)
+    #[serde(default)]
+    pub reject: bool,
 }
 
 /// Security headers configuration (OWASP best practices)
@@ -426,6 +1028,34 @@ pub struct SecurityHeadersConfig {
     /// Permissions-Policy header
     #[serde(default)]
     pub permissions_policy: Option<String>,
+
+    /// Per-request nonce for inline scripts allowed by the CSP (optional)
+    #[serde(default)]
+    pub csp_nonce: Option<CspNonceConfig>,
+}
+
+/// Per-request CSP nonce
+///
+/// `{nonce}` in `content_security_policy` is replaced with a fresh nonce for
+/// every request, and the same nonce is sent to the upstream in
+/// `request_header` so it can stamp `<script nonce="...">` tags.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct CspNonceConfig {
+    /// Request header carrying the nonce to the upstream (default: "X-CSP-Nonce")
+    #[serde(default = "default_csp_nonce_request_header")]
+    pub request_header: String,
+
+    /// Response header the policy is sent in (default: "Content-Security-Policy")
+    #[serde(default = "default_csp_nonce_response_header")]
+    pub response_header: String,
+}
+
+fn default_csp_nonce_request_header() -> String {
+    "X-CSP-Nonce".to_string()
+}
+
+fn default_csp_nonce_response_header() -> String {
+    "Content-Security-Policy".to_string()
 }
 
 impl Default for SecurityHeadersConfig {
@@ -439,6 +1069,7 @@ impl Default for SecurityHeadersConfig {
             content_security_policy: None,
             referrer_policy: None,
             permissions_policy: None,
+            csp_nonce: None,
         }
     }
 }
@@ -490,6 +1121,45 @@ fn default_sampling_ratio() -> f64 {
     1.0
 }
 
+/// Metrics export configuration
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct MetricsConfig {
+    /// StatsD/DogStatsD UDP endpoint, e.g. "127.0.0.1:8125" (disabled when unset)
+    #[serde(default)]
+    pub statsd_addr: Option<String>,
+
+    /// Prefix for StatsD metric names (default: "avalon")
+    #[serde(default = "default_statsd_prefix")]
+    pub statsd_prefix: String,
+
+    /// DogStatsD tags added to every metric, e.g. "env:prod"
+    #[serde(default)]
+    pub statsd_tags: Vec<String>,
+
+    /// Seconds between StatsD flushes (default: 10)
+    #[serde(default = "default_statsd_flush_interval")]
+    pub statsd_flush_interval: u64,
+}
+
+impl Default for MetricsConfig {
+    fn default() -> Self {
+        Self {
+            statsd_addr: None,
+            statsd_prefix: default_statsd_prefix(),
+            statsd_tags: Vec::new(),
+            statsd_flush_interval: default_statsd_flush_interval(),
+        }
+    }
+}
+
+fn default_statsd_prefix() -> String {
+    "avalon".to_string()
+}
+
+fn default_statsd_flush_interval() -> u64 {
+    10
+}
+
 /// Compression configuration options
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct CompressionOptions {
@@ -512,6 +1182,14 @@ pub struct CompressionOptions {
     /// Compression level: 1-9 for gzip, 0-11 for brotli (default: 6)
     #[serde(default = "default_compression_level")]
     pub level: u32,
+
+    /// Brotli quality for proxied and other per-request responses, 0-11 (default: `level`)
+    #[serde(default)]
+    pub brotli_dynamic_level: Option<u32>,
+
+    /// Brotli quality for file server responses, 0-11 (default: `level`)
+    #[serde(default)]
+    pub brotli_static_level: Option<u32>,
 }
 
 /// Response caching configuration options
@@ -540,6 +1218,16 @@ pub struct CacheOptions {
     /// Cacheable HTTP methods (default: ["GET", "HEAD"])
     #[serde(default = "default_cacheable_methods")]
     pub cacheable_methods: Vec<String>,
+
+    /// Response header reporting how the cache handled the request (HIT,
+    /// MISS, STALE, BYPASS, REVALIDATED); empty to send none (default: "X-Cache")
+    #[serde(default = "default_cache_status_header")]
+    pub status_header: String,
+
+    /// Serve an expired entry when revalidating it with the upstream fails
+    /// (default: false)
+    #[serde(default)]
+    pub stale_if_error: bool,
 }
 
 fn default_cache_ttl() -> u64 {
@@ -562,6 +1250,10 @@ fn default_cacheable_methods() -> Vec<String> {
     vec!["GET".to_string(), "HEAD".to_string()]
 }
 
+fn default_cache_status_header() -> String {
+    "X-Cache".to_string()
+}
+
 impl Default for CacheOptions {
     fn default() -> Self {
         Self {
@@ -571,6 +1263,8 @@ impl Default for CacheOptions {
             max_cache_size: default_cache_max_size(),
             cacheable_status: default_cacheable_status(),
             cacheable_methods: default_cacheable_methods(),
+            status_header: default_cache_status_header(),
+            stale_if_error: false,
         }
     }
 }
@@ -595,6 +1289,8 @@ impl Default for CompressionOptions {
             brotli: true,
             min_size: 1024,
             level: 6,
+            brotli_dynamic_level: None,
+            brotli_static_level: None,
         }
     }
 }
@@ -615,6 +1311,26 @@ fn default_grace_period() -> u64 {
     30
 }
 
+fn default_drain_timeout() -> u64 {
+    60
+}
+
+fn default_header_read_timeout() -> u64 {
+    60
+}
+
+fn default_handler_timeout() -> u64 {
+    30
+}
+
+fn default_max_servers() -> usize {
+    1000
+}
+
+fn default_max_routes() -> usize {
+    50_000
+}
+
 impl Default for GlobalConfig {
     fn default() -> Self {
         Self {
@@ -625,12 +1341,35 @@ impl Default for GlobalConfig {
             compression: CompressionOptions::default(),
             cache: CacheOptions::default(),
             grace_period: default_grace_period(),
+            drain_timeout: None,
+            pre_drain_delay: 0,
+            request_timeout: 0,
+            header_read_timeout: default_header_read_timeout(),
+            handler_timeout: default_handler_timeout(),
+            min_header_rate: 0,
+            max_connections: 0,
             tracing: TracingConfig::default(),
+            metrics: MetricsConfig::default(),
             security_headers: SecurityHeadersConfig::default(),
+            fault_injection: false,
+            path_normalization: PathNormalizationConfig::default(),
+            options_response: false,
+            strict_header_validation: HeaderValidation::default(),
+            max_servers: default_max_servers(),
+            max_routes: default_max_routes(),
+            response_hints: Vec::new(),
+            correlation_headers: Vec::new(),
         }
     }
 }
 
+impl GlobalConfig {
+    /// Drain limit in seconds, defaulting to 60 or `grace_period` when longer
+    pub fn drain_timeout(&self) -> u64 {
+        self.drain_timeout.unwrap_or_else(|| self.grace_period.max(default_drain_timeout()))
+    }
+}
+
 /// TLS configuration
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct TlsConfig {
@@ -657,6 +1396,92 @@ pub struct TlsConfig {
     /// Explicit private key file path (takes priority over auto-discovery and ACME)
     #[serde(default)]
     pub key_path: Option<PathBuf>,
+
+    /// Shared certificate storage backend (default: files under storage_path)
+    #[serde(default)]
+    pub storage: StorageConfig,
+
+    /// Request ACME certificates with OCSP Must-Staple (default: false)
+    ///
+    /// Must-Staple certificates are never served without a valid OCSP staple.
+    #[serde(default)]
+    pub must_staple: bool,
+
+    /// Fail the handshake when the SNI matches no certificate, instead of
+    /// serving the default one (default: false)
+    #[serde(default)]
+    pub reject_unknown_sni: bool,
+
+    /// CA bundle (PEM) used to verify client certificates for downstream
+    /// mTLS. Clients are asked for a certificate but may connect without
+    /// one; routes require it with `match.client_cert`.
+    #[serde(default)]
+    pub client_ca: Option<PathBuf>,
+}
+
+/// Backend holding ACME certificates and accounts
+///
+/// Clustered deployments use a shared backend so every instance sees renewed
+/// certificates. `storage_path` is still used for the local PEM files.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum StorageConfig {
+    /// Local directory at `storage_path`
+    #[default]
+    File,
+    /// Redis server
+    Redis(RedisStorageConfig),
+    /// S3-compatible object storage
+    S3(S3StorageConfig),
+}
+
+/// Redis certificate storage
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct RedisStorageConfig {
+    /// Connection URL, e.g. "redis://:password@127.0.0.1:6379/0"
+    pub url: String,
+
+    /// Key prefix (default: "avalon")
+    #[serde(default = "default_storage_prefix")]
+    pub prefix: String,
+}
+
+/// S3-compatible certificate storage
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct S3StorageConfig {
+    /// Endpoint URL, e.g. "https://s3.us-east-1.amazonaws.com" or "http://minio:9000"
+    pub endpoint: String,
+
+    /// Bucket name
+    pub bucket: String,
+
+    /// Region used for request signing (default: "us-east-1")
+    #[serde(default = "default_s3_region")]
+    pub region: String,
+
+    /// Object key prefix (default: "avalon")
+    #[serde(default = "default_storage_prefix")]
+    pub prefix: String,
+
+    /// Access key ID (default: AWS_ACCESS_KEY_ID environment variable)
+    #[serde(default)]
+    pub access_key: Option<String>,
+
+    /// Secret access key (default: AWS_SECRET_ACCESS_KEY environment variable)
+    #[serde(default)]
+    pub secret_key: Option<String>,
+
+    /// Use path-style URLs (endpoint/bucket/key) instead of virtual-hosted style (default: true)
+    #[serde(default = "default_true")]
+    pub path_style: bool,
+}
+
+fn default_storage_prefix() -> String {
+    "avalon".to_string()
+}
+
+fn default_s3_region() -> String {
+    "us-east-1".to_string()
 }
 
 fn default_acme_ca() -> String {
@@ -680,6 +1505,10 @@ impl Default for TlsConfig {
             acme_enabled: default_acme_enabled(),
             cert_path: None,
             key_path: None,
+            storage: StorageConfig::default(),
+            must_staple: false,
+            reject_unknown_sni: false,
+            client_ca: None,
         }
     }
 }
@@ -701,12 +1530,101 @@ pub struct ServerConfig {
     /// Enable automatic HTTPS redirect
     #[serde(default)]
     pub https_redirect: bool,
+
+    /// HTTP/2 settings for this server's listeners
+    #[serde(default)]
+    pub http2: Http2Config,
+
+    /// Access policy checked before any route handles a request
+    #[serde(default)]
+    pub access_control: Option<AccessControlConfig>,
+
+    /// Permissions of the socket files of `unix:` listen addresses, e.g.
+    /// `0o660` (default: left to the umask)
+    #[serde(default)]
+    pub unix_socket_mode: Option<u32>,
 }
 
 fn default_server_name() -> String {
     "default".to_string()
 }
 
+/// HTTP/2 settings advertised to clients
+///
+/// Without a stream limit one connection can open streams until the server
+/// runs out of memory, so a limit is always set.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Http2Config {
+    /// Streams a client may have open at once per connection (default: 100)
+    #[serde(default = "default_h2_max_concurrent_streams")]
+    pub max_concurrent_streams: u32,
+
+    /// Largest frame payload accepted, 16384 to 16777215 bytes (default: 16384)
+    #[serde(default)]
+    pub max_frame_size: Option<u32>,
+
+    /// Initial flow-control window per stream, up to 2^31-1 bytes (default: 65535)
+    #[serde(default)]
+    pub initial_window_size: Option<u32>,
+}
+
+impl Default for Http2Config {
+    fn default() -> Self {
+        Self {
+            max_concurrent_streams: default_h2_max_concurrent_streams(),
+            max_frame_size: None,
+            initial_window_size: None,
+        }
+    }
+}
+
+fn default_h2_max_concurrent_streams() -> u32 {
+    100
+}
+
+/// Server-wide access policy
+///
+/// Rules are checked in order and the first one whose conditions all match
+/// decides the request; a request no rule matches is allowed.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct AccessControlConfig {
+    #[serde(default)]
+    pub rules: Vec<AccessRuleConfig>,
+
+    /// Credentials checked by `require_auth` rules
+    #[serde(default)]
+    pub auth: Option<AuthConfig>,
+}
+
+/// One access rule; an empty condition matches every request
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct AccessRuleConfig {
+    /// Client IPs or CIDR ranges
+    #[serde(default)]
+    pub ip: Vec<String>,
+
+    /// HTTP methods
+    #[serde(default)]
+    pub methods: Vec<String>,
+
+    /// Path prefixes
+    #[serde(default)]
+    pub paths: Vec<String>,
+
+    pub action: AccessAction,
+}
+
+/// What a matching access rule does with the request
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum AccessAction {
+    Allow,
+    /// Refuse with 403
+    Deny,
+    /// Allow once the request passes the policy's `auth`
+    RequireAuth,
+}
+
 /// Route configuration
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct RouteConfig {
@@ -716,6 +1634,130 @@ pub struct RouteConfig {
 
     /// Handler configuration
     pub handle: HandlerConfig,
+
+    /// Trailing slash handling: "strict", "redirect", or "ignore"
+    #[serde(default)]
+    pub trailing_slash: TrailingSlash,
+
+    /// Access log override: `false` to disable, or a table with path/format
+    #[serde(default)]
+    pub access_log: Option<RouteAccessLog>,
+
+    /// Synthetic latency/faults for resilience testing
+    #[serde(default)]
+    pub fault_injection: Option<FaultInjectionConfig>,
+
+    /// `Link` header values added to HTML responses from this route,
+    /// after the global `response_hints`
+    #[serde(default)]
+    pub response_hints: Vec<String>,
+
+    /// User-Agent regexes refused before the request is handled
+    #[serde(default)]
+    pub block_user_agents: Vec<String>,
+
+    /// Status returned to blocked User-Agents, 403 or 429 (default: 403)
+    #[serde(default = "default_block_user_agents_status")]
+    pub block_user_agents_status: u16,
+}
+
+fn default_block_user_agents_status() -> u16 {
+    403
+}
+
+/// Fault injection for chaos testing
+///
+/// Only applied when `global.fault_injection` is enabled or the client
+/// connects from one of `trusted_ips`.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct FaultInjectionConfig {
+    /// Delay added before handling the request in milliseconds (default: 0)
+    #[serde(default)]
+    pub delay_ms: u64,
+
+    /// Respond with this status instead of handling the request
+    #[serde(default)]
+    pub abort_status: Option<u16>,
+
+    /// Fraction of requests affected, 0.0-1.0 (default: 1.0)
+    #[serde(default = "default_fault_probability")]
+    pub probability: f64,
+
+    /// Client IPs/CIDRs that get faults even when the global switch is off
+    #[serde(default)]
+    pub trusted_ips: Vec<String>,
+}
+
+fn default_fault_probability() -> f64 {
+    1.0
+}
+
+/// Access log target that sends records to the OTLP endpoint instead of a file
+pub const OTLP_ACCESS_LOG: &str = "otlp";
+
+/// Per-route access log override
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(untagged)]
+pub enum RouteAccessLog {
+    /// `access_log = false` suppresses logging, `true` uses the global logger
+    Enabled(bool),
+    /// Route-specific log file and/or format
+    Custom(RouteAccessLogConfig),
+}
+
+/// Route-specific access log settings
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct RouteAccessLogConfig {
+    /// Log requests for this route (default: true)
+    #[serde(default = "default_true")]
+    pub enabled: bool,
+
+    /// Log file path, or "otlp" (default: the global access log)
+    #[serde(default)]
+    pub path: Option<String>,
+
+    /// Log format: "common", "json", or "combined" (default: the global format)
+    #[serde(default)]
+    pub format: Option<String>,
+}
+
+/// Treatment of request header values containing control characters or invalid UTF-8
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum HeaderValidation {
+    /// Pass header values through unchecked
+    #[default]
+    Off,
+    /// Answer the request with 400
+    Reject,
+    /// Strip control characters and replace invalid UTF-8 with `?`
+    Sanitize,
+}
+
+/// Trailing slash behavior for route matching
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum TrailingSlash {
+    /// Match the request path exactly as received
+    #[default]
+    Strict,
+    /// 301-redirect to the path with the trailing slash added or removed
+    Redirect,
+    /// Match the path regardless of a trailing slash
+    Ignore,
+}
+
+impl TrailingSlash {
+    /// Return the request path with its trailing slash toggled, if applicable
+    pub fn toggle(path: &str) -> Option<String> {
+        if path == "/" {
+            None
+        } else if let Some(trimmed) = path.strip_suffix('/') {
+            Some(trimmed.to_string())
+        } else {
+            Some(format!("{}/", path))
+        }
+    }
 }
 
 /// Match conditions for a route
@@ -727,16 +1769,110 @@ pub struct MatchConfig {
     /// Match by path prefix
     pub path: Option<Vec<String>>,
 
+    /// Exclude path prefixes (checked after `path`)
+    pub path_not: Option<Vec<String>>,
+
     /// Match by HTTP method
     pub method: Option<Vec<String>>,
 
     /// Match by header
     pub header: Option<HashMap<String, String>>,
+
+    /// Match by User-Agent regexes, any of which must match; compiled and
+    /// checked by the proxy since plain `matches` has no request headers
+    pub user_agent: Option<Vec<String>>,
+
+    /// Match on whether the client presented a certificate that verified
+    /// against `tls.client_ca`; checked by the proxy from the handshake
+    pub client_cert: Option<bool>,
+
+    /// Match the Organization (O) of the verified client certificate's
+    /// subject, any of which must match; implies `client_cert = true`
+    pub client_cert_organization: Option<Vec<String>>,
+
+    /// Alternative matcher sets; besides the conditions above, at least one
+    /// of them must match (no `user_agent` or client certificate inside)
+    #[serde(default)]
+    pub any_of: Option<Vec<MatchConfig>>,
 }
 
 impl MatchConfig {
     /// Check if this matcher matches the given request
     pub fn matches(&self, host: Option<&str>, path: &str, method: &str) -> bool {
+        if !self.matches_own_target(host, path) {
+            return false;
+        }
+
+        // Check method
+        if let Some(methods) = &self.method {
+            if !methods.iter().any(|m| m.eq_ignore_ascii_case(method)) {
+                return false;
+            }
+        }
+
+        match &self.any_of {
+            Some(groups) => groups.iter().any(|g| g.matches(host, path, method)),
+            None => true,
+        }
+    }
+
+    /// Check the host and path conditions, ignoring the method
+    pub fn matches_target(&self, host: Option<&str>, path: &str) -> bool {
+        self.matches_own_target(host, path)
+            && self.any_of.as_ref().is_none_or(|groups| groups.iter().any(|g| g.matches_target(host, path)))
+    }
+
+    /// Methods accepted at a host and path, or `None` for any method
+    ///
+    /// Only meaningful when `matches_target` holds for the same host and path.
+    pub fn methods_for_target(&self, host: Option<&str>, path: &str) -> Option<Vec<String>> {
+        let own = self.method.clone();
+        let Some(groups) = &self.any_of else {
+            return own;
+        };
+
+        // Union over the groups that match the target; any unrestricted group allows every method
+        let mut grouped: Vec<String> = Vec::new();
+        for group in groups.iter().filter(|g| g.matches_target(host, path)) {
+            match group.methods_for_target(host, path) {
+                Some(methods) => grouped.extend(methods),
+                None => return own,
+            }
+        }
+        match own {
+            Some(own) => Some(own.into_iter().filter(|m| grouped.iter().any(|g| g.eq_ignore_ascii_case(m))).collect()),
+            None => Some(grouped),
+        }
+    }
+
+    /// Hosts a request must have for this matcher to hold, or `None` if any host may match
+    pub fn candidate_hosts(&self) -> Option<Vec<&String>> {
+        if let Some(hosts) = &self.host {
+            return Some(hosts.iter().collect());
+        }
+        let groups = self.any_of.as_ref()?;
+        let mut hosts = Vec::new();
+        for group in groups {
+            hosts.extend(group.candidate_hosts()?);
+        }
+        Some(hosts)
+    }
+
+    /// Path prefixes a request must have for this matcher to hold, or `None` if any path may match
+    pub fn candidate_paths(&self) -> Option<Vec<&String>> {
+        if let Some(paths) = &self.path {
+            return Some(paths.iter().collect());
+        }
+        let groups = self.any_of.as_ref()?;
+        let mut paths = Vec::new();
+        for group in groups {
+            paths.extend(group.candidate_paths()?);
+        }
+        Some(paths)
+    }
+
+    /// Host and path conditions of this matcher alone, without `any_of`
+    fn matches_own_target(&self, host: Option<&str>, path: &str) -> bool {
         // Check host
         if let Some(hosts) = &self.host {
             if let Some(req_host) = host {
@@ -755,9 +1891,9 @@ impl MatchConfig {
             }
         }
 
-        // Check method
-        if let Some(methods) = &self.method {
-            if !methods.iter().any(|m| m.eq_ignore_ascii_case(method)) {
+        // Check excluded paths
+        if let Some(excluded) = &self.path_not {
+            if excluded.iter().any(|p| path.starts_with(p)) {
                 return false;
             }
         }
@@ -786,6 +1922,19 @@ pub enum HandlerConfig {
     Script(ScriptConfig),
 }
 
+impl HandlerConfig {
+    /// Handler type as written in the `type` key
+    pub fn type_name(&self) -> &'static str {
+        match self {
+            HandlerConfig::ReverseProxy(_) => "reverse_proxy",
+            HandlerConfig::FileServer(_) => "file_server",
+            HandlerConfig::StaticResponse(_) => "static_response",
+            HandlerConfig::Redirect(_) => "redirect",
+            HandlerConfig::Script(_) => "script",
+        }
+    }
+}
+
 /// Reverse proxy configuration
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct ReverseProxyConfig {
@@ -799,7 +1948,11 @@ pub struct ReverseProxyConfig {
     /// Health check configuration
     pub health_check: Option<HealthCheckConfig>,
 
-    /// Headers to add to upstream requests
+    /// Credentials sent to the upstream in place of the client's (optional)
+    #[serde(default)]
+    pub upstream_auth: Option<UpstreamAuthConfig>,
+
+    /// Headers to set on upstream requests ("+Name" appends, "-Name" removes)
     #[serde(default)]
     pub headers_up: HashMap<String, String>,
 
@@ -831,8 +1984,8 @@ pub struct ReverseProxyConfig {
     pub auth: Option<AuthConfig>,
 
     /// Total duration to try upstream connections before giving up (in milliseconds)
-    /// Set to 0 to disable retry (default). Only retries on connection failure,
-    /// never after response headers have been received (Caddy-style behavior).
+    /// Set to 0 to disable retry (default). Retries on connection failure, and
+    /// on responses whose status is listed in `retry_on_status`.
     #[serde(default)]
     pub lb_try_duration: u64,
 
@@ -840,6 +1993,21 @@ pub struct ReverseProxyConfig {
     #[serde(default = "default_lb_try_interval")]
     pub lb_try_interval: u64,
 
+    /// How the interval grows from one retry to the next (default: fixed)
+    #[serde(default)]
+    pub lb_try_backoff: RetryBackoffMode,
+
+    /// Random spread of each retry interval, as a fraction of it: 0.5
+    /// waits between 50% and 150% of the interval (default: 0.0)
+    #[serde(default)]
+    pub lb_try_jitter: f64,
+
+    /// Upstream response statuses that are retried on another upstream
+    /// (e.g. `[502, 503]`). Only idempotent requests without a body are
+    /// retried, and only within `lb_try_duration`.
+    #[serde(default)]
+    pub retry_on_status: Vec<u16>,
+
     /// CORS configuration
     #[serde(default)]
     pub cors: Option<CorsConfig>,
@@ -848,10 +2016,21 @@ pub struct ReverseProxyConfig {
     #[serde(default)]
     pub max_request_body_size: u64,
 
+    /// Largest request body replayed to another upstream on a
+    /// `retry_on_status` retry (0 = bodies are never retried, at most 64 KiB).
+    /// Larger bodies, up to `max_request_body_size`, stream through unretried.
+    #[serde(default)]
+    pub request_buffer_limit: u64,
+
     /// Enable HTTP/2 for upstream connections (requires upstream_tls)
     #[serde(default)]
     pub upstream_http2: bool,
 
+    /// Fall back to HTTP/1.1 when an `upstream_http2` upstream doesn't
+    /// negotiate h2 (default: true). When false such responses fail with 502.
+    #[serde(default = "default_true")]
+    pub upstream_http2_fallback: bool,
+
     /// mTLS configuration for upstream connections
     #[serde(default)]
     pub upstream_mtls: Option<UpstreamMtlsConfig>,
@@ -863,87 +2042,399 @@ pub struct ReverseProxyConfig {
     /// IP filter configuration (optional whitelist/blacklist)
     #[serde(default)]
     pub ip_filter: Option<IpFilterConfigDef>,
-}
-
-/// Circuit breaker configuration for upstream protection
-#[derive(Debug, Clone, Serialize, Deserialize)]
-pub struct CircuitBreakerConfigDef {
-    /// Number of consecutive failures to trip the circuit (default: 5)
-    #[serde(default = "default_cb_failure_threshold")]
-    pub failure_threshold: u32,
-
-    /// Number of consecutive successes in half-open state to close circuit (default: 2)
-    #[serde(default = "default_cb_success_threshold")]
-    pub success_threshold: u32,
 
-    /// How long to wait before transitioning from Open to HalfOpen (seconds, default: 30)
-    #[serde(default = "default_cb_timeout")]
-    pub timeout: u64,
+    /// Rewriting of upstream Location/Refresh/Content-Location headers (optional)
+    #[serde(default)]
+    pub redirect_rewrite: Option<RedirectRewriteConfig>,
 
-    /// Sliding window size for failure counting (seconds, 0 = count all, default: 60)
-    #[serde(default = "default_cb_window_size")]
-    pub window_size: u64,
-}
+    /// Idempotency-Key replay protection (optional)
+    #[serde(default)]
+    pub idempotency: Option<IdempotencyConfig>,
 
-fn default_cb_failure_threshold() -> u32 {
-    5
-}
+    /// Flag-controlled canary upstream group (optional)
+    #[serde(default)]
+    pub canary: Option<CanaryConfig>,
 
-fn default_cb_success_threshold() -> u32 {
-    2
-}
+    /// Per-client-IP request rate limit (optional)
+    #[serde(default)]
+    pub rate_limit: Option<RateLimitOptions>,
 
-fn default_cb_timeout() -> u64 {
-    30
-}
+    /// Compress responses of this route when global compression is on (default: true)
+    #[serde(default = "default_true")]
+    pub compress: bool,
 
-fn default_cb_window_size() -> u64 {
-    60
-}
+    /// Cache TTL in seconds used instead of the upstream's `Cache-Control` (optional)
+    #[serde(default)]
+    pub cache_ttl_override: Option<u64>,
 
-impl Default for CircuitBreakerConfigDef {
-    fn default() -> Self {
-        Self {
-            failure_threshold: default_cb_failure_threshold(),
-            success_threshold: default_cb_success_threshold(),
-            timeout: default_cb_timeout(),
-            window_size: default_cb_window_size(),
-        }
-    }
-}
+    /// Honor upstream `Cache-Control` (no-store, no-cache, max-age) when caching (default: true);
+    /// `private` and `Set-Cookie` responses are never cached either way
+    #[serde(default = "default_true")]
+    pub respect_upstream_cache_control: bool,
 
-/// IP filter configuration for whitelist/blacklist
-#[derive(Debug, Clone, Serialize, Deserialize, Default)]
-pub struct IpFilterConfigDef {
-    /// IPs to allow (whitelist mode) - supports CIDR notation
-    /// Example: ["192.168.1.0/24", "10.0.0.1"]
+    /// Query parameters that make up the cache key (optional)
     #[serde(default)]
-    pub allow: Vec<String>,
+    pub cache_key_query: Option<CacheKeyQueryConfig>,
 
-    /// IPs to deny (blacklist mode) - supports CIDR notation
-    /// Example: ["192.168.1.100", "10.0.0.0/8"]
+    /// Send client HEAD requests upstream as GET and drop the body, for
+    /// upstreams that answer HEAD with 405 (default: false)
     #[serde(default)]
-    pub deny: Vec<String>,
-}
+    pub head_as_get: bool,
 
-/// mTLS configuration for upstream connections (mutual TLS / client certificate auth)
-#[derive(Debug, Clone, Serialize, Deserialize)]
-pub struct UpstreamMtlsConfig {
-    /// Path to client certificate file (PEM format)
-    pub client_cert: String,
+    /// Maintenance mode with signed preview tokens (optional)
+    #[serde(default)]
+    pub maintenance: Option<MaintenanceConfig>,
 
-    /// Path to client private key file (PEM format)
-    pub client_key: String,
+    /// Client IPs/CIDRs (e.g. a TLS-terminating load balancer) whose X-Forwarded-Proto is passed through
+    #[serde(default)]
+    pub trusted_proxies: Vec<String>,
 
-    /// Path to CA certificate for verifying upstream server (optional)
-    /// If not provided, system root CAs will be used
+    /// How X-Forwarded-For is sent upstream: "append", "replace", or "omit" (default: append)
     #[serde(default)]
-    pub ca_cert: Option<String>,
+    pub x_forwarded_for: ForwardedForMode,
 
-    /// Skip verification of upstream server certificate (default: false)
-    /// WARNING: Setting this to true is insecure and should only be used for testing
+    /// Host header sent upstream: "preserve" the client's, "upstream" for the
+    /// upstream's address, or a literal host name (default: preserve)
     #[serde(default)]
-    pub insecure_skip_verify: bool,
+    pub host_header: HostHeader,
+}
+
+/// Largest `request_buffer_limit`: Pingora keeps 64 KiB of a body for replays
+pub const MAX_REQUEST_BUFFER_LIMIT: u64 = 64 * 1024;
+
+/// Host header of upstream requests
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(from = "String", into = "String")]
+pub enum HostHeader {
+    /// Forward the Host the client sent
+    #[default]
+    Preserve,
+    /// Use the address of the selected upstream
+    Upstream,
+    /// Always send this value
+    Literal(String),
+}
+
+impl From<String> for HostHeader {
+    fn from(value: String) -> Self {
+        match value.as_str() {
+            "preserve" => Self::Preserve,
+            "upstream" => Self::Upstream,
+            _ => Self::Literal(value),
+        }
+    }
+}
+
+impl From<HostHeader> for String {
+    fn from(host: HostHeader) -> Self {
+        match host {
+            HostHeader::Preserve => "preserve".to_string(),
+            HostHeader::Upstream => "upstream".to_string(),
+            HostHeader::Literal(value) => value,
+        }
+    }
+}
+
+/// X-Forwarded-For handling for upstream requests
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ForwardedForMode {
+    /// Append the client IP to any existing chain
+    #[default]
+    Append,
+    /// Send only the resolved client IP, discarding the incoming chain
+    Replace,
+    /// Remove the header entirely
+    Omit,
+}
+
+/// Canary routing controlled by a feature flag
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct CanaryConfig {
+    /// Canary upstream server addresses
+    pub upstreams: Vec<String>,
+
+    /// Name of the flag that holds the canary weight
+    pub flag: String,
+
+    /// Percentage of traffic (0-100) sent to the canary until the flag is set (default: 0)
+    #[serde(default)]
+    pub default_weight: u32,
+
+    /// Request header that forces the canary ("true") or stable ("false") group
+    #[serde(default)]
+    pub header: Option<String>,
+
+    /// Rewrite applied instead of the route's `rewrite` when the canary group
+    /// is selected (default: the route's rewrite)
+    #[serde(default)]
+    pub rewrite: Option<RewriteConfig>,
+}
+
+/// Per-client-IP rate limiting for a route
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct RateLimitOptions {
+    /// Requests allowed per window
+    pub max_requests: u32,
+
+    /// Window length in seconds (default: 60)
+    #[serde(default = "default_rate_limit_window")]
+    pub window: u64,
+
+    /// Extra requests allowed in a burst (default: 10% of max_requests)
+    #[serde(default)]
+    pub burst: Option<u32>,
+
+    /// Most client IPs tracked at once; beyond it the least recently seen
+    /// are forgotten (default: 100000)
+    #[serde(default = "default_rate_limit_max_tracked_clients")]
+    pub max_tracked_clients: usize,
+
+    /// Status of rejected requests (default: 429)
+    #[serde(default = "default_rate_limit_reject_status")]
+    pub reject_status: u16,
+
+    /// Body of rejected requests, `{retry_after}` is replaced with the
+    /// Retry-After seconds (default: "429 Too Many Requests")
+    #[serde(default)]
+    pub reject_body: Option<String>,
+
+    /// Content-Type of `reject_body` (default: application/json for a JSON
+    /// object or array, otherwise text/plain)
+    #[serde(default)]
+    pub reject_content_type: Option<String>,
+
+    /// Extra headers on rejected responses
+    #[serde(default)]
+    pub reject_headers: HashMap<String, String>,
+
+    /// Retry-After seconds sent with rejections (default: the window)
+    #[serde(default)]
+    pub retry_after: Option<u64>,
+}
+
+fn default_rate_limit_max_tracked_clients() -> usize {
+    100_000
+}
+
+fn default_rate_limit_reject_status() -> u16 {
+    429
+}
+
+fn default_rate_limit_window() -> u64 {
+    60
+}
+
+/// Maintenance mode for a route
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct MaintenanceConfig {
+    /// Whether the route is in maintenance (default: true)
+    #[serde(default = "default_true")]
+    pub enabled: bool,
+
+    /// Status answered while in maintenance (default: 503)
+    #[serde(default = "default_maintenance_status")]
+    pub status: u16,
+
+    /// Response body message (default: "Service Unavailable")
+    #[serde(default = "default_maintenance_message")]
+    pub message: String,
+
+    /// Retry-After value in seconds sent with the response (optional)
+    #[serde(default)]
+    pub retry_after: Option<u64>,
+
+    /// HMAC-SHA256 secret for preview tokens; no bypass without it
+    #[serde(default)]
+    pub bypass_secret: Option<String>,
+
+    /// Query parameter carrying a preview token (default: "avalon_preview")
+    #[serde(default = "default_maintenance_bypass_name")]
+    pub bypass_param: String,
+
+    /// Cookie carrying a preview token (default: "avalon_preview")
+    #[serde(default = "default_maintenance_bypass_name")]
+    pub bypass_cookie: String,
+
+    /// Whether a valid token also skips the route's auth (default: false)
+    #[serde(default)]
+    pub bypass_auth: bool,
+}
+
+fn default_maintenance_status() -> u16 {
+    503
+}
+
+fn default_maintenance_message() -> String {
+    "Service Unavailable".to_string()
+}
+
+fn default_maintenance_bypass_name() -> String {
+    "avalon_preview".to_string()
+}
+
+/// Idempotency-Key replay cache configuration
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct IdempotencyConfig {
+    /// Request header carrying the idempotency key (default: "Idempotency-Key")
+    #[serde(default = "default_idempotency_header")]
+    pub header: String,
+
+    /// Methods the replay cache applies to (default: ["POST"])
+    #[serde(default = "default_idempotency_methods")]
+    pub methods: Vec<String>,
+
+    /// How long a stored response is replayed, in seconds (default: 86400)
+    #[serde(default = "default_idempotency_ttl")]
+    pub ttl: u64,
+
+    /// How long a duplicate request waits for the original to finish, in seconds (default: 30)
+    #[serde(default = "default_idempotency_wait_timeout")]
+    pub wait_timeout: u64,
+
+    /// Maximum response size stored for replay in bytes (default: 1MB)
+    #[serde(default = "default_idempotency_max_body_size")]
+    pub max_body_size: usize,
+}
+
+fn default_idempotency_header() -> String {
+    "Idempotency-Key".to_string()
+}
+
+fn default_idempotency_methods() -> Vec<String> {
+    vec!["POST".to_string()]
+}
+
+fn default_idempotency_ttl() -> u64 {
+    86400 // 24 hours
+}
+
+fn default_idempotency_wait_timeout() -> u64 {
+    30
+}
+
+fn default_idempotency_max_body_size() -> usize {
+    1024 * 1024 // 1MB
+}
+
+impl Default for IdempotencyConfig {
+    fn default() -> Self {
+        Self {
+            header: default_idempotency_header(),
+            methods: default_idempotency_methods(),
+            ttl: default_idempotency_ttl(),
+            wait_timeout: default_idempotency_wait_timeout(),
+            max_body_size: default_idempotency_max_body_size(),
+        }
+    }
+}
+
+/// Rewriting of redirect headers issued by upstreams
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct RedirectRewriteConfig {
+    /// Rewrite URLs pointing at this route's upstreams to the public scheme and host (default: true)
+    #[serde(default = "default_true")]
+    pub upstreams: bool,
+
+    /// Explicit internal URL prefix to public URL prefix mappings
+    /// Example: { "http://backend:8080/app" = "https://example.com" }
+    #[serde(default)]
+    pub map: HashMap<String, String>,
+}
+
+impl Default for RedirectRewriteConfig {
+    fn default() -> Self {
+        Self {
+            upstreams: true,
+            map: HashMap::new(),
+        }
+    }
+}
+
+/// Circuit breaker configuration for upstream protection
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct CircuitBreakerConfigDef {
+    /// Number of consecutive failures to trip the circuit (default: 5)
+    #[serde(default = "default_cb_failure_threshold")]
+    pub failure_threshold: u32,
+
+    /// Number of consecutive successes in half-open state to close circuit (default: 2)
+    #[serde(default = "default_cb_success_threshold")]
+    pub success_threshold: u32,
+
+    /// How long to wait before transitioning from Open to HalfOpen (seconds, default: 30)
+    #[serde(default = "default_cb_timeout")]
+    pub timeout: u64,
+
+    /// Sliding window size for failure counting (seconds, 0 = count all, default: 60)
+    #[serde(default = "default_cb_window_size")]
+    pub window_size: u64,
+}
+
+fn default_cb_failure_threshold() -> u32 {
+    5
+}
+
+fn default_cb_success_threshold() -> u32 {
+    2
+}
+
+fn default_cb_timeout() -> u64 {
+    30
+}
+
+fn default_cb_window_size() -> u64 {
+    60
+}
+
+impl Default for CircuitBreakerConfigDef {
+    fn default() -> Self {
+        Self {
+            failure_threshold: default_cb_failure_threshold(),
+            success_threshold: default_cb_success_threshold(),
+            timeout: default_cb_timeout(),
+            window_size: default_cb_window_size(),
+        }
+    }
+}
+
+/// IP filter configuration for whitelist/blacklist
+#[derive(Debug, Clone, Serialize, Deserialize, Default)]
+pub struct IpFilterConfigDef {
+    /// IPs to allow (whitelist mode) - supports CIDR notation
+    /// Example: ["192.168.1.0/24", "10.0.0.1"]
+    #[serde(default)]
+    pub allow: Vec<String>,
+
+    /// IPs to deny (blacklist mode) - supports CIDR notation
+    /// Example: ["192.168.1.100", "10.0.0.0/8"]
+    #[serde(default)]
+    pub deny: Vec<String>,
+}
+
+/// mTLS configuration for upstream connections (mutual TLS / client certificate auth)
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct UpstreamMtlsConfig {
+    /// Path to client certificate file (PEM format)
+    pub client_cert: String,
+
+    /// Path to client private key file (PEM format)
+    pub client_key: String,
+
+    /// Path to CA certificate for verifying upstream server (optional)
+    /// If not provided, system root CAs will be used
+    #[serde(default)]
+    pub ca_cert: Option<String>,
+
+    /// Skip verification of upstream server certificate (default: false)
+    /// WARNING: Setting this to true is insecure and should only be used for testing
+    #[serde(default)]
+    pub insecure_skip_verify: bool,
+
+    /// Check that the upstream certificate matches the SNI/hostname (default: true)
+    /// Set to false for IP-addressed upstreams; the certificate chain is still verified
+    #[serde(default = "default_true")]
+    pub verify_hostname: bool,
 }
 
 /// Timeout configuration for upstream connections
@@ -976,6 +2467,52 @@ pub struct TimeoutConfig {
     /// TCP keepalive probe count (default: 3)
     #[serde(default = "default_keepalive_count")]
     pub keepalive_count: u32,
+
+    /// Requests sent over one HTTP/1.1 keep-alive connection before it is closed, 0 = unlimited (default: 0)
+    #[serde(default)]
+    pub max_requests_per_connection: u32,
+
+    /// Send concurrent requests over one HTTP/2 upstream connection; when
+    /// false each connection carries one stream at a time (default: true)
+    #[serde(default = "default_h2_multiplexing")]
+    pub h2_multiplexing: bool,
+
+    /// Overrides for upgraded WebSocket connections
+    #[serde(default)]
+    pub websocket: WebSocketTimeoutConfig,
+}
+
+/// Timeouts for long-lived WebSocket connections
+///
+/// A WebSocket can legitimately stay silent for a long time, so the regular
+/// read timeout would cut it off. A value of 0 disables the timeout.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct WebSocketTimeoutConfig {
+    /// Read timeout in seconds, 0 = disabled (default: 0)
+    #[serde(default)]
+    pub read: u64,
+
+    /// Write timeout in seconds, 0 = disabled (default: 0)
+    #[serde(default)]
+    pub write: u64,
+
+    /// TCP keepalive interval in seconds to keep idle connections alive through NAT/load balancers, 0 = disabled (default: 30)
+    #[serde(default = "default_ws_keepalive_interval")]
+    pub keepalive_interval: u64,
+}
+
+fn default_ws_keepalive_interval() -> u64 {
+    30
+}
+
+impl Default for WebSocketTimeoutConfig {
+    fn default() -> Self {
+        Self {
+            read: 0,
+            write: 0,
+            keepalive_interval: default_ws_keepalive_interval(),
+        }
+    }
 }
 
 fn default_connect_timeout() -> u64 {
@@ -1006,6 +2543,10 @@ fn default_keepalive_count() -> u32 {
     3
 }
 
+fn default_h2_multiplexing() -> bool {
+    true
+}
+
 impl Default for TimeoutConfig {
     fn default() -> Self {
         Self {
@@ -1016,6 +2557,9 @@ impl Default for TimeoutConfig {
             keepalive: default_keepalive_enabled(),
             keepalive_interval: default_keepalive_interval(),
             keepalive_count: default_keepalive_count(),
+            max_requests_per_connection: 0,
+            h2_multiplexing: default_h2_multiplexing(),
+            websocket: WebSocketTimeoutConfig::default(),
         }
     }
 }
@@ -1108,12 +2652,44 @@ pub struct AuthConfig {
     /// Paths to exclude from authentication (e.g., health checks)
     #[serde(default)]
     pub exclude_paths: Vec<String>,
+
+    /// Delegate the decision to an external auth service
+    #[serde(default)]
+    pub forward_auth: Option<ForwardAuthConfig>,
 }
 
 fn default_auth_realm() -> String {
     "Restricted".to_string()
 }
 
+/// Forward authentication subrequest settings
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ForwardAuthConfig {
+    /// Auth service URL; a 2xx response allows the request
+    pub url: String,
+
+    /// Auth service response headers copied to the upstream request
+    #[serde(default)]
+    pub copy_headers: Vec<String>,
+
+    /// Timeout per attempt in milliseconds (default: 5000)
+    #[serde(default = "default_forward_auth_timeout")]
+    pub timeout: u64,
+
+    /// Extra attempts after a connection failure or timeout (default: 0)
+    #[serde(default)]
+    pub retries: u32,
+
+    /// Allow requests while the auth service is unreachable instead of
+    /// answering 503 (default: false)
+    #[serde(default)]
+    pub fail_open: bool,
+}
+
+fn default_forward_auth_timeout() -> u64 {
+    5000
+}
+
 /// Basic authentication credential
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct BasicAuthCredential {
@@ -1179,6 +2755,22 @@ fn default_jwt_header() -> String {
     "Authorization".to_string()
 }
 
+/// Which query parameters distinguish cached responses on a route
+///
+/// With `vary` set, only those parameters are part of the cache key; any
+/// parameter in `ignore` is always left out. Remaining parameters are sorted
+/// by name so their order in the URL doesn't split the cache.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct CacheKeyQueryConfig {
+    /// Parameters that vary the cached response, e.g. `["page", "sort"]` (empty = all)
+    #[serde(default)]
+    pub vary: Vec<String>,
+
+    /// Parameters that never vary it, e.g. tracking params like `["ref", "utm_source"]`
+    #[serde(default)]
+    pub ignore: Vec<String>,
+}
+
 /// Request/response rewrite configuration
 #[derive(Debug, Clone, Default, Serialize, Deserialize)]
 pub struct RewriteConfig {
@@ -1198,6 +2790,10 @@ pub struct RewriteConfig {
     #[serde(default)]
     pub replace_path: Option<String>,
 
+    /// Copy regex captures from the request path into upstream headers
+    #[serde(default)]
+    pub capture_headers: Option<CaptureHeaders>,
+
     /// Headers to add to the request (won't override existing)
     #[serde(default)]
     pub request_headers_add: HashMap<String, String>,
@@ -1222,6 +2818,19 @@ pub struct RewriteConfig {
     #[serde(default)]
     pub response_headers_delete: Vec<String>,
 
+    /// Upstream statuses presented to the client as a different status
+    #[serde(default)]
+    pub status_map: Vec<StatusMapping>,
+
+    /// Text substitutions applied to response bodies, in order
+    #[serde(default)]
+    pub response_body_replace: Vec<BodyReplacement>,
+
+    /// Largest response body that is buffered for `response_body_replace`;
+    /// larger ones stream through unmodified (default: 1048576)
+    #[serde(default)]
+    pub max_rewrite_body_size: Option<usize>,
+
     /// Rhai scripting rewrite rules (advanced)
     #[serde(default)]
     pub rhai_rules: Vec<RhaiRewriteRuleConfig>,
@@ -1294,12 +2903,59 @@ pub struct PathRegex {
     pub replacement: String,
 }
 
-/// Session affinity configuration for sticky sessions
+/// Upstream response status replaced before the response reaches the client
 #[derive(Debug, Clone, Serialize, Deserialize)]
-pub struct SessionAffinityConfig {
-    /// Affinity type: "cookie" or "ip_hash"
-    #[serde(default = "default_affinity_type")]
-    pub affinity_type: String,
+pub struct StatusMapping {
+    /// Status returned by the upstream
+    pub from: u16,
+
+    /// Status sent to the client
+    pub to: u16,
+
+    /// Body replacing the upstream's (default: keep the upstream body)
+    #[serde(default)]
+    pub body: Option<String>,
+
+    /// Content-Type sent with `body` (default: "text/plain; charset=utf-8")
+    #[serde(default = "default_status_mapping_content_type")]
+    pub content_type: String,
+}
+
+fn default_status_mapping_content_type() -> String {
+    "text/plain; charset=utf-8".to_string()
+}
+
+/// Literal text substitution in response bodies
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct BodyReplacement {
+    /// Text to find
+    pub from: String,
+
+    /// Text replacing every occurrence
+    #[serde(default)]
+    pub to: String,
+}
+
+/// Request headers filled from regex captures of the original path
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct CaptureHeaders {
+    /// Regex matched against the path before any rewrite
+    pub pattern: String,
+
+    /// Header name to value template ($1, $2, ${name} for capture groups)
+    pub header_map: HashMap<String, String>,
+}
+
+/// Session affinity configuration for sticky sessions
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SessionAffinityConfig {
+    /// Affinity type: "cookie", "ip_hash" or "jwt_claim"
+    #[serde(default = "default_affinity_type")]
+    pub affinity_type: String,
+
+    /// JWT claim hashed to pick the upstream for `jwt_claim` affinity (e.g. "tenant_id")
+    #[serde(default)]
+    pub claim: Option<String>,
 
     /// Cookie name for cookie-based affinity
     #[serde(default = "default_affinity_cookie")]
@@ -1326,6 +2982,17 @@ fn default_lb_try_interval() -> u64 {
     250 // 250ms default
 }
 
+/// Growth of the interval between retries
+#[derive(Debug, Clone, Copy, Default, Serialize, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum RetryBackoffMode {
+    /// Always wait `lb_try_interval`
+    #[default]
+    Fixed,
+    /// Double the interval after every retry
+    Exponential,
+}
+
 /// Load balancing strategy
 #[derive(Debug, Clone, Default, Serialize, Deserialize, PartialEq)]
 #[serde(rename_all = "snake_case")]
@@ -1356,6 +3023,74 @@ pub struct HealthCheckConfig {
     /// Expected HTTP status code
     #[serde(default = "default_health_status")]
     pub expected_status: u16,
+
+    /// Also eject servers whose real traffic is failing (optional)
+    #[serde(default)]
+    pub passive: Option<PassiveHealthCheckConfig>,
+}
+
+/// Passive health checking from proxied responses
+///
+/// A server answering more than `max_error_rate` of at least `min_requests`
+/// requests within `window` with a 5xx or connection failure is taken out
+/// of rotation for `eject_duration`, even while its active checks pass.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct PassiveHealthCheckConfig {
+    /// Window the error rate is measured over (default: "30s")
+    #[serde(default = "default_passive_window")]
+    pub window: String,
+
+    /// Requests needed in the window before a server can be ejected (default: 10)
+    #[serde(default = "default_passive_min_requests")]
+    pub min_requests: u32,
+
+    /// Share of failed requests, 0.0-1.0, above which the server is ejected (default: 0.5)
+    #[serde(default = "default_passive_max_error_rate")]
+    pub max_error_rate: f64,
+
+    /// How long an ejected server stays out of rotation (default: "30s")
+    #[serde(default = "default_passive_eject_duration")]
+    pub eject_duration: String,
+}
+
+fn default_passive_window() -> String {
+    "30s".to_string()
+}
+
+fn default_passive_min_requests() -> u32 {
+    10
+}
+
+fn default_passive_max_error_rate() -> f64 {
+    0.5
+}
+
+fn default_passive_eject_duration() -> String {
+    "30s".to_string()
+}
+
+/// Credentials injected into upstream requests
+///
+/// Exactly one of `basic` and `bearer_token` is set. Values may reference
+/// environment variables as `${NAME}`, expanded when the config is loaded.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct UpstreamAuthConfig {
+    /// Basic credentials
+    #[serde(default)]
+    pub basic: Option<BasicAuthCredential>,
+
+    /// Static bearer token
+    #[serde(default)]
+    pub bearer_token: Option<String>,
+
+    /// Header carrying the credentials, e.g. "Proxy-Authorization" for an
+    /// upstream forward proxy (default: "Authorization")
+    #[serde(default = "default_upstream_auth_header")]
+    pub header: String,
+}
+
+fn default_upstream_auth_header() -> String {
+    "Authorization".to_string()
 }
 
 fn default_health_path() -> String {
@@ -1415,8 +3150,23 @@ pub struct StaticResponseConfig {
     /// Response headers
     #[serde(default)]
     pub headers: HashMap<String, String>,
+
+    /// Artificial latency before responding, for load tests and demos (default: 0)
+    #[serde(default)]
+    pub delay_ms: u64,
+
+    /// Send a body of exactly this many bytes, repeating `body` (or filler
+    /// when it is empty) instead of `body` itself (default: unset)
+    #[serde(default)]
+    pub body_size: Option<usize>,
 }
 
+/// Largest `body_size` of a static response; the body is held in memory
+pub const MAX_STATIC_BODY_SIZE: usize = 64 * 1024 * 1024;
+
+/// Longest `delay_ms` of a static response; each waiting request holds a connection
+pub const MAX_STATIC_DELAY_MS: u64 = 60_000;
+
 fn default_static_status() -> u16 {
     200
 }
@@ -1491,6 +3241,11 @@ upstreams = ["127.0.0.1:9090"]
             path: Some(vec!["/api".to_string()]),
             method: None,
             header: None,
+            path_not: None,
+            user_agent: None,
+            client_cert: None,
+            client_cert_organization: None,
+            any_of: None,
         };
 
         assert!(matcher.matches(Some("example.com"), "/api/users", "GET"));
@@ -1505,6 +3260,34 @@ upstreams = ["127.0.0.1:9090"]
         assert!(matcher.matches(None, "/", "POST"));
     }
 
+    #[test]
+    fn test_match_path_not() {
+        let matcher = MatchConfig {
+            host: None,
+            path: Some(vec!["/".to_string()]),
+            path_not: Some(vec!["/admin".to_string()]),
+            method: None,
+            header: None,
+            user_agent: None,
+            client_cert: None,
+            client_cert_organization: None,
+            any_of: None,
+        };
+
+        assert!(matcher.matches(None, "/app", "GET"));
+        assert!(matcher.matches(None, "/", "GET"));
+        assert!(!matcher.matches(None, "/admin", "GET"));
+        assert!(!matcher.matches(None, "/admin/users", "GET"));
+
+        // Exclusions apply even without an inclusive path list
+        let matcher = MatchConfig {
+            path_not: Some(vec!["/internal".to_string()]),
+            ..Default::default()
+        };
+        assert!(matcher.matches(None, "/public", "GET"));
+        assert!(!matcher.matches(None, "/internal/metrics", "GET"));
+    }
+
     #[test]
     fn test_match_host() {
         let matcher = MatchConfig {
@@ -1512,6 +3295,11 @@ upstreams = ["127.0.0.1:9090"]
             path: None,
             method: None,
             header: None,
+            path_not: None,
+            user_agent: None,
+            client_cert: None,
+            client_cert_organization: None,
+            any_of: None,
         };
 
         assert!(matcher.matches(Some("a.com"), "/", "GET"));
@@ -1520,6 +3308,60 @@ upstreams = ["127.0.0.1:9090"]
         assert!(!matcher.matches(None, "/", "GET"));
     }
 
+    #[test]
+    fn test_match_any_of() {
+        let toml = r#"
+path = ["/api"]
+
+[[any_of]]
+host = ["a.com"]
+
+[[any_of]]
+host = ["b.com"]
+method = ["GET"]
+"#;
+        let matcher: MatchConfig = toml::from_str(toml).unwrap();
+
+        assert!(matcher.matches(Some("a.com"), "/api/users", "POST"));
+        assert!(matcher.matches(Some("b.com"), "/api/users", "GET"));
+        assert!(!matcher.matches(Some("b.com"), "/api/users", "POST"));
+        assert!(!matcher.matches(Some("c.com"), "/api/users", "GET"));
+        // The base conditions still apply to every group
+        assert!(!matcher.matches(Some("a.com"), "/web", "GET"));
+        assert!(!matcher.matches_target(Some("a.com"), "/web"));
+
+        let hosts: Vec<&str> = matcher.candidate_hosts().unwrap().into_iter().map(String::as_str).collect();
+        assert_eq!(hosts, vec!["a.com", "b.com"]);
+        assert_eq!(matcher.candidate_paths(), Some(vec![&"/api".to_string()]));
+
+        assert_eq!(matcher.methods_for_target(Some("a.com"), "/api"), None);
+        assert_eq!(matcher.methods_for_target(Some("b.com"), "/api"), Some(vec!["GET".to_string()]));
+    }
+
+    #[test]
+    fn test_match_any_of_with_unrestricted_group() {
+        let matcher = MatchConfig {
+            any_of: Some(vec![
+                MatchConfig {
+                    host: Some(vec!["a.com".to_string()]),
+                    ..Default::default()
+                },
+                MatchConfig {
+                    path: Some(vec!["/shared".to_string()]),
+                    ..Default::default()
+                },
+            ]),
+            ..Default::default()
+        };
+
+        assert!(matcher.matches(Some("a.com"), "/", "GET"));
+        assert!(matcher.matches(Some("c.com"), "/shared/x", "GET"));
+        assert!(!matcher.matches(Some("c.com"), "/", "GET"));
+        // A group without a host matches any host, so the route can't be bucketed by host
+        assert_eq!(matcher.candidate_hosts(), None);
+        assert_eq!(matcher.candidate_paths(), None);
+    }
+
     #[test]
     fn test_validation_no_listen() {
         let config = Config {
@@ -1528,6 +3370,9 @@ upstreams = ["127.0.0.1:9090"]
                 listen: vec![],
                 routes: vec![],
                 https_redirect: false,
+                http2: Http2Config::default(),
+                access_control: None,
+                unix_socket_mode: None,
             }],
             ..Default::default()
         };
@@ -1551,6 +3396,7 @@ upstreams = ["127.0.0.1:9090"]
                         upstreams: vec![],
                         load_balancing: LoadBalancingStrategy::RoundRobin,
                         health_check: None,
+                        upstream_auth: None,
                         headers_up: HashMap::new(),
                         headers_down: HashMap::new(),
                         timeout: 30,
@@ -1562,14 +3408,41 @@ upstreams = ["127.0.0.1:9090"]
                         cors: None,
                         lb_try_duration: 0,
                         lb_try_interval: 250,
+                        lb_try_backoff: RetryBackoffMode::Fixed,
+                        lb_try_jitter: 0.0,
                         max_request_body_size: 0,
+                        request_buffer_limit: 0,
                         circuit_breaker: None,
                         ip_filter: None,
                         upstream_http2: false,
                         upstream_mtls: None,
+                        redirect_rewrite: None,
+                        idempotency: None,
+                        canary: None,
+                        trusted_proxies: Vec::new(),
+                        x_forwarded_for: ForwardedForMode::Append,
+                        host_header: HostHeader::Preserve,
+                        retry_on_status: Vec::new(),
+                        upstream_http2_fallback: true,
+                        rate_limit: None,
+                        maintenance: None,
+                        compress: true,
+                        cache_ttl_override: None,
+                        respect_upstream_cache_control: true,
+                        cache_key_query: None,
+                        head_as_get: false,
                     })),
+                    trailing_slash: TrailingSlash::Strict,
+                    access_log: None,
+                    fault_injection: None,
+                    response_hints: Vec::new(),
+                    block_user_agents: Vec::new(),
+                    block_user_agents_status: 403,
                 }],
                 https_redirect: false,
+                http2: Http2Config::default(),
+                access_control: None,
+                unix_socket_mode: None,
             }],
             ..Default::default()
         };
@@ -1590,6 +3463,9 @@ upstreams = ["127.0.0.1:9090"]
                 listen: vec![":8080".to_string()],
                 routes: vec![],
                 https_redirect: false,
+                http2: Http2Config::default(),
+                access_control: None,
+                unix_socket_mode: None,
             }],
             ..Default::default()
         };
@@ -1614,15 +3490,31 @@ upstreams = ["127.0.0.1:9090"]
                             path: None,
                             method: None,
                             header: None,
+                            path_not: None,
+                            user_agent: None,
+                            client_cert: None,
+                            client_cert_organization: None,
+                            any_of: None,
                         },
                         handle: HandlerConfig::StaticResponse(StaticResponseConfig {
                             status: 200,
                             body: String::new(),
                             headers: HashMap::new(),
+                            delay_ms: 0,
+                            body_size: None,
                         }),
+                        trailing_slash: TrailingSlash::Strict,
+                        access_log: None,
+                        fault_injection: None,
+                        response_hints: Vec::new(),
+                        block_user_agents: Vec::new(),
+                        block_user_agents_status: 403,
                     },
                 ],
                 https_redirect: false,
+                http2: Http2Config::default(),
+                access_control: None,
+                unix_socket_mode: None,
             }],
             ..Default::default()
         };
@@ -1649,14 +3541,30 @@ upstreams = ["127.0.0.1:9090"]
                         path: None,
                         method: None,
                         header: None,
+                        path_not: None,
+                        user_agent: None,
+                        client_cert: None,
+                        client_cert_organization: None,
+                        any_of: None,
                     },
                     handle: HandlerConfig::StaticResponse(StaticResponseConfig {
                         status: 200,
                         body: String::new(),
                         headers: HashMap::new(),
+                        delay_ms: 0,
+                        body_size: None,
                     }),
+                    trailing_slash: TrailingSlash::Strict,
+                    access_log: None,
+                    fault_injection: None,
+                    response_hints: Vec::new(),
+                    block_user_agents: Vec::new(),
+                    block_user_agents_status: 403,
                 }],
                 https_redirect: false,
+                http2: Http2Config::default(),
+                access_control: None,
+                unix_socket_mode: None,
             }],
             ..Default::default()
         };
@@ -1682,14 +3590,30 @@ upstreams = ["127.0.0.1:9090"]
                         path: None,
                         method: None,
                         header: None,
+                        path_not: None,
+                        user_agent: None,
+                        client_cert: None,
+                        client_cert_organization: None,
+                        any_of: None,
                     },
                     handle: HandlerConfig::StaticResponse(StaticResponseConfig {
                         status: 200,
                         body: String::new(),
                         headers: HashMap::new(),
+                        delay_ms: 0,
+                        body_size: None,
                     }),
+                    trailing_slash: TrailingSlash::Strict,
+                    access_log: None,
+                    fault_injection: None,
+                    response_hints: Vec::new(),
+                    block_user_agents: Vec::new(),
+                    block_user_agents_status: 403,
                 }],
                 https_redirect: false,
+                http2: Http2Config::default(),
+                access_control: None,
+                unix_socket_mode: None,
             }],
             ..Default::default()
         };
@@ -1752,11 +3676,49 @@ expected_status = 200
             assert_eq!(hc.interval, "10s");
             assert_eq!(hc.timeout, "2s");
             assert_eq!(hc.expected_status, 200);
+            assert!(hc.passive.is_none());
         } else {
             panic!("Expected ReverseProxy handler");
         }
     }
 
+    #[test]
+    fn test_passive_health_check_config() {
+        let toml = r#"
+[tls]
+acme_enabled = false
+
+[[servers]]
+name = "test"
+listen = [":8080"]
+
+[[servers.routes]]
+[servers.routes.handle]
+type = "reverse_proxy"
+upstreams = ["127.0.0.1:9090"]
+
+[servers.routes.handle.health_check]
+path = "/health"
+
+[servers.routes.handle.health_check.passive]
+max_error_rate = 0.25
+"#;
+
+        let mut config: Config = toml::from_str(toml).unwrap();
+        assert!(config.validate().is_ok());
+        let HandlerConfig::ReverseProxy(proxy) = &mut config.servers[0].routes[0].handle else {
+            panic!("Expected ReverseProxy handler");
+        };
+        let passive = proxy.health_check.as_mut().unwrap().passive.as_mut().unwrap();
+        assert_eq!(passive.window, "30s");
+        assert_eq!(passive.min_requests, 10);
+        assert_eq!(passive.max_error_rate, 0.25);
+        assert_eq!(passive.eject_duration, "30s");
+
+        passive.max_error_rate = 1.5;
+        assert!(config.validate().is_err());
+    }
+
     #[test]
     fn test_file_server_config() {
         let toml = r#"
@@ -1815,6 +3777,66 @@ X-Custom = "value"
         }
     }
 
+    #[test]
+    fn test_static_body_size_capped() {
+        let toml = format!(
+            r#"
+[tls]
+acme_enabled = false
+
+[[servers]]
+name = "test"
+listen = [":8080"]
+
+[[servers.routes]]
+[servers.routes.handle]
+type = "static_response"
+body_size = {}
+"#,
+            MAX_STATIC_BODY_SIZE
+        );
+        let config: Config = toml::from_str(&toml).unwrap();
+        assert!(config.validate().is_ok());
+
+        let config: Config = toml::from_str(&toml.replace(
+            &MAX_STATIC_BODY_SIZE.to_string(),
+            &(MAX_STATIC_BODY_SIZE + 1).to_string(),
+        ))
+        .unwrap();
+        let err = config.validate().unwrap_err().to_string();
+        assert!(err.contains("body_size"), "{}", err);
+    }
+
+    #[test]
+    fn test_static_delay_capped() {
+        let toml = format!(
+            r#"
+[tls]
+acme_enabled = false
+
+[[servers]]
+name = "test"
+listen = [":8080"]
+
+[[servers.routes]]
+[servers.routes.handle]
+type = "static_response"
+delay_ms = {}
+"#,
+            MAX_STATIC_DELAY_MS
+        );
+        let config: Config = toml::from_str(&toml).unwrap();
+        assert!(config.validate().is_ok());
+
+        let config: Config = toml::from_str(&toml.replace(
+            &MAX_STATIC_DELAY_MS.to_string(),
+            &(MAX_STATIC_DELAY_MS + 1).to_string(),
+        ))
+        .unwrap();
+        let err = config.validate().unwrap_err().to_string();
+        assert!(err.contains("delay_ms"), "{}", err);
+    }
+
     #[test]
     fn test_redirect_config() {
         let toml = r#"
@@ -2040,4 +4062,983 @@ body = "Hello"
         assert_eq!(config.servers[0].name, "main");
         assert_eq!(config.servers[0].listen, vec![":80"]);
     }
+
+    #[test]
+    fn test_route_access_log_parse() {
+        let toml = r#"
+[[servers]]
+name = "main"
+listen = [":80"]
+
+[[servers.routes]]
+access_log = false
+[servers.routes.match]
+path = ["/health"]
+[servers.routes.handle]
+type = "static_response"
+body = "OK"
+
+[[servers.routes]]
+[servers.routes.access_log]
+path = "/var/log/avalon/payments.log"
+format = "json"
+[servers.routes.handle]
+type = "static_response"
+body = "Hello"
+"#;
+
+        let config: Config = toml::from_str(toml).unwrap();
+        let routes = &config.servers[0].routes;
+
+        assert!(matches!(routes[0].access_log, Some(RouteAccessLog::Enabled(false))));
+        match &routes[1].access_log {
+            Some(RouteAccessLog::Custom(custom)) => {
+                assert!(custom.enabled);
+                assert_eq!(custom.path.as_deref(), Some("/var/log/avalon/payments.log"));
+                assert_eq!(custom.format.as_deref(), Some("json"));
+            }
+            other => panic!("Expected custom access log, got {:?}", other),
+        }
+        assert!(!config.uses_otlp_access_log());
+
+        let mut config = config;
+        if let Some(RouteAccessLog::Custom(custom)) = &mut config.servers[0].routes[1].access_log {
+            custom.path = Some(OTLP_ACCESS_LOG.to_string());
+        }
+        assert!(config.uses_otlp_access_log());
+    }
+
+    #[test]
+    fn test_upstream_mtls_verify_hostname_default() {
+        let mtls: UpstreamMtlsConfig = toml::from_str("client_cert = \"c.pem\"\nclient_key = \"k.pem\"").unwrap();
+        assert!(mtls.verify_hostname);
+        assert!(!mtls.insecure_skip_verify);
+
+        let mtls: UpstreamMtlsConfig =
+            toml::from_str("client_cert = \"c.pem\"\nclient_key = \"k.pem\"\nverify_hostname = false").unwrap();
+        assert!(!mtls.verify_hostname);
+    }
+
+    #[test]
+    fn test_validation_route_limits() {
+        let route = || RouteConfig {
+            match_rule: MatchConfig::default(),
+            handle: HandlerConfig::StaticResponse(StaticResponseConfig {
+                status: 200,
+                body: String::new(),
+                headers: HashMap::new(),
+                delay_ms: 0,
+                body_size: None,
+            }),
+            trailing_slash: TrailingSlash::Strict,
+            access_log: None,
+            fault_injection: None,
+            response_hints: Vec::new(),
+            block_user_agents: Vec::new(),
+            block_user_agents_status: 403,
+        };
+        let server = |name: &str, routes: usize| ServerConfig {
+            name: name.to_string(),
+            listen: vec![":8080".to_string()],
+            routes: (0..routes).map(|_| route()).collect(),
+            https_redirect: false,
+            http2: Http2Config::default(),
+            access_control: None,
+            unix_socket_mode: None,
+        };
+        let mut config = Config {
+            global: GlobalConfig {
+                max_servers: 2,
+                max_routes: 5,
+                ..Default::default()
+            },
+            tls: TlsConfig {
+                acme_enabled: false,
+                ..Default::default()
+            },
+            servers: vec![server("a", 3), server("b", 2)],
+            ..Default::default()
+        };
+        assert!(config.validate().is_ok());
+
+        config.servers.push(server("c", 0));
+        let err = config.validate().unwrap_err().to_string();
+        assert!(err.contains("max_servers"), "{}", err);
+
+        config.servers.pop();
+        config.servers[1].routes.push(route());
+        let err = config.validate().unwrap_err().to_string();
+        assert!(err.contains("6 routes") && err.contains("max_routes"), "{}", err);
+    }
+
+    #[test]
+    fn test_validation_statsd() {
+        let toml = r#"
+[global.metrics]
+statsd_addr = "127.0.0.1:8125"
+statsd_tags = ["env:prod"]
+
+[tls]
+acme_enabled = false
+
+[[servers]]
+name = "web"
+listen = [":8080"]
+"#;
+        let mut config: Config = toml::from_str(toml).unwrap();
+        assert_eq!(config.global.metrics.statsd_prefix, "avalon");
+        assert_eq!(config.global.metrics.statsd_flush_interval, 10);
+        assert!(config.validate().is_ok());
+
+        config.global.metrics.statsd_addr = Some("127.0.0.1".to_string());
+        assert!(config.validate().is_err());
+
+        config.global.metrics.statsd_addr = Some("statsd:8125".to_string());
+        config.global.metrics.statsd_flush_interval = 0;
+        assert!(config.validate().is_err());
+    }
+
+    #[test]
+    fn test_validation_drain_timeout() {
+        let toml = r#"
+[global]
+grace_period = 30
+
+[tls]
+acme_enabled = false
+"#;
+        let mut config: Config = toml::from_str(toml).unwrap();
+        assert_eq!(config.global.drain_timeout(), 60);
+        assert!(config.validate().is_ok());
+
+        config.global.drain_timeout = Some(10);
+        assert!(config.validate().is_err());
+
+        config.global.drain_timeout = Some(30);
+        assert!(config.validate().is_ok());
+        assert_eq!(config.global.drain_timeout(), 30);
+    }
+
+    #[test]
+    fn test_drain_timeout_follows_long_grace_period() {
+        let toml = r#"
+[global]
+grace_period = 120
+
+[tls]
+acme_enabled = false
+"#;
+        let config: Config = toml::from_str(toml).unwrap();
+        assert!(config.validate().is_ok());
+        assert_eq!(config.global.drain_timeout(), 120);
+    }
+
+    #[test]
+    fn test_connection_limit_defaults() {
+        let toml = r#"
+[tls]
+acme_enabled = false
+"#;
+        let config: Config = toml::from_str(toml).unwrap();
+        assert_eq!(config.global.header_read_timeout, 60);
+        assert_eq!(config.global.min_header_rate, 0);
+        assert_eq!(config.global.max_connections, 0);
+
+        let toml = r#"
+[global]
+header_read_timeout = 10
+min_header_rate = 200
+max_connections = 5000
+
+[tls]
+acme_enabled = false
+"#;
+        let config: Config = toml::from_str(toml).unwrap();
+        assert_eq!(config.global.header_read_timeout, 10);
+        assert_eq!(config.global.min_header_rate, 200);
+        assert_eq!(config.global.max_connections, 5000);
+    }
+
+    #[test]
+    fn test_handler_timeout() {
+        let toml = r#"
+[tls]
+acme_enabled = false
+"#;
+        let config: Config = toml::from_str(toml).unwrap();
+        assert_eq!(config.global.handler_timeout, 30);
+
+        let toml = r#"
+[global]
+handler_timeout = 0
+
+[tls]
+acme_enabled = false
+"#;
+        let config: Config = toml::from_str(toml).unwrap();
+        assert_eq!(config.global.handler_timeout, 0);
+    }
+
+    #[test]
+    fn test_validation_response_hints() {
+        let toml = r#"
+[global]
+response_hints = ["<https://cdn.example.com>; rel=preconnect"]
+
+[tls]
+acme_enabled = false
+
+[[servers]]
+name = "web"
+listen = [":8080"]
+
+[[servers.routes]]
+response_hints = ["</app.css>; rel=preload; as=style"]
+[servers.routes.handle]
+type = "static_response"
+body = "ok"
+"#;
+        let mut config: Config = toml::from_str(toml).unwrap();
+        assert!(config.validate().is_ok());
+        assert_eq!(config.servers[0].routes[0].response_hints.len(), 1);
+
+        config.servers[0].routes[0].response_hints = vec!["https://cdn.example.com".to_string()];
+        assert!(config.validate().is_err());
+
+        config.servers[0].routes[0].response_hints = vec!["<https://cdn.example.com>; as=font".to_string()];
+        assert!(config.validate().is_err());
+    }
+
+    #[test]
+    fn test_expand_env() {
+        std::env::set_var("AVALON_TEST_EXPAND_USER", "svc");
+        std::env::set_var("AVALON_TEST_EXPAND_TOKEN", "s3cr3t");
+        assert_eq!(expand_env("plain").unwrap(), "plain");
+        assert_eq!(expand_env("${AVALON_TEST_EXPAND_TOKEN}").unwrap(), "s3cr3t");
+        assert_eq!(
+            expand_env("${AVALON_TEST_EXPAND_USER}:${AVALON_TEST_EXPAND_TOKEN}!").unwrap(),
+            "svc:s3cr3t!"
+        );
+        assert!(expand_env("${AVALON_TEST_EXPAND_UNSET}").is_err());
+        assert!(expand_env("${AVALON_TEST_EXPAND_TOKEN").is_err());
+    }
+
+    #[test]
+    fn test_validation_upstream_auth() {
+        std::env::set_var("AVALON_TEST_UPSTREAM_TOKEN", "token-from-env");
+        let toml = r#"
+[tls]
+acme_enabled = false
+
+[[servers]]
+name = "test"
+listen = [":8080"]
+
+[[servers.routes]]
+[servers.routes.handle]
+type = "reverse_proxy"
+upstreams = ["127.0.0.1:9090"]
+
+[servers.routes.handle.upstream_auth]
+bearer_token = "${AVALON_TEST_UPSTREAM_TOKEN}"
+"#;
+        let mut config: Config = toml::from_str(toml).unwrap();
+        assert!(config.validate().is_ok());
+
+        let HandlerConfig::ReverseProxy(proxy) = &mut config.servers[0].routes[0].handle else {
+            panic!("Expected ReverseProxy handler");
+        };
+        let upstream_auth = proxy.upstream_auth.as_mut().unwrap();
+        assert_eq!(upstream_auth.header, "Authorization");
+
+        upstream_auth.basic = Some(BasicAuthCredential {
+            username: "svc".to_string(),
+            password: "pw".to_string(),
+        });
+        assert!(config.validate().is_err());
+
+        let HandlerConfig::ReverseProxy(proxy) = &mut config.servers[0].routes[0].handle else {
+            unreachable!();
+        };
+        let upstream_auth = proxy.upstream_auth.as_mut().unwrap();
+        upstream_auth.basic = None;
+        upstream_auth.bearer_token = Some("${AVALON_TEST_UPSTREAM_UNSET}".to_string());
+        assert!(config.validate().is_err());
+    }
+
+    #[test]
+    fn test_validation_jwt_secret_env() {
+        std::env::set_var("AVALON_TEST_JWT_SECRET", "jwt-from-env");
+        let config_with = |secret: &str| {
+            let toml = format!(
+                r#"
+[tls]
+acme_enabled = false
+
+[[servers]]
+listen = [":8080"]
+
+[[servers.routes]]
+[servers.routes.handle]
+type = "reverse_proxy"
+upstreams = ["127.0.0.1:9090"]
+
+[servers.routes.handle.auth.jwt]
+secret = "{}"
+"#,
+                secret
+            );
+            toml::from_str::<Config>(&toml).unwrap()
+        };
+
+        assert!(config_with("${AVALON_TEST_JWT_SECRET}").validate().is_ok());
+        assert!(config_with("${AVALON_TEST_JWT_UNSET}").validate().is_err());
+    }
+
+    #[test]
+    fn test_validation_csp_nonce() {
+        let toml = r#"
+[global.security_headers]
+enabled = true
+content_security_policy = "script-src 'self' 'nonce-{nonce}'"
+
+[global.security_headers.csp_nonce]
+
+[tls]
+acme_enabled = false
+"#;
+        let mut config: Config = toml::from_str(toml).unwrap();
+        assert!(config.validate().is_ok());
+        let nonce = config.global.security_headers.csp_nonce.as_ref().unwrap();
+        assert_eq!(nonce.request_header, "X-CSP-Nonce");
+        assert_eq!(nonce.response_header, "Content-Security-Policy");
+
+        config.global.security_headers.content_security_policy = Some("script-src 'self'".to_string());
+        assert!(config.validate().is_err());
+
+        config.global.security_headers.content_security_policy = None;
+        assert!(config.validate().is_err());
+    }
+
+    #[test]
+    fn test_validation_block_user_agents() {
+        let toml = r#"
+[tls]
+acme_enabled = false
+
+[[servers]]
+name = "web"
+listen = [":8080"]
+
+[[servers.routes]]
+block_user_agents = ["(?i)bot", "^curl/"]
+[servers.routes.match]
+user_agent = ["Mozilla/"]
+[servers.routes.handle]
+type = "static_response"
+body = "ok"
+"#;
+        let mut config: Config = toml::from_str(toml).unwrap();
+        assert!(config.validate().is_ok());
+        let route = &config.servers[0].routes[0];
+        assert_eq!(route.block_user_agents.len(), 2);
+        assert_eq!(route.block_user_agents_status, 403);
+        assert_eq!(route.match_rule.user_agent, Some(vec!["Mozilla/".to_string()]));
+
+        config.servers[0].routes[0].block_user_agents_status = 429;
+        assert!(config.validate().is_ok());
+
+        config.servers[0].routes[0].block_user_agents_status = 404;
+        assert!(config.validate().is_err());
+    }
+
+    #[test]
+    fn test_validation_client_cert_match() {
+        let toml = r#"
+[tls]
+acme_enabled = false
+client_ca = "/etc/avalon/clients.pem"
+
+[[servers]]
+name = "web"
+listen = [":443"]
+
+[[servers.routes]]
+[servers.routes.match]
+path = ["/admin"]
+client_cert = true
+client_cert_organization = ["Acme Ops"]
+[servers.routes.handle]
+type = "static_response"
+body = "admin"
+"#;
+        let mut config: Config = toml::from_str(toml).unwrap();
+        assert!(config.validate().is_ok());
+        assert_eq!(config.tls.client_ca, Some(PathBuf::from("/etc/avalon/clients.pem")));
+        assert_eq!(config.servers[0].routes[0].match_rule.client_cert, Some(true));
+
+        // An organization can't be required of a connection without a certificate
+        config.servers[0].routes[0].match_rule.client_cert = Some(false);
+        assert!(config.validate().is_err());
+        config.servers[0].routes[0].match_rule.client_cert = None;
+        assert!(config.validate().is_ok());
+
+        // Certificates are only requested with a client CA
+        config.tls.client_ca = None;
+        assert!(config.validate().is_err());
+        config.servers[0].routes[0].match_rule.client_cert = Some(false);
+        config.servers[0].routes[0].match_rule.client_cert_organization = None;
+        assert!(config.validate().is_ok());
+
+        config.servers[0].routes[0].match_rule.any_of = Some(vec![MatchConfig {
+            client_cert: Some(true),
+            ..Default::default()
+        }]);
+        assert!(config.validate().is_err());
+    }
+
+    #[test]
+    fn test_strict_header_validation() {
+        let toml = r#"
+[tls]
+acme_enabled = false
+"#;
+        let config: Config = toml::from_str(toml).unwrap();
+        assert_eq!(config.global.strict_header_validation, HeaderValidation::Off);
+
+        let toml = r#"
+[global]
+strict_header_validation = "reject"
+
+[tls]
+acme_enabled = false
+"#;
+        let config: Config = toml::from_str(toml).unwrap();
+        assert_eq!(config.global.strict_header_validation, HeaderValidation::Reject);
+
+        let toml = r#"
+[global]
+strict_header_validation = "strip"
+
+[tls]
+acme_enabled = false
+"#;
+        assert!(toml::from_str::<Config>(toml).is_err());
+    }
+
+    #[test]
+    fn test_validation_status_map() {
+        let toml = r#"
+[tls]
+acme_enabled = false
+
+[[servers]]
+name = "web"
+listen = [":8080"]
+
+[[servers.routes]]
+[servers.routes.handle]
+type = "reverse_proxy"
+upstreams = ["127.0.0.1:3000"]
+
+[[servers.routes.handle.rewrite.status_map]]
+from = 500
+to = 503
+
+[[servers.routes.handle.rewrite.status_map]]
+from = 404
+to = 200
+body = "not here yet"
+"#;
+        fn status_map(config: &mut Config) -> &mut Vec<StatusMapping> {
+            match &mut config.servers[0].routes[0].handle {
+                HandlerConfig::ReverseProxy(proxy_config) => &mut proxy_config.rewrite.as_mut().unwrap().status_map,
+                _ => panic!("expected reverse_proxy"),
+            }
+        }
+
+        let mut config: Config = toml::from_str(toml).unwrap();
+        assert!(config.validate().is_ok());
+        assert_eq!(status_map(&mut config)[1].content_type, "text/plain; charset=utf-8");
+
+        // No body allowed with 204
+        status_map(&mut config)[1].to = 204;
+        assert!(config.validate().is_err());
+
+        // Duplicate upstream status
+        status_map(&mut config)[1].to = 200;
+        status_map(&mut config)[1].from = 500;
+        assert!(config.validate().is_err());
+
+        status_map(&mut config)[1].from = 404;
+        status_map(&mut config)[0].to = 600;
+        assert!(config.validate().is_err());
+    }
+
+    #[test]
+    fn test_validation_http2() {
+        let toml = r#"
+[tls]
+acme_enabled = false
+
+[[servers]]
+name = "web"
+listen = [":443"]
+
+[[servers]]
+name = "api"
+listen = [":8443"]
+
+[servers.http2]
+max_concurrent_streams = 32
+max_frame_size = 32768
+initial_window_size = 1048576
+"#;
+        let mut config: Config = toml::from_str(toml).unwrap();
+        assert!(config.validate().is_ok());
+        assert_eq!(config.servers[0].http2, Http2Config::default());
+        assert_eq!(config.servers[0].http2.max_concurrent_streams, 100);
+        assert_eq!(config.servers[1].http2.max_concurrent_streams, 32);
+        assert_eq!(config.servers[1].http2.max_frame_size, Some(32768));
+        assert_eq!(config.servers[1].http2.initial_window_size, Some(1048576));
+
+        config.servers[1].http2.max_frame_size = Some(1024);
+        assert!(config.validate().is_err());
+
+        config.servers[1].http2.max_frame_size = None;
+        config.servers[1].http2.initial_window_size = Some(u32::MAX);
+        assert!(config.validate().is_err());
+
+        config.servers[1].http2.initial_window_size = None;
+        config.servers[1].http2.max_concurrent_streams = 0;
+        assert!(config.validate().is_err());
+    }
+
+    #[test]
+    fn test_validation_any_of() {
+        let toml = r#"
+[tls]
+acme_enabled = false
+
+[[servers]]
+listen = [":8080"]
+
+[[servers.routes]]
+[[servers.routes.match.any_of]]
+host = ["a.com"]
+[[servers.routes.match.any_of]]
+host = ["b.com"]
+[servers.routes.handle]
+type = "static_response"
+body = "ok"
+"#;
+        let mut config: Config = toml::from_str(toml).unwrap();
+        assert!(config.validate().is_ok());
+
+        let groups = config.servers[0].routes[0].match_rule.any_of.as_mut().unwrap();
+        groups[1].user_agent = Some(vec!["bot".to_string()]);
+        assert!(config.validate().is_err());
+
+        config.servers[0].routes[0].match_rule.any_of = Some(Vec::new());
+        assert!(config.validate().is_err());
+    }
+
+    #[test]
+    fn test_validation_request_buffer_limit() {
+        let toml = r#"
+[tls]
+acme_enabled = false
+
+[[servers]]
+name = "web"
+listen = [":8080"]
+
+[[servers.routes]]
+[servers.routes.handle]
+type = "reverse_proxy"
+upstreams = ["127.0.0.1:3000"]
+max_request_body_size = 4194304
+request_buffer_limit = 65536
+"#;
+        let config: Config = toml::from_str(toml).unwrap();
+        assert!(config.validate().is_ok());
+
+        let config: Config = toml::from_str(&toml.replace("4194304", "32768")).unwrap();
+        assert!(config.validate().is_err());
+
+        // Bodies past Pingora's replay buffer can't be retried
+        let config: Config = toml::from_str(&toml.replace("65536", "1048576")).unwrap();
+        assert!(config.validate().is_err());
+
+        // Unlimited bodies can still be buffered up to the limit
+        let config: Config = toml::from_str(&toml.replace("4194304", "0")).unwrap();
+        assert!(config.validate().is_ok());
+    }
+
+    #[test]
+    fn test_retry_backoff_options() {
+        let toml = r#"
+[tls]
+acme_enabled = false
+
+[[servers]]
+name = "web"
+listen = [":8080"]
+
+[[servers.routes]]
+[servers.routes.handle]
+type = "reverse_proxy"
+upstreams = ["127.0.0.1:3000", "127.0.0.1:3001"]
+lb_try_duration = 5000
+lb_try_backoff = "exponential"
+lb_try_jitter = 0.25
+"#;
+        let config: Config = toml::from_str(toml).unwrap();
+        assert!(config.validate().is_ok());
+        match &config.servers[0].routes[0].handle {
+            HandlerConfig::ReverseProxy(proxy) => {
+                assert_eq!(proxy.lb_try_backoff, RetryBackoffMode::Exponential);
+                assert_eq!(proxy.lb_try_jitter, 0.25);
+                assert_eq!(proxy.lb_try_interval, 250);
+            }
+            _ => panic!("expected reverse_proxy"),
+        }
+
+        let config: Config = toml::from_str(&toml.replace("0.25", "1.5")).unwrap();
+        assert!(config.validate().is_err());
+
+        let config: Config = toml::from_str(&toml.replace("lb_try_backoff = \"exponential\"\n", "")).unwrap();
+        match &config.servers[0].routes[0].handle {
+            HandlerConfig::ReverseProxy(proxy) => assert_eq!(proxy.lb_try_backoff, RetryBackoffMode::Fixed),
+            _ => panic!("expected reverse_proxy"),
+        }
+    }
+
+    #[test]
+    fn test_brotli_levels() {
+        let toml = r#"
+[tls]
+acme_enabled = false
+
+[global.compression]
+brotli_dynamic_level = 4
+brotli_static_level = 11
+"#;
+        let mut config: Config = toml::from_str(toml).unwrap();
+        assert!(config.validate().is_ok());
+        assert_eq!(config.global.compression.brotli_dynamic_level, Some(4));
+        assert_eq!(config.global.compression.brotli_static_level, Some(11));
+
+        config.global.compression.brotli_static_level = Some(12);
+        assert!(config.validate().is_err());
+
+        let config: Config = toml::from_str("[tls]\nacme_enabled = false\n").unwrap();
+        assert_eq!(config.global.compression.brotli_dynamic_level, None);
+    }
+
+    #[test]
+    fn test_access_control_parsing() {
+        let toml = r#"
+[tls]
+acme_enabled = false
+
+[[servers]]
+listen = [":8080"]
+
+[[servers.access_control.rules]]
+ip = ["203.0.113.0/24"]
+action = "deny"
+
+[[servers.access_control.rules]]
+methods = ["POST", "PUT", "DELETE"]
+action = "require_auth"
+
+[[servers.access_control.rules]]
+action = "allow"
+
+[[servers.access_control.auth.basic]]
+username = "admin"
+password = "secret"
+
+[[servers.routes]]
+[servers.routes.handle]
+type = "static_response"
+body = "ok"
+"#;
+        let config: Config = toml::from_str(toml).unwrap();
+        config.validate().unwrap();
+
+        let access = config.servers[0].access_control.as_ref().unwrap();
+        let actions: Vec<_> = access.rules.iter().map(|r| r.action).collect();
+        assert_eq!(actions, vec![AccessAction::Deny, AccessAction::RequireAuth, AccessAction::Allow]);
+        assert_eq!(access.rules[0].ip, vec!["203.0.113.0/24"]);
+        assert!(access.rules[2].methods.is_empty());
+        assert_eq!(access.auth.as_ref().unwrap().basic.len(), 1);
+    }
+
+    #[test]
+    fn test_access_control_validation() {
+        let config_with = |rules: &str| {
+            let toml = format!(
+                r#"
+[tls]
+acme_enabled = false
+
+[[servers]]
+listen = [":8080"]
+{}
+
+[[servers.routes]]
+[servers.routes.handle]
+type = "static_response"
+body = "ok"
+"#,
+                rules
+            );
+            toml::from_str::<Config>(&toml).unwrap()
+        };
+
+        let bad_ip = config_with("[[servers.access_control.rules]]\nip = [\"10.0.0.0/33\"]\naction = \"deny\"");
+        assert!(bad_ip.validate().is_err());
+
+        let no_auth = config_with("[[servers.access_control.rules]]\naction = \"require_auth\"");
+        assert!(no_auth.validate().is_err());
+
+        let ok = config_with("[[servers.access_control.rules]]\nip = [\"10.0.0.1\", \"::1\"]\naction = \"allow\"");
+        assert!(ok.validate().is_ok());
+    }
+
+    #[test]
+    fn test_jwt_claim_affinity() {
+        let config_with = |extra: &str| {
+            let toml = format!(
+                r#"
+[tls]
+acme_enabled = false
+
+[[servers]]
+listen = [":8080"]
+
+[[servers.routes]]
+[servers.routes.handle]
+type = "reverse_proxy"
+upstreams = ["127.0.0.1:3000", "127.0.0.1:3001"]
+
+[servers.routes.handle.session_affinity]
+affinity_type = "jwt_claim"
+{}
+"#,
+                extra
+            );
+            toml::from_str::<Config>(&toml).unwrap()
+        };
+
+        let config = config_with("claim = \"tenant_id\"\n\n[servers.routes.handle.auth.jwt]\nsecret = \"s3cret\"");
+        config.validate().unwrap();
+        match &config.servers[0].routes[0].handle {
+            HandlerConfig::ReverseProxy(proxy) => {
+                let affinity = proxy.session_affinity.as_ref().unwrap();
+                assert_eq!(affinity.claim.as_deref(), Some("tenant_id"));
+            }
+            _ => panic!("expected reverse_proxy"),
+        }
+
+        // A claim name and a verified JWT are both required
+        assert!(config_with("\n[servers.routes.handle.auth.jwt]\nsecret = \"s3cret\"").validate().is_err());
+        assert!(config_with("claim = \"tenant_id\"").validate().is_err());
+    }
+
+    #[test]
+    fn test_rate_limit_rejection_options() {
+        let toml = r#"
+[tls]
+acme_enabled = false
+
+[[servers]]
+listen = [":8080"]
+
+[[servers.routes]]
+[servers.routes.handle]
+type = "reverse_proxy"
+upstreams = ["127.0.0.1:3000"]
+
+[servers.routes.handle.rate_limit]
+max_requests = 10
+reject_body = '{"error": "rate_limited"}'
+retry_after = 30
+reject_headers = { "X-RateLimit-Limit" = "10" }
+"#;
+        let mut config: Config = toml::from_str(toml).unwrap();
+        config.validate().unwrap();
+        let HandlerConfig::ReverseProxy(proxy) = &mut config.servers[0].routes[0].handle else {
+            panic!("expected reverse_proxy");
+        };
+        let rate_limit = proxy.rate_limit.as_mut().unwrap();
+        assert_eq!(rate_limit.reject_status, 429);
+        assert_eq!(rate_limit.reject_body.as_deref(), Some(r#"{"error": "rate_limited"}"#));
+        assert_eq!(rate_limit.retry_after, Some(30));
+        assert_eq!(rate_limit.reject_headers.get("X-RateLimit-Limit").map(String::as_str), Some("10"));
+
+        rate_limit.reject_status = 200;
+        assert!(config.validate().is_err());
+    }
+
+    #[test]
+    fn test_validation_correlation_headers() {
+        let toml = r#"
+[global]
+correlation_headers = ["X-Request-Id", "X-Correlation-Id", "traceparent"]
+
+[tls]
+acme_enabled = false
+"#;
+        let mut config: Config = toml::from_str(toml).unwrap();
+        assert!(config.validate().is_ok());
+        assert_eq!(config.global.correlation_headers.len(), 3);
+        assert!(Config::default().global.correlation_headers.is_empty());
+
+        config.global.correlation_headers = vec!["X Request Id".to_string()];
+        assert!(config.validate().is_err());
+        config.global.correlation_headers = vec![String::new()];
+        assert!(config.validate().is_err());
+    }
+
+    #[test]
+    fn test_validation_body_replace() {
+        let toml = r#"
+[tls]
+acme_enabled = false
+
+[[servers]]
+name = "web"
+listen = [":8080"]
+
+[[servers.routes]]
+[servers.routes.handle]
+type = "reverse_proxy"
+upstreams = ["127.0.0.1:3000"]
+
+[servers.routes.handle.rewrite]
+max_rewrite_body_size = 65536
+
+[[servers.routes.handle.rewrite.response_body_replace]]
+from = "http://internal:3000"
+to = "https://example.com"
+"#;
+        let mut config: Config = toml::from_str(toml).unwrap();
+        assert!(config.validate().is_ok());
+
+        let HandlerConfig::ReverseProxy(proxy) = &mut config.servers[0].routes[0].handle else {
+            panic!("Expected ReverseProxy handler");
+        };
+        let rewrite = proxy.rewrite.as_mut().unwrap();
+        assert_eq!(rewrite.max_rewrite_body_size, Some(65536));
+        assert_eq!(rewrite.response_body_replace[0].to, "https://example.com");
+
+        rewrite.response_body_replace[0].from = String::new();
+        assert!(config.validate().is_err());
+    }
+
+    #[test]
+    fn test_host_header_modes() {
+        let toml = r#"
+[tls]
+acme_enabled = false
+
+[[servers]]
+name = "web"
+listen = [":8080"]
+
+[[servers.routes]]
+[servers.routes.handle]
+type = "reverse_proxy"
+upstreams = ["127.0.0.1:3000"]
+
+[[servers.routes]]
+[servers.routes.handle]
+type = "reverse_proxy"
+upstreams = ["127.0.0.1:3001"]
+host_header = "upstream"
+
+[[servers.routes]]
+[servers.routes.handle]
+type = "reverse_proxy"
+upstreams = ["127.0.0.1:3002"]
+host_header = "api.internal"
+"#;
+        let mut config: Config = toml::from_str(toml).unwrap();
+        assert!(config.validate().is_ok());
+
+        let modes: Vec<HostHeader> = config.servers[0]
+            .routes
+            .iter()
+            .map(|route| match &route.handle {
+                HandlerConfig::ReverseProxy(proxy) => proxy.host_header.clone(),
+                _ => panic!("Expected ReverseProxy handler"),
+            })
+            .collect();
+        assert_eq!(
+            modes,
+            vec![HostHeader::Preserve, HostHeader::Upstream, HostHeader::Literal("api.internal".to_string())]
+        );
+
+        let HandlerConfig::ReverseProxy(proxy) = &mut config.servers[0].routes[2].handle else {
+            panic!("Expected ReverseProxy handler");
+        };
+        proxy.host_header = HostHeader::Literal("bad host".to_string());
+        assert!(config.validate().is_err());
+    }
+
+    #[test]
+    fn test_cache_status_header() {
+        let toml = r#"
+[global.cache]
+enabled = true
+status_header = ""
+stale_if_error = true
+
+[tls]
+acme_enabled = false
+"#;
+        let mut config: Config = toml::from_str(toml).unwrap();
+        assert!(config.validate().is_ok());
+        assert!(config.global.cache.status_header.is_empty());
+        assert!(config.global.cache.stale_if_error);
+        assert_eq!(CacheOptions::default().status_header, "X-Cache");
+
+        config.global.cache.status_header = "X Cache".to_string();
+        assert!(config.validate().is_err());
+    }
+
+    #[test]
+    fn test_unix_socket_listen() {
+        let toml = r#"
+[tls]
+acme_enabled = false
+
+[[servers]]
+name = "sidecar"
+listen = ["unix:/run/avalon.sock", ":8080"]
+unix_socket_mode = 0o660
+
+[[servers.routes]]
+[servers.routes.handle]
+type = "static_response"
+body = "ok"
+"#;
+        let mut config: Config = toml::from_str(toml).unwrap();
+        assert!(config.validate().is_ok());
+        assert_eq!(config.servers[0].unix_socket_mode, Some(0o660));
+        assert_eq!(unix_socket_path(&config.servers[0].listen[0]), Some(Path::new("/run/avalon.sock")));
+        assert_eq!(unix_socket_path(":8080"), None);
+
+        config.servers[0].listen = vec!["unix:@avalon".to_string()];
+        assert!(config.validate().is_err());
+        config.servers[0].listen = vec!["unix:".to_string()];
+        assert!(config.validate().is_err());
+
+        config.servers[0].listen = vec!["unix:/run/avalon.sock".to_string()];
+        config.servers[0].unix_socket_mode = Some(0o1777);
+        assert!(config.validate().is_err());
+    }
 }
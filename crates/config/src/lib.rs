@@ -4,7 +4,9 @@
 //! functionality for the avalon web server.
 
 pub mod config;
+pub mod export;
 pub mod watcher;
 
 pub use config::*;
-pub use watcher::{ConfigWatcher, ReloadManager};
+pub use export::ExportFormat;
+pub use watcher::{ConfigWatcher, ReloadCallback, ReloadManager};
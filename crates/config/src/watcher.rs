@@ -1,16 +1,21 @@
 //! Configuration file watcher for hot reload
 
-use notify::{Config, Event, RecommendedWatcher, RecursiveMode, Watcher};
+use crate::config::{Config, ConfigError};
+use notify::{Config as NotifyConfig, Event, RecommendedWatcher, RecursiveMode, Watcher};
 use std::path::{Path, PathBuf};
 use std::sync::mpsc::{channel, Receiver};
 use std::time::Duration;
 use tracing::{debug, error, info, warn};
 
+/// Callback invoked with the new configuration after a successful reload
+pub type ReloadCallback = Box<dyn Fn(&Config) + Send + Sync>;
+
 /// Configuration file watcher
 pub struct ConfigWatcher {
     config_path: PathBuf,
     _watcher: RecommendedWatcher,
     rx: Receiver<Result<Event, notify::Error>>,
+    callbacks: Vec<ReloadCallback>,
 }
 
 impl ConfigWatcher {
@@ -23,7 +28,7 @@ impl ConfigWatcher {
             move |res| {
                 let _ = tx.send(res);
             },
-            Config::default().with_poll_interval(Duration::from_secs(2)),
+            NotifyConfig::default().with_poll_interval(Duration::from_secs(2)),
         )?;
 
         let watch_path = config_path.parent().unwrap_or_else(|| Path::new("."));
@@ -35,9 +40,34 @@ impl ConfigWatcher {
             config_path,
             _watcher: watcher,
             rx,
+            callbacks: Vec::new(),
         })
     }
 
+    /// Register a callback fired with the new config after each successful reload
+    pub fn on_reload<F>(&mut self, callback: F)
+    where
+        F: Fn(&Config) + Send + Sync + 'static,
+    {
+        self.callbacks.push(Box::new(callback));
+    }
+
+    /// Load and validate the configuration file, then notify callbacks
+    ///
+    /// Callbacks are not called when the file fails to load or validate.
+    pub fn reload(&self) -> Result<Config, ConfigError> {
+        let config = Config::load(&self.config_path)?;
+        for callback in &self.callbacks {
+            callback(&config);
+        }
+        Ok(config)
+    }
+
+    /// Whether any reload callbacks are registered
+    pub fn has_callbacks(&self) -> bool {
+        !self.callbacks.is_empty()
+    }
+
     /// Poll for configuration changes (non-blocking)
     pub fn poll(&self) -> bool {
         let mut changed = false;
@@ -112,6 +142,14 @@ impl ReloadManager {
         Ok(Self { watcher })
     }
 
+    /// Register a callback fired with the new config after each successful reload
+    pub fn on_reload<F>(&mut self, callback: F)
+    where
+        F: Fn(&Config) + Send + Sync + 'static,
+    {
+        self.watcher.on_reload(callback);
+    }
+
     /// Start the reload loop in a background task
     pub fn start<F>(self, callback: F) -> tokio::task::JoinHandle<()>
     where
@@ -124,6 +162,12 @@ impl ReloadManager {
                 if self.watcher.poll() {
                     info!("Configuration change detected, triggering reload");
                     callback(self.watcher.config_path());
+
+                    if self.watcher.has_callbacks() {
+                        if let Err(e) = self.watcher.reload() {
+                            warn!(error = %e, "Reloaded configuration is invalid, keeping current");
+                        }
+                    }
                 }
             }
         })
@@ -175,6 +219,40 @@ mod tests {
         assert!(!watcher.is_relevant_event(&other_event));
     }
 
+    #[test]
+    fn test_on_reload_receives_new_config() {
+        use std::sync::{Arc, Mutex};
+
+        let temp_dir = TempDir::new().unwrap();
+        let config_path = temp_dir.path().join("config.toml");
+        fs::write(&config_path, "[tls]\nacme_enabled = false\n\n[[servers]]\nname = \"old\"\nlisten = [\":8080\"]\n").unwrap();
+
+        let mut watcher = ConfigWatcher::new(&config_path).unwrap();
+        let seen = Arc::new(Mutex::new(Vec::new()));
+        let seen_clone = seen.clone();
+        watcher.on_reload(move |config| {
+            seen_clone.lock().unwrap().push(config.servers[0].name.clone());
+        });
+
+        fs::write(&config_path, "[tls]\nacme_enabled = false\n\n[[servers]]\nname = \"new\"\nlisten = [\":9090\"]\n").unwrap();
+        let config = watcher.reload().unwrap();
+
+        assert_eq!(config.servers[0].listen, vec![":9090"]);
+        assert_eq!(*seen.lock().unwrap(), vec!["new".to_string()]);
+    }
+
+    #[test]
+    fn test_on_reload_skipped_for_invalid_config() {
+        let temp_dir = TempDir::new().unwrap();
+        let config_path = temp_dir.path().join("config.toml");
+        fs::write(&config_path, "[[servers]]\nlisten = []\n").unwrap();
+
+        let mut watcher = ConfigWatcher::new(&config_path).unwrap();
+        watcher.on_reload(|_| panic!("callback must not run for an invalid config"));
+
+        assert!(watcher.reload().is_err());
+    }
+
     #[test]
     fn test_reload_manager_creation() {
         let temp_dir = TempDir::new().unwrap();
@@ -1,5 +1,6 @@
 //! Plugin error types
 
+use std::time::Duration;
 use thiserror::Error;
 
 /// Plugin error type
@@ -26,6 +27,13 @@ pub enum PluginError {
     #[error("Hook execution error: {0}")]
     HookError(String),
 
+    #[error("Plugin {plugin} timed out after {timeout:?} in {phase} hook")]
+    HookTimeout {
+        plugin: String,
+        phase: &'static str,
+        timeout: Duration,
+    },
+
     #[error("Plugin already registered: {0}")]
     AlreadyRegistered(String),
 
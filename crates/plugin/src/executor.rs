@@ -1,23 +1,60 @@
 //! Hook executor for running hooks in priority order
 
 use crate::context::PluginContext;
-use crate::error::Result;
+use crate::error::{PluginError, Result};
 use crate::hooks::*;
 use crate::registry::PluginRegistry;
 use bytes::Bytes;
+use std::future::Future;
 use std::sync::Arc;
 use std::time::Duration;
 use tracing::{debug, trace, warn};
 
 /// Hook executor that runs hooks in priority order
+#[derive(Clone)]
 pub struct HookExecutor {
     registry: Arc<PluginRegistry>,
+    /// Time each async hook may take before it's abandoned
+    timeout: Option<Duration>,
 }
 
 impl HookExecutor {
     /// Create a new hook executor
     pub fn new(registry: Arc<PluginRegistry>) -> Self {
-        Self { registry }
+        Self {
+            registry,
+            timeout: None,
+        }
+    }
+
+    /// Fail hooks that don't complete within `timeout`
+    ///
+    /// A stalled hook fails with [`PluginError::HookTimeout`] naming the
+    /// plugin, so one hung plugin can't hold a request (and the worker
+    /// serving it) indefinitely. Synchronous hooks can't be interrupted and
+    /// aren't covered.
+    pub fn with_timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = Some(timeout);
+        self
+    }
+
+    /// Await the hook of `plugin`, giving up once it exceeds the hook timeout
+    async fn guard<F: Future>(
+        &self,
+        plugin: &str,
+        phase: &'static str,
+        hook: F,
+    ) -> Result<F::Output> {
+        let Some(timeout) = self.timeout else {
+            return Ok(hook.await);
+        };
+        tokio::time::timeout(timeout, hook)
+            .await
+            .map_err(|_| PluginError::HookTimeout {
+                plugin: plugin.to_string(),
+                phase,
+                timeout,
+            })
     }
 
     /// Run all early request hooks
@@ -29,8 +66,15 @@ impl HookExecutor {
         let hooks = self.registry.get_early_request_hooks();
         trace!(count = hooks.len(), "Running early request hooks");
 
-        for hook in hooks {
-            match hook.on_early_request(request, ctx).await {
+        for (plugin, hook) in hooks {
+            let outcome = self
+                .guard(
+                    &plugin,
+                    "early_request",
+                    hook.on_early_request(request, ctx),
+                )
+                .await;
+            match outcome.and_then(|result| result) {
                 Ok(HookAction::Continue) => continue,
                 Ok(HookAction::SkipPhase) => {
                     debug!("Early request hook requested phase skip");
@@ -41,7 +85,7 @@ impl HookExecutor {
                     return Ok(HookAction::ShortCircuit);
                 }
                 Err(e) => {
-                    warn!(error = %e, "Early request hook error");
+                    warn!(plugin = %plugin, error = %e, "Early request hook error");
                     return Err(e);
                 }
             }
@@ -59,8 +103,11 @@ impl HookExecutor {
         let hooks = self.registry.get_request_filter_hooks();
         trace!(count = hooks.len(), "Running request filter hooks");
 
-        for hook in hooks {
-            match hook.on_request(request, ctx).await {
+        for (plugin, hook) in hooks {
+            let outcome = self
+                .guard(&plugin, "request_filter", hook.on_request(request, ctx))
+                .await;
+            match outcome.and_then(|result| result) {
                 Ok(HookAction::Continue) => continue,
                 Ok(HookAction::SkipPhase) => {
                     debug!("Request filter hook requested phase skip");
@@ -71,7 +118,7 @@ impl HookExecutor {
                     return Ok(HookAction::ShortCircuit);
                 }
                 Err(e) => {
-                    warn!(error = %e, "Request filter hook error");
+                    warn!(plugin = %plugin, error = %e, "Request filter hook error");
                     return Err(e);
                 }
             }
@@ -89,15 +136,18 @@ impl HookExecutor {
         let hooks = self.registry.get_route_hooks();
         trace!(count = hooks.len(), "Running route hooks");
 
-        for hook in hooks {
-            match hook.select_route(request, ctx).await {
+        for (plugin, hook) in hooks {
+            let outcome = self
+                .guard(&plugin, "route", hook.select_route(request, ctx))
+                .await;
+            match outcome.and_then(|result| result) {
                 Ok(Some(route)) => {
                     debug!(route = %route, "Route hook selected custom route");
                     return Ok(Some(route));
                 }
                 Ok(None) => continue,
                 Err(e) => {
-                    warn!(error = %e, "Route hook error");
+                    warn!(plugin = %plugin, error = %e, "Route hook error");
                     return Err(e);
                 }
             }
@@ -115,15 +165,22 @@ impl HookExecutor {
         let hooks = self.registry.get_upstream_select_hooks();
         trace!(count = hooks.len(), "Running upstream select hooks");
 
-        for hook in hooks {
-            match hook.select_upstream(available, ctx).await {
+        for (plugin, hook) in hooks {
+            let outcome = self
+                .guard(
+                    &plugin,
+                    "upstream_select",
+                    hook.select_upstream(available, ctx),
+                )
+                .await;
+            match outcome.and_then(|result| result) {
                 Ok(Some(selection)) => {
                     debug!(upstream = %selection.address, "Upstream select hook chose upstream");
                     return Ok(Some(selection));
                 }
                 Ok(None) => continue,
                 Err(e) => {
-                    warn!(error = %e, "Upstream select hook error");
+                    warn!(plugin = %plugin, error = %e, "Upstream select hook error");
                     return Err(e);
                 }
             }
@@ -141,8 +198,15 @@ impl HookExecutor {
         let hooks = self.registry.get_upstream_request_hooks();
         trace!(count = hooks.len(), "Running upstream request hooks");
 
-        for hook in hooks {
-            match hook.on_upstream_request(request, ctx).await {
+        for (plugin, hook) in hooks {
+            let outcome = self
+                .guard(
+                    &plugin,
+                    "upstream_request",
+                    hook.on_upstream_request(request, ctx),
+                )
+                .await;
+            match outcome.and_then(|result| result) {
                 Ok(HookAction::Continue) => continue,
                 Ok(HookAction::SkipPhase) => {
                     debug!("Upstream request hook requested phase skip");
@@ -153,7 +217,7 @@ impl HookExecutor {
                     return Ok(HookAction::ShortCircuit);
                 }
                 Err(e) => {
-                    warn!(error = %e, "Upstream request hook error");
+                    warn!(plugin = %plugin, error = %e, "Upstream request hook error");
                     return Err(e);
                 }
             }
@@ -171,8 +235,11 @@ impl HookExecutor {
         let hooks = self.registry.get_response_filter_hooks();
         trace!(count = hooks.len(), "Running response filter hooks");
 
-        for hook in hooks {
-            match hook.on_response(response, ctx).await {
+        for (plugin, hook) in hooks {
+            let outcome = self
+                .guard(&plugin, "response_filter", hook.on_response(response, ctx))
+                .await;
+            match outcome.and_then(|result| result) {
                 Ok(HookAction::Continue) => continue,
                 Ok(HookAction::SkipPhase) => {
                     debug!("Response filter hook requested phase skip");
@@ -183,7 +250,7 @@ impl HookExecutor {
                     return Ok(HookAction::ShortCircuit);
                 }
                 Err(e) => {
-                    warn!(error = %e, "Response filter hook error");
+                    warn!(plugin = %plugin, error = %e, "Response filter hook error");
                     return Err(e);
                 }
             }
@@ -204,7 +271,7 @@ impl HookExecutor {
 
         let mut max_delay: Option<Duration> = None;
 
-        for hook in hooks {
+        for (plugin, hook) in hooks {
             match hook.on_body_chunk(body, end_of_stream, ctx) {
                 Ok(delay) => {
                     if let Some(d) = delay {
@@ -212,7 +279,7 @@ impl HookExecutor {
                     }
                 }
                 Err(e) => {
-                    warn!(error = %e, "Response body hook error");
+                    warn!(plugin = %plugin, error = %e, "Response body hook error");
                     return Err(e);
                 }
             }
@@ -232,8 +299,11 @@ impl HookExecutor {
         let hooks = self.registry.get_logging_hooks();
         trace!(count = hooks.len(), "Running logging hooks");
 
-        for hook in hooks {
-            hook.on_request_complete(request, response, error, ctx).await;
+        for (plugin, hook) in hooks {
+            let completed = hook.on_request_complete(request, response, error, ctx);
+            if let Err(e) = self.guard(&plugin, "logging", completed).await {
+                warn!(plugin = %plugin, error = %e, "Logging hook error");
+            }
         }
     }
 
@@ -247,7 +317,7 @@ impl HookExecutor {
         let hooks = self.registry.get_connection_failure_hooks();
         trace!(count = hooks.len(), upstream = %upstream, "Running connection failure hooks");
 
-        for hook in hooks {
+        for (_, hook) in hooks {
             if let Some(new_error) = hook.on_connect_failure(upstream, error, ctx) {
                 return Some(new_error);
             }
@@ -256,3 +326,59 @@ impl HookExecutor {
         None
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use async_trait::async_trait;
+    use std::time::Instant;
+
+    struct Slow(Duration);
+
+    #[async_trait]
+    impl RequestFilterHook for Slow {
+        async fn on_request(
+            &self,
+            _request: &RequestInfo,
+            _ctx: &mut PluginContext,
+        ) -> Result<HookAction> {
+            tokio::time::sleep(self.0).await;
+            Ok(HookAction::Continue)
+        }
+    }
+
+    #[tokio::test]
+    async fn test_stalled_hook_times_out() {
+        let registry = Arc::new(PluginRegistry::new());
+        registry.register_request_filter_hook("stuck", Arc::new(Slow(Duration::from_secs(60))));
+        let executor = HookExecutor::new(registry).with_timeout(Duration::from_millis(50));
+
+        let start = Instant::now();
+        let mut ctx = PluginContext::default();
+        let result = executor
+            .run_request_filter_hooks(&RequestInfo::default(), &mut ctx)
+            .await;
+        assert!(start.elapsed() < Duration::from_secs(5));
+        match result {
+            Err(PluginError::HookTimeout { plugin, phase, .. }) => {
+                assert_eq!(plugin, "stuck");
+                assert_eq!(phase, "request_filter");
+            }
+            other => panic!("expected a hook timeout, got {:?}", other.map(|_| ())),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_hook_within_timeout_completes() {
+        let registry = Arc::new(PluginRegistry::new());
+        registry.register_request_filter_hook("quick", Arc::new(Slow(Duration::from_millis(1))));
+        let executor = HookExecutor::new(registry).with_timeout(Duration::from_secs(5));
+
+        let mut ctx = PluginContext::default();
+        let action = executor
+            .run_request_filter_hooks(&RequestInfo::default(), &mut ctx)
+            .await
+            .unwrap();
+        assert!(matches!(action, HookAction::Continue));
+    }
+}
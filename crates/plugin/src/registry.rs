@@ -18,6 +18,14 @@ struct RegisteredHook<T> {
     hook: T,
 }
 
+/// Hooks of one type in priority order, paired with their plugin's name
+fn named<T: Clone>(hooks: &BTreeMap<HookPriority, Vec<RegisteredHook<T>>>) -> Vec<(String, T)> {
+    hooks
+        .values()
+        .flat_map(|v| v.iter().map(|h| (h.name.clone(), h.hook.clone())))
+        .collect()
+}
+
 /// Global plugin registry
 pub struct PluginRegistry {
     /// Registered plugin factories (for creating instances)
@@ -270,85 +278,49 @@ impl PluginRegistry {
     // Hook retrieval methods (for executor)
     // ==========================================================================
 
-    /// Get all early request hooks in priority order
-    pub fn get_early_request_hooks(&self) -> Vec<Arc<dyn EarlyRequestHook>> {
-        let hooks = self.early_request_hooks.read();
-        hooks
-            .values()
-            .flat_map(|v| v.iter().map(|h| h.hook.clone()))
-            .collect()
+    /// Get all early request hooks in priority order, with the plugin that registered each
+    pub fn get_early_request_hooks(&self) -> Vec<(String, Arc<dyn EarlyRequestHook>)> {
+        named(&self.early_request_hooks.read())
     }
 
-    /// Get all request filter hooks in priority order
-    pub fn get_request_filter_hooks(&self) -> Vec<Arc<dyn RequestFilterHook>> {
-        let hooks = self.request_filter_hooks.read();
-        hooks
-            .values()
-            .flat_map(|v| v.iter().map(|h| h.hook.clone()))
-            .collect()
+    /// Get all request filter hooks in priority order, with the plugin that registered each
+    pub fn get_request_filter_hooks(&self) -> Vec<(String, Arc<dyn RequestFilterHook>)> {
+        named(&self.request_filter_hooks.read())
     }
 
-    /// Get all route hooks in priority order
-    pub fn get_route_hooks(&self) -> Vec<Arc<dyn RouteHook>> {
-        let hooks = self.route_hooks.read();
-        hooks
-            .values()
-            .flat_map(|v| v.iter().map(|h| h.hook.clone()))
-            .collect()
+    /// Get all route hooks in priority order, with the plugin that registered each
+    pub fn get_route_hooks(&self) -> Vec<(String, Arc<dyn RouteHook>)> {
+        named(&self.route_hooks.read())
     }
 
-    /// Get all upstream select hooks in priority order
-    pub fn get_upstream_select_hooks(&self) -> Vec<Arc<dyn UpstreamSelectHook>> {
-        let hooks = self.upstream_select_hooks.read();
-        hooks
-            .values()
-            .flat_map(|v| v.iter().map(|h| h.hook.clone()))
-            .collect()
+    /// Get all upstream select hooks in priority order, with the plugin that registered each
+    pub fn get_upstream_select_hooks(&self) -> Vec<(String, Arc<dyn UpstreamSelectHook>)> {
+        named(&self.upstream_select_hooks.read())
     }
 
-    /// Get all upstream request hooks in priority order
-    pub fn get_upstream_request_hooks(&self) -> Vec<Arc<dyn UpstreamRequestHook>> {
-        let hooks = self.upstream_request_hooks.read();
-        hooks
-            .values()
-            .flat_map(|v| v.iter().map(|h| h.hook.clone()))
-            .collect()
+    /// Get all upstream request hooks in priority order, with the plugin that registered each
+    pub fn get_upstream_request_hooks(&self) -> Vec<(String, Arc<dyn UpstreamRequestHook>)> {
+        named(&self.upstream_request_hooks.read())
     }
 
-    /// Get all response filter hooks in priority order
-    pub fn get_response_filter_hooks(&self) -> Vec<Arc<dyn ResponseFilterHook>> {
-        let hooks = self.response_filter_hooks.read();
-        hooks
-            .values()
-            .flat_map(|v| v.iter().map(|h| h.hook.clone()))
-            .collect()
+    /// Get all response filter hooks in priority order, with the plugin that registered each
+    pub fn get_response_filter_hooks(&self) -> Vec<(String, Arc<dyn ResponseFilterHook>)> {
+        named(&self.response_filter_hooks.read())
     }
 
-    /// Get all response body hooks in priority order
-    pub fn get_response_body_hooks(&self) -> Vec<Arc<dyn ResponseBodyHook>> {
-        let hooks = self.response_body_hooks.read();
-        hooks
-            .values()
-            .flat_map(|v| v.iter().map(|h| h.hook.clone()))
-            .collect()
+    /// Get all response body hooks in priority order, with the plugin that registered each
+    pub fn get_response_body_hooks(&self) -> Vec<(String, Arc<dyn ResponseBodyHook>)> {
+        named(&self.response_body_hooks.read())
     }
 
-    /// Get all logging hooks in priority order
-    pub fn get_logging_hooks(&self) -> Vec<Arc<dyn LoggingHook>> {
-        let hooks = self.logging_hooks.read();
-        hooks
-            .values()
-            .flat_map(|v| v.iter().map(|h| h.hook.clone()))
-            .collect()
+    /// Get all logging hooks in priority order, with the plugin that registered each
+    pub fn get_logging_hooks(&self) -> Vec<(String, Arc<dyn LoggingHook>)> {
+        named(&self.logging_hooks.read())
     }
 
-    /// Get all connection failure hooks in priority order
-    pub fn get_connection_failure_hooks(&self) -> Vec<Arc<dyn ConnectionFailureHook>> {
-        let hooks = self.connection_failure_hooks.read();
-        hooks
-            .values()
-            .flat_map(|v| v.iter().map(|h| h.hook.clone()))
-            .collect()
+    /// Get all connection failure hooks in priority order, with the plugin that registered each
+    pub fn get_connection_failure_hooks(&self) -> Vec<(String, Arc<dyn ConnectionFailureHook>)> {
+        named(&self.connection_failure_hooks.read())
     }
 
     /// Stop all plugin instances
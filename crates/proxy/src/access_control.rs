@@ -0,0 +1,207 @@
+//! Server-level access control
+//!
+//! A server's `access_control` is an ordered rule list checked before any of
+//! its routes handle a request. Each rule matches on client IP, method and
+//! path prefix; the first rule that matches decides whether the request is
+//! allowed, denied, or must authenticate first. Requests no rule matches are
+//! allowed. A path rule covers the path itself and everything below it, so
+//! `/admin` matches `/admin/users` but not `/administrator`.
+
+use crate::auth::{AuthResult, CompiledAuth};
+use crate::error::Result;
+use crate::ip_filter::{CompiledIpFilter, IpFilterConfig};
+use config::{AccessAction, AccessControlConfig, AccessRuleConfig};
+use http::HeaderMap;
+use std::net::IpAddr;
+use tracing::debug;
+
+struct AccessRule {
+    /// Client ranges, `None` for any client
+    ips: Option<CompiledIpFilter>,
+    /// Uppercased methods, empty for any method
+    methods: Vec<String>,
+    paths: Vec<String>,
+    action: AccessAction,
+}
+
+impl AccessRule {
+    fn from_config(config: &AccessRuleConfig) -> Self {
+        let ips = (!config.ip.is_empty()).then(|| {
+            CompiledIpFilter::from_config(&IpFilterConfig {
+                allow: config.ip.clone(),
+                deny: Vec::new(),
+            })
+        });
+        Self {
+            ips,
+            methods: config.methods.iter().map(|m| m.to_ascii_uppercase()).collect(),
+            paths: config.paths.clone(),
+            action: config.action,
+        }
+    }
+
+    fn matches(&self, client_ip: Option<IpAddr>, method: &str, path: &str) -> bool {
+        let ip_matches = match &self.ips {
+            Some(ips) => client_ip.is_some_and(|ip| ips.is_allowed(&ip)),
+            None => true,
+        };
+        ip_matches
+            && (self.methods.is_empty() || self.methods.iter().any(|m| m.eq_ignore_ascii_case(method)))
+            && (self.paths.is_empty() || self.paths.iter().any(|p| covers_path(p, path)))
+    }
+}
+
+/// Whether `path` is the rule's path or lies below it
+fn covers_path(rule: &str, path: &str) -> bool {
+    match path.strip_prefix(rule) {
+        Some(rest) => rest.is_empty() || rule.ends_with('/') || rest.starts_with('/'),
+        None => false,
+    }
+}
+
+/// Compiled access policy of a server
+pub struct AccessControl {
+    rules: Vec<AccessRule>,
+    auth: Option<CompiledAuth>,
+}
+
+impl AccessControl {
+    pub fn from_config(config: &AccessControlConfig) -> Result<Self> {
+        Ok(Self {
+            rules: config.rules.iter().map(AccessRule::from_config).collect(),
+            auth: config.auth.as_ref().map(CompiledAuth::from_config).transpose()?,
+        })
+    }
+
+    /// Action of the first rule matching the request
+    pub fn evaluate(&self, client_ip: Option<IpAddr>, method: &str, path: &str) -> AccessAction {
+        match self.rules.iter().position(|rule| rule.matches(client_ip, method, path)) {
+            Some(idx) => {
+                debug!(rule = idx, client_ip = ?client_ip, method = %method, path = %path, "Access rule matched");
+                self.rules[idx].action
+            }
+            None => AccessAction::Allow,
+        }
+    }
+
+    /// Check a request against the policy's credentials, for `require_auth`
+    pub fn authenticate(&self, headers: &HeaderMap, query_string: Option<&str>, path: &str) -> AuthResult {
+        match &self.auth {
+            Some(auth) => auth.authenticate(headers, query_string, path),
+            None => AuthResult::Denied {
+                reason: "No credentials configured for access_control".to_string(),
+                request_auth: false,
+                realm: None,
+            },
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use base64::Engine;
+    use config::{AuthConfig, BasicAuthCredential};
+
+    fn rule(ip: &[&str], methods: &[&str], action: AccessAction) -> AccessRuleConfig {
+        AccessRuleConfig {
+            ip: ip.iter().map(|s| s.to_string()).collect(),
+            methods: methods.iter().map(|s| s.to_string()).collect(),
+            paths: Vec::new(),
+            action,
+        }
+    }
+
+    fn policy() -> AccessControl {
+        AccessControl::from_config(&AccessControlConfig {
+            rules: vec![
+                rule(&["203.0.113.7"], &[], AccessAction::Deny),
+                rule(&["10.0.0.0/8"], &[], AccessAction::RequireAuth),
+                rule(&[], &[], AccessAction::Allow),
+            ],
+            auth: Some(AuthConfig {
+                basic: vec![BasicAuthCredential {
+                    username: "admin".to_string(),
+                    password: "secret".to_string(),
+                }],
+                ..Default::default()
+            }),
+        })
+        .unwrap()
+    }
+
+    fn ip(s: &str) -> Option<IpAddr> {
+        Some(s.parse().unwrap())
+    }
+
+    #[test]
+    fn test_deny_require_auth_allow_rest() {
+        let policy = policy();
+        assert_eq!(policy.evaluate(ip("203.0.113.7"), "GET", "/"), AccessAction::Deny);
+        assert_eq!(policy.evaluate(ip("10.1.2.3"), "GET", "/"), AccessAction::RequireAuth);
+        assert_eq!(policy.evaluate(ip("198.51.100.1"), "POST", "/"), AccessAction::Allow);
+
+        let credentials = base64::engine::general_purpose::STANDARD.encode("admin:secret");
+        let good = format!("Basic {}", credentials);
+        let mut headers = HeaderMap::new();
+        headers.insert("authorization", good.parse().unwrap());
+        assert!(matches!(policy.authenticate(&headers, None, "/"), AuthResult::Authenticated { .. }));
+        assert!(matches!(policy.authenticate(&HeaderMap::new(), None, "/"), AuthResult::Denied { request_auth: true, .. }));
+    }
+
+    #[test]
+    fn test_first_match_wins() {
+        let policy = AccessControl::from_config(&AccessControlConfig {
+            rules: vec![
+                rule(&[], &["GET", "head"], AccessAction::Allow),
+                rule(&[], &[], AccessAction::Deny),
+            ],
+            auth: None,
+        })
+        .unwrap();
+        assert_eq!(policy.evaluate(ip("10.0.0.1"), "GET", "/"), AccessAction::Allow);
+        assert_eq!(policy.evaluate(ip("10.0.0.1"), "HEAD", "/"), AccessAction::Allow);
+        assert_eq!(policy.evaluate(ip("10.0.0.1"), "DELETE", "/"), AccessAction::Deny);
+    }
+
+    #[test]
+    fn test_path_and_unknown_client() {
+        let policy = AccessControl::from_config(&AccessControlConfig {
+            rules: vec![
+                AccessRuleConfig {
+                    paths: vec!["/admin".to_string()],
+                    ..rule(&["127.0.0.1", "::1"], &[], AccessAction::Allow)
+                },
+                AccessRuleConfig {
+                    paths: vec!["/admin".to_string()],
+                    ..rule(&[], &[], AccessAction::Deny)
+                },
+            ],
+            auth: None,
+        })
+        .unwrap();
+        assert_eq!(policy.evaluate(ip("::1"), "GET", "/admin/users"), AccessAction::Allow);
+        assert_eq!(policy.evaluate(ip("192.0.2.1"), "GET", "/admin"), AccessAction::Deny);
+        // Rules with IP conditions never match a client without an address
+        assert_eq!(policy.evaluate(None, "GET", "/admin"), AccessAction::Deny);
+        assert_eq!(policy.evaluate(ip("192.0.2.1"), "GET", "/public"), AccessAction::Allow);
+    }
+
+    #[test]
+    fn test_path_matches_whole_segments() {
+        let policy = AccessControl::from_config(&AccessControlConfig {
+            rules: vec![AccessRuleConfig {
+                paths: vec!["/admin".to_string(), "/internal/".to_string()],
+                ..rule(&[], &[], AccessAction::Deny)
+            }],
+            auth: None,
+        })
+        .unwrap();
+        assert_eq!(policy.evaluate(ip("192.0.2.1"), "GET", "/admin"), AccessAction::Deny);
+        assert_eq!(policy.evaluate(ip("192.0.2.1"), "GET", "/admin/"), AccessAction::Deny);
+        assert_eq!(policy.evaluate(ip("192.0.2.1"), "GET", "/admin/users"), AccessAction::Deny);
+        assert_eq!(policy.evaluate(ip("192.0.2.1"), "GET", "/internal/metrics"), AccessAction::Deny);
+        assert_eq!(policy.evaluate(ip("192.0.2.1"), "GET", "/administrator"), AccessAction::Allow);
+        assert_eq!(policy.evaluate(ip("192.0.2.1"), "GET", "/admins"), AccessAction::Allow);
+    }
+}
@@ -1,11 +1,64 @@
 //! Access logging for HTTP requests
+//!
+//! Entries go to a file, or to the OTLP endpoint when the path is `"otlp"`
+//! (see `otlp_log`).
 
+use crate::otlp_log;
 use chrono::{DateTime, Utc};
+use config::{RouteAccessLog, OTLP_ACCESS_LOG};
+use once_cell::sync::Lazy;
 use parking_lot::Mutex;
 use std::fs::{File, OpenOptions};
 use std::io::{BufWriter, Write};
-use std::path::Path;
-use std::sync::Arc;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Weak};
+use tracing::{info, warn};
+
+/// Every open log file, so all of them can be reopened after rotation
+static OPEN_LOGS: Lazy<Mutex<Vec<Weak<LogFile>>>> = Lazy::new(|| Mutex::new(Vec::new()));
+
+/// A log file that can be swapped for a fresh handle at the same path
+struct LogFile {
+    path: PathBuf,
+    writer: Mutex<BufWriter<File>>,
+}
+
+impl LogFile {
+    fn open(path: &Path) -> std::io::Result<BufWriter<File>> {
+        let file = OpenOptions::new().create(true).append(true).open(path)?;
+        Ok(BufWriter::new(file))
+    }
+
+    /// Replace the handle with one for the file now at `path`
+    ///
+    /// The swap happens under the writer lock, so a line is written either
+    /// entirely to the old file or entirely to the new one.
+    fn reopen(&self) -> std::io::Result<()> {
+        let fresh = Self::open(&self.path)?;
+        let mut writer = self.writer.lock();
+        let _ = writer.flush();
+        *writer = fresh;
+        Ok(())
+    }
+}
+
+/// Reopen all access log files, e.g. on SIGHUP after logrotate renamed them
+///
+/// Returns the number of files reopened.
+pub fn reopen_all() -> usize {
+    let mut open_logs = OPEN_LOGS.lock();
+    open_logs.retain(|log| log.strong_count() > 0);
+
+    let mut reopened = 0;
+    for log in open_logs.iter().filter_map(Weak::upgrade) {
+        match log.reopen() {
+            Ok(()) => reopened += 1,
+            Err(e) => warn!(path = ?log.path, error = %e, "Failed to reopen access log, still writing to old file"),
+        }
+    }
+    info!(count = reopened, "Reopened access log files");
+    reopened
+}
 
 /// Access log entry data
 #[derive(Debug, Clone)]
@@ -21,6 +74,10 @@ pub struct AccessLogEntry {
     pub referer: String,
     pub duration_ms: u64,
     pub is_websocket: bool,
+    /// Stage that ran past `request_timeout`, if any
+    pub timeout_stage: Option<&'static str>,
+    /// W3C `traceparent` request header, correlating OTLP records with the trace
+    pub traceparent: Option<String>,
 }
 
 /// Log format type
@@ -47,37 +104,65 @@ impl std::str::FromStr for LogFormat {
     }
 }
 
-/// Access logger that writes to a file
+/// Where an access logger sends its entries
+enum LogTarget {
+    File(Arc<LogFile>),
+    /// OpenTelemetry log records; the format doesn't apply
+    Otlp,
+}
+
+/// Access logger that writes to a file or exports over OTLP
 pub struct AccessLogger {
-    writer: Arc<Mutex<BufWriter<File>>>,
+    target: LogTarget,
     format: LogFormat,
 }
 
 impl AccessLogger {
-    /// Create a new access logger
+    /// Create a new access logger; the path `"otlp"` exports over OTLP
     pub fn new<P: AsRef<Path>>(path: P, format: LogFormat) -> std::io::Result<Self> {
-        let file = OpenOptions::new()
-            .create(true)
-            .append(true)
-            .open(path)?;
+        if path.as_ref() == Path::new(OTLP_ACCESS_LOG) {
+            return Ok(Self { target: LogTarget::Otlp, format });
+        }
 
-        let writer = BufWriter::new(file);
+        let path = path.as_ref().to_path_buf();
+        let writer = LogFile::open(&path)?;
 
-        Ok(Self {
-            writer: Arc::new(Mutex::new(writer)),
-            format,
-        })
+        let file = Arc::new(LogFile {
+            path,
+            writer: Mutex::new(writer),
+        });
+        OPEN_LOGS.lock().push(Arc::downgrade(&file));
+
+        Ok(Self { target: LogTarget::File(file), format })
+    }
+
+    /// Reopen the log file at its configured path
+    pub fn reopen(&self) -> std::io::Result<()> {
+        match &self.target {
+            LogTarget::File(file) => file.reopen(),
+            LogTarget::Otlp => Ok(()),
+        }
     }
 
     /// Log an access entry
     pub fn log(&self, entry: &AccessLogEntry) {
-        let line = match self.format {
+        self.log_with_format(entry, &self.format);
+    }
+
+    /// Log an access entry in a format other than the logger's own
+    pub fn log_with_format(&self, entry: &AccessLogEntry, format: &LogFormat) {
+        let file = match &self.target {
+            LogTarget::File(file) => file,
+            LogTarget::Otlp => return otlp_log::emit(entry),
+        };
+
+        let line = match format {
             LogFormat::Common => self.format_common(entry),
             LogFormat::Combined => self.format_combined(entry),
             LogFormat::Json => self.format_json(entry),
         };
 
-        let mut writer = self.writer.lock();
+        let mut writer = file.writer.lock();
         let _ = writeln!(writer, "{}", line);
         let _ = writer.flush();
     }
@@ -116,8 +201,12 @@ impl AccessLogger {
 
     /// Format entry as JSON
     fn format_json(&self, entry: &AccessLogEntry) -> String {
+        let timeout_stage = entry
+            .timeout_stage
+            .map(|stage| format!(r#","timeout_stage":"{}""#, stage))
+            .unwrap_or_default();
         format!(
-            r#"{{"timestamp":"{}","client_ip":"{}","method":"{}","path":"{}","host":"{}","status":{},"bytes_sent":{},"user_agent":"{}","referer":"{}","duration_ms":{},"websocket":{}}}"#,
+            r#"{{"timestamp":"{}","client_ip":"{}","method":"{}","path":"{}","host":"{}","status":{},"bytes_sent":{},"user_agent":"{}","referer":"{}","duration_ms":{},"websocket":{}{}}}"#,
             entry.timestamp.to_rfc3339(),
             escape_json(&entry.client_ip),
             escape_json(&entry.method),
@@ -128,22 +217,76 @@ impl AccessLogger {
             escape_json(&entry.user_agent),
             escape_json(&entry.referer),
             entry.duration_ms,
-            entry.is_websocket
+            entry.is_websocket,
+            timeout_stage
         )
     }
 }
 
 impl Clone for AccessLogger {
     fn clone(&self) -> Self {
+        let target = match &self.target {
+            LogTarget::File(file) => LogTarget::File(file.clone()),
+            LogTarget::Otlp => LogTarget::Otlp,
+        };
         Self {
-            writer: self.writer.clone(),
+            target,
             format: self.format.clone(),
         }
     }
 }
 
+/// Compiled per-route access log override
+#[derive(Clone)]
+pub struct RouteAccessLogger {
+    enabled: bool,
+    logger: Option<AccessLogger>,
+    format: Option<LogFormat>,
+}
+
+impl RouteAccessLogger {
+    pub fn from_config(config: &RouteAccessLog) -> std::io::Result<Self> {
+        match config {
+            RouteAccessLog::Enabled(enabled) => Ok(Self {
+                enabled: *enabled,
+                logger: None,
+                format: None,
+            }),
+            RouteAccessLog::Custom(custom) => {
+                let format: Option<LogFormat> = custom.format.as_ref().map(|f| f.parse().unwrap_or_default());
+                let logger = match &custom.path {
+                    Some(path) => Some(AccessLogger::new(path, format.clone().unwrap_or_default())?),
+                    None => None,
+                };
+
+                Ok(Self {
+                    enabled: custom.enabled,
+                    logger,
+                    format,
+                })
+            }
+        }
+    }
+
+    /// Write an entry for a request matched to this route
+    ///
+    /// Falls back to the global logger when the route has no file of its own.
+    pub fn log(&self, global: Option<&AccessLogger>, entry: &AccessLogEntry) {
+        if !self.enabled {
+            return;
+        }
+
+        if let Some(logger) = self.logger.as_ref().or(global) {
+            match &self.format {
+                Some(format) => logger.log_with_format(entry, format),
+                None => logger.log(entry),
+            }
+        }
+    }
+}
+
 /// Escape special characters for JSON strings
-fn escape_json(s: &str) -> String {
+pub(crate) fn escape_json(s: &str) -> String {
     let mut result = String::with_capacity(s.len());
     for c in s.chars() {
         match c {
@@ -164,6 +307,7 @@ fn escape_json(s: &str) -> String {
 #[cfg(test)]
 mod tests {
     use super::*;
+    use config::RouteAccessLogConfig;
     use std::fs;
     use tempfile::NamedTempFile;
 
@@ -180,6 +324,8 @@ mod tests {
             referer: "https://example.com".to_string(),
             duration_ms: 42,
             is_websocket: false,
+            timeout_stage: None,
+            traceparent: None,
         }
     }
 
@@ -235,6 +381,11 @@ mod tests {
         assert!(line.contains("\"method\":\"GET\""));
         assert!(line.contains("\"status\":200"));
         assert!(line.contains("\"websocket\":false"));
+        assert!(!line.contains("timeout_stage"));
+
+        let timed_out = AccessLogEntry { status: 504, timeout_stage: Some("upstream"), ..entry };
+        let line = logger.format_json(&timed_out);
+        assert!(line.ends_with(r#""websocket":false,"timeout_stage":"upstream"}"#), "{}", line);
     }
 
     #[test]
@@ -259,4 +410,83 @@ mod tests {
         let content = fs::read_to_string(&path).unwrap();
         assert!(content.contains("GET /api/test"));
     }
+
+    #[test]
+    fn test_route_access_log_disabled() {
+        let tmp = NamedTempFile::new().unwrap();
+        let global = AccessLogger::new(tmp.path(), LogFormat::Common).unwrap();
+
+        let quiet = RouteAccessLogger::from_config(&RouteAccessLog::Enabled(false)).unwrap();
+        let mut entry = make_test_entry();
+        entry.path = "/health".to_string();
+        quiet.log(Some(&global), &entry);
+
+        let normal = RouteAccessLogger::from_config(&RouteAccessLog::Enabled(true)).unwrap();
+        normal.log(Some(&global), &make_test_entry());
+
+        let content = fs::read_to_string(tmp.path()).unwrap();
+        assert!(!content.contains("/health"));
+        assert!(content.contains("GET /api/test"));
+        assert_eq!(content.lines().count(), 1);
+    }
+
+    #[test]
+    fn test_route_access_log_override() {
+        let global_tmp = NamedTempFile::new().unwrap();
+        let route_tmp = NamedTempFile::new().unwrap();
+        let global = AccessLogger::new(global_tmp.path(), LogFormat::Common).unwrap();
+
+        let config = RouteAccessLog::Custom(RouteAccessLogConfig {
+            enabled: true,
+            path: Some(route_tmp.path().to_string_lossy().to_string()),
+            format: Some("json".to_string()),
+        });
+        let route = RouteAccessLogger::from_config(&config).unwrap();
+        route.log(Some(&global), &make_test_entry());
+
+        assert!(fs::read_to_string(global_tmp.path()).unwrap().is_empty());
+        let content = fs::read_to_string(route_tmp.path()).unwrap();
+        assert!(content.contains("\"method\":\"GET\""));
+    }
+
+    #[test]
+    fn test_route_access_log_format_only() {
+        let tmp = NamedTempFile::new().unwrap();
+        let global = AccessLogger::new(tmp.path(), LogFormat::Common).unwrap();
+
+        let config = RouteAccessLog::Custom(RouteAccessLogConfig {
+            enabled: true,
+            path: None,
+            format: Some("json".to_string()),
+        });
+        let route = RouteAccessLogger::from_config(&config).unwrap();
+        route.log(Some(&global), &make_test_entry());
+
+        let content = fs::read_to_string(tmp.path()).unwrap();
+        assert!(content.contains("\"status\":200"));
+    }
+
+    #[test]
+    fn test_reopen_after_rotation() {
+        let dir = tempfile::TempDir::new().unwrap();
+        let path = dir.path().join("access.log");
+        let rotated = dir.path().join("access.log.1");
+        let logger = AccessLogger::new(&path, LogFormat::Common).unwrap();
+
+        let mut entry = make_test_entry();
+        entry.path = "/before".to_string();
+        logger.log(&entry);
+
+        // logrotate renames the file, then signals avalon
+        fs::rename(&path, &rotated).unwrap();
+        assert!(reopen_all() >= 1);
+
+        entry.path = "/after".to_string();
+        logger.log(&entry);
+
+        let old = fs::read_to_string(&rotated).unwrap();
+        let new = fs::read_to_string(&path).unwrap();
+        assert!(old.contains("/before") && !old.contains("/after"));
+        assert!(new.contains("/after") && !new.contains("/before"));
+    }
 }
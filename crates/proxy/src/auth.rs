@@ -5,9 +5,11 @@
 //! - API key authentication (header or query parameter)
 //! - JWT authentication (HMAC-based)
 
+use crate::error::{ProxyError, Result};
 use base64::Engine;
-use config::{ApiKeyConfig, AuthConfig, JwtAuthConfig};
+use config::{expand_env, ApiKeyConfig, AuthConfig, JwtAuthConfig};
 use hmac::{Hmac, Mac};
+use http::HeaderMap;
 use sha2::Sha256;
 use tracing::{debug, warn};
 
@@ -48,17 +50,23 @@ pub struct CompiledAuth {
 }
 
 impl CompiledAuth {
-    /// Create a new compiled auth from config
-    pub fn from_config(config: &AuthConfig) -> Self {
-        Self {
+    /// Create a new compiled auth from config, expanding `${NAME}`
+    /// environment references in the JWT secret
+    pub fn from_config(config: &AuthConfig) -> Result<Self> {
+        let mut jwt_config = config.jwt.clone();
+        if let Some(secret) = jwt_config.as_mut().and_then(|jwt| jwt.secret.as_mut()) {
+            *secret = expand_env(secret).map_err(|e| ProxyError::ConfigError(e.to_string()))?;
+        }
+
+        Ok(Self {
             basic_credentials: config.basic.iter()
                 .map(|c| (c.username.clone(), c.password.clone()))
                 .collect(),
             api_keys: config.api_keys.clone(),
-            jwt_config: config.jwt.clone(),
+            jwt_config,
             realm: config.realm.clone(),
             exclude_paths: config.exclude_paths.clone(),
-        }
+        })
     }
 
     /// Check if any authentication method is configured
@@ -81,13 +89,10 @@ impl CompiledAuth {
     /// Authenticate a request
     ///
     /// Returns AuthResult indicating success, failure, or not required
-    pub fn authenticate(
-        &self,
-        authorization_header: Option<&str>,
-        api_key_header: Option<&str>,
-        query_string: Option<&str>,
-        path: &str,
-    ) -> AuthResult {
+    pub fn authenticate(&self, headers: &HeaderMap, query_string: Option<&str>, path: &str) -> AuthResult {
+        let authorization_header = header_value(headers, "authorization");
+        let api_key_header = header_value(headers, "x-api-key");
+
         // Check if auth is configured
         if !self.has_auth() {
             return AuthResult::NotRequired;
@@ -115,9 +120,9 @@ impl CompiledAuth {
             }
         }
 
-        // Try JWT auth if configured
-        if self.jwt_config.is_some() {
-            if let Some(auth) = authorization_header {
+        // Try JWT auth if configured, from its own header
+        if let Some(jwt_config) = &self.jwt_config {
+            if let Some(auth) = header_value(headers, &jwt_config.header) {
                 if let Some(result) = self.check_jwt(auth) {
                     return result;
                 }
@@ -265,13 +270,7 @@ impl CompiledAuth {
         let jwt_config = self.jwt_config.as_ref()?;
         let secret = jwt_config.secret.as_ref()?;
 
-        // Parse "Bearer token"
-        let auth_header = auth_header.trim();
-        if !auth_header.to_lowercase().starts_with("bearer ") {
-            return None;
-        }
-
-        let token = &auth_header[7..].trim();
+        let token = bearer_token(auth_header)?;
 
         // Parse JWT (header.payload.signature)
         let parts: Vec<&str> = token.split('.').collect();
@@ -394,6 +393,25 @@ impl CompiledAuth {
         Some(AuthResult::Authenticated { identity: None })
     }
 
+    /// Value of `claim` in the request's JWT, if the token verifies
+    ///
+    /// The token goes through the same checks as JWT authentication, so a
+    /// forged or expired token yields nothing. String and integer claims are
+    /// supported.
+    pub fn jwt_claim(&self, headers: &HeaderMap, claim: &str) -> Option<String> {
+        let token_header = header_value(headers, &self.jwt_config.as_ref()?.header)?;
+        if !matches!(self.check_jwt(token_header)?, AuthResult::Authenticated { .. }) {
+            return None;
+        }
+
+        let token = bearer_token(token_header)?;
+        let payload_b64 = token.split('.').nth(1)?;
+        let payload = base64::engine::general_purpose::URL_SAFE_NO_PAD.decode(payload_b64).ok()?;
+        let payload = String::from_utf8(payload).ok()?;
+        extract_json_string(&payload, claim)
+            .or_else(|| extract_json_number(&payload, claim).map(|n| n.to_string()))
+    }
+
     /// Get the realm for authentication challenges
     pub fn realm(&self) -> &str {
         &self.realm
@@ -465,11 +483,28 @@ fn extract_json_number(json: &str, key: &str) -> Option<u64> {
     trimmed[..end].parse().ok()
 }
 
+/// A request header as text, if present
+fn header_value<'a>(headers: &'a HeaderMap, name: &str) -> Option<&'a str> {
+    headers.get(name).and_then(|v| v.to_str().ok())
+}
+
+/// Token of a "Bearer <token>" header value
+fn bearer_token(value: &str) -> Option<&str> {
+    let value = value.trim();
+    let scheme = value.get(..7)?;
+    scheme.eq_ignore_ascii_case("bearer ").then(|| value[7..].trim())
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
     use config::{AuthConfig, BasicAuthCredential};
 
+    /// Request headers holding `pairs`
+    fn headers(pairs: &[(&str, &str)]) -> HeaderMap {
+        pairs.iter().map(|(name, value)| (name.parse().unwrap(), value.parse().unwrap())).collect()
+    }
+
     fn make_basic_auth_config() -> AuthConfig {
         AuthConfig {
             basic: vec![BasicAuthCredential {
@@ -480,6 +515,7 @@ mod tests {
             jwt: None,
             realm: "Test Realm".to_string(),
             exclude_paths: vec!["/health".to_string()],
+            forward_auth: None,
         }
     }
 
@@ -495,19 +531,20 @@ mod tests {
             jwt: None,
             realm: "API".to_string(),
             exclude_paths: vec![],
+            forward_auth: None,
         }
     }
 
     #[test]
     fn test_basic_auth_success() {
         let config = make_basic_auth_config();
-        let auth = CompiledAuth::from_config(&config);
+        let auth = CompiledAuth::from_config(&config).unwrap();
 
         // Base64 encode "admin:secret"
         let credentials = base64::engine::general_purpose::STANDARD.encode("admin:secret");
         let header = format!("Basic {}", credentials);
 
-        let result = auth.authenticate(Some(&header), None, None, "/api");
+        let result = auth.authenticate(&headers(&[("authorization", &header)]), None, "/api");
 
         match result {
             AuthResult::Authenticated { identity } => {
@@ -520,12 +557,12 @@ mod tests {
     #[test]
     fn test_basic_auth_failure() {
         let config = make_basic_auth_config();
-        let auth = CompiledAuth::from_config(&config);
+        let auth = CompiledAuth::from_config(&config).unwrap();
 
         let credentials = base64::engine::general_purpose::STANDARD.encode("admin:wrong");
         let header = format!("Basic {}", credentials);
 
-        let result = auth.authenticate(Some(&header), None, None, "/api");
+        let result = auth.authenticate(&headers(&[("authorization", &header)]), None, "/api");
 
         match result {
             AuthResult::Denied { reason, request_auth, realm } => {
@@ -540,9 +577,9 @@ mod tests {
     #[test]
     fn test_excluded_path() {
         let config = make_basic_auth_config();
-        let auth = CompiledAuth::from_config(&config);
+        let auth = CompiledAuth::from_config(&config).unwrap();
 
-        let result = auth.authenticate(None, None, None, "/health");
+        let result = auth.authenticate(&HeaderMap::new(), None, "/health");
 
         match result {
             AuthResult::NotRequired => {}
@@ -553,9 +590,9 @@ mod tests {
     #[test]
     fn test_api_key_auth_success() {
         let config = make_api_key_config();
-        let auth = CompiledAuth::from_config(&config);
+        let auth = CompiledAuth::from_config(&config).unwrap();
 
-        let result = auth.authenticate(None, Some("test-api-key-123"), None, "/api");
+        let result = auth.authenticate(&headers(&[("x-api-key", "test-api-key-123")]), None, "/api");
 
         match result {
             AuthResult::Authenticated { identity } => {
@@ -568,9 +605,9 @@ mod tests {
     #[test]
     fn test_api_key_auth_failure() {
         let config = make_api_key_config();
-        let auth = CompiledAuth::from_config(&config);
+        let auth = CompiledAuth::from_config(&config).unwrap();
 
-        let result = auth.authenticate(None, Some("wrong-key"), None, "/api");
+        let result = auth.authenticate(&headers(&[("x-api-key", "wrong-key")]), None, "/api");
 
         match result {
             AuthResult::Denied { .. } => {}
@@ -581,9 +618,9 @@ mod tests {
     #[test]
     fn test_no_auth_configured() {
         let config = AuthConfig::default();
-        let auth = CompiledAuth::from_config(&config);
+        let auth = CompiledAuth::from_config(&config).unwrap();
 
-        let result = auth.authenticate(None, None, None, "/api");
+        let result = auth.authenticate(&HeaderMap::new(), None, "/api");
 
         match result {
             AuthResult::NotRequired => {}
@@ -613,4 +650,104 @@ mod tests {
         assert_eq!(extract_json_number(json, "iat"), Some(1234567800));
         assert_eq!(extract_json_number(json, "missing"), None);
     }
+
+    fn make_jwt_auth_with(secret: &str, header: &str) -> CompiledAuth {
+        CompiledAuth::from_config(&AuthConfig {
+            jwt: Some(config::JwtAuthConfig {
+                secret: Some(secret.to_string()),
+                algorithm: "HS256".to_string(),
+                header: header.to_string(),
+                issuer: None,
+                audience: None,
+            }),
+            ..Default::default()
+        })
+        .unwrap()
+    }
+
+    fn make_jwt_auth() -> CompiledAuth {
+        make_jwt_auth_with("jwt-secret", "Authorization")
+    }
+
+    /// HS256 bearer token over `payload`
+    fn bearer(payload: &str, secret: &str) -> String {
+        let engine = base64::engine::general_purpose::URL_SAFE_NO_PAD;
+        let signing_input = format!("{}.{}", engine.encode(r#"{"alg":"HS256","typ":"JWT"}"#), engine.encode(payload));
+        let mut mac = Hmac::<Sha256>::new_from_slice(secret.as_bytes()).unwrap();
+        mac.update(signing_input.as_bytes());
+        let signature = engine.encode(mac.finalize().into_bytes());
+        format!("Bearer {}.{}", signing_input, signature)
+    }
+
+    #[test]
+    fn test_jwt_claim_from_verified_token() {
+        let auth = make_jwt_auth();
+        let token = bearer(r#"{"sub":"alice","tenant_id":"acme","shard":7}"#, "jwt-secret");
+        let request = headers(&[("authorization", &token)]);
+        assert_eq!(auth.jwt_claim(&request, "tenant_id").as_deref(), Some("acme"));
+        assert_eq!(auth.jwt_claim(&request, "shard").as_deref(), Some("7"));
+        assert!(auth.jwt_claim(&request, "missing").is_none());
+
+        let forged = bearer(r#"{"tenant_id":"acme"}"#, "wrong-secret");
+        assert!(auth.jwt_claim(&headers(&[("authorization", &forged)]), "tenant_id").is_none());
+        assert!(auth.jwt_claim(&HeaderMap::new(), "tenant_id").is_none());
+        assert!(auth.jwt_claim(&headers(&[("authorization", "Bear")]), "tenant_id").is_none());
+    }
+
+    #[test]
+    fn test_jwt_from_configured_header_with_env_secret() {
+        std::env::set_var("AVALON_TEST_JWT_SECRET", "from-env");
+        let auth = make_jwt_auth_with("${AVALON_TEST_JWT_SECRET}", "X-Access-Token");
+        let token = bearer(r#"{"sub":"alice","tenant_id":"acme"}"#, "from-env");
+
+        let request = headers(&[("x-access-token", &token)]);
+        assert!(matches!(
+            auth.authenticate(&request, None, "/api"),
+            AuthResult::Authenticated { identity: Some(ref sub) } if sub == "alice"
+        ));
+        assert_eq!(auth.jwt_claim(&request, "tenant_id").as_deref(), Some("acme"));
+
+        // The default header is not consulted once another one is configured
+        let request = headers(&[("authorization", &token)]);
+        assert!(matches!(auth.authenticate(&request, None, "/api"), AuthResult::Denied { .. }));
+        assert!(auth.jwt_claim(&request, "tenant_id").is_none());
+
+        std::env::remove_var("AVALON_TEST_JWT_SECRET");
+        assert!(CompiledAuth::from_config(&AuthConfig {
+            jwt: Some(config::JwtAuthConfig {
+                secret: Some("${AVALON_TEST_JWT_SECRET}".to_string()),
+                algorithm: "HS256".to_string(),
+                header: "Authorization".to_string(),
+                issuer: None,
+                audience: None,
+            }),
+            ..Default::default()
+        })
+        .is_err());
+    }
+
+    #[test]
+    fn test_same_tenant_claim_sticks_to_one_upstream() {
+        use crate::upstream::UpstreamSelector;
+        use config::LoadBalancingStrategy;
+
+        let auth = make_jwt_auth();
+        let addresses: Vec<String> = (0..4).map(|i| format!("127.0.0.1:{}", 3000 + i)).collect();
+        let selector = UpstreamSelector::new(&addresses, LoadBalancingStrategy::RoundRobin, false).unwrap();
+
+        let upstream_for = |payload: &str| {
+            let token = bearer(payload, "jwt-secret");
+            let key = auth.jwt_claim(&headers(&[("authorization", &token)]), "tenant_id").map(|v| format!("tenant_id={}", v));
+            selector.select_with_affinity(key.as_deref()).unwrap().0.address_str.clone()
+        };
+
+        for tenant in ["acme", "globex", "initech", "1"] {
+            let first = upstream_for(&format!(r#"{{"sub":"a","tenant_id":"{}"}}"#, tenant));
+            for user in ["b", "c", "d", "e"] {
+                // Different users and round-robin turns in between don't move the tenant
+                let _ = selector.select();
+                assert_eq!(upstream_for(&format!(r#"{{"sub":"{}","tenant_id":"{}"}}"#, user, tenant)), first);
+            }
+        }
+    }
 }
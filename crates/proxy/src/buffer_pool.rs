@@ -0,0 +1,241 @@
+//! Reusable response body buffers
+//!
+//! Buffered responses (compression, caching, idempotent replay) collect the
+//! whole body in memory. Taking those buffers from a shared pool instead of
+//! allocating a fresh `Vec` per request avoids allocator churn at high RPS.
+
+use parking_lot::Mutex;
+use std::ops::Deref;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+
+/// Total capacity of the buffers kept for reuse by the global pool (16 MiB)
+const DEFAULT_MAX_RETAINED: usize = 16 * 1024 * 1024;
+
+/// Buffers that grew beyond this are freed instead of pooled (1 MiB)
+const DEFAULT_MAX_CAPACITY: usize = 1024 * 1024;
+
+/// Initial capacity of a newly allocated buffer (16 KiB)
+const INITIAL_CAPACITY: usize = 16 * 1024;
+
+static BUFFER_POOL: once_cell::sync::Lazy<Arc<BufferPool>> =
+    once_cell::sync::Lazy::new(|| Arc::new(BufferPool::new(DEFAULT_MAX_RETAINED, DEFAULT_MAX_CAPACITY)));
+
+/// Get the global buffer pool
+pub fn buffer_pool() -> &'static Arc<BufferPool> {
+    &BUFFER_POOL
+}
+
+/// Pool of cleared byte buffers
+pub struct BufferPool {
+    buffers: Mutex<Pooled>,
+    max_retained: usize,
+    max_capacity: usize,
+    allocated: AtomicU64,
+    reused: AtomicU64,
+}
+
+/// Buffers waiting for reuse and their total capacity
+#[derive(Default)]
+struct Pooled {
+    buffers: Vec<Vec<u8>>,
+    retained: usize,
+}
+
+impl BufferPool {
+    /// Keep buffers up to `max_retained` bytes of total capacity, each at most `max_capacity`
+    pub fn new(max_retained: usize, max_capacity: usize) -> Self {
+        Self {
+            buffers: Mutex::new(Pooled::default()),
+            max_retained,
+            max_capacity,
+            allocated: AtomicU64::new(0),
+            reused: AtomicU64::new(0),
+        }
+    }
+
+    /// Take an empty buffer, reusing a pooled one when available
+    fn take(&self) -> Vec<u8> {
+        let mut pooled = self.buffers.lock();
+        match pooled.buffers.pop() {
+            Some(buf) => {
+                pooled.retained -= buf.capacity();
+                self.reused.fetch_add(1, Ordering::Relaxed);
+                buf
+            }
+            None => {
+                self.allocated.fetch_add(1, Ordering::Relaxed);
+                Vec::with_capacity(INITIAL_CAPACITY)
+            }
+        }
+    }
+
+    /// Return a buffer; its contents are cleared before anyone else sees it
+    fn put(&self, mut buf: Vec<u8>) {
+        if buf.capacity() > self.max_capacity {
+            return;
+        }
+        buf.clear();
+
+        let mut pooled = self.buffers.lock();
+        if pooled.retained + buf.capacity() <= self.max_retained {
+            pooled.retained += buf.capacity();
+            pooled.buffers.push(buf);
+        }
+    }
+
+    /// Buffers currently waiting for reuse
+    pub fn pooled(&self) -> usize {
+        self.buffers.lock().buffers.len()
+    }
+
+    /// Total capacity of the buffers waiting for reuse
+    pub fn retained(&self) -> usize {
+        self.buffers.lock().retained
+    }
+
+    /// Buffers allocated because the pool was empty
+    pub fn allocated(&self) -> u64 {
+        self.allocated.load(Ordering::Relaxed)
+    }
+
+    /// Buffers handed out from the pool
+    pub fn reused(&self) -> u64 {
+        self.reused.load(Ordering::Relaxed)
+    }
+}
+
+/// A body buffer that goes back to its pool when dropped
+///
+/// Nothing is taken from the pool until the first write, so requests that
+/// never buffer don't touch it.
+pub struct PooledBuffer {
+    buf: Vec<u8>,
+    pool: Arc<BufferPool>,
+}
+
+impl PooledBuffer {
+    /// Create a buffer backed by the global pool
+    pub fn new() -> Self {
+        Self::with_pool(buffer_pool().clone())
+    }
+
+    pub fn with_pool(pool: Arc<BufferPool>) -> Self {
+        Self { buf: Vec::new(), pool }
+    }
+
+    pub fn extend_from_slice(&mut self, data: &[u8]) {
+        if self.buf.capacity() == 0 {
+            self.buf = self.pool.take();
+        }
+        self.buf.extend_from_slice(data);
+    }
+
+    /// Drop the contents, keeping the allocation
+    pub fn clear(&mut self) {
+        self.buf.clear();
+    }
+}
+
+impl Default for PooledBuffer {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Deref for PooledBuffer {
+    type Target = [u8];
+
+    fn deref(&self) -> &[u8] {
+        &self.buf
+    }
+}
+
+impl Drop for PooledBuffer {
+    fn drop(&mut self) {
+        if self.buf.capacity() > 0 {
+            self.pool.put(std::mem::take(&mut self.buf));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_buffer_cleared_between_uses() {
+        let pool = Arc::new(BufferPool::new(DEFAULT_MAX_CAPACITY, DEFAULT_MAX_CAPACITY));
+
+        let mut first = PooledBuffer::with_pool(pool.clone());
+        first.extend_from_slice(b"secret response body");
+        drop(first);
+        assert_eq!(pool.pooled(), 1);
+
+        let mut second = PooledBuffer::with_pool(pool.clone());
+        assert!(second.is_empty());
+        second.extend_from_slice(b"ok");
+        assert_eq!(&second[..], b"ok");
+        assert_eq!(pool.reused(), 1);
+    }
+
+    #[test]
+    fn test_unused_buffer_skips_pool() {
+        let pool = Arc::new(BufferPool::new(DEFAULT_MAX_CAPACITY, DEFAULT_MAX_CAPACITY));
+        drop(PooledBuffer::with_pool(pool.clone()));
+        assert_eq!(pool.allocated(), 0);
+        assert_eq!(pool.pooled(), 0);
+    }
+
+    #[test]
+    fn test_oversized_buffer_not_pooled() {
+        let pool = Arc::new(BufferPool::new(DEFAULT_MAX_CAPACITY, INITIAL_CAPACITY));
+        let mut buf = PooledBuffer::with_pool(pool.clone());
+        buf.extend_from_slice(&vec![0u8; INITIAL_CAPACITY * 2]);
+        drop(buf);
+        assert_eq!(pool.pooled(), 0);
+    }
+
+    #[test]
+    fn test_reuse_under_load() {
+        let pool = Arc::new(BufferPool::new(DEFAULT_MAX_RETAINED, DEFAULT_MAX_CAPACITY));
+        let chunk = vec![b'x'; 4096];
+
+        for _ in 0..10_000 {
+            let mut buf = PooledBuffer::with_pool(pool.clone());
+            for _ in 0..4 {
+                buf.extend_from_slice(&chunk);
+            }
+            assert_eq!(buf.len(), 4 * chunk.len());
+        }
+
+        // Sequential requests share a single allocation
+        assert_eq!(pool.allocated(), 1);
+        assert_eq!(pool.reused(), 9_999);
+    }
+
+    #[test]
+    fn test_retained_bytes_bounded() {
+        let pool = Arc::new(BufferPool::new(2 * DEFAULT_MAX_CAPACITY, DEFAULT_MAX_CAPACITY));
+
+        // Concurrent responses each grown to the per-buffer limit
+        let buffers: Vec<_> = (0..4)
+            .map(|_| {
+                let mut buf = PooledBuffer::with_pool(pool.clone());
+                buf.extend_from_slice(&vec![0u8; DEFAULT_MAX_CAPACITY]);
+                buf
+            })
+            .collect();
+        drop(buffers);
+
+        assert_eq!(pool.pooled(), 2);
+        assert!(pool.retained() <= 2 * DEFAULT_MAX_CAPACITY);
+
+        drop(PooledBuffer::with_pool(pool.clone()));
+        let mut buf = PooledBuffer::with_pool(pool.clone());
+        buf.extend_from_slice(b"x");
+        assert_eq!(pool.pooled(), 1);
+        drop(buf);
+        assert_eq!(pool.pooled(), 2);
+    }
+}
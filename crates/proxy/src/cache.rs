@@ -1,8 +1,11 @@
 //! Response caching for reverse proxy
 
+use crate::compression::{decodable_encoding, decompress};
 use bytes::Bytes;
+use config::CacheKeyQueryConfig;
 use dashmap::DashMap;
 use http::StatusCode;
+use pingora_http::ResponseHeader;
 use std::sync::Arc;
 use std::time::{Duration, Instant};
 use tracing::debug;
@@ -36,6 +39,61 @@ impl CachedResponse {
     pub fn remaining_ttl(&self) -> Duration {
         self.ttl.saturating_sub(self.cached_at.elapsed())
     }
+
+    /// Whether an expired entry can be revalidated instead of refetched
+    pub fn has_validator(&self) -> bool {
+        self.etag.is_some() || self.last_modified.is_some()
+    }
+
+    /// Headers making an upstream request conditional on this entry
+    pub fn conditional_headers(&self) -> Vec<(&'static str, String)> {
+        let mut headers = Vec::new();
+        if let Some(etag) = &self.etag {
+            headers.push(("If-None-Match", etag.clone()));
+        }
+        if let Some(last_modified) = &self.last_modified {
+            headers.push(("If-Modified-Since", last_modified.clone()));
+        }
+        headers
+    }
+}
+
+/// How the cache took part in a response, reported in the status header
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CacheStatus {
+    /// Served from a fresh entry
+    Hit,
+    /// Fetched from the upstream and stored
+    Miss,
+    /// Served from an expired entry the upstream couldn't revalidate
+    Stale,
+    /// Neither served from nor stored in the cache
+    Bypass,
+    /// Served from an expired entry the upstream confirmed unchanged
+    Revalidated,
+}
+
+impl CacheStatus {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::Hit => "HIT",
+            Self::Miss => "MISS",
+            Self::Stale => "STALE",
+            Self::Bypass => "BYPASS",
+            Self::Revalidated => "REVALIDATED",
+        }
+    }
+}
+
+/// Result of looking up a request in the cache
+#[derive(Debug)]
+pub enum CacheLookup {
+    /// A fresh entry that can be served as-is
+    Hit(CachedResponse),
+    /// An expired entry with a validator; revalidate before serving
+    Stale(CachedResponse),
+    /// Nothing usable is cached
+    Miss,
 }
 
 /// Cache key generation
@@ -65,6 +123,12 @@ impl CacheKey {
         self
     }
 
+    /// Keep only the query parameters the route caches by
+    pub fn with_query_filter(mut self, filter: &CacheKeyQuery) -> Self {
+        self.query = self.query.as_deref().and_then(|query| filter.apply(query));
+        self
+    }
+
     /// Generate a string key for the cache
     pub fn to_string_key(&self) -> String {
         let mut key = format!("{}:{}:{}", self.method, self.host, self.path);
@@ -79,6 +143,41 @@ impl CacheKey {
     }
 }
 
+/// Per-route choice of the query parameters in the cache key
+#[derive(Clone, Debug, Default)]
+pub struct CacheKeyQuery {
+    vary: Vec<String>,
+    ignore: Vec<String>,
+}
+
+impl CacheKeyQuery {
+    pub fn from_config(config: &CacheKeyQueryConfig) -> Self {
+        Self {
+            vary: config.vary.clone(),
+            ignore: config.ignore.clone(),
+        }
+    }
+
+    fn significant(&self, name: &str) -> bool {
+        (self.vary.is_empty() || self.vary.iter().any(|v| v == name)) && !self.ignore.iter().any(|i| i == name)
+    }
+
+    /// The significant parameters of `query` sorted by name, or None if there are none
+    pub fn apply(&self, query: &str) -> Option<String> {
+        let mut params: Vec<&str> = query
+            .split('&')
+            .filter(|param| !param.is_empty())
+            .filter(|param| self.significant(param.split('=').next().unwrap_or(param)))
+            .collect();
+        if params.is_empty() {
+            return None;
+        }
+        // Stable, so repeated parameters keep their relative order
+        params.sort_by_key(|param| param.split('=').next().unwrap_or(param));
+        Some(params.join("&"))
+    }
+}
+
 /// Cache configuration
 #[derive(Clone, Debug)]
 pub struct CacheConfig {
@@ -94,6 +193,10 @@ pub struct CacheConfig {
     pub cacheable_status: Vec<u16>,
     /// Cache only these methods
     pub cacheable_methods: Vec<String>,
+    /// Header reporting the cache status, `None` to send none
+    pub status_header: Option<String>,
+    /// Serve expired entries when revalidation fails
+    pub stale_if_error: bool,
 }
 
 impl Default for CacheConfig {
@@ -105,6 +208,26 @@ impl Default for CacheConfig {
             max_cache_size: 100 * 1024 * 1024, // 100MB
             cacheable_status: vec![200, 301, 302, 304, 307, 308],
             cacheable_methods: vec!["GET".to_string(), "HEAD".to_string()],
+            status_header: Some("X-Cache".to_string()),
+            stale_if_error: false,
+        }
+    }
+}
+
+/// Per-route overrides of how upstream responses are cached
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct CachePolicy {
+    /// TTL used instead of the one derived from the response headers
+    pub ttl_override: Option<Duration>,
+    /// Honor the upstream `Cache-Control` when deciding whether and how long to cache
+    pub respect_cache_control: bool,
+}
+
+impl Default for CachePolicy {
+    fn default() -> Self {
+        Self {
+            ttl_override: None,
+            respect_cache_control: true,
         }
     }
 }
@@ -127,12 +250,23 @@ impl ResponseCache {
 
     /// Get a cached response if valid
     pub fn get(&self, key: &CacheKey) -> Option<CachedResponse> {
+        match self.lookup(key) {
+            CacheLookup::Hit(entry) => Some(entry),
+            _ => None,
+        }
+    }
+
+    /// Look up a request, keeping expired entries that can be revalidated
+    pub fn lookup(&self, key: &CacheKey) -> CacheLookup {
         let string_key = key.to_string_key();
 
         if let Some(entry) = self.entries.get(&string_key) {
             if entry.is_valid() {
                 debug!(key = %string_key, remaining_ttl = ?entry.remaining_ttl(), "Cache hit");
-                return Some(entry.clone());
+                return CacheLookup::Hit(entry.clone());
+            } else if entry.has_validator() {
+                debug!(key = %string_key, etag = ?entry.etag, "Cache entry stale, revalidating");
+                return CacheLookup::Stale(entry.clone());
             } else {
                 // Entry expired, remove it
                 drop(entry);
@@ -142,7 +276,57 @@ impl ResponseCache {
         } else {
             debug!(key = %string_key, "Cache miss");
         }
-        None
+        CacheLookup::Miss
+    }
+
+    /// Whether requests with this method skip the cache entirely
+    pub fn bypasses(&self, method: &str) -> bool {
+        !self.config.enabled || !self.config.cacheable_methods.iter().any(|m| m.eq_ignore_ascii_case(method))
+    }
+
+    /// Whether an expired entry is served when revalidating it fails
+    pub fn stale_if_error(&self) -> bool {
+        self.config.stale_if_error
+    }
+
+    /// Report how the cache handled a response, unless the header is disabled
+    pub fn set_status_header(&self, response: &mut ResponseHeader, status: CacheStatus) -> pingora_error::Result<()> {
+        if let Some(name) = &self.config.status_header {
+            response.insert_header(name.clone(), status.as_str())?;
+        }
+        Ok(())
+    }
+
+    /// Refresh an entry after the upstream answered 304 Not Modified
+    ///
+    /// Headers from the 304 replace the stored ones (RFC 7234 Section 4.3.4)
+    /// and the TTL restarts; the stored body is kept.
+    pub fn refresh(
+        &self,
+        key: &CacheKey,
+        not_modified_headers: &[(String, String)],
+        policy: &CachePolicy,
+    ) -> Option<CachedResponse> {
+        let string_key = key.to_string_key();
+        let mut entry = self.entries.get_mut(&string_key)?;
+
+        for (name, value) in not_modified_headers {
+            if name.eq_ignore_ascii_case("content-length") {
+                continue;
+            }
+            entry.headers.retain(|(existing, _)| !existing.eq_ignore_ascii_case(name));
+            entry.headers.push((name.clone(), value.clone()));
+            if name.eq_ignore_ascii_case("etag") {
+                entry.etag = Some(value.clone());
+            } else if name.eq_ignore_ascii_case("last-modified") {
+                entry.last_modified = Some(value.clone());
+            }
+        }
+        entry.ttl = self.ttl_for(policy, &entry.headers);
+        entry.cached_at = Instant::now();
+
+        debug!(key = %string_key, ttl = ?entry.ttl, "Cache entry revalidated");
+        Some(entry.clone())
     }
 
     /// Store a response in the cache
@@ -159,12 +343,52 @@ impl ResponseCache {
         // Evict old entries if needed
         self.maybe_evict(entry_size);
 
-        // Store the entry
-        self.entries.insert(string_key.clone(), response);
+        // Store the entry, replacing any stale one
+        if let Some(old) = self.entries.insert(string_key.clone(), response) {
+            self.current_size.fetch_sub(self.estimate_size(&old), std::sync::atomic::Ordering::Relaxed);
+        }
         self.current_size.fetch_add(entry_size, std::sync::atomic::Ordering::Relaxed);
         debug!(key = %string_key, size = entry_size, "Cached response");
     }
 
+    /// Headers and body to store for a response, decoded to identity
+    ///
+    /// Entries hold the identity body so each hit can be compressed with the
+    /// requesting client's codec instead of replaying the first client's.
+    /// `Content-Encoding` and `Content-Length` are dropped and a strong ETag
+    /// becomes weak, as it named the encoded bytes. Codings avalon can't decode
+    /// are stored as received; `None` means the body didn't decode within
+    /// `max_entry_size` and the response shouldn't be cached.
+    pub fn identity_entry(&self, headers: &[(String, String)], body: &[u8]) -> Option<(Vec<(String, String)>, Bytes)> {
+        let content_encoding = headers
+            .iter()
+            .find(|(k, _)| k.eq_ignore_ascii_case("content-encoding"))
+            .map(|(_, v)| v.as_str());
+        let Some(encoding) = content_encoding.and_then(decodable_encoding) else {
+            return Some((headers.to_vec(), Bytes::copy_from_slice(body)));
+        };
+
+        let decoded = match decompress(body, encoding, self.config.max_entry_size) {
+            Ok(decoded) => decoded,
+            Err(e) => {
+                debug!(error = %e, encoding = encoding.header_value(), "Not caching undecodable response");
+                return None;
+            }
+        };
+        let headers = headers
+            .iter()
+            .filter(|(k, _)| !k.eq_ignore_ascii_case("content-encoding") && !k.eq_ignore_ascii_case("content-length"))
+            .map(|(k, v)| {
+                if k.eq_ignore_ascii_case("etag") && !v.starts_with("W/") {
+                    (k.clone(), format!("W/{}", v))
+                } else {
+                    (k.clone(), v.clone())
+                }
+            })
+            .collect();
+        Some((headers, decoded))
+    }
+
     /// Remove an entry from the cache
     pub fn remove(&self, key: &str) {
         if let Some((_, entry)) = self.entries.remove(key) {
@@ -239,6 +463,21 @@ impl ResponseCache {
 
     /// Check if a response is cacheable
     pub fn is_cacheable(&self, method: &str, status: u16, headers: &[(String, String)]) -> bool {
+        self.is_cacheable_for(&CachePolicy::default(), method, status, headers)
+    }
+
+    /// Check if a response is cacheable under a route's policy
+    ///
+    /// Without `respect_cache_control`, upstream `no-store` and `no-cache`
+    /// directives don't prevent caching. Responses marked `private` or
+    /// carrying `Set-Cookie` belong to one client and are never cached.
+    pub fn is_cacheable_for(
+        &self,
+        policy: &CachePolicy,
+        method: &str,
+        status: u16,
+        headers: &[(String, String)],
+    ) -> bool {
         if !self.config.enabled {
             return false;
         }
@@ -253,6 +492,20 @@ impl ResponseCache {
             return false;
         }
 
+        // Per-client responses must not be served to anyone else, whatever the policy
+        let per_client = headers.iter().any(|(name, value)| {
+            name.eq_ignore_ascii_case("set-cookie")
+                || (name.eq_ignore_ascii_case("cache-control")
+                    && value.split(',').any(|d| d.split('=').next().unwrap_or(d).trim().eq_ignore_ascii_case("private")))
+        });
+        if per_client {
+            return false;
+        }
+
+        if !policy.respect_cache_control {
+            return true;
+        }
+
         // Check Cache-Control header per RFC 7234
         for (name, value) in headers {
             if name.eq_ignore_ascii_case("cache-control") {
@@ -278,6 +531,18 @@ impl ResponseCache {
         true
     }
 
+    /// TTL for a response under a route's policy
+    ///
+    /// The override wins; otherwise the TTL comes from `Cache-Control`, or
+    /// is the default TTL when the route ignores upstream cache headers.
+    pub fn ttl_for(&self, policy: &CachePolicy, headers: &[(String, String)]) -> Duration {
+        match policy.ttl_override {
+            Some(ttl) => ttl,
+            None if policy.respect_cache_control => self.parse_ttl(headers),
+            None => Duration::from_secs(self.config.default_ttl),
+        }
+    }
+
     /// Parse Cache-Control header to determine TTL
     pub fn parse_ttl(&self, headers: &[(String, String)]) -> Duration {
         let mut max_age: Option<u64> = None;
@@ -360,6 +625,32 @@ mod tests {
         assert_eq!(key.to_string_key(), "GET:example.com:/api/users|accept-encoding:gzip");
     }
 
+    #[test]
+    fn test_query_filter_varies_only_on_significant_params() {
+        let filter = CacheKeyQuery::from_config(&CacheKeyQueryConfig {
+            vary: vec!["page".to_string()],
+            ignore: vec![],
+        });
+        let key = |query: &str| CacheKey::new("GET", "example.com", "/posts", Some(query)).with_query_filter(&filter);
+
+        // ?page=2 is its own entry, ?ref=twitter shares the unparameterized one
+        assert_ne!(key("page=2"), key("page=1"));
+        assert_eq!(key("ref=twitter"), CacheKey::new("GET", "example.com", "/posts", None));
+        assert_eq!(key("page=2&ref=twitter"), key("ref=facebook&page=2"));
+        assert_eq!(key("ref=twitter&page=2").to_string_key(), "GET:example.com:/posts?page=2");
+    }
+
+    #[test]
+    fn test_query_filter_ignore_list() {
+        let filter = CacheKeyQuery::from_config(&CacheKeyQueryConfig {
+            vary: vec![],
+            ignore: vec!["ref".to_string(), "utm_source".to_string()],
+        });
+        assert_eq!(filter.apply("sort=asc&ref=twitter&page=2&utm_source=x").as_deref(), Some("page=2&sort=asc"));
+        assert_eq!(filter.apply("ref=twitter"), None);
+        assert_eq!(filter.apply("tag=a&tag=b").as_deref(), Some("tag=a&tag=b"));
+    }
+
     #[test]
     fn test_cache_put_get() {
         let cache = ResponseCache::new(CacheConfig::default());
@@ -443,6 +734,45 @@ mod tests {
         assert_eq!(cache.parse_ttl(&headers), Duration::from_secs(300));
     }
 
+    #[test]
+    fn test_ttl_override_wins_over_max_age() {
+        let cache = ResponseCache::new(CacheConfig::default());
+        let policy = CachePolicy {
+            ttl_override: Some(Duration::from_secs(30)),
+            ..CachePolicy::default()
+        };
+
+        let headers = vec![("Cache-Control".to_string(), "max-age=600, s-maxage=1200".to_string())];
+        assert_eq!(cache.ttl_for(&policy, &headers), Duration::from_secs(30));
+        assert_eq!(cache.ttl_for(&policy, &[]), Duration::from_secs(30));
+        assert_eq!(cache.ttl_for(&CachePolicy::default(), &headers), Duration::from_secs(1200));
+    }
+
+    #[test]
+    fn test_ignoring_upstream_cache_control() {
+        let cache = ResponseCache::new(CacheConfig::default());
+        let ignore = CachePolicy {
+            respect_cache_control: false,
+            ..CachePolicy::default()
+        };
+
+        let no_cache = vec![("Cache-Control".to_string(), "no-cache, max-age=0".to_string())];
+        assert!(!cache.is_cacheable_for(&CachePolicy::default(), "GET", 200, &no_cache));
+        assert!(cache.is_cacheable_for(&ignore, "GET", 200, &no_cache));
+        assert_eq!(cache.ttl_for(&ignore, &no_cache), Duration::from_secs(300));
+
+        // Method and status rules still apply
+        assert!(!cache.is_cacheable_for(&ignore, "POST", 200, &no_cache));
+        assert!(!cache.is_cacheable_for(&ignore, "GET", 500, &no_cache));
+
+        // Per-client responses are never cached
+        let private = vec![("Cache-Control".to_string(), "Private, max-age=60".to_string())];
+        assert!(!cache.is_cacheable_for(&ignore, "GET", 200, &private));
+        let cookie = vec![("Set-Cookie".to_string(), "session=abc".to_string())];
+        assert!(!cache.is_cacheable_for(&ignore, "GET", 200, &cookie));
+        assert!(!cache.is_cacheable_for(&CachePolicy::default(), "GET", 200, &cookie));
+    }
+
     #[test]
     fn test_cache_stats() {
         let cache = ResponseCache::new(CacheConfig::default());
@@ -505,4 +835,197 @@ mod tests {
         assert!(response.is_valid());
         assert!(response.remaining_ttl() <= Duration::from_secs(300));
     }
+
+    fn entry_with_etag(etag: Option<&str>) -> CachedResponse {
+        CachedResponse {
+            status: StatusCode::OK,
+            headers: etag
+                .map(|e| vec![("ETag".to_string(), e.to_string())])
+                .unwrap_or_default(),
+            body: Bytes::from("large cached body"),
+            cached_at: Instant::now() - Duration::from_secs(400),
+            ttl: Duration::from_secs(300),
+            etag: etag.map(|e| e.to_string()),
+            last_modified: None,
+        }
+    }
+
+    #[test]
+    fn test_expired_entry_with_etag_is_revalidated() {
+        let cache = ResponseCache::new(CacheConfig::default());
+        let key = CacheKey::new("GET", "example.com", "/asset", None);
+        cache.put(&key, entry_with_etag(Some("\"v1\"")));
+
+        let stale = match cache.lookup(&key) {
+            CacheLookup::Stale(entry) => entry,
+            other => panic!("expected stale entry, got {:?}", other),
+        };
+        assert_eq!(stale.conditional_headers(), vec![("If-None-Match", "\"v1\"".to_string())]);
+        // Not served as a plain hit
+        assert!(cache.get(&key).is_none());
+        assert_eq!(cache.stats().entries, 1);
+    }
+
+    #[test]
+    fn test_expired_entry_without_validator_removed() {
+        let cache = ResponseCache::new(CacheConfig::default());
+        let key = CacheKey::new("GET", "example.com", "/asset", None);
+        cache.put(&key, entry_with_etag(None));
+
+        assert!(matches!(cache.lookup(&key), CacheLookup::Miss));
+        assert_eq!(cache.stats().entries, 0);
+    }
+
+    #[test]
+    fn test_not_modified_refreshes_entry() {
+        let cache = ResponseCache::new(CacheConfig::default());
+        let key = CacheKey::new("GET", "example.com", "/asset", None);
+        cache.put(&key, entry_with_etag(Some("\"v1\"")));
+        let CacheLookup::Stale(stale) = cache.lookup(&key) else {
+            panic!("expected stale entry");
+        };
+
+        // A 304 carries headers only
+        let not_modified = vec![
+            ("Cache-Control".to_string(), "max-age=60".to_string()),
+            ("ETag".to_string(), "\"v1\"".to_string()),
+            ("Content-Length".to_string(), "0".to_string()),
+        ];
+        let refreshed = cache.refresh(&key, &not_modified, &CachePolicy::default()).unwrap();
+
+        assert!(refreshed.is_valid());
+        assert_eq!(refreshed.ttl, Duration::from_secs(60));
+        // The stored body is reused, not copied or refetched
+        assert_eq!(refreshed.body.as_ptr(), stale.body.as_ptr());
+        assert!(!refreshed.headers.iter().any(|(n, _)| n.eq_ignore_ascii_case("content-length")));
+        assert_eq!(refreshed.headers.iter().filter(|(n, _)| n.eq_ignore_ascii_case("etag")).count(), 1);
+
+        match cache.lookup(&key) {
+            CacheLookup::Hit(entry) => assert_eq!(entry.body, Bytes::from("large cached body")),
+            other => panic!("expected hit after refresh, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_put_replaces_stale_entry_size() {
+        let cache = ResponseCache::new(CacheConfig::default());
+        let key = CacheKey::new("GET", "example.com", "/asset", None);
+        cache.put(&key, entry_with_etag(Some("\"v1\"")));
+        let size = cache.stats().size_bytes;
+        cache.put(&key, entry_with_etag(Some("\"v1\"")));
+        assert_eq!(cache.stats().size_bytes, size);
+    }
+
+    #[test]
+    #[cfg(all(feature = "gzip", feature = "brotli"))]
+    fn test_identity_body_compressed_per_client() {
+        use crate::compression::{compress, finalize_buffered_response, CompressionConfig, CompressionEncoding, ContentKind};
+        use pingora_http::ResponseHeader;
+
+        let page = "<html><body>".to_string() + &"cached page ".repeat(500) + "</body></html>";
+        let gzipped = compress(page.as_bytes(), CompressionEncoding::Gzip, 6).unwrap();
+        let upstream_headers = vec![
+            ("content-type".to_string(), "text/html".to_string()),
+            ("content-encoding".to_string(), "gzip".to_string()),
+            ("content-length".to_string(), gzipped.len().to_string()),
+            ("etag".to_string(), "\"v1\"".to_string()),
+        ];
+
+        // The first (gzip) client's response is stored decoded
+        let cache = ResponseCache::new(CacheConfig::default());
+        let (headers, body) = cache.identity_entry(&upstream_headers, &gzipped).unwrap();
+        assert_eq!(body, page.as_bytes());
+        assert!(!headers.iter().any(|(k, _)| k == "content-encoding" || k == "content-length"));
+        assert!(headers.contains(&("etag".to_string(), "W/\"v1\"".to_string())));
+
+        let key = CacheKey::new("GET", "example.com", "/page", None);
+        cache.put(&key, CachedResponse {
+            status: StatusCode::OK,
+            headers,
+            body,
+            cached_at: Instant::now(),
+            ttl: Duration::from_secs(300),
+            etag: Some("W/\"v1\"".to_string()),
+            last_modified: None,
+        });
+
+        // Each hit is compressed with the codec its client accepts
+        let serve = |encoding: CompressionEncoding| {
+            let cached = cache.get(&key).unwrap();
+            let mut header = ResponseHeader::build(200, None).unwrap();
+            for (name, value) in &cached.headers {
+                header.append_header(name.clone(), value.as_str()).unwrap();
+            }
+            let body = finalize_buffered_response(&mut header, cached.body, encoding, ContentKind::Dynamic, &CompressionConfig::default()).unwrap();
+            (header.headers.get("content-encoding").map(|v| v.to_str().unwrap().to_string()), body)
+        };
+
+        let (encoding, body) = serve(CompressionEncoding::Gzip);
+        assert_eq!(encoding.as_deref(), Some("gzip"));
+        assert_eq!(decompress(&body, CompressionEncoding::Gzip, page.len()).unwrap(), page.as_bytes());
+
+        let (encoding, body) = serve(CompressionEncoding::Brotli);
+        assert_eq!(encoding.as_deref(), Some("br"));
+        assert_eq!(decompress(&body, CompressionEncoding::Brotli, page.len()).unwrap(), page.as_bytes());
+
+        let (encoding, body) = serve(CompressionEncoding::Identity);
+        assert_eq!(encoding, None);
+        assert_eq!(body, page.as_bytes());
+    }
+
+    #[test]
+    fn test_identity_entry_keeps_unknown_coding() {
+        let cache = ResponseCache::new(CacheConfig::default());
+        let headers = vec![("content-encoding".to_string(), "zstd".to_string())];
+        let (stored, body) = cache.identity_entry(&headers, b"opaque").unwrap();
+        assert_eq!(stored, headers);
+        assert_eq!(body, &b"opaque"[..]);
+
+        let plain = vec![("content-type".to_string(), "text/plain".to_string())];
+        assert_eq!(cache.identity_entry(&plain, b"hello").unwrap().0, plain);
+    }
+
+    fn status_of(cache: &ResponseCache, status: CacheStatus) -> Option<String> {
+        let mut response = ResponseHeader::build(200, None).unwrap();
+        cache.set_status_header(&mut response, status).unwrap();
+        response.headers.get("x-cache").map(|v| v.to_str().unwrap().to_string())
+    }
+
+    #[test]
+    fn test_stale_and_bypass_status() {
+        let cache = ResponseCache::new(CacheConfig {
+            stale_if_error: true,
+            ..Default::default()
+        });
+        let key = CacheKey::new("GET", "example.com", "/asset", None);
+        cache.put(&key, entry_with_etag(Some("\"v1\"")));
+
+        // An expired entry served because revalidation failed
+        assert!(matches!(cache.lookup(&key), CacheLookup::Stale(_)));
+        assert!(cache.stale_if_error());
+        assert_eq!(status_of(&cache, CacheStatus::Stale).as_deref(), Some("STALE"));
+
+        // Methods the cache doesn't handle pass it by
+        assert!(!cache.bypasses("GET"));
+        assert!(cache.bypasses("POST"));
+        assert_eq!(status_of(&cache, CacheStatus::Bypass).as_deref(), Some("BYPASS"));
+    }
+
+    #[test]
+    fn test_status_header_renamed_or_disabled() {
+        let renamed = ResponseCache::new(CacheConfig {
+            status_header: Some("X-Edge-Cache".to_string()),
+            ..Default::default()
+        });
+        let mut response = ResponseHeader::build(200, None).unwrap();
+        renamed.set_status_header(&mut response, CacheStatus::Revalidated).unwrap();
+        assert_eq!(response.headers.get("x-edge-cache").unwrap(), "REVALIDATED");
+        assert!(response.headers.get("x-cache").is_none());
+
+        let disabled = ResponseCache::new(CacheConfig {
+            status_header: None,
+            ..Default::default()
+        });
+        assert_eq!(status_of(&disabled, CacheStatus::Hit), None);
+    }
 }
@@ -0,0 +1,273 @@
+//! Flag-controlled canary routing
+//!
+//! A route can carry a second (canary) upstream group. The share of traffic
+//! sent to the canary is read from a flag source on every request, so a
+//! rollout can be advanced or rolled back without editing the config file.
+//! The canary group may carry its own rewrite, for a backend that expects a
+//! different path layout than production.
+
+use crate::error::Result;
+use crate::rewrite::CompiledRewrite;
+use crate::upstream::UpstreamSelector;
+use config::{CanaryConfig, LoadBalancingStrategy};
+use dashmap::DashMap;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use tracing::{debug, warn};
+
+/// Source of canary weights (percentage of traffic, 0-100) keyed by flag name
+pub trait FlagSource: Send + Sync {
+    /// Current weight for a flag, or `None` if the source doesn't know it
+    fn weight(&self, flag: &str) -> Option<u32>;
+}
+
+/// In-process flag source backed by a map
+#[derive(Default)]
+pub struct LocalFlagSource {
+    flags: DashMap<String, u32>,
+}
+
+impl LocalFlagSource {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Set the canary weight for a flag (clamped to 100)
+    pub fn set(&self, flag: &str, weight: u32) {
+        self.flags.insert(flag.to_string(), weight.min(100));
+    }
+
+    /// Remove a flag so routes fall back to their default weight
+    pub fn clear(&self, flag: &str) {
+        self.flags.remove(flag);
+    }
+}
+
+impl FlagSource for LocalFlagSource {
+    fn weight(&self, flag: &str) -> Option<u32> {
+        self.flags.get(flag).map(|w| *w)
+    }
+}
+
+/// Global flag source instance
+static FLAGS: once_cell::sync::Lazy<Arc<LocalFlagSource>> =
+    once_cell::sync::Lazy::new(|| Arc::new(LocalFlagSource::new()));
+
+/// Get the global flag source used by canary routes by default
+pub fn flags() -> &'static Arc<LocalFlagSource> {
+    &FLAGS
+}
+
+/// Compiled canary routing for a route
+pub struct CanaryRouter {
+    selector: Arc<UpstreamSelector>,
+    flag: String,
+    default_weight: u32,
+    header: Option<String>,
+    source: Arc<dyn FlagSource>,
+    counter: AtomicU64,
+    rewrite: Option<Arc<CompiledRewrite>>,
+}
+
+impl CanaryRouter {
+    pub fn from_config(
+        config: &CanaryConfig,
+        strategy: LoadBalancingStrategy,
+        use_tls: bool,
+    ) -> Result<Self> {
+        let selector = UpstreamSelector::new(&config.upstreams, strategy, use_tls)?;
+        let rewrite = config.rewrite.as_ref().and_then(|rewrite_config| {
+            match CompiledRewrite::from_config(rewrite_config) {
+                Ok(rewrite) => Some(Arc::new(rewrite)),
+                Err(e) => {
+                    warn!(flag = %config.flag, error = %e, "Failed to compile canary rewrite rules, skipping");
+                    None
+                }
+            }
+        });
+
+        Ok(Self {
+            selector: Arc::new(selector),
+            flag: config.flag.clone(),
+            default_weight: config.default_weight.min(100),
+            header: config.header.as_ref().map(|h| h.to_lowercase()),
+            source: flags().clone(),
+            counter: AtomicU64::new(0),
+            rewrite,
+        })
+    }
+
+    /// Use a different flag source (e.g. one backed by an external flag service)
+    pub fn with_flag_source(mut self, source: Arc<dyn FlagSource>) -> Self {
+        self.source = source;
+        self
+    }
+
+    /// Upstream selector for the canary group
+    pub fn selector(&self) -> &Arc<UpstreamSelector> {
+        &self.selector
+    }
+
+    /// Rewrite for a request sent to the chosen group
+    ///
+    /// Production requests keep the route's rewrite; canary requests use the
+    /// canary group's rewrite when it has one.
+    pub fn rewrite_for(
+        &self,
+        canary: bool,
+        route_rewrite: Option<&Arc<CompiledRewrite>>,
+    ) -> Option<Arc<CompiledRewrite>> {
+        match (&self.rewrite, canary) {
+            (Some(rewrite), true) => Some(rewrite.clone()),
+            _ => route_rewrite.cloned(),
+        }
+    }
+
+    /// Request header that overrides the flag, if configured (lowercase)
+    pub fn header(&self) -> Option<&str> {
+        self.header.as_deref()
+    }
+
+    /// Current canary weight from the flag source
+    pub fn weight(&self) -> u32 {
+        self.source
+            .weight(&self.flag)
+            .unwrap_or(self.default_weight)
+            .min(100)
+    }
+
+    /// Decide whether this request goes to the canary group
+    ///
+    /// `header_value` is the value of the override header, if present.
+    pub fn use_canary(&self, header_value: Option<&str>) -> bool {
+        if let Some(value) = header_value {
+            match value.trim().to_lowercase().as_str() {
+                "1" | "true" | "on" | "always" => return true,
+                "0" | "false" | "off" | "never" => return false,
+                _ => {}
+            }
+        }
+
+        let weight = self.weight() as u64;
+        let selected = match weight {
+            0 => false,
+            100 => true,
+            _ => self.counter.fetch_add(1, Ordering::Relaxed) % 100 < weight,
+        };
+
+        debug!(flag = %self.flag, weight = weight, canary = selected, "Canary decision");
+        selected
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::rewrite::rewrite_uri;
+    use config::RewriteConfig;
+
+    fn make_router(source: Arc<LocalFlagSource>, header: Option<&str>) -> CanaryRouter {
+        let config = CanaryConfig {
+            upstreams: vec!["127.0.0.1:9001".to_string()],
+            flag: "checkout-v2".to_string(),
+            default_weight: 0,
+            header: header.map(|h| h.to_string()),
+            rewrite: Some(RewriteConfig {
+                strip_path_prefix: Some("/api".to_string()),
+                add_path_prefix: Some("/v2".to_string()),
+                ..Default::default()
+            }),
+        };
+        CanaryRouter::from_config(&config, LoadBalancingStrategy::RoundRobin, false)
+            .unwrap()
+            .with_flag_source(source)
+    }
+
+    #[test]
+    fn test_flag_flip_reroutes() {
+        let source = Arc::new(LocalFlagSource::new());
+        let router = make_router(source.clone(), None);
+
+        assert!(!router.use_canary(None));
+
+        source.set("checkout-v2", 100);
+        assert!(router.use_canary(None));
+        assert!(router.use_canary(None));
+
+        source.set("checkout-v2", 0);
+        assert!(!router.use_canary(None));
+
+        source.set("checkout-v2", 100);
+        source.clear("checkout-v2");
+        assert!(!router.use_canary(None));
+    }
+
+    #[test]
+    fn test_weighted_split() {
+        let source = Arc::new(LocalFlagSource::new());
+        source.set("checkout-v2", 25);
+        let router = make_router(source, None);
+
+        let canary = (0..100).filter(|_| router.use_canary(None)).count();
+        assert_eq!(canary, 25);
+    }
+
+    #[test]
+    fn test_header_override() {
+        let source = Arc::new(LocalFlagSource::new());
+        let router = make_router(source.clone(), Some("X-Canary"));
+
+        assert_eq!(router.header(), Some("x-canary"));
+        assert!(router.use_canary(Some("true")));
+
+        source.set("checkout-v2", 100);
+        assert!(!router.use_canary(Some("false")));
+        assert!(router.use_canary(Some("unrecognized")));
+    }
+
+    #[test]
+    fn test_weight_clamped() {
+        let source = Arc::new(LocalFlagSource::new());
+        source.set("checkout-v2", 500);
+        let router = make_router(source, None);
+        assert_eq!(router.weight(), 100);
+    }
+
+    #[test]
+    fn test_canary_rewrite_applies_to_canary_only() {
+        let source = Arc::new(LocalFlagSource::new());
+        let router = make_router(source, Some("X-Canary"));
+        let production = Arc::new(
+            CompiledRewrite::from_config(&RewriteConfig {
+                strip_path_prefix: Some("/api".to_string()),
+                ..Default::default()
+            })
+            .unwrap(),
+        );
+
+        let canary = router.use_canary(Some("true"));
+        let rewrite = router.rewrite_for(canary, Some(&production)).unwrap();
+        assert_eq!(rewrite_uri("/api/orders?id=1", &rewrite), "/v2/orders?id=1");
+
+        let canary = router.use_canary(Some("false"));
+        let rewrite = router.rewrite_for(canary, Some(&production)).unwrap();
+        assert_eq!(rewrite_uri("/api/orders?id=1", &rewrite), "/orders?id=1");
+        assert!(router.rewrite_for(false, None).is_none());
+    }
+
+    #[test]
+    fn test_canary_without_rewrite_keeps_route_rewrite() {
+        let config = CanaryConfig {
+            upstreams: vec!["127.0.0.1:9001".to_string()],
+            flag: "checkout-v2".to_string(),
+            default_weight: 100,
+            header: None,
+            rewrite: None,
+        };
+        let router = CanaryRouter::from_config(&config, LoadBalancingStrategy::RoundRobin, false).unwrap();
+        let production = Arc::new(CompiledRewrite::from_config(&RewriteConfig::default()).unwrap());
+
+        let rewrite = router.rewrite_for(router.use_canary(None), Some(&production)).unwrap();
+        assert!(Arc::ptr_eq(&rewrite, &production));
+    }
+}
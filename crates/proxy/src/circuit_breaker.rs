@@ -6,7 +6,13 @@
 //! - Closed: Normal operation, requests pass through
 //! - Open: Upstream is considered down, requests fail immediately
 //! - HalfOpen: After timeout, allows one test request to check recovery
+//!
+//! Routes with `circuit_breaker` give each upstream its own breaker. The
+//! built-in `GET /circuits` endpoint lists them, and `POST
+//! /circuits/{upstream}/reset` closes a tripped one without waiting for the
+//! timeout.
 
+use crate::access_log::escape_json;
 use std::sync::atomic::{AtomicU64, AtomicU8, Ordering};
 use std::time::{Duration, Instant};
 use parking_lot::RwLock;
@@ -24,6 +30,16 @@ pub enum CircuitState {
     HalfOpen = 2,
 }
 
+impl CircuitState {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            CircuitState::Closed => "closed",
+            CircuitState::Open => "open",
+            CircuitState::HalfOpen => "half_open",
+        }
+    }
+}
+
 impl From<u8> for CircuitState {
     fn from(v: u8) -> Self {
         match v {
@@ -48,6 +64,17 @@ pub struct CircuitBreakerConfig {
     pub window_size: Duration,
 }
 
+impl CircuitBreakerConfig {
+    pub fn from_config(config: &config::CircuitBreakerConfigDef) -> Self {
+        Self {
+            failure_threshold: config.failure_threshold,
+            success_threshold: config.success_threshold,
+            timeout: Duration::from_secs(config.timeout),
+            window_size: Duration::from_secs(config.window_size),
+        }
+    }
+}
+
 impl Default for CircuitBreakerConfig {
     fn default() -> Self {
         Self {
@@ -60,6 +87,7 @@ impl Default for CircuitBreakerConfig {
 }
 
 /// Circuit breaker implementation
+#[derive(Debug)]
 pub struct CircuitBreaker {
     config: CircuitBreakerConfig,
     state: AtomicU8,
@@ -215,6 +243,28 @@ pub struct CircuitBreakerStats {
     pub time_in_current_state: Option<Duration>,
 }
 
+/// JSON body of the `GET /circuits` endpoint
+pub fn circuits_json(stats: &[CircuitBreakerStats]) -> String {
+    let circuits: Vec<String> = stats
+        .iter()
+        .map(|s| {
+            let open_for = match s.time_in_current_state {
+                Some(elapsed) => elapsed.as_millis().to_string(),
+                None => "null".to_string(),
+            };
+            format!(
+                r#"{{"upstream":"{}","state":"{}","failures":{},"successes":{},"open_for_ms":{}}}"#,
+                escape_json(&s.name),
+                s.state.as_str(),
+                s.failure_count,
+                s.success_count,
+                open_for
+            )
+        })
+        .collect();
+    format!(r#"{{"circuits":[{}]}}"#, circuits.join(","))
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -393,4 +443,20 @@ mod tests {
         cb.record_failure();
         assert_eq!(cb.state(), CircuitState::Open);
     }
+
+    #[test]
+    fn test_circuits_json() {
+        let config = CircuitBreakerConfig {
+            failure_threshold: 1,
+            ..Default::default()
+        };
+        let closed = CircuitBreaker::new("10.0.0.1:80", config.clone());
+        let open = CircuitBreaker::new("10.0.0.2:80", config);
+        open.record_failure();
+
+        let json = circuits_json(&[closed.stats(), open.stats()]);
+        assert!(json.starts_with(r#"{"circuits":[{"upstream":"10.0.0.1:80","state":"closed","failures":0,"successes":0,"open_for_ms":null},"#), "{}", json);
+        assert!(json.contains(r#"{"upstream":"10.0.0.2:80","state":"open","failures":1,"successes":0,"open_for_ms":"#), "{}", json);
+        assert_eq!(circuits_json(&[]), r#"{"circuits":[]}"#);
+    }
 }
@@ -0,0 +1,111 @@
+//! Client certificate conditions for route matching
+//!
+//! With `tls.client_ca` set, TLS listeners ask clients for a certificate but
+//! still accept connections without one, so a single listener can serve
+//! public routes next to routes gated on `match.client_cert`. A certificate
+//! that fails verification fails the handshake, so any certificate seen here
+//! has already been verified.
+
+use config::MatchConfig;
+use pingora_core::protocols::tls::SslDigest;
+
+/// Verified client certificate of a downstream connection
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ClientCert {
+    /// Organization (O) of the certificate subject
+    pub organization: Option<String>,
+}
+
+impl ClientCert {
+    /// Certificate presented in a handshake, if the client sent one
+    pub fn from_digest(digest: &SslDigest) -> Option<Self> {
+        // The digest of the peer certificate is empty when none was presented
+        (!digest.cert_digest.is_empty()).then(|| Self {
+            organization: digest.organization.clone(),
+        })
+    }
+}
+
+/// Compiled `match.client_cert` and `match.client_cert_organization`
+pub struct ClientCertMatcher {
+    /// Whether a certificate must (true) or must not (false) be presented
+    presented: bool,
+    /// Accepted subject organizations, empty for any
+    organizations: Vec<String>,
+}
+
+impl ClientCertMatcher {
+    /// Matcher for a route's conditions, `None` when it has none
+    pub fn from_config(config: &MatchConfig) -> Option<Self> {
+        let organizations = config.client_cert_organization.clone().unwrap_or_default();
+        let presented = match config.client_cert {
+            Some(presented) => presented,
+            None if !organizations.is_empty() => true,
+            None => return None,
+        };
+        Some(Self {
+            presented,
+            organizations,
+        })
+    }
+
+    /// Whether the connection's certificate, if any, satisfies the conditions
+    pub fn matches(&self, cert: Option<&ClientCert>) -> bool {
+        match cert {
+            None => !self.presented,
+            Some(_) if !self.presented => false,
+            Some(cert) => {
+                self.organizations.is_empty()
+                    || cert
+                        .organization
+                        .as_deref()
+                        .is_some_and(|org| self.organizations.iter().any(|o| o == org))
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn cert(organization: Option<&str>) -> ClientCert {
+        ClientCert {
+            organization: organization.map(str::to_string),
+        }
+    }
+
+    #[test]
+    fn test_required_and_forbidden() {
+        let required = ClientCertMatcher::from_config(&MatchConfig {
+            client_cert: Some(true),
+            ..Default::default()
+        })
+        .unwrap();
+        assert!(!required.matches(None));
+        assert!(required.matches(Some(&cert(None))));
+
+        let forbidden = ClientCertMatcher::from_config(&MatchConfig {
+            client_cert: Some(false),
+            ..Default::default()
+        })
+        .unwrap();
+        assert!(forbidden.matches(None));
+        assert!(!forbidden.matches(Some(&cert(Some("Acme")))));
+
+        assert!(ClientCertMatcher::from_config(&MatchConfig::default()).is_none());
+    }
+
+    #[test]
+    fn test_organization_implies_certificate() {
+        let matcher = ClientCertMatcher::from_config(&MatchConfig {
+            client_cert_organization: Some(vec!["Acme Ops".to_string(), "Acme Billing".to_string()]),
+            ..Default::default()
+        })
+        .unwrap();
+        assert!(!matcher.matches(None));
+        assert!(matcher.matches(Some(&cert(Some("Acme Billing")))));
+        assert!(!matcher.matches(Some(&cert(Some("Other Corp")))));
+        assert!(!matcher.matches(Some(&cert(None))));
+    }
+}
@@ -0,0 +1,73 @@
+//! Clients that close the connection before their response is complete
+//!
+//! Browsers routinely give up on requests (navigation, closed tabs,
+//! cancelled fetches). Pingora then stops proxying and drops the upstream
+//! connection instead of returning it to the pool, and hands the downstream
+//! error to `logging`. Such requests are logged and counted with nginx's
+//! non-standard 499 status rather than as server errors.
+
+use pingora_core::{Error, ErrorSource, ErrorType};
+
+/// Status logged for requests the client abandoned (nginx's "Client Closed Request")
+pub const CLIENT_CLOSED_REQUEST: u16 = 499;
+
+/// Whether a request failed because the client went away
+pub fn is_client_disconnect(error: &Error) -> bool {
+    matches!(error.esource(), ErrorSource::Downstream)
+        && matches!(
+            error.etype(),
+            ErrorType::ConnectionClosed | ErrorType::WriteError | ErrorType::ReadError
+        )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pingora_core::protocols::http::ServerSession;
+    use pingora_http::ResponseHeader;
+    use tokio::io::AsyncWriteExt;
+    use tokio::net::{TcpListener, TcpStream};
+
+    #[test]
+    fn test_only_downstream_io_errors() {
+        assert!(is_client_disconnect(&Error::new(ErrorType::WriteError).into_down()));
+        assert!(is_client_disconnect(&Error::new(ErrorType::ConnectionClosed).into_down()));
+        // The same failure on the upstream side is a 502, not a cancellation
+        assert!(!is_client_disconnect(&Error::new(ErrorType::ConnectionClosed).into_up()));
+        assert!(!is_client_disconnect(&Error::new(ErrorType::InvalidHTTPHeader).into_down()));
+    }
+
+    #[tokio::test]
+    async fn test_client_closing_mid_request() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+
+        let mut client = TcpStream::connect(addr).await.unwrap();
+        client.write_all(b"GET /slow HTTP/1.1\r\nHost: example.com\r\n\r\n").await.unwrap();
+
+        let (stream, _) = listener.accept().await.unwrap();
+        let mut session = ServerSession::new_http1(Box::new(pingora_core::protocols::l4::stream::Stream::from(stream)));
+        assert!(session.read_request().await.unwrap());
+
+        // The client gives up while the upstream is still working
+        drop(client);
+
+        // What the proxy sees while waiting on the upstream...
+        let waiting = session.read_body_or_idle(true).await.map_err(|e| e.into_down()).unwrap_err();
+        assert!(is_client_disconnect(&waiting), "{}", waiting);
+
+        // ...or while streaming the response back
+        let mut header = ResponseHeader::build(200, None).unwrap();
+        header.insert_header("Content-Length", (64 * 1024 * 1024).to_string()).unwrap();
+        let mut result = session.write_response_header(Box::new(header)).await;
+        let chunk = bytes::Bytes::from(vec![b'x'; 64 * 1024]);
+        for _ in 0..1024 {
+            if result.is_err() {
+                break;
+            }
+            result = session.write_response_body(chunk.clone(), false).await.map(|_| ());
+        }
+        let writing = result.map_err(|e| e.into_down()).unwrap_err();
+        assert!(is_client_disconnect(&writing), "{}", writing);
+    }
+}
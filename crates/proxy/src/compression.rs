@@ -1,10 +1,14 @@
 //! Response compression support (gzip, brotli)
 
 use bytes::Bytes;
+#[cfg(feature = "gzip")]
 use flate2::write::GzEncoder;
+#[cfg(feature = "gzip")]
 use flate2::Compression;
-use std::io::Write;
-use tracing::debug;
+use pingora_http::ResponseHeader;
+#[cfg(any(feature = "gzip", feature = "brotli"))]
+use std::io::{Read, Write};
+use tracing::{debug, warn};
 
 /// Compression encoding types
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
@@ -23,6 +27,24 @@ impl CompressionEncoding {
             CompressionEncoding::Identity => "identity",
         }
     }
+
+    /// Whether this codec was compiled in
+    pub fn is_available(&self) -> bool {
+        match self {
+            CompressionEncoding::Gzip => cfg!(feature = "gzip"),
+            CompressionEncoding::Brotli => cfg!(feature = "brotli"),
+            CompressionEncoding::Identity => true,
+        }
+    }
+}
+
+/// Where a response body comes from
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ContentKind {
+    /// Produced per request: proxied, scripted or replayed from the cache
+    Dynamic,
+    /// Served by the file server
+    Static,
 }
 
 /// Compression configuration
@@ -36,6 +58,10 @@ pub struct CompressionConfig {
     pub min_size: usize,
     /// Compression level (1-9 for gzip, 0-11 for brotli)
     pub level: u32,
+    /// Brotli quality for `ContentKind::Dynamic` bodies
+    pub brotli_dynamic_level: u32,
+    /// Brotli quality for `ContentKind::Static` bodies
+    pub brotli_static_level: u32,
 }
 
 impl Default for CompressionConfig {
@@ -45,26 +71,64 @@ impl Default for CompressionConfig {
             brotli: true,
             min_size: 1024, // Don't compress responses smaller than 1KB
             level: 6,
+            brotli_dynamic_level: 6,
+            brotli_static_level: 6,
         }
     }
 }
 
-/// Parsed encoding with quality factor
-#[derive(Debug, Clone)]
-struct EncodingQuality {
-    encoding: &'static str,
-    quality: f32,
+impl CompressionConfig {
+    /// Level to compress a body of `kind` with
+    ///
+    /// High brotli qualities are only worth it for files, whose cost is paid
+    /// by far fewer bytes on every request; per-request bodies sit on the
+    /// latency path and get their own, usually lower, quality.
+    pub fn level_for(&self, encoding: CompressionEncoding, kind: ContentKind) -> u32 {
+        match (encoding, kind) {
+            (CompressionEncoding::Brotli, ContentKind::Dynamic) => self.brotli_dynamic_level,
+            (CompressionEncoding::Brotli, ContentKind::Static) => self.brotli_static_level,
+            _ => self.level,
+        }
+    }
+
+    /// Turn off codecs that are enabled but not compiled in
+    ///
+    /// Returns the names of the codecs that were disabled.
+    pub fn disable_unavailable(&mut self) -> Vec<&'static str> {
+        let mut disabled = Vec::new();
+        if self.gzip && !CompressionEncoding::Gzip.is_available() {
+            self.gzip = false;
+            disabled.push("gzip");
+        }
+        if self.brotli && !CompressionEncoding::Brotli.is_available() {
+            self.brotli = false;
+            disabled.push("brotli");
+        }
+        disabled
+    }
+
+    fn gzip_usable(&self) -> bool {
+        self.gzip && CompressionEncoding::Gzip.is_available()
+    }
+
+    fn brotli_usable(&self) -> bool {
+        self.brotli && CompressionEncoding::Brotli.is_available()
+    }
 }
 
 /// Parse Accept-Encoding header and select best encoding according to RFC 7231 Section 5.3.4
+///
+/// Each codec's quality is its own entry if listed, otherwise that of `*`.
+/// The highest quality enabled codec wins, ties going to the server's
+/// preference (br > gzip > identity). An empty header, or one excluding every
+/// codec, leaves the response uncompressed.
 pub fn select_encoding(accept_encoding: Option<&str>, config: &CompressionConfig) -> CompressionEncoding {
     let accept = match accept_encoding {
         Some(ae) => ae,
         None => return CompressionEncoding::Identity,
     };
 
-    // Parse all encodings with their quality factors
-    let mut encodings: Vec<EncodingQuality> = Vec::new();
+    let mut explicit: Vec<(String, f32)> = Vec::new();
     let mut wildcard_quality: Option<f32> = None;
 
     for part in accept.split(',') {
@@ -74,81 +138,48 @@ pub fn select_encoding(accept_encoding: Option<&str>, config: &CompressionConfig
         }
 
         let (encoding, quality) = parse_encoding_quality(part);
-        let encoding_lower = encoding.to_lowercase();
-
-        if encoding_lower == "*" {
+        let encoding = encoding.to_ascii_lowercase();
+        if encoding == "*" {
             wildcard_quality = Some(quality);
-        } else if encoding_lower == "br" || encoding_lower == "gzip" || encoding_lower == "identity" {
-            encodings.push(EncodingQuality {
-                encoding: match encoding_lower.as_str() {
-                    "br" => "br",
-                    "gzip" => "gzip",
-                    "identity" => "identity",
-                    _ => continue,
-                },
-                quality,
-            });
-        }
-    }
-
-    // Sort by quality (descending), then by server preference (br > gzip > identity)
-    // Server preference order: br (best compression) > gzip > identity
-    fn encoding_preference(enc: &str) -> u8 {
-        match enc {
-            "br" => 0,      // Most preferred
-            "gzip" => 1,
-            "identity" => 2,
-            _ => 3,
+        } else {
+            explicit.push((encoding, quality));
         }
     }
-    encodings.sort_by(|a, b| {
-        match b.quality.partial_cmp(&a.quality) {
-            Some(std::cmp::Ordering::Equal) | None => {
-                // When quality is equal, use server preference
-                encoding_preference(a.encoding).cmp(&encoding_preference(b.encoding))
-            }
-            Some(ord) => ord,
-        }
-    });
-
-    // Check if identity is explicitly rejected (q=0)
-    let identity_rejected = encodings.iter().any(|e| e.encoding == "identity" && e.quality == 0.0)
-        || (wildcard_quality == Some(0.0) && !encodings.iter().any(|e| e.encoding == "identity" && e.quality > 0.0));
 
-    // Select best encoding that is enabled and not rejected (q=0)
-    for enc in &encodings {
-        if enc.quality == 0.0 {
-            continue; // Explicitly rejected
-        }
-
-        match enc.encoding {
-            "br" if config.brotli => return CompressionEncoding::Brotli,
-            "gzip" if config.gzip => return CompressionEncoding::Gzip,
-            "identity" => return CompressionEncoding::Identity,
-            _ => continue,
-        }
-    }
+    let quality_of = |name: &str| {
+        explicit
+            .iter()
+            .find(|(encoding, _)| encoding == name)
+            .map(|(_, quality)| *quality)
+            .or(wildcard_quality)
+    };
 
-    // If wildcard is present with quality > 0, use best available encoding
-    if let Some(q) = wildcard_quality {
-        if q > 0.0 {
-            if config.brotli && !encodings.iter().any(|e| e.encoding == "br" && e.quality == 0.0) {
-                return CompressionEncoding::Brotli;
-            }
-            if config.gzip && !encodings.iter().any(|e| e.encoding == "gzip" && e.quality == 0.0) {
-                return CompressionEncoding::Gzip;
-            }
+    // Candidates in server preference order, so ties keep the earlier one
+    let candidates = [
+        (CompressionEncoding::Brotli, config.brotli_usable()),
+        (CompressionEncoding::Gzip, config.gzip_usable()),
+        (CompressionEncoding::Identity, true),
+    ];
+    let mut best: Option<(CompressionEncoding, f32)> = None;
+    for (encoding, usable) in candidates {
+        // Identity is only a contender when the client ranks it itself;
+        // otherwise it is the fallback
+        let quality = match encoding {
+            CompressionEncoding::Identity => explicit.iter().find(|(e, _)| e == "identity").map(|(_, q)| *q),
+            _ => quality_of(encoding.header_value()),
+        };
+        let Some(quality) = quality.filter(|q| *q > 0.0 && usable) else {
+            continue;
+        };
+        match best {
+            Some((_, best_quality)) if quality <= best_quality => {}
+            _ => best = Some((encoding, quality)),
         }
     }
 
-    // Default to identity if not rejected
-    if identity_rejected {
-        // Client rejects identity - this is technically a 406 Not Acceptable situation
-        // but we'll return Identity anyway and let the caller decide
-        CompressionEncoding::Identity
-    } else {
-        CompressionEncoding::Identity
-    }
+    // A client rejecting identity with nothing else usable is technically a
+    // 406 Not Acceptable situation, but we return Identity and let the caller decide
+    best.map(|(encoding, _)| encoding).unwrap_or(CompressionEncoding::Identity)
 }
 
 /// Parse encoding and quality factor from a single Accept-Encoding part
@@ -193,6 +224,22 @@ pub fn should_compress_content_type(content_type: Option<&str>) -> bool {
         || ct.contains("application/yaml")
 }
 
+/// Whether a proxied response body goes through the compression path
+///
+/// `route_compress` is the route's `compress` switch; when off the body is
+/// streamed through untouched whatever its content type.
+pub fn should_compress_response(
+    route_compress: bool,
+    encoding: CompressionEncoding,
+    already_compressed: bool,
+    content_type: Option<&str>,
+) -> bool {
+    route_compress
+        && encoding != CompressionEncoding::Identity
+        && !already_compressed
+        && should_compress_content_type(content_type)
+}
+
 /// Check if response is already compressed
 /// Properly parses Content-Encoding header per RFC 7231 Section 3.1.2.2
 pub fn is_already_compressed(content_encoding: Option<&str>) -> bool {
@@ -215,6 +262,7 @@ pub fn is_already_compressed(content_encoding: Option<&str>) -> bool {
 }
 
 /// Compress data using gzip
+#[cfg(feature = "gzip")]
 pub fn compress_gzip(data: &[u8], level: u32) -> Result<Bytes, std::io::Error> {
     let level = level.min(9);
     let mut encoder = GzEncoder::new(Vec::new(), Compression::new(level));
@@ -232,6 +280,7 @@ pub fn compress_gzip(data: &[u8], level: u32) -> Result<Bytes, std::io::Error> {
 }
 
 /// Compress data using brotli
+#[cfg(feature = "brotli")]
 pub fn compress_brotli(data: &[u8], level: u32) -> Result<Bytes, std::io::Error> {
     let level = level.min(11);
     let mut compressed = Vec::new();
@@ -258,12 +307,116 @@ pub fn compress_brotli(data: &[u8], level: u32) -> Result<Bytes, std::io::Error>
 /// Compress data with the specified encoding
 pub fn compress(data: &[u8], encoding: CompressionEncoding, level: u32) -> Result<Bytes, std::io::Error> {
     match encoding {
+        #[cfg(feature = "gzip")]
         CompressionEncoding::Gzip => compress_gzip(data, level),
+        #[cfg(feature = "brotli")]
         CompressionEncoding::Brotli => compress_brotli(data, level),
+        #[allow(unreachable_patterns)]
+        CompressionEncoding::Gzip | CompressionEncoding::Brotli => Err(std::io::Error::new(
+            std::io::ErrorKind::Unsupported,
+            format!("{} support was not compiled in", encoding.header_value()),
+        )),
+        CompressionEncoding::Identity => Ok(Bytes::copy_from_slice(data)),
+    }
+}
+
+/// Compress data, or `None` when the result is no smaller than the input
+///
+/// Already-compressed or random content grows slightly under gzip and
+/// brotli; such bodies are better sent with identity encoding. Compression
+/// errors are logged and also yield `None`.
+pub fn compress_if_smaller(data: &[u8], encoding: CompressionEncoding, level: u32) -> Option<Bytes> {
+    match compress(data, encoding, level) {
+        Ok(compressed) if compressed.len() < data.len() => Some(compressed),
+        Ok(compressed) => {
+            debug!(
+                original_size = data.len(),
+                compressed_size = compressed.len(),
+                encoding = %encoding.header_value(),
+                "Compression did not shrink the body, sending uncompressed"
+            );
+            None
+        }
+        Err(e) => {
+            warn!(error = %e, "Compression failed, sending uncompressed");
+            None
+        }
+    }
+}
+
+/// Codec of a `Content-Encoding` value that avalon can decode
+///
+/// Only a single compiled-in codec qualifies; stacked codings such as
+/// `gzip, br` and codecs like `deflate` or `zstd` are left alone.
+pub fn decodable_encoding(content_encoding: &str) -> Option<CompressionEncoding> {
+    let encoding = match content_encoding.trim().to_ascii_lowercase().as_str() {
+        "gzip" | "x-gzip" => CompressionEncoding::Gzip,
+        "br" => CompressionEncoding::Brotli,
+        _ => return None,
+    };
+    encoding.is_available().then_some(encoding)
+}
+
+/// Decompress data, failing if the output would exceed `limit` bytes
+pub fn decompress(data: &[u8], encoding: CompressionEncoding, limit: usize) -> Result<Bytes, std::io::Error> {
+    #[cfg(any(feature = "gzip", feature = "brotli"))]
+    fn read_limited(mut reader: impl Read, limit: usize) -> Result<Bytes, std::io::Error> {
+        let mut decoded = Vec::new();
+        reader.by_ref().take(limit as u64 + 1).read_to_end(&mut decoded)?;
+        if decoded.len() > limit {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::InvalidData,
+                format!("decompressed body exceeds {} bytes", limit),
+            ));
+        }
+        Ok(Bytes::from(decoded))
+    }
+
+    match encoding {
+        #[cfg(feature = "gzip")]
+        CompressionEncoding::Gzip => read_limited(flate2::read::GzDecoder::new(data), limit),
+        #[cfg(feature = "brotli")]
+        CompressionEncoding::Brotli => read_limited(brotli::Decompressor::new(data, 4096), limit),
+        #[allow(unreachable_patterns)]
+        CompressionEncoding::Gzip | CompressionEncoding::Brotli => Err(std::io::Error::new(
+            std::io::ErrorKind::Unsupported,
+            format!("{} support was not compiled in", encoding.header_value()),
+        )),
         CompressionEncoding::Identity => Ok(Bytes::copy_from_slice(data)),
     }
 }
 
+/// Compress a body that is fully buffered before its headers are sent
+///
+/// Sets an exact `Content-Length` (plus `Content-Encoding` when compressed)
+/// and drops `Transfer-Encoding`; chunked framing is only needed when the
+/// body is streamed. Bodies below `min_size`, that fail to compress, or that
+/// compression doesn't shrink are sent as-is.
+pub fn finalize_buffered_response(
+    header: &mut ResponseHeader,
+    body: Bytes,
+    encoding: CompressionEncoding,
+    kind: ContentKind,
+    config: &CompressionConfig,
+) -> pingora_error::Result<Bytes> {
+    let compressed = if encoding != CompressionEncoding::Identity && body.len() >= config.min_size {
+        compress_if_smaller(&body, encoding, config.level_for(encoding, kind))
+    } else {
+        None
+    };
+    let body = match compressed {
+        Some(compressed) => {
+            header.insert_header("Content-Encoding", encoding.header_value())?;
+            compressed
+        }
+        None => body,
+    };
+
+    header.remove_header("transfer-encoding");
+    header.insert_header("Content-Length", body.len().to_string())?;
+    Ok(body)
+}
+
 /// Response compressor that handles chunked responses
 pub struct ResponseCompressor {
     encoding: CompressionEncoding,
@@ -379,6 +532,40 @@ mod tests {
         assert_eq!(encoding, CompressionEncoding::Gzip);
     }
 
+    #[test]
+    fn test_select_encoding_wildcard_edge_cases() {
+        let config = CompressionConfig::default();
+
+        // `*` accepts every codec, so the server's preferred one is used
+        assert_eq!(select_encoding(Some("*"), &config), CompressionEncoding::Brotli);
+        assert_eq!(select_encoding(Some("identity, *"), &config), CompressionEncoding::Brotli);
+
+        // A listed codec ranked below `*` loses to codecs covered by `*`
+        assert_eq!(select_encoding(Some("gzip;q=0.3, *"), &config), CompressionEncoding::Brotli);
+        assert_eq!(select_encoding(Some("*;q=0.5, gzip;q=0.8"), &config), CompressionEncoding::Gzip);
+
+        // `*;q=0` excludes everything not listed
+        assert_eq!(select_encoding(Some("*;q=0, gzip"), &config), CompressionEncoding::Gzip);
+        assert_eq!(select_encoding(Some("*;q=0"), &config), CompressionEncoding::Identity);
+
+        // Codecs the server doesn't enable fall through to the next one `*` allows
+        let gzip_only = CompressionConfig {
+            brotli: false,
+            ..Default::default()
+        };
+        assert_eq!(select_encoding(Some("*"), &gzip_only), CompressionEncoding::Gzip);
+        assert_eq!(select_encoding(Some("zstd, *;q=0.1"), &gzip_only), CompressionEncoding::Gzip);
+    }
+
+    #[test]
+    fn test_select_encoding_empty_header() {
+        let config = CompressionConfig::default();
+        assert_eq!(select_encoding(Some(""), &config), CompressionEncoding::Identity);
+        assert_eq!(select_encoding(Some(" , "), &config), CompressionEncoding::Identity);
+        assert_eq!(select_encoding(Some("identity"), &config), CompressionEncoding::Identity);
+        assert_eq!(select_encoding(Some("zstd"), &config), CompressionEncoding::Identity);
+    }
+
     #[test]
     fn test_select_encoding_identity_rejected() {
         let config = CompressionConfig {
@@ -406,6 +593,19 @@ mod tests {
         assert!(!should_compress_content_type(None));
     }
 
+    #[test]
+    fn test_route_compress_disabled_streams_uncompressed() {
+        let json = Some("application/json");
+        assert!(should_compress_response(true, CompressionEncoding::Gzip, false, json));
+        assert!(!should_compress_response(false, CompressionEncoding::Gzip, false, json));
+        assert!(!should_compress_response(false, CompressionEncoding::Brotli, false, Some("text/html")));
+
+        // The other conditions still apply when the route allows compression
+        assert!(!should_compress_response(true, CompressionEncoding::Identity, false, json));
+        assert!(!should_compress_response(true, CompressionEncoding::Gzip, true, json));
+        assert!(!should_compress_response(true, CompressionEncoding::Gzip, false, Some("image/png")));
+    }
+
     #[test]
     fn test_is_already_compressed() {
         assert!(is_already_compressed(Some("gzip")));
@@ -420,6 +620,7 @@ mod tests {
     }
 
     #[test]
+    #[cfg(feature = "gzip")]
     fn test_compress_gzip() {
         let data = b"Hello, World! This is a test string for compression.";
         let compressed = compress_gzip(data, 6).unwrap();
@@ -428,12 +629,215 @@ mod tests {
     }
 
     #[test]
+    #[cfg(feature = "brotli")]
     fn test_compress_brotli() {
         let data = b"Hello, World! This is a test string for compression.";
         let compressed = compress_brotli(data, 6).unwrap();
         assert!(!compressed.is_empty());
     }
 
+    #[test]
+    #[cfg(all(feature = "gzip", feature = "brotli"))]
+    fn test_decompress_round_trip() {
+        let data = "Hello, World! ".repeat(100);
+        for encoding in [CompressionEncoding::Gzip, CompressionEncoding::Brotli] {
+            let compressed = compress(data.as_bytes(), encoding, 6).unwrap();
+            assert_eq!(decompress(&compressed, encoding, data.len()).unwrap(), data.as_bytes());
+            // Output over the limit is refused rather than buffered
+            assert!(decompress(&compressed, encoding, data.len() - 1).is_err());
+        }
+        assert!(decompress(b"not gzip", CompressionEncoding::Gzip, 1024).is_err());
+    }
+
+    #[test]
+    fn test_decodable_encoding() {
+        let gzip = cfg!(feature = "gzip").then_some(CompressionEncoding::Gzip);
+        let brotli = cfg!(feature = "brotli").then_some(CompressionEncoding::Brotli);
+        assert_eq!(decodable_encoding("gzip"), gzip);
+        assert_eq!(decodable_encoding(" X-Gzip "), gzip);
+        assert_eq!(decodable_encoding("br"), brotli);
+        assert_eq!(decodable_encoding("gzip, br"), None);
+        assert_eq!(decodable_encoding("deflate"), None);
+        assert_eq!(decodable_encoding("zstd"), None);
+    }
+
+    #[test]
+    fn test_unavailable_codecs_are_disabled() {
+        let mut config = CompressionConfig::default();
+        let disabled = config.disable_unavailable();
+
+        assert_eq!(disabled.contains(&"gzip"), !cfg!(feature = "gzip"));
+        assert_eq!(disabled.contains(&"brotli"), !cfg!(feature = "brotli"));
+        assert_eq!(config.gzip, cfg!(feature = "gzip"));
+        assert_eq!(config.brotli, cfg!(feature = "brotli"));
+    }
+
+    #[test]
+    fn test_select_encoding_only_available_codecs() {
+        // Config asks for both codecs even if the build lacks one
+        let config = CompressionConfig::default();
+
+        for accept in ["br", "gzip", "br, gzip", "*", "br;q=1.0, gzip;q=0.5"] {
+            let encoding = select_encoding(Some(accept), &config);
+            assert!(encoding.is_available(), "{} selected unavailable {:?}", accept, encoding);
+        }
+
+        let encoding = select_encoding(Some("br, gzip"), &config);
+        let expected = if cfg!(feature = "brotli") {
+            CompressionEncoding::Brotli
+        } else if cfg!(feature = "gzip") {
+            CompressionEncoding::Gzip
+        } else {
+            CompressionEncoding::Identity
+        };
+        assert_eq!(encoding, expected);
+    }
+
+    #[test]
+    #[cfg(feature = "gzip")]
+    fn test_finalize_buffered_response() {
+        let body = Bytes::from("Hello, World! ".repeat(200));
+        let mut header = ResponseHeader::build(200, None).unwrap();
+        header.insert_header("Transfer-Encoding", "chunked").unwrap();
+        header.insert_header("Content-Length", body.len().to_string()).unwrap();
+
+        let compressed = finalize_buffered_response(
+            &mut header,
+            body.clone(),
+            CompressionEncoding::Gzip,
+            ContentKind::Dynamic,
+            &CompressionConfig::default(),
+        )
+        .unwrap();
+
+        assert!(compressed.len() < body.len());
+        assert_eq!(header.headers.get("content-length").unwrap(), compressed.len().to_string().as_str());
+        assert_eq!(header.headers.get("content-encoding").unwrap(), "gzip");
+        assert!(header.headers.get("transfer-encoding").is_none());
+    }
+
+    #[test]
+    fn test_finalize_buffered_response_small_body() {
+        let mut header = ResponseHeader::build(200, None).unwrap();
+        let body = finalize_buffered_response(
+            &mut header,
+            Bytes::from_static(b"tiny"),
+            CompressionEncoding::Gzip,
+            ContentKind::Dynamic,
+            &CompressionConfig::default(),
+        )
+        .unwrap();
+
+        assert_eq!(body, Bytes::from_static(b"tiny"));
+        assert_eq!(header.headers.get("content-length").unwrap(), "4");
+        assert!(header.headers.get("content-encoding").is_none());
+    }
+
+    /// Deterministic bytes with no redundancy for the compressors to find
+    fn incompressible_body(len: usize) -> Bytes {
+        let mut state = 0x2545_f491_4f6c_dd1du64;
+        (0..len)
+            .map(|_| {
+                state ^= state << 13;
+                state ^= state >> 7;
+                state ^= state << 17;
+                state as u8
+            })
+            .collect::<Vec<u8>>()
+            .into()
+    }
+
+    #[test]
+    #[cfg(feature = "gzip")]
+    fn test_incompressible_body_sent_uncompressed() {
+        let body = incompressible_body(4096);
+        assert!(compress_if_smaller(&body, CompressionEncoding::Gzip, 6).is_none());
+
+        let mut header = ResponseHeader::build(200, None).unwrap();
+        header.insert_header("Transfer-Encoding", "chunked").unwrap();
+        let sent = finalize_buffered_response(
+            &mut header,
+            body.clone(),
+            CompressionEncoding::Gzip,
+            ContentKind::Dynamic,
+            &CompressionConfig::default(),
+        )
+        .unwrap();
+
+        assert_eq!(sent, body);
+        assert!(header.headers.get("content-encoding").is_none());
+        assert_eq!(header.headers.get("content-length").unwrap(), "4096");
+    }
+
+    #[test]
+    #[cfg(feature = "gzip")]
+    fn test_compressible_body_still_compressed() {
+        let body = Bytes::from("<li>item</li>\n".repeat(300));
+        let compressed = compress_if_smaller(&body, CompressionEncoding::Gzip, 6).unwrap();
+        assert!(compressed.len() < body.len());
+
+        let mut header = ResponseHeader::build(200, None).unwrap();
+        let sent = finalize_buffered_response(
+            &mut header,
+            body.clone(),
+            CompressionEncoding::Gzip,
+            ContentKind::Dynamic,
+            &CompressionConfig::default(),
+        )
+        .unwrap();
+        assert_eq!(sent, compressed);
+        assert_eq!(header.headers.get("content-encoding").unwrap(), "gzip");
+    }
+
+    #[test]
+    #[cfg(feature = "brotli")]
+    fn test_incompressible_body_brotli() {
+        let body = incompressible_body(4096);
+        assert!(compress_if_smaller(&body, CompressionEncoding::Brotli, 4).is_none());
+        let text = "Hello, World! ".repeat(200);
+        assert!(compress_if_smaller(text.as_bytes(), CompressionEncoding::Brotli, 4).is_some());
+    }
+
+    #[test]
+    fn test_brotli_level_by_content_kind() {
+        let config = CompressionConfig {
+            level: 6,
+            brotli_dynamic_level: 4,
+            brotli_static_level: 11,
+            ..Default::default()
+        };
+        assert_eq!(config.level_for(CompressionEncoding::Brotli, ContentKind::Static), 11);
+        assert_eq!(config.level_for(CompressionEncoding::Brotli, ContentKind::Dynamic), 4);
+        // gzip keeps the shared level
+        assert_eq!(config.level_for(CompressionEncoding::Gzip, ContentKind::Static), 6);
+        assert_eq!(config.level_for(CompressionEncoding::Gzip, ContentKind::Dynamic), 6);
+    }
+
+    #[test]
+    #[cfg(feature = "brotli")]
+    fn test_file_server_body_compressed_harder() {
+        let config = CompressionConfig {
+            brotli_dynamic_level: 0,
+            brotli_static_level: 11,
+            ..Default::default()
+        };
+        let body: Bytes = (0..2000)
+            .map(|i| format!("<li class=\"item-{}\">entry {} of the listing</li>\n", i % 37, i * 7919 % 1000))
+            .collect::<String>()
+            .into();
+
+        let finalize = |kind| {
+            let mut header = ResponseHeader::build(200, None).unwrap();
+            finalize_buffered_response(&mut header, body.clone(), CompressionEncoding::Brotli, kind, &config).unwrap()
+        };
+        let static_body = finalize(ContentKind::Static);
+        let dynamic_body = finalize(ContentKind::Dynamic);
+
+        assert_eq!(static_body, compress(&body, CompressionEncoding::Brotli, 11).unwrap());
+        assert_eq!(dynamic_body, compress(&body, CompressionEncoding::Brotli, 0).unwrap());
+        assert!(static_body.len() < dynamic_body.len());
+    }
+
     #[test]
     fn test_response_compressor() {
         let config = CompressionConfig {
@@ -0,0 +1,701 @@
+//! Connection limits and slow-header (slowloris) protection
+//!
+//! Pingora reads the first request header on a connection without any
+//! deadline, so a client trickling header bytes can hold a connection open
+//! indefinitely. `ConnectionGuard` wraps the proxy app: it caps the number of
+//! open connections, and wraps each stream so every request header must
+//! arrive within `header_read_timeout` and, optionally, no slower than
+//! `min_header_rate`. Once the blank line ending the header is seen the
+//! stream is a plain passthrough until a final response has been written
+//! and the client starts sending its next request on the kept-alive
+//! connection; that header is timed from its first byte.
+//!
+//! The shared `ConnectionTracker` also drives shutdown: connections get
+//! `grace_period` to finish, then until `drain_timeout`, after which the
+//! ones still open are closed.
+
+use crate::metrics::metrics;
+use async_trait::async_trait;
+use config::GlobalConfig;
+use pingora_core::apps::ServerApp;
+use pingora_core::protocols::tls::SslDigest;
+use pingora_core::protocols::{
+    GetProxyDigest, GetSocketDigest, GetTimingDigest, Peek, ProxyDigest, Shutdown, SocketDigest, Ssl, Stream,
+    TimingDigest, UniqueID, UniqueIDType, ALPN,
+};
+use pingora_core::server::ShutdownWatch;
+use std::future::Future;
+use std::io;
+use std::pin::Pin;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::task::{Context, Poll};
+use std::time::{Duration, Instant};
+use tokio::io::{AsyncRead, AsyncWrite, ReadBuf};
+use tokio::sync::watch;
+use tokio::time::Sleep;
+use tracing::{debug, info, warn};
+
+/// How long a header may trickle in before the minimum rate is enforced
+const RATE_GRACE: Duration = Duration::from_secs(1);
+
+/// Connection limits applied to every listener
+#[derive(Debug, Clone, Default)]
+pub struct ConnectionLimits {
+    /// Time allowed to receive a complete request header
+    pub header_read_timeout: Option<Duration>,
+    /// Minimum header data rate in bytes per second (0 = off)
+    pub min_header_rate: u64,
+    /// Maximum open client connections (0 = unlimited)
+    pub max_connections: usize,
+}
+
+impl ConnectionLimits {
+    pub fn from_config(global: &GlobalConfig) -> Self {
+        Self {
+            header_read_timeout: (global.header_read_timeout > 0)
+                .then(|| Duration::from_secs(global.header_read_timeout)),
+            min_header_rate: global.min_header_rate,
+            max_connections: global.max_connections,
+        }
+    }
+}
+
+/// Progress of the request header currently being received
+#[derive(Debug)]
+struct HeaderWatch {
+    armed: bool,
+    /// A final response went out, so the next bytes read start a new header
+    awaiting_next: bool,
+    started: Instant,
+    received: u64,
+    at_line_start: bool,
+    cr_at_line_start: bool,
+}
+
+impl HeaderWatch {
+    fn new() -> Self {
+        Self {
+            armed: true,
+            awaiting_next: false,
+            started: Instant::now(),
+            received: 0,
+            at_line_start: false,
+            cr_at_line_start: false,
+        }
+    }
+
+    /// Start timing the next request header on a reused connection
+    fn rearm(&mut self) {
+        *self = Self::new();
+    }
+
+    /// Note written data that starts a final (non-1xx) HTTP/1 response
+    ///
+    /// Interim responses such as `100 Continue` and `101 Switching Protocols`
+    /// are followed by more of the same request, or by another protocol.
+    fn observe_write(&mut self, data: &[u8]) {
+        if !self.armed && data.starts_with(b"HTTP/1.") && data.get(9).is_some_and(|status| *status != b'1') {
+            self.awaiting_next = true;
+        }
+    }
+
+    /// Scan received bytes for the blank line ending the header
+    fn observe(&mut self, data: &[u8]) {
+        self.received += data.len() as u64;
+        for &byte in data {
+            match byte {
+                b'\n' if self.at_line_start || self.cr_at_line_start => {
+                    self.armed = false;
+                    return;
+                }
+                b'\n' => {
+                    self.at_line_start = true;
+                    self.cr_at_line_start = false;
+                }
+                b'\r' if self.at_line_start => {
+                    self.at_line_start = false;
+                    self.cr_at_line_start = true;
+                }
+                _ => {
+                    self.at_line_start = false;
+                    self.cr_at_line_start = false;
+                }
+            }
+        }
+    }
+
+    /// Fail a header that is too slow overall or arriving too slowly
+    fn check(&self, timeout: Duration, min_rate: u64) -> io::Result<()> {
+        if !self.armed {
+            return Ok(());
+        }
+        let elapsed = self.started.elapsed();
+        if elapsed >= timeout {
+            return Err(io::Error::new(io::ErrorKind::TimedOut, "request header read timed out"));
+        }
+        if min_rate > 0 && elapsed > RATE_GRACE && (self.received as f64 / elapsed.as_secs_f64()) < min_rate as f64 {
+            return Err(io::Error::new(io::ErrorKind::TimedOut, "request header sent too slowly"));
+        }
+        Ok(())
+    }
+}
+
+/// Stream that fails reads while a request header arrives too slowly
+pub struct GuardedStream<S> {
+    inner: S,
+    watch: HeaderWatch,
+    timeout: Duration,
+    min_rate: u64,
+    timer: Option<Pin<Box<Sleep>>>,
+}
+
+impl<S> GuardedStream<S> {
+    pub fn new(inner: S, timeout: Duration, min_rate: u64) -> Self {
+        Self {
+            inner,
+            watch: HeaderWatch::new(),
+            timeout,
+            min_rate,
+            timer: None,
+        }
+    }
+}
+
+impl<S> std::fmt::Debug for GuardedStream<S> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("GuardedStream")
+            .field("timeout", &self.timeout)
+            .field("min_rate", &self.min_rate)
+            .finish()
+    }
+}
+
+impl<S: AsyncRead + Unpin> AsyncRead for GuardedStream<S> {
+    fn poll_read(self: Pin<&mut Self>, cx: &mut Context<'_>, buf: &mut ReadBuf<'_>) -> Poll<io::Result<()>> {
+        let this = self.get_mut();
+        let watch = &mut this.watch;
+        let before = buf.filled().len();
+        if !watch.armed {
+            let poll = Pin::new(&mut this.inner).poll_read(cx, buf);
+            let data = &buf.filled()[before..];
+            if watch.awaiting_next && !data.is_empty() {
+                // First bytes of the next request on a kept-alive connection
+                watch.rearm();
+                watch.observe(data);
+            }
+            return poll;
+        }
+
+        match Pin::new(&mut this.inner).poll_read(cx, buf) {
+            Poll::Ready(Ok(())) => {
+                watch.observe(&buf.filled()[before..]);
+                Poll::Ready(watch.check(this.timeout, this.min_rate))
+            }
+            Poll::Ready(Err(e)) => Poll::Ready(Err(e)),
+            Poll::Pending => {
+                // Nothing to read yet: wake up when the header deadline passes
+                let deadline = tokio::time::Instant::from_std(watch.started + this.timeout);
+                let timer = this.timer.get_or_insert_with(|| Box::pin(tokio::time::sleep_until(deadline)));
+                if timer.deadline() != deadline {
+                    timer.as_mut().reset(deadline);
+                }
+                match timer.as_mut().poll(cx) {
+                    Poll::Ready(()) => Poll::Ready(watch.check(this.timeout, this.min_rate)),
+                    Poll::Pending => Poll::Pending,
+                }
+            }
+        }
+    }
+}
+
+impl<S: AsyncWrite + Unpin> AsyncWrite for GuardedStream<S> {
+    fn poll_write(self: Pin<&mut Self>, cx: &mut Context<'_>, buf: &[u8]) -> Poll<io::Result<usize>> {
+        let this = self.get_mut();
+        this.watch.observe_write(buf);
+        Pin::new(&mut this.inner).poll_write(cx, buf)
+    }
+
+    fn poll_write_vectored(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        bufs: &[io::IoSlice<'_>],
+    ) -> Poll<io::Result<usize>> {
+        let this = self.get_mut();
+        if let Some(first) = bufs.iter().find(|b| !b.is_empty()) {
+            this.watch.observe_write(first);
+        }
+        Pin::new(&mut this.inner).poll_write_vectored(cx, bufs)
+    }
+
+    fn is_write_vectored(&self) -> bool {
+        self.inner.is_write_vectored()
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.get_mut().inner).poll_flush(cx)
+    }
+
+    fn poll_shutdown(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.get_mut().inner).poll_shutdown(cx)
+    }
+}
+
+#[async_trait]
+impl Shutdown for GuardedStream<Stream> {
+    async fn shutdown(&mut self) {
+        self.inner.shutdown().await
+    }
+}
+
+impl UniqueID for GuardedStream<Stream> {
+    fn id(&self) -> UniqueIDType {
+        self.inner.id()
+    }
+}
+
+impl Ssl for GuardedStream<Stream> {
+    fn get_ssl_digest(&self) -> Option<Arc<SslDigest>> {
+        self.inner.get_ssl_digest()
+    }
+
+    fn selected_alpn_proto(&self) -> Option<ALPN> {
+        self.inner.selected_alpn_proto()
+    }
+}
+
+impl GetTimingDigest for GuardedStream<Stream> {
+    fn get_timing_digest(&self) -> Vec<Option<TimingDigest>> {
+        self.inner.get_timing_digest()
+    }
+}
+
+impl GetProxyDigest for GuardedStream<Stream> {
+    fn get_proxy_digest(&self) -> Option<Arc<ProxyDigest>> {
+        self.inner.get_proxy_digest()
+    }
+
+    fn set_proxy_digest(&mut self, digest: ProxyDigest) {
+        self.inner.set_proxy_digest(digest)
+    }
+}
+
+impl GetSocketDigest for GuardedStream<Stream> {
+    fn get_socket_digest(&self) -> Option<Arc<SocketDigest>> {
+        self.inner.get_socket_digest()
+    }
+
+    fn set_socket_digest(&mut self, digest: SocketDigest) {
+        self.inner.set_socket_digest(digest)
+    }
+}
+
+#[async_trait]
+impl Peek for GuardedStream<Stream> {
+    async fn try_peek(&mut self, buf: &mut [u8]) -> io::Result<bool> {
+        self.inner.try_peek(buf).await
+    }
+}
+
+/// Client connections open across all listeners
+///
+/// Counts connections against `max_connections` and for shutdown draining,
+/// and can close the ones that outlive `drain_timeout`.
+#[derive(Debug)]
+pub struct ConnectionTracker {
+    open: AtomicUsize,
+    force_close: watch::Sender<bool>,
+}
+
+impl ConnectionTracker {
+    pub fn new() -> Self {
+        Self {
+            open: AtomicUsize::new(0),
+            force_close: watch::channel(false).0,
+        }
+    }
+
+    pub fn open_connections(&self) -> usize {
+        self.open.load(Ordering::Relaxed)
+    }
+
+    /// Wait for every connection to close, giving up after `timeout`
+    pub fn wait_for_drain(&self, timeout: Duration) -> bool {
+        let start = Instant::now();
+        while self.open_connections() > 0 {
+            if start.elapsed() >= timeout {
+                return false;
+            }
+            std::thread::sleep(Duration::from_millis(100).min(timeout));
+        }
+        true
+    }
+
+    /// Close all open connections and any accepted later; returns how many were open
+    pub fn force_close(&self) -> usize {
+        let open = self.open_connections();
+        self.force_close.send_replace(true);
+        open
+    }
+
+    /// Drain for shutdown; returns the number of connections closed forcibly
+    ///
+    /// `grace_period` is the soft target, `drain_timeout` the hard deadline.
+    pub fn drain(&self, grace_period: Duration, drain_timeout: Duration) -> usize {
+        info!(
+            open_connections = self.open_connections(),
+            grace_period_secs = grace_period.as_secs(),
+            "Waiting for connections to drain..."
+        );
+        if self.wait_for_drain(grace_period) {
+            info!("All connections drained successfully");
+            return 0;
+        }
+
+        warn!(
+            open_connections = self.open_connections(),
+            drain_timeout_secs = drain_timeout.as_secs(),
+            "Grace period expired, waiting until the drain timeout"
+        );
+        if self.wait_for_drain(drain_timeout.saturating_sub(grace_period)) {
+            info!("All connections drained successfully");
+            return 0;
+        }
+
+        let closed = self.force_close();
+        warn!(force_closed = closed, "Drain timeout expired, closing remaining connections");
+        closed
+    }
+
+    fn open(&self) -> OpenConnection<'_> {
+        self.open.fetch_add(1, Ordering::Relaxed);
+        metrics().active_connections.inc();
+        OpenConnection(self)
+    }
+}
+
+impl Default for ConnectionTracker {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// An open connection, counted until dropped
+struct OpenConnection<'a>(&'a ConnectionTracker);
+
+impl Drop for OpenConnection<'_> {
+    fn drop(&mut self) {
+        self.0.open.fetch_sub(1, Ordering::Relaxed);
+        metrics().active_connections.dec();
+    }
+}
+
+/// Server app enforcing `ConnectionLimits` in front of another app
+pub struct ConnectionGuard<A> {
+    app: Arc<A>,
+    limits: ConnectionLimits,
+    tracker: Arc<ConnectionTracker>,
+}
+
+impl<A> ConnectionGuard<A> {
+    /// Guard `app`; `tracker` is shared by every listener the limit covers
+    pub fn new(app: A, limits: ConnectionLimits, tracker: Arc<ConnectionTracker>) -> Self {
+        Self { app: Arc::new(app), limits, tracker }
+    }
+
+    fn try_open(&self) -> Option<OpenConnection<'_>> {
+        let connection = self.tracker.open();
+        let open = self.tracker.open_connections();
+        if self.limits.max_connections > 0 && open > self.limits.max_connections {
+            return None;
+        }
+        Some(connection)
+    }
+}
+
+#[async_trait]
+impl<A: ServerApp + Send + Sync + 'static> ServerApp for ConnectionGuard<A> {
+    async fn process_new(self: &Arc<Self>, stream: Stream, shutdown: &ShutdownWatch) -> Option<Stream> {
+        let Some(_connection) = self.try_open() else {
+            warn!(max_connections = self.limits.max_connections, "Connection limit reached, closing connection");
+            return None;
+        };
+
+        // Dropping the connection's future closes its socket
+        let mut force_close = self.tracker.force_close.subscribe();
+        tokio::select! {
+            _ = self.serve(stream, shutdown) => {}
+            _ = force_close.wait_for(|closed| *closed) => debug!("Connection closed forcibly"),
+        }
+        None
+    }
+
+    async fn cleanup(&self) {
+        self.app.cleanup().await
+    }
+}
+
+impl<A: ServerApp + Send + Sync + 'static> ConnectionGuard<A> {
+    async fn serve(self: &Arc<Self>, stream: Stream, shutdown: &ShutdownWatch) {
+        // HTTP apps keep connections alive internally; the stream itself
+        // times each request header it reads
+        let stream: Stream = match self.limits.header_read_timeout {
+            Some(timeout) => Box::new(GuardedStream::new(stream, timeout, self.limits.min_header_rate)),
+            None => stream,
+        };
+        let mut reused = self.app.process_new(stream, shutdown).await;
+        while let Some(stream) = reused {
+            reused = self.app.process_new(stream, shutdown).await;
+        }
+        debug!("Connection closed");
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pingora_core::apps::{HttpServerApp, ReusedHttpStream};
+    use pingora_core::protocols::http::ServerSession;
+    use pingora_http::ResponseHeader;
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+    use tokio::net::{TcpListener, TcpStream};
+
+    /// Read a request header the way an HTTP server would
+    async fn read_header(stream: &mut GuardedStream<TcpStream>) -> io::Result<Vec<u8>> {
+        let mut header = Vec::new();
+        let mut buf = [0u8; 64];
+        while !header.windows(4).any(|w| w == b"\r\n\r\n") {
+            let n = stream.read(&mut buf).await?;
+            if n == 0 {
+                return Err(io::ErrorKind::UnexpectedEof.into());
+            }
+            header.extend_from_slice(&buf[..n]);
+        }
+        Ok(header)
+    }
+
+    async fn connect(timeout: Duration, min_rate: u64) -> (TcpStream, GuardedStream<TcpStream>) {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let client = TcpStream::connect(listener.local_addr().unwrap()).await.unwrap();
+        let (server, _) = listener.accept().await.unwrap();
+        (client, GuardedStream::new(server, timeout, min_rate))
+    }
+
+    #[tokio::test]
+    async fn test_slow_header_closed_within_timeout() {
+        let (mut client, mut server) = connect(Duration::from_millis(500), 0).await;
+
+        // Trickle the header one byte every 50ms, never finishing in time
+        let writer = tokio::spawn(async move {
+            for byte in b"GET / HTTP/1.1\r\nHost: example.com\r\nX-Slow: yes\r\n\r\n" {
+                if client.write_all(&[*byte]).await.is_err() {
+                    break;
+                }
+                tokio::time::sleep(Duration::from_millis(50)).await;
+            }
+        });
+
+        let started = Instant::now();
+        let err = read_header(&mut server).await.unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::TimedOut);
+        assert!(started.elapsed() < Duration::from_millis(1500), "{:?}", started.elapsed());
+        writer.abort();
+    }
+
+    #[tokio::test]
+    async fn test_idle_connection_closed_within_timeout() {
+        let (_client, mut server) = connect(Duration::from_millis(200), 0).await;
+        let started = Instant::now();
+        let err = read_header(&mut server).await.unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::TimedOut);
+        assert!(started.elapsed() < Duration::from_secs(1));
+    }
+
+    #[tokio::test]
+    async fn test_header_below_min_rate_closed() {
+        let (mut client, mut server) = connect(Duration::from_secs(30), 1000).await;
+        let writer = tokio::spawn(async move {
+            loop {
+                if client.write_all(b"X").await.is_err() {
+                    break;
+                }
+                tokio::time::sleep(Duration::from_millis(100)).await;
+            }
+        });
+
+        let started = Instant::now();
+        let err = read_header(&mut server).await.unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::TimedOut);
+        assert!(started.elapsed() < Duration::from_secs(3));
+        writer.abort();
+    }
+
+    #[tokio::test]
+    async fn test_prompt_header_and_body_pass_through() {
+        let (mut client, mut server) = connect(Duration::from_millis(300), 0).await;
+        client
+            .write_all(b"POST / HTTP/1.1\r\nHost: example.com\r\nContent-Length: 4\r\n\r\n")
+            .await
+            .unwrap();
+        let header = read_header(&mut server).await.unwrap();
+        assert!(header.starts_with(b"POST / HTTP/1.1"));
+
+        // Once the header is complete the body may take its time
+        tokio::time::sleep(Duration::from_millis(500)).await;
+        client.write_all(b"body").await.unwrap();
+        let mut body = [0u8; 4];
+        server.read_exact(&mut body).await.unwrap();
+        assert_eq!(&body, b"body");
+
+        // After the response, the next header gets a fresh deadline from its first byte
+        server.write_all(b"HTTP/1.1 200 OK\r\nContent-Length: 0\r\n\r\n").await.unwrap();
+        tokio::time::sleep(Duration::from_millis(500)).await;
+        client.write_all(b"GET / HTTP/1.1\r\nHost: example.com\r\n\r\n").await.unwrap();
+        assert!(read_header(&mut server).await.is_ok());
+    }
+
+    /// HTTP app answering every request with "ok", keeping the connection alive
+    struct Ok200;
+
+    #[async_trait]
+    impl HttpServerApp for Ok200 {
+        async fn process_new_http(
+            self: &Arc<Self>,
+            mut session: ServerSession,
+            _shutdown: &ShutdownWatch,
+        ) -> Option<ReusedHttpStream> {
+            if !session.read_request().await.ok()? {
+                return None;
+            }
+            let mut response = ResponseHeader::build(200, None).unwrap();
+            response.insert_header("Content-Length", "2").unwrap();
+            session.write_response_header(Box::new(response)).await.ok()?;
+            session.write_response_body(bytes::Bytes::from_static(b"ok"), true).await.ok()?;
+            let stream = session.finish().await.ok()??;
+            Some(ReusedHttpStream::new(stream, None))
+        }
+    }
+
+    #[tokio::test]
+    async fn test_stalled_keepalive_request_closed() {
+        let limits = ConnectionLimits { header_read_timeout: Some(Duration::from_millis(300)), ..Default::default() };
+        let guard = Arc::new(ConnectionGuard::new(Ok200, limits, Arc::new(ConnectionTracker::new())));
+
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let mut client = TcpStream::connect(listener.local_addr().unwrap()).await.unwrap();
+        let (server, _) = listener.accept().await.unwrap();
+        let connection = tokio::spawn(async move {
+            let (_shutdown_tx, shutdown) = watch::channel(false);
+            let stream: Stream = Box::new(pingora_core::protocols::l4::stream::Stream::from(server));
+            guard.process_new(stream, &shutdown).await
+        });
+
+        let mut response = [0u8; 1024];
+        client.write_all(b"GET / HTTP/1.1\r\nHost: example.com\r\n\r\n").await.unwrap();
+        let n = client.read(&mut response).await.unwrap();
+        assert!(response[..n].starts_with(b"HTTP/1.1 200"));
+
+        // Idling between requests is up to the keep-alive timeout, not the header timeout
+        tokio::time::sleep(Duration::from_millis(500)).await;
+        client.write_all(b"GET / HTTP/1.1\r\nHost: example.com\r\n\r\n").await.unwrap();
+        let n = client.read(&mut response).await.unwrap();
+        assert!(response[..n].starts_with(b"HTTP/1.1 200"));
+
+        // The third request stalls halfway through its header
+        client.write_all(b"GET / HTTP/1.1\r\nHost: exa").await.unwrap();
+        let started = Instant::now();
+        let read = tokio::time::timeout(Duration::from_secs(2), client.read(&mut response)).await.unwrap();
+        assert!(matches!(read, Ok(0) | Err(_)), "{:?}", read);
+        assert!(started.elapsed() < Duration::from_millis(1500), "{:?}", started.elapsed());
+        assert!(tokio::time::timeout(Duration::from_secs(1), connection).await.is_ok());
+    }
+
+    #[test]
+    fn test_header_end_detection() {
+        let mut watch = HeaderWatch::new();
+        watch.observe(b"GET / HTTP/1.1\r\nHost: a\r");
+        assert!(watch.armed);
+        watch.observe(b"\n\r");
+        assert!(watch.armed);
+        watch.observe(b"\n");
+        assert!(!watch.armed);
+
+        let mut bare_lf = HeaderWatch::new();
+        bare_lf.observe(b"GET / HTTP/1.1\nHost: a\n\n");
+        assert!(!bare_lf.armed);
+    }
+
+    #[test]
+    fn test_connection_limit() {
+        let guard = ConnectionGuard::new(
+            (),
+            ConnectionLimits { max_connections: 2, ..Default::default() },
+            Arc::new(ConnectionTracker::new()),
+        );
+        let first = guard.try_open();
+        let second = guard.try_open();
+        assert!(first.is_some() && second.is_some());
+        assert!(guard.try_open().is_none());
+        drop(first);
+        assert!(guard.try_open().is_some());
+        assert_eq!(guard.tracker.open_connections(), 1);
+    }
+
+    /// App that keeps a connection open until the client goes away
+    struct HoldOpen;
+
+    #[async_trait]
+    impl ServerApp for HoldOpen {
+        async fn process_new(self: &Arc<Self>, mut stream: Stream, _shutdown: &ShutdownWatch) -> Option<Stream> {
+            let mut buf = [0u8; 1024];
+            while matches!(stream.read(&mut buf).await, Ok(n) if n > 0) {}
+            None
+        }
+    }
+
+    #[tokio::test]
+    async fn test_connections_past_drain_timeout_force_closed() {
+        let tracker = Arc::new(ConnectionTracker::new());
+        let guard = Arc::new(ConnectionGuard::new(HoldOpen, ConnectionLimits::default(), tracker.clone()));
+
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let mut client = TcpStream::connect(listener.local_addr().unwrap()).await.unwrap();
+        let (server, _) = listener.accept().await.unwrap();
+        let connection = tokio::spawn(async move {
+            let (_shutdown_tx, shutdown) = watch::channel(false);
+            let stream: Stream = Box::new(pingora_core::protocols::l4::stream::Stream::from(server));
+            guard.process_new(stream, &shutdown).await
+        });
+        while tracker.open_connections() == 0 {
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+
+        // The idle client outlives both the grace period and the drain timeout
+        let drainer = tracker.clone();
+        let started = Instant::now();
+        let closed = tokio::task::spawn_blocking(move || {
+            drainer.drain(Duration::from_millis(100), Duration::from_millis(300))
+        })
+        .await
+        .unwrap();
+        assert_eq!(closed, 1);
+        assert!(started.elapsed() >= Duration::from_millis(300));
+
+        assert!(tokio::time::timeout(Duration::from_secs(2), connection).await.is_ok());
+        assert_eq!(tracker.open_connections(), 0);
+        let mut buf = [0u8; 16];
+        let read = tokio::time::timeout(Duration::from_secs(2), client.read(&mut buf)).await.unwrap();
+        assert!(matches!(read, Ok(0) | Err(_)));
+    }
+
+    #[test]
+    fn test_drained_connections_not_force_closed() {
+        let tracker = ConnectionTracker::new();
+        assert_eq!(tracker.drain(Duration::from_millis(50), Duration::from_millis(100)), 0);
+
+        let connection = tracker.open();
+        assert!(!tracker.wait_for_drain(Duration::from_millis(50)));
+        drop(connection);
+        assert!(tracker.wait_for_drain(Duration::ZERO));
+    }
+}
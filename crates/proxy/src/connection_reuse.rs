@@ -0,0 +1,88 @@
+//! Limits on upstream connection reuse
+//!
+//! Some backends degrade when one keep-alive connection serves requests for
+//! a long time (per-connection state, leaks), and a single busy connection
+//! can end up carrying most of a backend's traffic. With
+//! `timeouts.max_requests_per_connection` an HTTP/1.1 upstream connection is
+//! retired after that many requests: the last one is sent with
+//! `Connection: close`, so the upstream closes the connection instead of it
+//! going back to the pool. Connections that ALPN settles on HTTP/1.1 count
+//! too, even when HTTP/2 was offered. `timeouts.h2_multiplexing = false`
+//! limits HTTP/2 upstream connections to one stream at a time.
+
+use dashmap::DashMap;
+use pingora_core::upstreams::peer::HttpPeer;
+use std::os::unix::io::RawFd;
+
+/// Requests sent over each open upstream connection, keyed by socket
+#[derive(Debug, Default)]
+pub struct ConnectionRequests {
+    counts: DashMap<RawFd, u32>,
+}
+
+impl ConnectionRequests {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Count a request sent over the connection `fd`, returning whether it
+    /// has to be the connection's last
+    ///
+    /// A connection that isn't `reused` is new, even if its descriptor
+    /// belonged to a connection closed earlier.
+    pub fn record(&self, fd: RawFd, reused: bool, limit: u32) -> bool {
+        if limit == 0 {
+            return false;
+        }
+        let last = {
+            let mut count = self.counts.entry(fd).or_insert(0);
+            if !reused {
+                *count = 0;
+            }
+            *count += 1;
+            *count >= limit
+        };
+        if last {
+            self.counts.remove(&fd);
+        }
+        last
+    }
+}
+
+/// Limit HTTP/2 connections to `peer` to one stream unless `h2_multiplexing` is on
+pub fn apply_h2_multiplexing(peer: &mut HttpPeer, h2_multiplexing: bool) {
+    if !h2_multiplexing {
+        peer.options.max_h2_streams = 1;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_connection_retired_after_limit() {
+        let requests = ConnectionRequests::new();
+        assert!(!requests.record(7, false, 3));
+        assert!(!requests.record(7, true, 3));
+        assert!(requests.record(7, true, 3));
+
+        // Other connections are counted separately
+        assert!(!requests.record(8, false, 3));
+
+        // A new connection on a recycled descriptor starts over
+        assert!(!requests.record(8, false, 3));
+        assert!(!requests.record(8, true, 3));
+        assert!(requests.record(8, true, 3));
+    }
+
+    #[test]
+    fn test_unlimited_connection_not_tracked() {
+        let requests = ConnectionRequests::new();
+        for _ in 0..100 {
+            assert!(!requests.record(7, true, 0));
+        }
+        assert!(requests.counts.is_empty());
+        assert!(requests.record(7, false, 1));
+    }
+}
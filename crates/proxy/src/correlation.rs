@@ -0,0 +1,146 @@
+//! Correlation headers forwarded to upstreams
+//!
+//! Each header listed in `global.correlation_headers` is kept as the client
+//! (or the hop in front of us) sent it and forwarded upstream unchanged. A
+//! missing one is generated, so every upstream request carries the full set
+//! and the same ids show up in every hop's logs. `traceparent` is generated
+//! as a new W3C trace context, and replaced when the client's is malformed;
+//! other headers get a random UUID.
+
+use crate::otlp_log::parse_traceparent;
+use pingora_http::RequestHeader;
+
+const TRACEPARENT: &str = "traceparent";
+
+/// Correlation header values of one request
+#[derive(Debug, Clone, Default)]
+pub struct CorrelationIds {
+    /// Header name and value, in config order
+    headers: Vec<(String, String)>,
+}
+
+impl CorrelationIds {
+    /// Values of the `names` headers in `request`, generating missing ones
+    pub fn from_request(names: &[String], request: &RequestHeader) -> Self {
+        let headers = names
+            .iter()
+            .map(|name| {
+                let sent = request
+                    .headers
+                    .get(name.as_str())
+                    .and_then(|v| v.to_str().ok())
+                    .map(str::trim)
+                    .filter(|v| !v.is_empty())
+                    .filter(|v| !name.eq_ignore_ascii_case(TRACEPARENT) || parse_traceparent(v).is_some());
+                let value = match sent {
+                    Some(value) => value.to_string(),
+                    None => generate(name),
+                };
+                (name.clone(), value)
+            })
+            .collect();
+        Self { headers }
+    }
+
+    /// Value sent upstream for a header
+    pub fn get(&self, name: &str) -> Option<&str> {
+        self.headers
+            .iter()
+            .find(|(n, _)| n.eq_ignore_ascii_case(name))
+            .map(|(_, v)| v.as_str())
+    }
+
+    /// Set every correlation header on the upstream request
+    pub fn add_to_request(&self, request: &mut RequestHeader) -> pingora_error::Result<()> {
+        for (name, value) in &self.headers {
+            request.insert_header(name.clone(), value.as_str())?;
+        }
+        Ok(())
+    }
+}
+
+fn generate(name: &str) -> String {
+    if name.eq_ignore_ascii_case(TRACEPARENT) {
+        let ids = random_bytes::<24>();
+        // A fresh sampled trace rooted at this proxy
+        format!("00-{}-{}-01", hex(&ids[..16]), hex(&ids[16..]))
+    } else {
+        uuid_v4()
+    }
+}
+
+/// Random UUID in its canonical 8-4-4-4-12 form
+fn uuid_v4() -> String {
+    let mut bytes = random_bytes::<16>();
+    bytes[6] = (bytes[6] & 0x0f) | 0x40;
+    bytes[8] = (bytes[8] & 0x3f) | 0x80;
+    let hex = hex(&bytes);
+    format!("{}-{}-{}-{}-{}", &hex[..8], &hex[8..12], &hex[12..16], &hex[16..20], &hex[20..])
+}
+
+fn random_bytes<const N: usize>() -> [u8; N] {
+    let mut bytes = [0u8; N];
+    getrandom::getrandom(&mut bytes).expect("OS random number generator unavailable");
+    bytes
+}
+
+fn hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn names() -> Vec<String> {
+        ["X-Request-Id", "X-Correlation-Id", "traceparent"].iter().map(|s| s.to_string()).collect()
+    }
+
+    fn forwarded(ids: &CorrelationIds, name: &str) -> String {
+        let mut upstream = RequestHeader::build("GET", b"/", None).unwrap();
+        ids.add_to_request(&mut upstream).unwrap();
+        upstream.headers.get(name).unwrap().to_str().unwrap().to_string()
+    }
+
+    #[test]
+    fn test_client_ids_forwarded_unchanged() {
+        let traceparent = "00-4bf92f3577b34da6a3ce929d0e0e4736-00f067aa0ba902b7-01";
+        let mut request = RequestHeader::build("GET", b"/", None).unwrap();
+        request.insert_header("x-request-id", "req-123").unwrap();
+        request.insert_header("X-Correlation-Id", "order-42").unwrap();
+        request.insert_header("traceparent", traceparent).unwrap();
+
+        let ids = CorrelationIds::from_request(&names(), &request);
+        assert_eq!(forwarded(&ids, "x-request-id"), "req-123");
+        assert_eq!(forwarded(&ids, "x-correlation-id"), "order-42");
+        assert_eq!(forwarded(&ids, "traceparent"), traceparent);
+        assert_eq!(ids.get("X-REQUEST-ID"), Some("req-123"));
+    }
+
+    #[test]
+    fn test_missing_ids_generated_and_propagated() {
+        let request = RequestHeader::build("GET", b"/", None).unwrap();
+        let ids = CorrelationIds::from_request(&names(), &request);
+
+        let request_id = forwarded(&ids, "x-request-id");
+        assert_eq!(request_id.len(), 36);
+        assert_eq!(&request_id[14..15], "4");
+        assert_ne!(request_id, forwarded(&ids, "x-correlation-id"));
+        assert!(parse_traceparent(&forwarded(&ids, "traceparent")).is_some());
+
+        // Each request gets its own ids
+        let other = CorrelationIds::from_request(&names(), &request);
+        assert_ne!(other.get("X-Request-Id"), ids.get("X-Request-Id"));
+    }
+
+    #[test]
+    fn test_malformed_traceparent_replaced() {
+        let mut request = RequestHeader::build("GET", b"/", None).unwrap();
+        request.insert_header("traceparent", "not-a-trace").unwrap();
+        request.insert_header("X-Request-Id", "").unwrap();
+
+        let ids = CorrelationIds::from_request(&names(), &request);
+        assert!(parse_traceparent(ids.get("traceparent").unwrap()).is_some());
+        assert_eq!(ids.get("X-Request-Id").unwrap().len(), 36);
+    }
+}
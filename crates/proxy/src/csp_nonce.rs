@@ -0,0 +1,108 @@
+//! Per-request CSP nonces for inline scripts
+//!
+//! A fresh nonce is generated for every request when
+//! `security_headers.csp_nonce` is set. The upstream receives it in a request
+//! header to stamp into `<script nonce="...">`, and the client receives the
+//! Content-Security-Policy with `{nonce}` replaced by the same value.
+
+use base64::Engine;
+use config::CspNonceConfig;
+use pingora_http::{RequestHeader, ResponseHeader};
+
+/// Placeholder in `content_security_policy` replaced with the nonce
+pub const NONCE_PLACEHOLDER: &str = "{nonce}";
+
+/// Nonce for one request and the headers it travels in
+#[derive(Debug, Clone)]
+pub struct CspNonce {
+    value: String,
+    request_header: String,
+    response_header: String,
+}
+
+impl CspNonce {
+    /// 128 random bits, base64 encoded as CSP expects
+    pub fn generate(config: &CspNonceConfig) -> Self {
+        let mut bytes = [0u8; 16];
+        getrandom::getrandom(&mut bytes).expect("OS random number generator unavailable");
+        Self {
+            value: base64::engine::general_purpose::STANDARD.encode(bytes),
+            request_header: config.request_header.clone(),
+            response_header: config.response_header.clone(),
+        }
+    }
+
+    pub fn value(&self) -> &str {
+        &self.value
+    }
+
+    /// Pass the nonce to the upstream, replacing any value sent by the client
+    pub fn add_to_request(&self, request: &mut RequestHeader) -> pingora_error::Result<()> {
+        request.insert_header(self.request_header.clone(), self.value.as_str())
+    }
+
+    /// Send `policy` with its placeholder replaced by the nonce
+    pub fn add_to_response(&self, response: &mut ResponseHeader, policy: &str) -> pingora_error::Result<()> {
+        response.insert_header(self.response_header.clone(), policy.replace(NONCE_PLACEHOLDER, &self.value))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const POLICY: &str = "default-src 'self'; script-src 'self' 'nonce-{nonce}'";
+
+    fn config() -> CspNonceConfig {
+        CspNonceConfig {
+            request_header: "X-CSP-Nonce".to_string(),
+            response_header: "Content-Security-Policy".to_string(),
+        }
+    }
+
+    fn header<'a>(headers: &'a http::HeaderMap, name: &str) -> &'a str {
+        headers.get(name).unwrap().to_str().unwrap()
+    }
+
+    #[test]
+    fn test_same_nonce_in_request_and_policy() {
+        let nonce = CspNonce::generate(&config());
+
+        let mut request = RequestHeader::build("GET", b"/", None).unwrap();
+        request.insert_header("X-CSP-Nonce", "client-chosen").unwrap();
+        nonce.add_to_request(&mut request).unwrap();
+        assert_eq!(header(&request.headers, "x-csp-nonce"), nonce.value());
+        assert_eq!(request.headers.get_all("x-csp-nonce").iter().count(), 1);
+
+        let mut response = ResponseHeader::build(200, None).unwrap();
+        nonce.add_to_response(&mut response, POLICY).unwrap();
+        assert_eq!(
+            header(&response.headers, "content-security-policy"),
+            format!("default-src 'self'; script-src 'self' 'nonce-{}'", nonce.value())
+        );
+    }
+
+    #[test]
+    fn test_each_request_gets_unique_nonce() {
+        let nonces: std::collections::HashSet<String> =
+            (0..1000).map(|_| CspNonce::generate(&config()).value().to_string()).collect();
+        assert_eq!(nonces.len(), 1000);
+
+        let nonce = CspNonce::generate(&config());
+        let decoded = base64::engine::general_purpose::STANDARD.decode(nonce.value()).unwrap();
+        assert_eq!(decoded.len(), 16);
+    }
+
+    #[test]
+    fn test_report_only_header() {
+        let config = CspNonceConfig {
+            response_header: "Content-Security-Policy-Report-Only".to_string(),
+            ..config()
+        };
+        let nonce = CspNonce::generate(&config);
+        let mut response = ResponseHeader::build(200, None).unwrap();
+        nonce.add_to_response(&mut response, POLICY).unwrap();
+        assert!(response.headers.get("content-security-policy").is_none());
+        assert!(header(&response.headers, "content-security-policy-report-only").contains(nonce.value()));
+    }
+}
@@ -0,0 +1,156 @@
+//! Overall per-request deadline
+//!
+//! Upstream timeouts bound single connects and reads. `request_timeout`
+//! bounds the whole request from `request_start`, whichever stage is slow:
+//! awaited phases run under the remaining time, upstream timeouts are capped
+//! to it, and the stage that ran out is reported with the 504.
+
+use std::future::Future;
+use std::time::{Duration, Instant};
+
+/// Phase of a request that can exceed the deadline
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RequestStage {
+    /// Routing and checks in `request_filter`
+    RequestFilter,
+    /// Forward auth subrequest
+    Auth,
+    /// Connecting to and waiting on the upstream
+    Upstream,
+    /// Receiving the request body
+    RequestBody,
+    /// Sending the response body
+    ResponseBody,
+}
+
+impl RequestStage {
+    /// Label used in logs and metrics
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            RequestStage::RequestFilter => "request_filter",
+            RequestStage::Auth => "auth",
+            RequestStage::Upstream => "upstream",
+            RequestStage::RequestBody => "request_body",
+            RequestStage::ResponseBody => "response_body",
+        }
+    }
+}
+
+/// The deadline passed during a stage
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct DeadlineExceeded {
+    pub stage: RequestStage,
+}
+
+impl DeadlineExceeded {
+    /// 504 error for the proxy to answer with
+    pub fn into_error(self) -> Box<pingora_core::Error> {
+        pingora_core::Error::explain(
+            pingora_core::ErrorType::HTTPStatus(504),
+            format!("request timeout exceeded in {} stage", self.stage.as_str()),
+        )
+    }
+}
+
+/// Deadline for one request; unbounded when `request_timeout` is 0
+#[derive(Debug, Clone, Copy, Default)]
+pub struct RequestDeadline {
+    at: Option<Instant>,
+}
+
+impl RequestDeadline {
+    pub fn new(start: Instant, timeout: Duration) -> Self {
+        Self { at: (!timeout.is_zero()).then(|| start + timeout) }
+    }
+
+    /// Time left, or `None` when the request is unbounded
+    pub fn remaining(&self) -> Option<Duration> {
+        self.at.map(|at| at.saturating_duration_since(Instant::now()))
+    }
+
+    pub fn is_expired(&self) -> bool {
+        self.remaining().is_some_and(|left| left.is_zero())
+    }
+
+    /// Fail if the deadline has already passed
+    pub fn check(&self, stage: RequestStage) -> Result<(), DeadlineExceeded> {
+        if self.is_expired() {
+            return Err(DeadlineExceeded { stage });
+        }
+        Ok(())
+    }
+
+    /// Run a stage within the time left
+    pub async fn run<F: Future>(&self, stage: RequestStage, stage_future: F) -> Result<F::Output, DeadlineExceeded> {
+        match self.remaining() {
+            Some(left) => tokio::time::timeout(left, stage_future)
+                .await
+                .map_err(|_| DeadlineExceeded { stage }),
+            None => Ok(stage_future.await),
+        }
+    }
+
+    /// Shorten an upstream timeout so it ends by the deadline
+    pub fn cap(&self, timeout: Option<Duration>) -> Option<Duration> {
+        match (timeout, self.remaining()) {
+            (Some(timeout), Some(left)) => Some(timeout.min(left)),
+            (timeout, left) => timeout.or(left),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn deadline(ms: u64) -> RequestDeadline {
+        RequestDeadline::new(Instant::now(), Duration::from_millis(ms))
+    }
+
+    #[tokio::test]
+    async fn test_stalled_upstream_times_out() {
+        let deadline = deadline(50);
+        let err = deadline
+            .run(RequestStage::Upstream, std::future::pending::<()>())
+            .await
+            .unwrap_err();
+        assert_eq!(err.stage, RequestStage::Upstream);
+        assert!(matches!(err.into_error().etype(), pingora_core::ErrorType::HTTPStatus(504)));
+        assert!(deadline.is_expired());
+    }
+
+    #[tokio::test]
+    async fn test_stalled_auth_times_out() {
+        let deadline = deadline(50);
+        let err = deadline
+            .run(RequestStage::Auth, tokio::time::sleep(Duration::from_secs(10)))
+            .await
+            .unwrap_err();
+        assert_eq!(err.stage.as_str(), "auth");
+        assert_eq!(deadline.check(RequestStage::ResponseBody).unwrap_err().stage, RequestStage::ResponseBody);
+    }
+
+    #[tokio::test]
+    async fn test_fast_stage_completes() {
+        let deadline = deadline(5_000);
+        assert_eq!(deadline.run(RequestStage::Auth, async { 7 }).await, Ok(7));
+        assert!(deadline.check(RequestStage::RequestFilter).is_ok());
+    }
+
+    #[test]
+    fn test_zero_timeout_is_unbounded() {
+        let deadline = RequestDeadline::new(Instant::now(), Duration::ZERO);
+        assert_eq!(deadline.remaining(), None);
+        assert_eq!(deadline.cap(Some(Duration::from_secs(30))), Some(Duration::from_secs(30)));
+        assert_eq!(deadline.cap(None), None);
+    }
+
+    #[test]
+    fn test_upstream_timeouts_capped() {
+        let deadline = deadline(1_000);
+        let capped = deadline.cap(Some(Duration::from_secs(30))).unwrap();
+        assert!(capped <= Duration::from_secs(1));
+        assert!(deadline.cap(None).unwrap() <= Duration::from_secs(1));
+        assert_eq!(deadline.cap(Some(Duration::from_millis(10))), Some(Duration::from_millis(10)));
+    }
+}
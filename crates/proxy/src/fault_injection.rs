@@ -0,0 +1,130 @@
+//! Synthetic latency and fault injection for chaos testing
+//!
+//! Routes can delay or abort a fraction of requests so teams can verify
+//! client retry and timeout behavior. Faults are only applied when the
+//! global switch is on or the client connects from a trusted IP.
+
+use crate::ip_filter::{CompiledIpFilter, IpFilterConfig};
+use crate::upstream::rand_usize;
+use config::FaultInjectionConfig;
+use std::net::IpAddr;
+use std::time::Duration;
+
+/// A fault selected for a single request
+#[derive(Debug, Clone, PartialEq)]
+pub struct InjectedFault {
+    /// Delay before the request is handled
+    pub delay: Duration,
+    /// Status to respond with instead of handling the request
+    pub abort_status: Option<u16>,
+}
+
+/// Compiled fault injection settings for a route
+pub struct FaultInjector {
+    delay: Duration,
+    abort_status: Option<u16>,
+    probability: f64,
+    trusted: CompiledIpFilter,
+}
+
+impl FaultInjector {
+    pub fn from_config(config: &FaultInjectionConfig) -> Self {
+        let trusted = CompiledIpFilter::from_config(&IpFilterConfig {
+            allow: config.trusted_ips.clone(),
+            deny: Vec::new(),
+        });
+
+        Self {
+            delay: Duration::from_millis(config.delay_ms),
+            abort_status: config.abort_status,
+            probability: config.probability,
+            trusted,
+        }
+    }
+
+    /// Check whether faults may be applied to a client
+    ///
+    /// `client_ip` should be the connection's peer address, not a
+    /// forwarded header, so clients can't opt themselves in.
+    pub fn permitted(&self, globally_enabled: bool, client_ip: Option<IpAddr>) -> bool {
+        if globally_enabled {
+            return true;
+        }
+
+        match client_ip {
+            Some(ip) => self.trusted.is_active() && self.trusted.is_allowed(&ip),
+            None => false,
+        }
+    }
+
+    /// Decide whether this request gets a fault, and which
+    pub fn sample(&self) -> Option<InjectedFault> {
+        if self.delay.is_zero() && self.abort_status.is_none() {
+            return None;
+        }
+
+        let hit = if self.probability >= 1.0 {
+            true
+        } else if self.probability <= 0.0 {
+            false
+        } else {
+            (rand_usize() % 1_000_000) as f64 / 1_000_000.0 < self.probability
+        };
+
+        hit.then(|| InjectedFault {
+            delay: self.delay,
+            abort_status: self.abort_status,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn make_injector(probability: f64, trusted_ips: Vec<String>) -> FaultInjector {
+        FaultInjector::from_config(&FaultInjectionConfig {
+            delay_ms: 250,
+            abort_status: Some(503),
+            probability,
+            trusted_ips,
+        })
+    }
+
+    #[test]
+    fn test_probability_one_always_applies() {
+        let injector = make_injector(1.0, Vec::new());
+        for _ in 0..100 {
+            assert_eq!(
+                injector.sample(),
+                Some(InjectedFault {
+                    delay: Duration::from_millis(250),
+                    abort_status: Some(503),
+                })
+            );
+        }
+    }
+
+    #[test]
+    fn test_probability_zero_never_applies() {
+        let injector = make_injector(0.0, Vec::new());
+        for _ in 0..100 {
+            assert_eq!(injector.sample(), None);
+        }
+    }
+
+    #[test]
+    fn test_gated_by_global_flag_or_trusted_ip() {
+        let trusted: IpAddr = "10.0.0.5".parse().unwrap();
+        let other: IpAddr = "192.168.1.1".parse().unwrap();
+
+        let open = make_injector(1.0, Vec::new());
+        assert!(open.permitted(true, Some(other)));
+        assert!(!open.permitted(false, Some(other)));
+
+        let restricted = make_injector(1.0, vec!["10.0.0.0/24".to_string()]);
+        assert!(restricted.permitted(false, Some(trusted)));
+        assert!(!restricted.permitted(false, Some(other)));
+        assert!(!restricted.permitted(false, None));
+    }
+}
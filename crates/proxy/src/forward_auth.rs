@@ -0,0 +1,274 @@
+//! Forward authentication
+//!
+//! Before a request is proxied, its headers are sent to an external auth
+//! service. A 2xx answer lets the request through (optionally copying some of
+//! the service's response headers upstream); any other answer is returned to
+//! the client as-is. When the service can't be reached within `timeout` after
+//! `retries` extra attempts, `fail_open` decides between letting the request
+//! through and answering 503.
+
+use bytes::Bytes;
+use config::ForwardAuthConfig;
+use http::HeaderMap;
+use std::time::Duration;
+use tracing::{debug, warn};
+
+/// Request headers never forwarded to the auth service
+const SKIPPED_HEADERS: &[&str] = &[
+    "host", "connection", "keep-alive", "transfer-encoding", "te", "trailer",
+    "upgrade", "content-length",
+];
+
+/// Auth service response headers passed to the client on denial
+const DENIAL_HEADERS: &[&str] = &["content-type", "location", "set-cookie", "www-authenticate"];
+
+/// Outcome of a forward auth check
+#[derive(Debug)]
+pub enum ForwardAuthOutcome {
+    /// Request may proceed; headers to add to the upstream request
+    Allowed(Vec<(String, String)>),
+    /// The auth service refused the request with this response
+    Denied {
+        status: u16,
+        headers: Vec<(String, String)>,
+        body: Bytes,
+    },
+    /// The auth service couldn't be reached and the policy is fail-closed
+    Unavailable,
+}
+
+/// Original request details sent to the auth service
+pub struct ForwardedRequest<'a> {
+    pub method: &'a str,
+    pub scheme: &'a str,
+    pub host: Option<&'a str>,
+    pub uri: &'a str,
+    pub client_ip: Option<&'a str>,
+    pub headers: &'a HeaderMap,
+}
+
+/// Compiled forward auth settings for a route
+pub struct ForwardAuth {
+    url: String,
+    copy_headers: Vec<String>,
+    timeout: Duration,
+    retries: u32,
+    fail_open: bool,
+    exclude_paths: Vec<String>,
+    client: reqwest::Client,
+}
+
+impl ForwardAuth {
+    pub fn from_config(config: &ForwardAuthConfig, exclude_paths: &[String]) -> Self {
+        let client = reqwest::Client::builder()
+            .redirect(reqwest::redirect::Policy::none())
+            .build()
+            .unwrap_or_default();
+        Self {
+            url: config.url.clone(),
+            copy_headers: config.copy_headers.clone(),
+            timeout: Duration::from_millis(config.timeout),
+            retries: config.retries,
+            fail_open: config.fail_open,
+            exclude_paths: exclude_paths.to_vec(),
+            client,
+        }
+    }
+
+    /// Headers the auth service vouches for; client-sent copies are dropped
+    pub fn copy_headers(&self) -> &[String] {
+        &self.copy_headers
+    }
+
+    /// Check if a path should be excluded from authentication
+    pub fn is_path_excluded(&self, path: &str) -> bool {
+        self.exclude_paths.iter().any(|exclude| path.starts_with(exclude.as_str()))
+    }
+
+    /// Ask the auth service whether the request may proceed
+    pub async fn check(&self, request: &ForwardedRequest<'_>) -> ForwardAuthOutcome {
+        for attempt in 0..=self.retries {
+            match tokio::time::timeout(self.timeout, self.send(request)).await {
+                Ok(Ok(outcome)) => return outcome,
+                Ok(Err(e)) => {
+                    warn!(url = %self.url, attempt = attempt + 1, error = %e, "Forward auth request failed")
+                }
+                Err(_) => {
+                    warn!(url = %self.url, attempt = attempt + 1, timeout = ?self.timeout, "Forward auth request timed out")
+                }
+            }
+        }
+
+        if self.fail_open {
+            warn!(url = %self.url, "Forward auth unavailable, allowing request (fail_open)");
+            ForwardAuthOutcome::Allowed(Vec::new())
+        } else {
+            ForwardAuthOutcome::Unavailable
+        }
+    }
+
+    async fn send(&self, request: &ForwardedRequest<'_>) -> Result<ForwardAuthOutcome, reqwest::Error> {
+        let mut subrequest = self.client.get(&self.url);
+        for (name, value) in request.headers {
+            if !SKIPPED_HEADERS.contains(&name.as_str()) {
+                subrequest = subrequest.header(name, value);
+            }
+        }
+        subrequest = subrequest
+            .header("X-Forwarded-Method", request.method)
+            .header("X-Forwarded-Proto", request.scheme)
+            .header("X-Forwarded-Uri", request.uri);
+        if let Some(host) = request.host {
+            subrequest = subrequest.header("X-Forwarded-Host", host);
+        }
+        if let Some(client_ip) = request.client_ip {
+            subrequest = subrequest.header("X-Forwarded-For", client_ip);
+        }
+
+        let response = subrequest.send().await?;
+        let status = response.status();
+        let headers = response.headers();
+
+        if status.is_success() {
+            debug!(url = %self.url, status = status.as_u16(), "Forward auth allowed request");
+            let copied = self
+                .copy_headers
+                .iter()
+                .filter_map(|name| {
+                    let value = headers.get(name.as_str())?.to_str().ok()?;
+                    Some((name.clone(), value.to_string()))
+                })
+                .collect();
+            return Ok(ForwardAuthOutcome::Allowed(copied));
+        }
+
+        let denial_headers = headers
+            .iter()
+            .filter(|(name, _)| DENIAL_HEADERS.contains(&name.as_str()))
+            .filter_map(|(name, value)| Some((name.as_str().to_string(), value.to_str().ok()?.to_string())))
+            .collect();
+        let status = status.as_u16();
+        let body = response.bytes().await?;
+        debug!(url = %self.url, status, "Forward auth denied request");
+        Ok(ForwardAuthOutcome::Denied { status, headers: denial_headers, body })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::net::SocketAddr;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::Arc;
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+    use tokio::net::TcpListener;
+
+    fn forward_auth(addr: SocketAddr, retries: u32, fail_open: bool) -> ForwardAuth {
+        ForwardAuth::from_config(
+            &ForwardAuthConfig {
+                url: format!("http://{}/verify", addr),
+                copy_headers: vec!["X-User".to_string()],
+                timeout: 200,
+                retries,
+                fail_open,
+            },
+            &[],
+        )
+    }
+
+    async fn check(auth: &ForwardAuth) -> ForwardAuthOutcome {
+        let mut headers = HeaderMap::new();
+        headers.insert("authorization", "Bearer token".parse().unwrap());
+        auth.check(&ForwardedRequest {
+            method: "GET",
+            scheme: "https",
+            host: Some("example.com"),
+            uri: "/private",
+            client_ip: Some("10.0.0.1"),
+            headers: &headers,
+        })
+        .await
+    }
+
+    /// An address nothing is listening on
+    async fn unreachable() -> SocketAddr {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        listener.local_addr().unwrap()
+    }
+
+    /// Auth service answering every request with `response`
+    async fn auth_service(response: &'static str) -> SocketAddr {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(async move {
+            while let Ok((mut stream, _)) = listener.accept().await {
+                let mut buf = [0u8; 4096];
+                let _ = stream.read(&mut buf).await;
+                let _ = stream.write_all(response.as_bytes()).await;
+            }
+        });
+        addr
+    }
+
+    #[tokio::test]
+    async fn test_unreachable_fail_closed() {
+        let auth = forward_auth(unreachable().await, 1, false);
+        assert!(matches!(check(&auth).await, ForwardAuthOutcome::Unavailable));
+    }
+
+    #[tokio::test]
+    async fn test_unreachable_fail_open() {
+        let auth = forward_auth(unreachable().await, 1, true);
+        match check(&auth).await {
+            ForwardAuthOutcome::Allowed(headers) => assert!(headers.is_empty()),
+            other => panic!("expected fail-open, got {:?}", other),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_timeout_retried_then_fails_closed() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let attempts = Arc::new(AtomicUsize::new(0));
+        let counter = attempts.clone();
+        tokio::spawn(async move {
+            let mut held = Vec::new();
+            while let Ok((stream, _)) = listener.accept().await {
+                counter.fetch_add(1, Ordering::SeqCst);
+                // Never answer
+                held.push(stream);
+            }
+        });
+
+        let auth = forward_auth(addr, 2, false);
+        assert!(matches!(check(&auth).await, ForwardAuthOutcome::Unavailable));
+        assert_eq!(attempts.load(Ordering::SeqCst), 3);
+    }
+
+    #[tokio::test]
+    async fn test_allowed_copies_headers() {
+        let addr = auth_service("HTTP/1.1 200 OK\r\nX-User: alice\r\nContent-Length: 0\r\n\r\n").await;
+        match check(&forward_auth(addr, 0, false)).await {
+            ForwardAuthOutcome::Allowed(headers) => {
+                assert_eq!(headers, vec![("X-User".to_string(), "alice".to_string())])
+            }
+            other => panic!("expected allowed, got {:?}", other),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_denial_passed_through() {
+        let addr = auth_service(
+            "HTTP/1.1 401 Unauthorized\r\nWWW-Authenticate: Bearer\r\nX-Internal: 1\r\nContent-Length: 6\r\n\r\ndenied",
+        )
+        .await;
+        match check(&forward_auth(addr, 0, true)).await {
+            ForwardAuthOutcome::Denied { status, headers, body } => {
+                assert_eq!(status, 401);
+                assert_eq!(headers, vec![("www-authenticate".to_string(), "Bearer".to_string())]);
+                assert_eq!(&body[..], b"denied");
+            }
+            other => panic!("expected denial, got {:?}", other),
+        }
+    }
+}
@@ -0,0 +1,110 @@
+//! X-Forwarded-* request headers sent to upstreams
+
+use config::ForwardedForMode;
+
+/// Value of `X-Forwarded-Proto` for the upstream request
+///
+/// Reflects the scheme the client used to reach avalon, not the scheme of the
+/// upstream connection. An existing header is only kept when the immediate
+/// peer is a trusted proxy that terminated the client connection itself.
+pub fn forwarded_proto(client_is_tls: bool, existing: Option<&str>, peer_trusted: bool) -> &'static str {
+    if peer_trusted {
+        if let Some(proto) = existing.map(str::trim) {
+            if proto.eq_ignore_ascii_case("https") {
+                return "https";
+            }
+            if proto.eq_ignore_ascii_case("http") {
+                return "http";
+            }
+        }
+    }
+
+    if client_is_tls {
+        "https"
+    } else {
+        "http"
+    }
+}
+
+/// Value of `X-Forwarded-For` for the upstream request, or None to omit it
+///
+/// In `Replace` mode the resolved client is the last hop recorded by a
+/// trusted peer, or the peer itself otherwise.
+pub fn forwarded_for(
+    mode: ForwardedForMode,
+    existing: Option<&str>,
+    client_ip: &str,
+    peer_trusted: bool,
+) -> Option<String> {
+    let existing = existing.map(str::trim).filter(|v| !v.is_empty());
+
+    match mode {
+        ForwardedForMode::Append => Some(match existing {
+            Some(chain) => format!("{}, {}", chain, client_ip),
+            None => client_ip.to_string(),
+        }),
+        ForwardedForMode::Replace => {
+            let forwarded_client = existing
+                .filter(|_| peer_trusted)
+                .and_then(|chain| chain.rsplit(',').map(str::trim).find(|ip| !ip.is_empty()));
+            Some(forwarded_client.unwrap_or(client_ip).to_string())
+        }
+        ForwardedForMode::Omit => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_https_client_to_http_upstream() {
+        assert_eq!(forwarded_proto(true, None, false), "https");
+        assert_eq!(forwarded_proto(false, None, false), "http");
+    }
+
+    #[test]
+    fn test_untrusted_header_replaced() {
+        assert_eq!(forwarded_proto(false, Some("https"), false), "http");
+        assert_eq!(forwarded_proto(true, Some("http"), false), "https");
+    }
+
+    #[test]
+    fn test_trusted_header_passed_through() {
+        assert_eq!(forwarded_proto(false, Some("https"), true), "https");
+        assert_eq!(forwarded_proto(false, Some("HTTPS"), true), "https");
+        // Garbage from a trusted peer falls back to the inbound scheme
+        assert_eq!(forwarded_proto(true, Some("gopher"), true), "https");
+    }
+
+    #[test]
+    fn test_forwarded_for_append() {
+        assert_eq!(
+            forwarded_for(ForwardedForMode::Append, Some("1.1.1.1, 2.2.2.2"), "10.0.0.1", false),
+            Some("1.1.1.1, 2.2.2.2, 10.0.0.1".to_string())
+        );
+        assert_eq!(
+            forwarded_for(ForwardedForMode::Append, None, "10.0.0.1", false),
+            Some("10.0.0.1".to_string())
+        );
+    }
+
+    #[test]
+    fn test_forwarded_for_replace() {
+        // Spoofed chain from an untrusted client is dropped
+        assert_eq!(
+            forwarded_for(ForwardedForMode::Replace, Some("6.6.6.6"), "10.0.0.1", false),
+            Some("10.0.0.1".to_string())
+        );
+        // A trusted load balancer's last hop is the real client
+        assert_eq!(
+            forwarded_for(ForwardedForMode::Replace, Some("6.6.6.6, 203.0.113.7"), "10.0.0.1", true),
+            Some("203.0.113.7".to_string())
+        );
+    }
+
+    #[test]
+    fn test_forwarded_for_omit() {
+        assert_eq!(forwarded_for(ForwardedForMode::Omit, Some("1.1.1.1"), "10.0.0.1", true), None);
+    }
+}
@@ -0,0 +1,38 @@
+//! HEAD requests for upstreams that only implement GET
+//!
+//! With `head_as_get`, a client HEAD is sent upstream as a GET. The response
+//! headers, Content-Length included, go back to the client unchanged and the
+//! body is dropped as it arrives, so the client sees what a HEAD-capable
+//! upstream would have answered.
+
+use bytes::Bytes;
+use http::Method;
+use pingora_http::RequestHeader;
+
+/// Send a HEAD request upstream as a GET; returns whether it was translated
+pub fn translate_request(request: &mut RequestHeader) -> bool {
+    if request.method != Method::HEAD {
+        return false;
+    }
+    request.set_method(Method::GET);
+    true
+}
+
+/// Drop a chunk of the GET body answering a translated HEAD
+pub fn strip_body(body: &mut Option<Bytes>) {
+    *body = None;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_other_methods_untouched() {
+        let mut request = RequestHeader::build("GET", b"/page", None).unwrap();
+        assert!(!translate_request(&mut request));
+        let mut request = RequestHeader::build("POST", b"/page", None).unwrap();
+        assert!(!translate_request(&mut request));
+        assert_eq!(request.method, Method::POST);
+    }
+}
@@ -1,8 +1,14 @@
 //! Health checker for upstream servers
+//!
+//! Active checks probe each server on an interval. Passive checks watch the
+//! outcome of proxied requests, so a server that passes its probe but fails
+//! real traffic is still ejected; `UpstreamServer::is_healthy` combines both.
 
 use crate::upstream::UpstreamServer;
+use parking_lot::Mutex;
+use std::sync::atomic::{AtomicBool, Ordering};
 use std::sync::Arc;
-use std::time::Duration;
+use std::time::{Duration, Instant};
 use tokio::net::TcpStream;
 use tokio::time::{interval, timeout};
 use tracing::{debug, info, warn};
@@ -14,6 +20,7 @@ pub struct HealthCheckConfig {
     pub interval: Duration,
     pub timeout: Duration,
     pub expected_status: u16,
+    pub passive: Option<PassiveHealthConfig>,
 }
 
 impl Default for HealthCheckConfig {
@@ -23,6 +30,7 @@ impl Default for HealthCheckConfig {
             interval: Duration::from_secs(30),
             timeout: Duration::from_secs(5),
             expected_status: 200,
+            passive: None,
         }
     }
 }
@@ -53,10 +61,123 @@ impl HealthCheckConfig {
             interval: Self::parse_duration(&config.interval).unwrap_or(Duration::from_secs(30)),
             timeout: Self::parse_duration(&config.timeout).unwrap_or(Duration::from_secs(5)),
             expected_status: config.expected_status,
+            passive: config.passive.as_ref().map(PassiveHealthConfig::from_config),
         }
     }
 }
 
+/// Passive health check thresholds
+#[derive(Debug, Clone)]
+pub struct PassiveHealthConfig {
+    pub window: Duration,
+    pub min_requests: u32,
+    pub max_error_rate: f64,
+    pub eject_duration: Duration,
+}
+
+impl Default for PassiveHealthConfig {
+    fn default() -> Self {
+        Self {
+            window: Duration::from_secs(30),
+            min_requests: 10,
+            max_error_rate: 0.5,
+            eject_duration: Duration::from_secs(30),
+        }
+    }
+}
+
+impl PassiveHealthConfig {
+    pub fn from_config(config: &config::PassiveHealthCheckConfig) -> Self {
+        Self {
+            window: HealthCheckConfig::parse_duration(&config.window).unwrap_or(Duration::from_secs(30)),
+            min_requests: config.min_requests,
+            max_error_rate: config.max_error_rate,
+            eject_duration: HealthCheckConfig::parse_duration(&config.eject_duration)
+                .unwrap_or(Duration::from_secs(30)),
+        }
+    }
+}
+
+/// Error rate of a server's recent real traffic
+///
+/// Does nothing until configured by a `HealthChecker` with passive checks.
+#[derive(Debug, Default)]
+pub struct PassiveHealth {
+    /// Fast path for `is_ejected`; the deadline lives in `state`
+    ejected: AtomicBool,
+    state: Mutex<PassiveState>,
+}
+
+#[derive(Debug, Default)]
+struct PassiveState {
+    config: Option<PassiveHealthConfig>,
+    window_start: Option<Instant>,
+    successes: u32,
+    failures: u32,
+    ejected_until: Option<Instant>,
+}
+
+impl PassiveState {
+    fn reset_window(&mut self, now: Instant) {
+        self.window_start = Some(now);
+        self.successes = 0;
+        self.failures = 0;
+    }
+}
+
+impl PassiveHealth {
+    pub fn configure(&self, config: PassiveHealthConfig) {
+        self.state.lock().config = Some(config);
+    }
+
+    /// Record the outcome of a proxied request; returns true if it ejected the server
+    pub fn record(&self, success: bool) -> bool {
+        let mut state = self.state.lock();
+        let Some(config) = state.config.clone() else {
+            return false;
+        };
+        let now = Instant::now();
+
+        // Requests finishing after an ejection don't count toward the next window
+        if state.ejected_until.is_some_and(|until| now < until) {
+            return false;
+        }
+        if state.window_start.is_none_or(|start| now.duration_since(start) >= config.window) {
+            state.reset_window(now);
+        }
+
+        if success {
+            state.successes += 1;
+        } else {
+            state.failures += 1;
+        }
+
+        let total = state.successes + state.failures;
+        let error_rate = f64::from(state.failures) / f64::from(total);
+        if total >= config.min_requests.max(1) && error_rate > config.max_error_rate {
+            state.ejected_until = Some(now + config.eject_duration);
+            state.reset_window(now);
+            self.ejected.store(true, Ordering::Relaxed);
+            return true;
+        }
+        false
+    }
+
+    /// Whether recent traffic has taken the server out of rotation
+    pub fn is_ejected(&self) -> bool {
+        if !self.ejected.load(Ordering::Relaxed) {
+            return false;
+        }
+        let mut state = self.state.lock();
+        if state.ejected_until.is_some_and(|until| Instant::now() < until) {
+            return true;
+        }
+        state.ejected_until = None;
+        self.ejected.store(false, Ordering::Relaxed);
+        false
+    }
+}
+
 /// Health checker
 pub struct HealthChecker {
     servers: Vec<Arc<UpstreamServer>>,
@@ -65,6 +186,11 @@ pub struct HealthChecker {
 
 impl HealthChecker {
     pub fn new(servers: Vec<Arc<UpstreamServer>>, config: HealthCheckConfig) -> Self {
+        if let Some(passive) = &config.passive {
+            for server in &servers {
+                server.passive_health().configure(passive.clone());
+            }
+        }
         Self { servers, config }
     }
 
@@ -81,6 +207,7 @@ impl HealthChecker {
             interval = ?self.config.interval,
             path = %self.config.path,
             servers = self.servers.len(),
+            passive = self.config.passive.is_some(),
             "Health checker started"
         );
 
@@ -91,12 +218,12 @@ impl HealthChecker {
                 let healthy = self.check_server(server).await;
 
                 if healthy {
-                    if !server.is_healthy() {
+                    if !server.is_active_healthy() {
                         info!(upstream = %server.address_str, "Upstream marked healthy");
                     }
                     server.set_healthy(true);
                 } else {
-                    if server.is_healthy() {
+                    if server.is_active_healthy() {
                         warn!(upstream = %server.address_str, "Upstream marked unhealthy");
                     }
                     server.set_healthy(false);
@@ -253,4 +380,80 @@ mod tests {
         let healthy = checker.check_server(&server).await;
         assert!(!healthy);
     }
+
+    /// Upstream answering every request with `200 OK`
+    async fn healthy_upstream() -> String {
+        use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap().to_string();
+        tokio::spawn(async move {
+            while let Ok((mut stream, _)) = listener.accept().await {
+                let mut buf = [0u8; 1024];
+                let _ = stream.read(&mut buf).await;
+                let _ = stream.write_all(b"HTTP/1.1 200 OK\r\nContent-Length: 0\r\n\r\n").await;
+            }
+        });
+        addr
+    }
+
+    fn passive_config() -> HealthCheckConfig {
+        HealthCheckConfig {
+            timeout: Duration::from_secs(2),
+            passive: Some(PassiveHealthConfig {
+                window: Duration::from_secs(60),
+                min_requests: 10,
+                max_error_rate: 0.5,
+                eject_duration: Duration::from_millis(200),
+            }),
+            ..Default::default()
+        }
+    }
+
+    #[tokio::test]
+    async fn test_failing_traffic_ejects_server_passing_active_checks() {
+        let server = Arc::new(UpstreamServer::new(&healthy_upstream().await, false).unwrap());
+        let checker = HealthChecker::new(vec![server.clone()], passive_config());
+
+        assert!(checker.check_server(&server).await);
+        server.set_healthy(true);
+
+        // 4 of 10 failing stays under the 50% threshold
+        for i in 0..10 {
+            server.record_response(i % 3 != 0);
+        }
+        assert!(server.is_healthy());
+
+        for _ in 0..10 {
+            server.record_response(false);
+        }
+        assert!(server.is_active_healthy());
+        assert!(!server.is_healthy());
+
+        // Still passing its active check, the server returns after the ejection
+        assert!(checker.check_server(&server).await);
+        tokio::time::sleep(Duration::from_millis(250)).await;
+        assert!(server.is_healthy());
+    }
+
+    #[test]
+    fn test_passive_needs_min_requests() {
+        let health = PassiveHealth::default();
+        health.configure(PassiveHealthConfig::default());
+        for _ in 0..9 {
+            assert!(!health.record(false));
+        }
+        assert!(!health.is_ejected());
+        assert!(health.record(false));
+        assert!(health.is_ejected());
+    }
+
+    #[test]
+    fn test_passive_disabled_without_config() {
+        let server = UpstreamServer::new("127.0.0.1:8080", false).unwrap();
+        for _ in 0..100 {
+            server.record_response(false);
+        }
+        assert!(server.is_healthy());
+    }
 }
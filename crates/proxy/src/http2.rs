@@ -0,0 +1,59 @@
+//! HTTP/2 settings for client connections
+//!
+//! Pingora hands these to the h2 server handshake on every listener; the
+//! stream limit is what keeps a single connection from opening unbounded
+//! concurrent streams.
+
+use config::Http2Config;
+use pingora_core::protocols::http::v2::server::H2Options;
+
+/// Build the h2 server settings for a server's listeners
+pub fn h2_options(config: &Http2Config) -> H2Options {
+    let mut options = H2Options::new();
+    options.max_concurrent_streams(config.max_concurrent_streams);
+    if let Some(size) = config.max_frame_size {
+        options.max_frame_size(size);
+    }
+    if let Some(size) = config.initial_window_size {
+        options.initial_window_size(size);
+    }
+    options
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // The builder has no getters; its Debug output lists the SETTINGS it will send
+    fn settings(options: &H2Options) -> String {
+        format!("{:?}", options)
+    }
+
+    #[test]
+    fn test_max_concurrent_streams_applied() {
+        let options = h2_options(&Http2Config {
+            max_concurrent_streams: 32,
+            ..Default::default()
+        });
+        assert!(settings(&options).contains("max_concurrent_streams: 32"));
+    }
+
+    #[test]
+    fn test_default_limits_streams() {
+        let settings = settings(&h2_options(&Http2Config::default()));
+        assert!(settings.contains("max_concurrent_streams: 100"));
+        assert!(!settings.contains("max_frame_size"));
+    }
+
+    #[test]
+    fn test_frame_and_window_sizes_applied() {
+        let options = h2_options(&Http2Config {
+            max_concurrent_streams: 100,
+            max_frame_size: Some(32_768),
+            initial_window_size: Some(1_048_576),
+        });
+        let settings = settings(&options);
+        assert!(settings.contains("max_frame_size: 32768"));
+        assert!(settings.contains("initial_window_size: 1048576"));
+    }
+}
@@ -0,0 +1,360 @@
+//! Idempotency-Key replay cache
+//!
+//! Clients send an `Idempotency-Key` header with non-idempotent requests
+//! (typically POST). The first request with a given key is forwarded to the
+//! upstream and its response is stored; retries with the same key are served
+//! the stored response instead of being executed again. Concurrent duplicates
+//! wait for the original request to finish rather than racing it.
+//!
+//! Each stored response remembers a digest of the request body it answered,
+//! so a key reused with a different body is refused instead of replayed.
+//! Responses larger than `max_body_size` aren't stored; requests waiting on
+//! one are forwarded like any other request.
+
+use crate::cache::{CacheConfig, CacheKey, CachedResponse, ResponseCache};
+use config::IdempotencyConfig;
+use dashmap::DashMap;
+use sha2::{Digest, Sha256};
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::watch;
+use tracing::debug;
+
+/// Header the request digest is stored under, never sent to clients
+const REQUEST_DIGEST_HEADER: &str = "x-avalon-request-digest";
+
+/// Outcome of looking up an idempotency key
+pub enum IdempotencyOutcome {
+    /// A response is stored for this key and should be replayed
+    Replay(StoredResponse),
+    /// The caller owns the key and must `complete`, `pass_through` or `abandon` it
+    Execute,
+    /// Another request with the same key is in flight
+    Pending(watch::Receiver<Completion>),
+}
+
+/// Outcome of waiting for an in-flight request with the same key
+pub enum WaitOutcome {
+    /// The original stored its response
+    Replay(StoredResponse),
+    /// The original's response was too large to store; handle the request normally
+    Forward,
+    /// The original failed or didn't finish within `wait_timeout`
+    Failed,
+}
+
+/// How an in-flight request ended, as seen by the requests waiting on it
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Completion {
+    Running,
+    Stored,
+    TooLarge,
+}
+
+/// A stored response and the digest of the request body it answered
+pub struct StoredResponse {
+    pub response: CachedResponse,
+    request_digest: String,
+}
+
+impl StoredResponse {
+    /// Whether a request body with this digest may be answered with the response
+    pub fn answers(&self, request_digest: &str) -> bool {
+        self.request_digest == request_digest
+    }
+}
+
+/// SHA-256 of a request body, fed as the body streams through
+#[derive(Clone, Default)]
+pub struct RequestDigest(Sha256);
+
+impl RequestDigest {
+    pub fn update(&mut self, chunk: &[u8]) {
+        self.0.update(chunk);
+    }
+
+    pub fn finish(self) -> String {
+        format!("{:x}", self.0.finalize())
+    }
+}
+
+/// Replay cache keyed by the idempotency header
+pub struct IdempotencyCache {
+    responses: ResponseCache,
+    in_flight: DashMap<String, watch::Sender<Completion>>,
+    header: String,
+    methods: Vec<String>,
+    ttl: Duration,
+    wait_timeout: Duration,
+    max_body_size: usize,
+}
+
+impl IdempotencyCache {
+    pub fn new(config: &IdempotencyConfig) -> Self {
+        let cache_config = CacheConfig {
+            enabled: true,
+            default_ttl: config.ttl,
+            max_entry_size: config.max_body_size,
+            ..Default::default()
+        };
+
+        Self {
+            responses: ResponseCache::new(cache_config),
+            in_flight: DashMap::new(),
+            header: config.header.to_lowercase(),
+            methods: config.methods.clone(),
+            ttl: Duration::from_secs(config.ttl),
+            wait_timeout: Duration::from_secs(config.wait_timeout),
+            max_body_size: config.max_body_size,
+        }
+    }
+
+    /// Header carrying the idempotency key (lowercase)
+    pub fn header(&self) -> &str {
+        &self.header
+    }
+
+    /// Largest response body stored for replay
+    pub fn max_body_size(&self) -> usize {
+        self.max_body_size
+    }
+
+    /// Check whether requests with this method are subject to replay protection
+    pub fn applies_to(&self, method: &str) -> bool {
+        self.methods.iter().any(|m| m.eq_ignore_ascii_case(method))
+    }
+
+    /// Build the storage key, scoped to the endpoint so keys can't collide across routes
+    pub fn key(method: &str, host: &str, path: &str, idempotency_key: &str) -> CacheKey {
+        CacheKey::new(method, host, path, None).with_vary_header("idempotency-key", idempotency_key)
+    }
+
+    /// Look up a key, claiming it for execution if it is unknown
+    pub fn begin(&self, key: &CacheKey) -> IdempotencyOutcome {
+        if let Some(stored) = self.stored(key) {
+            return IdempotencyOutcome::Replay(stored);
+        }
+
+        match self.in_flight.entry(key.to_string_key()) {
+            dashmap::mapref::entry::Entry::Occupied(entry) => {
+                debug!(key = %entry.key(), "Idempotent request already in flight");
+                IdempotencyOutcome::Pending(entry.get().subscribe())
+            }
+            dashmap::mapref::entry::Entry::Vacant(entry) => {
+                let (tx, _rx) = watch::channel(Completion::Running);
+                entry.insert(tx);
+                // The original may have finished between the lookup and claiming the key
+                match self.stored(key) {
+                    Some(stored) => {
+                        self.in_flight.remove(&key.to_string_key());
+                        IdempotencyOutcome::Replay(stored)
+                    }
+                    None => IdempotencyOutcome::Execute,
+                }
+            }
+        }
+    }
+
+    /// Wait for an in-flight request with the same key to finish
+    pub async fn wait(&self, key: &CacheKey, mut pending: watch::Receiver<Completion>) -> WaitOutcome {
+        let finished = tokio::time::timeout(self.wait_timeout, pending.wait_for(|c| *c != Completion::Running)).await;
+        let completion = match finished {
+            Ok(Ok(completion)) => *completion,
+            _ => return WaitOutcome::Failed,
+        };
+        match completion {
+            Completion::TooLarge => WaitOutcome::Forward,
+            _ => self.stored(key).map_or(WaitOutcome::Failed, WaitOutcome::Replay),
+        }
+    }
+
+    /// Store the response of the request that owns the key and release waiters
+    ///
+    /// `request_digest` is the digest of the request body the response answers.
+    pub fn complete(&self, key: &CacheKey, mut response: CachedResponse, request_digest: String) {
+        response.ttl = self.ttl;
+        response.headers.push((REQUEST_DIGEST_HEADER.to_string(), request_digest));
+        self.responses.put(key, response);
+        let completion = if self.responses.get(key).is_some() { Completion::Stored } else { Completion::TooLarge };
+        self.finish(key, completion);
+    }
+
+    /// Release a key whose response is too large to store, forwarding its waiters
+    pub fn pass_through(&self, key: &CacheKey) {
+        self.finish(key, Completion::TooLarge);
+    }
+
+    /// Release a key without storing a response so it can be retried
+    pub fn abandon(&self, key: &CacheKey) {
+        // Dropping the sender wakes waiters with an error
+        self.in_flight.remove(&key.to_string_key());
+    }
+
+    fn finish(&self, key: &CacheKey, completion: Completion) {
+        if let Some((_, tx)) = self.in_flight.remove(&key.to_string_key()) {
+            let _ = tx.send(completion);
+        }
+    }
+
+    /// The stored response for a key, with the request digest split off
+    fn stored(&self, key: &CacheKey) -> Option<StoredResponse> {
+        let mut response = self.responses.get(key)?;
+        let at = response.headers.iter().position(|(name, _)| name == REQUEST_DIGEST_HEADER)?;
+        let (_, request_digest) = response.headers.remove(at);
+        Some(StoredResponse { response, request_digest })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use bytes::Bytes;
+    use http::StatusCode;
+    use std::time::Instant;
+
+    fn make_response(body: &'static str) -> CachedResponse {
+        CachedResponse {
+            status: StatusCode::CREATED,
+            headers: vec![("content-type".to_string(), "application/json".to_string())],
+            body: Bytes::from(body),
+            cached_at: Instant::now(),
+            ttl: Duration::from_secs(0),
+            etag: None,
+            last_modified: None,
+        }
+    }
+
+    fn digest(body: &str) -> String {
+        let mut digest = RequestDigest::default();
+        digest.update(body.as_bytes());
+        digest.finish()
+    }
+
+    #[test]
+    fn test_replay_returns_stored_response() {
+        let cache = IdempotencyCache::new(&IdempotencyConfig::default());
+        let key = IdempotencyCache::key("POST", "example.com", "/orders", "abc");
+
+        assert!(matches!(cache.begin(&key), IdempotencyOutcome::Execute));
+        cache.complete(&key, make_response(r#"{"id":1}"#), digest("order"));
+
+        match cache.begin(&key) {
+            IdempotencyOutcome::Replay(stored) => {
+                assert!(stored.answers(&digest("order")));
+                assert_eq!(stored.response.status, StatusCode::CREATED);
+                assert_eq!(stored.response.body, Bytes::from(r#"{"id":1}"#));
+                assert_eq!(stored.response.headers, make_response("").headers);
+            }
+            _ => panic!("Expected stored response to be replayed"),
+        }
+    }
+
+    #[test]
+    fn test_keys_scoped_by_endpoint() {
+        let cache = IdempotencyCache::new(&IdempotencyConfig::default());
+        let orders = IdempotencyCache::key("POST", "example.com", "/orders", "abc");
+        let payments = IdempotencyCache::key("POST", "example.com", "/payments", "abc");
+
+        assert!(matches!(cache.begin(&orders), IdempotencyOutcome::Execute));
+        cache.complete(&orders, make_response("order"), digest("order"));
+
+        assert!(matches!(cache.begin(&payments), IdempotencyOutcome::Execute));
+    }
+
+    #[test]
+    fn test_abandon_allows_retry() {
+        let cache = IdempotencyCache::new(&IdempotencyConfig::default());
+        let key = IdempotencyCache::key("POST", "example.com", "/orders", "abc");
+
+        assert!(matches!(cache.begin(&key), IdempotencyOutcome::Execute));
+        cache.abandon(&key);
+        assert!(matches!(cache.begin(&key), IdempotencyOutcome::Execute));
+    }
+
+    #[test]
+    fn test_applies_to_methods() {
+        let cache = IdempotencyCache::new(&IdempotencyConfig::default());
+        assert!(cache.applies_to("POST"));
+        assert!(cache.applies_to("post"));
+        assert!(!cache.applies_to("GET"));
+        assert_eq!(cache.header(), "idempotency-key");
+    }
+
+    #[tokio::test]
+    async fn test_concurrent_duplicates_coalesced() {
+        let cache = Arc::new(IdempotencyCache::new(&IdempotencyConfig::default()));
+        let key = IdempotencyCache::key("POST", "example.com", "/orders", "abc");
+
+        assert!(matches!(cache.begin(&key), IdempotencyOutcome::Execute));
+
+        let mut waiters = Vec::new();
+        for _ in 0..3 {
+            let pending = match cache.begin(&key) {
+                IdempotencyOutcome::Pending(rx) => rx,
+                _ => panic!("Expected duplicate to wait for the in-flight request"),
+            };
+            let cache = cache.clone();
+            let key = key.clone();
+            waiters.push(tokio::spawn(async move { cache.wait(&key, pending).await }));
+        }
+
+        cache.complete(&key, make_response("done"), digest("order"));
+
+        for waiter in waiters {
+            match waiter.await.unwrap() {
+                WaitOutcome::Replay(stored) => assert_eq!(stored.response.body, Bytes::from("done")),
+                _ => panic!("waiter should receive stored response"),
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn test_waiter_released_on_abandon() {
+        let cache = Arc::new(IdempotencyCache::new(&IdempotencyConfig::default()));
+        let key = IdempotencyCache::key("POST", "example.com", "/orders", "abc");
+
+        assert!(matches!(cache.begin(&key), IdempotencyOutcome::Execute));
+        let pending = match cache.begin(&key) {
+            IdempotencyOutcome::Pending(rx) => rx,
+            _ => panic!("Expected pending outcome"),
+        };
+
+        cache.abandon(&key);
+        assert!(matches!(cache.wait(&key, pending).await, WaitOutcome::Failed));
+    }
+
+    #[test]
+    fn test_reused_key_with_other_body_not_answered() {
+        let cache = IdempotencyCache::new(&IdempotencyConfig::default());
+        let key = IdempotencyCache::key("POST", "example.com", "/orders", "abc");
+
+        assert!(matches!(cache.begin(&key), IdempotencyOutcome::Execute));
+        cache.complete(&key, make_response("order"), digest(r#"{"amount":10}"#));
+
+        match cache.begin(&key) {
+            IdempotencyOutcome::Replay(stored) => {
+                assert!(stored.answers(&digest(r#"{"amount":10}"#)));
+                assert!(!stored.answers(&digest(r#"{"amount":1000}"#)));
+            }
+            _ => panic!("Expected stored response"),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_waiters_forwarded_when_response_too_large() {
+        let config = IdempotencyConfig { max_body_size: 16, ..Default::default() };
+        let cache = Arc::new(IdempotencyCache::new(&config));
+        let key = IdempotencyCache::key("POST", "example.com", "/orders", "abc");
+
+        assert!(matches!(cache.begin(&key), IdempotencyOutcome::Execute));
+        let pending = match cache.begin(&key) {
+            IdempotencyOutcome::Pending(rx) => rx,
+            _ => panic!("Expected pending outcome"),
+        };
+        cache.complete(&key, make_response("a body larger than sixteen bytes"), digest("order"));
+
+        assert!(matches!(cache.wait(&key, pending).await, WaitOutcome::Forward));
+        // Nothing was stored, so the next request runs again
+        assert!(matches!(cache.begin(&key), IdempotencyOutcome::Execute));
+    }
+}
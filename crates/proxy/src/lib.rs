@@ -3,52 +3,105 @@
 //! This crate provides the core reverse proxy functionality based on
 //! Cloudflare's Pingora framework.
 
+pub mod access_control;
 pub mod access_log;
 pub mod auth;
+pub mod buffer_pool;
 pub mod cache;
+pub mod canary;
 pub mod circuit_breaker;
+pub mod client_cert;
+pub mod client_disconnect;
 pub mod compression;
+pub mod connection_guard;
+pub mod connection_reuse;
+pub mod correlation;
 pub mod ip_filter;
 pub mod cors;
+pub mod csp_nonce;
+pub mod deadline;
 pub mod error;
+pub mod fault_injection;
 pub mod file_server;
+pub mod forward_auth;
+pub mod forwarded;
+pub mod head;
 pub mod health;
+pub mod http2;
+pub mod idempotency;
+pub mod lifecycle;
+pub mod maintenance;
 pub mod metrics;
+pub mod otlp_log;
+pub mod path_normalize;
+pub mod preflight;
 pub mod proxy;
 pub mod rate_limit;
+pub mod redirect_rewrite;
+pub mod request_body;
+pub mod request_validation;
+pub mod response_hints;
+pub mod retry;
 pub mod rewrite;
 pub mod rhai_rewrite;
 pub mod route;
+pub mod route_index;
 pub mod script_handler;
+pub mod set_cookie;
+pub mod static_response;
+pub mod statsd;
 pub mod upstream;
+pub mod upstream_auth;
+pub mod upstream_headers;
+pub mod upstream_tls;
+pub mod user_agent;
 
 #[cfg(feature = "plugins")]
 pub mod plugin_integration;
 
-pub use access_log::{AccessLogEntry, AccessLogger, LogFormat};
+pub use access_control::AccessControl;
+pub use access_log::{AccessLogEntry, AccessLogger, LogFormat, RouteAccessLogger};
 pub use auth::{AuthResult, CompiledAuth};
+pub use buffer_pool::{BufferPool, PooledBuffer, buffer_pool};
 pub use circuit_breaker::{CircuitBreaker, CircuitBreakerConfig, CircuitBreakerStats, CircuitState};
-pub use cache::{CacheConfig, CacheKey, CacheStats, CachedResponse, ResponseCache};
+pub use cache::{CacheConfig, CacheKey, CacheStats, CacheStatus, CachedResponse, ResponseCache};
+pub use canary::{CanaryRouter, FlagSource, LocalFlagSource, flags};
+pub use client_cert::{ClientCert, ClientCertMatcher};
 pub use compression::{
-    CompressionConfig, CompressionEncoding, ResponseCompressor,
+    CompressionConfig, CompressionEncoding, ContentKind, ResponseCompressor,
     compress, compress_brotli, compress_gzip, is_already_compressed,
-    select_encoding, should_compress_content_type,
+    select_encoding, should_compress_content_type, should_compress_response,
 };
+pub use connection_guard::{ConnectionGuard, ConnectionLimits, ConnectionTracker};
+pub use connection_reuse::ConnectionRequests;
+pub use correlation::CorrelationIds;
 pub use cors::CompiledCors;
+pub use csp_nonce::CspNonce;
+pub use deadline::{RequestDeadline, RequestStage};
 pub use error::*;
+pub use fault_injection::{FaultInjector, InjectedFault};
 pub use ip_filter::{CompiledIpFilter, IpFilterConfig, parse_client_ip};
 pub use file_server::FileServer;
-pub use health::{HealthCheckConfig, HealthChecker};
+pub use health::{HealthCheckConfig, HealthChecker, PassiveHealth, PassiveHealthConfig};
+pub use idempotency::{IdempotencyCache, IdempotencyOutcome};
+pub use lifecycle::Lifecycle;
+pub use maintenance::{Maintenance, MaintenanceBypass};
 pub use metrics::{metrics, wait_for_connections_drain, MetricsRegistry, RequestTimer};
+pub use preflight::{PreflightCheck, run_preflight};
 pub use proxy::AvalonProxy;
-pub use rate_limit::{RateLimitConfig, RateLimiter, RateLimitResult, check_rate_limit};
+pub use rate_limit::{RateLimitConfig, RateLimitRejection, RateLimiter, RateLimitResult, check_rate_limit};
+pub use redirect_rewrite::CompiledRedirectRewrite;
 pub use rewrite::CompiledRewrite;
 pub use rhai_rewrite::{
     RhaiRewriteConfig, RhaiRewriteEngine, RhaiRewriteError, RequestContext, RewriteResult,
 };
-pub use route::RouteTable;
+pub use route::{RouteMatch, RouteMatchInfo, RouteTable, RoutingContext};
 pub use script_handler::{CompiledScriptHandler, ScriptHandlerError, ScriptRequestContext, ScriptResult};
+pub use statsd::StatsdExporter;
 pub use upstream::UpstreamSelector;
+pub use upstream_auth::UpstreamAuth;
+pub use upstream_tls::UpstreamTlsFailure;
+pub use user_agent::UserAgentMatcher;
 
 #[cfg(feature = "plugins")]
 pub use plugin_integration::{PluginState, HookResult};
@@ -0,0 +1,137 @@
+//! Shutdown sequencing and the "lame duck" phase
+//!
+//! On the shutdown signal `/ready` flips to 503 at once so load balancers
+//! stop routing new traffic here, while every other request (including ones
+//! still arriving from balancers that haven't noticed yet) is served as
+//! usual for `pre_drain_delay`. Only then do connections start draining.
+//! Components can register hooks that run as soon as shutdown begins.
+
+use http::StatusCode;
+use parking_lot::Mutex;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::Duration;
+use tracing::info;
+
+type ShutdownHook = Box<dyn Fn() + Send + Sync>;
+
+/// Process-wide shutdown state shared by the proxy and the signal handler
+#[derive(Default)]
+pub struct Lifecycle {
+    shutting_down: AtomicBool,
+    hooks: Mutex<Vec<ShutdownHook>>,
+}
+
+impl Lifecycle {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Run `hook` when shutdown begins, before the pre-drain delay
+    pub fn on_shutdown(&self, hook: impl Fn() + Send + Sync + 'static) {
+        self.hooks.lock().push(Box::new(hook));
+    }
+
+    pub fn is_shutting_down(&self) -> bool {
+        self.shutting_down.load(Ordering::Acquire)
+    }
+
+    /// Mark the process unready and run the shutdown hooks
+    ///
+    /// Returns false if shutdown had already begun; hooks run only once.
+    pub fn begin_shutdown(&self) -> bool {
+        if self.shutting_down.swap(true, Ordering::AcqRel) {
+            return false;
+        }
+        for hook in self.hooks.lock().iter() {
+            hook();
+        }
+        true
+    }
+
+    /// Begin shutdown, keep serving for `pre_drain_delay`, then call `drain`
+    pub fn shutdown<T>(&self, pre_drain_delay: Duration, drain: impl FnOnce() -> T) -> T {
+        self.begin_shutdown();
+        if !pre_drain_delay.is_zero() {
+            info!(
+                pre_drain_delay_secs = pre_drain_delay.as_secs_f64(),
+                "Reporting not ready, serving requests until the pre-drain delay ends"
+            );
+            std::thread::sleep(pre_drain_delay);
+        }
+        drain()
+    }
+
+    /// Status and JSON body for `/ready`
+    pub fn readiness(&self, has_healthy_upstreams: bool) -> (StatusCode, &'static str) {
+        if self.is_shutting_down() {
+            (StatusCode::SERVICE_UNAVAILABLE, r#"{"status":"not_ready","reason":"shutting down"}"#)
+        } else if has_healthy_upstreams {
+            (StatusCode::OK, r#"{"status":"ready"}"#)
+        } else {
+            (StatusCode::SERVICE_UNAVAILABLE, r#"{"status":"not_ready","reason":"no healthy upstreams"}"#)
+        }
+    }
+}
+
+impl std::fmt::Debug for Lifecycle {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Lifecycle")
+            .field("shutting_down", &self.is_shutting_down())
+            .field("hooks", &self.hooks.lock().len())
+            .finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::atomic::AtomicUsize;
+    use std::sync::Arc;
+    use std::time::Instant;
+
+    #[test]
+    fn test_ready_until_shutdown() {
+        let lifecycle = Lifecycle::new();
+        assert_eq!(lifecycle.readiness(true).0, StatusCode::OK);
+        assert_eq!(lifecycle.readiness(false).0, StatusCode::SERVICE_UNAVAILABLE);
+
+        assert!(lifecycle.begin_shutdown());
+        assert!(!lifecycle.begin_shutdown());
+        let (status, body) = lifecycle.readiness(true);
+        assert_eq!(status, StatusCode::SERVICE_UNAVAILABLE);
+        assert!(body.contains("shutting down"));
+    }
+
+    #[test]
+    fn test_not_ready_but_serving_during_pre_drain() {
+        let lifecycle = Arc::new(Lifecycle::new());
+        let hook_calls = Arc::new(AtomicUsize::new(0));
+        let calls = hook_calls.clone();
+        lifecycle.on_shutdown(move || {
+            calls.fetch_add(1, Ordering::SeqCst);
+        });
+
+        let drained = Arc::new(AtomicBool::new(false));
+        let signal = {
+            let (lifecycle, drained) = (lifecycle.clone(), drained.clone());
+            std::thread::spawn(move || {
+                let started = Instant::now();
+                lifecycle.shutdown(Duration::from_millis(300), || {
+                    drained.store(true, Ordering::SeqCst);
+                    started.elapsed()
+                })
+            })
+        };
+
+        // Within the pre-drain window: unready, hooks run, connections not yet draining
+        std::thread::sleep(Duration::from_millis(100));
+        assert_eq!(lifecycle.readiness(true).0, StatusCode::SERVICE_UNAVAILABLE);
+        assert_eq!(hook_calls.load(Ordering::SeqCst), 1);
+        assert!(!drained.load(Ordering::SeqCst));
+
+        let drain_started = signal.join().unwrap();
+        assert!(drain_started >= Duration::from_millis(300));
+        assert!(drained.load(Ordering::SeqCst));
+        assert_eq!(hook_calls.load(Ordering::SeqCst), 1);
+    }
+}
@@ -0,0 +1,244 @@
+//! Maintenance mode with signed preview tokens
+//!
+//! A route in maintenance answers every request with the configured status.
+//! Holders of a token signed with `bypass_secret` still reach the upstream,
+//! which lets a distributed team preview the site without IP allowlists.
+//! A token is `<expiry unix seconds>.<base64url HMAC-SHA256 of the expiry>`;
+//! it is accepted from the `bypass_param` query parameter (and then stored
+//! in the `bypass_cookie` cookie for the following requests) or the cookie.
+
+use base64::Engine;
+use config::MaintenanceConfig;
+use hmac::{Hmac, Mac};
+use sha2::Sha256;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+type HmacSha256 = Hmac<Sha256>;
+
+/// Where a valid preview token was found
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum MaintenanceBypass {
+    /// From the cookie; nothing to remember
+    Cookie,
+    /// From the query string; `Set-Cookie` value storing it until expiry
+    Query(String),
+}
+
+/// Compiled maintenance settings for a route
+pub struct Maintenance {
+    enabled: bool,
+    status: u16,
+    message: String,
+    retry_after: Option<u64>,
+    secret: Option<String>,
+    bypass_param: String,
+    bypass_cookie: String,
+    bypass_auth: bool,
+}
+
+impl Maintenance {
+    pub fn from_config(config: &MaintenanceConfig) -> Self {
+        Self {
+            enabled: config.enabled,
+            status: config.status,
+            message: config.message.clone(),
+            retry_after: config.retry_after,
+            secret: config.bypass_secret.clone(),
+            bypass_param: config.bypass_param.clone(),
+            bypass_cookie: config.bypass_cookie.clone(),
+            bypass_auth: config.bypass_auth,
+        }
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        self.enabled
+    }
+
+    pub fn status(&self) -> u16 {
+        self.status
+    }
+
+    pub fn message(&self) -> &str {
+        &self.message
+    }
+
+    pub fn retry_after(&self) -> Option<u64> {
+        self.retry_after
+    }
+
+    /// Whether a valid token also skips the route's auth
+    pub fn bypasses_auth(&self) -> bool {
+        self.bypass_auth
+    }
+
+    /// Look for a valid preview token in the query string or cookies
+    pub fn bypass(&self, query: Option<&str>, cookie_header: Option<&str>) -> Option<MaintenanceBypass> {
+        let secret = self.secret.as_deref()?;
+        let now = unix_now();
+
+        let from_query = query.and_then(|q| {
+            q.split('&')
+                .filter_map(|pair| pair.split_once('='))
+                .find(|(name, _)| *name == self.bypass_param)
+                .map(|(_, token)| token)
+        });
+        if let Some(token) = from_query {
+            if let Some(expires) = verify_token(secret, token, now) {
+                let cookie = format!(
+                    "{}={}; Path=/; Max-Age={}; HttpOnly; SameSite=Lax",
+                    self.bypass_cookie,
+                    token,
+                    expires - now
+                );
+                return Some(MaintenanceBypass::Query(cookie));
+            }
+        }
+
+        let from_cookie = cookie_header.and_then(|cookies| {
+            cookies
+                .split(';')
+                .filter_map(|c| c.trim().split_once('='))
+                .find(|(name, _)| *name == self.bypass_cookie)
+                .map(|(_, token)| token)
+        });
+        from_cookie
+            .and_then(|token| verify_token(secret, token, now))
+            .map(|_| MaintenanceBypass::Cookie)
+    }
+
+    /// Query string without the preview token, `None` when nothing else is left
+    pub fn strip_query(&self, query: &str) -> Option<String> {
+        let kept: Vec<&str> = query
+            .split('&')
+            .filter(|pair| !pair.is_empty() && pair.split('=').next() != Some(self.bypass_param.as_str()))
+            .collect();
+        (!kept.is_empty()).then(|| kept.join("&"))
+    }
+
+    /// Cookie header without the preview token, `None` when no other cookie is left
+    pub fn strip_cookie(&self, cookie_header: &str) -> Option<String> {
+        let kept: Vec<&str> = cookie_header
+            .split(';')
+            .map(str::trim)
+            .filter(|c| !c.is_empty() && c.split('=').next() != Some(self.bypass_cookie.as_str()))
+            .collect();
+        (!kept.is_empty()).then(|| kept.join("; "))
+    }
+}
+
+/// Sign a preview token valid until `expires` (unix seconds)
+pub fn sign_token(secret: &str, expires: u64) -> String {
+    let expires = expires.to_string();
+    let mut mac = HmacSha256::new_from_slice(secret.as_bytes()).expect("HMAC accepts keys of any length");
+    mac.update(expires.as_bytes());
+    let signature = base64::engine::general_purpose::URL_SAFE_NO_PAD.encode(mac.finalize().into_bytes());
+    format!("{}.{}", expires, signature)
+}
+
+/// Expiry of a correctly signed, unexpired token
+fn verify_token(secret: &str, token: &str, now: u64) -> Option<u64> {
+    let (expires_str, signature_b64) = token.split_once('.')?;
+    let expires: u64 = expires_str.parse().ok()?;
+    let signature = base64::engine::general_purpose::URL_SAFE_NO_PAD.decode(signature_b64).ok()?;
+
+    let mut mac = HmacSha256::new_from_slice(secret.as_bytes()).ok()?;
+    mac.update(expires_str.as_bytes());
+    mac.verify_slice(&signature).ok()?;
+
+    (expires > now).then_some(expires)
+}
+
+fn unix_now() -> u64 {
+    SystemTime::now().duration_since(UNIX_EPOCH).map(|d| d.as_secs()).unwrap_or(0)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SECRET: &str = "preview-secret";
+
+    fn config() -> MaintenanceConfig {
+        MaintenanceConfig {
+            enabled: true,
+            status: 503,
+            message: "Down for maintenance".to_string(),
+            retry_after: Some(600),
+            bypass_secret: Some(SECRET.to_string()),
+            bypass_param: "avalon_preview".to_string(),
+            bypass_cookie: "avalon_preview".to_string(),
+            bypass_auth: false,
+        }
+    }
+
+    fn maintenance() -> Maintenance {
+        Maintenance::from_config(&config())
+    }
+
+    fn valid_token() -> String {
+        sign_token(SECRET, unix_now() + 3600)
+    }
+
+    #[test]
+    fn test_valid_query_token_bypasses_and_sets_cookie() {
+        let token = valid_token();
+        let query = format!("page=2&avalon_preview={}", token);
+        match maintenance().bypass(Some(&query), None) {
+            Some(MaintenanceBypass::Query(cookie)) => {
+                assert!(cookie.starts_with(&format!("avalon_preview={}; Path=/; Max-Age=", token)), "{}", cookie);
+            }
+            other => panic!("expected query bypass, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_valid_cookie_token_bypasses() {
+        let cookies = format!("session=abc; avalon_preview={}", valid_token());
+        assert_eq!(maintenance().bypass(None, Some(&cookies)), Some(MaintenanceBypass::Cookie));
+    }
+
+    #[test]
+    fn test_expired_token_rejected() {
+        let expired = sign_token(SECRET, unix_now() - 1);
+        let query = format!("avalon_preview={}", expired);
+        assert_eq!(maintenance().bypass(Some(&query), None), None);
+        let cookies = format!("avalon_preview={}", expired);
+        assert_eq!(maintenance().bypass(None, Some(&cookies)), None);
+    }
+
+    #[test]
+    fn test_forged_token_rejected() {
+        let expires = unix_now() + 3600;
+        let forged = sign_token("wrong-secret", expires);
+        assert_eq!(maintenance().bypass(Some(&format!("avalon_preview={}", forged)), None), None);
+
+        // A valid signature doesn't carry over to a different expiry
+        let valid = sign_token(SECRET, expires);
+        let (_, signature) = valid.split_once('.').unwrap();
+        let extended = format!("{}.{}", expires + 86_400, signature);
+        assert_eq!(maintenance().bypass(None, Some(&format!("avalon_preview={}", extended))), None);
+
+        assert_eq!(maintenance().bypass(Some("avalon_preview=garbage"), None), None);
+        assert_eq!(maintenance().bypass(None, None), None);
+    }
+
+    #[test]
+    fn test_token_stripped_for_upstream() {
+        let maintenance = maintenance();
+        assert_eq!(maintenance.strip_query("page=2&avalon_preview=1.abc").as_deref(), Some("page=2"));
+        assert_eq!(maintenance.strip_query("avalon_preview=1.abc"), None);
+        assert_eq!(maintenance.strip_query("avalon_preview_x=1").as_deref(), Some("avalon_preview_x=1"));
+
+        assert_eq!(maintenance.strip_cookie("session=abc; avalon_preview=1.abc; theme=dark").as_deref(), Some("session=abc; theme=dark"));
+        assert_eq!(maintenance.strip_cookie("avalon_preview=1.abc"), None);
+    }
+
+    #[test]
+    fn test_no_secret_means_no_bypass() {
+        let maintenance = Maintenance::from_config(&MaintenanceConfig { bypass_secret: None, ..config() });
+        let query = format!("avalon_preview={}", valid_token());
+        assert_eq!(maintenance.bypass(Some(&query), None), None);
+        assert_eq!(maintenance.status(), 503);
+        assert!(maintenance.is_enabled());
+    }
+}
@@ -8,6 +8,14 @@ use std::sync::atomic::{AtomicU64, Ordering};
 use std::sync::Arc;
 use std::time::Instant;
 
+/// Upper bounds of the request duration buckets, in seconds
+const DURATION_BUCKETS: &[f64] = &[0.001, 0.005, 0.01, 0.025, 0.05, 0.1, 0.25, 0.5, 1.0, 2.5, 5.0, 10.0];
+
+/// Upper bounds of the request/response size buckets, in bytes (256 B to 64 MiB)
+const SIZE_BUCKETS: &[f64] = &[
+    256.0, 1024.0, 4096.0, 16384.0, 65536.0, 262144.0, 1048576.0, 4194304.0, 16777216.0, 67108864.0,
+];
+
 /// Global metrics registry
 pub struct MetricsRegistry {
     /// Total requests counter
@@ -20,8 +28,14 @@ pub struct MetricsRegistry {
     pub requests_by_host: CounterVec,
     /// Request duration histogram
     pub request_duration: Histogram,
+    /// Request body size histogram
+    pub request_size: Histogram,
+    /// Response body size histogram
+    pub response_size: Histogram,
     /// Active connections gauge
     pub active_connections: Gauge,
+    /// Requests currently being processed
+    pub in_flight_requests: Gauge,
     /// Upstream health status
     pub upstream_health: GaugeVec,
     /// Upstream request count
@@ -31,8 +45,16 @@ pub struct MetricsRegistry {
     pub cache_misses: Counter,
     /// Rate limit rejections
     pub rate_limit_rejections: Counter,
-    /// TLS handshake errors
-    pub tls_errors: Counter,
+    /// Requests abandoned by the client before their response completed
+    pub client_cancellations: Counter,
+    /// Requests that ran past `request_timeout`, by stage
+    pub request_timeouts: CounterVec,
+    /// Script handlers, file servers and plugin hooks that ran past `handler_timeout`, by handler
+    pub handler_timeouts: CounterVec,
+    /// Failed TLS handshakes, by reason
+    pub tls_errors: CounterVec,
+    /// Failed TLS handshakes with upstreams, by reason
+    pub upstream_tls_errors: CounterVec,
     /// Bytes sent/received
     pub bytes_sent: Counter,
     pub bytes_received: Counter,
@@ -47,16 +69,21 @@ impl MetricsRegistry {
             requests_by_status: CounterVec::new(),
             requests_by_method: CounterVec::new(),
             requests_by_host: CounterVec::new(),
-            request_duration: Histogram::new(vec![
-                0.001, 0.005, 0.01, 0.025, 0.05, 0.1, 0.25, 0.5, 1.0, 2.5, 5.0, 10.0,
-            ]),
+            request_duration: Histogram::new(DURATION_BUCKETS.to_vec()),
+            request_size: Histogram::new(SIZE_BUCKETS.to_vec()),
+            response_size: Histogram::new(SIZE_BUCKETS.to_vec()),
             active_connections: Gauge::new(),
+            in_flight_requests: Gauge::new(),
             upstream_health: GaugeVec::new(),
             upstream_requests: CounterVec::new(),
             cache_hits: Counter::new(),
             cache_misses: Counter::new(),
             rate_limit_rejections: Counter::new(),
-            tls_errors: Counter::new(),
+            client_cancellations: Counter::new(),
+            request_timeouts: CounterVec::new(),
+            handler_timeouts: CounterVec::new(),
+            tls_errors: CounterVec::new(),
+            upstream_tls_errors: CounterVec::new(),
             bytes_sent: Counter::new(),
             bytes_received: Counter::new(),
             start_time: Instant::now(),
@@ -72,6 +99,14 @@ impl MetricsRegistry {
         output.push_str("# TYPE avalon_info gauge\n");
         output.push_str("avalon_info{version=\"0.1.0\"} 1\n\n");
 
+        // Build info
+        output.push_str("# HELP avalon_build_info Build information of the running binary\n");
+        output.push_str("# TYPE avalon_build_info gauge\n");
+        output.push_str(&format!(
+            "avalon_build_info{{version=\"{}\"}} 1\n\n",
+            env!("CARGO_PKG_VERSION")
+        ));
+
         // Uptime
         output.push_str("# HELP avalon_uptime_seconds Proxy uptime in seconds\n");
         output.push_str("# TYPE avalon_uptime_seconds gauge\n");
@@ -121,27 +156,22 @@ impl MetricsRegistry {
         }
         output.push('\n');
 
-        // Request duration histogram
-        output.push_str(
-            "# HELP avalon_request_duration_seconds Request duration in seconds\n",
+        // Request duration and size histograms
+        self.request_duration.export(
+            &mut output,
+            "avalon_request_duration_seconds",
+            "Request duration in seconds",
+        );
+        self.request_size.export(
+            &mut output,
+            "avalon_request_size_bytes",
+            "Request body size in bytes",
+        );
+        self.response_size.export(
+            &mut output,
+            "avalon_response_size_bytes",
+            "Response body size in bytes",
         );
-        output.push_str("# TYPE avalon_request_duration_seconds histogram\n");
-        let (buckets, sum, count) = self.request_duration.get_stats();
-        for (le, bucket_count) in buckets {
-            output.push_str(&format!(
-                "avalon_request_duration_seconds_bucket{{le=\"{}\"}} {}\n",
-                le, bucket_count
-            ));
-        }
-        output.push_str(&format!(
-            "avalon_request_duration_seconds_bucket{{le=\"+Inf\"}} {}\n",
-            count
-        ));
-        output.push_str(&format!("avalon_request_duration_seconds_sum {}\n", sum));
-        output.push_str(&format!(
-            "avalon_request_duration_seconds_count {}\n\n",
-            count
-        ));
 
         // Active connections
         output.push_str("# HELP avalon_active_connections Current active connections\n");
@@ -151,6 +181,14 @@ impl MetricsRegistry {
             self.active_connections.get()
         ));
 
+        // In-flight requests
+        output.push_str("# HELP avalon_in_flight_requests Requests currently being processed\n");
+        output.push_str("# TYPE avalon_in_flight_requests gauge\n");
+        output.push_str(&format!(
+            "avalon_in_flight_requests {}\n\n",
+            self.in_flight_requests.get()
+        ));
+
         // Upstream health
         output.push_str("# HELP avalon_upstream_healthy Upstream server health status (1=healthy, 0=unhealthy)\n");
         output.push_str("# TYPE avalon_upstream_healthy gauge\n");
@@ -193,10 +231,57 @@ impl MetricsRegistry {
             self.rate_limit_rejections.get()
         ));
 
+        // Client cancellations
+        output.push_str("# HELP avalon_client_cancellations_total Requests the client abandoned before the response completed\n");
+        output.push_str("# TYPE avalon_client_cancellations_total counter\n");
+        output.push_str(&format!(
+            "avalon_client_cancellations_total {}\n\n",
+            self.client_cancellations.get()
+        ));
+
+        // Request timeouts
+        output.push_str("# HELP avalon_request_timeouts_total Requests that exceeded the request timeout, by stage\n");
+        output.push_str("# TYPE avalon_request_timeouts_total counter\n");
+        for (stage, count) in self.request_timeouts.get_all() {
+            output.push_str(&format!(
+                "avalon_request_timeouts_total{{stage=\"{}\"}} {}\n",
+                stage, count
+            ));
+        }
+        output.push('\n');
+
+        // Handler timeouts
+        output.push_str("# HELP avalon_handler_timeouts_total Handlers and plugin hooks that exceeded the handler timeout, by handler\n");
+        output.push_str("# TYPE avalon_handler_timeouts_total counter\n");
+        for (handler, count) in self.handler_timeouts.get_all() {
+            output.push_str(&format!(
+                "avalon_handler_timeouts_total{{handler=\"{}\"}} {}\n",
+                handler, count
+            ));
+        }
+        output.push('\n');
+
         // TLS errors
-        output.push_str("# HELP avalon_tls_errors_total TLS handshake error count\n");
+        output.push_str("# HELP avalon_tls_errors_total Failed TLS handshakes, by reason\n");
         output.push_str("# TYPE avalon_tls_errors_total counter\n");
-        output.push_str(&format!("avalon_tls_errors_total {}\n\n", self.tls_errors.get()));
+        for (reason, count) in self.tls_errors.get_all() {
+            output.push_str(&format!(
+                "avalon_tls_errors_total{{reason=\"{}\"}} {}\n",
+                reason, count
+            ));
+        }
+        output.push('\n');
+
+        // Upstream TLS errors
+        output.push_str("# HELP avalon_upstream_tls_errors_total Failed TLS handshakes with upstreams, by reason\n");
+        output.push_str("# TYPE avalon_upstream_tls_errors_total counter\n");
+        for (reason, count) in self.upstream_tls_errors.get_all() {
+            output.push_str(&format!(
+                "avalon_upstream_tls_errors_total{{reason=\"{}\"}} {}\n",
+                reason, count
+            ));
+        }
+        output.push('\n');
 
         // Bytes transferred
         output.push_str("# HELP avalon_bytes_sent_total Total bytes sent to clients\n");
@@ -404,6 +489,19 @@ impl Histogram {
         let count = self.count.load(Ordering::Relaxed);
         (buckets, sum, count)
     }
+
+    /// Append the histogram in Prometheus text format
+    fn export(&self, output: &mut String, name: &str, help: &str) {
+        output.push_str(&format!("# HELP {} {}\n", name, help));
+        output.push_str(&format!("# TYPE {} histogram\n", name));
+        let (buckets, sum, count) = self.get_stats();
+        for (le, bucket_count) in buckets {
+            output.push_str(&format!("{}_bucket{{le=\"{}\"}} {}\n", name, le, bucket_count));
+        }
+        output.push_str(&format!("{}_bucket{{le=\"+Inf\"}} {}\n", name, count));
+        output.push_str(&format!("{}_sum {}\n", name, sum));
+        output.push_str(&format!("{}_count {}\n\n", name, count));
+    }
 }
 
 /// Global metrics instance
@@ -528,6 +626,26 @@ mod tests {
         assert_eq!(buckets[2].1, 3); // <= 1.0
     }
 
+    #[test]
+    fn test_size_histograms() {
+        let registry = MetricsRegistry::new();
+        registry.response_size.observe(1500.0);
+        registry.request_size.observe(100.0);
+
+        let output = registry.export();
+        assert!(output.contains("# TYPE avalon_response_size_bytes histogram"));
+        assert!(output.contains("avalon_response_size_bytes_bucket{le=\"1024\"} 0\n"));
+        assert!(output.contains("avalon_response_size_bytes_bucket{le=\"4096\"} 1\n"));
+        assert!(output.contains("avalon_response_size_bytes_bucket{le=\"+Inf\"} 1\n"));
+        assert!(output.contains("avalon_response_size_bytes_sum 1500\n"));
+        assert!(output.contains("avalon_request_size_bytes_bucket{le=\"256\"} 1\n"));
+        assert!(output.contains("avalon_request_size_bytes_count 1\n"));
+
+        // Duration keeps its own buckets
+        assert!(output.contains("avalon_request_duration_seconds_bucket{le=\"0.001\"} 0\n"));
+        assert!(!output.contains("avalon_request_duration_seconds_bucket{le=\"1024\"}"));
+    }
+
     #[test]
     fn test_metrics_export() {
         let registry = MetricsRegistry::new();
@@ -540,4 +658,52 @@ mod tests {
         assert!(output.contains("avalon_requests_by_status_total{status=\"200\"} 1"));
         assert!(output.contains("avalon_requests_by_method_total{method=\"GET\"} 1"));
     }
+
+    #[test]
+    fn test_upstream_tls_errors_export() {
+        let registry = MetricsRegistry::new();
+        registry.upstream_tls_errors.inc("invalid_certificate");
+        registry.upstream_tls_errors.inc("invalid_certificate");
+
+        let output = registry.export();
+        assert!(output.contains("# TYPE avalon_upstream_tls_errors_total counter"));
+        assert!(output.contains("avalon_upstream_tls_errors_total{reason=\"invalid_certificate\"} 2"));
+    }
+
+    #[test]
+    fn test_handler_timeouts_export() {
+        let registry = MetricsRegistry::new();
+        registry.handler_timeouts.inc("script");
+        registry.handler_timeouts.inc("plugin");
+        registry.handler_timeouts.inc("script");
+
+        let output = registry.export();
+        assert!(output.contains("# TYPE avalon_handler_timeouts_total counter"));
+        assert!(output.contains("avalon_handler_timeouts_total{handler=\"script\"} 2"));
+        assert!(output.contains("avalon_handler_timeouts_total{handler=\"plugin\"} 1"));
+    }
+
+    #[test]
+    fn test_in_flight_requests_export() {
+        let registry = MetricsRegistry::new();
+        assert!(registry.export().contains("avalon_in_flight_requests 0"));
+
+        registry.in_flight_requests.inc();
+        registry.in_flight_requests.inc();
+        assert!(registry.export().contains("avalon_in_flight_requests 2"));
+
+        registry.in_flight_requests.dec();
+        assert!(registry.export().contains("avalon_in_flight_requests 1"));
+    }
+
+    #[test]
+    fn test_build_info_export() {
+        let registry = MetricsRegistry::new();
+        let output = registry.export();
+        assert!(output.contains("# TYPE avalon_build_info gauge"));
+        assert!(output.contains(&format!(
+            "avalon_build_info{{version=\"{}\"}} 1",
+            env!("CARGO_PKG_VERSION")
+        )));
+    }
 }
@@ -0,0 +1,178 @@
+//! Access log records exported over OTLP
+//!
+//! `access_log = "otlp"` sends each `AccessLogEntry` to the tracing OTLP
+//! endpoint as a log record instead of writing a line to a file. Attributes
+//! follow the OpenTelemetry HTTP semantic conventions, and a W3C
+//! `traceparent` sent with the request becomes the record's trace context,
+//! so the log lines up with the trace it belongs to.
+
+use crate::access_log::AccessLogEntry;
+use once_cell::sync::OnceCell;
+use opentelemetry::logs::{AnyValue, LogRecord, Logger, LoggerProvider as _, Severity};
+use opentelemetry::trace::{SpanId, TraceFlags, TraceId};
+use opentelemetry_sdk::logs::{Logger as SdkLogger, LoggerProvider};
+use std::time::SystemTime;
+use tracing::debug;
+
+/// Logger shared by every access log with the OTLP target
+static LOGGER: OnceCell<SdkLogger> = OnceCell::new();
+
+/// Route OTLP access logs to `provider`; only the first call takes effect
+pub fn install(provider: &LoggerProvider) {
+    if LOGGER.set(provider.logger("avalon.access_log")).is_err() {
+        debug!("OTLP access log exporter already installed");
+    }
+}
+
+/// Export an entry through the installed logger, if any
+pub fn emit(entry: &AccessLogEntry) {
+    if let Some(logger) = LOGGER.get() {
+        emit_to(logger, entry);
+    }
+}
+
+/// Trace context carried by a W3C `traceparent` header
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct TraceParent {
+    pub trace_id: TraceId,
+    pub span_id: SpanId,
+    pub flags: TraceFlags,
+}
+
+/// Parse `00-<trace id>-<parent span id>-<flags>`; all-zero ids are invalid
+pub fn parse_traceparent(value: &str) -> Option<TraceParent> {
+    let mut parts = value.trim().split('-');
+    let version = parts.next()?;
+    let trace_id = parts.next()?;
+    let span_id = parts.next()?;
+    let flags = parts.next()?;
+    if version.len() != 2 || version == "ff" || trace_id.len() != 32 || span_id.len() != 16 || flags.len() != 2 {
+        return None;
+    }
+
+    let trace_id = TraceId::from_hex(trace_id).ok()?;
+    let span_id = SpanId::from_hex(span_id).ok()?;
+    if trace_id == TraceId::INVALID || span_id == SpanId::INVALID {
+        return None;
+    }
+    let flags = TraceFlags::new(u8::from_str_radix(flags, 16).ok()?);
+    Some(TraceParent { trace_id, span_id, flags })
+}
+
+/// Build and emit the log record for one request
+fn emit_to<L: Logger>(logger: &L, entry: &AccessLogEntry) {
+    let mut record = logger.create_log_record();
+    record.set_timestamp(SystemTime::from(entry.timestamp));
+    record.set_observed_timestamp(SystemTime::now());
+    let (severity, severity_text) = match entry.status {
+        500.. => (Severity::Error, "ERROR"),
+        400..=499 => (Severity::Warn, "WARN"),
+        _ => (Severity::Info, "INFO"),
+    };
+    record.set_severity_number(severity);
+    record.set_severity_text(severity_text);
+    record.set_body(AnyValue::from(format!("{} {} {}", entry.method, entry.path, entry.status)));
+
+    record.add_attribute("http.request.method", entry.method.clone());
+    record.add_attribute("url.path", entry.path.clone());
+    record.add_attribute("server.address", entry.host.clone());
+    record.add_attribute("http.response.status_code", i64::from(entry.status));
+    record.add_attribute("http.response.body.size", entry.bytes_sent as i64);
+    record.add_attribute("client.address", entry.client_ip.clone());
+    record.add_attribute("user_agent.original", entry.user_agent.clone());
+    record.add_attribute("http.request.header.referer", entry.referer.clone());
+    record.add_attribute("avalon.duration_ms", entry.duration_ms as i64);
+    record.add_attribute("avalon.websocket", entry.is_websocket);
+    if let Some(stage) = entry.timeout_stage {
+        record.add_attribute("avalon.timeout_stage", stage);
+    }
+
+    if let Some(parent) = entry.traceparent.as_deref().and_then(parse_traceparent) {
+        record.set_trace_context(parent.trace_id, parent.span_id, Some(parent.flags));
+    }
+
+    logger.emit(record);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::Utc;
+    use opentelemetry::Key;
+    use opentelemetry_sdk::testing::logs::InMemoryLogsExporter;
+
+    const TRACEPARENT: &str = "00-4bf92f3577b34da6a3ce929d0e0e4736-00f067aa0ba902b7-01";
+
+    fn entry() -> AccessLogEntry {
+        AccessLogEntry {
+            timestamp: Utc::now(),
+            client_ip: "10.0.0.1".to_string(),
+            method: "POST".to_string(),
+            path: "/api/orders".to_string(),
+            host: "shop.example.com".to_string(),
+            status: 201,
+            bytes_sent: 512,
+            user_agent: "curl/8.0".to_string(),
+            referer: "-".to_string(),
+            duration_ms: 37,
+            is_websocket: false,
+            timeout_stage: None,
+            traceparent: Some(TRACEPARENT.to_string()),
+        }
+    }
+
+    #[test]
+    fn test_request_exported_as_log_record() {
+        let exporter = InMemoryLogsExporter::default();
+        let provider = LoggerProvider::builder().with_simple_exporter(exporter.clone()).build();
+        emit_to(&provider.logger("avalon.access_log"), &entry());
+
+        let logs = exporter.get_emitted_logs().unwrap();
+        assert_eq!(logs.len(), 1);
+        let record = &logs[0].record;
+
+        let attribute = |name: &'static str| {
+            record
+                .attributes_iter()
+                .find(|(key, _)| *key == Key::from_static_str(name))
+                .map(|(_, value)| value.clone())
+        };
+        assert_eq!(attribute("http.request.method"), Some(AnyValue::from("POST".to_string())));
+        assert_eq!(attribute("url.path"), Some(AnyValue::from("/api/orders".to_string())));
+        assert_eq!(attribute("server.address"), Some(AnyValue::from("shop.example.com".to_string())));
+        assert_eq!(attribute("http.response.status_code"), Some(AnyValue::from(201i64)));
+        assert_eq!(attribute("http.response.body.size"), Some(AnyValue::from(512i64)));
+        assert_eq!(attribute("avalon.duration_ms"), Some(AnyValue::from(37i64)));
+        assert_eq!(attribute("avalon.timeout_stage"), None);
+        assert_eq!(record.severity_number, Some(Severity::Info));
+
+        let trace = record.trace_context.as_ref().expect("trace context");
+        assert_eq!(trace.trace_id, TraceId::from_hex("4bf92f3577b34da6a3ce929d0e0e4736").unwrap());
+        assert_eq!(trace.span_id, SpanId::from_hex("00f067aa0ba902b7").unwrap());
+        assert_eq!(trace.trace_flags, Some(TraceFlags::SAMPLED));
+    }
+
+    #[test]
+    fn test_record_without_traceparent_has_no_trace_context() {
+        let exporter = InMemoryLogsExporter::default();
+        let provider = LoggerProvider::builder().with_simple_exporter(exporter.clone()).build();
+        let entry = AccessLogEntry { traceparent: None, status: 503, ..entry() };
+        emit_to(&provider.logger("avalon.access_log"), &entry);
+
+        let logs = exporter.get_emitted_logs().unwrap();
+        assert!(logs[0].record.trace_context.is_none());
+        assert_eq!(logs[0].record.severity_number, Some(Severity::Error));
+    }
+
+    #[test]
+    fn test_parse_traceparent() {
+        let parent = parse_traceparent(TRACEPARENT).unwrap();
+        assert!(parent.flags.is_sampled());
+
+        assert!(parse_traceparent("00-00000000000000000000000000000000-00f067aa0ba902b7-01").is_none());
+        assert!(parse_traceparent("00-4bf92f3577b34da6a3ce929d0e0e4736-0000000000000000-01").is_none());
+        assert!(parse_traceparent("ff-4bf92f3577b34da6a3ce929d0e0e4736-00f067aa0ba902b7-01").is_none());
+        assert!(parse_traceparent("00-4bf92f35-00f067aa0ba902b7-01").is_none());
+        assert!(parse_traceparent("garbage").is_none());
+    }
+}
@@ -0,0 +1,188 @@
+//! Request path normalization
+//!
+//! Canonicalizes the request path before route matching so that encoded
+//! or redundant forms can't bypass prefix matchers or confuse upstreams.
+
+use config::PathNormalizationConfig;
+
+/// Outcome of normalizing a request path
+#[derive(Debug, Clone, PartialEq)]
+pub enum PathVerdict {
+    /// Path is already canonical
+    Unchanged,
+    /// Path should be replaced with this canonical form
+    Normalized(String),
+    /// Request should be rejected with 400
+    Rejected(&'static str),
+}
+
+/// Compiled path normalization settings
+#[derive(Debug, Clone)]
+pub struct PathNormalizer {
+    merge_slashes: bool,
+    resolve_dot_segments: bool,
+    decode_unreserved: bool,
+    reject: bool,
+}
+
+impl PathNormalizer {
+    /// Build a normalizer, or None when every step is disabled
+    pub fn from_config(config: &PathNormalizationConfig) -> Option<Self> {
+        if !config.merge_slashes && !config.resolve_dot_segments && !config.decode_unreserved {
+            return None;
+        }
+
+        Some(Self {
+            merge_slashes: config.merge_slashes,
+            resolve_dot_segments: config.resolve_dot_segments,
+            decode_unreserved: config.decode_unreserved,
+            reject: config.reject,
+        })
+    }
+
+    pub fn normalize(&self, path: &str) -> PathVerdict {
+        if !path.starts_with('/') {
+            // Asterisk-form ("*") and similar are left to the HTTP layer
+            return PathVerdict::Unchanged;
+        }
+
+        let decoded;
+        let path_to_split = if self.decode_unreserved {
+            decoded = decode_unreserved(path);
+            decoded.as_str()
+        } else {
+            path
+        };
+
+        let segments: Vec<&str> = path_to_split[1..].split('/').collect();
+        let last = segments.len() - 1;
+        let mut output: Vec<&str> = Vec::with_capacity(segments.len());
+
+        for (i, segment) in segments.iter().enumerate() {
+            match *segment {
+                "" if self.merge_slashes && i != last => {}
+                "." if self.resolve_dot_segments => {
+                    if i == last {
+                        output.push("");
+                    }
+                }
+                ".." if self.resolve_dot_segments => {
+                    if output.pop().is_none() {
+                        return PathVerdict::Rejected("path traverses above root");
+                    }
+                    if i == last {
+                        output.push("");
+                    }
+                }
+                _ => output.push(segment),
+            }
+        }
+
+        let normalized = format!("/{}", output.join("/"));
+        if normalized == path {
+            PathVerdict::Unchanged
+        } else if self.reject {
+            PathVerdict::Rejected("non-canonical path")
+        } else {
+            PathVerdict::Normalized(normalized)
+        }
+    }
+}
+
+/// Decode `%XX` escapes of unreserved characters (RFC 3986 section 2.3)
+fn decode_unreserved(path: &str) -> String {
+    let bytes = path.as_bytes();
+    let mut out = String::with_capacity(path.len());
+    let mut i = 0;
+
+    while i < bytes.len() {
+        if bytes[i] == b'%' && i + 2 < bytes.len() {
+            let decoded = std::str::from_utf8(&bytes[i + 1..i + 3])
+                .ok()
+                .and_then(|hex| u8::from_str_radix(hex, 16).ok());
+            if let Some(b) = decoded.filter(|b| b.is_ascii_alphanumeric() || matches!(b, b'-' | b'.' | b'_' | b'~')) {
+                out.push(b as char);
+                i += 3;
+                continue;
+            }
+        }
+        // Copy one UTF-8 character
+        let ch_len = path[i..].chars().next().map(|c| c.len_utf8()).unwrap_or(1);
+        out.push_str(&path[i..i + ch_len]);
+        i += ch_len;
+    }
+
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn normalizer(reject: bool) -> PathNormalizer {
+        PathNormalizer::from_config(&PathNormalizationConfig {
+            merge_slashes: true,
+            resolve_dot_segments: true,
+            decode_unreserved: true,
+            reject,
+        })
+        .unwrap()
+    }
+
+    #[test]
+    fn test_disabled_by_default() {
+        assert!(PathNormalizer::from_config(&PathNormalizationConfig::default()).is_none());
+    }
+
+    #[test]
+    fn test_merge_slashes() {
+        let n = normalizer(false);
+        assert_eq!(n.normalize("//api//x"), PathVerdict::Normalized("/api/x".to_string()));
+        assert_eq!(n.normalize("//api///users/"), PathVerdict::Normalized("/api/users/".to_string()));
+        assert_eq!(n.normalize("/api/x"), PathVerdict::Unchanged);
+        assert_eq!(n.normalize("/"), PathVerdict::Unchanged);
+    }
+
+    #[test]
+    fn test_dot_segments() {
+        let n = normalizer(false);
+        assert_eq!(n.normalize("/api/./users"), PathVerdict::Normalized("/api/users".to_string()));
+        assert_eq!(n.normalize("/api/v1/../users"), PathVerdict::Normalized("/api/users".to_string()));
+        assert_eq!(n.normalize("/api/%2e/users"), PathVerdict::Normalized("/api/users".to_string()));
+        assert_eq!(n.normalize("/api/x/.."), PathVerdict::Normalized("/api/".to_string()));
+    }
+
+    #[test]
+    fn test_traversal_rejected() {
+        let n = normalizer(false);
+        assert!(matches!(n.normalize("/../etc/passwd"), PathVerdict::Rejected(_)));
+        assert!(matches!(n.normalize("/static/%2e%2e/%2e%2e/etc/passwd"), PathVerdict::Rejected(_)));
+    }
+
+    #[test]
+    fn test_reject_mode() {
+        let n = normalizer(true);
+        assert!(matches!(n.normalize("//api//x"), PathVerdict::Rejected(_)));
+        assert!(matches!(n.normalize("/api/v1/../users"), PathVerdict::Rejected(_)));
+        assert_eq!(n.normalize("/api/x"), PathVerdict::Unchanged);
+    }
+
+    #[test]
+    fn test_decode_only_unreserved() {
+        let n = normalizer(false);
+        assert_eq!(n.normalize("/caf%41"), PathVerdict::Normalized("/cafA".to_string()));
+        // Reserved characters stay encoded
+        assert_eq!(n.normalize("/a%2Fb"), PathVerdict::Unchanged);
+        assert_eq!(n.normalize("/a%20b"), PathVerdict::Unchanged);
+    }
+
+    #[test]
+    fn test_individual_steps() {
+        let merge_only = PathNormalizer::from_config(&PathNormalizationConfig {
+            merge_slashes: true,
+            ..Default::default()
+        })
+        .unwrap();
+        assert_eq!(merge_only.normalize("//a/./b"), PathVerdict::Normalized("/a/./b".to_string()));
+    }
+}
@@ -3,12 +3,14 @@
 //! This module provides integration between the proxy and the plugin system,
 //! including hook execution at various stages of request processing.
 
+use crate::metrics::metrics;
 use plugin::{
-    HookAction, HookExecutor, PluginContext, PluginRegistry,
+    HookAction, HookExecutor, PluginContext, PluginError, PluginRegistry,
     RequestInfo, ResponseInfo, UpstreamInfo, UpstreamSelection,
 };
 use std::collections::HashMap;
 use std::sync::Arc;
+use std::time::Duration;
 use tracing::{debug, trace, warn};
 
 /// Plugin system state
@@ -32,6 +34,12 @@ impl PluginState {
         let executor = HookExecutor::new(registry.clone());
         Self { registry, executor }
     }
+
+    /// Fail hooks that run longer than `timeout`
+    pub fn with_hook_timeout(mut self, timeout: Duration) -> Self {
+        self.executor = self.executor.with_timeout(timeout);
+        self
+    }
 }
 
 impl Default for PluginState {
@@ -44,7 +52,7 @@ impl Clone for PluginState {
     fn clone(&self) -> Self {
         Self {
             registry: self.registry.clone(),
-            executor: HookExecutor::new(self.registry.clone()),
+            executor: self.executor.clone(),
         }
     }
 }
@@ -199,6 +207,13 @@ impl From<HookAction> for HookResult {
     }
 }
 
+/// Count a hook that ran past the handler timeout
+fn record_timeout(error: &PluginError) {
+    if matches!(error, PluginError::HookTimeout { .. }) {
+        metrics().handler_timeouts.inc("plugin");
+    }
+}
+
 /// Synchronous hook runner implementation
 pub struct SyncHookRunner;
 
@@ -213,6 +228,7 @@ impl SyncHookRunner {
             Ok(action) => action.into(),
             Err(e) => {
                 warn!(error = %e, "Early request hook error");
+                record_timeout(&e);
                 HookResult::Error
             }
         }
@@ -228,6 +244,7 @@ impl SyncHookRunner {
             Ok(action) => action.into(),
             Err(e) => {
                 warn!(error = %e, "Request filter hook error");
+                record_timeout(&e);
                 HookResult::Error
             }
         }
@@ -243,6 +260,7 @@ impl SyncHookRunner {
             Ok(route) => route,
             Err(e) => {
                 warn!(error = %e, "Route hook error");
+                record_timeout(&e);
                 None
             }
         }
@@ -258,6 +276,7 @@ impl SyncHookRunner {
             Ok(selection) => selection,
             Err(e) => {
                 warn!(error = %e, "Upstream select hook error");
+                record_timeout(&e);
                 None
             }
         }
@@ -273,6 +292,7 @@ impl SyncHookRunner {
             Ok(action) => action.into(),
             Err(e) => {
                 warn!(error = %e, "Upstream request hook error");
+                record_timeout(&e);
                 HookResult::Error
             }
         }
@@ -288,6 +308,7 @@ impl SyncHookRunner {
             Ok(action) => action.into(),
             Err(e) => {
                 warn!(error = %e, "Response filter hook error");
+                record_timeout(&e);
                 HookResult::Error
             }
         }
@@ -0,0 +1,205 @@
+//! Pre-flight checks for `avalon validate --strict`
+//!
+//! Syntax validation only proves the config parses. These checks go one step
+//! further and probe the environment: upstreams accept TCP connections,
+//! explicit certificate files load, and TLS domains resolve in DNS.
+
+use crate::upstream::normalize_upstream_address;
+use config::{Config, HandlerConfig, TlsConfig};
+use std::net::{TcpStream, ToSocketAddrs};
+use std::time::Duration;
+use tls::CertResolver;
+
+/// Result of a single pre-flight check
+#[derive(Debug, Clone)]
+pub struct PreflightCheck {
+    /// What was checked, e.g. `upstream 127.0.0.1:8080`
+    pub subject: String,
+    /// Failure reason, `None` if the check passed
+    pub error: Option<String>,
+}
+
+impl PreflightCheck {
+    fn new(subject: String, result: Result<(), String>) -> Self {
+        Self {
+            subject,
+            error: result.err(),
+        }
+    }
+
+    pub fn passed(&self) -> bool {
+        self.error.is_none()
+    }
+}
+
+/// Try to open a TCP connection to an upstream address
+pub fn check_upstream_reachable(address: &str, timeout: Duration) -> Result<(), String> {
+    let addrs: Vec<_> = address
+        .to_socket_addrs()
+        .map_err(|e| format!("cannot resolve: {}", e))?
+        .collect();
+
+    if addrs.is_empty() {
+        return Err("address resolved to nothing".to_string());
+    }
+
+    let mut last_error = None;
+    for addr in addrs {
+        match TcpStream::connect_timeout(&addr, timeout) {
+            Ok(_) => return Ok(()),
+            Err(e) => last_error = Some(format!("{}: {}", addr, e)),
+        }
+    }
+
+    Err(last_error.unwrap_or_default())
+}
+
+/// Check that every upstream referenced by the config accepts connections
+pub fn check_upstreams(config: &Config, timeout: Duration) -> Vec<PreflightCheck> {
+    let mut addresses: Vec<String> = Vec::new();
+
+    for server in &config.servers {
+        for route in &server.routes {
+            if let HandlerConfig::ReverseProxy(proxy_config) = &route.handle {
+                let canary = proxy_config.canary.iter().flat_map(|c| c.upstreams.iter());
+                for upstream in proxy_config.upstreams.iter().chain(canary) {
+                    let (address, _) = normalize_upstream_address(upstream, proxy_config.upstream_tls);
+                    if !addresses.contains(&address) {
+                        addresses.push(address);
+                    }
+                }
+            }
+        }
+    }
+
+    addresses
+        .into_iter()
+        .map(|addr| {
+            let result = check_upstream_reachable(&addr, timeout);
+            PreflightCheck::new(format!("upstream {}", addr), result)
+        })
+        .collect()
+}
+
+/// Check that explicitly configured certificate files load
+pub fn check_certificates(tls_config: &TlsConfig) -> Vec<PreflightCheck> {
+    match (&tls_config.cert_path, &tls_config.key_path) {
+        (Some(cert_path), Some(key_path)) => {
+            let result = CertResolver::load_from_files(cert_path, key_path)
+                .map(|_| ())
+                .map_err(|e| e.to_string());
+            vec![PreflightCheck::new(
+                format!("certificate {}", cert_path.display()),
+                result,
+            )]
+        }
+        (Some(path), None) | (None, Some(path)) => vec![PreflightCheck::new(
+            format!("certificate {}", path.display()),
+            Err("cert_path and key_path must be set together".to_string()),
+        )],
+        (None, None) => Vec::new(),
+    }
+}
+
+/// Check that every domain needing a certificate resolves in DNS
+pub fn check_tls_domains(config: &Config) -> Vec<PreflightCheck> {
+    config
+        .get_tls_domains()
+        .into_iter()
+        .map(|domain| {
+            let result = (domain.as_str(), 443)
+                .to_socket_addrs()
+                .map_err(|e| format!("cannot resolve: {}", e))
+                .and_then(|mut addrs| {
+                    addrs
+                        .next()
+                        .map(|_| ())
+                        .ok_or_else(|| "no DNS records".to_string())
+                });
+            PreflightCheck::new(format!("domain {}", domain), result)
+        })
+        .collect()
+}
+
+/// Run all pre-flight checks
+pub fn run_preflight(config: &Config, timeout: Duration) -> Vec<PreflightCheck> {
+    let mut checks = check_upstreams(config, timeout);
+    checks.extend(check_certificates(&config.tls));
+    checks.extend(check_tls_domains(config));
+    checks
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::net::TcpListener;
+    use std::path::PathBuf;
+
+    const TIMEOUT: Duration = Duration::from_millis(500);
+
+    fn make_config(upstream: &str) -> Config {
+        let toml = format!(
+            r#"
+[[servers]]
+name = "test"
+listen = [":8080"]
+
+[[servers.routes]]
+[servers.routes.handle]
+type = "reverse_proxy"
+upstreams = ["{}"]
+"#,
+            upstream
+        );
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("avalon.toml");
+        std::fs::write(&path, toml).unwrap();
+        Config::load(&path).unwrap()
+    }
+
+    fn closed_port() -> String {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        drop(listener);
+        addr.to_string()
+    }
+
+    #[test]
+    fn test_reachable_upstream() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap().to_string();
+
+        assert!(check_upstream_reachable(&addr, TIMEOUT).is_ok());
+
+        let checks = check_upstreams(&make_config(&addr), TIMEOUT);
+        assert_eq!(checks.len(), 1);
+        assert!(checks[0].passed());
+    }
+
+    #[test]
+    fn test_unreachable_upstream() {
+        let addr = closed_port();
+
+        assert!(check_upstream_reachable(&addr, TIMEOUT).is_err());
+
+        let checks = check_upstreams(&make_config(&addr), TIMEOUT);
+        assert_eq!(checks.len(), 1);
+        assert!(!checks[0].passed());
+        assert!(checks[0].subject.contains(&addr));
+    }
+
+    #[test]
+    fn test_missing_certificate_files() {
+        let tls_config = TlsConfig {
+            cert_path: Some(PathBuf::from("/nonexistent/cert.pem")),
+            key_path: Some(PathBuf::from("/nonexistent/key.pem")),
+            ..Default::default()
+        };
+
+        let checks = check_certificates(&tls_config);
+        assert_eq!(checks.len(), 1);
+        assert!(!checks[0].passed());
+
+        assert!(check_certificates(&TlsConfig::default()).is_empty());
+    }
+}
@@ -1,30 +1,56 @@
 //! Main proxy implementation using Pingora's ProxyHttp trait
 
-use crate::access_log::{AccessLogEntry, AccessLogger, LogFormat};
+use crate::access_log::{escape_json, AccessLogEntry, AccessLogger, LogFormat, RouteAccessLogger};
 use crate::auth::{AuthResult, CompiledAuth};
-use crate::cache::{CacheConfig, CacheKey, CachedResponse, ResponseCache};
+use crate::cache::{CacheConfig, CacheKey, CacheLookup, CachePolicy, CacheStatus, CachedResponse, ResponseCache};
+use crate::circuit_breaker::circuits_json;
+use crate::client_cert::ClientCert;
+use crate::client_disconnect::{is_client_disconnect, CLIENT_CLOSED_REQUEST};
+use crate::connection_reuse::{apply_h2_multiplexing, ConnectionRequests};
+use crate::correlation::CorrelationIds;
 use crate::cors::CompiledCors;
+use crate::csp_nonce::CspNonce;
+use crate::deadline::{DeadlineExceeded, RequestDeadline, RequestStage};
 use crate::compression::{
-    CompressionConfig, CompressionEncoding, is_already_compressed,
-    select_encoding, should_compress_content_type, compress,
+    CompressionConfig, CompressionEncoding, ContentKind, finalize_buffered_response, is_already_compressed,
+    select_encoding, should_compress_content_type, should_compress_response, compress,
 };
+use crate::fault_injection::FaultInjector;
+use crate::forward_auth::{ForwardAuthOutcome, ForwardedRequest};
 use crate::file_server::FileServer;
+use crate::forwarded::{forwarded_for, forwarded_proto};
+use crate::head;
+use crate::idempotency::{IdempotencyCache, IdempotencyOutcome, RequestDigest, StoredResponse, WaitOutcome};
+use crate::ip_filter::{CompiledIpFilter, parse_client_ip};
+use crate::lifecycle::Lifecycle;
+use crate::maintenance::{Maintenance, MaintenanceBypass};
 use crate::metrics::metrics;
-use crate::rewrite::CompiledRewrite;
+use crate::path_normalize::{PathNormalizer, PathVerdict};
+use crate::rate_limit::{check_rate_limit, RateLimitRejection, RateLimitResult};
+use crate::redirect_rewrite::{CompiledRedirectRewrite, REDIRECT_HEADERS};
+use crate::request_validation::{check_header_values, check_message_framing, request_host, sanitize_header_values};
+use crate::response_hints::add_link_hints;
+use crate::retry::{request_declares_body, RetryBackoff, StatusRetry};
+use crate::rewrite::{CompiledRewrite, DEFAULT_MAX_REWRITE_BODY_SIZE};
 use crate::rhai_rewrite::{RhaiRewriteEngine, RequestContext as RhaiRequestContext};
-use crate::route::RoutingContext;
-use crate::script_handler::{ScriptRequestContext, ScriptResult};
-use crate::upstream::{UpstreamSelector, UpstreamServer};
+use crate::route::{CompiledRoute, RouteMatch, RoutingContext};
+use crate::script_handler::{ScriptHandlerError, ScriptRequestContext, ScriptResult};
+use crate::set_cookie::{affinity_cookie, is_set_cookie, set_response_header};
+use crate::static_response::simulate_latency;
+use crate::upstream::{PeerTimeouts, UpstreamSelector, UpstreamServer};
+use crate::upstream_auth::UpstreamAuth;
+use crate::upstream_headers::{apply_headers_up, apply_host_header};
+use crate::upstream_tls::UpstreamTlsFailure;
 use async_trait::async_trait;
 use bytes::Bytes;
-use config::{Config, HandlerConfig};
-use tls::ChallengeTokens;
+use config::{AccessAction, Config, ForwardedForMode, HandlerConfig, HeaderValidation, HostHeader};
+use tls::{CertStorage, ChallengeTokens, SniResolver};
 use chrono::Utc;
 use http::StatusCode;
 use parking_lot::RwLock;
 use pingora::prelude::*;
 use pingora_http::{RequestHeader, ResponseHeader};
-use pingora_proxy::{ProxyHttp, Session};
+use pingora_proxy::{FailToProxy, ProxyHttp, Session};
 use std::sync::Arc;
 use std::time::{Duration, Instant};
 use tracing::{debug, error, info, warn};
@@ -38,7 +64,9 @@ use crate::plugin_integration::{
 #[cfg(feature = "plugins")]
 use plugin::PluginContext;
 
+use crate::buffer_pool::PooledBuffer;
 use crate::error::ProxyError;
+use crate::request_body::{body_replayable, skip_withheld_body, RequestBodyLimit};
 
 /// Per-request context
 pub struct RequestCtx {
@@ -49,29 +77,64 @@ pub struct RequestCtx {
     pub static_body: Option<String>,
     pub redirect_to: Option<String>,
     pub redirect_code: Option<u16>,
+    pub custom_headers_up: Vec<(String, String)>,
     pub custom_headers_down: Vec<(String, String)>,
     pub is_websocket: bool,
     pub request_start: Instant,
+    /// Overall deadline from `global.request_timeout`
+    pub deadline: RequestDeadline,
+    /// Stage that ran past the deadline, for logs and metrics
+    pub timeout_stage: Option<RequestStage>,
     /// Selected compression encoding based on Accept-Encoding header
     pub compression_encoding: CompressionEncoding,
-    /// Buffer for response body (for compression)
-    pub response_body_buffer: Vec<u8>,
+    /// Route allows response compression (`compress = false` turns it off)
+    pub compress: bool,
+    /// Buffer for response body (for compression), returned to the pool on drop
+    pub response_body_buffer: PooledBuffer,
     /// Content-Type of the response
     pub response_content_type: Option<String>,
     /// Whether the response is already compressed
     pub response_already_compressed: bool,
     /// Session affinity cookie to set on response (name, value, max_age)
     pub affinity_cookie: Option<(String, String, u64)>,
+    /// Set-Cookie storing a maintenance preview token given in the query
+    pub maintenance_cookie: Option<String>,
+    /// Maintenance bypassed with a preview token, which is kept from the upstream
+    pub maintenance_bypass: Option<Arc<Maintenance>>,
     /// Cache key for caching responses
     pub cache_key: Option<CacheKey>,
     /// Whether this response should be cached
     pub should_cache: bool,
+    /// Cache decision made in `request_filter`, `None` when the cache isn't involved
+    pub cache_status: Option<CacheStatus>,
+    /// Route's TTL override and `Cache-Control` handling
+    pub cache_policy: CachePolicy,
+    /// Nonce for this request's CSP when `security_headers.csp_nonce` is set
+    pub csp_nonce: Option<CspNonce>,
+    /// Correlation headers forwarded upstream, when `correlation_headers` is set
+    pub correlation: Option<CorrelationIds>,
+    /// Credentials the route sends to its upstream
+    pub upstream_auth: Option<Arc<UpstreamAuth>>,
+    /// Expired cache entry to revalidate with the upstream
+    pub stale_cache_entry: Option<CachedResponse>,
+    /// The upstream request carries the stale entry's validators
+    pub revalidating: bool,
+    /// Entry the upstream confirmed unchanged, sent by `fail_to_proxy`
+    pub revalidated: Option<CachedResponse>,
+    /// Client HEAD sent upstream as GET; the response body is dropped
+    pub head_as_get: bool,
     /// Response status for caching
     pub response_status: u16,
+    /// Body filter has work for this response; decided once the headers are final
+    pub transform_needed: bool,
     /// Response headers for caching
     pub response_headers: Vec<(String, String)>,
     /// Compiled rewrite rules for this request
     pub rewrite: Option<Arc<CompiledRewrite>>,
+    /// Body from a status_map rewrite replacing the upstream body
+    pub replacement_body: Option<Bytes>,
+    /// Response body is buffered for `response_body_replace`
+    pub rewrite_body: bool,
     /// Compiled Rhai rewrite engine for this request
     pub rhai_rewrite: Option<Arc<RhaiRewriteEngine>>,
     /// Compiled auth rules for this request
@@ -85,8 +148,12 @@ pub struct RequestCtx {
     pub retry_deadline: Option<Instant>,
     /// Retry duration in milliseconds (from config)
     pub lb_try_duration: u64,
-    /// Retry interval in milliseconds (from config)
-    pub lb_try_interval: u64,
+    /// Wait between retry attempts (from config)
+    pub retry_backoff: RetryBackoff,
+    /// Wait before the next upstream attempt, set when a retry is scheduled
+    pub retry_delay: Option<Duration>,
+    /// Upstream response statuses retried on another upstream
+    pub retry_on_status: Vec<u16>,
     /// Reference to upstream selector for retry logic
     pub upstream_selector: Option<Arc<UpstreamSelector>>,
     /// Compiled CORS configuration for this request
@@ -97,10 +164,41 @@ pub struct RequestCtx {
     pub timeouts: Option<config::TimeoutConfig>,
     /// Maximum request body size in bytes (0 = unlimited)
     pub max_request_body_size: u64,
+    /// Streamed request body size, checked against `max_request_body_size`
+    pub request_body_limit: RequestBodyLimit,
+    /// Largest request body replayed to another upstream on a status retry
+    pub request_buffer_limit: u64,
     /// Enable HTTP/2 for upstream connections
     pub upstream_http2: bool,
+    /// Accept HTTP/1.1 from upstreams that don't negotiate h2
+    pub upstream_http2_fallback: bool,
     /// mTLS configuration for upstream connections
     pub upstream_mtls: Option<config::UpstreamMtlsConfig>,
+    /// Requests per HTTP/1.1 upstream connection before it is closed, 0 = unlimited
+    pub max_upstream_requests: u32,
+    /// Socket of the upstream connection and whether it was reused, until
+    /// its requests are counted
+    pub upstream_connection: Option<(std::os::unix::io::RawFd, bool)>,
+    /// Ask the upstream to close the connection after this request
+    pub close_upstream_connection: bool,
+    /// Whether this request is counted in the in-flight gauge
+    pub in_flight: bool,
+    /// Rewriting of upstream redirect headers for this request
+    pub redirect_rewrite: Option<Arc<CompiledRedirectRewrite>>,
+    /// Idempotency-Key this request owns and must complete or abandon
+    pub idempotency: Option<(Arc<IdempotencyCache>, CacheKey)>,
+    /// Digest of the request body, stored with the idempotent response
+    pub request_digest: Option<RequestDigest>,
+    /// Access log override of the matched route
+    pub access_log: Option<Arc<RouteAccessLogger>>,
+    /// Resource hints of the matched route
+    pub response_hints: Option<Arc<Vec<String>>>,
+    /// Peers whose X-Forwarded-Proto is passed through
+    pub trusted_proxies: Option<Arc<CompiledIpFilter>>,
+    /// X-Forwarded-For handling for the upstream request
+    pub forwarded_for: ForwardedForMode,
+    /// Host header sent upstream
+    pub host_header: HostHeader,
 }
 
 #[derive(Clone)]
@@ -122,19 +220,37 @@ impl RequestCtx {
             static_body: None,
             redirect_to: None,
             redirect_code: None,
+            custom_headers_up: Vec::new(),
             custom_headers_down: Vec::new(),
             is_websocket: false,
             request_start: Instant::now(),
+            deadline: RequestDeadline::default(),
+            timeout_stage: None,
             compression_encoding: CompressionEncoding::Identity,
-            response_body_buffer: Vec::new(),
+            compress: true,
+            response_body_buffer: PooledBuffer::new(),
             response_content_type: None,
             response_already_compressed: false,
             affinity_cookie: None,
+            maintenance_cookie: None,
+            maintenance_bypass: None,
             cache_key: None,
             should_cache: false,
+            cache_status: None,
+            cache_policy: CachePolicy::default(),
+            csp_nonce: None,
+            correlation: None,
+            upstream_auth: None,
+            stale_cache_entry: None,
+            revalidating: false,
+            revalidated: None,
+            head_as_get: false,
             response_status: 0,
+            transform_needed: true,
             response_headers: Vec::new(),
             rewrite: None,
+            replacement_body: None,
+            rewrite_body: false,
             rhai_rewrite: None,
             auth: None,
             #[cfg(feature = "plugins")]
@@ -142,16 +258,124 @@ impl RequestCtx {
             tried_upstreams: Vec::new(),
             retry_deadline: None,
             lb_try_duration: 0,
-            lb_try_interval: 250,
+            retry_backoff: RetryBackoff::default(),
+            retry_delay: None,
+            retry_on_status: Vec::new(),
             upstream_selector: None,
             cors: None,
             request_origin: None,
             timeouts: None,
             max_request_body_size: 0,
+            request_body_limit: RequestBodyLimit::default(),
+            request_buffer_limit: 0,
             upstream_http2: false,
+            upstream_http2_fallback: true,
             upstream_mtls: None,
+            max_upstream_requests: 0,
+            upstream_connection: None,
+            close_upstream_connection: false,
+            in_flight: false,
+            redirect_rewrite: None,
+            idempotency: None,
+            request_digest: None,
+            access_log: None,
+            response_hints: None,
+            trusted_proxies: None,
+            forwarded_for: ForwardedForMode::Append,
+            host_header: HostHeader::Preserve,
         }
     }
+
+    /// Whether the response body is compressed on its way through
+    fn compresses_body(&self) -> bool {
+        should_compress_response(
+            self.compress,
+            self.compression_encoding,
+            self.response_already_compressed,
+            self.response_content_type.as_deref(),
+        ) && !self.is_websocket
+    }
+
+    /// Whether the body has to be collected: compressed, cached, rewritten, or kept for idempotent replay
+    fn buffers_body(&self) -> bool {
+        self.compresses_body() || self.should_cache || self.idempotency.is_some() || self.rewrite_body
+    }
+
+    /// Whether `response_body_filter` does anything with this response
+    fn body_transform_needed(&self) -> bool {
+        self.head_as_get || self.replacement_body.is_some() || self.buffers_body()
+    }
+
+    /// Take a chunk into the response buffer when the body is collected
+    ///
+    /// Returns false, leaving the chunk to stream on, for bodies that aren't.
+    fn buffer_response_chunk(&mut self, body: &mut Option<Bytes>) -> bool {
+        if !self.transform_needed || !self.buffers_body() {
+            return false;
+        }
+        if let Some(chunk) = body.take() {
+            self.response_body_buffer.extend_from_slice(&chunk);
+        }
+
+        // A body of unknown length that outgrows the cap isn't rewritten
+        let max_rewrite = self.rewrite.as_ref().map_or(DEFAULT_MAX_REWRITE_BODY_SIZE, |r| r.max_rewrite_body_size);
+        if self.rewrite_body && self.response_body_buffer.len() > max_rewrite {
+            warn!(
+                buffered = self.response_body_buffer.len(),
+                max_rewrite_body_size = max_rewrite,
+                "Response too large to rewrite, streaming it unmodified"
+            );
+            self.rewrite_body = false;
+            if !self.buffers_body() {
+                self.stop_buffering(body);
+                return false;
+            }
+        }
+
+        // Nor is one too large to store for idempotent replay; requests
+        // waiting on it are forwarded instead
+        if let Some((idempotency, key)) = &self.idempotency {
+            if self.response_body_buffer.len() > idempotency.max_body_size() {
+                warn!(
+                    buffered = self.response_body_buffer.len(),
+                    max_body_size = idempotency.max_body_size(),
+                    "Response too large for idempotent replay, streaming it"
+                );
+                idempotency.pass_through(key);
+                self.idempotency = None;
+                if !self.buffers_body() {
+                    self.stop_buffering(body);
+                    return false;
+                }
+            }
+        }
+        true
+    }
+
+    /// Send what was held back and stream the rest
+    fn stop_buffering(&mut self, body: &mut Option<Bytes>) {
+        *body = Some(Bytes::copy_from_slice(&self.response_body_buffer));
+        self.response_body_buffer.clear();
+        self.transform_needed = self.body_transform_needed();
+    }
+
+    /// Set the wait before the next upstream attempt, never past the retry
+    /// deadline
+    fn schedule_retry(&mut self) {
+        let delay = self.retry_backoff.delay(self.tried_upstreams.len() as u32);
+        let remaining = self
+            .retry_deadline
+            .map(|deadline| deadline.saturating_duration_since(Instant::now()))
+            .unwrap_or_default();
+        self.retry_delay = Some(delay.min(remaining));
+    }
+
+    /// Give back the connection slot `upstream_peer` counted for this request
+    fn release_upstream(&mut self) -> Option<Arc<UpstreamServer>> {
+        let upstream = self.upstream.take()?;
+        upstream.decrement_connections();
+        Some(upstream)
+    }
 }
 
 impl Default for RequestCtx {
@@ -160,6 +384,12 @@ impl Default for RequestCtx {
     }
 }
 
+/// Error ending the proxying of a 304 for a revalidated cache entry
+///
+/// Pingora has nothing left to send after a 304, so `fail_to_proxy` answers
+/// with the refreshed entry instead.
+const CACHE_REVALIDATED: &str = "CacheRevalidated";
+
 /// Merge a value into the Vary header (RFC 7231 Section 7.1.4)
 /// If the header already exists, append the new value; otherwise set it
 fn merge_vary_header(response: &mut ResponseHeader, value: &str) -> Result<()> {
@@ -183,6 +413,27 @@ fn merge_vary_header(response: &mut ResponseHeader, value: &str) -> Result<()> {
     Ok(())
 }
 
+/// Collect response headers worth storing for replay (excluding hop-by-hop headers)
+fn replayable_headers(response: &ResponseHeader) -> Vec<(String, String)> {
+    response.headers
+        .iter()
+        .filter(|(name, _)| {
+            let n = name.as_str().to_lowercase();
+            !["connection", "keep-alive", "transfer-encoding", "te",
+              "trailer", "proxy-authorization", "proxy-authenticate",
+              "upgrade", "content-length"].contains(&n.as_str())
+        })
+        .map(|(name, value)| {
+            (name.as_str().to_string(), value.to_str().unwrap_or("").to_string())
+        })
+        .collect()
+}
+
+/// Whether a client connected over loopback, for the operator endpoints
+fn is_loopback(addr: Option<&pingora_core::protocols::l4::socket::SocketAddr>) -> bool {
+    addr.and_then(|a| a.as_inet()).is_some_and(|a| a.ip().is_loopback())
+}
+
 /// Check if status code allows a message body (RFC 7230 Section 3.3.3)
 /// Returns false for 1xx, 204, and 304 responses
 fn status_allows_body(status: u16) -> bool {
@@ -190,7 +441,12 @@ fn status_allows_body(status: u16) -> bool {
 }
 
 /// Add security headers to response based on configuration
-fn add_security_headers(response: &mut ResponseHeader, config: &config::SecurityHeadersConfig, is_tls: bool) -> Result<()> {
+fn add_security_headers(
+    response: &mut ResponseHeader,
+    config: &config::SecurityHeadersConfig,
+    is_tls: bool,
+    nonce: Option<&CspNonce>,
+) -> Result<()> {
     if !config.enabled {
         return Ok(());
     }
@@ -215,7 +471,10 @@ fn add_security_headers(response: &mut ResponseHeader, config: &config::Security
     }
 
     if let Some(ref csp) = config.content_security_policy {
-        response.insert_header("Content-Security-Policy", csp.clone())?;
+        match nonce {
+            Some(nonce) => nonce.add_to_response(response, csp)?,
+            None => response.insert_header("Content-Security-Policy", csp.clone())?,
+        }
     }
 
     if let Some(ref rp) = config.referrer_policy {
@@ -237,6 +496,12 @@ pub struct AvalonProxy {
     access_logger: Option<AccessLogger>,
     compression_config: CompressionConfig,
     cache: Option<ResponseCache>,
+    /// Requests sent over each pooled upstream connection
+    connection_requests: Arc<ConnectionRequests>,
+    /// Certificates served by the TLS listeners, for `reload_certs`
+    certificates: Option<CertificateStore>,
+    /// Shutdown state; `/ready` reports 503 once shutdown begins
+    lifecycle: Arc<Lifecycle>,
     /// Plugin state (when plugins feature is enabled)
     #[cfg(feature = "plugins")]
     plugin_state: Option<PluginState>,
@@ -268,12 +533,14 @@ impl AvalonProxy {
 
         // Initialize compression config from global settings
         let compression_opts = &config.global.compression;
-        let compression_config = if compression_opts.enabled {
+        let mut compression_config = if compression_opts.enabled {
             CompressionConfig {
                 gzip: compression_opts.gzip,
                 brotli: compression_opts.brotli,
                 min_size: compression_opts.min_size,
                 level: compression_opts.level,
+                brotli_dynamic_level: compression_opts.brotli_dynamic_level.unwrap_or(compression_opts.level),
+                brotli_static_level: compression_opts.brotli_static_level.unwrap_or(compression_opts.level),
             }
         } else {
             CompressionConfig {
@@ -281,15 +548,23 @@ impl AvalonProxy {
                 brotli: false,
                 min_size: 0,
                 level: 0,
+                brotli_dynamic_level: 0,
+                brotli_static_level: 0,
             }
         };
 
+        for codec in compression_config.disable_unavailable() {
+            warn!(codec = codec, "Compression codec is enabled in config but not compiled in; disabling it");
+        }
+
         if compression_opts.enabled {
             info!(
-                gzip = compression_opts.gzip,
-                brotli = compression_opts.brotli,
+                gzip = compression_config.gzip,
+                brotli = compression_config.brotli,
                 min_size = compression_opts.min_size,
                 level = compression_opts.level,
+                brotli_dynamic_level = compression_config.brotli_dynamic_level,
+                brotli_static_level = compression_config.brotli_static_level,
                 "Compression enabled"
             );
         }
@@ -304,6 +579,8 @@ impl AvalonProxy {
                 max_cache_size: cache_opts.max_cache_size,
                 cacheable_status: cache_opts.cacheable_status.clone(),
                 cacheable_methods: cache_opts.cacheable_methods.clone(),
+                status_header: Some(cache_opts.status_header.clone()).filter(|name| !name.is_empty()),
+                stale_if_error: cache_opts.stale_if_error,
             };
             info!(
                 default_ttl = cache_opts.default_ttl,
@@ -323,15 +600,21 @@ impl AvalonProxy {
             access_logger,
             compression_config,
             cache,
+            connection_requests: Arc::new(ConnectionRequests::new()),
+            certificates: None,
+            lifecycle: Arc::new(Lifecycle::new()),
             #[cfg(feature = "plugins")]
             plugin_state: None,
         })
     }
 
-    /// Set the plugin state for this proxy
+    /// Set the plugin state for this proxy, its hooks limited to `global.handler_timeout`
     #[cfg(feature = "plugins")]
     pub fn with_plugin_state(mut self, state: PluginState) -> Self {
-        self.plugin_state = Some(state);
+        self.plugin_state = Some(match self.handler_timeout() {
+            Some(timeout) => state.with_hook_timeout(timeout),
+            None => state,
+        });
         self
     }
 
@@ -341,6 +624,37 @@ impl AvalonProxy {
         self.plugin_state.as_ref()
     }
 
+    /// Attach the SNI resolver of the TLS listeners and the storage its
+    /// certificates come from, so `reload_certs` can refresh them
+    pub fn with_certificates(mut self, resolver: Arc<SniResolver>, storage: Arc<CertStorage>) -> Self {
+        self.certificates = Some(CertificateStore { resolver, storage });
+        self
+    }
+
+    /// Re-read the certificates of the configured TLS domains from storage
+    ///
+    /// Picks up certificates renewed out of band (certbot writing to the
+    /// storage directory) without a full config reload; routing is left
+    /// as it is. Returns the number of loaded certificates.
+    pub async fn reload_certs(&self) -> Result<usize, ProxyError> {
+        let certs = self
+            .certificates
+            .as_ref()
+            .ok_or_else(|| ProxyError::ConfigError("No TLS certificates to reload".to_string()))?;
+        let (domains, storage_path) = {
+            let config = self.config.read();
+            (config.get_tls_domains(), config.tls.storage_path.clone())
+        };
+        tls::load_all_certs(&certs.resolver, &certs.storage, &domains, &storage_path)
+            .await
+            .map_err(|e| ProxyError::ConfigError(format!("Failed to reload certificates: {}", e)))?;
+        certs.resolver.refresh_staples().await;
+
+        let count = certs.resolver.domain_count();
+        info!(certificates = count, "TLS certificates reloaded");
+        Ok(count)
+    }
+
     pub fn reload_config(&self, config: Config) -> Result<(), ProxyError> {
         self.routing
             .load_config(&config.servers)
@@ -350,6 +664,11 @@ impl AvalonProxy {
         Ok(())
     }
 
+    /// Shutdown state shared with the signal handler
+    pub fn lifecycle(&self) -> Arc<Lifecycle> {
+        self.lifecycle.clone()
+    }
+
     pub fn get_all_upstreams(&self) -> Vec<Arc<crate::upstream::UpstreamSelector>> {
         self.routing.get_all_upstreams()
     }
@@ -367,15 +686,17 @@ impl AvalonProxy {
     }
 
     fn get_host<'a>(&self, session: &'a Session) -> Option<&'a str> {
-        session
-            .req_header()
-            .headers
-            .get("host")
-            .and_then(|v| v.to_str().ok())
-            .map(|h| h.split(':').next().unwrap_or(h))
+        request_host(&session.req_header().headers).ok().flatten()
     }
 }
 
+/// SNI resolver of the TLS listeners and its certificate storage
+#[derive(Clone)]
+struct CertificateStore {
+    resolver: Arc<SniResolver>,
+    storage: Arc<CertStorage>,
+}
+
 impl Clone for AvalonProxy {
     fn clone(&self) -> Self {
         Self {
@@ -385,6 +706,9 @@ impl Clone for AvalonProxy {
             access_logger: self.access_logger.clone(),
             compression_config: self.compression_config.clone(),
             cache: self.cache.clone(),
+            connection_requests: self.connection_requests.clone(),
+            certificates: self.certificates.clone(),
+            lifecycle: self.lifecycle.clone(),
             #[cfg(feature = "plugins")]
             plugin_state: self.plugin_state.clone(),
         }
@@ -404,6 +728,50 @@ impl ProxyHttp for AvalonProxy {
         session: &mut Session,
         ctx: &mut Self::CTX,
     ) -> Result<()> {
+        metrics().in_flight_requests.inc();
+        ctx.in_flight = true;
+
+        // Canonicalize the path before anything matches on it
+        let normalizer = PathNormalizer::from_config(&self.config.read().global.path_normalization);
+        if let Some(normalizer) = normalizer {
+            match normalizer.normalize(session.req_header().uri.path()) {
+                PathVerdict::Unchanged => {}
+                PathVerdict::Normalized(normalized) => {
+                    let uri = match session.req_header().uri.query() {
+                        Some(query) => format!("{}?{}", normalized, query),
+                        None => normalized,
+                    };
+                    debug!(original = %session.req_header().uri, normalized = %uri, "Normalized request path");
+                    match uri.parse() {
+                        Ok(uri) => session.req_header_mut().set_uri(uri),
+                        Err(_) => return Err(pingora_core::Error::new(pingora_core::ErrorType::HTTPStatus(400))),
+                    }
+                }
+                PathVerdict::Rejected(reason) => {
+                    warn!(path = %session.req_header().uri.path(), reason = reason, "Rejecting request path");
+                    return Err(pingora_core::Error::new(pingora_core::ErrorType::HTTPStatus(400)));
+                }
+            }
+        }
+
+        // Control characters and invalid UTF-8 in header values break log lines and upstream parsers
+        let header_validation = self.config.read().global.strict_header_validation;
+        match header_validation {
+            HeaderValidation::Off => {}
+            HeaderValidation::Reject => {
+                if let Err((name, reason)) = check_header_values(&session.req_header().headers) {
+                    warn!(header = %name, reason = reason, "Rejecting request with malformed header value");
+                    return Err(pingora_core::Error::new(pingora_core::ErrorType::HTTPStatus(400)));
+                }
+            }
+            HeaderValidation::Sanitize => {
+                let sanitized = sanitize_header_values(&mut session.req_header_mut().headers);
+                if !sanitized.is_empty() {
+                    warn!(headers = ?sanitized, "Sanitized malformed request header values");
+                }
+            }
+        }
+
         let req_header = session.req_header();
         let path = req_header.uri.path();
 
@@ -423,15 +791,16 @@ impl ProxyHttp for AvalonProxy {
             return Err(pingora_core::Error::new(pingora_core::ErrorType::HTTPStatus(400)));
         }
 
-        // RFC 7230 Section 3.3.2: Reject negative Content-Length
-        if let Some(content_length) = req_header.headers.get("content-length") {
-            if let Ok(cl_str) = content_length.to_str() {
-                // Check if it starts with a minus sign or fails to parse as u64
-                if cl_str.starts_with('-') || cl_str.parse::<u64>().is_err() {
-                    warn!(content_length = %cl_str, "Invalid Content-Length header");
-                    return Err(pingora_core::Error::new(pingora_core::ErrorType::HTTPStatus(400)));
-                }
-            }
+        // Malformed Host headers (control characters, bad IPv6 literals, oversized) get a 400
+        if let Err(reason) = request_host(&req_header.headers) {
+            warn!(reason = reason, "Rejecting request with malformed Host header");
+            return Err(pingora_core::Error::new(pingora_core::ErrorType::HTTPStatus(400)));
+        }
+
+        // RFC 7230 Section 3.3.3: Reject ambiguous body framing (request smuggling)
+        if let Err(reason) = check_message_framing(&req_header.headers) {
+            warn!(reason = reason, "Rejecting request with ambiguous message framing");
+            return Err(pingora_core::Error::new(pingora_core::ErrorType::HTTPStatus(400)));
         }
 
         if let Some(response) = self.check_acme_challenge(path) {
@@ -458,6 +827,12 @@ impl ProxyHttp for AvalonProxy {
             debug!(path = %path, "WebSocket upgrade request detected");
         }
 
+        // Long-lived WebSocket connections aren't bound by the request timeout
+        if !ctx.is_websocket {
+            let request_timeout = Duration::from_secs(self.config.read().global.request_timeout);
+            ctx.deadline = RequestDeadline::new(ctx.request_start, request_timeout);
+        }
+
         // Parse Accept-Encoding for compression
         let accept_encoding = headers
             .get("accept-encoding")
@@ -513,15 +888,10 @@ impl ProxyHttp for AvalonProxy {
                 return Ok(true);
             }
             "/ready" | "/readyz" => {
-                // Check if we have any healthy upstreams
+                // Not ready while shutting down or without any healthy upstreams
                 let upstreams = self.get_all_upstreams();
                 let has_healthy = upstreams.is_empty() || upstreams.iter().any(|u| u.has_healthy_server());
-
-                let (status, body) = if has_healthy {
-                    (StatusCode::OK, r#"{"status":"ready"}"#)
-                } else {
-                    (StatusCode::SERVICE_UNAVAILABLE, r#"{"status":"not_ready","reason":"no healthy upstreams"}"#)
-                };
+                let (status, body) = self.lifecycle.readiness(has_healthy);
 
                 let mut header = ResponseHeader::build(status, None)?;
                 header.insert_header("Content-Type", "application/json")?;
@@ -532,9 +902,58 @@ impl ProxyHttp for AvalonProxy {
                 session.write_response_body(Some(body.into()), true).await?;
                 return Ok(true);
             }
+            "/circuits" => {
+                if session.req_header().method != http::Method::GET {
+                    return self.send_error_response(session, 405, "Method Not Allowed").await;
+                }
+                // Exposes upstream addresses, so only local operators may call it
+                if !is_loopback(session.client_addr()) {
+                    return self.send_error_response(session, 403, "Forbidden").await;
+                }
+                let body = circuits_json(&self.routing.circuits());
+                return self.send_json_response(session, StatusCode::OK, body).await;
+            }
+            "/certs/reload" => {
+                if session.req_header().method != http::Method::POST {
+                    return self.send_error_response(session, 405, "Method Not Allowed").await;
+                }
+                // Changes proxy state, so only local operators may call it
+                if !is_loopback(session.client_addr()) {
+                    return self.send_error_response(session, 403, "Forbidden").await;
+                }
+                return match self.reload_certs().await {
+                    Ok(count) => {
+                        let body = format!(r#"{{"certificates":{}}}"#, count);
+                        self.send_json_response(session, StatusCode::OK, body).await
+                    }
+                    Err(e) => {
+                        warn!(error = %e, "Certificate reload via API failed");
+                        self.send_error_response(session, 500, &e.to_string()).await
+                    }
+                };
+            }
             _ => {}
         }
 
+        // Manually close a tripped circuit: POST /circuits/{upstream}/reset
+        if let Some(upstream) = path.strip_prefix("/circuits/").and_then(|p| p.strip_suffix("/reset")) {
+            if session.req_header().method != http::Method::POST {
+                return self.send_error_response(session, 405, "Method Not Allowed").await;
+            }
+            // Changes proxy state, so only local operators may call it
+            if !is_loopback(session.client_addr()) {
+                return self.send_error_response(session, 403, "Forbidden").await;
+            }
+            let upstream = urlencoding::decode(upstream).map(|u| u.into_owned()).unwrap_or_else(|_| upstream.to_string());
+            let reset = self.routing.reset_circuit(&upstream);
+            if reset == 0 {
+                return self.send_error_response(session, 404, "No circuit breaker for upstream").await;
+            }
+            info!(upstream = %upstream, circuits = reset, "Circuit breaker reset via API");
+            let body = format!(r#"{{"upstream":"{}","reset":{}}}"#, escape_json(&upstream), reset);
+            return self.send_json_response(session, StatusCode::OK, body).await;
+        }
+
         // Handle ACME challenge
         if ctx.is_acme_challenge {
             let response = ctx.acme_response.as_ref().unwrap();
@@ -549,369 +968,598 @@ impl ProxyHttp for AvalonProxy {
             return Ok(true);
         }
 
-        // Check cache before proxying
-        if let (Some(cache), Some(cache_key)) = (&self.cache, &ctx.cache_key) {
-            if let Some(cached) = cache.get(cache_key) {
-                debug!(key = %cache_key.to_string_key(), "Serving from cache");
-                metrics().cache_hits.inc();
+        // Nonce shared by the upstream request and the response CSP
+        ctx.csp_nonce = {
+            let config = self.config.read();
+            let security_headers = &config.global.security_headers;
+            security_headers.csp_nonce.as_ref().filter(|_| security_headers.enabled).map(CspNonce::generate)
+        };
 
-                let status = cached.status;
-                let mut header = ResponseHeader::build(status, None)?;
+        // Correlation ids kept from the client or generated for this request
+        ctx.correlation = {
+            let config = self.config.read();
+            let names = &config.global.correlation_headers;
+            (!names.is_empty()).then(|| CorrelationIds::from_request(names, session.req_header()))
+        };
 
-                for (name, value) in cached.headers.clone() {
-                    header.insert_header(name, value)?;
-                }
-                header.insert_header("X-Cache", "HIT")?;
-                header.insert_header("Content-Length", cached.body.len().to_string())?;
+        let host = self.get_host(session);
+        let path = session.req_header().uri.path();
+        let method = session.req_header().method.as_str();
 
-                session.write_response_header(Box::new(header), false).await?;
-                session.write_response_body(Some(cached.body.clone()), true).await?;
+        // Redirect plain HTTP requests for hosts served over TLS
+        let is_tls = session.digest().map(|d| d.ssl_digest.is_some()).unwrap_or(false);
+        if let Some(host) = host.filter(|h| !is_tls && self.routing.should_redirect_https(h)) {
+            let query = session.req_header().uri.query().map(|q| format!("?{}", q)).unwrap_or_default();
+            let location = format!("https://{}{}{}", host, path, query);
 
-                return Ok(true);
-            } else {
-                metrics().cache_misses.inc();
+            let mut header = ResponseHeader::build(StatusCode::MOVED_PERMANENTLY, None)?;
+            header.insert_header("Location", location)?;
+            header.insert_header("Server", "avalon")?;
+            // RFC 7230: Redirect responses should include Content-Length: 0
+            header.insert_header("Content-Length", "0")?;
+
+            session.write_response_header(Box::new(header), true).await?;
+            return Ok(true);
+        }
+
+        // Default OPTIONS response listing the methods the route accepts;
+        // routes with CORS keep handling their own preflight below
+        if method == "OPTIONS" && self.config.read().global.options_response {
+            for table in self.routing.tables_for_host(host) {
+                let has_cors = table.match_route(host, path, method).is_some_and(|r| r.cors.is_some());
+                if has_cors {
+                    break;
+                }
+                if let Some(methods) = table.allowed_methods(host, path) {
+                    return self.send_options_response(session, &methods.join(", ")).await;
+                }
             }
         }
 
-        let host = self.get_host(session);
-        let path = session.req_header().uri.path();
-        let method = session.req_header().method.as_str();
+        // Find matching route
+        let user_agent = session.req_header().headers.get("user-agent").and_then(|v| v.to_str().ok());
+        let client_cert = session
+            .digest()
+            .and_then(|d| d.ssl_digest.as_deref())
+            .and_then(ClientCert::from_digest);
+        for table in self.routing.tables_for_host(host) {
+            let Some(resolved) = table.resolve_request(host, path, method, user_agent, client_cert.as_ref()) else {
+                continue;
+            };
+
+            // The resolving server's access policy comes before routing, and so before the cache
+            if let Some(access) = &table.access_control {
+                let client_ip = session.client_addr().and_then(|a| a.as_inet()).map(|a| a.ip());
+                match access.evaluate(client_ip, method, path) {
+                    AccessAction::Allow => {}
+                    AccessAction::Deny => {
+                        warn!(client_ip = ?client_ip, method = %method, path = %path, "Denied by access control");
+                        return self.send_error_response(session, 403, "Forbidden").await;
+                    }
+                    AccessAction::RequireAuth => {
+                        let query = session.req_header().uri.query();
+                        if let AuthResult::Denied { reason, request_auth, realm } =
+                            access.authenticate(&session.req_header().headers, query, path)
+                        {
+                            warn!(reason = %reason, path = %path, "Access control authentication denied");
+                            return self.send_auth_response(session, request_auth, realm.as_deref()).await;
+                        }
+                    }
+                }
+            }
 
-        // Check for HTTPS redirect (only on non-TLS connections)
-        // TODO: detect actual TLS state from session
-        for table in self.routing.tables() {
-            if table.should_redirect_https() {
-                if let Some(host) = host {
+            let route = match resolved {
+                RouteMatch::Matched(route) => route,
+                RouteMatch::Redirect(location) => {
                     let query = session.req_header().uri.query().map(|q| format!("?{}", q)).unwrap_or_default();
-                    let location = format!("https://{}{}{}", host, path, query);
+                    return self.send_redirect_response(session, 301, &format!("{}{}", location, query)).await;
+                }
+            };
 
-                    let mut header = ResponseHeader::build(StatusCode::MOVED_PERMANENTLY, None)?;
-                    header.insert_header("Location", location)?;
-                    header.insert_header("Server", "avalon")?;
-                    // RFC 7230: Redirect responses should include Content-Length: 0
-                    header.insert_header("Content-Length", "0")?;
+            ctx.access_log = route.access_log.clone();
+            ctx.response_hints = route.response_hints.clone();
 
-                    session.write_response_header(Box::new(header), true).await?;
-                    return Ok(true);
+            // Bot management: refuse blocked User-Agents before handling, cache included
+            if let Some(status) = route.blocked_user_agent_status(user_agent) {
+                debug!(user_agent = ?user_agent, status, "Blocked User-Agent");
+                let message = if status == 429 { "Too Many Requests" } else { "Forbidden" };
+                return self.send_error_response(session, status, message).await;
+            }
+
+            // Chaos testing: delay or abort a share of requests
+            if let Some(injector) = &route.fault_injection {
+                if let Some(status) = self.apply_fault_injection(session, injector).await {
+                    return self.send_error_response(session, status, "Injected Fault").await;
                 }
             }
-        }
 
-        // Find matching route
-        for table in self.routing.tables() {
-            if let Some(route) = table.match_route(host, path, method) {
-                match &route.handler {
-                    HandlerConfig::ReverseProxy(proxy_config) => {
-                        if let Some(upstream_selector) = &route.upstream {
-                            // Handle session affinity if configured
-                            let (upstream, affinity_cookie) = if let Some(affinity_config) = &proxy_config.session_affinity {
-                                // Get affinity key based on type
-                                let affinity_key = match affinity_config.affinity_type.as_str() {
-                                    "cookie" => {
-                                        // Extract cookie value from request
-                                        session.req_header().headers
-                                            .get("cookie")
-                                            .and_then(|v| v.to_str().ok())
-                                            .and_then(|cookies| {
-                                                cookies.split(';')
-                                                    .map(|c| c.trim())
-                                                    .find(|c| c.starts_with(&format!("{}=", affinity_config.cookie_name)))
-                                                    .and_then(|c| c.split('=').nth(1))
-                                                    .map(|v| v.to_string())
-                                            })
-                                    }
-                                    "ip_hash" => {
-                                        // Use client IP as affinity key
-                                        session.client_addr()
-                                            .map(|a| {
-                                                let s = a.to_string();
-                                                s.split(':').next().unwrap_or(&s).to_string()
-                                            })
-                                    }
-                                    _ => None,
-                                };
+            match &route.handler {
+                HandlerConfig::ReverseProxy(proxy_config) => {
+                    if let Some(upstream_selector) = &route.upstream {
+                        // Send the request to the canary group when its flag selects it;
+                        // the chosen group decides which rewrite applies
+                        let (upstream_selector, rewrite) = match &route.canary {
+                            Some(canary) => {
+                                let override_value = canary.header().and_then(|name| {
+                                    session.req_header().headers.get(name).and_then(|v| v.to_str().ok())
+                                });
+                                let use_canary = canary.use_canary(override_value);
+                                let rewrite = canary.rewrite_for(use_canary, route.rewrite.as_ref());
+                                if use_canary {
+                                    (canary.selector(), rewrite)
+                                } else {
+                                    (upstream_selector, rewrite)
+                                }
+                            }
+                            None => (upstream_selector, route.rewrite.clone()),
+                        };
+
+                        // Handle session affinity if configured
+                        let (upstream, affinity_cookie) = if let Some(affinity_config) = &proxy_config.session_affinity {
+                            // Get affinity key based on type
+                            let affinity_key = match affinity_config.affinity_type.as_str() {
+                                "cookie" => {
+                                    // Extract cookie value from request
+                                    session.req_header().headers
+                                        .get("cookie")
+                                        .and_then(|v| v.to_str().ok())
+                                        .and_then(|cookies| {
+                                            cookies.split(';')
+                                                .map(|c| c.trim())
+                                                .find(|c| c.starts_with(&format!("{}=", affinity_config.cookie_name)))
+                                                .and_then(|c| c.split('=').nth(1))
+                                                .map(|v| v.to_string())
+                                        })
+                                }
+                                "ip_hash" => {
+                                    // Use client IP as affinity key
+                                    session.client_addr()
+                                        .map(|a| {
+                                            let s = a.to_string();
+                                            s.split(':').next().unwrap_or(&s).to_string()
+                                        })
+                                }
+                                "jwt_claim" => {
+                                    // Hash a claim of the verified token, e.g. the tenant
+                                    // whose data lives on one shard; prefixed with the claim
+                                    // name so numeric values aren't taken as upstream indices
+                                    let claim = affinity_config.claim.as_deref().unwrap_or_default();
+                                    route.auth.as_ref()
+                                        .and_then(|auth| auth.jwt_claim(&session.req_header().headers, claim))
+                                        .map(|value| format!("{}={}", claim, value))
+                                }
+                                _ => None,
+                            };
+
+                            match upstream_selector.select_with_affinity(affinity_key.as_deref()) {
+                                Ok((server, idx)) => {
+                                    // Only set cookie if using cookie affinity
+                                    let cookie = if affinity_config.affinity_type == "cookie" {
+                                        Some((
+                                            affinity_config.cookie_name.clone(),
+                                            idx.to_string(),
+                                            affinity_config.cookie_max_age,
+                                        ))
+                                    } else {
+                                        None
+                                    };
+                                    (Ok(server), cookie)
+                                }
+                                Err(e) => (Err(e), None),
+                            }
+                        } else {
+                            // No affinity, use normal selection
+                            (upstream_selector.select(), None)
+                        };
+
+                        match upstream {
+                            Ok(upstream) => {
+                                ctx.handler_type = Some(HandlerType::ReverseProxy);
+                                ctx.upstream = Some(upstream);
+                                ctx.affinity_cookie = affinity_cookie;
+                                ctx.rewrite = rewrite;
+                                ctx.rhai_rewrite = route.rhai_rewrite.clone();
+                                ctx.auth = route.auth.clone();
+                                ctx.cors = route.cors.clone();
+                                ctx.redirect_rewrite = route.redirect_rewrite.clone();
+                                ctx.trusted_proxies = route.trusted_proxies.clone();
+                                ctx.upstream_auth = route.upstream_auth.clone();
+
+                                // Handle CORS preflight (OPTIONS) request
+                                if method == "OPTIONS" {
+                                    if let Some(cors) = &ctx.cors {
+                                        let request_method = session.req_header().headers
+                                            .get("access-control-request-method")
+                                            .and_then(|v| v.to_str().ok());
+                                        let request_headers = session.req_header().headers
+                                            .get("access-control-request-headers")
+                                            .and_then(|v| v.to_str().ok());
 
-                                match upstream_selector.select_with_affinity(affinity_key.as_deref()) {
-                                    Ok((server, idx)) => {
-                                        // Only set cookie if using cookie affinity
-                                        let cookie = if affinity_config.affinity_type == "cookie" {
-                                            Some((
-                                                affinity_config.cookie_name.clone(),
-                                                idx.to_string(),
-                                                affinity_config.cookie_max_age,
-                                            ))
+                                        if let Some(cors_headers) = cors.preflight_headers(
+                                            ctx.request_origin.as_deref(),
+                                            request_method,
+                                            request_headers,
+                                        ) {
+                                            debug!("CORS preflight request accepted");
+                                            return self.send_cors_preflight_response(session, cors_headers).await;
                                         } else {
-                                            None
-                                        };
-                                        (Ok(server), cookie)
+                                            debug!("CORS preflight request rejected");
+                                            return self.send_error_response(session, 403, "CORS preflight rejected").await;
+                                        }
                                     }
-                                    Err(e) => (Err(e), None),
                                 }
-                            } else {
-                                // No affinity, use normal selection
-                                (upstream_selector.select(), None)
-                            };
 
-                            match upstream {
-                                Ok(upstream) => {
-                                    ctx.handler_type = Some(HandlerType::ReverseProxy);
-                                    ctx.upstream = Some(upstream);
-                                    ctx.affinity_cookie = affinity_cookie;
-                                    ctx.rewrite = route.rewrite.clone();
-                                    ctx.rhai_rewrite = route.rhai_rewrite.clone();
-                                    ctx.auth = route.auth.clone();
-                                    ctx.cors = route.cors.clone();
-
-                                    // Handle CORS preflight (OPTIONS) request
-                                    if method == "OPTIONS" {
-                                        if let Some(cors) = &ctx.cors {
-                                            let request_method = session.req_header().headers
-                                                .get("access-control-request-method")
-                                                .and_then(|v| v.to_str().ok());
-                                            let request_headers = session.req_header().headers
-                                                .get("access-control-request-headers")
-                                                .and_then(|v| v.to_str().ok());
-
-                                            if let Some(cors_headers) = cors.preflight_headers(
-                                                ctx.request_origin.as_deref(),
-                                                request_method,
-                                                request_headers,
-                                            ) {
-                                                debug!("CORS preflight request accepted");
-                                                return self.send_cors_preflight_response(session, cors_headers).await;
-                                            } else {
-                                                debug!("CORS preflight request rejected");
-                                                return self.send_error_response(session, 403, "CORS preflight rejected").await;
+                                // Store retry configuration
+                                ctx.lb_try_duration = proxy_config.lb_try_duration;
+                                ctx.retry_backoff = RetryBackoff {
+                                    interval: Duration::from_millis(proxy_config.lb_try_interval),
+                                    mode: proxy_config.lb_try_backoff,
+                                    jitter: proxy_config.lb_try_jitter,
+                                };
+                                ctx.retry_on_status = proxy_config.retry_on_status.clone();
+                                ctx.upstream_selector = Some(upstream_selector.clone());
+                                if proxy_config.lb_try_duration > 0 {
+                                    ctx.retry_deadline = Some(Instant::now() + Duration::from_millis(proxy_config.lb_try_duration));
+                                }
+
+                                // Store timeout configuration for connection pool
+                                ctx.timeouts = Some(proxy_config.timeouts.clone());
+
+                                // Store max request body size for size limiting
+                                ctx.max_request_body_size = proxy_config.max_request_body_size;
+                                ctx.request_body_limit = RequestBodyLimit::new(proxy_config.max_request_body_size);
+                                ctx.request_buffer_limit = proxy_config.request_buffer_limit;
+
+                                // Store HTTP/2 upstream configuration
+                                ctx.upstream_http2 = proxy_config.upstream_http2;
+                                ctx.upstream_http2_fallback = proxy_config.upstream_http2_fallback;
+
+                                // Store mTLS configuration for upstream connections
+                                ctx.upstream_mtls = proxy_config.upstream_mtls.clone();
+
+                                // Pre-compressed or latency-sensitive routes skip compression and its buffering
+                                ctx.compress = proxy_config.compress;
+
+                                // HEAD answered from an upstream GET: headers as-is, body neither compressed nor cached
+                                ctx.head_as_get = proxy_config.head_as_get && method == "HEAD";
+                                if ctx.head_as_get {
+                                    ctx.compress = false;
+                                    ctx.cache_key = None;
+                                    ctx.cache_status = self.cache.as_ref().map(|_| CacheStatus::Bypass);
+                                }
+
+                                ctx.cache_policy = CachePolicy {
+                                    ttl_override: proxy_config.cache_ttl_override.map(Duration::from_secs),
+                                    respect_cache_control: proxy_config.respect_upstream_cache_control,
+                                };
+
+                                // Answer for the route while in maintenance unless a preview token is presented
+                                let mut skip_auth = false;
+                                if let Some(maintenance) = route.maintenance.as_ref().filter(|m| m.is_enabled()) {
+                                    let cookies = session.req_header().headers.get("cookie").and_then(|v| v.to_str().ok());
+                                    match maintenance.bypass(session.req_header().uri.query(), cookies) {
+                                        Some(bypass) => {
+                                            debug!(path = %path, "Maintenance bypassed with preview token");
+                                            if let MaintenanceBypass::Query(cookie) = bypass {
+                                                ctx.maintenance_cookie = Some(cookie);
                                             }
+                                            skip_auth = maintenance.bypasses_auth();
+                                            // Previews see the upstream as it is now, and
+                                            // aren't served to anyone else from the cache
+                                            ctx.maintenance_bypass = Some(maintenance.clone());
+                                            ctx.cache_key = None;
+                                            ctx.cache_status = self.cache.as_ref().map(|_| CacheStatus::Bypass);
+                                        }
+                                        None => return self.send_maintenance_response(session, maintenance).await,
+                                    }
+                                }
+
+                                // Rate limit by client IP before any of the body is read
+                                if let Some(limiter) = &route.rate_limiter {
+                                    let client_ip = session.client_addr().and_then(|a| a.as_inet()).map(|a| a.ip());
+                                    if let Some(ip) = client_ip {
+                                        if let RateLimitResult::Denied { retry_after_secs } = check_rate_limit(limiter, ip) {
+                                            warn!(client_ip = %ip, path = %path, "Rate limit exceeded");
+                                            metrics().rate_limit_rejections.inc();
+                                            return self.send_rate_limited_response(session, limiter.rejection(), retry_after_secs).await;
                                         }
                                     }
+                                }
 
-                                    // Store retry configuration
-                                    ctx.lb_try_duration = proxy_config.lb_try_duration;
-                                    ctx.lb_try_interval = proxy_config.lb_try_interval;
-                                    ctx.upstream_selector = Some(upstream_selector.clone());
-                                    if proxy_config.lb_try_duration > 0 {
-                                        ctx.retry_deadline = Some(Instant::now() + Duration::from_millis(proxy_config.lb_try_duration));
+                                // Check request body size limit
+                                if ctx.max_request_body_size > 0 {
+                                    if let Some(content_length) = session.req_header().headers
+                                        .get("content-length")
+                                        .and_then(|v| v.to_str().ok())
+                                        .and_then(|s| s.parse::<u64>().ok())
+                                    {
+                                        if content_length > ctx.max_request_body_size {
+                                            warn!(
+                                                content_length = content_length,
+                                                max_size = ctx.max_request_body_size,
+                                                "Request body too large"
+                                            );
+                                            return self.send_error_response(session, 413, "Payload Too Large").await;
+                                        }
                                     }
+                                }
 
-                                    // Store timeout configuration for connection pool
-                                    ctx.timeouts = Some(proxy_config.timeouts.clone());
-
-                                    // Store max request body size for size limiting
-                                    ctx.max_request_body_size = proxy_config.max_request_body_size;
-
-                                    // Store HTTP/2 upstream configuration
-                                    ctx.upstream_http2 = proxy_config.upstream_http2;
-
-                                    // Store mTLS configuration for upstream connections
-                                    ctx.upstream_mtls = proxy_config.upstream_mtls.clone();
-
-                                    // Check request body size limit
-                                    if ctx.max_request_body_size > 0 {
-                                        if let Some(content_length) = session.req_header().headers
-                                            .get("content-length")
-                                            .and_then(|v| v.to_str().ok())
-                                            .and_then(|s| s.parse::<u64>().ok())
-                                        {
-                                            if content_length > ctx.max_request_body_size {
-                                                warn!(
-                                                    content_length = content_length,
-                                                    max_size = ctx.max_request_body_size,
-                                                    "Request body too large"
-                                                );
-                                                return self.send_error_response(session, 413, "Payload Too Large").await;
+                                // Check authentication if configured
+                                if let Some(auth) = ctx.auth.as_ref().filter(|_| !skip_auth) {
+                                    let query = session.req_header().uri.query();
+                                    let result = auth.authenticate(&session.req_header().headers, query, path);
+
+                                    match result {
+                                        AuthResult::Authenticated { identity } => {
+                                            if let Some(id) = identity {
+                                                debug!(identity = %id, "Request authenticated");
                                             }
                                         }
+                                        AuthResult::Denied { reason, request_auth, realm } => {
+                                            warn!(reason = %reason, path = %path, "Authentication denied");
+                                            return self.send_auth_response(session, request_auth, realm.as_deref()).await;
+                                        }
+                                        AuthResult::NotRequired => {
+                                            // Path is excluded from auth, continue
+                                        }
                                     }
+                                }
 
-                                    // Check authentication if configured
-                                    if let Some(auth) = &ctx.auth {
-                                        // Extract auth info from request
-                                        let headers = &session.req_header().headers;
-                                        let auth_header = headers
-                                            .get("authorization")
-                                            .and_then(|v| v.to_str().ok());
-                                        let api_key_header = headers
-                                            .get("x-api-key")
-                                            .and_then(|v| v.to_str().ok());
-                                        let query = session.req_header().uri.query();
+                                // Only the auth service sets its headers, so client-sent copies
+                                // never reach the upstream, even when it isn't asked
+                                if let Some(forward_auth) = &route.forward_auth {
+                                    let removed = forward_auth.copy_headers().iter().map(|name| (format!("-{}", name), String::new()));
+                                    ctx.custom_headers_up.extend(removed);
+                                }
+
+                                // Ask the external auth service, if any
+                                if let Some(forward_auth) = route.forward_auth.as_ref().filter(|f| !skip_auth && !f.is_path_excluded(path)) {
+                                    let is_tls = session.digest().map(|d| d.ssl_digest.is_some()).unwrap_or(false);
+                                    let client_ip = session.client_addr().and_then(|a| a.as_inet()).map(|a| a.ip().to_string());
+                                    let req = session.req_header();
+                                    let uri = req.uri.path_and_query().map(|p| p.as_str()).unwrap_or(path);
+                                    let check = forward_auth.check(&ForwardedRequest {
+                                        method,
+                                        scheme: if is_tls { "https" } else { "http" },
+                                        host,
+                                        uri,
+                                        client_ip: client_ip.as_deref(),
+                                        headers: &req.headers,
+                                    });
+                                    let outcome = match ctx.deadline.run(RequestStage::Auth, check).await {
+                                        Ok(outcome) => outcome,
+                                        Err(exceeded) => return Err(self.request_timed_out(ctx, exceeded)),
+                                    };
+
+                                    match outcome {
+                                        ForwardAuthOutcome::Allowed(headers) => ctx.custom_headers_up.extend(headers),
+                                        ForwardAuthOutcome::Denied { status, headers, body } => {
+                                            warn!(status = status, path = %path, "Forward auth denied");
+                                            return self.send_forward_auth_denial(session, status, headers, body).await;
+                                        }
+                                        ForwardAuthOutcome::Unavailable => {
+                                            return self.send_error_response(session, 503, "Service Unavailable").await;
+                                        }
+                                    }
+                                }
 
-                                        let result = auth.authenticate(auth_header, api_key_header, query, path);
+                                // Only requests that got past the checks above see the cache
+                                self.scope_cache_key(ctx, route);
+                                if let Some(cached) = self.lookup_cache(ctx) {
+                                    return self.send_cached_response(session, ctx, &cached, CacheStatus::Hit).await;
+                                }
 
-                                        match result {
-                                            AuthResult::Authenticated { identity } => {
-                                                if let Some(id) = identity {
-                                                    debug!(identity = %id, "Request authenticated");
-                                                }
+                                // Replay or coalesce requests carrying an idempotency key
+                                if let Some(idempotency) = &route.idempotency {
+                                    let idempotency_key = session.req_header().headers
+                                        .get(idempotency.header())
+                                        .and_then(|v| v.to_str().ok())
+                                        .filter(|_| idempotency.applies_to(method))
+                                        .map(|v| v.to_string());
+
+                                    if let Some(idempotency_key) = idempotency_key {
+                                        let key = IdempotencyCache::key(method, host.unwrap_or(""), path, &idempotency_key);
+                                        match idempotency.begin(&key) {
+                                            IdempotencyOutcome::Replay(stored) => {
+                                                debug!(key = %idempotency_key, "Replaying stored idempotent response");
+                                                return self.send_replayed_response(session, ctx, &stored).await;
                                             }
-                                            AuthResult::Denied { reason, request_auth, realm } => {
-                                                warn!(reason = %reason, path = %path, "Authentication denied");
-                                                return self.send_auth_response(session, request_auth, realm.as_deref()).await;
+                                            IdempotencyOutcome::Pending(pending) => {
+                                                debug!(key = %idempotency_key, "Waiting for in-flight idempotent request");
+                                                match idempotency.wait(&key, pending).await {
+                                                    WaitOutcome::Replay(stored) => return self.send_replayed_response(session, ctx, &stored).await,
+                                                    WaitOutcome::Failed => return self.send_error_response(session, 409, "Conflict").await,
+                                                    // Too large to replay; this request is forwarded too
+                                                    WaitOutcome::Forward => {
+                                                        debug!(key = %idempotency_key, "Idempotent response not stored, forwarding request");
+                                                    }
+                                                }
                                             }
-                                            AuthResult::NotRequired => {
-                                                // Path is excluded from auth, continue
+                                            IdempotencyOutcome::Execute => {
+                                                ctx.idempotency = Some((idempotency.clone(), key));
+                                                ctx.request_digest = Some(RequestDigest::default());
                                             }
                                         }
                                     }
+                                }
 
-                                    for (key, value) in &proxy_config.headers_down {
-                                        ctx.custom_headers_down.push((key.clone(), value.clone()));
-                                    }
+                                ctx.forwarded_for = proxy_config.x_forwarded_for;
+                                ctx.host_header = proxy_config.host_header.clone();
+
+                                for (key, value) in &proxy_config.headers_up {
+                                    ctx.custom_headers_up.push((key.clone(), value.clone()));
+                                }
 
-                                    return Ok(false);
+                                for (key, value) in &proxy_config.headers_down {
+                                    ctx.custom_headers_down.push((key.clone(), value.clone()));
                                 }
-                                Err(e) => {
-                                    warn!(error = %e, "Failed to select upstream");
-                                    return self.send_error_response(session, 502, "Bad Gateway").await;
+
+                                if let Err(exceeded) = ctx.deadline.check(RequestStage::RequestFilter) {
+                                    return Err(self.request_timed_out(ctx, exceeded));
                                 }
+
+                                return Ok(false);
+                            }
+                            Err(e) => {
+                                warn!(error = %e, "Failed to select upstream");
+                                return self.send_error_response(session, 502, "Bad Gateway").await;
                             }
                         }
                     }
-                    HandlerConfig::StaticResponse(config) => {
-                        let status = config.status;
-                        let headers: Vec<(String, String)> = config.headers.iter()
-                            .map(|(k, v)| (k.clone(), v.clone()))
-                            .collect();
-                        let body = config.body.clone();
+                }
+                HandlerConfig::StaticResponse(config) => {
+                    self.scope_cache_key(ctx, route);
+                    if let Some(cached) = self.lookup_cache(ctx) {
+                        return self.send_cached_response(session, ctx, &cached, CacheStatus::Hit).await;
+                    }
 
-                        let mut header = ResponseHeader::build(
-                            StatusCode::from_u16(status).unwrap_or(StatusCode::OK),
-                            None,
-                        )?;
+                    if let Err(exceeded) = ctx.deadline.run(RequestStage::RequestFilter, simulate_latency(config)).await {
+                        return Err(self.request_timed_out(ctx, exceeded));
+                    }
 
-                        for (key, value) in headers {
-                            header.insert_header(key, value)?;
-                        }
+                    // Served like a fetched response: stored decoded, compressed per client
+                    let response = CachedResponse {
+                        status: StatusCode::from_u16(config.status).unwrap_or(StatusCode::OK),
+                        headers: config.headers.iter().map(|(k, v)| (k.clone(), v.clone())).collect(),
+                        body: route.static_body.clone().unwrap_or_default(),
+                        cached_at: Instant::now(),
+                        ttl: Duration::ZERO,
+                        etag: None,
+                        last_modified: None,
+                    };
+                    let cache_status = if self.store_in_cache(ctx, &response.headers, &response.body, response.status) {
+                        CacheStatus::Miss
+                    } else {
+                        CacheStatus::Bypass
+                    };
+                    return self.send_cached_response(session, ctx, &response, cache_status).await;
+                }
+                HandlerConfig::Redirect(config) => {
+                    let code = config.code;
+                    let location = config.to.clone();
 
-                        if !body.is_empty() {
-                            header.insert_header("Content-Length", body.len().to_string())?;
-                        }
+                    let status = StatusCode::from_u16(code).unwrap_or(StatusCode::FOUND);
+                    let mut header = ResponseHeader::build(status, None)?;
+                    header.insert_header("Location", location)?;
+                    // RFC 7230: Redirect responses should include Content-Length: 0
+                    header.insert_header("Content-Length", "0")?;
 
-                        session.write_response_header(Box::new(header), body.is_empty()).await?;
-                        if !body.is_empty() {
-                            session.write_response_body(Some(body.into()), true).await?;
-                        }
+                    session.write_response_header(Box::new(header), true).await?;
+                    return Ok(true);
+                }
+                HandlerConfig::FileServer(config) => {
+                    let file_server = FileServer::new(&config.root)
+                        .with_browse(config.browse)
+                        .with_index_files(config.index.clone());
+
+                    // Use serve_request to validate HTTP method
+                    let serving = file_server.serve_request(method, path);
+                    let response = match self.handler_timeout() {
+                        Some(timeout) => match tokio::time::timeout(timeout, serving).await {
+                            Ok(response) => response,
+                            Err(_) => {
+                                self.handler_timed_out("file_server", host, path, timeout);
+                                return self.send_error_response(session, 504, "Gateway Timeout").await;
+                            }
+                        },
+                        None => serving.await,
+                    };
 
-                        return Ok(true);
+                    let mut header = ResponseHeader::build(response.status, None)?;
+                    header.insert_header("Content-Type", response.content_type.clone())?;
+                    header.insert_header("Server", "avalon")?;
+
+                    // Add response headers from file server (ETag, Last-Modified, etc.)
+                    for (key, value) in &response.headers {
+                        header.insert_header(key.clone(), value.clone())?;
                     }
-                    HandlerConfig::Redirect(config) => {
-                        let code = config.code;
-                        let location = config.to.clone();
 
-                        let status = StatusCode::from_u16(code).unwrap_or(StatusCode::FOUND);
-                        let mut header = ResponseHeader::build(status, None)?;
-                        header.insert_header("Location", location)?;
-                        // RFC 7230: Redirect responses should include Content-Length: 0
-                        header.insert_header("Content-Length", "0")?;
+                    // Check if compression is enabled and applicable
+                    let should_compress = config.compress
+                        && ctx.compression_encoding != CompressionEncoding::Identity
+                        && should_compress_content_type(Some(&response.content_type))
+                        && response.body.len() >= self.compression_config.min_size
+                        && response.status == StatusCode::OK;
+
+                    // RFC 7231: Add Vary: Accept-Encoding for compressible content types
+                    if should_compress_content_type(Some(&response.content_type))
+                        && ctx.compression_encoding != CompressionEncoding::Identity
+                    {
+                        merge_vary_header(&mut header, "Accept-Encoding")?;
+                    }
 
+                    // For HEAD requests, don't send body but include Content-Length
+                    let is_head = method == "HEAD";
+                    if is_head {
+                        header.insert_header("Content-Length", response.body.len().to_string())?;
                         session.write_response_header(Box::new(header), true).await?;
-                        return Ok(true);
+                    } else if should_compress {
+                        let body = finalize_buffered_response(
+                            &mut header,
+                            response.body,
+                            ctx.compression_encoding,
+                            ContentKind::Static,
+                            &self.compression_config,
+                        )?;
+                        session.write_response_header(Box::new(header), body.is_empty()).await?;
+                        if !body.is_empty() {
+                            session.write_response_body(Some(body), true).await?;
+                        }
+                    } else {
+                        header.insert_header("Content-Length", response.body.len().to_string())?;
+                        session.write_response_header(Box::new(header), response.body.is_empty()).await?;
+                        if !response.body.is_empty() {
+                            session.write_response_body(Some(response.body), true).await?;
+                        }
                     }
-                    HandlerConfig::FileServer(config) => {
-                        let file_server = FileServer::new(&config.root)
-                            .with_browse(config.browse)
-                            .with_index_files(config.index.clone());
-
-                        // Use serve_request to validate HTTP method
-                        let response = file_server.serve_request(method, path).await;
 
-                        let mut header = ResponseHeader::build(response.status, None)?;
-                        header.insert_header("Content-Type", response.content_type.clone())?;
-                        header.insert_header("Server", "avalon")?;
+                    return Ok(true);
+                }
+                HandlerConfig::Script(_) => {
+                    // Handle script handler using compiled handler from route
+                    if let Some(script_handler) = &route.script_handler {
+                        // Build request context
+                        let headers: std::collections::HashMap<String, String> = session
+                            .req_header()
+                            .headers
+                            .iter()
+                            .map(|(k, v)| (k.as_str().to_string(), v.to_str().unwrap_or("").to_string()))
+                            .collect();
 
-                        // Add response headers from file server (ETag, Last-Modified, etc.)
-                        for (key, value) in &response.headers {
-                            header.insert_header(key.clone(), value.clone())?;
-                        }
+                        let client_ip = session.client_addr().map(|a| {
+                            let s = a.to_string();
+                            s.split(':').next().unwrap_or(&s).to_string()
+                        });
 
-                        // Check if compression is enabled and applicable
-                        let should_compress = config.compress
-                            && ctx.compression_encoding != CompressionEncoding::Identity
-                            && should_compress_content_type(Some(&response.content_type))
-                            && response.body.len() >= self.compression_config.min_size
-                            && response.status == StatusCode::OK;
+                        let query = session.req_header().uri.query();
 
-                        // RFC 7231: Add Vary: Accept-Encoding for compressible content types
-                        if should_compress_content_type(Some(&response.content_type))
-                            && ctx.compression_encoding != CompressionEncoding::Identity
-                        {
-                            merge_vary_header(&mut header, "Accept-Encoding")?;
-                        }
+                        let script_ctx = ScriptRequestContext::new(
+                            method,
+                            path,
+                            query,
+                            host,
+                            client_ip.as_deref(),
+                            headers,
+                        );
 
-                        // For HEAD requests, don't send body but include Content-Length
-                        let is_head = method == "HEAD";
-                        if is_head {
-                            header.insert_header("Content-Length", response.body.len().to_string())?;
-                            session.write_response_header(Box::new(header), true).await?;
-                        } else if should_compress {
-                            // Compress the response body
-                            match compress(&response.body, ctx.compression_encoding, self.compression_config.level) {
-                                Ok(compressed) => {
-                                    header.insert_header("Content-Encoding", ctx.compression_encoding.header_value())?;
-                                    header.insert_header("Content-Length", compressed.len().to_string())?;
-                                    session.write_response_header(Box::new(header), false).await?;
-                                    session.write_response_body(Some(compressed), true).await?;
-                                }
-                                Err(_) => {
-                                    // Compression failed, send uncompressed
-                                    header.insert_header("Content-Length", response.body.len().to_string())?;
-                                    session.write_response_header(Box::new(header), response.body.is_empty()).await?;
-                                    if !response.body.is_empty() {
-                                        session.write_response_body(Some(response.body), true).await?;
-                                    }
-                                }
+                        // Execute script
+                        let timeout = self.handler_timeout();
+                        match script_handler.execute_within(&script_ctx, timeout) {
+                            Ok(result) => {
+                                return self.handle_script_result(session, result).await;
                             }
-                        } else {
-                            header.insert_header("Content-Length", response.body.len().to_string())?;
-                            session.write_response_header(Box::new(header), response.body.is_empty()).await?;
-                            if !response.body.is_empty() {
-                                session.write_response_body(Some(response.body), true).await?;
+                            Err(ScriptHandlerError::Timeout) => {
+                                self.handler_timed_out("script", host, path, timeout.unwrap_or_default());
+                                return self.send_error_response(session, 504, "Gateway Timeout").await;
                             }
-                        }
-
-                        return Ok(true);
-                    }
-                    HandlerConfig::Script(_) => {
-                        // Handle script handler using compiled handler from route
-                        if let Some(script_handler) = &route.script_handler {
-                            // Build request context
-                            let headers: std::collections::HashMap<String, String> = session
-                                .req_header()
-                                .headers
-                                .iter()
-                                .map(|(k, v)| (k.as_str().to_string(), v.to_str().unwrap_or("").to_string()))
-                                .collect();
-
-                            let client_ip = session.client_addr().map(|a| {
-                                let s = a.to_string();
-                                s.split(':').next().unwrap_or(&s).to_string()
-                            });
-
-                            let query = session.req_header().uri.query();
-
-                            let script_ctx = ScriptRequestContext::new(
-                                method,
-                                path,
-                                query,
-                                host,
-                                client_ip.as_deref(),
-                                headers,
-                            );
-
-                            // Execute script
-                            match script_handler.execute(&script_ctx) {
-                                Ok(result) => {
-                                    return self.handle_script_result(session, result).await;
-                                }
-                                Err(e) => {
-                                    warn!(error = %e, "Script execution failed");
-                                    return self.send_error_response(session, 500, "Script Error").await;
-                                }
+                            Err(e) => {
+                                warn!(error = %e, "Script execution failed");
+                                return self.send_error_response(session, 500, "Script Error").await;
                             }
-                        } else {
-                            warn!("Script handler not compiled");
-                            return self.send_error_response(session, 500, "Script Not Compiled").await;
                         }
+                    } else {
+                        warn!("Script handler not compiled");
+                        return self.send_error_response(session, 500, "Script Not Compiled").await;
                     }
                 }
             }
@@ -921,10 +1569,18 @@ impl ProxyHttp for AvalonProxy {
     }
 
     async fn upstream_peer(&self, _session: &mut Session, ctx: &mut Self::CTX) -> Result<Box<HttpPeer>> {
+        if let Some(delay) = ctx.retry_delay.take() {
+            tokio::time::sleep(delay).await;
+        }
+
         let upstream = ctx.upstream.as_ref().ok_or_else(|| {
             pingora_core::Error::new(pingora_core::ErrorType::ConnectProxyFailure)
         })?;
 
+        if let Err(exceeded) = ctx.deadline.check(RequestStage::Upstream) {
+            return Err(self.request_timed_out(ctx, exceeded));
+        }
+
         upstream.increment_connections();
 
         let mut peer = HttpPeer::new(
@@ -935,48 +1591,67 @@ impl ProxyHttp for AvalonProxy {
 
         // Apply timeout and connection pool configuration
         if let Some(ref timeouts) = ctx.timeouts {
-            // Set connection timeout
-            peer.options.connection_timeout = Some(Duration::from_secs(timeouts.connect));
+            // Upgraded WebSocket connections use their own read/write/keepalive settings
+            let resolved = PeerTimeouts::from_config(timeouts, ctx.is_websocket);
 
-            // Set read timeout
-            peer.options.read_timeout = Some(Duration::from_secs(timeouts.read));
-
-            // Set write timeout
-            peer.options.write_timeout = Some(Duration::from_secs(timeouts.write));
+            peer.options.connection_timeout = Some(resolved.connect);
+            peer.options.read_timeout = resolved.read;
+            peer.options.write_timeout = resolved.write;
 
             // Set idle timeout for connection pool
-            peer.options.idle_timeout = Some(Duration::from_secs(timeouts.idle));
+            peer.options.idle_timeout = Some(resolved.idle);
+
+            // Protect fragile upstreams from long-lived or multiplexed connections
+            apply_h2_multiplexing(&mut peer, resolved.h2_multiplexing);
+            ctx.max_upstream_requests = resolved.max_requests;
 
             // Configure TCP keepalive if enabled
-            if timeouts.keepalive {
+            if let Some((interval, count)) = resolved.keepalive {
                 peer.options.tcp_keepalive = Some(pingora_core::protocols::TcpKeepalive {
-                    idle: Duration::from_secs(timeouts.keepalive_interval),
-                    interval: Duration::from_secs(timeouts.keepalive_interval),
-                    count: timeouts.keepalive_count as usize,
+                    idle: interval,
+                    interval,
+                    count,
                     #[cfg(target_os = "linux")]
                     user_timeout: Duration::from_secs(0),
                 });
             }
 
             debug!(
-                connect_timeout = timeouts.connect,
-                read_timeout = timeouts.read,
-                write_timeout = timeouts.write,
-                idle_timeout = timeouts.idle,
-                keepalive = timeouts.keepalive,
+                connect_timeout = ?resolved.connect,
+                read_timeout = ?resolved.read,
+                write_timeout = ?resolved.write,
+                idle_timeout = ?resolved.idle,
+                keepalive = resolved.keepalive.is_some(),
+                max_requests = resolved.max_requests,
+                h2_multiplexing = resolved.h2_multiplexing,
+                websocket = ctx.is_websocket,
                 "Applied connection pool configuration"
             );
         }
 
+        // Upstream waits end by the overall request deadline
+        if ctx.deadline.remaining().is_some() {
+            peer.options.connection_timeout = ctx.deadline.cap(peer.options.connection_timeout);
+            peer.options.total_connection_timeout = ctx.deadline.cap(peer.options.total_connection_timeout);
+            peer.options.read_timeout = ctx.deadline.cap(peer.options.read_timeout);
+            peer.options.write_timeout = ctx.deadline.cap(peer.options.write_timeout);
+        }
+
         // Configure HTTP/2 upstream via ALPN if enabled (requires TLS)
         if ctx.upstream_http2 && upstream.use_tls {
-            peer.options.alpn = pingora_core::protocols::ALPN::H2;
-            debug!(upstream = %upstream.address_str, "Using HTTP/2 for upstream connection");
+            peer.options.alpn = crate::upstream::upstream_alpn(true, ctx.upstream_http2_fallback);
+            debug!(
+                upstream = %upstream.address_str,
+                fallback = ctx.upstream_http2_fallback,
+                "Offering HTTP/2 for upstream connection"
+            );
         }
 
         // Configure mTLS (mutual TLS) for upstream connections if enabled
         if let Some(ref mtls_config) = ctx.upstream_mtls {
             if upstream.use_tls {
+                crate::upstream::apply_tls_verification(&mut peer.options, mtls_config);
+
                 // Load client certificate and key
                 match crate::upstream::load_mtls_connector(mtls_config) {
                     Ok(cert_key) => {
@@ -1007,12 +1682,96 @@ impl ProxyHttp for AvalonProxy {
         Ok(Box::new(peer))
     }
 
+    async fn connected_to_upstream(
+        &self,
+        _session: &mut Session,
+        reused: bool,
+        _peer: &HttpPeer,
+        fd: std::os::unix::io::RawFd,
+        _digest: Option<&pingora_core::protocols::Digest>,
+        ctx: &mut Self::CTX,
+    ) -> Result<()> {
+        // Counted once the request shows which protocol ALPN settled on
+        ctx.close_upstream_connection = false;
+        ctx.upstream_connection = (ctx.max_upstream_requests > 0).then_some((fd, reused));
+        Ok(())
+    }
+
+    async fn request_body_filter(
+        &self,
+        _session: &mut Session,
+        body: &mut Option<Bytes>,
+        _end_of_stream: bool,
+        ctx: &mut Self::CTX,
+    ) -> Result<()>
+    where
+        Self::CTX: Send + Sync,
+    {
+        if let Err(exceeded) = ctx.deadline.check(RequestStage::RequestBody) {
+            return Err(self.request_timed_out(ctx, exceeded));
+        }
+
+        // Bodies stream straight through; only their size and digest are tracked
+        if let Some(chunk) = body {
+            if let Err(received) = ctx.request_body_limit.observe(chunk.len()) {
+                warn!(
+                    received = received,
+                    max_size = ctx.max_request_body_size,
+                    "Streamed request body too large"
+                );
+                return Err(pingora_core::Error::new(pingora_core::ErrorType::HTTPStatus(413)));
+            }
+            if let Some(digest) = &mut ctx.request_digest {
+                digest.update(chunk);
+            }
+        }
+        Ok(())
+    }
+
     async fn upstream_request_filter(
         &self,
         session: &mut Session,
         upstream_request: &mut RequestHeader,
         ctx: &mut Self::CTX,
     ) -> Result<()> {
+        // HTTP/2 connections are limited by their stream count instead; an
+        // h2 offer that fell back to HTTP/1.1 is counted like any HTTP/1.1 one
+        if let Some((fd, reused)) = ctx.upstream_connection.take() {
+            if upstream_request.version != http::Version::HTTP_2 {
+                ctx.close_upstream_connection = self.connection_requests.record(fd, reused, ctx.max_upstream_requests);
+                if ctx.close_upstream_connection {
+                    debug!(max_requests = ctx.max_upstream_requests, "Closing upstream connection after its last request");
+                }
+            }
+        }
+
+        if ctx.head_as_get {
+            head::translate_request(upstream_request);
+        }
+
+        // The preview token is for the proxy, not the upstream
+        if let Some(maintenance) = &ctx.maintenance_bypass {
+            if let Some(query) = upstream_request.uri.query() {
+                let path = upstream_request.uri.path();
+                let uri = match maintenance.strip_query(query) {
+                    Some(query) => format!("{}?{}", path, query),
+                    None => path.to_string(),
+                };
+                if let Ok(uri) = uri.parse() {
+                    upstream_request.set_uri(uri);
+                }
+            }
+
+            let cookies: Vec<&str> = upstream_request.headers.get_all("cookie").iter().filter_map(|v| v.to_str().ok()).collect();
+            if !cookies.is_empty() {
+                let stripped = maintenance.strip_cookie(&cookies.join("; "));
+                upstream_request.remove_header("cookie");
+                if let Some(cookies) = stripped {
+                    upstream_request.insert_header("Cookie", cookies)?;
+                }
+            }
+        }
+
         // For WebSocket requests, ensure upgrade headers are preserved
         if ctx.is_websocket {
             // Collect WebSocket headers into owned strings to avoid lifetime issues
@@ -1057,6 +1816,9 @@ impl ProxyHttp for AvalonProxy {
 
         // Apply path rewriting if configured
         if let Some(rewrite) = &ctx.rewrite {
+            // Captures match the client's path, so fill them in before it is rewritten
+            rewrite.apply_capture_headers(upstream_request)?;
+
             if rewrite.has_path_rewrite() {
                 let original_uri = upstream_request.uri.to_string();
                 let new_uri = crate::rewrite::rewrite_uri(&original_uri, rewrite);
@@ -1179,16 +1941,28 @@ impl ProxyHttp for AvalonProxy {
             }
         }
 
+        let peer_trusted = match &ctx.trusted_proxies {
+            Some(trusted) => session
+                .client_addr()
+                .and_then(|a| parse_client_ip(None, None, Some(&a.to_string())))
+                .is_some_and(|ip| trusted.is_allowed(&ip)),
+            None => false,
+        };
+
         // Add X-Forwarded-For and X-Real-IP
         if let Some(client_addr) = session.client_addr() {
             let client_ip = client_addr.to_string();
             let client_ip = client_ip.split(':').next().unwrap_or(&client_ip);
 
-            if let Some(existing) = upstream_request.headers.get("x-forwarded-for") {
-                let new_value = format!("{}, {}", existing.to_str().unwrap_or(""), client_ip);
-                upstream_request.insert_header("X-Forwarded-For", new_value)?;
-            } else {
-                upstream_request.insert_header("X-Forwarded-For", client_ip)?;
+            let existing = upstream_request
+                .headers
+                .get("x-forwarded-for")
+                .and_then(|v| v.to_str().ok());
+            match forwarded_for(ctx.forwarded_for, existing, client_ip, peer_trusted) {
+                Some(value) => upstream_request.insert_header("X-Forwarded-For", value)?,
+                None => {
+                    upstream_request.remove_header("x-forwarded-for");
+                }
             }
 
             upstream_request.insert_header("X-Real-IP", client_ip)?;
@@ -1205,12 +1979,48 @@ impl ProxyHttp for AvalonProxy {
             .digest()
             .map(|d| d.ssl_digest.is_some())
             .unwrap_or(false);
-        let proto = if client_is_tls { "https" } else { "http" };
+        let existing_proto = upstream_request
+            .headers
+            .get("x-forwarded-proto")
+            .and_then(|v| v.to_str().ok());
+        let proto = forwarded_proto(client_is_tls, existing_proto, peer_trusted);
         upstream_request.insert_header("X-Forwarded-Proto", proto)?;
 
-        // Add custom upstream headers from config
-        if let Some(HandlerType::ReverseProxy) = &ctx.handler_type {
-            // Headers are already stored in ctx from request_filter
+        if let Some(nonce) = &ctx.csp_nonce {
+            nonce.add_to_request(upstream_request)?;
+        }
+        if let Some(correlation) = &ctx.correlation {
+            correlation.add_to_request(upstream_request)?;
+        }
+
+        if let Some(upstream) = &ctx.upstream {
+            apply_host_header(upstream_request, &ctx.host_header, upstream)?;
+        }
+
+        // Apply headers_up last so config can override the forwarding headers
+        apply_headers_up(upstream_request, &ctx.custom_headers_up)?;
+
+        // Upstream credentials replace whatever the client sent
+        if let Some(upstream_auth) = &ctx.upstream_auth {
+            upstream_auth.apply(upstream_request)?;
+        }
+
+        // Ask whether an expired entry is still current, unless the client
+        // made the request conditional itself and gets the answer as is
+        if let Some(stale) = &ctx.stale_cache_entry {
+            let client_conditional = upstream_request.headers.contains_key("if-none-match")
+                || upstream_request.headers.contains_key("if-modified-since");
+            if !client_conditional {
+                for (name, value) in stale.conditional_headers() {
+                    upstream_request.insert_header(name, value)?;
+                }
+                ctx.revalidating = true;
+            }
+        }
+
+        // The connection served its last request; the upstream closes it
+        if ctx.close_upstream_connection {
+            upstream_request.insert_header("Connection", "close")?;
         }
 
         Ok(())
@@ -1223,12 +2033,27 @@ impl ProxyHttp for AvalonProxy {
         ctx: &mut Self::CTX,
         mut e: Box<pingora_core::Error>,
     ) -> Box<pingora_core::Error> {
+        let tls_failure = UpstreamTlsFailure::classify(&e);
+        if let Some(failure) = tls_failure {
+            metrics().upstream_tls_errors.inc(failure.as_str());
+        }
+
         // Add failed upstream to tried list
         if let Some(upstream) = ctx.upstream.take() {
-            warn!(upstream = %upstream.address_str, error = %e, "Failed to connect");
+            match tls_failure {
+                Some(failure) => {
+                    warn!(upstream = %upstream.address_str, reason = %failure, error = %e, "Upstream TLS handshake failed")
+                }
+                None => warn!(upstream = %upstream.address_str, error = %e, "Failed to connect"),
+            }
+            upstream.record_response(false);
             ctx.tried_upstreams.push(upstream);
         }
 
+        if let Err(exceeded) = ctx.deadline.check(RequestStage::Upstream) {
+            return self.request_timed_out(ctx, exceeded);
+        }
+
         // Check if retry is enabled and we're within the deadline
         if ctx.lb_try_duration > 0 {
             if let Some(deadline) = ctx.retry_deadline {
@@ -1243,6 +2068,7 @@ impl ProxyHttp for AvalonProxy {
                                     "Retrying with different upstream"
                                 );
                                 ctx.upstream = Some(new_upstream);
+                                ctx.schedule_retry();
                                 e.set_retry(true);
                             }
                             Err(err) => {
@@ -1266,30 +2092,248 @@ impl ProxyHttp for AvalonProxy {
         e
     }
 
+    async fn fail_to_proxy(&self, session: &mut Session, e: &pingora_core::Error, ctx: &mut Self::CTX) -> FailToProxy
+    where
+        Self::CTX: Send + Sync,
+    {
+        if let Some(revalidated) = ctx.revalidated.take() {
+            let sent = self.send_cached_response(session, ctx, &revalidated, CacheStatus::Revalidated).await;
+            if let Err(err) = &sent {
+                warn!(error = %err, "Failed to send revalidated cache entry");
+            }
+            return FailToProxy {
+                error_code: revalidated.status.as_u16(),
+                can_reuse_downstream: sent.is_ok(),
+            };
+        }
+
+        // An expired entry the upstream couldn't be asked about, under stale_if_error
+        let stale_if_error = self.cache.as_ref().is_some_and(|c| c.stale_if_error());
+        if stale_if_error && *e.esource() == pingora_core::ErrorSource::Upstream && session.response_written().is_none() {
+            if let Some(stale) = ctx.stale_cache_entry.take() {
+                debug!(error = %e, "Serving stale cache entry after failed revalidation");
+                let sent = self.send_cached_response(session, ctx, &stale, CacheStatus::Stale).await;
+                if let Err(err) = &sent {
+                    warn!(error = %err, "Failed to send stale cache entry");
+                }
+                return FailToProxy {
+                    error_code: stale.status.as_u16(),
+                    can_reuse_downstream: false,
+                };
+            }
+        }
+
+        // Say why the upstream handshake failed instead of a bare 502
+        if let Some(failure) = UpstreamTlsFailure::classify(e) {
+            if let Err(err) = self.send_error_response(session, 502, failure.message()).await {
+                warn!(error = %err, "Failed to send upstream TLS error response");
+            }
+            return FailToProxy {
+                error_code: 502,
+                can_reuse_downstream: false,
+            };
+        }
+
+        // Same mapping as Pingora's default
+        let code = match e.etype() {
+            pingora_core::ErrorType::HTTPStatus(code) => *code,
+            etype => match e.esource() {
+                pingora_core::ErrorSource::Upstream => 502,
+                pingora_core::ErrorSource::Downstream => match etype {
+                    pingora_core::ErrorType::WriteError
+                    | pingora_core::ErrorType::ReadError
+                    | pingora_core::ErrorType::ConnectionClosed => 0,
+                    _ => 400,
+                },
+                pingora_core::ErrorSource::Internal | pingora_core::ErrorSource::Unset => 500,
+            },
+        };
+        if code > 0 {
+            if let Err(err) = session.respond_error(code).await {
+                error!(error = %err, "Failed to send error response to downstream");
+            }
+        }
+        FailToProxy {
+            error_code: code,
+            can_reuse_downstream: false,
+        }
+    }
+
+    fn suppress_error_log(&self, _session: &Session, _ctx: &Self::CTX, error: &pingora_core::Error) -> bool {
+        // Logged as a client cancellation by `logging` instead; a revalidated
+        // cache entry isn't an error at all
+        is_client_disconnect(error) || *error.etype() == pingora_core::ErrorType::Custom(CACHE_REVALIDATED)
+    }
+
+    fn error_while_proxy(
+        &self,
+        peer: &HttpPeer,
+        session: &mut Session,
+        e: Box<pingora_core::Error>,
+        ctx: &mut Self::CTX,
+        client_reused: bool,
+    ) -> Box<pingora_core::Error> {
+        // A capped upstream read that ran out is the request timeout, not a 502
+        if let Err(exceeded) = ctx.deadline.check(RequestStage::Upstream) {
+            return self.request_timed_out(ctx, exceeded);
+        }
+
+        let mut e = e.more_context(format!("Peer: {}", peer));
+        e.retry.decide_reuse(client_reused && !session.retry_buffer_truncated());
+        e
+    }
+
+    fn upstream_response_filter(
+        &self,
+        session: &mut Session,
+        upstream_response: &mut ResponseHeader,
+        ctx: &mut Self::CTX,
+    ) -> Result<()> {
+        // Report what ALPN actually negotiated with an HTTP/2 upstream
+        if let Some(upstream) = ctx.upstream.as_ref().filter(|u| ctx.upstream_http2 && u.use_tls) {
+            let negotiated_h2 = upstream_response.version == http::Version::HTTP_2;
+            debug!(upstream = %upstream.address_str, version = ?upstream_response.version, "Upstream protocol");
+            if !negotiated_h2 && !ctx.upstream_http2_fallback {
+                warn!(upstream = %upstream.address_str, "Upstream did not negotiate HTTP/2 and fallback is disabled");
+                return Err(pingora_core::Error::explain(
+                    pingora_core::ErrorType::HTTPStatus(502),
+                    "upstream did not negotiate HTTP/2",
+                ));
+            }
+        }
+
+        // A 304 to our validators refreshes the expired entry, which is sent instead
+        if ctx.revalidating && upstream_response.status == StatusCode::NOT_MODIFIED {
+            let stale = ctx.stale_cache_entry.take();
+            let refreshed = match (&self.cache, &ctx.cache_key) {
+                (Some(cache), Some(cache_key)) => {
+                    cache.refresh(cache_key, &replayable_headers(upstream_response), &ctx.cache_policy)
+                }
+                _ => None,
+            };
+            ctx.revalidated = refreshed.or(stale);
+            let mut e = pingora_core::Error::explain(
+                pingora_core::ErrorType::Custom(CACHE_REVALIDATED),
+                "upstream confirmed the cached entry",
+            );
+            e.set_retry(false);
+            return Err(e);
+        }
+
+        if ctx.retry_on_status.is_empty() {
+            return Ok(());
+        }
+        let (Some(selector), Some(current)) = (&ctx.upstream_selector, &ctx.upstream) else {
+            return Ok(());
+        };
+
+        let status = upstream_response.status.as_u16();
+        let has_body = ctx.request_body_limit.received() > 0 || request_declares_body(&session.req_header().headers);
+        let replayable = has_body && body_replayable(session, &ctx.request_body_limit, ctx.request_buffer_limit);
+        if has_body && !replayable && ctx.retry_on_status.contains(&status) {
+            debug!(
+                received = ctx.request_body_limit.received(),
+                request_buffer_limit = ctx.request_buffer_limit,
+                "Request body can't be replayed, not retrying"
+            );
+        }
+        let retry = StatusRetry {
+            retry_on_status: &ctx.retry_on_status,
+            status,
+            method: &session.req_header().method,
+            has_body: has_body && !replayable,
+            deadline: ctx.retry_deadline,
+        };
+        let Some(next) = retry.next_upstream(selector, current, &ctx.tried_upstreams) else {
+            return Ok(());
+        };
+
+        // Nothing has been sent downstream yet, so the response can be dropped
+        if let Some(upstream) = ctx.upstream.take() {
+            warn!(upstream = %upstream.address_str, status = status, "Retryable upstream status");
+            upstream.record_response(!upstream_response.status.is_server_error());
+            upstream.decrement_connections();
+            ctx.tried_upstreams.push(upstream);
+        }
+        debug!(
+            upstream = %next.address_str,
+            tried = ctx.tried_upstreams.len(),
+            "Retrying with different upstream"
+        );
+        ctx.upstream = Some(next);
+        ctx.schedule_retry();
+        // Pingora replays the body through request_body_filter
+        ctx.request_body_limit.restart();
+        if ctx.request_digest.is_some() {
+            ctx.request_digest = Some(RequestDigest::default());
+        }
+
+        let mut e = pingora_core::Error::explain(
+            pingora_core::ErrorType::HTTPStatus(status),
+            "retryable upstream status",
+        );
+        e.set_retry(true);
+        Err(e)
+    }
+
     async fn response_filter(
         &self,
         session: &mut Session,
         upstream_response: &mut ResponseHeader,
         ctx: &mut Self::CTX,
     ) -> Result<()> {
+        // Passive health: real traffic answered with a server error counts against the upstream
+        if let Some(upstream) = &ctx.upstream {
+            upstream.record_response(!upstream_response.status.is_server_error());
+        }
+
+        // Present mapped upstream statuses as configured; everything below sees the new status
+        if let Some(rewrite) = &ctx.rewrite {
+            let body = rewrite.apply_status_map(upstream_response)?;
+            if session.req_header().method != http::Method::HEAD {
+                ctx.replacement_body = body;
+            }
+        }
+
         let headers: Vec<(String, String)> = ctx.custom_headers_down
             .iter()
             .map(|(k, v)| (k.clone(), v.clone()))
             .collect();
 
         for (key, value) in headers {
-            upstream_response.insert_header(key, value)?;
+            set_response_header(upstream_response, &key, &value)?;
         }
 
         upstream_response.insert_header("Server", "avalon")?;
 
-        // Add security headers based on global configuration
-        let config = self.config.read();
         let is_tls = session
             .digest()
             .map(|d| d.ssl_digest.is_some())
             .unwrap_or(false);
-        add_security_headers(upstream_response, &config.global.security_headers, is_tls)?;
+
+        // Rewrite redirect headers that point at internal upstream addresses
+        if let Some(redirect_rewrite) = &ctx.redirect_rewrite {
+            if let Some(host) = session.req_header().headers.get("host").and_then(|v| v.to_str().ok()) {
+                let public_origin = format!("{}://{}", if is_tls { "https" } else { "http" }, host);
+                for name in REDIRECT_HEADERS {
+                    let rewritten = upstream_response.headers
+                        .get(name)
+                        .and_then(|v| v.to_str().ok())
+                        .and_then(|v| redirect_rewrite.rewrite_header(name, v, &public_origin));
+                    if let Some(value) = rewritten {
+                        upstream_response.insert_header(name, value)?;
+                    }
+                }
+            }
+        }
+
+        // Add security headers based on global configuration
+        let config = self.config.read();
+        add_security_headers(upstream_response, &config.global.security_headers, is_tls, ctx.csp_nonce.as_ref())?;
+
+        // Resource hints for HTML pages, global ones first
+        let route_hints = ctx.response_hints.as_deref().map(Vec::as_slice).unwrap_or(&[]);
+        add_link_hints(upstream_response, config.global.response_hints.iter().chain(route_hints))?;
         drop(config);
 
         // Add CORS headers to response if configured
@@ -1309,22 +2353,22 @@ impl ProxyHttp for AvalonProxy {
 
         // Set session affinity cookie if needed
         if let Some((name, value, max_age)) = &ctx.affinity_cookie {
-            let cookie = if *max_age > 0 {
-                format!("{}={}; Path=/; Max-Age={}; HttpOnly", name, value, max_age)
-            } else {
-                format!("{}={}; Path=/; HttpOnly", name, value)
-            };
-            upstream_response.insert_header("Set-Cookie", cookie)?;
+            set_response_header(upstream_response, "Set-Cookie", &affinity_cookie(name, value, *max_age))?;
             debug!(cookie_name = %name, server_idx = %value, "Set session affinity cookie");
         }
 
+        // Remember a maintenance preview token for the pages and assets that follow
+        if let Some(cookie) = ctx.maintenance_cookie.take() {
+            upstream_response.append_header("Set-Cookie", cookie)?;
+        }
+
         // Apply response header rewrites if configured
         if let Some(rewrite) = &ctx.rewrite {
             if rewrite.has_response_header_rewrite() {
                 // Apply response header additions (won't override existing)
                 for (name, value) in &rewrite.response_headers_add {
-                    if !upstream_response.headers.contains_key(name.as_str()) {
-                        upstream_response.insert_header(name.clone(), value.clone())?;
+                    if is_set_cookie(name) || !upstream_response.headers.contains_key(name.as_str()) {
+                        set_response_header(upstream_response, name, value)?;
                     }
                 }
 
@@ -1342,6 +2386,16 @@ impl ProxyHttp for AvalonProxy {
             }
         }
 
+        // Substituted text changes the body's length, so it's sent chunked
+        ctx.rewrite_body = ctx.replacement_body.is_none()
+            && !ctx.is_websocket
+            && status_allows_body(upstream_response.status.as_u16())
+            && ctx.rewrite.as_ref().is_some_and(|r| r.rewrites_body(upstream_response));
+        if ctx.rewrite_body {
+            upstream_response.remove_header("content-length");
+            upstream_response.insert_header("Transfer-Encoding", "chunked")?;
+        }
+
         // Check content type for compression eligibility
         let content_type = upstream_response.headers
             .get("content-type")
@@ -1361,30 +2415,38 @@ impl ProxyHttp for AvalonProxy {
         // Check if response is cacheable and store headers
         if let (Some(cache), Some(cache_key)) = (&self.cache, &ctx.cache_key) {
             // Collect response headers for caching (excluding hop-by-hop headers)
-            let cacheable_headers: Vec<(String, String)> = upstream_response.headers
-                .iter()
-                .filter(|(name, _)| {
-                    let n = name.as_str().to_lowercase();
-                    !["connection", "keep-alive", "transfer-encoding", "te",
-                      "trailer", "proxy-authorization", "proxy-authenticate",
-                      "upgrade", "content-length"].contains(&n.as_str())
-                })
-                .map(|(name, value)| {
-                    (name.as_str().to_string(), value.to_str().unwrap_or("").to_string())
-                })
-                .collect();
+            let cacheable_headers = replayable_headers(upstream_response);
 
             ctx.response_headers = cacheable_headers.clone();
 
             // Check if we should cache this response
             let method = cache_key.method.as_str();
-            if cache.is_cacheable(method, ctx.response_status, &cacheable_headers) {
+            if cache.is_cacheable_for(&ctx.cache_policy, method, ctx.response_status, &cacheable_headers) {
                 ctx.should_cache = true;
-                upstream_response.insert_header("X-Cache", "MISS")?;
                 debug!(key = %cache_key.to_string_key(), status = ctx.response_status, "Response will be cached");
             }
         }
 
+        // A fetched response the cache won't store passed it by
+        if let (Some(cache), Some(status)) = (&self.cache, ctx.cache_status) {
+            let status = match status {
+                CacheStatus::Miss if !ctx.should_cache => CacheStatus::Bypass,
+                status => status,
+            };
+            cache.set_status_header(upstream_response, status)?;
+        }
+
+        // Capture the response for the idempotency replay cache; server errors are not stored
+        if ctx.idempotency.is_some() {
+            if ctx.response_status >= 500 {
+                if let Some((idempotency, key)) = ctx.idempotency.take() {
+                    idempotency.abandon(&key);
+                }
+            } else if ctx.response_headers.is_empty() {
+                ctx.response_headers = replayable_headers(upstream_response);
+            }
+        }
+
         // Check if content type is compressible
         let is_compressible_type = should_compress_content_type(content_type.as_deref());
 
@@ -1393,6 +2455,7 @@ impl ProxyHttp for AvalonProxy {
         // 2. Client sent Accept-Encoding header (compression_encoding != Identity means client accepts compression)
         // This is important for caching proxies even if we don't actually compress
         if is_compressible_type
+            && ctx.compress
             && ctx.compression_encoding != CompressionEncoding::Identity
             && status_allows_body(ctx.response_status)
         {
@@ -1401,10 +2464,12 @@ impl ProxyHttp for AvalonProxy {
 
         // Determine if we should compress this response
         // RFC 7230 Section 3.3.3: 1xx, 204, 304 responses MUST NOT contain a message body
-        let should_compress = ctx.compression_encoding != CompressionEncoding::Identity
-            && !ctx.response_already_compressed
-            && !ctx.is_websocket
-            && is_compressible_type
+        let should_compress = should_compress_response(
+            ctx.compress,
+            ctx.compression_encoding,
+            ctx.response_already_compressed,
+            content_type.as_deref(),
+        ) && !ctx.is_websocket
             && status_allows_body(ctx.response_status);
 
         // Check Content-Length to skip compression for small responses
@@ -1445,12 +2510,15 @@ impl ProxyHttp for AvalonProxy {
             );
         }
 
+        // Passthrough responses skip the body filter's per-chunk checks
+        ctx.transform_needed = ctx.body_transform_needed();
+
         Ok(())
     }
 
     fn response_body_filter(
         &self,
-        _session: &mut Session,
+        session: &mut Session,
         body: &mut Option<Bytes>,
         end_of_stream: bool,
         ctx: &mut Self::CTX,
@@ -1458,61 +2526,78 @@ impl ProxyHttp for AvalonProxy {
     where
         Self::CTX: Send + Sync,
     {
-        // Determine if we need compression
-        let should_compress = ctx.compression_encoding != CompressionEncoding::Identity
-            && !ctx.response_already_compressed
-            && !ctx.is_websocket
-            && should_compress_content_type(ctx.response_content_type.as_deref());
+        if let Err(exceeded) = ctx.deadline.check(RequestStage::ResponseBody) {
+            return Err(self.request_timed_out(ctx, exceeded));
+        }
 
-        // We need to buffer if we're compressing OR caching
-        let should_buffer = should_compress || ctx.should_cache;
+        // Nothing to compress, cache, replace or strip: forward the chunk as-is
+        if !ctx.transform_needed {
+            return Ok(None);
+        }
 
-        if !should_buffer {
+        if ctx.head_as_get {
+            head::strip_body(body);
             return Ok(None);
         }
 
-        // Buffer the response body
-        if let Some(b) = body.take() {
-            ctx.response_body_buffer.extend_from_slice(&b);
+        // A status_map body is sent in place of the upstream body
+        if let Some(replacement) = &ctx.replacement_body {
+            *body = end_of_stream.then(|| replacement.clone());
         }
 
-        // Process when we have the complete response
-        if end_of_stream && !ctx.response_body_buffer.is_empty() {
-            // Store in cache if caching is enabled (always cache uncompressed body)
-            if ctx.should_cache {
-                if let (Some(cache), Some(cache_key)) = (&self.cache, &ctx.cache_key) {
-                    let ttl = cache.parse_ttl(&ctx.response_headers);
+        let should_compress = ctx.compresses_body();
+
+        // We need to buffer if we're compressing, caching, rewriting, or storing for idempotent replay
+        if !ctx.buffer_response_chunk(body) {
+            return Ok(None);
+        }
+
+        // Substitute text once the whole body is in; cache and replay keep the result
+        if end_of_stream && ctx.rewrite_body {
+            if let Some(rewritten) = ctx.rewrite.as_ref().and_then(|r| r.rewrite_body(&ctx.response_body_buffer)) {
+                ctx.response_body_buffer.clear();
+                ctx.response_body_buffer.extend_from_slice(&rewritten);
+            }
+        }
 
-                    let cached_response = CachedResponse {
+        // Store the complete response for idempotent replay, once the digest
+        // covers the whole request body
+        if end_of_stream {
+            if let Some((idempotency, key)) = ctx.idempotency.take() {
+                match ctx.request_digest.take() {
+                    Some(digest) if session.is_body_done() => idempotency.complete(&key, CachedResponse {
                         status: StatusCode::from_u16(ctx.response_status).unwrap_or(StatusCode::OK),
                         headers: ctx.response_headers.clone(),
                         body: Bytes::copy_from_slice(&ctx.response_body_buffer),
                         cached_at: Instant::now(),
-                        ttl,
-                        etag: ctx.response_headers.iter()
-                            .find(|(k, _)| k.eq_ignore_ascii_case("etag"))
-                            .map(|(_, v)| v.clone()),
-                        last_modified: ctx.response_headers.iter()
-                            .find(|(k, _)| k.eq_ignore_ascii_case("last-modified"))
-                            .map(|(_, v)| v.clone()),
-                    };
-
-                    cache.put(cache_key, cached_response);
-                    debug!(
-                        key = %cache_key.to_string_key(),
-                        size = ctx.response_body_buffer.len(),
-                        ttl = ?ttl,
-                        "Response cached"
-                    );
+                        ttl: Duration::ZERO,
+                        etag: None,
+                        last_modified: None,
+                    }, digest.finish()),
+                    _ => idempotency.abandon(&key),
                 }
             }
+        }
+
+        // Process when we have the complete response
+        if end_of_stream && !ctx.response_body_buffer.is_empty() {
+            // Store in cache if caching is enabled (always cache uncompressed body)
+            if ctx.should_cache {
+                let status = StatusCode::from_u16(ctx.response_status).unwrap_or(StatusCode::OK);
+                self.put_in_cache(ctx, &ctx.response_headers, &ctx.response_body_buffer, status);
+            }
 
             // Apply compression if needed
             // Note: Size check was already done in upstream_response_filter based on Content-Length
-            // If we're here with should_compress=true, we should always compress
+            // If we're here with should_compress=true, we should always compress.
+            // Content-Encoding already went out with the headers, so a body that
+            // compression doesn't shrink is still sent encoded here; responses
+            // buffered before their headers (cache hits, file server) fall back
+            // to identity in finalize_buffered_response instead
             if should_compress {
                 // Compress the body
-                match compress(&ctx.response_body_buffer, ctx.compression_encoding, self.compression_config.level) {
+                let level = self.compression_config.level_for(ctx.compression_encoding, ContentKind::Dynamic);
+                match compress(&ctx.response_body_buffer, ctx.compression_encoding, level) {
                     Ok(compressed) => {
                         debug!(
                             original_size = ctx.response_body_buffer.len(),
@@ -1536,17 +2621,42 @@ impl ProxyHttp for AvalonProxy {
         Ok(None)
     }
 
-    async fn logging(&self, session: &mut Session, _e: Option<&pingora_core::Error>, ctx: &mut Self::CTX) {
-        if let Some(upstream) = &ctx.upstream {
-            upstream.decrement_connections();
+    async fn logging(&self, session: &mut Session, e: Option<&pingora_core::Error>, ctx: &mut Self::CTX) {
+        // A request answered before its 100-continue body was read closes the
+        // connection instead of draining that body
+        if skip_withheld_body(session) {
+            debug!("Closing connection instead of reading withheld request body");
+        }
+
+        if ctx.in_flight {
+            metrics().in_flight_requests.dec();
+            ctx.in_flight = false;
+        }
+
+        // Release an idempotency key whose response was never completed
+        if let Some((idempotency, key)) = ctx.idempotency.take() {
+            idempotency.abandon(&key);
+        }
+
+        // Pingora has already dropped the upstream connection of a request
+        // the client abandoned; it's logged as a 499, not a server error
+        let client_cancelled = e.is_some_and(is_client_disconnect);
+
+        let upstream = ctx.release_upstream();
+        if let Some(upstream) = &upstream {
             // Record upstream request metric
             metrics().upstream_requests.inc(&upstream.address_str);
         }
 
-        let status = session
-            .response_written()
-            .map(|r| r.status.as_u16())
-            .unwrap_or(0);
+        let status = if client_cancelled {
+            metrics().client_cancellations.inc();
+            CLIENT_CLOSED_REQUEST
+        } else {
+            session
+                .response_written()
+                .map(|r| r.status.as_u16())
+                .unwrap_or(0)
+        };
 
         let method = session.req_header().method.as_str();
         let path = session.req_header().uri.path();
@@ -1560,9 +2670,20 @@ impl ProxyHttp for AvalonProxy {
         metrics().requests_by_method.inc(method);
         metrics().requests_by_host.inc(host);
         metrics().request_duration.observe(duration_secs);
+        // The declared request size counts bodies that were rejected before being read
+        let request_size = session.req_header().headers
+            .get("content-length")
+            .and_then(|v| v.to_str().ok())
+            .and_then(|s| s.parse::<usize>().ok())
+            .unwrap_or_else(|| session.body_bytes_read());
+        metrics().request_size.observe(request_size as f64);
+        metrics().response_size.observe(session.body_bytes_sent() as f64);
+        if let Some(stage) = ctx.timeout_stage {
+            metrics().request_timeouts.inc(stage.as_str());
+        }
 
-        // Write to access log if configured
-        if let Some(logger) = &self.access_logger {
+        // Write to access log if configured, globally or for the matched route
+        if self.access_logger.is_some() || ctx.access_log.is_some() {
             let client_ip = session
                 .client_addr()
                 .map(|a| {
@@ -1599,13 +2720,39 @@ impl ProxyHttp for AvalonProxy {
                 referer,
                 duration_ms,
                 is_websocket: ctx.is_websocket,
+                timeout_stage: ctx.timeout_stage.map(|stage| stage.as_str()),
+                // The trace context sent upstream, which may have been generated
+                traceparent: ctx
+                    .correlation
+                    .as_ref()
+                    .and_then(|c| c.get("traceparent"))
+                    .or_else(|| session.req_header().headers.get("traceparent").and_then(|v| v.to_str().ok()))
+                    .map(|v| v.to_string()),
             };
 
-            logger.log(&entry);
+            match &ctx.access_log {
+                Some(route_log) => route_log.log(self.access_logger.as_ref(), &entry),
+                None => {
+                    if let Some(logger) = &self.access_logger {
+                        logger.log(&entry);
+                    }
+                }
+            }
         }
 
         // Also log via tracing
-        if ctx.is_websocket {
+        if client_cancelled {
+            info!(
+                method = %method,
+                path = %path,
+                host = %host,
+                status = %status,
+                duration_ms = %duration_ms,
+                upstream = upstream.as_ref().map(|u| u.address_str.as_str()).unwrap_or("-"),
+                error = %e.map(|e| e.to_string()).unwrap_or_default(),
+                "Request cancelled by client"
+            );
+        } else if ctx.is_websocket {
             info!(method = %method, path = %path, host = %host, status = %status, duration_ms = %duration_ms, websocket = true, "WebSocket request completed");
         } else {
             info!(method = %method, path = %path, host = %host, status = %status, duration_ms = %duration_ms, "Request completed");
@@ -1614,6 +2761,25 @@ impl ProxyHttp for AvalonProxy {
 }
 
 impl AvalonProxy {
+    /// Apply a route's fault injection, returning the status to abort with, if any
+    async fn apply_fault_injection(&self, session: &Session, injector: &FaultInjector) -> Option<u16> {
+        let globally_enabled = self.config.read().global.fault_injection;
+        let client_ip = session
+            .client_addr()
+            .and_then(|a| parse_client_ip(None, None, Some(&a.to_string())));
+
+        if !injector.permitted(globally_enabled, client_ip) {
+            return None;
+        }
+
+        let fault = injector.sample()?;
+        debug!(delay_ms = fault.delay.as_millis() as u64, abort_status = ?fault.abort_status, "Injecting fault");
+        if !fault.delay.is_zero() {
+            tokio::time::sleep(fault.delay).await;
+        }
+        fault.abort_status
+    }
+
     async fn send_error_response(&self, session: &mut Session, status: u16, message: &str) -> Result<bool> {
         let status_code = StatusCode::from_u16(status).unwrap_or(StatusCode::INTERNAL_SERVER_ERROR);
         let body = format!("{} {}", status, message);
@@ -1629,40 +2795,335 @@ impl AvalonProxy {
         Ok(true)
     }
 
-    async fn send_auth_response(&self, session: &mut Session, request_auth: bool, realm: Option<&str>) -> Result<bool> {
-        let (status_code, body) = if request_auth {
-            (StatusCode::UNAUTHORIZED, "401 Unauthorized")
-        } else {
-            (StatusCode::FORBIDDEN, "403 Forbidden")
-        };
-
-        let mut header = ResponseHeader::build(status_code, None)?;
-        header.insert_header("Content-Type", "text/plain")?;
+    async fn send_json_response(&self, session: &mut Session, status: StatusCode, body: String) -> Result<bool> {
+        let mut header = ResponseHeader::build(status, None)?;
+        header.insert_header("Content-Type", "application/json")?;
         header.insert_header("Content-Length", body.len().to_string())?;
         header.insert_header("Server", "avalon")?;
 
-        // Add WWW-Authenticate header for 401 responses (Basic auth challenge)
-        if request_auth {
-            let realm_value = realm.unwrap_or("Restricted");
-            header.insert_header("WWW-Authenticate", format!("Basic realm=\"{}\"", realm_value))?;
-        }
-
         session.write_response_header(Box::new(header), false).await?;
         session.write_response_body(Some(body.into()), true).await?;
 
         Ok(true)
     }
 
-    async fn send_cors_preflight_response(&self, session: &mut Session, cors_headers: Vec<(String, String)>) -> Result<bool> {
-        let mut header = ResponseHeader::build(StatusCode::NO_CONTENT, None)?;
+    async fn send_forward_auth_denial(
+        &self,
+        session: &mut Session,
+        status: u16,
+        headers: Vec<(String, String)>,
+        body: Bytes,
+    ) -> Result<bool> {
+        let mut header = ResponseHeader::build(status, None)?;
+        for (name, value) in headers {
+            header.append_header(name, value)?;
+        }
+        header.insert_header("Content-Length", body.len().to_string())?;
         header.insert_header("Server", "avalon")?;
-        header.insert_header("Content-Length", "0")?;
 
-        for (name, value) in cors_headers {
-            header.insert_header(name, value)?;
+        session.write_response_header(Box::new(header), body.is_empty()).await?;
+        if !body.is_empty() {
+            session.write_response_body(Some(body), true).await?;
         }
 
-        session.write_response_header(Box::new(header), true).await?;
+        Ok(true)
+    }
+
+    /// Look up the request's cache key, returning a fresh entry to serve
+    ///
+    /// An expired entry is kept in the context for revalidation instead.
+    fn lookup_cache(&self, ctx: &mut RequestCtx) -> Option<CachedResponse> {
+        let (Some(cache), Some(cache_key)) = (&self.cache, &ctx.cache_key) else {
+            return None;
+        };
+        if cache.bypasses(&cache_key.method) {
+            ctx.cache_status = Some(CacheStatus::Bypass);
+            return None;
+        }
+
+        ctx.cache_status = Some(CacheStatus::Miss);
+        match cache.lookup(cache_key) {
+            CacheLookup::Hit(cached) => {
+                debug!(key = %cache_key.to_string_key(), "Serving from cache");
+                metrics().cache_hits.inc();
+                Some(cached)
+            }
+            CacheLookup::Stale(cached) => {
+                metrics().cache_misses.inc();
+                ctx.stale_cache_entry = Some(cached);
+                None
+            }
+            CacheLookup::Miss => {
+                metrics().cache_misses.inc();
+                None
+            }
+        }
+    }
+
+    /// Key the request's cache entry for `route`
+    ///
+    /// The key keeps only the query parameters the route names. Routes picked
+    /// by user agent or client certificate share their URLs with the routes
+    /// they shadow, so their responses are never cached, nor are responses
+    /// carrying this request's CSP nonce.
+    fn scope_cache_key(&self, ctx: &mut RequestCtx, route: &CompiledRoute) {
+        if route.user_agent.is_some() || route.client_cert.is_some() || ctx.csp_nonce.is_some() {
+            ctx.cache_key = None;
+            ctx.cache_status = self.cache.as_ref().map(|_| CacheStatus::Bypass);
+        }
+        if let Some(cache_key) = ctx.cache_key.take() {
+            ctx.cache_key = Some(match &route.cache_key_query {
+                Some(filter) => cache_key.with_query_filter(filter),
+                None => cache_key,
+            });
+        }
+    }
+
+    /// Cache a response generated in the proxy if it is cacheable; returns whether it was stored
+    fn store_in_cache(&self, ctx: &RequestCtx, headers: &[(String, String)], body: &[u8], status: StatusCode) -> bool {
+        let (Some(cache), Some(cache_key)) = (&self.cache, &ctx.cache_key) else {
+            return false;
+        };
+        if cache.bypasses(&cache_key.method)
+            || !cache.is_cacheable_for(&ctx.cache_policy, &cache_key.method, status.as_u16(), headers)
+        {
+            return false;
+        }
+        self.put_in_cache(ctx, headers, body, status);
+        true
+    }
+
+    /// Store a complete response under the request's cache key
+    fn put_in_cache(&self, ctx: &RequestCtx, headers: &[(String, String)], body: &[u8], status: StatusCode) {
+        let (Some(cache), Some(cache_key)) = (&self.cache, &ctx.cache_key) else {
+            return;
+        };
+        let ttl = cache.ttl_for(&ctx.cache_policy, headers);
+
+        // Stored decoded so hits are compressed for each client's own codec
+        if let Some((headers, stored_body)) = cache.identity_entry(headers, body) {
+            let size = stored_body.len();
+            let cached_response = CachedResponse {
+                status,
+                etag: headers.iter()
+                    .find(|(k, _)| k.eq_ignore_ascii_case("etag"))
+                    .map(|(_, v)| v.clone()),
+                last_modified: headers.iter()
+                    .find(|(k, _)| k.eq_ignore_ascii_case("last-modified"))
+                    .map(|(_, v)| v.clone()),
+                headers,
+                body: stored_body,
+                cached_at: Instant::now(),
+                ttl,
+            };
+
+            cache.put(cache_key, cached_response);
+            debug!(
+                key = %cache_key.to_string_key(),
+                size = size,
+                ttl = ?ttl,
+                "Response cached"
+            );
+        }
+    }
+
+    async fn send_cached_response(
+        &self,
+        session: &mut Session,
+        ctx: &RequestCtx,
+        cached: &CachedResponse,
+        cache_status: CacheStatus,
+    ) -> Result<bool> {
+        let status = cached.status;
+        let mut header = ResponseHeader::build(status, None)?;
+
+        // Append so repeated headers such as Set-Cookie keep every line
+        for (name, value) in cached.headers.clone() {
+            header.append_header(name, value)?;
+        }
+        if let Some(cache) = &self.cache {
+            cache.set_status_header(&mut header, cache_status)?;
+        }
+
+        // The cached body is complete, so compress it up front and send
+        // an exact Content-Length instead of chunked encoding
+        let compressible = ctx.compression_encoding != CompressionEncoding::Identity
+            && status_allows_body(status.as_u16())
+            && should_compress_content_type(header.headers.get("content-type").and_then(|v| v.to_str().ok()))
+            && !is_already_compressed(header.headers.get("content-encoding").and_then(|v| v.to_str().ok()));
+        if compressible {
+            merge_vary_header(&mut header, "Accept-Encoding")?;
+        }
+        let encoding = if compressible { ctx.compression_encoding } else { CompressionEncoding::Identity };
+        let body = finalize_buffered_response(&mut header, cached.body.clone(), encoding, ContentKind::Dynamic, &self.compression_config)?;
+
+        session.write_response_header(Box::new(header), false).await?;
+        session.write_response_body(Some(body), true).await?;
+
+        Ok(true)
+    }
+
+    /// Replay a stored idempotent response, if it answered the same request body
+    ///
+    /// The body is streamed into its digest for comparison, held to the
+    /// route's `max_request_body_size` and the request deadline like a
+    /// forwarded one; a key reused with a different body is refused with 422.
+    async fn send_replayed_response(&self, session: &mut Session, ctx: &mut RequestCtx, stored: &StoredResponse) -> Result<bool> {
+        let mut digest = RequestDigest::default();
+        loop {
+            let chunk = match ctx.deadline.run(RequestStage::RequestBody, session.read_request_body()).await {
+                Ok(chunk) => chunk?,
+                Err(exceeded) => return Err(self.request_timed_out(ctx, exceeded)),
+            };
+            let Some(chunk) = chunk else {
+                break;
+            };
+            if let Err(received) = ctx.request_body_limit.observe(chunk.len()) {
+                warn!(
+                    received = received,
+                    max_size = ctx.max_request_body_size,
+                    "Replayed request body too large"
+                );
+                return self.send_error_response(session, 413, "Payload Too Large").await;
+            }
+            digest.update(&chunk);
+        }
+        if !stored.answers(&digest.finish()) {
+            warn!("Idempotency key reused with a different request body");
+            return self.send_error_response(session, 422, "Unprocessable Content").await;
+        }
+
+        let cached = &stored.response;
+        let mut header = ResponseHeader::build(cached.status, None)?;
+
+        // Append so repeated headers such as Set-Cookie keep every line
+        for (name, value) in cached.headers.clone() {
+            header.append_header(name, value)?;
+        }
+        header.insert_header("Idempotent-Replayed", "true")?;
+        header.insert_header("Content-Length", cached.body.len().to_string())?;
+
+        session.write_response_header(Box::new(header), cached.body.is_empty()).await?;
+        if !cached.body.is_empty() {
+            session.write_response_body(Some(cached.body.clone()), true).await?;
+        }
+
+        Ok(true)
+    }
+
+    async fn send_redirect_response(&self, session: &mut Session, code: u16, location: &str) -> Result<bool> {
+        let status = StatusCode::from_u16(code).unwrap_or(StatusCode::FOUND);
+        let mut header = ResponseHeader::build(status, None)?;
+        header.insert_header("Location", location.to_string())?;
+        header.insert_header("Server", "avalon")?;
+        // RFC 7230: Redirect responses should include Content-Length: 0
+        header.insert_header("Content-Length", "0")?;
+
+        session.write_response_header(Box::new(header), true).await?;
+        Ok(true)
+    }
+
+    /// `global.handler_timeout`, unless disabled
+    fn handler_timeout(&self) -> Option<Duration> {
+        let seconds = self.config.read().global.handler_timeout;
+        (seconds > 0).then(|| Duration::from_secs(seconds))
+    }
+
+    /// Log and count an in-proxy handler that ran past `handler_timeout`
+    fn handler_timed_out(&self, handler: &'static str, host: Option<&str>, path: &str, timeout: Duration) {
+        warn!(handler, host = ?host, path = %path, ?timeout, "Handler timed out");
+        metrics().handler_timeouts.inc(handler);
+    }
+
+    /// Record the stage that ran past the request deadline and build its 504
+    fn request_timed_out(&self, ctx: &mut RequestCtx, exceeded: DeadlineExceeded) -> Box<pingora_core::Error> {
+        if ctx.timeout_stage.is_none() {
+            warn!(
+                stage = exceeded.stage.as_str(),
+                elapsed_ms = ctx.request_start.elapsed().as_millis() as u64,
+                "Request timeout exceeded"
+            );
+            ctx.timeout_stage = Some(exceeded.stage);
+        }
+        exceeded.into_error()
+    }
+
+    async fn send_maintenance_response(&self, session: &mut Session, maintenance: &Maintenance) -> Result<bool> {
+        let status_code = StatusCode::from_u16(maintenance.status()).unwrap_or(StatusCode::SERVICE_UNAVAILABLE);
+        let body = format!("{} {}", maintenance.status(), maintenance.message());
+        let mut header = ResponseHeader::build(status_code, None)?;
+        header.insert_header("Content-Type", "text/plain")?;
+        header.insert_header("Content-Length", body.len().to_string())?;
+        header.insert_header("Cache-Control", "no-store")?;
+        if let Some(retry_after) = maintenance.retry_after() {
+            header.insert_header("Retry-After", retry_after.to_string())?;
+        }
+        header.insert_header("Server", "avalon")?;
+
+        session.write_response_header(Box::new(header), false).await?;
+        session.write_response_body(Some(body.into()), true).await?;
+
+        Ok(true)
+    }
+
+    async fn send_rate_limited_response(
+        &self,
+        session: &mut Session,
+        rejection: &RateLimitRejection,
+        retry_after_secs: u64,
+    ) -> Result<bool> {
+        let (header, body) = rejection.response(retry_after_secs)?;
+
+        session.write_response_header(Box::new(header), false).await?;
+        session.write_response_body(Some(body), true).await?;
+
+        Ok(true)
+    }
+
+    async fn send_auth_response(&self, session: &mut Session, request_auth: bool, realm: Option<&str>) -> Result<bool> {
+        let (status_code, body) = if request_auth {
+            (StatusCode::UNAUTHORIZED, "401 Unauthorized")
+        } else {
+            (StatusCode::FORBIDDEN, "403 Forbidden")
+        };
+
+        let mut header = ResponseHeader::build(status_code, None)?;
+        header.insert_header("Content-Type", "text/plain")?;
+        header.insert_header("Content-Length", body.len().to_string())?;
+        header.insert_header("Server", "avalon")?;
+
+        // Add WWW-Authenticate header for 401 responses (Basic auth challenge)
+        if request_auth {
+            let realm_value = realm.unwrap_or("Restricted");
+            header.insert_header("WWW-Authenticate", format!("Basic realm=\"{}\"", realm_value))?;
+        }
+
+        session.write_response_header(Box::new(header), false).await?;
+        session.write_response_body(Some(body.into()), true).await?;
+
+        Ok(true)
+    }
+
+    async fn send_options_response(&self, session: &mut Session, allow: &str) -> Result<bool> {
+        let mut header = ResponseHeader::build(StatusCode::NO_CONTENT, None)?;
+        header.insert_header("Server", "avalon")?;
+        header.insert_header("Allow", allow)?;
+        header.insert_header("Content-Length", "0")?;
+
+        session.write_response_header(Box::new(header), true).await?;
+        Ok(true)
+    }
+
+    async fn send_cors_preflight_response(&self, session: &mut Session, cors_headers: Vec<(String, String)>) -> Result<bool> {
+        let mut header = ResponseHeader::build(StatusCode::NO_CONTENT, None)?;
+        header.insert_header("Server", "avalon")?;
+        header.insert_header("Content-Length", "0")?;
+
+        for (name, value) in cors_headers {
+            header.insert_header(name, value)?;
+        }
+
+        session.write_response_header(Box::new(header), true).await?;
         Ok(true)
     }
 
@@ -1729,3 +3190,1430 @@ impl AvalonProxy {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pingora_core::connectors::http::Connector;
+
+    /// Context as `response_filter` leaves it for a response of `content_type`
+    fn response_ctx(content_type: &str) -> RequestCtx {
+        let mut ctx = RequestCtx::new();
+        ctx.compression_encoding = CompressionEncoding::Gzip;
+        ctx.response_content_type = Some(content_type.to_string());
+        ctx.transform_needed = ctx.body_transform_needed();
+        ctx
+    }
+
+    #[test]
+    fn test_passthrough_response_streams_unbuffered() {
+        let mut ctx = response_ctx("image/png");
+        assert!(!ctx.transform_needed);
+
+        for chunk in [Bytes::from_static(b"\x89PNG"), Bytes::from_static(b"rest of image")] {
+            let mut body = Some(chunk.clone());
+            assert!(!ctx.buffer_response_chunk(&mut body));
+            assert_eq!(body, Some(chunk));
+        }
+        assert!(ctx.response_body_buffer.is_empty());
+    }
+
+    #[test]
+    fn test_transformed_responses_are_buffered() {
+        let mut ctx = response_ctx("text/html");
+        assert!(ctx.transform_needed);
+        let mut body = Some(Bytes::from_static(b"<html>"));
+        assert!(ctx.buffer_response_chunk(&mut body));
+        assert!(body.is_none());
+        assert_eq!(&ctx.response_body_buffer[..], b"<html>");
+
+        let mut cached = RequestCtx::new();
+        cached.should_cache = true;
+        assert!(cached.body_transform_needed());
+
+        let mut replaced = RequestCtx::new();
+        replaced.replacement_body = Some(Bytes::from_static(b"mapped"));
+        assert!(replaced.body_transform_needed());
+
+        let mut head = RequestCtx::new();
+        head.head_as_get = true;
+        assert!(head.body_transform_needed());
+
+        // Compression turned off for the route leaves a compressible type alone
+        let mut uncompressed = RequestCtx::new();
+        uncompressed.compress = false;
+        uncompressed.compression_encoding = CompressionEncoding::Gzip;
+        uncompressed.response_content_type = Some("text/html".to_string());
+        assert!(!uncompressed.body_transform_needed());
+    }
+
+    #[test]
+    fn test_retry_delay_stays_within_deadline() {
+        let mut ctx = RequestCtx::new();
+        ctx.retry_backoff = RetryBackoff {
+            interval: Duration::from_millis(100),
+            mode: config::RetryBackoffMode::Exponential,
+            jitter: 0.0,
+        };
+        ctx.retry_deadline = Some(Instant::now() + Duration::from_secs(60));
+        ctx.schedule_retry();
+        assert_eq!(ctx.retry_delay, Some(Duration::from_millis(100)));
+
+        // Third retry: 400ms, cut short by a deadline 50ms away
+        ctx.tried_upstreams = vec![upstream(); 3];
+        ctx.schedule_retry();
+        assert_eq!(ctx.retry_delay, Some(Duration::from_millis(400)));
+        ctx.retry_deadline = Some(Instant::now() + Duration::from_millis(50));
+        ctx.schedule_retry();
+        assert!(ctx.retry_delay.unwrap() <= Duration::from_millis(50));
+    }
+
+    fn upstream() -> Arc<UpstreamServer> {
+        UpstreamSelector::new(&["127.0.0.1:8081".to_string()], config::LoadBalancingStrategy::RoundRobin, false)
+            .unwrap()
+            .servers()[0]
+            .clone()
+    }
+
+    #[test]
+    fn test_cancelled_request_releases_upstream() {
+        let server = upstream();
+        server.increment_connections();
+        let mut ctx = RequestCtx::new();
+        ctx.upstream = Some(server.clone());
+
+        assert!(ctx.release_upstream().is_some());
+        assert_eq!(server.connection_count(), 0);
+        // Released once, however many paths reach it
+        assert!(ctx.release_upstream().is_none());
+        assert_eq!(server.connection_count(), 0);
+    }
+
+    fn body_rewrite_ctx(max: usize) -> RequestCtx {
+        let rewrite = CompiledRewrite::from_config(&config::RewriteConfig {
+            response_body_replace: vec![config::BodyReplacement {
+                from: "backend".to_string(),
+                to: "example.com".to_string(),
+            }],
+            max_rewrite_body_size: Some(max),
+            ..Default::default()
+        })
+        .unwrap();
+        let mut ctx = RequestCtx::new();
+        ctx.rewrite = Some(Arc::new(rewrite));
+        ctx.rewrite_body = true;
+        ctx.transform_needed = ctx.body_transform_needed();
+        ctx
+    }
+
+    #[test]
+    fn test_small_rewritten_body_is_buffered() {
+        let mut ctx = body_rewrite_ctx(64);
+        let mut body = Some(Bytes::from_static(b"see backend"));
+        assert!(ctx.buffer_response_chunk(&mut body));
+        assert!(body.is_none());
+        assert!(ctx.rewrite_body);
+        assert_eq!(
+            ctx.rewrite.as_ref().unwrap().rewrite_body(&ctx.response_body_buffer).unwrap(),
+            Bytes::from_static(b"see example.com")
+        );
+    }
+
+    #[test]
+    fn test_oversized_rewrite_streams_unmodified() {
+        let mut ctx = body_rewrite_ctx(8);
+        let mut body = Some(Bytes::from_static(b"backend"));
+        assert!(ctx.buffer_response_chunk(&mut body));
+
+        // Past the cap the held-back bytes go out with the chunk, unmodified
+        let mut body = Some(Bytes::from_static(b" backend"));
+        assert!(!ctx.buffer_response_chunk(&mut body));
+        assert_eq!(body, Some(Bytes::from_static(b"backend backend")));
+        assert!(!ctx.rewrite_body && !ctx.transform_needed);
+        assert!(ctx.response_body_buffer.is_empty());
+
+        let mut body = Some(Bytes::from_static(b" backend"));
+        assert!(!ctx.buffer_response_chunk(&mut body));
+        assert_eq!(body, Some(Bytes::from_static(b" backend")));
+    }
+
+    #[test]
+    fn test_operator_endpoints_need_loopback() {
+        use pingora_core::protocols::l4::socket::SocketAddr;
+
+        let inet = |addr: &str| SocketAddr::Inet(addr.parse().unwrap());
+        assert!(is_loopback(Some(&inet("127.0.0.1:40000"))));
+        assert!(is_loopback(Some(&inet("[::1]:40000"))));
+        assert!(!is_loopback(Some(&inet("10.0.0.1:40000"))));
+        assert!(!is_loopback(None));
+    }
+
+    #[tokio::test]
+    async fn test_reload_certs_keeps_routing() {
+        use pingora_core::tls::x509::X509;
+
+        let dir = tempfile::tempdir().unwrap();
+        let toml = format!(
+            r#"
+[tls]
+acme_enabled = false
+storage_path = "{}"
+
+[[servers]]
+name = "https"
+listen = [":443"]
+
+[[servers.routes]]
+[servers.routes.match]
+host = ["example.com"]
+[servers.routes.handle]
+type = "reverse_proxy"
+upstreams = ["127.0.0.1:3000"]
+"#,
+            dir.path().display()
+        );
+        let path = dir.path().join("avalon.toml");
+        std::fs::write(&path, toml).unwrap();
+        let config = Config::load(&path).unwrap();
+
+        let storage = Arc::new(CertStorage::new(dir.path()).await.unwrap());
+        let resolver = Arc::new(SniResolver::new());
+        let listener = resolver.as_ref().clone();
+        let proxy = AvalonProxy::new(config, Arc::new(Default::default()))
+            .unwrap()
+            .with_certificates(resolver, storage.clone());
+        let upstreams = proxy.get_all_upstreams();
+        let served = || listener.certificate("example.com").map(|pair| pair.cert.to_der().unwrap());
+        let der = |bundle: &tls::storage::CertBundle| X509::from_pem(bundle.certificate_pem.as_bytes()).unwrap().to_der().unwrap();
+
+        let original = tls::self_signed::generate_self_signed("example.com", 30).unwrap();
+        storage.store_cert(&original).await.unwrap();
+        assert_eq!(proxy.reload_certs().await.unwrap(), 1);
+        assert_eq!(served(), Some(der(&original)));
+
+        // Renewed out of band
+        let renewed = tls::self_signed::generate_self_signed("example.com", 90).unwrap();
+        storage.store_cert(&renewed).await.unwrap();
+        assert_eq!(proxy.reload_certs().await.unwrap(), 1);
+        assert_eq!(served(), Some(der(&renewed)));
+
+        let reloaded = proxy.get_all_upstreams();
+        assert_eq!(reloaded.len(), upstreams.len());
+        assert!(reloaded.iter().zip(&upstreams).all(|(a, b)| Arc::ptr_eq(a, b)));
+    }
+
+    #[tokio::test]
+    async fn test_reload_certs_without_tls() {
+        let proxy = AvalonProxy::new(Config::default(), Arc::new(Default::default())).unwrap();
+        assert!(proxy.reload_certs().await.is_err());
+    }
+
+    /// Config parsed from `toml`, as loaded from a file
+    fn load_config(toml: &str) -> Config {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("avalon.toml");
+        std::fs::write(&path, toml).unwrap();
+        Config::load(&path).unwrap()
+    }
+
+    /// Serve `proxy` on a free local port until the returned sender is dropped
+    async fn serve(proxy: AvalonProxy) -> (std::net::SocketAddr, tokio::sync::watch::Sender<bool>) {
+        serve_with(proxy, None).await
+    }
+
+    /// Like `serve`, over TLS when `tls` is given
+    async fn serve_with(
+        proxy: AvalonProxy,
+        tls: Option<pingora_core::listeners::tls::TlsSettings>,
+    ) -> (std::net::SocketAddr, tokio::sync::watch::Sender<bool>) {
+        use pingora_core::services::Service as _;
+
+        let addr = std::net::TcpListener::bind("127.0.0.1:0").unwrap().local_addr().unwrap();
+        let mut service = pingora_proxy::http_proxy_service(&Arc::new(Default::default()), proxy);
+        match tls {
+            Some(settings) => service.add_tls_with_settings(&addr.to_string(), None, settings),
+            None => service.add_tcp(&addr.to_string()),
+        }
+        let (shutdown, watch) = tokio::sync::watch::channel(false);
+        tokio::spawn(async move { service.start_service(None, watch, 1).await });
+
+        for _ in 0..100 {
+            if tokio::net::TcpStream::connect(addr).await.is_ok() {
+                break;
+            }
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+        (addr, shutdown)
+    }
+
+    /// Upstream answering each request with `respond`, keeping the requests it got
+    async fn fake_upstream(
+        respond: impl Fn(&str) -> String + Send + Sync + 'static,
+    ) -> (std::net::SocketAddr, Arc<parking_lot::Mutex<Vec<String>>>) {
+        use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let requests = Arc::new(parking_lot::Mutex::new(Vec::new()));
+        let respond = Arc::new(respond);
+        let seen = requests.clone();
+        tokio::spawn(async move {
+            while let Ok((mut stream, _)) = listener.accept().await {
+                let (respond, seen) = (respond.clone(), seen.clone());
+                tokio::spawn(async move {
+                    let mut buf = Vec::new();
+                    let mut chunk = [0u8; 4096];
+                    loop {
+                        // One request at a time, with its Content-Length body
+                        while let Some(end) = buf.windows(4).position(|w| w == b"\r\n\r\n") {
+                            let head = String::from_utf8_lossy(&buf[..end + 4]).to_ascii_lowercase();
+                            let body_len: usize = head
+                                .lines()
+                                .find_map(|l| l.strip_prefix("content-length:"))
+                                .map_or(0, |v| v.trim().parse().unwrap());
+                            if buf.len() < end + 4 + body_len {
+                                break;
+                            }
+                            let request = String::from_utf8_lossy(&buf[..end + 4 + body_len]).into_owned();
+                            buf.drain(..end + 4 + body_len);
+                            let response = respond(&request);
+                            seen.lock().push(request);
+                            if stream.write_all(response.as_bytes()).await.is_err() {
+                                return;
+                            }
+                        }
+                        match stream.read(&mut chunk).await {
+                            Ok(0) | Err(_) => return,
+                            Ok(n) => buf.extend_from_slice(&chunk[..n]),
+                        }
+                    }
+                });
+            }
+        });
+        (addr, requests)
+    }
+
+    /// A 200 response carrying `body`
+    fn ok(body: &str) -> String {
+        format!("HTTP/1.1 200 OK\r\nContent-Type: text/plain\r\nContent-Length: {}\r\n\r\n{}", body.len(), body)
+    }
+
+    /// Send a GET for `target` with extra header lines and read the whole response
+    async fn get(addr: std::net::SocketAddr, target: &str, headers: &str) -> String {
+        get_from(addr, [127, 0, 0, 1].into(), target, headers).await
+    }
+
+    /// Like `get`, connecting from the local address `source`
+    async fn get_from(addr: std::net::SocketAddr, source: std::net::IpAddr, target: &str, headers: &str) -> String {
+        use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+        let socket = tokio::net::TcpSocket::new_v4().unwrap();
+        socket.bind((source, 0).into()).unwrap();
+        let mut stream = socket.connect(addr).await.unwrap();
+        let request = format!("GET {} HTTP/1.1\r\nHost: example.com\r\n{}Connection: close\r\n\r\n", target, headers);
+        stream.write_all(request.as_bytes()).await.unwrap();
+        let mut response = Vec::new();
+        stream.read_to_end(&mut response).await.unwrap();
+        String::from_utf8_lossy(&response).into_owned()
+    }
+
+    /// Send `method` for `target` with `body` and read the whole response
+    async fn send(addr: std::net::SocketAddr, method: &str, target: &str, body: &str) -> String {
+        send_with(addr, method, target, &[], body).await
+    }
+
+    /// `send` with extra request headers
+    async fn send_with(
+        addr: std::net::SocketAddr,
+        method: &str,
+        target: &str,
+        headers: &[(&str, &str)],
+        body: &str,
+    ) -> String {
+        use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+        let mut stream = tokio::net::TcpStream::connect(addr).await.unwrap();
+        let headers: String = headers.iter().map(|(name, value)| format!("{}: {}\r\n", name, value)).collect();
+        let request = format!(
+            "{} {} HTTP/1.1\r\nHost: example.com\r\n{}Content-Length: {}\r\nConnection: close\r\n\r\n{}",
+            method,
+            target,
+            headers,
+            body.len(),
+            body
+        );
+        stream.write_all(request.as_bytes()).await.unwrap();
+        let mut response = Vec::new();
+        stream.read_to_end(&mut response).await.unwrap();
+        String::from_utf8_lossy(&response).into_owned()
+    }
+
+    #[tokio::test]
+    async fn test_head_answered_from_get_only_upstream() {
+        // Only implements GET
+        let (upstream, requests) = fake_upstream(|request| {
+            if request.starts_with("GET ") {
+                ok("hello world")
+            } else {
+                "HTTP/1.1 405 Method Not Allowed\r\nAllow: GET\r\nContent-Length: 0\r\n\r\n".to_string()
+            }
+        })
+        .await;
+        let config = load_config(&format!(
+            r#"
+[tls]
+acme_enabled = false
+
+[[servers]]
+name = "http"
+listen = [":8080"]
+
+[[servers.routes]]
+[servers.routes.handle]
+type = "reverse_proxy"
+upstreams = ["{upstream}"]
+head_as_get = true
+"#
+        ));
+        let proxy = AvalonProxy::new(config, Arc::new(Default::default())).unwrap();
+        let (addr, _shutdown) = serve(proxy).await;
+
+        let response = send(addr, "HEAD", "/page", "").await;
+        assert!(response.starts_with("HTTP/1.1 200"), "{}", response);
+        assert!(response.to_ascii_lowercase().contains("content-length: 11\r\n"), "{}", response);
+        assert!(response.ends_with("\r\n\r\n"), "HEAD answered with a body: {}", response);
+        assert!(requests.lock()[0].starts_with("GET /page "));
+
+        // GET still gets the body
+        let response = get(addr, "/page", "").await;
+        assert!(response.ends_with("hello world"), "{}", response);
+    }
+
+    #[tokio::test]
+    async fn test_body_within_buffer_limit_retried() {
+        use std::sync::atomic::{AtomicUsize, Ordering};
+
+        // Whichever upstream is tried first fails, the other echoes the body
+        let attempts = Arc::new(AtomicUsize::new(0));
+        let respond = move |request: &str| {
+            if attempts.fetch_add(1, Ordering::SeqCst) % 2 == 0 {
+                return "HTTP/1.1 503 Service Unavailable\r\nContent-Length: 0\r\n\r\n".to_string();
+            }
+            ok(request.split("\r\n\r\n").nth(1).unwrap_or_default())
+        };
+        let (first, first_requests) = fake_upstream(respond.clone()).await;
+        let (second, second_requests) = fake_upstream(respond).await;
+        let config = load_config(&format!(
+            r#"
+[tls]
+acme_enabled = false
+
+[[servers]]
+name = "http"
+listen = [":8080"]
+
+[[servers.routes]]
+[servers.routes.handle]
+type = "reverse_proxy"
+upstreams = ["{first}", "{second}"]
+lb_try_duration = 5000
+lb_try_interval = 0
+retry_on_status = [503]
+request_buffer_limit = 1024
+"#
+        ));
+        let (addr, _shutdown) = serve(AvalonProxy::new(config, Arc::new(Default::default())).unwrap()).await;
+
+        let small = "a".repeat(512);
+        let response = send(addr, "PUT", "/upload", &small).await;
+        assert!(response.starts_with("HTTP/1.1 200"), "{}", response);
+        assert!(response.ends_with(&small));
+        let requests = || first_requests.lock().len() + second_requests.lock().len();
+        assert_eq!(requests(), 2);
+
+        // Over the limit the body still goes through once, but isn't replayed
+        let large = "b".repeat(2048);
+        let response = send(addr, "PUT", "/upload", &large).await;
+        assert!(response.starts_with("HTTP/1.1 503"), "{}", response);
+        assert_eq!(requests(), 3);
+        let all: Vec<String> = first_requests.lock().iter().chain(second_requests.lock().iter()).cloned().collect();
+        assert!(all.iter().any(|r| r.ends_with(&large)));
+    }
+
+    /// Upstream counting the request body bytes it gets, without keeping them
+    ///
+    /// Answers a Content-Length body with its size once it is all in; a chunked
+    /// body is only counted.
+    async fn counting_upstream() -> (std::net::SocketAddr, Arc<std::sync::atomic::AtomicUsize>) {
+        use std::sync::atomic::Ordering;
+        use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let received = Arc::new(std::sync::atomic::AtomicUsize::new(0));
+        let counter = received.clone();
+        tokio::spawn(async move {
+            let (mut stream, _) = listener.accept().await.unwrap();
+            let mut head = Vec::new();
+            let mut byte = [0u8; 1];
+            while !head.ends_with(b"\r\n\r\n") {
+                if stream.read(&mut byte).await.unwrap_or(0) == 0 {
+                    return;
+                }
+                head.push(byte[0]);
+            }
+            let content_length = String::from_utf8_lossy(&head)
+                .to_ascii_lowercase()
+                .lines()
+                .find_map(|l| l.strip_prefix("content-length:").map(|v| v.trim().parse::<usize>().unwrap()));
+
+            let mut chunk = vec![0u8; 64 * 1024];
+            loop {
+                if content_length.is_some_and(|len| counter.load(Ordering::SeqCst) >= len) {
+                    let body = counter.load(Ordering::SeqCst).to_string();
+                    let _ = stream.write_all(ok(&body).as_bytes()).await;
+                    return;
+                }
+                match stream.read(&mut chunk).await {
+                    Ok(0) | Err(_) => return,
+                    Ok(n) => counter.fetch_add(n, Ordering::SeqCst),
+                };
+            }
+        });
+        (addr, received)
+    }
+
+    fn upload_config(upstream: std::net::SocketAddr, max_request_body_size: u64) -> Config {
+        load_config(&format!(
+            r#"
+[tls]
+acme_enabled = false
+
+[[servers]]
+name = "http"
+listen = [":8080"]
+
+[[servers.routes]]
+[servers.routes.handle]
+type = "reverse_proxy"
+upstreams = ["{upstream}"]
+max_request_body_size = {max_request_body_size}
+"#
+        ))
+    }
+
+    #[tokio::test]
+    async fn test_large_upload_streams_to_upstream() {
+        use std::sync::atomic::Ordering;
+        use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+        let total = 8 * 1024 * 1024;
+        let (upstream, received) = counting_upstream().await;
+        let proxy = AvalonProxy::new(upload_config(upstream, 16 * 1024 * 1024), Arc::new(Default::default())).unwrap();
+        let (addr, _shutdown) = serve(proxy).await;
+
+        let mut client = tokio::net::TcpStream::connect(addr).await.unwrap();
+        let head = format!("PUT /upload HTTP/1.1\r\nHost: example.com\r\nContent-Length: {}\r\nConnection: close\r\n\r\n", total);
+        client.write_all(head.as_bytes()).await.unwrap();
+        let chunk = vec![b'u'; 64 * 1024];
+        for _ in 0..total / chunk.len() / 2 {
+            client.write_all(&chunk).await.unwrap();
+        }
+
+        // The first half reaches the upstream before the client sends the rest
+        for _ in 0..500 {
+            if received.load(Ordering::SeqCst) > 0 {
+                break;
+            }
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+        assert!(received.load(Ordering::SeqCst) > 0, "body wasn't forwarded while still being uploaded");
+        assert!(received.load(Ordering::SeqCst) <= total / 2);
+
+        for _ in 0..total / chunk.len() / 2 {
+            client.write_all(&chunk).await.unwrap();
+        }
+        let mut response = Vec::new();
+        client.read_to_end(&mut response).await.unwrap();
+        let response = String::from_utf8_lossy(&response);
+        assert!(response.starts_with("HTTP/1.1 200"), "{}", response);
+        assert!(response.ends_with(&total.to_string()), "{}", response);
+    }
+
+    #[tokio::test]
+    async fn test_chunked_upload_over_limit_rejected() {
+        use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+        let limit = 1024 * 1024;
+        let (upstream, _) = counting_upstream().await;
+        let proxy = AvalonProxy::new(upload_config(upstream, limit), Arc::new(Default::default())).unwrap();
+        let (addr, _shutdown) = serve(proxy).await;
+
+        // No Content-Length to check up front; the limit applies as it streams
+        let mut client = tokio::net::TcpStream::connect(addr).await.unwrap();
+        client
+            .write_all(b"PUT /upload HTTP/1.1\r\nHost: example.com\r\nTransfer-Encoding: chunked\r\nConnection: close\r\n\r\n")
+            .await
+            .unwrap();
+        let chunk = format!("{:x}\r\n{}\r\n", 64 * 1024, "u".repeat(64 * 1024));
+        for _ in 0..(4 * limit) / (64 * 1024) {
+            if client.write_all(chunk.as_bytes()).await.is_err() {
+                break;
+            }
+        }
+        let _ = client.write_all(b"0\r\n\r\n").await;
+
+        let mut response = Vec::new();
+        let _ = client.read_to_end(&mut response).await;
+        let response = String::from_utf8_lossy(&response);
+        assert!(response.starts_with("HTTP/1.1 413"), "{}", response);
+    }
+
+    #[tokio::test]
+    async fn test_cache_key_query_of_resolved_route() {
+        let (upstream, requests) = fake_upstream(|_| ok("page")).await;
+        let config = load_config(&format!(
+            r#"
+[tls]
+acme_enabled = false
+
+[global.cache]
+enabled = true
+
+[[servers]]
+name = "http"
+listen = [":8080"]
+
+[[servers.routes]]
+[servers.routes.match]
+user_agent = ["bot"]
+[servers.routes.handle]
+type = "reverse_proxy"
+upstreams = ["{upstream}"]
+[servers.routes.handle.cache_key_query]
+ignore = ["utm"]
+
+[[servers.routes]]
+[servers.routes.handle]
+type = "reverse_proxy"
+upstreams = ["{upstream}"]
+"#
+        ));
+        let (addr, _shutdown) = serve(AvalonProxy::new(config, Arc::new(Default::default())).unwrap()).await;
+
+        // The bot route's filter doesn't apply to browsers, so utm still splits their cache
+        assert!(get(addr, "/page?utm=a", "User-Agent: browser\r\n").await.contains("page"));
+        let second = get(addr, "/page?utm=b", "User-Agent: browser\r\n").await;
+        assert!(second.to_ascii_lowercase().contains("x-cache: miss"));
+        assert_eq!(requests.lock().len(), 2);
+
+        let repeated = get(addr, "/page?utm=b", "User-Agent: browser\r\n").await;
+        assert!(repeated.to_ascii_lowercase().contains("x-cache: hit"));
+        assert_eq!(requests.lock().len(), 2);
+    }
+
+    #[tokio::test]
+    async fn test_expired_entry_revalidated_on_the_proxied_request() {
+        use std::sync::atomic::{AtomicBool, Ordering};
+
+        let changed = Arc::new(AtomicBool::new(false));
+        let upstream_changed = changed.clone();
+        let (upstream, requests) = fake_upstream(move |request| {
+            let etag = if upstream_changed.load(Ordering::SeqCst) { "\"v2\"" } else { "\"v1\"" };
+            if request.to_ascii_lowercase().contains(&format!("if-none-match: {}", etag)) {
+                return format!("HTTP/1.1 304 Not Modified\r\nETag: {}\r\nCache-Control: max-age=1\r\n\r\n", etag);
+            }
+            let body = if etag == "\"v1\"" { "first" } else { "second" };
+            format!(
+                "HTTP/1.1 200 OK\r\nContent-Type: text/plain\r\nETag: {}\r\nCache-Control: max-age=1\r\nContent-Length: {}\r\n\r\n{}",
+                etag,
+                body.len(),
+                body
+            )
+        })
+        .await;
+        let config = load_config(&format!(
+            r#"
+[tls]
+acme_enabled = false
+
+[global.cache]
+enabled = true
+
+[[servers]]
+name = "http"
+listen = [":8080"]
+
+[[servers.routes]]
+[servers.routes.handle]
+type = "reverse_proxy"
+upstreams = ["{upstream}"]
+"#
+        ));
+        let (addr, _shutdown) = serve(AvalonProxy::new(config, Arc::new(Default::default())).unwrap()).await;
+
+        assert!(get(addr, "/page", "").await.ends_with("first"));
+        tokio::time::sleep(Duration::from_millis(1100)).await;
+
+        // Unchanged: one conditional request, answered from the refreshed entry
+        let revalidated = get(addr, "/page", "").await;
+        assert!(revalidated.starts_with("HTTP/1.1 200"), "{}", revalidated);
+        assert!(revalidated.to_ascii_lowercase().contains("x-cache: revalidated"));
+        assert!(revalidated.ends_with("first"));
+        assert_eq!(requests.lock().len(), 2);
+        assert!(requests.lock()[1].to_ascii_lowercase().contains("if-none-match: \"v1\""));
+        assert!(get(addr, "/page", "").await.to_ascii_lowercase().contains("x-cache: hit"));
+        assert_eq!(requests.lock().len(), 2);
+
+        // Changed: the same single request brings the new body
+        changed.store(true, Ordering::SeqCst);
+        tokio::time::sleep(Duration::from_millis(1100)).await;
+        let refetched = get(addr, "/page", "").await;
+        assert!(refetched.to_ascii_lowercase().contains("x-cache: miss"));
+        assert!(refetched.ends_with("second"));
+        assert_eq!(requests.lock().len(), 3);
+    }
+
+    #[tokio::test]
+    async fn test_uncacheable_method_bypasses_cache() {
+        let (upstream, requests) = fake_upstream(|_| ok("created")).await;
+        let config = load_config(&format!(
+            r#"
+[tls]
+acme_enabled = false
+
+[global.cache]
+enabled = true
+
+[[servers]]
+name = "http"
+listen = [":8080"]
+
+[[servers.routes]]
+[servers.routes.handle]
+type = "reverse_proxy"
+upstreams = ["{upstream}"]
+"#
+        ));
+        let (addr, _shutdown) = serve(AvalonProxy::new(config, Arc::new(Default::default())).unwrap()).await;
+
+        for _ in 0..2 {
+            let response = send(addr, "POST", "/items", "{}").await;
+            assert!(response.ends_with("created"));
+            assert!(response.to_ascii_lowercase().contains("x-cache: bypass"), "{}", response);
+        }
+        assert_eq!(requests.lock().len(), 2);
+    }
+
+    #[tokio::test]
+    async fn test_client_disconnect_aborts_slow_upstream() {
+        use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+        // Reads the request, never answers, and reports when the proxy hangs up
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let upstream = listener.local_addr().unwrap();
+        let (aborted_tx, aborted_rx) = tokio::sync::oneshot::channel();
+        tokio::spawn(async move {
+            let (mut stream, _) = listener.accept().await.unwrap();
+            let mut buf = [0u8; 4096];
+            while stream.read(&mut buf).await.is_ok_and(|n| n > 0) {}
+            let _ = aborted_tx.send(());
+        });
+
+        let dir = tempfile::tempdir().unwrap();
+        let log_path = dir.path().join("access.log");
+        let config = load_config(&format!(
+            r#"
+[global]
+access_log = "{log}"
+access_log_format = "json"
+
+[tls]
+acme_enabled = false
+
+[[servers]]
+name = "http"
+listen = [":8080"]
+
+[[servers.routes]]
+[servers.routes.handle]
+type = "reverse_proxy"
+upstreams = ["{upstream}"]
+"#,
+            log = log_path.display()
+        ));
+        let proxy = AvalonProxy::new(config, Arc::new(Default::default())).unwrap();
+        let server = proxy.get_all_upstreams()[0].servers()[0].clone();
+        let (addr, _shutdown) = serve(proxy).await;
+
+        let mut client = tokio::net::TcpStream::connect(addr).await.unwrap();
+        client.write_all(b"GET /slow HTTP/1.1\r\nHost: example.com\r\n\r\n").await.unwrap();
+        for _ in 0..100 {
+            if server.connection_count() == 1 {
+                break;
+            }
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+        assert_eq!(server.connection_count(), 1);
+
+        // The client gives up while the upstream is still working
+        drop(client);
+        tokio::time::timeout(Duration::from_secs(5), aborted_rx)
+            .await
+            .expect("upstream connection should be dropped")
+            .unwrap();
+
+        let mut logged = String::new();
+        for _ in 0..100 {
+            logged = std::fs::read_to_string(&log_path).unwrap_or_default();
+            if !logged.is_empty() {
+                break;
+            }
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+        assert!(logged.contains("\"status\":499"), "{}", logged);
+        assert_eq!(server.connection_count(), 0);
+    }
+
+    fn idempotent_config(upstream: std::net::SocketAddr, max_body_size: usize) -> Config {
+        load_config(&format!(
+            r#"
+[tls]
+acme_enabled = false
+
+[[servers]]
+name = "http"
+listen = [":8080"]
+
+[[servers.routes]]
+[servers.routes.handle]
+type = "reverse_proxy"
+upstreams = ["{upstream}"]
+
+[servers.routes.handle.idempotency]
+max_body_size = {max_body_size}
+"#
+        ))
+    }
+
+    #[tokio::test]
+    async fn test_idempotency_key_reused_with_other_body_refused() {
+        let (upstream, requests) = fake_upstream(|_| ok("order 1")).await;
+        let proxy = AvalonProxy::new(idempotent_config(upstream, 1024), Arc::new(Default::default())).unwrap();
+        let (addr, _shutdown) = serve(proxy).await;
+        let key = [("Idempotency-Key", "abc")];
+
+        assert!(send_with(addr, "POST", "/orders", &key, r#"{"amount":10}"#).await.ends_with("order 1"));
+        let replayed = send_with(addr, "POST", "/orders", &key, r#"{"amount":10}"#).await;
+        assert!(replayed.to_ascii_lowercase().contains("idempotent-replayed: true"), "{}", replayed);
+        assert!(replayed.ends_with("order 1"));
+        assert!(!replayed.to_ascii_lowercase().contains("x-avalon-request-digest"));
+
+        let reused = send_with(addr, "POST", "/orders", &key, r#"{"amount":1000}"#).await;
+        assert!(reused.starts_with("HTTP/1.1 422"), "{}", reused);
+        assert_eq!(requests.lock().len(), 1);
+    }
+
+    #[tokio::test]
+    async fn test_replayed_request_body_held_to_size_limit() {
+        use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+        let (upstream, requests) = fake_upstream(|_| ok("order 1")).await;
+        let config = load_config(&format!(
+            r#"
+[tls]
+acme_enabled = false
+
+[[servers]]
+name = "http"
+listen = [":8080"]
+
+[[servers.routes]]
+[servers.routes.handle]
+type = "reverse_proxy"
+upstreams = ["{upstream}"]
+max_request_body_size = 1024
+
+[servers.routes.handle.idempotency]
+max_body_size = 1024
+"#
+        ));
+        let (addr, _shutdown) = serve(AvalonProxy::new(config, Arc::new(Default::default())).unwrap()).await;
+        let key = [("Idempotency-Key", "abc")];
+        assert!(send_with(addr, "POST", "/orders", &key, r#"{"amount":10}"#).await.ends_with("order 1"));
+
+        // A chunked body has no length to refuse up front; reading it for the digest stops at the limit
+        let mut client = tokio::net::TcpStream::connect(addr).await.unwrap();
+        client
+            .write_all(b"POST /orders HTTP/1.1\r\nHost: example.com\r\nIdempotency-Key: abc\r\nTransfer-Encoding: chunked\r\nConnection: close\r\n\r\n")
+            .await
+            .unwrap();
+        let chunk = format!("{:x}\r\n{}\r\n", 16 * 1024, "u".repeat(16 * 1024));
+        for _ in 0..64 {
+            if client.write_all(chunk.as_bytes()).await.is_err() {
+                break;
+            }
+        }
+        let _ = client.write_all(b"0\r\n\r\n").await;
+
+        let mut response = Vec::new();
+        let _ = client.read_to_end(&mut response).await;
+        let response = String::from_utf8_lossy(&response);
+        assert!(response.starts_with("HTTP/1.1 413"), "{}", response);
+        assert_eq!(requests.lock().len(), 1);
+    }
+
+    #[tokio::test]
+    async fn test_idempotent_response_too_large_to_store_is_streamed() {
+        let large = "x".repeat(4096);
+        let body = large.clone();
+        let (upstream, requests) = fake_upstream(move |_| ok(&body)).await;
+        let proxy = AvalonProxy::new(idempotent_config(upstream, 1024), Arc::new(Default::default())).unwrap();
+        let (addr, _shutdown) = serve(proxy).await;
+        let key = [("Idempotency-Key", "abc")];
+
+        // Neither stored nor refused: each request with the key reaches the upstream
+        for _ in 0..2 {
+            let response = send_with(addr, "POST", "/orders", &key, "{}").await;
+            assert!(response.ends_with(&large), "{}", response);
+            assert!(!response.to_ascii_lowercase().contains("idempotent-replayed"));
+        }
+        assert_eq!(requests.lock().len(), 2);
+    }
+
+    #[tokio::test]
+    async fn test_failed_revalidation_serves_stale_entry() {
+        use std::sync::atomic::{AtomicBool, Ordering};
+
+        let down = Arc::new(AtomicBool::new(false));
+        let upstream_down = down.clone();
+        let (upstream, _) = fake_upstream(move |_| {
+            if upstream_down.load(Ordering::SeqCst) {
+                return "not http\r\n\r\n".to_string();
+            }
+            "HTTP/1.1 200 OK\r\nContent-Type: text/plain\r\nETag: \"v1\"\r\nCache-Control: max-age=1\r\nContent-Length: 6\r\n\r\ncached".to_string()
+        })
+        .await;
+        let config = load_config(&format!(
+            r#"
+[tls]
+acme_enabled = false
+
+[global.cache]
+enabled = true
+stale_if_error = true
+
+[[servers]]
+name = "http"
+listen = [":8080"]
+
+[[servers.routes]]
+[servers.routes.handle]
+type = "reverse_proxy"
+upstreams = ["{upstream}"]
+"#
+        ));
+        let (addr, _shutdown) = serve(AvalonProxy::new(config, Arc::new(Default::default())).unwrap()).await;
+
+        assert!(get(addr, "/page", "").await.ends_with("cached"));
+        tokio::time::sleep(Duration::from_millis(1100)).await;
+
+        down.store(true, Ordering::SeqCst);
+        let stale = get(addr, "/page", "").await;
+        assert!(stale.starts_with("HTTP/1.1 200"), "{}", stale);
+        assert!(stale.to_ascii_lowercase().contains("x-cache: stale"));
+        assert!(stale.ends_with("cached"));
+    }
+
+    /// GET `/` over TLS, presenting the certificate of `identity` if given, returning the body
+    async fn tls_get(addr: std::net::SocketAddr, identity: Option<&config::UpstreamMtlsConfig>) -> String {
+        let mut peer = HttpPeer::new(addr, true, "localhost".to_string());
+        peer.options.verify_cert = false;
+        peer.options.verify_hostname = false;
+        peer.client_cert_key = identity.map(|mtls| crate::upstream::load_mtls_connector(mtls).unwrap());
+
+        let (mut session, _) = Connector::new(None).get_http_session(&peer).await.unwrap();
+        let mut request = RequestHeader::build("GET", b"/", None).unwrap();
+        request.insert_header("Host", "localhost").unwrap();
+        session.write_request_header(Box::new(request)).await.unwrap();
+        session.finish_request_body().await.unwrap();
+        session.read_response_header().await.unwrap();
+        let mut body = Vec::new();
+        while let Some(chunk) = session.read_response_body().await.unwrap() {
+            body.extend_from_slice(&chunk);
+        }
+        String::from_utf8(body).unwrap()
+    }
+
+    /// TLS upstream that only accepts http/1.1 in ALPN, recording request heads
+    async fn tls_http1_upstream(cert_path: &std::path::Path, key_path: &std::path::Path) -> (std::net::SocketAddr, Arc<parking_lot::Mutex<Vec<String>>>) {
+        use pingora_core::tls::ssl::{select_next_proto, AlpnError, Ssl, SslAcceptor, SslFiletype, SslMethod};
+        use pingora_core::tls::tokio_ssl::SslStream;
+        use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+        let mut acceptor = SslAcceptor::mozilla_intermediate_v5(SslMethod::tls()).unwrap();
+        acceptor.set_certificate_chain_file(cert_path).unwrap();
+        acceptor.set_private_key_file(key_path, SslFiletype::PEM).unwrap();
+        acceptor.set_alpn_select_callback(|_, client| select_next_proto(b"\x08http/1.1", client).ok_or(AlpnError::ALERT_FATAL));
+        let acceptor = acceptor.build();
+
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let requests = Arc::new(parking_lot::Mutex::new(Vec::new()));
+        let seen = requests.clone();
+        tokio::spawn(async move {
+            while let Ok((tcp, _)) = listener.accept().await {
+                let mut stream = SslStream::new(Ssl::new(acceptor.context()).unwrap(), tcp).unwrap();
+                let seen = seen.clone();
+                tokio::spawn(async move {
+                    if std::pin::Pin::new(&mut stream).accept().await.is_err() {
+                        return;
+                    }
+                    let mut buf = Vec::new();
+                    let mut chunk = [0u8; 4096];
+                    loop {
+                        while let Some(end) = buf.windows(4).position(|w| w == b"\r\n\r\n") {
+                            seen.lock().push(String::from_utf8_lossy(&buf[..end + 4]).to_ascii_lowercase());
+                            buf.drain(..end + 4);
+                            if stream.write_all(ok("ok").as_bytes()).await.is_err() {
+                                return;
+                            }
+                        }
+                        match stream.read(&mut chunk).await {
+                            Ok(0) | Err(_) => return,
+                            Ok(n) => buf.extend_from_slice(&chunk[..n]),
+                        }
+                    }
+                });
+            }
+        });
+        (addr, requests)
+    }
+
+    #[tokio::test]
+    async fn test_request_limit_applies_after_http1_fallback() {
+        let dir = tempfile::tempdir().unwrap();
+        let bundle = tls::self_signed::generate_self_signed("localhost", 30).unwrap();
+        let cert_path = dir.path().join("cert.pem");
+        let key_path = dir.path().join("key.pem");
+        std::fs::write(&cert_path, &bundle.certificate_pem).unwrap();
+        std::fs::write(&key_path, &bundle.private_key_pem).unwrap();
+        let (upstream, requests) = tls_http1_upstream(&cert_path, &key_path).await;
+
+        let config = load_config(&format!(
+            r#"
+[tls]
+acme_enabled = false
+
+[[servers]]
+name = "http"
+listen = [":8080"]
+
+[[servers.routes]]
+[servers.routes.handle]
+type = "reverse_proxy"
+upstreams = ["https://{upstream}"]
+upstream_http2 = true
+upstream_http2_fallback = true
+
+[servers.routes.handle.timeouts]
+max_requests_per_connection = 2
+
+[servers.routes.handle.upstream_mtls]
+client_cert = "{cert}"
+client_key = "{key}"
+insecure_skip_verify = true
+"#,
+            cert = cert_path.display(),
+            key = key_path.display()
+        ));
+        let (addr, _shutdown) = serve(AvalonProxy::new(config, Arc::new(Default::default())).unwrap()).await;
+
+        for _ in 0..2 {
+            assert!(get(addr, "/", "").await.ends_with("ok"));
+        }
+        // Offered h2, settled on HTTP/1.1: the second request is the connection's last
+        let requests = requests.lock();
+        assert_eq!(requests.len(), 2);
+        assert!(!requests[0].contains("connection: close"), "{}", requests[0]);
+        assert!(requests[1].contains("connection: close"), "{}", requests[1]);
+    }
+
+    #[tokio::test]
+    async fn test_client_cert_route_is_not_cached() {
+        use pingora_core::listeners::tls::TlsSettings;
+
+        let (gated, _) = fake_upstream(|_| ok("gated")).await;
+        let (public, _) = fake_upstream(|_| ok("public")).await;
+
+        // One self-signed certificate serves the listener, signs the client and is the client CA
+        let dir = tempfile::tempdir().unwrap();
+        let bundle = tls::self_signed::generate_self_signed("localhost", 30).unwrap();
+        let cert_path = dir.path().join("cert.pem");
+        let key_path = dir.path().join("key.pem");
+        std::fs::write(&cert_path, &bundle.certificate_pem).unwrap();
+        std::fs::write(&key_path, &bundle.private_key_pem).unwrap();
+
+        let config = load_config(&format!(
+            r#"
+[tls]
+acme_enabled = false
+client_ca = "{ca}"
+
+[global.cache]
+enabled = true
+
+[[servers]]
+name = "mtls"
+listen = [":443"]
+
+[[servers.routes]]
+[servers.routes.match]
+client_cert = true
+[servers.routes.handle]
+type = "reverse_proxy"
+upstreams = ["{gated}"]
+
+[[servers.routes]]
+[servers.routes.handle]
+type = "reverse_proxy"
+upstreams = ["{public}"]
+"#,
+            ca = cert_path.display()
+        ));
+        let mut settings = TlsSettings::intermediate(cert_path.to_str().unwrap(), key_path.to_str().unwrap()).unwrap();
+        tls::request_client_certs(&mut settings, &cert_path).unwrap();
+        let proxy = AvalonProxy::new(config, Arc::new(Default::default())).unwrap();
+        let (addr, _shutdown) = serve_with(proxy, Some(settings)).await;
+
+        let identity = config::UpstreamMtlsConfig {
+            client_cert: cert_path.display().to_string(),
+            client_key: key_path.display().to_string(),
+            ca_cert: None,
+            insecure_skip_verify: true,
+            verify_hostname: false,
+        };
+        assert_eq!(tls_get(addr, Some(&identity)).await, "gated");
+        // Same URL without a certificate: the gated body must not come from the cache
+        assert_eq!(tls_get(addr, None).await, "public");
+        assert_eq!(tls_get(addr, Some(&identity)).await, "gated");
+        assert_eq!(tls_get(addr, None).await, "public");
+    }
+
+    #[tokio::test]
+    async fn test_access_control_before_cache() {
+        let (upstream, requests) = fake_upstream(|_| ok("page")).await;
+        let config = load_config(&format!(
+            r#"
+[tls]
+acme_enabled = false
+
+[global.cache]
+enabled = true
+
+[[servers]]
+name = "http"
+listen = [":8080"]
+
+[[servers.access_control.rules]]
+ip = ["127.0.0.2"]
+action = "deny"
+
+[[servers.routes]]
+[servers.routes.handle]
+type = "reverse_proxy"
+upstreams = ["{upstream}"]
+"#
+        ));
+        let (addr, _shutdown) = serve(AvalonProxy::new(config, Arc::new(Default::default())).unwrap()).await;
+
+        assert!(get(addr, "/page", "").await.contains("page"));
+        assert!(get(addr, "/page", "").await.to_ascii_lowercase().contains("x-cache: hit"));
+
+        // Cached or not, the denied client gets nothing
+        let denied = get_from(addr, [127, 0, 0, 2].into(), "/page", "").await;
+        assert!(denied.starts_with("HTTP/1.1 403"));
+        assert!(!denied.contains("page"));
+        assert_eq!(requests.lock().len(), 1);
+    }
+
+    #[tokio::test]
+    async fn test_access_control_of_resolving_server_only() {
+        let (upstream, _) = fake_upstream(|request| {
+            ok(if request.starts_with("GET /admin") { "admin" } else { "public" })
+        })
+        .await;
+        let config = load_config(&format!(
+            r#"
+[tls]
+acme_enabled = false
+
+[[servers]]
+name = "admin"
+listen = [":8080"]
+
+[[servers.access_control.rules]]
+ip = ["127.0.0.2"]
+action = "deny"
+
+[[servers.routes]]
+[servers.routes.match]
+path = ["/admin"]
+[servers.routes.handle]
+type = "reverse_proxy"
+upstreams = ["{upstream}"]
+
+[[servers]]
+name = "public"
+listen = [":8081"]
+
+[[servers.routes]]
+[servers.routes.handle]
+type = "reverse_proxy"
+upstreams = ["{upstream}"]
+"#
+        ));
+        let (addr, _shutdown) = serve(AvalonProxy::new(config, Arc::new(Default::default())).unwrap()).await;
+
+        // The admin server's policy only covers requests its routes handle
+        let denied = get_from(addr, [127, 0, 0, 2].into(), "/admin/users", "").await;
+        assert!(denied.starts_with("HTTP/1.1 403"));
+        let public = get_from(addr, [127, 0, 0, 2].into(), "/page", "").await;
+        assert!(public.starts_with("HTTP/1.1 200"));
+        assert!(public.contains("public"));
+        assert!(get(addr, "/admin/users", "").await.contains("admin"));
+    }
+
+    #[tokio::test]
+    async fn test_blocked_user_agent_before_cache() {
+        let (upstream, requests) = fake_upstream(|_| ok("page")).await;
+        let config = load_config(&format!(
+            r#"
+[tls]
+acme_enabled = false
+
+[global.cache]
+enabled = true
+
+[[servers]]
+name = "http"
+listen = [":8080"]
+
+[[servers.routes]]
+block_user_agents = ["(?i)crawler"]
+[servers.routes.handle]
+type = "reverse_proxy"
+upstreams = ["{upstream}"]
+"#
+        ));
+        let (addr, _shutdown) = serve(AvalonProxy::new(config, Arc::new(Default::default())).unwrap()).await;
+
+        assert!(get(addr, "/page", "User-Agent: browser\r\n").await.contains("page"));
+        let blocked = get(addr, "/page", "User-Agent: Crawler/1.0\r\n").await;
+        assert!(blocked.starts_with("HTTP/1.1 403"));
+        assert!(!blocked.to_ascii_lowercase().contains("x-cache"));
+        assert_eq!(requests.lock().len(), 1);
+    }
+
+    #[tokio::test]
+    async fn test_maintenance_preview_bypasses_cache() {
+        let (upstream, requests) = fake_upstream(|_| ok("preview")).await;
+        let config = load_config(&format!(
+            r#"
+[tls]
+acme_enabled = false
+
+[global.cache]
+enabled = true
+
+[[servers]]
+name = "http"
+listen = [":8080"]
+
+[[servers.routes]]
+[servers.routes.handle]
+type = "reverse_proxy"
+upstreams = ["{upstream}"]
+[servers.routes.handle.maintenance]
+bypass_secret = "preview-secret"
+"#
+        ));
+        let (addr, _shutdown) = serve(AvalonProxy::new(config, Arc::new(Default::default())).unwrap()).await;
+        let expires = std::time::SystemTime::now().duration_since(std::time::UNIX_EPOCH).unwrap().as_secs() + 3600;
+        let token = crate::maintenance::sign_token("preview-secret", expires);
+
+        let preview = get(addr, &format!("/page?lang=en&avalon_preview={}", token), "").await;
+        assert!(preview.contains("preview"));
+        assert!(preview.to_ascii_lowercase().contains("x-cache: bypass"));
+        let cookie = format!("Cookie: theme=dark; avalon_preview={}\r\n", token);
+        assert!(get(addr, "/page?lang=en", &cookie).await.contains("preview"));
+
+        // The token never reaches the upstream, and previews never reach the cache
+        let heads = requests.lock().clone();
+        assert_eq!(heads.len(), 2);
+        assert!(heads[0].starts_with("GET /page?lang=en HTTP/1.1"));
+        assert!(heads.iter().all(|head| !head.contains("avalon_preview")));
+        assert!(heads[1].to_ascii_lowercase().contains("cookie: theme=dark"));
+        assert!(get(addr, "/page?lang=en", "").await.starts_with("HTTP/1.1 503"));
+    }
+
+    #[tokio::test]
+    async fn test_csp_nonce_responses_are_not_cached() {
+        let (upstream, requests) = fake_upstream(|head| {
+            let nonce = head.lines().find_map(|l| l.strip_prefix("X-CSP-Nonce: ").or(l.strip_prefix("x-csp-nonce: ")));
+            let body = format!("<script nonce=\"{}\"></script>", nonce.unwrap_or_default());
+            format!("HTTP/1.1 200 OK\r\nContent-Type: text/html\r\nContent-Length: {}\r\n\r\n{}", body.len(), body)
+        })
+        .await;
+        let config = load_config(&format!(
+            r#"
+[tls]
+acme_enabled = false
+
+[global.cache]
+enabled = true
+
+[global.security_headers]
+enabled = true
+content_security_policy = "script-src 'nonce-{{nonce}}'"
+
+[global.security_headers.csp_nonce]
+
+[[servers]]
+name = "http"
+listen = [":8080"]
+
+[[servers.routes]]
+[servers.routes.handle]
+type = "reverse_proxy"
+upstreams = ["{upstream}"]
+"#
+        ));
+        let (addr, _shutdown) = serve(AvalonProxy::new(config, Arc::new(Default::default())).unwrap()).await;
+
+        let first = get(addr, "/", "").await;
+        let second = get(addr, "/", "").await;
+        assert!(second.to_ascii_lowercase().contains("x-cache: bypass"));
+        assert_eq!(requests.lock().len(), 2);
+
+        // Each page carries the nonce of its own policy
+        let body_nonce = |response: &str| response.split("nonce=\"").nth(1).and_then(|s| s.split('"').next()).map(str::to_string);
+        assert_ne!(body_nonce(&first), body_nonce(&second));
+        assert!(second.contains(&format!("'nonce-{}'", body_nonce(&second).unwrap())));
+    }
+
+    #[tokio::test]
+    async fn test_forward_auth_replaces_client_copy_headers() {
+        let (upstream, requests) = fake_upstream(|_| ok("page")).await;
+        let (auth, _) = fake_upstream(|head| {
+            let user = if head.contains("/verify/alice") { "X-User: alice\r\n" } else { "" };
+            format!("HTTP/1.1 200 OK\r\n{}Content-Length: 0\r\n\r\n", user)
+        })
+        .await;
+        let config = load_config(&format!(
+            r#"
+[tls]
+acme_enabled = false
+
+[[servers]]
+name = "http"
+listen = [":8080"]
+
+[[servers.routes]]
+[servers.routes.match]
+path = ["/alice"]
+[servers.routes.handle]
+type = "reverse_proxy"
+upstreams = ["{upstream}"]
+[servers.routes.handle.auth.forward_auth]
+url = "http://{auth}/verify/alice"
+copy_headers = ["X-User", "X-Groups"]
+
+[[servers.routes]]
+[servers.routes.handle]
+type = "reverse_proxy"
+upstreams = ["{upstream}"]
+[servers.routes.handle.auth.forward_auth]
+url = "http://{auth}/verify/anonymous"
+copy_headers = ["X-User", "X-Groups"]
+"#
+        ));
+        let (addr, _shutdown) = serve(AvalonProxy::new(config, Arc::new(Default::default())).unwrap()).await;
+        let spoofed = "X-User: admin\r\nX-Groups: root\r\n";
+
+        assert!(get(addr, "/alice", spoofed).await.contains("page"));
+        assert!(get(addr, "/other", spoofed).await.contains("page"));
+
+        let heads: Vec<String> = requests.lock().iter().map(|h| h.to_ascii_lowercase()).collect();
+        assert_eq!(heads.len(), 2);
+        assert!(heads[0].contains("x-user: alice\r\n"));
+        assert!(heads.iter().all(|h| !h.contains("x-user: admin") && !h.contains("x-groups")));
+        assert!(!heads[1].contains("x-user"));
+    }
+
+    #[tokio::test]
+    async fn test_static_response_compressed_and_cached() {
+        let config = load_config(
+            r#"
+[tls]
+acme_enabled = false
+
+[global.cache]
+enabled = true
+
+[[servers]]
+name = "http"
+listen = [":8080"]
+
+[[servers.routes]]
+[servers.routes.handle]
+type = "static_response"
+body = "static text "
+body_size = 8192
+
+[servers.routes.handle.headers]
+Content-Type = "text/plain"
+"#,
+        );
+        let (addr, _shutdown) = serve(AvalonProxy::new(config, Arc::new(Default::default())).unwrap()).await;
+
+        let first = get(addr, "/", "Accept-Encoding: gzip\r\n").await.to_ascii_lowercase();
+        assert!(first.starts_with("http/1.1 200"));
+        assert!(first.contains("content-encoding: gzip"));
+        assert!(first.contains("x-cache: miss"));
+
+        let hit = get(addr, "/", "").await.to_ascii_lowercase();
+        assert!(hit.contains("x-cache: hit"));
+        assert!(!hit.contains("content-encoding"));
+        assert!(hit.contains("static text static text"));
+    }
+
+    #[tokio::test]
+    async fn test_static_response_delay_bounded_by_request_timeout() {
+        let config = load_config(
+            r#"
+[tls]
+acme_enabled = false
+
+[global]
+request_timeout = 1
+
+[[servers]]
+name = "http"
+listen = [":8080"]
+
+[[servers.routes]]
+[servers.routes.handle]
+type = "static_response"
+body = "late"
+delay_ms = 10000
+"#,
+        );
+        let (addr, _shutdown) = serve(AvalonProxy::new(config, Arc::new(Default::default())).unwrap()).await;
+
+        let start = std::time::Instant::now();
+        let response = get(addr, "/", "").await;
+        assert!(response.starts_with("HTTP/1.1 504"), "{}", response);
+        assert!(start.elapsed() < Duration::from_secs(5));
+    }
+}
@@ -1,13 +1,23 @@
 //! Rate limiting middleware
 //!
 //! Provides request rate limiting based on client IP address.
+//!
+//! Every client seen gets a token bucket. A background sweeper drops buckets
+//! that have sat idle long enough to be full again (they are then no
+//! different from a fresh one), and the number of tracked clients is capped:
+//! once full, the least recently seen clients are evicted to make room.
 
+use bytes::Bytes;
 use dashmap::DashMap;
+use pingora_http::ResponseHeader;
 use std::net::IpAddr;
-use std::sync::Arc;
+use std::sync::{Arc, Weak};
 use std::time::{Duration, Instant};
 use tracing::debug;
 
+/// Default cap on clients tracked by one limiter
+pub const DEFAULT_MAX_TRACKED_CLIENTS: usize = 100_000;
+
 /// Rate limiter configuration
 #[derive(Debug, Clone)]
 pub struct RateLimitConfig {
@@ -17,6 +27,12 @@ pub struct RateLimitConfig {
     pub window: Duration,
     /// Burst allowance (extra requests above max during burst)
     pub burst: u32,
+    /// Most clients tracked at once; the least recently seen are evicted beyond it
+    pub max_tracked_clients: usize,
+    /// How often idle buckets are swept (default: the window)
+    pub sweep_interval: Duration,
+    /// Response sent to rejected requests
+    pub rejection: RateLimitRejection,
 }
 
 impl Default for RateLimitConfig {
@@ -25,6 +41,9 @@ impl Default for RateLimitConfig {
             max_requests: 100,
             window: Duration::from_secs(60),
             burst: 10,
+            max_tracked_clients: DEFAULT_MAX_TRACKED_CLIENTS,
+            sweep_interval: Duration::from_secs(60),
+            rejection: RateLimitRejection::default(),
         }
     }
 }
@@ -35,6 +54,9 @@ impl RateLimitConfig {
             max_requests,
             window: Duration::from_secs(window_secs),
             burst: max_requests / 10, // 10% burst by default
+            max_tracked_clients: DEFAULT_MAX_TRACKED_CLIENTS,
+            sweep_interval: Duration::from_secs(window_secs),
+            rejection: RateLimitRejection::default(),
         }
     }
 
@@ -42,6 +64,81 @@ impl RateLimitConfig {
         self.burst = burst;
         self
     }
+
+    pub fn with_max_tracked_clients(mut self, max_tracked_clients: usize) -> Self {
+        self.max_tracked_clients = max_tracked_clients.max(1);
+        self
+    }
+
+    pub fn with_sweep_interval(mut self, sweep_interval: Duration) -> Self {
+        self.sweep_interval = sweep_interval;
+        self
+    }
+
+    pub fn with_rejection(mut self, rejection: RateLimitRejection) -> Self {
+        self.rejection = rejection;
+        self
+    }
+}
+
+/// Response sent to requests over the limit
+#[derive(Debug, Clone)]
+pub struct RateLimitRejection {
+    pub status: u16,
+    /// Body, with `{retry_after}` replaced by the Retry-After seconds
+    pub body: Option<String>,
+    /// Content-Type of `body`; guessed from the body when unset
+    pub content_type: Option<String>,
+    pub headers: Vec<(String, String)>,
+    /// Retry-After seconds, instead of the limiter's own estimate
+    pub retry_after: Option<u64>,
+}
+
+impl Default for RateLimitRejection {
+    fn default() -> Self {
+        Self {
+            status: 429,
+            body: None,
+            content_type: None,
+            headers: Vec::new(),
+            retry_after: None,
+        }
+    }
+}
+
+impl RateLimitRejection {
+    /// Response header and body for a rejection
+    ///
+    /// `retry_after_secs` is the limiter's estimate, used unless the
+    /// rejection sets its own.
+    pub fn response(&self, retry_after_secs: u64) -> pingora_error::Result<(ResponseHeader, Bytes)> {
+        let retry_after = self.retry_after.unwrap_or(retry_after_secs).to_string();
+        let body = match &self.body {
+            Some(body) => body.replace("{retry_after}", &retry_after),
+            None => {
+                let reason = http::StatusCode::from_u16(self.status).ok().and_then(|s| s.canonical_reason());
+                format!("{} {}", self.status, reason.unwrap_or("Too Many Requests"))
+            }
+        };
+        let content_type = self.content_type.as_deref().unwrap_or_else(|| {
+            let trimmed = body.trim_start();
+            if trimmed.starts_with('{') || trimmed.starts_with('[') {
+                "application/json"
+            } else {
+                "text/plain"
+            }
+        });
+
+        let mut header = ResponseHeader::build(self.status, None)?;
+        header.insert_header("Content-Type", content_type)?;
+        for (name, value) in &self.headers {
+            header.insert_header(name.clone(), value.as_str())?;
+        }
+        header.insert_header("Content-Length", body.len().to_string())?;
+        header.insert_header("Retry-After", retry_after)?;
+        header.insert_header("Server", "avalon")?;
+        Ok((header, Bytes::from(body)))
+    }
 }
 
 /// Token bucket for rate limiting
@@ -80,6 +177,11 @@ impl TokenBucket {
         self.tokens = (self.tokens + elapsed * self.refill_rate).min(self.max_tokens);
         self.last_update = now;
     }
+
+    /// Idle for a whole window: refilled completely, same as a new bucket
+    fn is_expired(&self, now: Instant, window: Duration) -> bool {
+        now.duration_since(self.last_update) >= window
+    }
 }
 
 /// Rate limiter using token bucket algorithm
@@ -100,10 +202,19 @@ impl RateLimiter {
         limiter
     }
 
+    /// Response for requests this limiter rejects
+    pub fn rejection(&self) -> &RateLimitRejection {
+        &self.config.rejection
+    }
+
     /// Check if request from IP should be allowed
     pub fn check(&self, ip: IpAddr) -> bool {
         let max_tokens = self.config.max_requests + self.config.burst;
 
+        if !self.buckets.contains_key(&ip) && self.buckets.len() >= self.config.max_tracked_clients {
+            self.make_room();
+        }
+
         let mut entry = self.buckets.entry(ip).or_insert_with(|| {
             TokenBucket::new(max_tokens, self.config.window)
         });
@@ -127,28 +238,63 @@ impl RateLimiter {
         }
     }
 
-    /// Start background cleanup task to remove stale entries
-    fn start_cleanup(&self) {
-        let buckets = self.buckets.clone();
-        let window = self.config.window;
+    /// Number of clients currently tracked
+    pub fn tracked_clients(&self) -> usize {
+        self.buckets.len()
+    }
 
-        std::thread::spawn(move || {
-            loop {
-                std::thread::sleep(window * 2);
+    /// Drop buckets idle for a whole window; returns how many were removed
+    pub fn sweep(&self) -> usize {
+        sweep_expired(&self.buckets, self.config.window)
+    }
 
-                let now = Instant::now();
-                let stale_threshold = window * 2;
+    /// Free space for a new client once the cap is reached
+    ///
+    /// Expired buckets go first; if that isn't enough, the least recently
+    /// seen tenth of the clients is evicted so the scan isn't repeated on
+    /// every new client.
+    fn make_room(&self) {
+        if self.sweep() > 0 && self.buckets.len() < self.config.max_tracked_clients {
+            return;
+        }
 
-                buckets.retain(|_, bucket| {
-                    now.duration_since(bucket.last_update) < stale_threshold
-                });
+        let mut by_age: Vec<(IpAddr, Instant)> =
+            self.buckets.iter().map(|entry| (*entry.key(), entry.value().last_update)).collect();
+        by_age.sort_unstable_by_key(|(_, last_update)| *last_update);
 
-                debug!(entries = buckets.len(), "Rate limiter cleanup completed");
-            }
+        let target = self.config.max_tracked_clients - (self.config.max_tracked_clients / 10).max(1);
+        let evict = self.buckets.len().saturating_sub(target);
+        for (ip, _) in by_age.into_iter().take(evict) {
+            self.buckets.remove(&ip);
+        }
+        debug!(evicted = evict, entries = self.buckets.len(), "Rate limiter at capacity, evicted least recent clients");
+    }
+
+    /// Start the background sweeper; it stops once the limiter is dropped
+    fn start_cleanup(&self) {
+        let buckets: Weak<DashMap<IpAddr, TokenBucket>> = Arc::downgrade(&self.buckets);
+        let window = self.config.window;
+        let interval = self.config.sweep_interval;
+
+        std::thread::spawn(move || loop {
+            std::thread::sleep(interval);
+
+            let Some(buckets) = buckets.upgrade() else {
+                break;
+            };
+            let removed = sweep_expired(&buckets, window);
+            debug!(removed = removed, entries = buckets.len(), "Rate limiter cleanup completed");
         });
     }
 }
 
+fn sweep_expired(buckets: &DashMap<IpAddr, TokenBucket>, window: Duration) -> usize {
+    let before = buckets.len();
+    let now = Instant::now();
+    buckets.retain(|_, bucket| !bucket.is_expired(now, window));
+    before.saturating_sub(buckets.len())
+}
+
 impl Clone for RateLimiter {
     fn clone(&self) -> Self {
         Self {
@@ -284,4 +430,111 @@ mod tests {
         }
         assert_eq!(limiter.remaining(ip), 4);
     }
+
+    #[test]
+    fn test_sweeper_removes_expired_buckets() {
+        let mut config = RateLimitConfig::new(10, 60).with_sweep_interval(Duration::from_millis(50));
+        config.window = Duration::from_millis(100);
+        let limiter = RateLimiter::new(config);
+
+        for i in 0..20u8 {
+            limiter.check(IpAddr::V4(Ipv4Addr::new(10, 0, 0, i)));
+        }
+        assert_eq!(limiter.tracked_clients(), 20);
+
+        // Idle past the window, then at least one sweep
+        std::thread::sleep(Duration::from_millis(250));
+        assert_eq!(limiter.tracked_clients(), 0);
+    }
+
+    #[test]
+    fn test_sweep_keeps_active_buckets() {
+        let mut config = RateLimitConfig::new(10, 60);
+        config.window = Duration::from_millis(100);
+        let limiter = RateLimiter::new(config);
+        let idle = IpAddr::V4(Ipv4Addr::new(10, 0, 0, 1));
+        let active = IpAddr::V4(Ipv4Addr::new(10, 0, 0, 2));
+
+        limiter.check(idle);
+        limiter.check(active);
+        std::thread::sleep(Duration::from_millis(120));
+        limiter.check(active);
+
+        assert_eq!(limiter.sweep(), 1);
+        assert_eq!(limiter.tracked_clients(), 1);
+        assert_eq!(limiter.remaining(active), 10);
+    }
+
+    #[test]
+    fn test_tracked_clients_bounded() {
+        let config = RateLimitConfig::new(10, 60).with_max_tracked_clients(1000);
+        let limiter = RateLimiter::new(config);
+
+        for i in 0..50_000u32 {
+            limiter.check(IpAddr::V4(Ipv4Addr::from(0x0a00_0000 + i)));
+            assert!(limiter.tracked_clients() <= 1000);
+        }
+
+        // The most recent client is kept, the oldest evicted
+        assert!(limiter.buckets.contains_key(&IpAddr::V4(Ipv4Addr::from(0x0a00_0000 + 49_999))));
+        assert!(!limiter.buckets.contains_key(&IpAddr::V4(Ipv4Addr::from(0x0a00_0000))));
+    }
+
+    #[test]
+    fn test_evicted_client_starts_fresh() {
+        let config = RateLimitConfig::new(1, 60).with_burst(0).with_max_tracked_clients(1);
+        let limiter = RateLimiter::new(config);
+        let first = IpAddr::V4(Ipv4Addr::new(10, 0, 0, 1));
+        let second = IpAddr::V4(Ipv4Addr::new(10, 0, 0, 2));
+
+        assert!(limiter.check(first));
+        assert!(!limiter.check(first));
+        assert!(limiter.check(second));
+        assert_eq!(limiter.tracked_clients(), 1);
+    }
+
+    fn header_value<'a>(header: &'a ResponseHeader, name: &str) -> &'a str {
+        header.headers.get(name).unwrap().to_str().unwrap()
+    }
+
+    #[test]
+    fn test_default_rejection() {
+        let limiter = RateLimiter::new(RateLimitConfig::new(1, 30).with_burst(0));
+        let ip = IpAddr::V4(Ipv4Addr::new(192, 0, 2, 1));
+        assert!(check_rate_limit(&limiter, ip).is_allowed());
+        let RateLimitResult::Denied { retry_after_secs } = check_rate_limit(&limiter, ip) else {
+            panic!("expected the second request to be rate limited");
+        };
+
+        let (header, body) = limiter.rejection().response(retry_after_secs).unwrap();
+        assert_eq!(header.status, 429);
+        assert_eq!(body, Bytes::from_static(b"429 Too Many Requests"));
+        assert_eq!(header_value(&header, "content-type"), "text/plain");
+        assert_eq!(header_value(&header, "retry-after"), "30");
+    }
+
+    #[test]
+    fn test_configured_json_rejection() {
+        let rejection = RateLimitRejection {
+            status: 503,
+            body: Some(r#"{"error":"rate_limited","retry_after":{retry_after}}"#.to_string()),
+            content_type: None,
+            headers: vec![("X-RateLimit-Policy".to_string(), "100;w=60".to_string())],
+            retry_after: Some(5),
+        };
+        let limiter = RateLimiter::new(RateLimitConfig::new(1, 60).with_burst(0).with_rejection(rejection));
+        let ip = IpAddr::V4(Ipv4Addr::new(192, 0, 2, 2));
+        assert!(check_rate_limit(&limiter, ip).is_allowed());
+        let RateLimitResult::Denied { retry_after_secs } = check_rate_limit(&limiter, ip) else {
+            panic!("expected the second request to be rate limited");
+        };
+
+        let (header, body) = limiter.rejection().response(retry_after_secs).unwrap();
+        assert_eq!(header.status, 503);
+        assert_eq!(body, Bytes::from_static(br#"{"error":"rate_limited","retry_after":5}"#));
+        assert_eq!(header_value(&header, "content-type"), "application/json");
+        assert_eq!(header_value(&header, "content-length"), body.len().to_string());
+        assert_eq!(header_value(&header, "retry-after"), "5");
+        assert_eq!(header_value(&header, "x-ratelimit-policy"), "100;w=60");
+    }
 }
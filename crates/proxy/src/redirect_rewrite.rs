@@ -0,0 +1,190 @@
+//! Rewriting of upstream redirect headers
+//!
+//! Upstreams frequently issue redirects that point at their own internal
+//! address (e.g. `http://backend:8080/login`). This module maps such URLs
+//! back to the public scheme and host the client used.
+
+use crate::upstream::normalize_upstream_address;
+use config::RedirectRewriteConfig;
+use tracing::debug;
+
+/// Response headers that carry URLs which may need rewriting
+pub const REDIRECT_HEADERS: [&str; 3] = ["location", "content-location", "refresh"];
+
+/// Compiled redirect rewrite rules for a route
+#[derive(Debug, Clone)]
+pub struct CompiledRedirectRewrite {
+    /// (internal prefix, public prefix); `None` maps to the request's own origin
+    mappings: Vec<(String, Option<String>)>,
+}
+
+impl CompiledRedirectRewrite {
+    /// Compile the rewrite rules for a route with the given upstreams
+    pub fn from_config(config: &RedirectRewriteConfig, upstreams: &[String]) -> Self {
+        let mut mappings: Vec<(String, Option<String>)> = config
+            .map
+            .iter()
+            .map(|(internal, public)| {
+                (
+                    internal.trim_end_matches('/').to_ascii_lowercase(),
+                    Some(public.trim_end_matches('/').to_string()),
+                )
+            })
+            .collect();
+
+        if config.upstreams {
+            for upstream in upstreams {
+                let (address, _) = normalize_upstream_address(upstream, false);
+                let address = address.to_ascii_lowercase();
+                mappings.push((format!("http://{}", address), None));
+                mappings.push((format!("https://{}", address), None));
+
+                // Upstreams often omit the default port in the URLs they generate
+                if let Some(host) = address.strip_suffix(":80") {
+                    mappings.push((format!("http://{}", host), None));
+                } else if let Some(host) = address.strip_suffix(":443") {
+                    mappings.push((format!("https://{}", host), None));
+                }
+            }
+        }
+
+        // Prefer the most specific prefix when several could apply
+        mappings.sort_by(|a, b| b.0.len().cmp(&a.0.len()));
+
+        Self { mappings }
+    }
+
+    /// Check if there are any rewrite rules
+    pub fn is_empty(&self) -> bool {
+        self.mappings.is_empty()
+    }
+
+    /// Rewrite a URL, returning `None` if it does not point at an internal address
+    ///
+    /// `public_origin` is the client-facing origin, e.g. `https://example.com`.
+    pub fn rewrite_url(&self, url: &str, public_origin: &str) -> Option<String> {
+        let lower = url.to_ascii_lowercase();
+
+        for (internal, public) in &self.mappings {
+            if !lower.starts_with(internal.as_str()) {
+                continue;
+            }
+
+            // Only match on a URL boundary so "backend:80" doesn't match "backend:8080"
+            let rest = &url[internal.len()..];
+            if !(rest.is_empty() || rest.starts_with(['/', '?', '#'])) {
+                continue;
+            }
+
+            let base = public.as_deref().unwrap_or(public_origin);
+            let rewritten = if rest.is_empty() {
+                format!("{}/", base)
+            } else {
+                format!("{}{}", base, rest)
+            };
+            debug!(original = %url, rewritten = %rewritten, "Rewrote upstream redirect URL");
+            return Some(rewritten);
+        }
+
+        None
+    }
+
+    /// Rewrite the value of one of the `REDIRECT_HEADERS`
+    pub fn rewrite_header(&self, name: &str, value: &str, public_origin: &str) -> Option<String> {
+        if !name.eq_ignore_ascii_case("refresh") {
+            return self.rewrite_url(value.trim(), public_origin);
+        }
+
+        // Refresh: "5; url=http://backend/next"
+        let idx = value.to_ascii_lowercase().find("url=")? + "url=".len();
+        let url = value[idx..].trim().trim_matches(|c| c == '"' || c == '\'');
+        let rewritten = self.rewrite_url(url, public_origin)?;
+        Some(format!("{}{}", &value[..idx], rewritten))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashMap;
+
+    fn make_rewrite(map: HashMap<String, String>) -> CompiledRedirectRewrite {
+        let config = RedirectRewriteConfig {
+            upstreams: true,
+            map,
+        };
+        CompiledRedirectRewrite::from_config(&config, &["backend:8080".to_string()])
+    }
+
+    #[test]
+    fn test_internal_location_rewritten() {
+        let rewrite = make_rewrite(HashMap::new());
+
+        assert_eq!(
+            rewrite.rewrite_url("http://backend:8080/login?next=/", "https://example.com"),
+            Some("https://example.com/login?next=/".to_string())
+        );
+        assert_eq!(
+            rewrite.rewrite_url("http://backend:8080", "https://example.com"),
+            Some("https://example.com/".to_string())
+        );
+    }
+
+    #[test]
+    fn test_external_location_untouched() {
+        let rewrite = make_rewrite(HashMap::new());
+
+        assert_eq!(rewrite.rewrite_url("https://accounts.example.org/login", "https://example.com"), None);
+        assert_eq!(rewrite.rewrite_url("http://backend:80801/login", "https://example.com"), None);
+        assert_eq!(rewrite.rewrite_url("/relative/path", "https://example.com"), None);
+    }
+
+    #[test]
+    fn test_explicit_mapping() {
+        let mut map = HashMap::new();
+        map.insert("http://internal.svc/app/".to_string(), "https://example.com/app".to_string());
+        let rewrite = make_rewrite(map);
+
+        assert_eq!(
+            rewrite.rewrite_url("http://internal.svc/app/home", "https://ignored.com"),
+            Some("https://example.com/app/home".to_string())
+        );
+    }
+
+    #[test]
+    fn test_refresh_header() {
+        let rewrite = make_rewrite(HashMap::new());
+
+        assert_eq!(
+            rewrite.rewrite_header("Refresh", "5; url=http://backend:8080/next", "https://example.com"),
+            Some("5; url=https://example.com/next".to_string())
+        );
+        assert_eq!(rewrite.rewrite_header("Refresh", "5", "https://example.com"), None);
+    }
+
+    #[test]
+    fn test_scheme_upstream() {
+        let config = RedirectRewriteConfig {
+            upstreams: true,
+            map: HashMap::new(),
+        };
+        let rewrite = CompiledRedirectRewrite::from_config(&config, &["https://backend".to_string()]);
+
+        assert_eq!(
+            rewrite.rewrite_url("https://backend/login", "https://example.com"),
+            Some("https://example.com/login".to_string())
+        );
+    }
+
+    #[test]
+    fn test_upstreams_disabled() {
+        let config = RedirectRewriteConfig {
+            upstreams: false,
+            map: HashMap::new(),
+        };
+        let rewrite = CompiledRedirectRewrite::from_config(&config, &["backend:8080".to_string()]);
+
+        assert!(rewrite.is_empty());
+        assert_eq!(rewrite.rewrite_url("http://backend:8080/login", "https://example.com"), None);
+    }
+}
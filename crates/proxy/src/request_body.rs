@@ -0,0 +1,150 @@
+//! Streaming request body accounting
+//!
+//! Request bodies are forwarded to the upstream chunk by chunk as they
+//! arrive. The size limit is enforced by counting bytes as they stream past,
+//! so chunked uploads without a Content-Length are limited too. Nothing here
+//! holds the body: Pingora keeps the first 64 KiB for replays, so a body
+//! within the route's `request_buffer_limit` can be sent again when
+//! `retry_on_status` moves the request to another upstream. Larger bodies
+//! still stream through, they just aren't retried.
+//!
+//! Rate limiting, auth and the Content-Length check all run in
+//! `request_filter`, before the upstream is contacted, so a client sending
+//! `Expect: 100-continue` never gets the go-ahead for a rejected upload. The
+//! connection is then closed rather than drained, so the body isn't sent.
+
+use crate::retry::request_declares_body;
+use http::HeaderMap;
+use pingora_core::protocols::http::ServerSession;
+use pingora_proxy::Session;
+
+/// Running count of a streamed request body against a size limit
+#[derive(Debug, Default)]
+pub struct RequestBodyLimit {
+    limit: u64,
+    received: u64,
+}
+
+impl RequestBodyLimit {
+    /// Track a body against `limit` bytes (0 = unlimited)
+    pub fn new(limit: u64) -> Self {
+        Self { limit, received: 0 }
+    }
+
+    /// Count a chunk; fails once the body grows past the limit
+    pub fn observe(&mut self, chunk_len: usize) -> Result<(), u64> {
+        self.received += chunk_len as u64;
+        if self.limit > 0 && self.received > self.limit {
+            return Err(self.received);
+        }
+        Ok(())
+    }
+
+    /// Bytes received so far
+    pub fn received(&self) -> u64 {
+        self.received
+    }
+
+    /// Count from zero again, for a body replayed to another upstream
+    pub fn restart(&mut self) {
+        self.received = 0;
+    }
+}
+
+/// Whether a request body can be sent again to another upstream
+///
+/// It must have arrived in full, within `buffer_limit` and without
+/// overflowing Pingora's replay buffer.
+pub fn body_replayable(session: &mut Session, limit: &RequestBodyLimit, buffer_limit: u64) -> bool {
+    limit.received() <= buffer_limit && session.is_body_done() && !session.retry_buffer_truncated()
+}
+
+/// Whether the client waits for `100 Continue` before sending its body
+pub fn expects_continue(headers: &HeaderMap) -> bool {
+    headers
+        .get(http::header::EXPECT)
+        .and_then(|v| v.to_str().ok())
+        .is_some_and(|v| v.trim().eq_ignore_ascii_case("100-continue"))
+}
+
+/// Keep a body the client is still holding back from being pulled in
+///
+/// Called once a response has been sent; if the request was answered without
+/// reading an announced `100-continue` body, the connection is closed instead
+/// of drained for reuse. Returns whether keep-alive was disabled.
+pub fn skip_withheld_body(session: &mut ServerSession) -> bool {
+    let headers = &session.req_header().headers;
+    let withheld = expects_continue(headers) && request_declares_body(headers);
+    if !withheld || session.is_body_done() {
+        return false;
+    }
+    session.set_keepalive(None);
+    true
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::rate_limit::{check_rate_limit, RateLimitConfig, RateLimitResult, RateLimiter};
+    use pingora_http::ResponseHeader;
+    use std::net::IpAddr;
+    use std::time::Duration;
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+    use tokio::net::{TcpListener, TcpStream};
+
+    #[test]
+    fn test_expects_continue() {
+        let mut headers = HeaderMap::new();
+        assert!(!expects_continue(&headers));
+        headers.insert("expect", "100-Continue".parse().unwrap());
+        assert!(expects_continue(&headers));
+    }
+
+    #[tokio::test]
+    async fn test_rate_limited_upload_rejected_before_body() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+
+        // Client announces a 1MB upload and waits for 100 Continue
+        let client = tokio::spawn(async move {
+            let mut stream = TcpStream::connect(addr).await.unwrap();
+            stream
+                .write_all(b"PUT /upload HTTP/1.1\r\nHost: example.com\r\nContent-Length: 1048576\r\nExpect: 100-continue\r\n\r\n")
+                .await
+                .unwrap();
+            let mut response = Vec::new();
+            stream.read_to_end(&mut response).await.unwrap();
+            String::from_utf8(response).unwrap()
+        });
+
+        let (stream, _) = listener.accept().await.unwrap();
+        let mut session = ServerSession::new_http1(Box::new(pingora_core::protocols::l4::stream::Stream::from(stream)));
+        assert!(session.read_request().await.unwrap());
+
+        let limiter = RateLimiter::new(RateLimitConfig::new(1, 60).with_burst(0));
+        let ip: IpAddr = "10.0.0.1".parse().unwrap();
+        assert!(limiter.check(ip));
+        let RateLimitResult::Denied { retry_after_secs } = check_rate_limit(&limiter, ip) else {
+            panic!("second request should be rate limited");
+        };
+
+        let mut header = ResponseHeader::build(429, None).unwrap();
+        header.insert_header("Retry-After", retry_after_secs.to_string()).unwrap();
+        header.insert_header("Content-Length", "0").unwrap();
+        session.write_response_header(Box::new(header)).await.unwrap();
+        session.finish_body().await.unwrap();
+        assert!(skip_withheld_body(&mut session));
+
+        // Draining would wait for a body that never comes
+        let reused = tokio::time::timeout(Duration::from_secs(5), session.finish())
+            .await
+            .expect("connection should close instead of draining the body")
+            .unwrap();
+        assert!(reused.is_none());
+
+        let response = client.await.unwrap();
+        assert!(response.starts_with("HTTP/1.1 429"), "{}", response);
+        assert!(response.contains("Retry-After: 60") || response.contains("retry-after: 60"), "{}", response);
+        assert!(!response.contains("100 Continue"), "{}", response);
+    }
+}
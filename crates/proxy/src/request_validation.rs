@@ -0,0 +1,299 @@
+//! Request validation against malformed or ambiguous HTTP messages
+//!
+//! Requests whose body length can be interpreted differently by avalon and
+//! the upstream are a request smuggling vector (RFC 7230 Section 3.3.3), so
+//! they are rejected before being proxied. Host headers are parsed strictly
+//! because they drive route selection, and other header values can be
+//! checked for control characters and invalid UTF-8 that break log lines
+//! and downstream parsers.
+
+use http::{HeaderMap, HeaderValue};
+
+/// Longest accepted Host header value (253-byte DNS name plus port)
+pub const MAX_HOST_LENGTH: usize = 260;
+
+/// Check that a request's body framing is unambiguous
+///
+/// Returns the reason for rejection if the request must be answered with 400.
+pub fn check_message_framing(headers: &HeaderMap) -> Result<(), &'static str> {
+    let mut content_length: Option<u64> = None;
+    let mut has_content_length = false;
+
+    // Content-Length may appear several times or as a list; all values must agree
+    for value in headers.get_all(http::header::CONTENT_LENGTH) {
+        has_content_length = true;
+        let value = value.to_str().map_err(|_| "Invalid Content-Length header")?;
+        for part in value.split(',') {
+            let part = part.trim();
+            if part.is_empty() || !part.bytes().all(|b| b.is_ascii_digit()) {
+                return Err("Invalid Content-Length header");
+            }
+            let length: u64 = part.parse().map_err(|_| "Invalid Content-Length header")?;
+            match content_length {
+                Some(existing) if existing != length => return Err("Conflicting Content-Length headers"),
+                _ => content_length = Some(length),
+            }
+        }
+    }
+
+    let mut codings = Vec::new();
+    for value in headers.get_all(http::header::TRANSFER_ENCODING) {
+        let value = value.to_str().map_err(|_| "Invalid Transfer-Encoding header")?;
+        codings.extend(value.split(',').map(|c| c.trim().to_ascii_lowercase()));
+    }
+
+    if codings.is_empty() {
+        return Ok(());
+    }
+
+    if has_content_length {
+        return Err("Request has both Content-Length and Transfer-Encoding");
+    }
+
+    // The final coding must be chunked, otherwise the body length is unknowable
+    if codings.last().map(String::as_str) != Some("chunked") {
+        return Err("Transfer-Encoding does not end with chunked");
+    }
+
+    if codings.iter().filter(|c| c.as_str() == "chunked").count() > 1 {
+        return Err("Transfer-Encoding applies chunked more than once");
+    }
+
+    Ok(())
+}
+
+/// Extract the hostname (without port) from the request's Host header
+///
+/// Returns `Ok(None)` when there is no Host header, and the reason for
+/// rejection if the header is malformed and the request must get a 400.
+pub fn request_host(headers: &HeaderMap) -> Result<Option<&str>, &'static str> {
+    match headers.get(http::header::HOST) {
+        Some(value) => {
+            let value = value.to_str().map_err(|_| "Host header is not valid ASCII")?;
+            parse_host(value).map(Some)
+        }
+        None => Ok(None),
+    }
+}
+
+/// Split a Host header value into its hostname, validating the whole value
+///
+/// IPv6 literals keep their brackets off: `[::1]:443` yields `::1`.
+pub fn parse_host(value: &str) -> Result<&str, &'static str> {
+    if value.is_empty() {
+        return Err("Empty Host header");
+    }
+    if value.len() > MAX_HOST_LENGTH {
+        return Err("Host header too long");
+    }
+    if value.bytes().any(|b| b.is_ascii_control() || b == b' ') {
+        return Err("Host header contains control characters");
+    }
+
+    let (host, port) = if let Some(rest) = value.strip_prefix('[') {
+        let (literal, after) = rest.split_once(']').ok_or("Unterminated IPv6 literal in Host header")?;
+        if literal.parse::<std::net::Ipv6Addr>().is_err() {
+            return Err("Invalid IPv6 literal in Host header");
+        }
+        let port = match after {
+            "" => None,
+            _ => Some(after.strip_prefix(':').ok_or("Invalid Host header")?),
+        };
+        (literal, port)
+    } else {
+        match value.split_once(':') {
+            Some((_, port)) if port.contains(':') => return Err("Unbracketed IPv6 address in Host header"),
+            Some((host, port)) => (host, Some(port)),
+            None => (value, None),
+        }
+    };
+
+    if let Some(port) = port {
+        if port.is_empty() || port.len() > 5 || !port.bytes().all(|b| b.is_ascii_digit()) {
+            return Err("Invalid port in Host header");
+        }
+    }
+    if host.is_empty() {
+        return Err("Empty hostname in Host header");
+    }
+    if host.bytes().any(|b| matches!(b, b'/' | b'\\' | b'@' | b'?' | b'#')) {
+        return Err("Invalid characters in Host header");
+    }
+
+    Ok(host)
+}
+
+/// Control characters other than horizontal tab, which RFC 9110 allows as whitespace
+fn is_forbidden_byte(b: u8) -> bool {
+    (b < 0x20 && b != b'\t') || b == 0x7f
+}
+
+/// Check a header value for control characters and invalid UTF-8
+pub fn check_header_value(value: &[u8]) -> Result<(), &'static str> {
+    if value.iter().any(|&b| is_forbidden_byte(b)) {
+        return Err("Header value contains control characters");
+    }
+    if std::str::from_utf8(value).is_err() {
+        return Err("Header value is not valid UTF-8");
+    }
+    Ok(())
+}
+
+/// Check every header value of a request
+///
+/// Returns the offending header name and the reason for rejection.
+pub fn check_header_values(headers: &HeaderMap) -> Result<(), (String, &'static str)> {
+    for (name, value) in headers {
+        check_header_value(value.as_bytes()).map_err(|reason| (name.to_string(), reason))?;
+    }
+    Ok(())
+}
+
+/// Clean up header values that fail [`check_header_value`] in place
+///
+/// Control characters are dropped and invalid UTF-8 sequences become `?`.
+/// Returns the names of the headers that were changed.
+pub fn sanitize_header_values(headers: &mut HeaderMap) -> Vec<String> {
+    let mut sanitized = Vec::new();
+    for (name, value) in headers.iter_mut() {
+        if check_header_value(value.as_bytes()).is_ok() {
+            continue;
+        }
+        let cleaned: String = String::from_utf8_lossy(value.as_bytes())
+            .chars()
+            .filter(|c| *c == '\t' || !c.is_control())
+            .map(|c| if c == char::REPLACEMENT_CHARACTER { '?' } else { c })
+            .collect();
+        if let Ok(cleaned) = HeaderValue::from_str(&cleaned) {
+            *value = cleaned;
+            sanitized.push(name.to_string());
+        }
+    }
+    sanitized
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use http::HeaderValue;
+
+    fn headers(pairs: &[(&'static str, &'static str)]) -> HeaderMap {
+        let mut map = HeaderMap::new();
+        for (name, value) in pairs {
+            map.append(*name, HeaderValue::from_static(value));
+        }
+        map
+    }
+
+    #[test]
+    fn test_valid_framing() {
+        assert!(check_message_framing(&headers(&[])).is_ok());
+        assert!(check_message_framing(&headers(&[("content-length", "42")])).is_ok());
+        assert!(check_message_framing(&headers(&[("transfer-encoding", "chunked")])).is_ok());
+        assert!(check_message_framing(&headers(&[("transfer-encoding", "gzip, chunked")])).is_ok());
+        // Repeated identical values are tolerated
+        assert!(check_message_framing(&headers(&[("content-length", "42"), ("content-length", "42")])).is_ok());
+    }
+
+    #[test]
+    fn test_content_length_and_transfer_encoding_rejected() {
+        let map = headers(&[("content-length", "5"), ("transfer-encoding", "chunked")]);
+        assert!(check_message_framing(&map).is_err());
+    }
+
+    #[test]
+    fn test_conflicting_content_length_rejected() {
+        let map = headers(&[("content-length", "5"), ("content-length", "6")]);
+        assert!(check_message_framing(&map).is_err());
+
+        let map = headers(&[("content-length", "5, 6")]);
+        assert!(check_message_framing(&map).is_err());
+    }
+
+    #[test]
+    fn test_invalid_content_length_rejected() {
+        assert!(check_message_framing(&headers(&[("content-length", "-1")])).is_err());
+        assert!(check_message_framing(&headers(&[("content-length", "+5")])).is_err());
+        assert!(check_message_framing(&headers(&[("content-length", "abc")])).is_err());
+    }
+
+    #[test]
+    fn test_invalid_transfer_encoding_rejected() {
+        assert!(check_message_framing(&headers(&[("transfer-encoding", "gzip")])).is_err());
+        assert!(check_message_framing(&headers(&[("transfer-encoding", "chunked, gzip")])).is_err());
+        assert!(check_message_framing(&headers(&[("transfer-encoding", "chunked"), ("transfer-encoding", "chunked")])).is_err());
+    }
+
+    #[test]
+    fn test_parse_host() {
+        assert_eq!(parse_host("example.com"), Ok("example.com"));
+        assert_eq!(parse_host("example.com:8080"), Ok("example.com"));
+        assert_eq!(parse_host("127.0.0.1:443"), Ok("127.0.0.1"));
+    }
+
+    #[test]
+    fn test_parse_ipv6_host() {
+        assert_eq!(parse_host("[::1]:443"), Ok("::1"));
+        assert_eq!(parse_host("[2001:db8::1]"), Ok("2001:db8::1"));
+        assert!(parse_host("::1").is_err());
+        assert!(parse_host("[::1").is_err());
+        assert!(parse_host("[not-ipv6]:80").is_err());
+    }
+
+    #[test]
+    fn test_malformed_host_rejected() {
+        assert!(parse_host("example.com\r\nX-Injected: 1").is_err());
+        assert!(parse_host("example.com\t").is_err());
+        assert!(parse_host("exa mple.com").is_err());
+        assert!(parse_host("").is_err());
+        assert!(parse_host("example.com:").is_err());
+        assert!(parse_host("example.com:80a").is_err());
+        assert!(parse_host("user@example.com").is_err());
+        assert!(parse_host(&"a".repeat(MAX_HOST_LENGTH + 1)).is_err());
+    }
+
+    #[test]
+    fn test_embedded_newline_rejected() {
+        assert!(check_header_value(b"alice\r\nX-Admin: true").is_err());
+        assert!(check_header_value(b"a\nb").is_err());
+        assert!(check_header_value(b"a\x00b").is_err());
+        assert!(check_header_value(b"a\x7fb").is_err());
+    }
+
+    #[test]
+    fn test_invalid_utf8_rejected() {
+        let mut map = headers(&[("host", "example.com")]);
+        map.insert("x-name", HeaderValue::from_bytes(b"caf\xe9").unwrap());
+        let (name, _) = check_header_values(&map).unwrap_err();
+        assert_eq!(name, "x-name");
+    }
+
+    #[test]
+    fn test_clean_request_passes() {
+        let map = headers(&[
+            ("host", "example.com"),
+            ("user-agent", "Mozilla/5.0 (X11; Linux x86_64)"),
+            ("accept", "text/html;q=0.9,\t*/*;q=0.8"),
+        ]);
+        assert!(check_header_values(&map).is_ok());
+        assert!(check_header_value("café".as_bytes()).is_ok());
+    }
+
+    #[test]
+    fn test_sanitize_header_values() {
+        let mut map = headers(&[("host", "example.com"), ("accept", "*/*")]);
+        map.insert("x-name", HeaderValue::from_bytes(b"caf\xe9 \xff\xfe").unwrap());
+
+        assert_eq!(sanitize_header_values(&mut map), vec!["x-name"]);
+        assert_eq!(map["x-name"], "caf? ??");
+        assert_eq!(map["accept"], "*/*");
+        assert!(check_header_values(&map).is_ok());
+    }
+
+    #[test]
+    fn test_request_host() {
+        assert_eq!(request_host(&HeaderMap::new()), Ok(None));
+        assert_eq!(request_host(&headers(&[("host", "[::1]:8443")])), Ok(Some("::1")));
+        assert!(request_host(&headers(&[("host", "bad host")])).is_err());
+    }
+}
@@ -0,0 +1,78 @@
+//! Resource hint `Link` headers
+//!
+//! Preconnect, dns-prefetch and preload hints only help a browser that is
+//! about to render a page, so they are added to HTML responses only.
+
+use pingora_http::ResponseHeader;
+
+/// Whether a response carries an HTML document
+pub fn is_html(response: &ResponseHeader) -> bool {
+    response
+        .headers
+        .get(http::header::CONTENT_TYPE)
+        .and_then(|v| v.to_str().ok())
+        .map(|v| v.split(';').next().unwrap_or("").trim().to_ascii_lowercase())
+        .is_some_and(|mime| mime == "text/html" || mime == "application/xhtml+xml")
+}
+
+/// Append a `Link` header per hint to an HTML response
+pub fn add_link_hints<'a>(
+    response: &mut ResponseHeader,
+    hints: impl IntoIterator<Item = &'a String>,
+) -> pingora_error::Result<()> {
+    if !is_html(response) {
+        return Ok(());
+    }
+    for hint in hints {
+        response.append_header("Link", hint.as_str())?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn response(content_type: &str) -> ResponseHeader {
+        let mut response = ResponseHeader::build(200, None).unwrap();
+        response.insert_header("Content-Type", content_type).unwrap();
+        response
+    }
+
+    fn links(response: &ResponseHeader) -> Vec<&str> {
+        response
+            .headers
+            .get_all("link")
+            .iter()
+            .map(|v| v.to_str().unwrap())
+            .collect()
+    }
+
+    fn hints() -> Vec<String> {
+        vec![
+            "<https://cdn.example.com>; rel=preconnect".to_string(),
+            "</app.css>; rel=preload; as=style".to_string(),
+        ]
+    }
+
+    #[test]
+    fn test_hints_added_to_html() {
+        let mut html = response("text/html; charset=utf-8");
+        add_link_hints(&mut html, &hints()).unwrap();
+        assert_eq!(
+            links(&html),
+            vec!["<https://cdn.example.com>; rel=preconnect", "</app.css>; rel=preload; as=style"]
+        );
+    }
+
+    #[test]
+    fn test_hints_skipped_for_json() {
+        let mut json = response("application/json");
+        add_link_hints(&mut json, &hints()).unwrap();
+        assert!(links(&json).is_empty());
+
+        let mut untyped = ResponseHeader::build(200, None).unwrap();
+        add_link_hints(&mut untyped, &hints()).unwrap();
+        assert!(links(&untyped).is_empty());
+    }
+}
@@ -0,0 +1,237 @@
+//! Retrying requests on another upstream after a retryable response status
+//!
+//! Connection failures are retried in `fail_to_connect`. A response is only
+//! retried when its status is listed in `retry_on_status`, the request is
+//! idempotent, has no body or one small enough to replay (see
+//! `request_buffer_limit`; larger bodies can't be resent once streamed), and
+//! the `lb_try_duration` deadline has not passed.
+//!
+//! Each retry waits `lb_try_interval` first, doubled per retry with the
+//! exponential backoff and spread by `lb_try_jitter` so clients failing at
+//! the same moment don't retry in lockstep.
+
+use crate::upstream::{UpstreamSelector, UpstreamServer};
+use config::RetryBackoffMode;
+use http::{HeaderMap, Method};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+/// Doublings after which the exponential interval stops growing
+const MAX_DOUBLINGS: u32 = 16;
+
+/// Wait before each retry of a route
+#[derive(Debug, Clone, Copy)]
+pub struct RetryBackoff {
+    pub interval: Duration,
+    pub mode: RetryBackoffMode,
+    /// Fraction of the interval it may randomly vary by, 0.0 to 1.0
+    pub jitter: f64,
+}
+
+impl Default for RetryBackoff {
+    fn default() -> Self {
+        Self {
+            interval: Duration::from_millis(250),
+            mode: RetryBackoffMode::Fixed,
+            jitter: 0.0,
+        }
+    }
+}
+
+impl RetryBackoff {
+    /// Wait before retry number `attempt` (1 for the first retry)
+    pub fn delay(&self, attempt: u32) -> Duration {
+        self.delay_with(attempt, random_unit())
+    }
+
+    /// `delay` with the jitter drawn from `random`, in [0, 1)
+    fn delay_with(&self, attempt: u32, random: f64) -> Duration {
+        let base = match self.mode {
+            RetryBackoffMode::Fixed => self.interval,
+            RetryBackoffMode::Exponential => {
+                let doublings = attempt.saturating_sub(1).min(MAX_DOUBLINGS);
+                self.interval.saturating_mul(1 << doublings)
+            }
+        };
+        base.mul_f64(1.0 + self.jitter * (2.0 * random - 1.0))
+    }
+}
+
+/// Uniform random number in [0, 1)
+fn random_unit() -> f64 {
+    let mut bytes = [0u8; 8];
+    if getrandom::getrandom(&mut bytes).is_err() {
+        return 0.5;
+    }
+    (u64::from_le_bytes(bytes) >> 11) as f64 / (1u64 << 53) as f64
+}
+
+/// Methods that can be sent twice without changing the outcome
+pub fn is_idempotent(method: &Method) -> bool {
+    matches!(
+        *method,
+        Method::GET | Method::HEAD | Method::OPTIONS | Method::TRACE | Method::PUT | Method::DELETE
+    )
+}
+
+/// Whether a request's framing announces a body
+///
+/// Framing has already been validated, so any Transfer-Encoding means a
+/// chunked body.
+pub fn request_declares_body(headers: &HeaderMap) -> bool {
+    headers.contains_key(http::header::TRANSFER_ENCODING)
+        || headers
+            .get(http::header::CONTENT_LENGTH)
+            .is_some_and(|v| v.as_bytes() != b"0")
+}
+
+/// A response that may be retried elsewhere
+pub struct StatusRetry<'a> {
+    pub retry_on_status: &'a [u16],
+    pub status: u16,
+    pub method: &'a Method,
+    /// Whether the request carries a body that can't be replayed
+    pub has_body: bool,
+    pub deadline: Option<Instant>,
+}
+
+impl StatusRetry<'_> {
+    /// Whether the request may be sent to another upstream
+    pub fn is_retryable(&self) -> bool {
+        self.retry_on_status.contains(&self.status)
+            && is_idempotent(self.method)
+            && !self.has_body
+            && self.deadline.is_some_and(|deadline| Instant::now() < deadline)
+    }
+
+    /// Pick an upstream other than `current` and those already tried
+    pub fn next_upstream(
+        &self,
+        selector: &UpstreamSelector,
+        current: &Arc<UpstreamServer>,
+        tried: &[Arc<UpstreamServer>],
+    ) -> Option<Arc<UpstreamServer>> {
+        if !self.is_retryable() {
+            return None;
+        }
+        let mut exclude = tried.to_vec();
+        exclude.push(current.clone());
+        selector.select_excluding(&exclude).ok()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use config::LoadBalancingStrategy;
+    use std::time::Duration;
+
+    fn selector() -> UpstreamSelector {
+        UpstreamSelector::new(
+            &["127.0.0.1:8081".to_string(), "127.0.0.1:8082".to_string()],
+            LoadBalancingStrategy::RoundRobin,
+            false,
+        )
+        .unwrap()
+    }
+
+    fn retry<'a>(status: u16, method: &'a Method, has_body: bool) -> StatusRetry<'a> {
+        StatusRetry {
+            retry_on_status: &[502, 503],
+            status,
+            method,
+            has_body,
+            deadline: Some(Instant::now() + Duration::from_secs(5)),
+        }
+    }
+
+    #[test]
+    fn test_503_retries_on_another_upstream() {
+        let selector = selector();
+        let first = selector.servers()[0].clone();
+
+        let next = retry(503, &Method::GET, false)
+            .next_upstream(&selector, &first, &[])
+            .expect("503 should be retried");
+        assert!(!Arc::ptr_eq(&next, &first));
+
+        // Both upstreams have now answered with 503
+        assert!(retry(503, &Method::GET, false)
+            .next_upstream(&selector, &next, &[first])
+            .is_none());
+    }
+
+    #[test]
+    fn test_200_not_retried() {
+        let selector = selector();
+        let first = selector.servers()[0].clone();
+        assert!(retry(200, &Method::GET, false)
+            .next_upstream(&selector, &first, &[])
+            .is_none());
+    }
+
+    #[test]
+    fn test_request_declares_body() {
+        let mut headers = HeaderMap::new();
+        assert!(!request_declares_body(&headers));
+        headers.insert("content-length", "0".parse().unwrap());
+        assert!(!request_declares_body(&headers));
+        headers.insert("content-length", "12".parse().unwrap());
+        assert!(request_declares_body(&headers));
+
+        let mut chunked = HeaderMap::new();
+        chunked.insert("transfer-encoding", "chunked".parse().unwrap());
+        assert!(request_declares_body(&chunked));
+    }
+
+    #[test]
+    fn test_exponential_backoff_grows() {
+        let backoff = RetryBackoff {
+            interval: Duration::from_millis(100),
+            mode: RetryBackoffMode::Exponential,
+            jitter: 0.0,
+        };
+        let delays: Vec<_> = (1..=4).map(|attempt| backoff.delay(attempt)).collect();
+        assert_eq!(
+            delays,
+            [100, 200, 400, 800].map(Duration::from_millis).to_vec()
+        );
+        // Capped, never overflowing
+        assert_eq!(backoff.delay(1000), Duration::from_millis(100) * (1 << MAX_DOUBLINGS));
+
+        let fixed = RetryBackoff {
+            mode: RetryBackoffMode::Fixed,
+            ..backoff
+        };
+        assert_eq!(fixed.delay(1), fixed.delay(4));
+    }
+
+    #[test]
+    fn test_jitter_varies_interval() {
+        let backoff = RetryBackoff {
+            interval: Duration::from_millis(1000),
+            mode: RetryBackoffMode::Fixed,
+            jitter: 0.5,
+        };
+        assert_eq!(backoff.delay_with(1, 0.0), Duration::from_millis(500));
+        assert_eq!(backoff.delay_with(1, 0.5), Duration::from_millis(1000));
+        assert!(backoff.delay_with(1, 0.999) < Duration::from_millis(1500));
+
+        let delays: Vec<_> = (0..20).map(|_| backoff.delay(1)).collect();
+        assert!(delays.iter().all(|d| (Duration::from_millis(500)..=Duration::from_millis(1500)).contains(d)));
+        assert!(delays.iter().any(|d| *d != delays[0]), "{:?}", delays);
+    }
+
+    #[test]
+    fn test_unsafe_requests_not_retried() {
+        assert!(!retry(503, &Method::POST, false).is_retryable());
+        assert!(!retry(503, &Method::PUT, true).is_retryable());
+        assert!(retry(503, &Method::PUT, false).is_retryable());
+
+        let mut expired = retry(503, &Method::GET, false);
+        expired.deadline = Some(Instant::now() - Duration::from_millis(1));
+        assert!(!expired.is_retryable());
+        expired.deadline = None;
+        assert!(!expired.is_retryable());
+    }
+}
@@ -1,9 +1,15 @@
 //! Request and response rewriting functionality
 
+use crate::compression::should_compress_content_type;
+use bytes::Bytes;
 use config::RewriteConfig;
+use pingora_http::{RequestHeader, ResponseHeader};
 use regex::Regex;
 use std::collections::HashMap;
-use tracing::debug;
+use tracing::{debug, warn};
+
+/// Largest response body buffered for `response_body_replace` by default
+pub const DEFAULT_MAX_REWRITE_BODY_SIZE: usize = 1024 * 1024;
 
 /// Compiled rewrite rules for efficient execution
 pub struct CompiledRewrite {
@@ -19,6 +25,9 @@ pub struct CompiledRewrite {
     /// Replace entire path
     pub replace_path: Option<String>,
 
+    /// Compiled regex for capture headers, with (header, template) pairs
+    pub capture_headers: Option<(Regex, Vec<(String, String)>)>,
+
     /// Headers to add to request (won't override)
     pub request_headers_add: HashMap<String, String>,
 
@@ -36,6 +45,23 @@ pub struct CompiledRewrite {
 
     /// Headers to remove from response
     pub response_headers_delete: Vec<String>,
+
+    /// Client-facing statuses keyed by upstream status
+    pub status_map: HashMap<u16, StatusRewrite>,
+
+    /// Literal (from, to) substitutions in response bodies
+    pub body_replacements: Vec<(String, String)>,
+
+    /// Largest response body buffered for substitution
+    pub max_rewrite_body_size: usize,
+}
+
+/// Replacement for one upstream status
+#[derive(Debug, Clone)]
+pub struct StatusRewrite {
+    pub status: u16,
+    /// Body and its Content-Type replacing the upstream body
+    pub body: Option<(Bytes, String)>,
 }
 
 impl CompiledRewrite {
@@ -48,17 +74,40 @@ impl CompiledRewrite {
             None
         };
 
+        let capture_headers = if let Some(ref ch) = config.capture_headers {
+            let regex = Regex::new(&ch.pattern)?;
+            let headers = ch.header_map.iter().map(|(k, v)| (k.clone(), v.clone())).collect();
+            Some((regex, headers))
+        } else {
+            None
+        };
+
         Ok(Self {
             strip_path_prefix: config.strip_path_prefix.clone(),
             add_path_prefix: config.add_path_prefix.clone(),
             path_regex,
             replace_path: config.replace_path.clone(),
+            capture_headers,
             request_headers_add: config.request_headers_add.clone(),
             request_headers_set: config.request_headers_set.clone(),
             request_headers_delete: config.request_headers_delete.clone(),
             response_headers_add: config.response_headers_add.clone(),
             response_headers_set: config.response_headers_set.clone(),
             response_headers_delete: config.response_headers_delete.clone(),
+            status_map: config
+                .status_map
+                .iter()
+                .map(|m| {
+                    let body = m.body.as_ref().map(|b| (Bytes::from(b.clone()), m.content_type.clone()));
+                    (m.from, StatusRewrite { status: m.to, body })
+                })
+                .collect(),
+            body_replacements: config
+                .response_body_replace
+                .iter()
+                .map(|r| (r.from.clone(), r.to.clone()))
+                .collect(),
+            max_rewrite_body_size: config.max_rewrite_body_size.unwrap_or(DEFAULT_MAX_REWRITE_BODY_SIZE),
         })
     }
 
@@ -102,6 +151,99 @@ impl CompiledRewrite {
         path
     }
 
+    /// Set capture headers on an upstream request from its (not yet rewritten) path
+    ///
+    /// Mapped headers sent by the client are always dropped, so when the path
+    /// doesn't match the upstream never sees a client-chosen value.
+    pub fn apply_capture_headers(&self, request: &mut RequestHeader) -> pingora_error::Result<()> {
+        let Some((ref regex, ref headers)) = self.capture_headers else {
+            return Ok(());
+        };
+
+        let path = request.uri.path().to_string();
+        let captures = regex.captures(&path);
+        for (name, template) in headers {
+            request.remove_header(name);
+            if let Some(captures) = &captures {
+                let mut value = String::new();
+                captures.expand(template, &mut value);
+                if !value.is_empty() {
+                    debug!(header = %name, value = %value, "Set header from path capture");
+                    request.insert_header(name.clone(), value)?;
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Replace a mapped upstream status on the response
+    ///
+    /// Returns the body that must replace the upstream's, if the mapping has one;
+    /// the response headers already describe that body.
+    pub fn apply_status_map(&self, response: &mut ResponseHeader) -> pingora_error::Result<Option<Bytes>> {
+        let upstream_status = response.status.as_u16();
+        let Some(mapping) = self.status_map.get(&upstream_status) else {
+            return Ok(None);
+        };
+
+        debug!(upstream = upstream_status, status = mapping.status, "Rewriting response status");
+        response.set_status(mapping.status)?;
+
+        let Some((body, content_type)) = &mapping.body else {
+            return Ok(None);
+        };
+        // Headers describing the upstream body no longer apply
+        for name in ["content-encoding", "transfer-encoding", "etag", "last-modified", "content-range"] {
+            response.remove_header(name);
+        }
+        response.insert_header("Content-Type", content_type.as_str())?;
+        response.insert_header("Content-Length", body.len().to_string())?;
+        Ok(Some(body.clone()))
+    }
+
+    /// Whether the body of a response with these headers is rewritten
+    ///
+    /// Only uncompressed text bodies are; a declared length over
+    /// `max_rewrite_body_size` streams through unmodified.
+    pub fn rewrites_body(&self, response: &ResponseHeader) -> bool {
+        if self.body_replacements.is_empty() || response.headers.contains_key("content-encoding") {
+            return false;
+        }
+        let content_type = response.headers.get("content-type").and_then(|v| v.to_str().ok());
+        if !should_compress_content_type(content_type) {
+            return false;
+        }
+        let content_length = response
+            .headers
+            .get("content-length")
+            .and_then(|v| v.to_str().ok())
+            .and_then(|v| v.parse::<usize>().ok());
+        if let Some(length) = content_length.filter(|&len| len > self.max_rewrite_body_size) {
+            warn!(
+                content_length = length,
+                max_rewrite_body_size = self.max_rewrite_body_size,
+                "Response too large to rewrite, streaming it unmodified"
+            );
+            return false;
+        }
+        true
+    }
+
+    /// Apply the body substitutions, `None` when nothing changed
+    ///
+    /// Bodies that aren't valid UTF-8 are left alone.
+    pub fn rewrite_body(&self, body: &[u8]) -> Option<Bytes> {
+        let text = std::str::from_utf8(body).ok()?;
+        if !self.body_replacements.iter().any(|(from, _)| text.contains(from.as_str())) {
+            return None;
+        }
+        let mut rewritten = text.to_string();
+        for (from, to) in &self.body_replacements {
+            rewritten = rewritten.replace(from.as_str(), to);
+        }
+        Some(Bytes::from(rewritten))
+    }
+
     /// Check if this rewrite has any path modifications
     pub fn has_path_rewrite(&self) -> bool {
         self.strip_path_prefix.is_some()
@@ -115,6 +257,7 @@ impl CompiledRewrite {
         !self.request_headers_add.is_empty()
             || !self.request_headers_set.is_empty()
             || !self.request_headers_delete.is_empty()
+            || self.capture_headers.is_some()
     }
 
     /// Check if this rewrite has any response header modifications
@@ -146,7 +289,7 @@ pub fn rewrite_uri(uri: &str, rewrite: &CompiledRewrite) -> String {
 #[cfg(test)]
 mod tests {
     use super::*;
-    use config::PathRegex;
+    use config::{BodyReplacement, CaptureHeaders, PathRegex, StatusMapping};
 
     fn make_config() -> RewriteConfig {
         RewriteConfig::default()
@@ -253,6 +396,104 @@ mod tests {
         assert!(rewrite.has_response_header_rewrite());
     }
 
+    fn capture_tenant() -> CompiledRewrite {
+        let mut config = make_config();
+        config.capture_headers = Some(CaptureHeaders {
+            pattern: r"^/t/(?P<tenant>[^/]+)/(\w+)".to_string(),
+            header_map: HashMap::from([
+                ("X-Tenant".to_string(), "$tenant".to_string()),
+                ("X-Service".to_string(), "svc-$2".to_string()),
+            ]),
+        });
+        CompiledRewrite::from_config(&config).unwrap()
+    }
+
+    fn header<'a>(request: &'a RequestHeader, name: &str) -> Vec<&'a str> {
+        request.headers.get_all(name).iter().map(|v| v.to_str().unwrap()).collect()
+    }
+
+    #[test]
+    fn test_capture_headers_from_path() {
+        let rewrite = capture_tenant();
+        assert!(rewrite.has_request_header_rewrite());
+
+        let mut request = RequestHeader::build("GET", b"/t/acme/api?page=2", None).unwrap();
+        request.insert_header("X-Tenant", "spoofed").unwrap();
+        rewrite.apply_capture_headers(&mut request).unwrap();
+
+        assert_eq!(header(&request, "x-tenant"), vec!["acme"]);
+        assert_eq!(header(&request, "x-service"), vec!["svc-api"]);
+    }
+
+    #[test]
+    fn test_capture_headers_without_match() {
+        let rewrite = capture_tenant();
+        let mut request = RequestHeader::build("GET", b"/public/page", None).unwrap();
+        request.insert_header("X-Tenant", "spoofed").unwrap();
+        rewrite.apply_capture_headers(&mut request).unwrap();
+
+        assert!(request.headers.get("x-tenant").is_none());
+        assert!(request.headers.get("x-service").is_none());
+    }
+
+    fn status_rewrite() -> CompiledRewrite {
+        let mut config = make_config();
+        config.status_map = vec![
+            StatusMapping {
+                from: 500,
+                to: 503,
+                body: None,
+                content_type: "text/plain; charset=utf-8".to_string(),
+            },
+            StatusMapping {
+                from: 404,
+                to: 200,
+                body: Some("{\"items\":[]}".to_string()),
+                content_type: "application/json".to_string(),
+            },
+        ];
+        CompiledRewrite::from_config(&config).unwrap()
+    }
+
+    #[test]
+    fn test_status_map_500_as_503() {
+        let mut response = ResponseHeader::build(500, None).unwrap();
+        response.insert_header("Content-Length", "21").unwrap();
+
+        let body = status_rewrite().apply_status_map(&mut response).unwrap();
+        assert_eq!(response.status.as_u16(), 503);
+        assert!(body.is_none());
+        assert_eq!(response.headers["content-length"], "21");
+    }
+
+    #[test]
+    fn test_status_map_replacement_body() {
+        let mut response = ResponseHeader::build(404, None).unwrap();
+        response.insert_header("Content-Type", "text/html").unwrap();
+        response.insert_header("Content-Encoding", "gzip").unwrap();
+        response.insert_header("Content-Length", "512").unwrap();
+
+        let body = status_rewrite().apply_status_map(&mut response).unwrap();
+        assert_eq!(response.status.as_u16(), 200);
+        assert_eq!(body.as_deref(), Some(&b"{\"items\":[]}"[..]));
+        assert_eq!(response.headers["content-type"], "application/json");
+        assert_eq!(response.headers["content-length"], "12");
+        assert!(response.headers.get("content-encoding").is_none());
+    }
+
+    #[test]
+    fn test_status_map_unmapped_passes_through() {
+        let mut response = ResponseHeader::build(502, None).unwrap();
+        let body = status_rewrite().apply_status_map(&mut response).unwrap();
+        assert_eq!(response.status.as_u16(), 502);
+        assert!(body.is_none());
+
+        let mut response = ResponseHeader::build(500, None).unwrap();
+        let body = CompiledRewrite::from_config(&make_config()).unwrap().apply_status_map(&mut response).unwrap();
+        assert_eq!(response.status.as_u16(), 500);
+        assert!(body.is_none());
+    }
+
     #[test]
     fn test_invalid_regex() {
         let mut config = make_config();
@@ -263,4 +504,50 @@ mod tests {
 
         assert!(CompiledRewrite::from_config(&config).is_err());
     }
+
+    fn body_rewrite(max: usize) -> CompiledRewrite {
+        let mut config = make_config();
+        config.response_body_replace = vec![BodyReplacement {
+            from: "http://backend:3000".to_string(),
+            to: "https://example.com".to_string(),
+        }];
+        config.max_rewrite_body_size = Some(max);
+        CompiledRewrite::from_config(&config).unwrap()
+    }
+
+    fn text_response(length: usize) -> ResponseHeader {
+        let mut response = ResponseHeader::build(200, None).unwrap();
+        response.insert_header("Content-Type", "text/html; charset=utf-8").unwrap();
+        response.insert_header("Content-Length", length.to_string()).unwrap();
+        response
+    }
+
+    #[test]
+    fn test_small_body_rewritten() {
+        let rewrite = body_rewrite(1024);
+        let body = b"<a href=\"http://backend:3000/login\">http://backend:3000</a>";
+        assert!(rewrite.rewrites_body(&text_response(body.len())));
+        assert_eq!(
+            rewrite.rewrite_body(body).unwrap(),
+            Bytes::from("<a href=\"https://example.com/login\">https://example.com</a>")
+        );
+        assert!(rewrite.rewrite_body(b"nothing to replace").is_none());
+        assert!(rewrite.rewrite_body(b"\xff\xfe http://backend:3000").is_none());
+    }
+
+    #[test]
+    fn test_oversized_or_binary_body_not_rewritten() {
+        let rewrite = body_rewrite(1024);
+        assert!(!rewrite.rewrites_body(&text_response(4096)));
+
+        let mut image = text_response(100);
+        image.insert_header("Content-Type", "image/png").unwrap();
+        assert!(!rewrite.rewrites_body(&image));
+
+        let mut gzipped = text_response(100);
+        gzipped.insert_header("Content-Encoding", "gzip").unwrap();
+        assert!(!rewrite.rewrites_body(&gzipped));
+
+        assert!(!CompiledRewrite::from_config(&make_config()).unwrap().rewrites_body(&text_response(100)));
+    }
 }
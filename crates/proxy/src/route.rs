@@ -1,18 +1,54 @@
 //! Route matching and routing table
 
+use crate::access_control::AccessControl;
+use crate::access_log::RouteAccessLogger;
 use crate::auth::CompiledAuth;
+use crate::cache::CacheKeyQuery;
+use crate::canary::CanaryRouter;
+use crate::circuit_breaker::{CircuitBreakerConfig, CircuitBreakerStats};
+use crate::client_cert::{ClientCert, ClientCertMatcher};
 use crate::cors::CompiledCors;
-use crate::error::Result;
+use crate::error::{ProxyError, Result};
+use crate::fault_injection::FaultInjector;
+use crate::forward_auth::ForwardAuth;
+use crate::idempotency::IdempotencyCache;
+use crate::maintenance::Maintenance;
 use crate::ip_filter::{CompiledIpFilter, IpFilterConfig};
+use crate::rate_limit::{RateLimitConfig, RateLimitRejection, RateLimiter};
+use crate::redirect_rewrite::CompiledRedirectRewrite;
 use crate::rewrite::CompiledRewrite;
 use crate::rhai_rewrite::{RhaiRewriteConfig, RhaiRewriteEngine};
+use crate::route_index::PathIndex;
 use crate::script_handler::CompiledScriptHandler;
+use crate::static_response::response_body;
 use crate::upstream::UpstreamSelector;
-use config::{HandlerConfig, MatchConfig, RouteConfig, ServerConfig};
+use crate::upstream_auth::UpstreamAuth;
+use crate::user_agent::UserAgentMatcher;
+use bytes::Bytes;
+use config::{HandlerConfig, MatchConfig, RouteConfig, ServerConfig, TrailingSlash};
 use parking_lot::RwLock;
+use std::collections::{HashMap, HashSet};
 use std::sync::Arc;
 use tracing::{debug, warn};
 
+/// Methods listed in `Allow` for routes without a method matcher
+const ANY_METHOD: &[&str] = &["GET", "HEAD", "POST", "PUT", "PATCH", "DELETE", "OPTIONS"];
+
+/// Which route would handle a request, for tooling and tests
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RouteMatchInfo {
+    /// Name of the server whose route matched
+    pub server: String,
+    /// Position of the route in the server's `routes`
+    pub route_index: usize,
+    /// Handler type as written in config, e.g. `reverse_proxy`
+    pub handler: &'static str,
+    /// Upstream addresses of a reverse proxy route
+    pub upstreams: Vec<String>,
+    /// Location the route redirects to under its trailing slash policy
+    pub redirect: Option<String>,
+}
+
 /// A compiled route ready for matching
 pub struct CompiledRoute {
     pub matcher: MatchConfig,
@@ -24,6 +60,37 @@ pub struct CompiledRoute {
     pub cors: Option<Arc<CompiledCors>>,
     pub script_handler: Option<Arc<CompiledScriptHandler>>,
     pub ip_filter: Option<Arc<CompiledIpFilter>>,
+    pub redirect_rewrite: Option<Arc<CompiledRedirectRewrite>>,
+    pub idempotency: Option<Arc<IdempotencyCache>>,
+    pub canary: Option<Arc<CanaryRouter>>,
+    pub trailing_slash: TrailingSlash,
+    pub access_log: Option<Arc<RouteAccessLogger>>,
+    pub fault_injection: Option<Arc<FaultInjector>>,
+    pub trusted_proxies: Option<Arc<CompiledIpFilter>>,
+    pub response_hints: Option<Arc<Vec<String>>>,
+    pub forward_auth: Option<Arc<ForwardAuth>>,
+    pub rate_limiter: Option<Arc<RateLimiter>>,
+    pub maintenance: Option<Arc<Maintenance>>,
+    /// Compiled `match.user_agent` patterns
+    pub user_agent: Option<Arc<UserAgentMatcher>>,
+    /// Compiled `block_user_agents` patterns
+    pub blocked_user_agents: Option<Arc<UserAgentMatcher>>,
+    pub block_user_agents_status: u16,
+    /// Compiled `match.client_cert` conditions
+    pub client_cert: Option<ClientCertMatcher>,
+    pub upstream_auth: Option<Arc<UpstreamAuth>>,
+    /// Query parameters kept in the route's cache keys
+    pub cache_key_query: Option<Arc<CacheKeyQuery>>,
+    /// Body of a `static_response` handler, built once per config
+    pub static_body: Option<Bytes>,
+}
+
+/// Outcome of resolving a request against a route table
+pub enum RouteMatch<'a> {
+    /// A route matched the request
+    Matched(&'a CompiledRoute),
+    /// A route matches once the trailing slash is toggled; redirect to this path
+    Redirect(String),
 }
 
 impl CompiledRoute {
@@ -35,6 +102,11 @@ impl CompiledRoute {
                     proxy_config.load_balancing.clone(),
                     proxy_config.upstream_tls,
                 )?;
+                if let Some(circuit_breaker) = &proxy_config.circuit_breaker {
+                    for server in selector.servers() {
+                        server.set_circuit_breaker(CircuitBreakerConfig::from_config(circuit_breaker));
+                    }
+                }
 
                 // Compile rewrite rules if configured
                 let compiled_rewrite = if let Some(ref rewrite_config) = proxy_config.rewrite {
@@ -96,7 +168,7 @@ impl CompiledRoute {
 
                 // Compile auth rules if configured
                 let compiled_auth = if let Some(ref auth_config) = proxy_config.auth {
-                    let auth = CompiledAuth::from_config(auth_config);
+                    let auth = CompiledAuth::from_config(auth_config)?;
                     if auth.has_auth() {
                         debug!("Compiled auth rules for route");
                         Some(Arc::new(auth))
@@ -154,6 +226,127 @@ impl CompiledRoute {
             _ => (None, None, None, None, None, None, None),
         };
 
+        // Compile upstream redirect header rewriting if configured
+        let redirect_rewrite = match &config.handle {
+            HandlerConfig::ReverseProxy(proxy_config) => proxy_config
+                .redirect_rewrite
+                .as_ref()
+                .map(|r| CompiledRedirectRewrite::from_config(r, &proxy_config.upstreams))
+                .filter(|r| !r.is_empty())
+                .map(Arc::new),
+            _ => None,
+        };
+
+        // Create the Idempotency-Key replay cache if configured
+        let idempotency = match &config.handle {
+            HandlerConfig::ReverseProxy(proxy_config) => proxy_config
+                .idempotency
+                .as_ref()
+                .map(|i| Arc::new(IdempotencyCache::new(i))),
+            _ => None,
+        };
+
+        // Compile the flag-controlled canary group if configured
+        let canary = match &config.handle {
+            HandlerConfig::ReverseProxy(proxy_config) => match &proxy_config.canary {
+                Some(canary_config) => Some(Arc::new(CanaryRouter::from_config(
+                    canary_config,
+                    proxy_config.load_balancing.clone(),
+                    proxy_config.upstream_tls,
+                )?)),
+                None => None,
+            },
+            _ => None,
+        };
+
+        // External auth service consulted before proxying
+        let forward_auth = match &config.handle {
+            HandlerConfig::ReverseProxy(proxy_config) => proxy_config.auth.as_ref().and_then(|auth| {
+                auth.forward_auth
+                    .as_ref()
+                    .map(|f| Arc::new(ForwardAuth::from_config(f, &auth.exclude_paths)))
+            }),
+            _ => None,
+        };
+
+        // Per-client-IP request rate limit
+        let rate_limiter = match &config.handle {
+            HandlerConfig::ReverseProxy(proxy_config) => proxy_config.rate_limit.as_ref().map(|options| {
+                let mut limit = RateLimitConfig::new(options.max_requests, options.window)
+                    .with_max_tracked_clients(options.max_tracked_clients);
+                if let Some(burst) = options.burst {
+                    limit = limit.with_burst(burst);
+                }
+                let mut reject_headers: Vec<_> =
+                    options.reject_headers.iter().map(|(k, v)| (k.clone(), v.clone())).collect();
+                reject_headers.sort();
+                limit = limit.with_rejection(RateLimitRejection {
+                    status: options.reject_status,
+                    body: options.reject_body.clone(),
+                    content_type: options.reject_content_type.clone(),
+                    headers: reject_headers,
+                    retry_after: options.retry_after,
+                });
+                Arc::new(RateLimiter::new(limit))
+            }),
+            _ => None,
+        };
+
+        // Maintenance mode and its preview tokens
+        let maintenance = match &config.handle {
+            HandlerConfig::ReverseProxy(proxy_config) => {
+                proxy_config.maintenance.as_ref().map(|m| Arc::new(Maintenance::from_config(m)))
+            }
+            _ => None,
+        };
+
+        let user_agent = match &config.match_rule.user_agent {
+            Some(patterns) => Some(Arc::new(compile_user_agents("match.user_agent", patterns)?)),
+            None => None,
+        };
+        let blocked_user_agents = if config.block_user_agents.is_empty() {
+            None
+        } else {
+            Some(Arc::new(compile_user_agents("block_user_agents", &config.block_user_agents)?))
+        };
+
+        let upstream_auth = match &config.handle {
+            HandlerConfig::ReverseProxy(proxy_config) => match &proxy_config.upstream_auth {
+                Some(auth_config) => Some(Arc::new(UpstreamAuth::from_config(auth_config)?)),
+                None => None,
+            },
+            _ => None,
+        };
+
+        let cache_key_query = match &config.handle {
+            HandlerConfig::ReverseProxy(proxy_config) => {
+                proxy_config.cache_key_query.as_ref().map(|q| Arc::new(CacheKeyQuery::from_config(q)))
+            }
+            _ => None,
+        };
+
+        // Peers allowed to set X-Forwarded-Proto for the client
+        let trusted_proxies = match &config.handle {
+            HandlerConfig::ReverseProxy(proxy_config) if !proxy_config.trusted_proxies.is_empty() => {
+                Some(Arc::new(CompiledIpFilter::from_config(&IpFilterConfig {
+                    allow: proxy_config.trusted_proxies.clone(),
+                    deny: Vec::new(),
+                })))
+            }
+            _ => None,
+        };
+
+        let static_body = match &config.handle {
+            HandlerConfig::StaticResponse(static_config) => Some(response_body(static_config)),
+            _ => None,
+        };
+
+        // Open the route's own access log if it overrides the global one
+        let access_log = match &config.access_log {
+            Some(access_log_config) => Some(Arc::new(RouteAccessLogger::from_config(access_log_config)?)),
+            None => None,
+        };
+
         Ok(Self {
             matcher: config.match_rule.clone(),
             handler: config.handle.clone(),
@@ -164,12 +357,61 @@ impl CompiledRoute {
             cors,
             script_handler,
             ip_filter,
+            redirect_rewrite,
+            idempotency,
+            canary,
+            trailing_slash: config.trailing_slash,
+            access_log,
+            fault_injection: config.fault_injection.as_ref().map(|f| Arc::new(FaultInjector::from_config(f))),
+            trusted_proxies,
+            response_hints: (!config.response_hints.is_empty()).then(|| Arc::new(config.response_hints.clone())),
+            forward_auth,
+            rate_limiter,
+            maintenance,
+            user_agent,
+            blocked_user_agents,
+            block_user_agents_status: config.block_user_agents_status,
+            client_cert: ClientCertMatcher::from_config(&config.match_rule),
+            upstream_auth,
+            cache_key_query,
+            static_body,
         })
     }
 
     pub fn matches(&self, host: Option<&str>, path: &str, method: &str) -> bool {
         self.matcher.matches(host, path, method)
     }
+
+    /// Whether the `match.user_agent` condition, if any, accepts the request
+    pub fn matches_user_agent(&self, user_agent: Option<&str>) -> bool {
+        self.user_agent.as_ref().is_none_or(|m| m.matches(user_agent))
+    }
+
+    /// Whether the `match.client_cert` conditions, if any, accept the connection
+    pub fn matches_client_cert(&self, cert: Option<&ClientCert>) -> bool {
+        self.client_cert.as_ref().is_none_or(|m| m.matches(cert))
+    }
+
+    /// Status to refuse the request with when its User-Agent is blocked
+    pub fn blocked_user_agent_status(&self, user_agent: Option<&str>) -> Option<u16> {
+        self.blocked_user_agents
+            .as_ref()
+            .filter(|m| m.matches(user_agent))
+            .map(|_| self.block_user_agents_status)
+    }
+
+    /// Path to retry matching with when the trailing slash policy allows it
+    fn alternate_path(&self, path: &str) -> Option<String> {
+        match self.trailing_slash {
+            TrailingSlash::Strict => None,
+            TrailingSlash::Redirect | TrailingSlash::Ignore => TrailingSlash::toggle(path),
+        }
+    }
+}
+
+fn compile_user_agents(field: &str, patterns: &[String]) -> Result<UserAgentMatcher> {
+    UserAgentMatcher::new(patterns)
+        .map_err(|e| ProxyError::ConfigError(format!("invalid {} pattern: {}", field, e)))
 }
 
 /// Route table for a server
@@ -177,28 +419,133 @@ pub struct RouteTable {
     pub routes: Vec<CompiledRoute>,
     server_name: String,
     pub https_redirect: bool,
+    /// Server-wide access policy, checked before the matched route runs
+    pub access_control: Option<Arc<AccessControl>>,
+    /// Routes restricted to each host, by path prefix
+    host_index: HashMap<String, PathIndex>,
+    /// Routes without a host matcher, by path prefix
+    any_host: PathIndex,
 }
 
 impl RouteTable {
     pub fn from_config(config: &ServerConfig) -> Result<Self> {
-        let routes: Result<Vec<_>> = config.routes.iter().map(CompiledRoute::from_config).collect();
+        let routes: Vec<_> = config.routes.iter().map(CompiledRoute::from_config).collect::<Result<_>>()?;
+
+        let mut host_index: HashMap<String, PathIndex> = HashMap::new();
+        let mut any_host = PathIndex::default();
+        for (idx, route) in routes.iter().enumerate() {
+            match route.matcher.candidate_hosts() {
+                Some(hosts) => {
+                    for host in hosts {
+                        host_index.entry(host.clone()).or_default().insert(idx, &route.matcher);
+                    }
+                }
+                None => any_host.insert(idx, &route.matcher),
+            }
+        }
 
         Ok(Self {
-            routes: routes?,
+            routes,
             server_name: config.name.clone(),
             https_redirect: config.https_redirect,
+            access_control: config.access_control.as_ref().map(AccessControl::from_config).transpose()?.map(Arc::new),
+            host_index,
+            any_host,
         })
     }
 
+    /// Indices of the routes that can match `host` and any of `paths`, in config order
+    ///
+    /// Only these need to be evaluated, which keeps matching fast for
+    /// configs with many virtual hosts or path prefixes while preserving
+    /// first-match order.
+    fn candidates(&self, host: Option<&str>, paths: &[&str]) -> Vec<usize> {
+        let hosted = host.and_then(|h| self.host_index.get(h));
+        let mut candidates = Vec::new();
+        for path in paths {
+            if let Some(hosted) = hosted {
+                hosted.collect(path, &mut candidates);
+            }
+            self.any_host.collect(path, &mut candidates);
+        }
+        candidates.sort_unstable();
+        candidates.dedup();
+        candidates
+    }
+
     pub fn should_redirect_https(&self) -> bool {
         self.https_redirect
     }
 
     pub fn match_route(&self, host: Option<&str>, path: &str, method: &str) -> Option<&CompiledRoute> {
-        for route in &self.routes {
+        match self.resolve_route(host, path, method) {
+            Some(RouteMatch::Matched(route)) => Some(route),
+            _ => None,
+        }
+    }
+
+    /// Name of the server this table routes for
+    pub fn server_name(&self) -> &str {
+        &self.server_name
+    }
+
+    /// Resolve a request to a route, applying each route's trailing slash policy
+    ///
+    /// The request is treated as having no User-Agent and no client
+    /// certificate; see `resolve_request`.
+    pub fn resolve_route(&self, host: Option<&str>, path: &str, method: &str) -> Option<RouteMatch<'_>> {
+        self.resolve_request(host, path, method, None, None)
+    }
+
+    /// Resolve a request to a route, including its `match.user_agent` and
+    /// `match.client_cert` conditions
+    pub fn resolve_request(
+        &self,
+        host: Option<&str>,
+        path: &str,
+        method: &str,
+        user_agent: Option<&str>,
+        client_cert: Option<&ClientCert>,
+    ) -> Option<RouteMatch<'_>> {
+        match self.resolve_index(host, path, method, user_agent, client_cert)? {
+            (_, Some(location)) => Some(RouteMatch::Redirect(location)),
+            (idx, None) => Some(RouteMatch::Matched(&self.routes[idx])),
+        }
+    }
+
+    /// Position of the route handling a request, with its redirect location
+    /// if the route redirects instead
+    fn resolve_index(
+        &self,
+        host: Option<&str>,
+        path: &str,
+        method: &str,
+        user_agent: Option<&str>,
+        client_cert: Option<&ClientCert>,
+    ) -> Option<(usize, Option<String>)> {
+        // Candidates for the path and its trailing-slash twin, in config order
+        let alternate = TrailingSlash::toggle(path);
+        let paths: Vec<&str> = std::iter::once(path).chain(alternate.as_deref()).collect();
+
+        for idx in self.candidates(host, &paths) {
+            let route = &self.routes[idx];
+            if !route.matches_user_agent(user_agent) || !route.matches_client_cert(client_cert) {
+                continue;
+            }
             if route.matches(host, path, method) {
                 debug!(server = %self.server_name, host = ?host, path = %path, "Route matched");
-                return Some(route);
+                return Some((idx, None));
+            }
+
+            if let Some(alternate) = route.alternate_path(path) {
+                if route.matches(host, &alternate, method) {
+                    if route.trailing_slash == TrailingSlash::Redirect {
+                        debug!(server = %self.server_name, path = %path, location = %alternate, "Trailing slash redirect");
+                        return Some((idx, Some(alternate)));
+                    }
+                    debug!(server = %self.server_name, host = ?host, path = %path, "Route matched ignoring trailing slash");
+                    return Some((idx, None));
+                }
             }
         }
 
@@ -206,6 +553,44 @@ impl RouteTable {
         None
     }
 
+    /// Methods accepted at a host and path, for an `Allow` header
+    ///
+    /// Collected from the method matchers of every route matching the target.
+    /// HEAD is implied by GET and OPTIONS is always listed. Returns `None` when
+    /// no route matches the target at all.
+    pub fn allowed_methods(&self, host: Option<&str>, path: &str) -> Option<Vec<String>> {
+        let mut matched = false;
+        let mut methods: Vec<String> = Vec::new();
+
+        let candidates = self.candidates(host, &[path]);
+        for route in candidates.iter().map(|&i| &self.routes[i]).filter(|r| r.matcher.matches_target(host, path)) {
+            matched = true;
+            let route_methods = match route.matcher.methods_for_target(host, path) {
+                Some(route_methods) => route_methods.iter().map(|m| m.to_ascii_uppercase()).collect(),
+                None => ANY_METHOD.iter().map(|m| m.to_string()).collect::<Vec<_>>(),
+            };
+            for method in route_methods {
+                if !methods.contains(&method) {
+                    methods.push(method);
+                }
+            }
+        }
+
+        if !matched {
+            return None;
+        }
+
+        if let Some(pos) = methods.iter().position(|m| m == "GET") {
+            if !methods.iter().any(|m| m == "HEAD") {
+                methods.insert(pos + 1, "HEAD".to_string());
+            }
+        }
+        if !methods.iter().any(|m| m == "OPTIONS") {
+            methods.push("OPTIONS".to_string());
+        }
+        Some(methods)
+    }
+
     pub fn len(&self) -> usize {
         self.routes.len()
     }
@@ -217,13 +602,41 @@ impl RouteTable {
 
 /// Global routing context
 pub struct RoutingContext {
-    tables: RwLock<Vec<Arc<RouteTable>>>,
+    tables: RwLock<LoadedTables>,
+}
+
+/// Route tables with the servers bucketed by the hosts they route
+#[derive(Default)]
+struct LoadedTables {
+    all: Vec<Arc<RouteTable>>,
+    /// Tables with routes restricted to a host, by position in `all`
+    by_host: HashMap<String, Vec<usize>>,
+    /// Tables with routes for any host
+    any_host: Vec<usize>,
+    /// Lowercased hosts served over TLS
+    tls_hosts: HashSet<String>,
+}
+
+impl LoadedTables {
+    fn new(all: Vec<Arc<RouteTable>>, tls_hosts: HashSet<String>) -> Self {
+        let mut by_host: HashMap<String, Vec<usize>> = HashMap::new();
+        let mut any_host = Vec::new();
+        for (idx, table) in all.iter().enumerate() {
+            for host in table.host_index.keys() {
+                by_host.entry(host.clone()).or_default().push(idx);
+            }
+            if !table.any_host.is_empty() {
+                any_host.push(idx);
+            }
+        }
+        Self { all, by_host, any_host, tls_hosts }
+    }
 }
 
 impl RoutingContext {
     pub fn new() -> Self {
         Self {
-            tables: RwLock::new(Vec::new()),
+            tables: RwLock::new(LoadedTables::default()),
         }
     }
 
@@ -233,28 +646,98 @@ impl RoutingContext {
             .map(|s| Ok(Arc::new(RouteTable::from_config(s)?)))
             .collect();
 
-        *self.tables.write() = tables?;
+        let tls_hosts = config::tls_domains(servers).iter().map(|h| h.to_ascii_lowercase()).collect();
+        *self.tables.write() = LoadedTables::new(tables?, tls_hosts);
         Ok(())
     }
 
     pub fn tables(&self) -> Vec<Arc<RouteTable>> {
-        self.tables.read().clone()
+        self.tables.read().all.clone()
+    }
+
+    /// Tables that may route a request for `host`, in config order
+    pub fn tables_for_host(&self, host: Option<&str>) -> Vec<Arc<RouteTable>> {
+        let tables = self.tables.read();
+        let hosted = host.and_then(|h| tables.by_host.get(h)).map(Vec::as_slice).unwrap_or(&[]);
+
+        let mut indices: Vec<usize> = hosted.iter().chain(&tables.any_host).copied().collect();
+        indices.sort_unstable();
+        indices.dedup();
+        indices.into_iter().map(|i| tables.all[i].clone()).collect()
+    }
+
+    /// Whether a plain HTTP request for `host` should be redirected to HTTPS
+    ///
+    /// Only hosts served by an HTTPS listener are redirected, so HTTP-only
+    /// hosts on a redirecting server keep working.
+    pub fn should_redirect_https(&self, host: &str) -> bool {
+        let tables = self.tables.read();
+        tables.all.iter().any(|t| t.should_redirect_https())
+            && tables.tls_hosts.contains(&host.to_ascii_lowercase())
+    }
+
+    /// Which route would handle a request, without sending one
+    ///
+    /// Uses the same table selection and matching as request handling.
+    pub fn resolve(&self, host: Option<&str>, path: &str, method: &str) -> Option<RouteMatchInfo> {
+        self.tables_for_host(host).iter().find_map(|table| {
+            let (route_index, redirect) = table.resolve_index(host, path, method, None, None)?;
+            let route = &table.routes[route_index];
+            Some(RouteMatchInfo {
+                server: table.server_name.clone(),
+                route_index,
+                handler: route.handler.type_name(),
+                upstreams: route
+                    .upstream
+                    .as_ref()
+                    .map(|u| u.servers().iter().map(|s| s.address_str.clone()).collect())
+                    .unwrap_or_default(),
+                redirect,
+            })
+        })
     }
 
     pub fn get_all_upstreams(&self) -> Vec<Arc<UpstreamSelector>> {
         let tables = self.tables.read();
         let mut upstreams = Vec::new();
 
-        for table in tables.iter() {
+        for table in tables.all.iter() {
             for route in &table.routes {
                 if let Some(upstream) = &route.upstream {
                     upstreams.push(upstream.clone());
                 }
+                if let Some(canary) = &route.canary {
+                    upstreams.push(canary.selector().clone());
+                }
             }
         }
 
         upstreams
     }
+
+    /// State of every upstream circuit breaker
+    pub fn circuits(&self) -> Vec<CircuitBreakerStats> {
+        self.get_all_upstreams()
+            .iter()
+            .flat_map(|selector| selector.servers().iter())
+            .filter_map(|server| server.circuit_breaker().map(|circuit| circuit.stats()))
+            .collect()
+    }
+
+    /// Close the circuits of an upstream, in every route using it; returns
+    /// how many were reset
+    pub fn reset_circuit(&self, upstream: &str) -> usize {
+        let mut reset = 0;
+        for selector in self.get_all_upstreams() {
+            for server in selector.servers().iter().filter(|s| s.address_str == upstream) {
+                if let Some(circuit) = server.circuit_breaker() {
+                    circuit.reset();
+                    reset += 1;
+                }
+            }
+        }
+        reset
+    }
 }
 
 impl Default for RoutingContext {
@@ -266,7 +749,7 @@ impl Default for RoutingContext {
 #[cfg(test)]
 mod tests {
     use super::*;
-    use config::{LoadBalancingStrategy, ReverseProxyConfig, StaticResponseConfig, RedirectConfig, TimeoutConfig};
+    use config::{LoadBalancingStrategy, ReverseProxyConfig, StaticResponseConfig, RedirectConfig, TimeoutConfig, ForwardedForMode, HostHeader, Http2Config, RetryBackoffMode};
     use std::collections::HashMap;
 
     fn make_test_config() -> ServerConfig {
@@ -279,11 +762,17 @@ mod tests {
                     path: Some(vec!["/api".to_string()]),
                     method: None,
                     header: None,
+                    path_not: None,
+                    user_agent: None,
+                    client_cert: None,
+                    client_cert_organization: None,
+                    any_of: None,
                 },
                 handle: HandlerConfig::ReverseProxy(Box::new(ReverseProxyConfig {
                     upstreams: vec!["127.0.0.1:9090".to_string()],
                     load_balancing: LoadBalancingStrategy::RoundRobin,
                     health_check: None,
+                    upstream_auth: None,
                     headers_up: Default::default(),
                     headers_down: Default::default(),
                     timeout: 30,
@@ -294,15 +783,42 @@ mod tests {
                     cors: None,
                     lb_try_duration: 0,
                     lb_try_interval: 250,
+                    lb_try_backoff: RetryBackoffMode::Fixed,
+                    lb_try_jitter: 0.0,
                     timeouts: TimeoutConfig::default(),
                     max_request_body_size: 0,
+                    request_buffer_limit: 0,
                     circuit_breaker: None,
                     ip_filter: None,
                     upstream_http2: false,
                     upstream_mtls: None,
+                    redirect_rewrite: None,
+                    idempotency: None,
+                    canary: None,
+                    trusted_proxies: Vec::new(),
+                    x_forwarded_for: ForwardedForMode::Append,
+                    host_header: HostHeader::Preserve,
+                    retry_on_status: Vec::new(),
+                    upstream_http2_fallback: true,
+                    rate_limit: None,
+                    maintenance: None,
+                    compress: true,
+                    cache_ttl_override: None,
+                    respect_upstream_cache_control: true,
+                    cache_key_query: None,
+                    head_as_get: false,
                 })),
+                trailing_slash: TrailingSlash::Strict,
+                access_log: None,
+                fault_injection: None,
+                response_hints: Vec::new(),
+                block_user_agents: Vec::new(),
+                block_user_agents_status: 403,
             }],
             https_redirect: false,
+            http2: Http2Config::default(),
+            access_control: None,
+            unix_socket_mode: None,
         }
     }
 
@@ -331,6 +847,9 @@ mod tests {
             listen: vec![":8080".to_string()],
             routes: vec![],
             https_redirect: false,
+            http2: Http2Config::default(),
+            access_control: None,
+            unix_socket_mode: None,
         };
         let table = RouteTable::from_config(&config).unwrap();
         assert!(table.is_empty());
@@ -348,12 +867,25 @@ mod tests {
                         path: Some(vec!["/api/v2".to_string()]),
                         method: None,
                         header: None,
+                        path_not: None,
+                        user_agent: None,
+                        client_cert: None,
+                        client_cert_organization: None,
+                        any_of: None,
                     },
                     handle: HandlerConfig::StaticResponse(StaticResponseConfig {
                         status: 200,
                         body: "v2".to_string(),
                         headers: HashMap::new(),
+                        delay_ms: 0,
+                        body_size: None,
                     }),
+                    trailing_slash: TrailingSlash::Strict,
+                    access_log: None,
+                    fault_injection: None,
+                    response_hints: Vec::new(),
+                    block_user_agents: Vec::new(),
+                    block_user_agents_status: 403,
                 },
                 RouteConfig {
                     match_rule: MatchConfig {
@@ -361,15 +893,31 @@ mod tests {
                         path: Some(vec!["/api".to_string()]),
                         method: None,
                         header: None,
+                        path_not: None,
+                        user_agent: None,
+                        client_cert: None,
+                        client_cert_organization: None,
+                        any_of: None,
                     },
                     handle: HandlerConfig::StaticResponse(StaticResponseConfig {
                         status: 200,
                         body: "v1".to_string(),
                         headers: HashMap::new(),
+                        delay_ms: 0,
+                        body_size: None,
                     }),
+                    trailing_slash: TrailingSlash::Strict,
+                    access_log: None,
+                    fault_injection: None,
+                    response_hints: Vec::new(),
+                    block_user_agents: Vec::new(),
+                    block_user_agents_status: 403,
                 },
             ],
             https_redirect: false,
+            http2: Http2Config::default(),
+            access_control: None,
+            unix_socket_mode: None,
         };
         let table = RouteTable::from_config(&config).unwrap();
 
@@ -391,12 +939,25 @@ mod tests {
                         path: None,
                         method: None,
                         header: None,
+                        path_not: None,
+                        user_agent: None,
+                        client_cert: None,
+                        client_cert_organization: None,
+                        any_of: None,
                     },
                     handle: HandlerConfig::StaticResponse(StaticResponseConfig {
                         status: 200,
                         body: "api".to_string(),
                         headers: HashMap::new(),
+                        delay_ms: 0,
+                        body_size: None,
                     }),
+                    trailing_slash: TrailingSlash::Strict,
+                    access_log: None,
+                    fault_injection: None,
+                    response_hints: Vec::new(),
+                    block_user_agents: Vec::new(),
+                    block_user_agents_status: 403,
                 },
                 RouteConfig {
                     match_rule: MatchConfig::default(),
@@ -404,10 +965,21 @@ mod tests {
                         status: 404,
                         body: "not found".to_string(),
                         headers: HashMap::new(),
+                        delay_ms: 0,
+                        body_size: None,
                     }),
+                    trailing_slash: TrailingSlash::Strict,
+                    access_log: None,
+                    fault_injection: None,
+                    response_hints: Vec::new(),
+                    block_user_agents: Vec::new(),
+                    block_user_agents_status: 403,
                 },
             ],
             https_redirect: false,
+            http2: Http2Config::default(),
+            access_control: None,
+            unix_socket_mode: None,
         };
         let table = RouteTable::from_config(&config).unwrap();
 
@@ -429,12 +1001,25 @@ mod tests {
                         path: Some(vec!["/api".to_string()]),
                         method: Some(vec!["POST".to_string()]),
                         header: None,
+                        path_not: None,
+                        user_agent: None,
+                        client_cert: None,
+                        client_cert_organization: None,
+                        any_of: None,
                     },
                     handle: HandlerConfig::StaticResponse(StaticResponseConfig {
                         status: 200,
                         body: "write".to_string(),
                         headers: HashMap::new(),
+                        delay_ms: 0,
+                        body_size: None,
                     }),
+                    trailing_slash: TrailingSlash::Strict,
+                    access_log: None,
+                    fault_injection: None,
+                    response_hints: Vec::new(),
+                    block_user_agents: Vec::new(),
+                    block_user_agents_status: 403,
                 },
                 RouteConfig {
                     match_rule: MatchConfig {
@@ -442,15 +1027,31 @@ mod tests {
                         path: Some(vec!["/api".to_string()]),
                         method: Some(vec!["GET".to_string()]),
                         header: None,
+                        path_not: None,
+                        user_agent: None,
+                        client_cert: None,
+                        client_cert_organization: None,
+                        any_of: None,
                     },
                     handle: HandlerConfig::StaticResponse(StaticResponseConfig {
                         status: 200,
                         body: "read".to_string(),
                         headers: HashMap::new(),
+                        delay_ms: 0,
+                        body_size: None,
                     }),
+                    trailing_slash: TrailingSlash::Strict,
+                    access_log: None,
+                    fault_injection: None,
+                    response_hints: Vec::new(),
+                    block_user_agents: Vec::new(),
+                    block_user_agents_status: 403,
                 },
             ],
             https_redirect: false,
+            http2: Http2Config::default(),
+            access_control: None,
+            unix_socket_mode: None,
         };
         let table = RouteTable::from_config(&config).unwrap();
 
@@ -467,6 +1068,58 @@ mod tests {
         assert!(table.match_route(None, "/api/resource", "DELETE").is_none());
     }
 
+    #[test]
+    fn test_allowed_methods() {
+        let route = |path: &str, methods: Option<Vec<&str>>| RouteConfig {
+            match_rule: MatchConfig {
+                host: None,
+                path: Some(vec![path.to_string()]),
+                method: methods.map(|m| m.into_iter().map(String::from).collect()),
+                header: None,
+                path_not: None,
+                user_agent: None,
+                client_cert: None,
+                client_cert_organization: None,
+                any_of: None,
+            },
+            handle: HandlerConfig::StaticResponse(StaticResponseConfig {
+                status: 200,
+                body: String::new(),
+                headers: HashMap::new(),
+                delay_ms: 0,
+                body_size: None,
+            }),
+            trailing_slash: TrailingSlash::Strict,
+            access_log: None,
+            fault_injection: None,
+            response_hints: Vec::new(),
+            block_user_agents: Vec::new(),
+            block_user_agents_status: 403,
+        };
+        let config = ServerConfig {
+            name: "methods".to_string(),
+            listen: vec![":8080".to_string()],
+            routes: vec![
+                route("/read", Some(vec!["GET"])),
+                route("/write", Some(vec!["post", "PUT"])),
+                route("/any", None),
+            ],
+            https_redirect: false,
+            http2: Http2Config::default(),
+            access_control: None,
+            unix_socket_mode: None,
+        };
+        let table = RouteTable::from_config(&config).unwrap();
+
+        assert_eq!(table.allowed_methods(None, "/read").unwrap().join(", "), "GET, HEAD, OPTIONS");
+        assert_eq!(table.allowed_methods(None, "/write/1").unwrap().join(", "), "POST, PUT, OPTIONS");
+        assert_eq!(
+            table.allowed_methods(None, "/any").unwrap().join(", "),
+            "GET, HEAD, POST, PUT, PATCH, DELETE, OPTIONS"
+        );
+        assert!(table.allowed_methods(None, "/missing").is_none());
+    }
+
     #[test]
     fn test_routing_context() {
         let servers = vec![ServerConfig {
@@ -478,14 +1131,30 @@ mod tests {
                     path: None,
                     method: None,
                     header: None,
+                    path_not: None,
+                    user_agent: None,
+                    client_cert: None,
+                    client_cert_organization: None,
+                    any_of: None,
                 },
                 handle: HandlerConfig::StaticResponse(StaticResponseConfig {
                     status: 200,
                     body: "server1".to_string(),
                     headers: HashMap::new(),
+                    delay_ms: 0,
+                    body_size: None,
                 }),
+                trailing_slash: TrailingSlash::Strict,
+                access_log: None,
+                fault_injection: None,
+                response_hints: Vec::new(),
+                block_user_agents: Vec::new(),
+                block_user_agents_status: 403,
             }],
             https_redirect: false,
+            http2: Http2Config::default(),
+            access_control: None,
+            unix_socket_mode: None,
         }];
 
         let ctx = RoutingContext::new();
@@ -502,6 +1171,7 @@ mod tests {
                 upstreams: vec!["127.0.0.1:8080".to_string(), "127.0.0.1:8081".to_string()],
                 load_balancing: LoadBalancingStrategy::RoundRobin,
                 health_check: None,
+                upstream_auth: None,
                 headers_up: HashMap::new(),
                 headers_down: HashMap::new(),
                 timeout: 30,
@@ -512,13 +1182,37 @@ mod tests {
                 cors: None,
                 lb_try_duration: 0,
                 lb_try_interval: 250,
+                lb_try_backoff: RetryBackoffMode::Fixed,
+                lb_try_jitter: 0.0,
                 timeouts: TimeoutConfig::default(),
                 max_request_body_size: 0,
+                request_buffer_limit: 0,
                 circuit_breaker: None,
                 ip_filter: None,
                 upstream_http2: false,
                 upstream_mtls: None,
+                redirect_rewrite: None,
+                idempotency: None,
+                canary: None,
+                trusted_proxies: Vec::new(),
+                x_forwarded_for: ForwardedForMode::Append,
+                host_header: HostHeader::Preserve,
+                retry_on_status: Vec::new(),
+                upstream_http2_fallback: true,
+                rate_limit: None,
+                maintenance: None,
+                compress: true,
+                cache_ttl_override: None,
+                respect_upstream_cache_control: true,
+                cache_key_query: None,
+                head_as_get: false,
             })),
+            trailing_slash: TrailingSlash::Strict,
+            access_log: None,
+            fault_injection: None,
+            response_hints: Vec::new(),
+            block_user_agents: Vec::new(),
+            block_user_agents_status: 403,
         };
 
         let compiled = CompiledRoute::from_config(&route_config).unwrap();
@@ -526,6 +1220,170 @@ mod tests {
         assert_eq!(compiled.upstream.as_ref().unwrap().servers().len(), 2);
     }
 
+    fn make_slash_config(trailing_slash: TrailingSlash) -> ServerConfig {
+        ServerConfig {
+            name: "slash".to_string(),
+            listen: vec![":8080".to_string()],
+            routes: vec![RouteConfig {
+                match_rule: MatchConfig {
+                    host: None,
+                    path: Some(vec!["/api/".to_string()]),
+                    method: None,
+                    header: None,
+                    path_not: None,
+                    user_agent: None,
+                    client_cert: None,
+                    client_cert_organization: None,
+                    any_of: None,
+                },
+                handle: HandlerConfig::StaticResponse(StaticResponseConfig {
+                    status: 200,
+                    body: "api".to_string(),
+                    headers: HashMap::new(),
+                    delay_ms: 0,
+                    body_size: None,
+                }),
+                trailing_slash,
+                access_log: None,
+                fault_injection: None,
+                response_hints: Vec::new(),
+                block_user_agents: Vec::new(),
+                block_user_agents_status: 403,
+            }],
+            https_redirect: false,
+            http2: Http2Config::default(),
+            access_control: None,
+            unix_socket_mode: None,
+        }
+    }
+
+    #[test]
+    fn test_trailing_slash_strict() {
+        let table = RouteTable::from_config(&make_slash_config(TrailingSlash::Strict)).unwrap();
+        assert!(table.match_route(None, "/api/", "GET").is_some());
+        assert!(table.resolve_route(None, "/api", "GET").is_none());
+    }
+
+    #[test]
+    fn test_trailing_slash_redirect() {
+        let table = RouteTable::from_config(&make_slash_config(TrailingSlash::Redirect)).unwrap();
+
+        match table.resolve_route(None, "/api", "GET") {
+            Some(RouteMatch::Redirect(location)) => assert_eq!(location, "/api/"),
+            _ => panic!("Expected trailing slash redirect"),
+        }
+        assert!(matches!(table.resolve_route(None, "/api/", "GET"), Some(RouteMatch::Matched(_))));
+    }
+
+    #[test]
+    fn test_trailing_slash_ignore() {
+        let table = RouteTable::from_config(&make_slash_config(TrailingSlash::Ignore)).unwrap();
+        assert!(table.match_route(None, "/api", "GET").is_some());
+        assert!(table.match_route(None, "/api/", "GET").is_some());
+        assert!(table.match_route(None, "/web", "GET").is_none());
+    }
+
+    const BROWSER_UA: &str = "Mozilla/5.0 (Macintosh; Intel Mac OS X 14_4) AppleWebKit/605.1.15 (KHTML, like Gecko) Version/17.4 Safari/605.1.15";
+
+    fn user_agent_table(routes: Vec<RouteConfig>) -> RouteTable {
+        RouteTable::from_config(&ServerConfig {
+            name: "bots".to_string(),
+            listen: vec![":8080".to_string()],
+            routes,
+            https_redirect: false,
+            http2: Http2Config::default(),
+            access_control: None,
+            unix_socket_mode: None,
+        })
+        .unwrap()
+    }
+
+    #[test]
+    fn test_blocked_user_agent() {
+        let mut route = static_route(None, "/", "site");
+        route.block_user_agents = vec!["(?i)(bot|crawler|spider)".to_string(), "^$".to_string()];
+        let table = user_agent_table(vec![route]);
+
+        let route = table.match_route(None, "/", "GET").unwrap();
+        assert_eq!(route.blocked_user_agent_status(Some("Mozilla/5.0 (compatible; AhrefsBot/7.0)")), Some(403));
+        assert_eq!(route.blocked_user_agent_status(None), Some(403));
+        assert_eq!(route.blocked_user_agent_status(Some(BROWSER_UA)), None);
+
+        let mut route = static_route(None, "/", "site");
+        route.block_user_agents = vec!["^python-requests/".to_string()];
+        route.block_user_agents_status = 429;
+        let table = user_agent_table(vec![route]);
+        let route = table.match_route(None, "/", "GET").unwrap();
+        assert_eq!(route.blocked_user_agent_status(Some("python-requests/2.31.0")), Some(429));
+        assert_eq!(route.blocked_user_agent_status(Some(BROWSER_UA)), None);
+    }
+
+    #[test]
+    fn test_user_agent_matcher() {
+        let mut crawlers = static_route(None, "/", "prerendered");
+        crawlers.match_rule.user_agent = Some(vec!["(?i)googlebot".to_string()]);
+        let table = user_agent_table(vec![crawlers, static_route(None, "/", "site")]);
+
+        let resolve = |user_agent| match table.resolve_request(None, "/", "GET", user_agent, None) {
+            Some(RouteMatch::Matched(route)) => body_of(Some(route)),
+            _ => None,
+        };
+        assert_eq!(resolve(Some("Mozilla/5.0 (compatible; Googlebot/2.1)")), Some("prerendered"));
+        assert_eq!(resolve(Some(BROWSER_UA)), Some("site"));
+        assert_eq!(resolve(None), Some("site"));
+    }
+
+    #[test]
+    fn test_client_cert_gated_route() {
+        let mut admin = static_route(None, "/admin", "admin");
+        admin.match_rule.client_cert = Some(true);
+        let mut ops = static_route(None, "/ops", "ops");
+        ops.match_rule.client_cert_organization = Some(vec!["Acme Ops".to_string()]);
+        let table = user_agent_table(vec![
+            admin,
+            ops,
+            static_route(None, "/admin", "public admin login"),
+            static_route(None, "/", "public"),
+        ]);
+
+        let resolve = |path, cert: Option<&ClientCert>| match table.resolve_request(None, path, "GET", None, cert) {
+            Some(RouteMatch::Matched(route)) => body_of(Some(route)),
+            _ => None,
+        };
+        let ops_cert = ClientCert {
+            organization: Some("Acme Ops".to_string()),
+        };
+        let other_cert = ClientCert {
+            organization: Some("Acme Sales".to_string()),
+        };
+
+        // A certless connection skips the gated routes
+        assert_eq!(resolve("/admin", None), Some("public admin login"));
+        assert_eq!(resolve("/ops", None), Some("public"));
+        assert_eq!(resolve("/", None), Some("public"));
+
+        // A verified certificate reaches them
+        assert_eq!(resolve("/admin", Some(&other_cert)), Some("admin"));
+        assert_eq!(resolve("/ops", Some(&ops_cert)), Some("ops"));
+        assert_eq!(resolve("/ops", Some(&other_cert)), Some("public"));
+    }
+
+    #[test]
+    fn test_invalid_user_agent_pattern() {
+        let mut route = static_route(None, "/", "site");
+        route.block_user_agents = vec!["(unclosed".to_string()];
+        let config = ServerConfig {
+            name: "bots".to_string(),
+            listen: vec![":8080".to_string()],
+            routes: vec![route],
+            https_redirect: false,
+            http2: Http2Config::default(),
+            access_control: None,
+            unix_socket_mode: None,
+        };
+        assert!(RouteTable::from_config(&config).is_err());
+    }
+
     #[test]
     fn test_compiled_route_without_upstream() {
         let route_config = RouteConfig {
@@ -534,9 +1392,485 @@ mod tests {
                 to: "https://example.com".to_string(),
                 code: 301,
             }),
+            trailing_slash: TrailingSlash::Strict,
+            access_log: None,
+            fault_injection: None,
+            response_hints: Vec::new(),
+            block_user_agents: Vec::new(),
+            block_user_agents_status: 403,
         };
 
         let compiled = CompiledRoute::from_config(&route_config).unwrap();
         assert!(compiled.upstream.is_none());
     }
+
+    fn static_route(host: Option<&str>, path: &str, body: &str) -> RouteConfig {
+        RouteConfig {
+            match_rule: MatchConfig {
+                host: host.map(|h| vec![h.to_string()]),
+                path: Some(vec![path.to_string()]),
+                method: None,
+                header: None,
+                path_not: None,
+                user_agent: None,
+                client_cert: None,
+                client_cert_organization: None,
+                any_of: None,
+            },
+            handle: HandlerConfig::StaticResponse(StaticResponseConfig {
+                status: 200,
+                body: body.to_string(),
+                headers: HashMap::new(),
+                delay_ms: 0,
+                body_size: None,
+            }),
+            trailing_slash: TrailingSlash::Strict,
+            access_log: None,
+            fault_injection: None,
+            response_hints: Vec::new(),
+            block_user_agents: Vec::new(),
+            block_user_agents_status: 403,
+        }
+    }
+
+    fn body_of(route: Option<&CompiledRoute>) -> Option<&str> {
+        match route.map(|r| &r.handler) {
+            Some(HandlerConfig::StaticResponse(cfg)) => Some(cfg.body.as_str()),
+            _ => None,
+        }
+    }
+
+    fn linear_match<'a>(table: &'a RouteTable, host: Option<&str>, path: &str) -> Option<&'a CompiledRoute> {
+        table.routes.iter().find(|r| r.matches(host, path, "GET"))
+    }
+
+    fn many_hosts_table(hosts: usize) -> RouteTable {
+        let mut routes = Vec::new();
+        for i in 0..hosts {
+            let host = format!("host{}.example.com", i);
+            routes.push(static_route(Some(&host), "/api", &format!("{}-api", i)));
+            if i % 10 == 0 {
+                // Host-less routes interleaved with host routes
+                routes.push(static_route(None, &format!("/shared{}", i), &format!("shared-{}", i)));
+            }
+            routes.push(static_route(Some(&host), "/", &format!("{}-root", i)));
+        }
+        routes.push(static_route(None, "/", "fallback"));
+
+        RouteTable::from_config(&ServerConfig {
+            name: "many".to_string(),
+            listen: vec![":8080".to_string()],
+            routes,
+            https_redirect: false,
+            http2: Http2Config::default(),
+            access_control: None,
+            unix_socket_mode: None,
+        })
+        .unwrap()
+    }
+
+    #[test]
+    fn test_host_index_matches_linear_scan() {
+        let table = many_hosts_table(200);
+
+        let hosts = [None, Some("host0.example.com"), Some("host57.example.com"), Some("host199.example.com"), Some("unknown.example.com")];
+        let paths = ["/", "/api", "/api/users", "/shared0", "/shared50/x", "/other"];
+        for host in hosts {
+            for path in paths {
+                assert_eq!(
+                    body_of(table.match_route(host, path, "GET")),
+                    body_of(linear_match(&table, host, path)),
+                    "host={:?} path={}",
+                    host,
+                    path
+                );
+            }
+        }
+
+        // A host-less route listed before a host's own routes still wins
+        assert_eq!(body_of(table.match_route(Some("host0.example.com"), "/shared0", "GET")), Some("shared-0"));
+        assert_eq!(body_of(table.match_route(Some("unknown.example.com"), "/api", "GET")), Some("fallback"));
+    }
+
+    #[test]
+    fn test_host_index_matches_linear_scan_at_scale() {
+        let table = many_hosts_table(10_000);
+        for i in (0..10_000).step_by(97).chain([9_999]) {
+            let host = format!("host{}.example.com", i);
+            for path in ["/api/x", "/", "/shared40"] {
+                assert_eq!(
+                    body_of(table.match_route(Some(&host), path, "GET")),
+                    body_of(linear_match(&table, Some(&host), path)),
+                    "host={} path={}",
+                    host,
+                    path
+                );
+            }
+        }
+    }
+
+    /// Deterministic pseudo-random numbers for generated configs
+    struct Lcg(u64);
+
+    impl Lcg {
+        /// Next number below `n`
+        fn below(&mut self, n: usize) -> usize {
+            self.0 = self.0.wrapping_mul(6364136223846793005).wrapping_add(1442695040888963407);
+            (self.0 >> 33) as usize % n
+        }
+    }
+
+    /// The pre-index algorithm: evaluate every route in config order
+    fn linear_resolve(table: &RouteTable, host: Option<&str>, path: &str) -> Option<(usize, Option<String>)> {
+        for (idx, route) in table.routes.iter().enumerate() {
+            if route.matches(host, path, "GET") {
+                return Some((idx, None));
+            }
+            if let Some(alternate) = route.alternate_path(path) {
+                if route.matches(host, &alternate, "GET") {
+                    let redirect = (route.trailing_slash == TrailingSlash::Redirect).then_some(alternate);
+                    return Some((idx, redirect));
+                }
+            }
+        }
+        None
+    }
+
+    fn indexed_resolve(table: &RouteTable, host: Option<&str>, path: &str) -> Option<(usize, Option<String>)> {
+        match table.resolve_route(host, path, "GET")? {
+            RouteMatch::Matched(route) => {
+                let idx = table.routes.iter().position(|r| std::ptr::eq(r, route)).unwrap();
+                Some((idx, None))
+            }
+            RouteMatch::Redirect(location) => {
+                // Identify the redirecting route the same way the linear scan does
+                linear_resolve(table, host, path).map(|(idx, _)| (idx, Some(location)))
+            }
+        }
+    }
+
+    #[test]
+    fn test_index_matches_linear_across_configs() {
+        let hosts = [None, Some("a.com"), Some("b.com"), Some("c.com")];
+        let prefixes = ["/", "/api", "/api/", "/api/v1", "/static", "/s", ""];
+        let policies = [TrailingSlash::Strict, TrailingSlash::Redirect, TrailingSlash::Ignore];
+        let paths = ["/", "/api", "/api/", "/api/v1/users", "/static/app.js", "/s", "/s/", "/other"];
+
+        // Deterministic pseudo-random configs
+        let mut rng = Lcg(0x2486);
+        let mut next = |n: usize| rng.below(n);
+
+        for _ in 0..200 {
+            let routes = (0..1 + next(12))
+                .map(|i| {
+                    let mut route = static_route(hosts[next(hosts.len())], prefixes[next(prefixes.len())], &i.to_string());
+                    if next(4) == 0 {
+                        route.match_rule.path = None;
+                    }
+                    if next(5) == 0 {
+                        route.match_rule.path_not = Some(vec!["/api/v1".to_string()]);
+                    }
+                    route.trailing_slash = policies[next(policies.len())];
+                    route
+                })
+                .collect();
+            let table = RouteTable::from_config(&ServerConfig {
+                name: "random".to_string(),
+                listen: vec![":8080".to_string()],
+                routes,
+                https_redirect: false,
+                http2: Http2Config::default(),
+                access_control: None,
+                unix_socket_mode: None,
+            })
+            .unwrap();
+
+            for host in hosts.iter().copied().chain([Some("unknown.com")]) {
+                for path in paths {
+                    assert_eq!(
+                        indexed_resolve(&table, host, path),
+                        linear_resolve(&table, host, path),
+                        "host={:?} path={}",
+                        host,
+                        path
+                    );
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn test_https_redirect_only_for_tls_hosts() {
+        let redirecting = ServerConfig {
+            name: "http".to_string(),
+            listen: vec![":80".to_string()],
+            routes: vec![static_route(Some("secure.com"), "/", "secure"), static_route(Some("plain.com"), "/", "plain")],
+            https_redirect: true,
+            http2: Http2Config::default(),
+            access_control: None,
+            unix_socket_mode: None,
+        };
+        let tls = ServerConfig {
+            name: "https".to_string(),
+            listen: vec![":443".to_string()],
+            routes: vec![static_route(Some("secure.com"), "/", "secure")],
+            https_redirect: false,
+            http2: Http2Config::default(),
+            access_control: None,
+            unix_socket_mode: None,
+        };
+        let routing = RoutingContext::new();
+        routing.load_config(&[redirecting.clone(), tls.clone()]).unwrap();
+
+        assert!(routing.should_redirect_https("secure.com"));
+        assert!(routing.should_redirect_https("SECURE.com"));
+        assert!(!routing.should_redirect_https("plain.com"));
+        assert!(!routing.should_redirect_https("unknown.com"));
+
+        // No server asks for redirects
+        let mut quiet = redirecting;
+        quiet.https_redirect = false;
+        routing.load_config(&[quiet, tls]).unwrap();
+        assert!(!routing.should_redirect_https("secure.com"));
+    }
+
+    #[test]
+    fn test_tables_for_host() {
+        let server = |name: &str, host: Option<&str>| ServerConfig {
+            name: name.to_string(),
+            listen: vec![":8080".to_string()],
+            routes: vec![static_route(host, "/", name)],
+            https_redirect: false,
+            http2: Http2Config::default(),
+            access_control: None,
+            unix_socket_mode: None,
+        };
+        let routing = RoutingContext::new();
+        routing
+            .load_config(&[server("a", Some("a.com")), server("any", None), server("b", Some("b.com"))])
+            .unwrap();
+
+        let names = |host| -> Vec<String> {
+            routing
+                .tables_for_host(host)
+                .iter()
+                .map(|t| body_of(t.match_route(host, "/", "GET")).unwrap_or("-").to_string())
+                .collect()
+        };
+        assert_eq!(names(Some("b.com")), vec!["any", "b"]);
+        assert_eq!(names(Some("a.com")), vec!["a", "any"]);
+        assert_eq!(names(None), vec!["any"]);
+        assert_eq!(routing.tables().len(), 3);
+    }
+
+    #[test]
+    fn test_any_of_host_groups() {
+        let mut shared = static_route(None, "/", "shared");
+        shared.match_rule.any_of = Some(vec![
+            MatchConfig {
+                host: Some(vec!["a.com".to_string(), "www.a.com".to_string()]),
+                ..Default::default()
+            },
+            MatchConfig {
+                host: Some(vec!["b.com".to_string()]),
+                method: Some(vec!["GET".to_string()]),
+                ..Default::default()
+            },
+        ]);
+        let server = ServerConfig {
+            name: "consolidated".to_string(),
+            listen: vec![":8080".to_string()],
+            routes: vec![shared, static_route(Some("c.com"), "/", "c")],
+            https_redirect: false,
+            http2: Http2Config::default(),
+            access_control: None,
+            unix_socket_mode: None,
+        };
+        let routing = RoutingContext::new();
+        routing.load_config(&[server]).unwrap();
+
+        let body = |host, method| {
+            routing
+                .tables_for_host(Some(host))
+                .iter()
+                .find_map(|t| body_of(t.match_route(Some(host), "/page", method)).map(str::to_string))
+        };
+        assert_eq!(body("a.com", "POST").as_deref(), Some("shared"));
+        assert_eq!(body("www.a.com", "GET").as_deref(), Some("shared"));
+        assert_eq!(body("b.com", "GET").as_deref(), Some("shared"));
+        assert_eq!(body("b.com", "POST"), None);
+        assert_eq!(body("c.com", "GET").as_deref(), Some("c"));
+        assert_eq!(body("d.com", "GET"), None);
+
+        let table = &routing.tables()[0];
+        assert_eq!(table.allowed_methods(Some("b.com"), "/").unwrap(), vec!["GET", "HEAD", "OPTIONS"]);
+    }
+
+    /// Resolve a request the way `request_filter` does
+    fn proxy_resolve(routing: &RoutingContext, host: Option<&str>, path: &str, method: &str) -> Option<(String, usize, Option<String>)> {
+        for table in routing.tables_for_host(host) {
+            match table.resolve_route(host, path, method) {
+                Some(RouteMatch::Matched(route)) => {
+                    let idx = table.routes.iter().position(|r| std::ptr::eq(r, route)).unwrap();
+                    return Some((table.server_name().to_string(), idx, None));
+                }
+                Some(RouteMatch::Redirect(location)) => {
+                    let idx = table
+                        .routes
+                        .iter()
+                        .position(|r| r.trailing_slash == TrailingSlash::Redirect && r.matches(host, &location, method))
+                        .unwrap();
+                    return Some((table.server_name().to_string(), idx, Some(location)));
+                }
+                None => {}
+            }
+        }
+        None
+    }
+
+    #[test]
+    fn test_resolve_reports_route() {
+        let routing = RoutingContext::new();
+        let mut static_server = ServerConfig {
+            name: "static".to_string(),
+            listen: vec![":8080".to_string()],
+            routes: vec![static_route(None, "/docs/", "docs")],
+            https_redirect: false,
+            http2: Http2Config::default(),
+            access_control: None,
+            unix_socket_mode: None,
+        };
+        static_server.routes[0].trailing_slash = TrailingSlash::Redirect;
+        routing.load_config(&[make_test_config(), static_server]).unwrap();
+
+        let info = routing.resolve(Some("example.com"), "/api/users", "GET").unwrap();
+        assert_eq!(info.server, "test");
+        assert_eq!(info.route_index, 0);
+        assert_eq!(info.handler, "reverse_proxy");
+        assert_eq!(info.upstreams, vec!["127.0.0.1:9090".to_string()]);
+        assert_eq!(info.redirect, None);
+
+        let info = routing.resolve(Some("other.com"), "/docs", "GET").unwrap();
+        assert_eq!(info.server, "static");
+        assert_eq!(info.handler, "static_response");
+        assert!(info.upstreams.is_empty());
+        assert_eq!(info.redirect.as_deref(), Some("/docs/"));
+
+        assert!(routing.resolve(Some("other.com"), "/api/users", "GET").is_none());
+    }
+
+    #[test]
+    fn test_resolve_matches_proxy_across_configs() {
+        let hosts = [None, Some("a.com"), Some("b.com")];
+        let prefixes = ["/", "/api", "/api/", "/static"];
+        let policies = [TrailingSlash::Strict, TrailingSlash::Redirect, TrailingSlash::Ignore];
+        let methods = [None, Some("GET"), Some("POST")];
+        let paths = ["/", "/api", "/api/", "/api/v1", "/static/app.js", "/other"];
+
+        let mut rng = Lcg(0x2489);
+        let mut next = |n: usize| rng.below(n);
+
+        for _ in 0..50 {
+            let servers: Vec<ServerConfig> = (0..3)
+                .map(|s| ServerConfig {
+                    name: format!("server{}", s),
+                    listen: vec![":8080".to_string()],
+                    routes: (0..4)
+                        .map(|r| {
+                            let mut route = static_route(hosts[next(hosts.len())], prefixes[next(prefixes.len())], &format!("{}-{}", s, r));
+                            route.match_rule.method = methods[next(methods.len())].map(|m| vec![m.to_string()]);
+                            route.trailing_slash = policies[next(policies.len())];
+                            route
+                        })
+                        .collect(),
+                    https_redirect: false,
+                    http2: Http2Config::default(),
+                    access_control: None,
+                    unix_socket_mode: None,
+                })
+                .collect();
+            let routing = RoutingContext::new();
+            routing.load_config(&servers).unwrap();
+
+            for host in hosts {
+                for path in paths {
+                    for method in ["GET", "POST"] {
+                        let expected = proxy_resolve(&routing, host, path, method);
+                        let resolved = routing
+                            .resolve(host, path, method)
+                            .map(|info| (info.server, info.route_index, info.redirect));
+                        assert_eq!(resolved, expected, "host={:?} path={} method={}", host, path, method);
+                    }
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn test_path_index_matches_linear_scan_at_scale() {
+        // One host with thousands of distinct path prefixes
+        let routes = (0..10_000)
+            .map(|i| static_route(Some("api.example.com"), &format!("/svc{}/", i), &i.to_string()))
+            .collect();
+        let table = RouteTable::from_config(&ServerConfig {
+            name: "paths".to_string(),
+            listen: vec![":8080".to_string()],
+            routes,
+            https_redirect: false,
+            http2: Http2Config::default(),
+            access_control: None,
+            unix_socket_mode: None,
+        })
+        .unwrap();
+
+        let mut rng = Lcg(0x2486);
+        for _ in 0..200 {
+            let path = format!("/svc{}/items/{}", rng.below(12_000), rng.below(10));
+            for host in [Some("api.example.com"), None] {
+                assert_eq!(
+                    body_of(table.match_route(host, &path, "GET")),
+                    body_of(linear_match(&table, host, &path)),
+                    "host={:?} path={}",
+                    host,
+                    path
+                );
+            }
+        }
+        assert_eq!(body_of(table.match_route(Some("api.example.com"), "/svc1/x", "GET")), Some("1"));
+        assert_eq!(body_of(table.match_route(Some("api.example.com"), "/svc1", "GET")), None);
+    }
+
+    #[test]
+    fn test_reset_open_circuit() {
+        use crate::circuit_breaker::CircuitState;
+
+        let mut config = make_test_config();
+        if let HandlerConfig::ReverseProxy(proxy) = &mut config.routes[0].handle {
+            proxy.circuit_breaker = Some(config::CircuitBreakerConfigDef {
+                failure_threshold: 2,
+                ..Default::default()
+            });
+        }
+        let routing = RoutingContext::new();
+        routing.load_config(&[config]).unwrap();
+        let selector = routing.get_all_upstreams()[0].clone();
+        let server = selector.select().unwrap();
+
+        let state = || routing.circuits().iter().map(|c| (c.name.clone(), c.state)).collect::<Vec<_>>();
+        assert_eq!(state(), vec![("127.0.0.1:9090".to_string(), CircuitState::Closed)]);
+
+        server.record_response(false);
+        server.record_response(false);
+        assert_eq!(state(), vec![("127.0.0.1:9090".to_string(), CircuitState::Open)]);
+        assert!(selector.select().is_err());
+        let listed = crate::circuit_breaker::circuits_json(&routing.circuits());
+        assert!(listed.contains(r#""upstream":"127.0.0.1:9090","state":"open","failures":2"#), "{}", listed);
+
+        // Traffic flows again right away, without waiting for the timeout
+        assert_eq!(routing.reset_circuit("127.0.0.1:9090"), 1);
+        assert_eq!(state(), vec![("127.0.0.1:9090".to_string(), CircuitState::Closed)]);
+        assert!(selector.select().is_ok());
+
+        assert_eq!(routing.reset_circuit("127.0.0.1:1"), 0);
+    }
 }
@@ -0,0 +1,129 @@
+//! Path prefix index over the routes of one host bucket
+//!
+//! Route path prefixes are stored in a byte trie. A lookup walks the request
+//! path once and returns every route whose path conditions can hold, so the
+//! route table only evaluates those and keeps first-match semantics
+//! identical to a linear scan.
+
+use config::MatchConfig;
+
+/// Routes of one host bucket, by path prefix
+#[derive(Default)]
+pub struct PathIndex {
+    trie: Vec<TrieNode>,
+    any_path: Vec<usize>,
+}
+
+#[derive(Default)]
+struct TrieNode {
+    children: Vec<(u8, usize)>,
+    routes: Vec<usize>,
+}
+
+impl PathIndex {
+    /// Add the route at position `idx`
+    pub fn insert(&mut self, idx: usize, matcher: &MatchConfig) {
+        match matcher.candidate_paths() {
+            Some(prefixes) => {
+                for prefix in prefixes {
+                    let node = self.node_for(prefix.as_bytes());
+                    self.trie[node].routes.push(idx);
+                }
+            }
+            None => self.any_path.push(idx),
+        }
+    }
+
+    fn node_for(&mut self, prefix: &[u8]) -> usize {
+        if self.trie.is_empty() {
+            self.trie.push(TrieNode::default());
+        }
+        let mut node = 0;
+        for &byte in prefix {
+            node = match self.trie[node].children.iter().find(|(b, _)| *b == byte) {
+                Some(&(_, child)) => child,
+                None => {
+                    let child = self.trie.len();
+                    self.trie.push(TrieNode::default());
+                    self.trie[node].children.push((byte, child));
+                    child
+                }
+            };
+        }
+        node
+    }
+
+    /// Add routes whose path prefixes are prefixes of `path`
+    pub fn collect(&self, path: &str, out: &mut Vec<usize>) {
+        out.extend_from_slice(&self.any_path);
+        let Some(root) = self.trie.first() else {
+            return;
+        };
+
+        out.extend_from_slice(&root.routes);
+        let mut node = root;
+        for &byte in path.as_bytes() {
+            match node.children.iter().find(|(b, _)| *b == byte) {
+                Some(&(_, child)) => {
+                    node = &self.trie[child];
+                    out.extend_from_slice(&node.routes);
+                }
+                None => break,
+            }
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.trie.is_empty() && self.any_path.is_empty()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn matcher(paths: Option<&[&str]>) -> MatchConfig {
+        MatchConfig {
+            path: paths.map(|p| p.iter().map(|s| s.to_string()).collect()),
+            ..Default::default()
+        }
+    }
+
+    fn index(matchers: &[MatchConfig]) -> PathIndex {
+        let mut index = PathIndex::default();
+        for (idx, matcher) in matchers.iter().enumerate() {
+            index.insert(idx, matcher);
+        }
+        index
+    }
+
+    fn candidates(index: &PathIndex, path: &str) -> Vec<usize> {
+        let mut out = Vec::new();
+        index.collect(path, &mut out);
+        out.sort_unstable();
+        out
+    }
+
+    #[test]
+    fn test_candidates_by_prefix() {
+        let index = index(&[
+            matcher(Some(&["/api"])),
+            matcher(Some(&["/static", "/assets"])),
+            matcher(Some(&["/api/v2"])),
+            matcher(None),
+        ]);
+
+        assert_eq!(candidates(&index, "/api/v2/users"), vec![0, 2, 3]);
+        assert_eq!(candidates(&index, "/assets/x.css"), vec![1, 3]);
+        assert_eq!(candidates(&index, "/ap"), vec![3]);
+        assert!(!index.is_empty());
+        assert!(PathIndex::default().is_empty());
+    }
+
+    #[test]
+    fn test_empty_prefix_matches_everything() {
+        let index = index(&[matcher(Some(&[""])), matcher(Some(&["/x"]))]);
+        assert_eq!(candidates(&index, "/y"), vec![0]);
+        assert_eq!(candidates(&index, "/x"), vec![0, 1]);
+    }
+}
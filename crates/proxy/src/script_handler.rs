@@ -21,9 +21,11 @@
 //! ```
 
 use once_cell::sync::Lazy;
-use rhai::{Dynamic, Engine, Map, Scope, AST};
+use rhai::{Dynamic, Engine, EvalAltResult, Map, Scope, AST};
+use std::cell::Cell;
 use std::collections::HashMap;
 use std::sync::Arc;
+use std::time::{Duration, Instant};
 use thiserror::Error;
 
 /// Script handler error types
@@ -37,6 +39,9 @@ pub enum ScriptHandlerError {
 
     #[error("Invalid script result: {0}")]
     InvalidResult(String),
+
+    #[error("Script ran past the handler timeout")]
+    Timeout,
 }
 
 /// Request context exposed to Rhai scripts
@@ -144,6 +149,31 @@ impl Default for ScriptResult {
     }
 }
 
+/// Operations a script runs between deadline checks
+const DEADLINE_CHECK_INTERVAL: u64 = 64;
+
+thread_local! {
+    /// When the script running on this thread has to stop, if it's limited
+    static DEADLINE: Cell<Option<Instant>> = const { Cell::new(None) };
+}
+
+/// Sets this thread's script deadline, clearing it when dropped so a
+/// panicking script can't leave it behind for the next one
+struct DeadlineGuard;
+
+impl DeadlineGuard {
+    fn set(at: Instant) -> Self {
+        DEADLINE.with(|deadline| deadline.set(Some(at)));
+        DeadlineGuard
+    }
+}
+
+impl Drop for DeadlineGuard {
+    fn drop(&mut self) {
+        DEADLINE.with(|deadline| deadline.set(None));
+    }
+}
+
 /// Thread-safe Rhai engine singleton (shared with rhai_rewrite)
 static SCRIPT_ENGINE: Lazy<Arc<Engine>> = Lazy::new(|| {
     let mut engine = Engine::new();
@@ -157,6 +187,18 @@ static SCRIPT_ENGINE: Lazy<Arc<Engine>> = Lazy::new(|| {
     engine.set_max_array_size(10_000);
     engine.set_max_map_size(10_000);
 
+    // Stop scripts running past their deadline. Evaluation is synchronous,
+    // so a timeout future around it couldn't interrupt it.
+    engine.on_progress(|operations| {
+        if operations % DEADLINE_CHECK_INTERVAL != 0 {
+            return None;
+        }
+        DEADLINE.with(|deadline| match deadline.get() {
+            Some(deadline) if Instant::now() >= deadline => Some(Dynamic::UNIT),
+            _ => None,
+        })
+    });
+
     // Register built-in functions
     register_builtin_functions(&mut engine);
 
@@ -342,12 +384,29 @@ impl CompiledScriptHandler {
         // Execute script
         let result: Dynamic = engine
             .eval_ast_with_scope(&mut scope, &self.ast)
-            .map_err(|e| ScriptHandlerError::RuntimeError(e.to_string()))?;
+            .map_err(|e| match e.unwrap_inner() {
+                EvalAltResult::ErrorTerminated(..) => ScriptHandlerError::Timeout,
+                _ => ScriptHandlerError::RuntimeError(e.to_string()),
+            })?;
 
         // Parse result
         self.parse_result(result)
     }
 
+    /// Execute the script, stopping it with [`ScriptHandlerError::Timeout`]
+    /// once it has run for `timeout`
+    pub fn execute_within(
+        &self,
+        ctx: &ScriptRequestContext,
+        timeout: Option<Duration>,
+    ) -> Result<ScriptResult, ScriptHandlerError> {
+        let Some(timeout) = timeout else {
+            return self.execute(ctx);
+        };
+        let _deadline = DeadlineGuard::set(Instant::now() + timeout);
+        self.execute(ctx)
+    }
+
     /// Parse the script result into a ScriptResult
     fn parse_result(&self, result: Dynamic) -> Result<ScriptResult, ScriptHandlerError> {
         // Handle map result
@@ -734,4 +793,59 @@ mod tests {
         let result = handler.execute(&ctx);
         assert!(result.is_err());
     }
+
+    /// Scans a 512KB string until stopped
+    const SLOW_SCRIPT: &str = r#"
+        let s = "a";
+        for i in 0..19 { s += s; }
+        let found = 0;
+        loop {
+            if s.contains("ab") { found += 1; }
+        }
+    "#;
+
+    #[test]
+    fn test_slow_script_cut_off_at_timeout() {
+        let handler = CompiledScriptHandler::compile(SLOW_SCRIPT).unwrap();
+        let ctx = make_context();
+
+        let start = Instant::now();
+        let result = handler.execute_within(&ctx, Some(Duration::from_millis(20)));
+        let elapsed = start.elapsed();
+        assert!(matches!(result, Err(ScriptHandlerError::Timeout)), "{:?}", result);
+        assert!(elapsed >= Duration::from_millis(20));
+        assert!(elapsed < Duration::from_secs(2), "stopped after {:?}", elapsed);
+
+        // The deadline doesn't outlive the execution
+        assert!(DEADLINE.with(|deadline| deadline.get()).is_none());
+    }
+
+    #[test]
+    fn test_deadline_cleared_on_panic() {
+        let unwound = std::panic::catch_unwind(|| {
+            let _deadline = DeadlineGuard::set(Instant::now() + Duration::from_secs(60));
+            panic!("script host function panicked");
+        });
+        assert!(unwound.is_err());
+        assert!(DEADLINE.with(|deadline| deadline.get()).is_none());
+    }
+
+    #[test]
+    fn test_fast_script_within_timeout() {
+        let script = r#"
+            let total = 0;
+            for i in 0..1000 { total += i; }
+            #{ status: 200, body: `${total}` }
+        "#;
+
+        let handler = CompiledScriptHandler::compile(script).unwrap();
+        let result = handler.execute_within(&make_context(), Some(Duration::from_secs(5))).unwrap();
+        match result {
+            ScriptResult::Response { status, body, .. } => {
+                assert_eq!(status, 200);
+                assert_eq!(body, "499500");
+            }
+            _ => panic!("Expected Response"),
+        }
+    }
 }
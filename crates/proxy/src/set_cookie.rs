@@ -0,0 +1,79 @@
+//! Set-Cookie lines added by avalon
+//!
+//! Every cookie travels on its own `Set-Cookie` line (RFC 6265 Section 3),
+//! so cookies avalon adds (session affinity, `headers_down`, response
+//! rewrites) are appended next to the upstream's instead of replacing them.
+
+use pingora_http::ResponseHeader;
+
+pub fn is_set_cookie(name: &str) -> bool {
+    name.eq_ignore_ascii_case("set-cookie")
+}
+
+/// Set a header on a response; `Set-Cookie` is appended, others replace the existing value
+pub fn set_response_header(response: &mut ResponseHeader, name: &str, value: &str) -> pingora_error::Result<()> {
+    if is_set_cookie(name) {
+        response.append_header(name.to_string(), value)
+    } else {
+        response.insert_header(name.to_string(), value)
+    }
+}
+
+/// Cookie pinning a client to the upstream at `server_index`
+pub fn affinity_cookie(name: &str, server_index: &str, max_age: u64) -> String {
+    if max_age > 0 {
+        format!("{}={}; Path=/; Max-Age={}; HttpOnly", name, server_index, max_age)
+    } else {
+        format!("{}={}; Path=/; HttpOnly", name, server_index)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn cookies(response: &ResponseHeader) -> Vec<String> {
+        response
+            .headers
+            .get_all("set-cookie")
+            .iter()
+            .map(|v| v.to_str().unwrap().to_string())
+            .collect()
+    }
+
+    #[test]
+    fn test_upstream_and_affinity_cookies_both_reach_client() {
+        let mut response = ResponseHeader::build(200, None).unwrap();
+        response.append_header("Set-Cookie", "session=abc; Path=/; Secure").unwrap();
+        response.append_header("Set-Cookie", "theme=dark").unwrap();
+
+        set_response_header(&mut response, "Set-Cookie", &affinity_cookie("avalon_srv", "1", 3600)).unwrap();
+
+        assert_eq!(
+            cookies(&response),
+            vec![
+                "session=abc; Path=/; Secure",
+                "theme=dark",
+                "avalon_srv=1; Path=/; Max-Age=3600; HttpOnly",
+            ]
+        );
+    }
+
+    #[test]
+    fn test_other_headers_replace() {
+        let mut response = ResponseHeader::build(200, None).unwrap();
+        response.insert_header("X-Frame-Options", "SAMEORIGIN").unwrap();
+        set_response_header(&mut response, "X-Frame-Options", "DENY").unwrap();
+        set_response_header(&mut response, "set-cookie", "a=1").unwrap();
+        set_response_header(&mut response, "set-cookie", "b=2").unwrap();
+
+        let frame_options: Vec<_> = response.headers.get_all("x-frame-options").iter().collect();
+        assert_eq!(frame_options, vec!["DENY"]);
+        assert_eq!(cookies(&response), vec!["a=1", "b=2"]);
+    }
+
+    #[test]
+    fn test_affinity_cookie_without_max_age() {
+        assert_eq!(affinity_cookie("srv", "0", 0), "srv=0; Path=/; HttpOnly");
+    }
+}
@@ -0,0 +1,76 @@
+//! Static response bodies and simulated latency
+//!
+//! Besides fixed replies, `static_response` can stand in for a backend in
+//! load tests and demos: `delay_ms` holds the response back and `body_size`
+//! sends a body of a given size, built once when the route is compiled.
+//! The body is compressed and cached like a fetched one, and the delay counts
+//! against the request deadline.
+
+use bytes::{Bytes, BytesMut};
+use config::StaticResponseConfig;
+use std::time::Duration;
+
+/// Repeated to fill `body_size` when no `body` is configured
+const FILLER: &[u8] = b"avalon static response filler\n";
+
+/// Body sent for a static response, built when the route is compiled
+pub fn response_body(config: &StaticResponseConfig) -> Bytes {
+    let Some(size) = config.body_size else {
+        return Bytes::from(config.body.clone());
+    };
+    let pattern = if config.body.is_empty() { FILLER } else { config.body.as_bytes() };
+    let mut body = BytesMut::with_capacity(size);
+    while body.len() < size {
+        let take = pattern.len().min(size - body.len());
+        body.extend_from_slice(&pattern[..take]);
+    }
+    body.freeze()
+}
+
+/// Wait out the configured `delay_ms`, if any
+pub async fn simulate_latency(config: &StaticResponseConfig) {
+    if config.delay_ms > 0 {
+        tokio::time::sleep(Duration::from_millis(config.delay_ms)).await;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashMap;
+    use std::time::Instant;
+
+    fn config(body: &str, delay_ms: u64, body_size: Option<usize>) -> StaticResponseConfig {
+        StaticResponseConfig {
+            status: 200,
+            body: body.to_string(),
+            headers: HashMap::new(),
+            delay_ms,
+            body_size,
+        }
+    }
+
+    #[test]
+    fn test_generated_body_size() {
+        assert_eq!(response_body(&config("hello", 0, None)), Bytes::from("hello"));
+
+        let body = response_body(&config("", 0, Some(100_000)));
+        assert_eq!(body.len(), 100_000);
+        assert!(body.starts_with(FILLER));
+
+        let body = response_body(&config("abc", 0, Some(7)));
+        assert_eq!(body, Bytes::from("abcabca"));
+        assert!(response_body(&config("abc", 0, Some(0))).is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_delay_observed() {
+        let start = Instant::now();
+        simulate_latency(&config("", 50, None)).await;
+        assert!(start.elapsed() >= Duration::from_millis(50));
+
+        let start = Instant::now();
+        simulate_latency(&config("", 0, None)).await;
+        assert!(start.elapsed() < Duration::from_millis(50));
+    }
+}
@@ -0,0 +1,244 @@
+//! StatsD/DogStatsD metrics exporter
+//!
+//! Prometheus scrapes `/metrics`; StatsD is push based, so a background thread
+//! flushes the `MetricsRegistry` to a UDP endpoint every interval. Counters
+//! are sent as the increase since the last flush, gauges as their current
+//! value, and request duration as the mean of the requests finished during
+//! the interval. Labels become DogStatsD tags (`|#status:200`).
+
+use crate::metrics::{metrics, MetricsRegistry};
+use config::MetricsConfig;
+use std::collections::HashMap;
+use std::io;
+use std::net::{SocketAddr, ToSocketAddrs, UdpSocket};
+use std::time::Duration;
+use tracing::{info, warn};
+
+/// Largest datagram sent, safely below a typical 1500 byte MTU
+const MAX_PACKET_SIZE: usize = 1432;
+
+/// Periodic StatsD exporter for a metrics registry
+pub struct StatsdExporter {
+    socket: UdpSocket,
+    prefix: String,
+    tags: Vec<String>,
+    /// Counter values at the last flush, by metric line key
+    previous: HashMap<String, u64>,
+    /// Request duration sum and count at the last flush
+    previous_duration: (f64, u64),
+}
+
+impl StatsdExporter {
+    /// Create an exporter sending to `addr`
+    pub fn new(addr: &str, prefix: &str, tags: &[String]) -> io::Result<Self> {
+        let target = addr
+            .to_socket_addrs()?
+            .next()
+            .ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, "StatsD address did not resolve"))?;
+        let local: SocketAddr = if target.is_ipv4() { ([0, 0, 0, 0], 0).into() } else { ([0u16; 8], 0).into() };
+        let socket = UdpSocket::bind(local)?;
+        socket.connect(target)?;
+        Ok(Self {
+            socket,
+            prefix: prefix.trim_end_matches('.').to_string(),
+            tags: tags.to_vec(),
+            previous: HashMap::new(),
+            previous_duration: (0.0, 0),
+        })
+    }
+
+    /// Format the registry as StatsD lines, advancing the counter baselines
+    pub fn lines(&mut self, registry: &MetricsRegistry) -> Vec<String> {
+        let mut lines = Vec::new();
+
+        self.counter(&mut lines, "requests_total", None, registry.requests_total.get());
+        for (status, count) in registry.requests_by_status.get_all() {
+            self.counter(&mut lines, "requests", Some(("status", status.as_str())), count);
+        }
+        for (method, count) in registry.requests_by_method.get_all() {
+            self.counter(&mut lines, "requests_by_method", Some(("method", method.as_str())), count);
+        }
+        for (host, count) in registry.requests_by_host.get_all() {
+            self.counter(&mut lines, "requests_by_host", Some(("host", host.as_str())), count);
+        }
+        for (stage, count) in registry.request_timeouts.get_all() {
+            self.counter(&mut lines, "request_timeouts", Some(("stage", stage.as_str())), count);
+        }
+        for (handler, count) in registry.handler_timeouts.get_all() {
+            self.counter(&mut lines, "handler_timeouts", Some(("handler", handler.as_str())), count);
+        }
+        for (upstream, count) in registry.upstream_requests.get_all() {
+            self.counter(&mut lines, "upstream_requests", Some(("upstream", upstream.as_str())), count);
+        }
+        self.counter(&mut lines, "cache_hits", None, registry.cache_hits.get());
+        self.counter(&mut lines, "cache_misses", None, registry.cache_misses.get());
+        self.counter(&mut lines, "rate_limit_rejections", None, registry.rate_limit_rejections.get());
+        self.counter(&mut lines, "client_cancellations", None, registry.client_cancellations.get());
+        for (reason, count) in registry.tls_errors.get_all() {
+            self.counter(&mut lines, "tls_errors", Some(("reason", reason.as_str())), count);
+        }
+        for (reason, count) in registry.upstream_tls_errors.get_all() {
+            self.counter(&mut lines, "upstream_tls_errors", Some(("reason", reason.as_str())), count);
+        }
+        self.counter(&mut lines, "bytes_sent", None, registry.bytes_sent.get());
+        self.counter(&mut lines, "bytes_received", None, registry.bytes_received.get());
+
+        lines.push(self.line("active_connections", None, registry.active_connections.get(), "g"));
+        lines.push(self.line("in_flight_requests", None, registry.in_flight_requests.get(), "g"));
+        for (upstream, healthy) in registry.upstream_health.get_all() {
+            lines.push(self.line("upstream_healthy", Some(("upstream", upstream.as_str())), healthy, "g"));
+        }
+
+        let (_, sum, count) = registry.request_duration.get_stats();
+        let (previous_sum, previous_count) = self.previous_duration;
+        if count > previous_count {
+            let mean_ms = (sum - previous_sum) / (count - previous_count) as f64 * 1000.0;
+            lines.push(self.line("request_duration", None, format!("{:.3}", mean_ms), "ms"));
+        }
+        self.previous_duration = (sum, count);
+
+        lines
+    }
+
+    /// Send the registry to the StatsD endpoint
+    pub fn flush(&mut self, registry: &MetricsRegistry) -> io::Result<()> {
+        let lines = self.lines(registry);
+        for packet in packets(&lines) {
+            self.socket.send(packet.as_bytes())?;
+        }
+        Ok(())
+    }
+
+    fn counter(&mut self, lines: &mut Vec<String>, name: &str, label: Option<(&str, &str)>, value: u64) {
+        let line = self.line(name, label, "", "c");
+        let previous = self.previous.insert(line, value).unwrap_or(0);
+        let delta = value.saturating_sub(previous);
+        if delta > 0 {
+            lines.push(self.line(name, label, delta, "c"));
+        }
+    }
+
+    fn line(&self, name: &str, label: Option<(&str, &str)>, value: impl std::fmt::Display, kind: &str) -> String {
+        let mut line = format!("{}.{}:{}|{}", self.prefix, name, value, kind);
+        let label = label.map(|(key, value)| format!("{}:{}", key, sanitize_tag(value)));
+        let tags: Vec<&str> = self.tags.iter().map(String::as_str).chain(label.as_deref()).collect();
+        if !tags.is_empty() {
+            line.push_str("|#");
+            line.push_str(&tags.join(","));
+        }
+        line
+    }
+}
+
+/// Tag values can't contain the separators used by the line format
+fn sanitize_tag(value: &str) -> String {
+    value.replace([',', '|', '#', '\n'], "_")
+}
+
+/// Join lines into newline-separated datagrams of at most `MAX_PACKET_SIZE`
+fn packets(lines: &[String]) -> Vec<String> {
+    let mut packets = Vec::new();
+    let mut current = String::new();
+    for line in lines {
+        if !current.is_empty() && current.len() + 1 + line.len() > MAX_PACKET_SIZE {
+            packets.push(std::mem::take(&mut current));
+        }
+        if !current.is_empty() {
+            current.push('\n');
+        }
+        current.push_str(line);
+    }
+    if !current.is_empty() {
+        packets.push(current);
+    }
+    packets
+}
+
+/// Start flushing the global registry when `statsd_addr` is configured
+pub fn start(config: &MetricsConfig) {
+    let Some(addr) = config.statsd_addr.clone() else {
+        return;
+    };
+    let mut exporter = match StatsdExporter::new(&addr, &config.statsd_prefix, &config.statsd_tags) {
+        Ok(exporter) => exporter,
+        Err(e) => {
+            warn!(addr = %addr, error = %e, "Failed to start StatsD exporter");
+            return;
+        }
+    };
+    let interval = Duration::from_secs(config.statsd_flush_interval.max(1));
+
+    info!(addr = %addr, interval_secs = interval.as_secs(), "Exporting metrics to StatsD");
+    std::thread::spawn(move || loop {
+        std::thread::sleep(interval);
+        if let Err(e) = exporter.flush(metrics()) {
+            warn!(addr = %addr, error = %e, "Failed to send metrics to StatsD");
+        }
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn receive(socket: &UdpSocket) -> Vec<String> {
+        let mut buf = [0u8; 2048];
+        let len = socket.recv(&mut buf).unwrap();
+        String::from_utf8(buf[..len].to_vec())
+            .unwrap()
+            .lines()
+            .map(String::from)
+            .collect()
+    }
+
+    #[test]
+    fn test_flush_sends_statsd_lines() {
+        let server = UdpSocket::bind("127.0.0.1:0").unwrap();
+        server.set_read_timeout(Some(Duration::from_secs(5))).unwrap();
+        let addr = server.local_addr().unwrap().to_string();
+        let mut exporter = StatsdExporter::new(&addr, "avalon", &["env:test".to_string()]).unwrap();
+
+        let registry = MetricsRegistry::new();
+        registry.requests_total.add(3);
+        registry.requests_by_status.inc("200");
+        registry.in_flight_requests.set(2);
+        registry.request_duration.observe(0.01);
+        registry.request_duration.observe(0.03);
+
+        exporter.flush(&registry).unwrap();
+        let lines = receive(&server);
+        assert!(lines.contains(&"avalon.requests_total:3|c|#env:test".to_string()), "{:?}", lines);
+        assert!(lines.contains(&"avalon.requests:1|c|#env:test,status:200".to_string()), "{:?}", lines);
+        assert!(lines.contains(&"avalon.in_flight_requests:2|g|#env:test".to_string()), "{:?}", lines);
+        assert!(lines.contains(&"avalon.request_duration:20.000|ms|#env:test".to_string()), "{:?}", lines);
+        assert!(!lines.iter().any(|l| l.starts_with("avalon.cache_hits:")), "{:?}", lines);
+
+        // Counters are sent as the increase since the previous flush
+        registry.requests_total.inc();
+        exporter.flush(&registry).unwrap();
+        let lines = receive(&server);
+        assert!(lines.contains(&"avalon.requests_total:1|c|#env:test".to_string()), "{:?}", lines);
+        assert!(!lines.iter().any(|l| l.starts_with("avalon.requests:")), "{:?}", lines);
+        assert!(!lines.iter().any(|l| l.starts_with("avalon.request_duration:")), "{:?}", lines);
+    }
+
+    #[test]
+    fn test_lines_without_tags() {
+        let server = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let mut exporter = StatsdExporter::new(&server.local_addr().unwrap().to_string(), "edge.", &[]).unwrap();
+        let registry = MetricsRegistry::new();
+        registry.cache_hits.inc();
+        let lines = exporter.lines(&registry);
+        assert!(lines.contains(&"edge.cache_hits:1|c".to_string()), "{:?}", lines);
+        assert!(lines.contains(&"edge.active_connections:0|g".to_string()), "{:?}", lines);
+    }
+
+    #[test]
+    fn test_packets_split_at_size_limit() {
+        let lines: Vec<String> = (0..100).map(|i| format!("avalon.metric_{:03}:1|c", i)).collect();
+        let packets = packets(&lines);
+        assert!(packets.len() > 1);
+        assert!(packets.iter().all(|p| p.len() <= MAX_PACKET_SIZE));
+        assert_eq!(packets.join("\n").lines().count(), 100);
+    }
+}
@@ -1,11 +1,14 @@
 //! Upstream server selection and load balancing
 
+use crate::circuit_breaker::{CircuitBreaker, CircuitBreakerConfig};
 use crate::error::{ProxyError, Result};
-use config::LoadBalancingStrategy;
+use crate::health::PassiveHealth;
+use config::{LoadBalancingStrategy, TimeoutConfig};
 use std::net::SocketAddr;
 use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
-use std::sync::Arc;
-use tracing::debug;
+use std::sync::{Arc, OnceLock};
+use std::time::Duration;
+use tracing::{debug, warn};
 
 /// Information about an upstream server
 #[derive(Debug)]
@@ -16,34 +19,83 @@ pub struct UpstreamServer {
     pub active_connections: AtomicUsize,
     pub use_tls: bool,
     pub sni: Option<String>,
+    /// Error rate of proxied requests, for passive health checks
+    passive: PassiveHealth,
+    /// Breaker of the route's `circuit_breaker`, if it has one
+    circuit: OnceLock<CircuitBreaker>,
 }
 
 impl UpstreamServer {
+    /// Create an upstream server from a config address
+    ///
+    /// The address may carry an `http://` or `https://` scheme, which overrides
+    /// `use_tls`; a missing port is inferred from the scheme.
     pub fn new(address_str: &str, use_tls: bool) -> Result<Self> {
-        let addr = parse_address(address_str)?;
+        let (address_str, use_tls) = normalize_upstream_address(address_str, use_tls);
+        let addr = parse_address(&address_str)?;
 
         Ok(Self {
             address: addr,
-            address_str: address_str.to_string(),
-            healthy: AtomicBool::new(true),
-            active_connections: AtomicUsize::new(0),
-            use_tls,
             sni: if use_tls {
                 address_str.split(':').next().map(|s| s.to_string())
             } else {
                 None
             },
+            address_str,
+            healthy: AtomicBool::new(true),
+            active_connections: AtomicUsize::new(0),
+            use_tls,
+            passive: PassiveHealth::default(),
+            circuit: OnceLock::new(),
         })
     }
 
+    /// Whether the server takes traffic: its active check passes, real
+    /// traffic hasn't ejected it and its circuit isn't open
     pub fn is_healthy(&self) -> bool {
+        self.is_active_healthy()
+            && !self.passive.is_ejected()
+            && self.circuit.get().is_none_or(|circuit| circuit.allow_request())
+    }
+
+    /// Result of the last active health check
+    pub fn is_active_healthy(&self) -> bool {
         self.healthy.load(Ordering::Relaxed)
     }
 
+    /// Record an active health check result
     pub fn set_healthy(&self, healthy: bool) {
         self.healthy.store(healthy, Ordering::Relaxed);
     }
 
+    pub fn passive_health(&self) -> &PassiveHealth {
+        &self.passive
+    }
+
+    /// Give the server a circuit breaker; only the first call has an effect
+    pub fn set_circuit_breaker(&self, config: CircuitBreakerConfig) {
+        let _ = self.circuit.set(CircuitBreaker::new(&self.address_str, config));
+    }
+
+    pub fn circuit_breaker(&self) -> Option<&CircuitBreaker> {
+        self.circuit.get()
+    }
+
+    /// Record whether a proxied request succeeded, for passive health checks
+    /// and the circuit breaker
+    pub fn record_response(&self, success: bool) {
+        if self.passive.record(success) {
+            warn!(upstream = %self.address_str, "Upstream ejected for failing requests");
+        }
+        if let Some(circuit) = self.circuit.get() {
+            if success {
+                circuit.record_success();
+            } else {
+                circuit.record_failure();
+            }
+        }
+    }
+
     pub fn increment_connections(&self) {
         self.active_connections.fetch_add(1, Ordering::Relaxed);
     }
@@ -57,6 +109,40 @@ impl UpstreamServer {
     }
 }
 
+/// Normalize an upstream address to `host:port` and decide whether it uses TLS
+///
+/// `https://backend` becomes `("backend:443", true)` and `http://backend`
+/// becomes `("backend:80", false)`. Without a scheme, `default_tls` picks the
+/// scheme. An explicit port is always kept as written.
+pub fn normalize_upstream_address(address: &str, default_tls: bool) -> (String, bool) {
+    let (rest, use_tls) = if let Some(rest) = strip_prefix_ignore_case(address, "https://") {
+        (rest, true)
+    } else if let Some(rest) = strip_prefix_ignore_case(address, "http://") {
+        (rest, false)
+    } else {
+        (address, default_tls)
+    };
+    let rest = rest.trim_end_matches('/');
+
+    let has_port = match rest.rfind(']') {
+        // Bracketed IPv6 literal: only a colon after the bracket is a port
+        Some(bracket) => rest[bracket..].contains(':'),
+        None => rest.contains(':'),
+    };
+
+    if has_port {
+        (rest.to_string(), use_tls)
+    } else {
+        let port = if use_tls { 443 } else { 80 };
+        (format!("{}:{}", rest, port), use_tls)
+    }
+}
+
+fn strip_prefix_ignore_case<'a>(s: &'a str, prefix: &str) -> Option<&'a str> {
+    let head = s.get(..prefix.len())?;
+    head.eq_ignore_ascii_case(prefix).then(|| &s[prefix.len()..])
+}
+
 fn parse_address(addr: &str) -> Result<SocketAddr> {
     use std::net::ToSocketAddrs;
 
@@ -243,12 +329,63 @@ fn hash_string(s: &str) -> usize {
     hasher.finish() as usize
 }
 
-fn rand_usize() -> usize {
+pub(crate) fn rand_usize() -> usize {
     use std::collections::hash_map::RandomState;
     use std::hash::{BuildHasher, Hasher};
     RandomState::new().build_hasher().finish() as usize
 }
 
+/// Timeouts to apply to an upstream connection
+#[derive(Debug, Clone, PartialEq)]
+pub struct PeerTimeouts {
+    pub connect: Duration,
+    /// `None` disables the read timeout
+    pub read: Option<Duration>,
+    /// `None` disables the write timeout
+    pub write: Option<Duration>,
+    pub idle: Duration,
+    /// TCP keepalive (interval, probe count), if enabled
+    pub keepalive: Option<(Duration, usize)>,
+    /// Requests per HTTP/1.1 connection before it is closed, 0 = unlimited
+    pub max_requests: u32,
+    /// Whether HTTP/2 connections carry concurrent streams
+    pub h2_multiplexing: bool,
+}
+
+impl PeerTimeouts {
+    /// Resolve the timeouts for a connection, using the WebSocket overrides for upgraded requests
+    pub fn from_config(timeouts: &TimeoutConfig, is_websocket: bool) -> Self {
+        let secs = |s: u64| (s > 0).then(|| Duration::from_secs(s));
+
+        if is_websocket {
+            let ws = &timeouts.websocket;
+            return Self {
+                connect: Duration::from_secs(timeouts.connect),
+                read: secs(ws.read),
+                write: secs(ws.write),
+                idle: Duration::from_secs(timeouts.idle),
+                keepalive: secs(ws.keepalive_interval)
+                    .map(|interval| (interval, timeouts.keepalive_count as usize)),
+                // An upgraded connection is never reused
+                max_requests: 0,
+                h2_multiplexing: timeouts.h2_multiplexing,
+            };
+        }
+
+        Self {
+            connect: Duration::from_secs(timeouts.connect),
+            read: Some(Duration::from_secs(timeouts.read)),
+            write: Some(Duration::from_secs(timeouts.write)),
+            idle: Duration::from_secs(timeouts.idle),
+            keepalive: timeouts.keepalive.then(|| {
+                (Duration::from_secs(timeouts.keepalive_interval), timeouts.keepalive_count as usize)
+            }),
+            max_requests: timeouts.max_requests_per_connection,
+            h2_multiplexing: timeouts.h2_multiplexing,
+        }
+    }
+}
+
 /// Load mTLS (mutual TLS) client certificate and key for upstream connections.
 /// Returns an Arc<CertKey> for use with HttpPeer's client_cert_key field.
 pub fn load_mtls_connector(
@@ -298,6 +435,31 @@ pub fn load_mtls_connector(
     Ok(Arc::new(cert_key))
 }
 
+/// Apply the route's upstream certificate verification settings to a peer
+///
+/// Hostname checking can be relaxed on its own while the chain is still
+/// verified; `insecure_skip_verify` turns off both.
+pub fn apply_tls_verification(
+    options: &mut pingora_core::upstreams::peer::PeerOptions,
+    mtls_config: &config::UpstreamMtlsConfig,
+) {
+    options.verify_cert = !mtls_config.insecure_skip_verify;
+    options.verify_hostname = !mtls_config.insecure_skip_verify && mtls_config.verify_hostname;
+}
+
+/// ALPN offered to a TLS upstream
+///
+/// With `upstream_http2` both h2 and http/1.1 are offered when `fallback` is
+/// set, so an HTTP/1.1-only upstream still completes the handshake.
+pub fn upstream_alpn(http2: bool, fallback: bool) -> pingora_core::protocols::ALPN {
+    use pingora_core::protocols::ALPN;
+    match (http2, fallback) {
+        (false, _) => ALPN::H1,
+        (true, true) => ALPN::H2H1,
+        (true, false) => ALPN::H2,
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -324,6 +486,90 @@ mod tests {
         assert_eq!(addr.port(), 80);
     }
 
+    #[test]
+    fn test_normalize_upstream_address() {
+        assert_eq!(normalize_upstream_address("https://backend", false), ("backend:443".to_string(), true));
+        assert_eq!(normalize_upstream_address("http://backend", true), ("backend:80".to_string(), false));
+        assert_eq!(normalize_upstream_address("backend:9090", false), ("backend:9090".to_string(), false));
+        assert_eq!(normalize_upstream_address("HTTPS://backend:8443/", false), ("backend:8443".to_string(), true));
+        assert_eq!(normalize_upstream_address("backend", true), ("backend:443".to_string(), true));
+        assert_eq!(normalize_upstream_address("http://[::1]", false), ("[::1]:80".to_string(), false));
+        assert_eq!(normalize_upstream_address("[::1]:8080", false), ("[::1]:8080".to_string(), false));
+        // A multi-byte character across the scheme length is not a scheme
+        assert_eq!(normalize_upstream_address("ünïcödé", true), ("ünïcödé:443".to_string(), true));
+    }
+
+    #[test]
+    fn test_upstream_server_scheme() {
+        let server = UpstreamServer::new("https://localhost", false).unwrap();
+        assert!(server.use_tls);
+        assert_eq!(server.address.port(), 443);
+        assert_eq!(server.address_str, "localhost:443");
+        assert_eq!(server.sni, Some("localhost".to_string()));
+
+        let server = UpstreamServer::new("127.0.0.1:9090", false).unwrap();
+        assert!(!server.use_tls);
+        assert_eq!(server.address.port(), 9090);
+        assert_eq!(server.sni, None);
+    }
+
+    #[test]
+    fn test_peer_timeouts_websocket() {
+        let timeouts = TimeoutConfig {
+            read: 5,
+            ..Default::default()
+        };
+
+        let http = PeerTimeouts::from_config(&timeouts, false);
+        assert_eq!(http.read, Some(Duration::from_secs(5)));
+
+        // An idle WebSocket outlives the regular read timeout and is kept alive by TCP probes
+        let ws = PeerTimeouts::from_config(&timeouts, true);
+        assert_eq!(ws.read, None);
+        assert_eq!(ws.write, None);
+        assert_eq!(ws.keepalive.map(|(interval, _)| interval), Some(Duration::from_secs(30)));
+        assert_eq!(ws.connect, http.connect);
+    }
+
+    #[test]
+    fn test_peer_timeouts_websocket_overrides() {
+        let mut timeouts = TimeoutConfig::default();
+        timeouts.websocket.read = 3600;
+        timeouts.websocket.keepalive_interval = 0;
+
+        let ws = PeerTimeouts::from_config(&timeouts, true);
+        assert_eq!(ws.read, Some(Duration::from_secs(3600)));
+        assert_eq!(ws.keepalive, None);
+    }
+
+    #[test]
+    fn test_peer_connection_reuse_limits() {
+        use crate::connection_reuse::apply_h2_multiplexing;
+        use pingora_core::upstreams::peer::HttpPeer;
+
+        let timeouts = TimeoutConfig {
+            max_requests_per_connection: 100,
+            h2_multiplexing: false,
+            ..Default::default()
+        };
+        let resolved = PeerTimeouts::from_config(&timeouts, false);
+        assert_eq!(resolved.max_requests, 100);
+
+        let mut peer = HttpPeer::new("127.0.0.1:8080", true, "backend".to_string());
+        apply_h2_multiplexing(&mut peer, resolved.h2_multiplexing);
+        assert_eq!(peer.options.max_h2_streams, 1);
+
+        let ws = PeerTimeouts::from_config(&timeouts, true);
+        assert_eq!(ws.max_requests, 0);
+
+        let defaults = PeerTimeouts::from_config(&TimeoutConfig::default(), false);
+        assert_eq!(defaults.max_requests, 0);
+        let mut peer = HttpPeer::new("127.0.0.1:8080", true, "backend".to_string());
+        let streams = peer.options.max_h2_streams;
+        apply_h2_multiplexing(&mut peer, defaults.h2_multiplexing);
+        assert_eq!(peer.options.max_h2_streams, streams);
+    }
+
     #[test]
     fn test_upstream_server_health() {
         let server = UpstreamServer::new("127.0.0.1:8080", false).unwrap();
@@ -524,4 +770,111 @@ mod tests {
 
         assert_eq!(selector.servers().len(), 2);
     }
+
+    fn mtls_config(insecure_skip_verify: bool, verify_hostname: bool) -> config::UpstreamMtlsConfig {
+        config::UpstreamMtlsConfig {
+            client_cert: "client.pem".to_string(),
+            client_key: "client.key".to_string(),
+            ca_cert: None,
+            insecure_skip_verify,
+            verify_hostname,
+        }
+    }
+
+    #[test]
+    fn test_tls_verification_settings() {
+        let mut options = pingora_core::upstreams::peer::PeerOptions::new();
+
+        // Default: chain and hostname are both verified
+        apply_tls_verification(&mut options, &mtls_config(false, true));
+        assert!(options.verify_cert);
+        assert!(options.verify_hostname);
+
+        // Hostname mismatch tolerated, chain still verified
+        apply_tls_verification(&mut options, &mtls_config(false, false));
+        assert!(options.verify_cert);
+        assert!(!options.verify_hostname);
+
+        apply_tls_verification(&mut options, &mtls_config(true, true));
+        assert!(!options.verify_cert);
+        assert!(!options.verify_hostname);
+    }
+
+    #[test]
+    fn test_upstream_alpn() {
+        use pingora_core::protocols::ALPN;
+        assert_eq!(upstream_alpn(false, true), ALPN::H1);
+        assert_eq!(upstream_alpn(true, true), ALPN::H2H1);
+        assert_eq!(upstream_alpn(true, false), ALPN::H2);
+    }
+
+    /// TLS upstream that only accepts http/1.1 in ALPN
+    async fn http1_only_upstream() -> SocketAddr {
+        use pingora_core::tls::pkey::PKey;
+        use pingora_core::tls::ssl::{select_next_proto, AlpnError, Ssl, SslAcceptor, SslMethod};
+        use pingora_core::tls::tokio_ssl::SslStream;
+        use pingora_core::tls::x509::X509;
+        use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+        let key_pair = rcgen::KeyPair::generate().unwrap();
+        let cert = rcgen::CertificateParams::new(vec!["localhost".to_string()])
+            .unwrap()
+            .self_signed(&key_pair)
+            .unwrap();
+
+        let mut acceptor = SslAcceptor::mozilla_intermediate_v5(SslMethod::tls()).unwrap();
+        acceptor.set_certificate(&X509::from_pem(cert.pem().as_bytes()).unwrap()).unwrap();
+        acceptor
+            .set_private_key(&PKey::private_key_from_pem(key_pair.serialize_pem().as_bytes()).unwrap())
+            .unwrap();
+        acceptor.set_alpn_select_callback(|_, client| {
+            select_next_proto(b"\x08http/1.1", client).ok_or(AlpnError::ALERT_FATAL)
+        });
+        let acceptor = acceptor.build();
+
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(async move {
+            let (tcp, _) = listener.accept().await.unwrap();
+            let mut stream = SslStream::new(Ssl::new(acceptor.context()).unwrap(), tcp).unwrap();
+            std::pin::Pin::new(&mut stream).accept().await.unwrap();
+
+            let mut request = Vec::new();
+            let mut buf = [0u8; 1024];
+            while !request.ends_with(b"\r\n\r\n") {
+                let n = stream.read(&mut buf).await.unwrap();
+                assert!(n > 0);
+                request.extend_from_slice(&buf[..n]);
+            }
+            stream.write_all(b"HTTP/1.1 200 OK\r\nContent-Length: 2\r\n\r\nok").await.unwrap();
+        });
+        addr
+    }
+
+    #[tokio::test]
+    async fn test_http2_falls_back_to_http1_upstream() {
+        use pingora_core::connectors::http::Connector;
+        use pingora_core::protocols::http::client::HttpSession;
+        use pingora_core::upstreams::peer::HttpPeer;
+        use pingora_http::RequestHeader;
+
+        let addr = http1_only_upstream().await;
+        let mut peer = HttpPeer::new(addr, true, "localhost".to_string());
+        peer.options.verify_cert = false;
+        peer.options.verify_hostname = false;
+        peer.options.alpn = upstream_alpn(true, true);
+
+        let connector = Connector::new(None);
+        let (mut session, _reused) = connector.get_http_session(&peer).await.unwrap();
+        assert!(matches!(session, HttpSession::H1(_)));
+
+        let mut request = RequestHeader::build("GET", b"/", None).unwrap();
+        request.insert_header("Host", "localhost").unwrap();
+        session.write_request_header(Box::new(request)).await.unwrap();
+        session.read_response_header().await.unwrap();
+
+        let response = session.response_header().unwrap();
+        assert_eq!(response.status.as_u16(), 200);
+        assert_eq!(response.version, http::Version::HTTP_11);
+    }
 }
@@ -0,0 +1,138 @@
+//! Credentials injected into upstream requests
+//!
+//! For upstreams behind their own Basic or Bearer auth, the route holds the
+//! credentials and sends them on every proxied request, replacing whatever
+//! the client sent in the same header.
+
+use crate::error::{ProxyError, Result};
+use base64::Engine;
+use config::{expand_env, UpstreamAuthConfig};
+use http::HeaderValue;
+use pingora_http::RequestHeader;
+
+/// Header name and value added to upstream requests
+#[derive(Debug)]
+pub struct UpstreamAuth {
+    header: String,
+    value: HeaderValue,
+}
+
+impl UpstreamAuth {
+    /// Build the header value, expanding `${NAME}` environment references
+    pub fn from_config(config: &UpstreamAuthConfig) -> Result<Self> {
+        let expand = |value: &str| expand_env(value).map_err(|e| ProxyError::ConfigError(e.to_string()));
+        let value = match (&config.basic, &config.bearer_token) {
+            (Some(basic), None) => {
+                let credentials = format!("{}:{}", expand(&basic.username)?, expand(&basic.password)?);
+                format!("Basic {}", base64::engine::general_purpose::STANDARD.encode(credentials))
+            }
+            (None, Some(token)) => format!("Bearer {}", expand(token)?),
+            _ => {
+                return Err(ProxyError::ConfigError(
+                    "upstream_auth needs exactly one of basic or bearer_token".to_string(),
+                ))
+            }
+        };
+
+        let mut value = HeaderValue::from_str(&value)
+            .map_err(|_| ProxyError::ConfigError("upstream_auth credentials are not a valid header value".to_string()))?;
+        value.set_sensitive(true);
+        Ok(Self {
+            header: config.header.clone(),
+            value,
+        })
+    }
+
+    /// Set the credentials on an upstream request, replacing the client's
+    pub fn apply(&self, request: &mut RequestHeader) -> pingora_error::Result<()> {
+        request.insert_header(self.header.clone(), self.value.clone())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use config::BasicAuthCredential;
+
+    fn request_with_client_auth() -> RequestHeader {
+        let mut request = RequestHeader::build("GET", b"/api", None).unwrap();
+        request.insert_header("Authorization", "Bearer client-token").unwrap();
+        request
+    }
+
+    fn header(request: &RequestHeader, name: &str) -> Vec<String> {
+        request
+            .headers
+            .get_all(name)
+            .iter()
+            .map(|v| v.to_str().unwrap().to_string())
+            .collect()
+    }
+
+    #[test]
+    fn test_basic_credentials_replace_client_authorization() {
+        let auth = UpstreamAuth::from_config(&UpstreamAuthConfig {
+            basic: Some(BasicAuthCredential {
+                username: "svc".to_string(),
+                password: "pa:ss".to_string(),
+            }),
+            bearer_token: None,
+            header: "Authorization".to_string(),
+        })
+        .unwrap();
+
+        let mut request = request_with_client_auth();
+        auth.apply(&mut request).unwrap();
+        // base64("svc:pa:ss")
+        assert_eq!(header(&request, "authorization"), vec!["Basic c3ZjOnBhOnNz"]);
+    }
+
+    #[test]
+    fn test_bearer_token_from_env() {
+        std::env::set_var("AVALON_TEST_UPSTREAM_AUTH_TOKEN", "upstream-token");
+        let auth = UpstreamAuth::from_config(&UpstreamAuthConfig {
+            basic: None,
+            bearer_token: Some("${AVALON_TEST_UPSTREAM_AUTH_TOKEN}".to_string()),
+            header: "Authorization".to_string(),
+        })
+        .unwrap();
+
+        let mut request = request_with_client_auth();
+        auth.apply(&mut request).unwrap();
+        assert_eq!(header(&request, "authorization"), vec!["Bearer upstream-token"]);
+    }
+
+    #[test]
+    fn test_proxy_authorization_keeps_client_authorization() {
+        let auth = UpstreamAuth::from_config(&UpstreamAuthConfig {
+            basic: None,
+            bearer_token: Some("proxy-token".to_string()),
+            header: "Proxy-Authorization".to_string(),
+        })
+        .unwrap();
+
+        let mut request = request_with_client_auth();
+        auth.apply(&mut request).unwrap();
+        assert_eq!(header(&request, "proxy-authorization"), vec!["Bearer proxy-token"]);
+        assert_eq!(header(&request, "authorization"), vec!["Bearer client-token"]);
+    }
+
+    #[test]
+    fn test_invalid_config_rejected() {
+        let config = UpstreamAuthConfig {
+            basic: None,
+            bearer_token: Some("${AVALON_TEST_UPSTREAM_AUTH_UNSET}".to_string()),
+            header: "Authorization".to_string(),
+        };
+        assert!(UpstreamAuth::from_config(&config).is_err());
+
+        let config = UpstreamAuthConfig { bearer_token: None, ..config };
+        assert!(UpstreamAuth::from_config(&config).is_err());
+
+        let config = UpstreamAuthConfig {
+            bearer_token: Some("line\nbreak".to_string()),
+            ..config
+        };
+        assert!(UpstreamAuth::from_config(&config).is_err());
+    }
+}
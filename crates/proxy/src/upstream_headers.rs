@@ -0,0 +1,106 @@
+//! Configured header changes for upstream requests (`headers_up`)
+//!
+//! Keys follow the usual proxy convention:
+//! - `Name = "value"` sets the header, replacing any value from the client
+//! - `+Name = "value"` adds a value, keeping existing ones
+//! - `-Name = ""` removes the header
+//!
+//! The `Host` header is set by `host_header` before `headers_up` is applied.
+
+use crate::upstream::UpstreamServer;
+use config::HostHeader;
+use pingora_http::RequestHeader;
+
+/// Set the `Host` header of an upstream request sent to `upstream`
+pub fn apply_host_header(
+    request: &mut RequestHeader,
+    host_header: &HostHeader,
+    upstream: &UpstreamServer,
+) -> pingora_error::Result<()> {
+    match host_header {
+        HostHeader::Preserve => Ok(()),
+        HostHeader::Upstream => request.insert_header("Host", upstream_host(upstream)),
+        HostHeader::Literal(host) => request.insert_header("Host", host.as_str()),
+    }
+}
+
+/// Host header naming `upstream`, without the scheme's default port
+fn upstream_host(upstream: &UpstreamServer) -> String {
+    let default_port = if upstream.use_tls { ":443" } else { ":80" };
+    let address = upstream.address_str.as_str();
+    address.strip_suffix(default_port).unwrap_or(address).to_string()
+}
+
+/// Apply `headers_up` entries to an upstream request
+pub fn apply_headers_up(
+    request: &mut RequestHeader,
+    headers: &[(String, String)],
+) -> pingora_error::Result<()> {
+    for (key, value) in headers {
+        if let Some(name) = key.strip_prefix('-') {
+            request.remove_header(name);
+        } else if let Some(name) = key.strip_prefix('+') {
+            request.append_header(name.to_string(), value.as_str())?;
+        } else {
+            request.insert_header(key.clone(), value.as_str())?;
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn header_values(request: &RequestHeader, name: &str) -> Vec<String> {
+        request
+            .headers
+            .get_all(name)
+            .iter()
+            .map(|v| v.to_str().unwrap().to_string())
+            .collect()
+    }
+
+    #[test]
+    fn test_headers_up_reach_upstream_request() {
+        let mut request = RequestHeader::build("GET", b"/api", None).unwrap();
+        request.insert_header("X-Tenant", "client-supplied").unwrap();
+        request.insert_header("Accept", "text/html").unwrap();
+        request.insert_header("X-Debug", "1").unwrap();
+
+        let headers = vec![
+            ("X-Tenant".to_string(), "acme".to_string()),
+            ("X-Api-Version".to_string(), "2".to_string()),
+            ("+Accept".to_string(), "application/json".to_string()),
+            ("-X-Debug".to_string(), String::new()),
+        ];
+        apply_headers_up(&mut request, &headers).unwrap();
+
+        assert_eq!(header_values(&request, "x-tenant"), vec!["acme"]);
+        assert_eq!(header_values(&request, "x-api-version"), vec!["2"]);
+        assert_eq!(header_values(&request, "accept"), vec!["text/html", "application/json"]);
+        assert!(request.headers.get("x-debug").is_none());
+    }
+
+    fn sent_host(host_header: HostHeader, upstream: &str) -> String {
+        let mut request = RequestHeader::build("GET", b"/", None).unwrap();
+        request.insert_header("Host", "www.example.com").unwrap();
+        let upstream = UpstreamServer::new(upstream, false).unwrap();
+        apply_host_header(&mut request, &host_header, &upstream).unwrap();
+        header_values(&request, "host").join(",")
+    }
+
+    #[test]
+    fn test_host_header_modes() {
+        assert_eq!(sent_host(HostHeader::Preserve, "127.0.0.1:3000"), "www.example.com");
+        assert_eq!(sent_host(HostHeader::Upstream, "127.0.0.1:3000"), "127.0.0.1:3000");
+        assert_eq!(
+            sent_host(HostHeader::Literal("api.internal".to_string()), "127.0.0.1:3000"),
+            "api.internal"
+        );
+
+        // The scheme's default port is left out
+        assert_eq!(sent_host(HostHeader::Upstream, "http://127.0.0.1"), "127.0.0.1");
+        assert_eq!(sent_host(HostHeader::Upstream, "https://127.0.0.1"), "127.0.0.1");
+    }
+}
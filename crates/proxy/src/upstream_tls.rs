@@ -0,0 +1,103 @@
+//! Upstream TLS handshake failures
+//!
+//! With `upstream_tls`, a handshake that fails (untrusted or expired
+//! certificate, SNI mismatch, protocol errors) surfaces as a connect failure
+//! like a refused connection. These are told apart by the error type Pingora
+//! assigns, so they can be logged with their reason, counted in
+//! `avalon_upstream_tls_errors_total` and answered with a 502 that says what
+//! went wrong.
+
+use pingora_core::{Error, ErrorType};
+use std::fmt;
+
+/// Why a handshake with an upstream failed
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum UpstreamTlsFailure {
+    /// The upstream's certificate didn't verify
+    InvalidCertificate,
+    /// The handshake failed for another reason
+    Handshake,
+    /// The handshake didn't finish in time
+    Timeout,
+}
+
+impl UpstreamTlsFailure {
+    /// The handshake failure behind a connect error, if it is one
+    pub fn classify(error: &Error) -> Option<Self> {
+        match error.etype() {
+            ErrorType::InvalidCert => Some(Self::InvalidCertificate),
+            ErrorType::TLSHandshakeFailure | ErrorType::HandshakeError => Some(Self::Handshake),
+            ErrorType::TLSHandshakeTimedout => Some(Self::Timeout),
+            _ => None,
+        }
+    }
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::InvalidCertificate => "invalid_certificate",
+            Self::Handshake => "handshake_failure",
+            Self::Timeout => "handshake_timeout",
+        }
+    }
+
+    /// Body of the 502 sent to the client
+    pub fn message(&self) -> &'static str {
+        match self {
+            Self::InvalidCertificate => "Bad Gateway: upstream TLS certificate could not be verified",
+            Self::Handshake => "Bad Gateway: upstream TLS handshake failed",
+            Self::Timeout => "Bad Gateway: upstream TLS handshake timed out",
+        }
+    }
+}
+
+impl fmt::Display for UpstreamTlsFailure {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pingora_core::connectors::TransportConnector;
+    use pingora_core::upstreams::peer::HttpPeer;
+    use std::sync::Arc;
+    use tls::{CertResolver, SniTlsSettings};
+    use tokio::net::TcpListener;
+
+    #[test]
+    fn test_classify_error_types() {
+        let classify = |etype| UpstreamTlsFailure::classify(&Error::new(etype));
+        assert_eq!(classify(ErrorType::InvalidCert), Some(UpstreamTlsFailure::InvalidCertificate));
+        assert_eq!(classify(ErrorType::TLSHandshakeFailure), Some(UpstreamTlsFailure::Handshake));
+        assert_eq!(classify(ErrorType::TLSHandshakeTimedout), Some(UpstreamTlsFailure::Timeout));
+        assert_eq!(classify(ErrorType::ConnectRefused), None);
+        assert_eq!(classify(ErrorType::ConnectTimedout), None);
+    }
+
+    #[tokio::test]
+    async fn test_untrusted_upstream_certificate() {
+        // An upstream presenting a self-signed certificate
+        let bundle = tls::self_signed::generate_self_signed("upstream.internal", 30).unwrap();
+        let resolver = Arc::new(CertResolver::new());
+        resolver.set_default(CertResolver::load_from_pem(&bundle.certificate_pem, &bundle.private_key_pem).unwrap());
+        let acceptor = SniTlsSettings::new(resolver).build().unwrap();
+
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(async move {
+            if let Ok((stream, _)) = listener.accept().await {
+                let _ = acceptor.accept(stream).await;
+            }
+        });
+
+        let peer = HttpPeer::new(addr, true, "upstream.internal".to_string());
+        let error = TransportConnector::new(None).new_stream(&peer).await.unwrap_err();
+        assert_eq!(
+            UpstreamTlsFailure::classify(&error),
+            Some(UpstreamTlsFailure::InvalidCertificate),
+            "{}",
+            error
+        );
+    }
+}
@@ -0,0 +1,58 @@
+//! User-Agent patterns for route matching and bot blocking
+//!
+//! Both `match.user_agent` and a route's `block_user_agents` are lists of
+//! regexes compiled once into a `RegexSet` when the route table is built.
+//! A request without a `User-Agent` header is checked as an empty string,
+//! so `^$` catches clients that omit it.
+
+use regex::RegexSet;
+
+/// Compiled set of User-Agent regexes; matches when any pattern does
+pub struct UserAgentMatcher {
+    patterns: RegexSet,
+}
+
+impl UserAgentMatcher {
+    pub fn new(patterns: &[String]) -> Result<Self, regex::Error> {
+        Ok(Self {
+            patterns: RegexSet::new(patterns)?,
+        })
+    }
+
+    /// Whether the request's User-Agent matches one of the patterns
+    pub fn matches(&self, user_agent: Option<&str>) -> bool {
+        self.patterns.is_match(user_agent.unwrap_or(""))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const BROWSER: &str = "Mozilla/5.0 (Windows NT 10.0; Win64; x64) AppleWebKit/537.36 (KHTML, like Gecko) Chrome/124.0 Safari/537.36";
+
+    fn matcher(patterns: &[&str]) -> UserAgentMatcher {
+        UserAgentMatcher::new(&patterns.iter().map(|p| p.to_string()).collect::<Vec<_>>()).unwrap()
+    }
+
+    #[test]
+    fn test_any_pattern_matches() {
+        let bots = matcher(&["(?i)bot\\b", "^curl/", "^python-requests/"]);
+        assert!(bots.matches(Some("Mozilla/5.0 (compatible; Googlebot/2.1)")));
+        assert!(bots.matches(Some("curl/8.4.0")));
+        assert!(bots.matches(Some("python-requests/2.31")));
+        assert!(!bots.matches(Some(BROWSER)));
+    }
+
+    #[test]
+    fn test_missing_user_agent_checked_as_empty() {
+        assert!(matcher(&["^$"]).matches(None));
+        assert!(!matcher(&["^$"]).matches(Some(BROWSER)));
+        assert!(!matcher(&["bot"]).matches(None));
+    }
+
+    #[test]
+    fn test_invalid_pattern_rejected() {
+        assert!(UserAgentMatcher::new(&["(unclosed".to_string()]).is_err());
+    }
+}
@@ -1,6 +1,8 @@
 //! ACME client for automatic certificate provisioning
 
 use crate::error::TlsError;
+use crate::issuance_lock::IssuanceLock;
+use crate::ocsp::must_staple_extension;
 use crate::storage::{AcmeAccount, CertBundle, CertStorage};
 use chrono::Utc;
 use dashmap::DashMap;
@@ -22,6 +24,8 @@ pub struct AcmeManager {
     email: String,
     storage: Arc<CertStorage>,
     challenge_tokens: ChallengeTokens,
+    issuance_lock: IssuanceLock,
+    must_staple: bool,
 }
 
 impl AcmeManager {
@@ -32,9 +36,17 @@ impl AcmeManager {
             email,
             storage,
             challenge_tokens: Arc::new(DashMap::new()),
+            issuance_lock: IssuanceLock::new(IssuanceLock::process_owner_id()),
+            must_staple: false,
         }
     }
 
+    /// Request certificates with the OCSP Must-Staple extension
+    pub fn with_must_staple(mut self, must_staple: bool) -> Self {
+        self.must_staple = must_staple;
+        self
+    }
+
     /// Get the challenge tokens map for HTTP-01 challenge handling
     pub fn challenge_tokens(&self) -> ChallengeTokens {
         self.challenge_tokens.clone()
@@ -125,15 +137,20 @@ impl AcmeManager {
         // Validate domain first
         Self::validate_domain(domain)?;
 
-        // Wrap the entire operation in a timeout
-        let timeout_duration = Duration::from_secs(ACME_TOTAL_TIMEOUT_SECS);
-        match tokio::time::timeout(timeout_duration, self.obtain_certificate_inner(domain)).await {
-            Ok(result) => result,
-            Err(_) => Err(TlsError::Acme(format!(
-                "ACME certificate acquisition timed out after {} seconds",
-                ACME_TOTAL_TIMEOUT_SECS
-            ))),
-        }
+        // Only one node in a cluster sharing storage issues per domain;
+        // the issuance itself is wrapped in a timeout
+        self.issuance_lock
+            .run(&self.storage, domain, || async {
+                let timeout_duration = Duration::from_secs(ACME_TOTAL_TIMEOUT_SECS);
+                match tokio::time::timeout(timeout_duration, self.obtain_certificate_inner(domain)).await {
+                    Ok(result) => result,
+                    Err(_) => Err(TlsError::Acme(format!(
+                        "ACME certificate acquisition timed out after {} seconds",
+                        ACME_TOTAL_TIMEOUT_SECS
+                    ))),
+                }
+            })
+            .await
     }
 
     /// Internal certificate acquisition (without timeout wrapper)
@@ -198,19 +215,11 @@ impl AcmeManager {
         }
 
         // Generate CSR
-        let mut params = CertificateParams::new(vec![domain.to_string()])
-            .map_err(|e| TlsError::Acme(e.to_string()))?;
-        params.distinguished_name = DistinguishedName::new();
-
-        let key_pair = KeyPair::generate()
-            .map_err(|e| TlsError::Acme(e.to_string()))?;
-        let csr = params
-            .serialize_request(&key_pair)
-            .map_err(|e| TlsError::Acme(e.to_string()))?;
+        let (csr, key_pair) = Self::certificate_request(domain, self.must_staple)?;
 
         // Finalize order
         order
-            .finalize(csr.der())
+            .finalize(&csr)
             .await
             .map_err(|e| TlsError::Acme(e.to_string()))?;
 
@@ -240,6 +249,24 @@ impl AcmeManager {
         Ok(bundle)
     }
 
+    /// Generate a key pair and a DER-encoded CSR for `domain`
+    fn certificate_request(domain: &str, must_staple: bool) -> Result<(Vec<u8>, KeyPair), TlsError> {
+        let mut params = CertificateParams::new(vec![domain.to_string()])
+            .map_err(|e| TlsError::Acme(e.to_string()))?;
+        params.distinguished_name = DistinguishedName::new();
+        if must_staple {
+            params.custom_extensions.push(must_staple_extension());
+        }
+
+        let key_pair = KeyPair::generate()
+            .map_err(|e| TlsError::Acme(e.to_string()))?;
+        let csr = params
+            .serialize_request(&key_pair)
+            .map_err(|e| TlsError::Acme(e.to_string()))?;
+
+        Ok((csr.der().to_vec(), key_pair))
+    }
+
     /// Wait for order to be ready
     async fn wait_for_order_ready(
         &self,
@@ -283,3 +310,29 @@ impl AcmeManager {
         Ok(())
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// TLS Feature OID followed by the `status_request` value, as DER
+    const MUST_STAPLE_DER: &[u8] = &[
+        0x06, 0x08, 0x2b, 0x06, 0x01, 0x05, 0x05, 0x07, 0x01, 0x18, 0x04, 0x05, 0x30, 0x03, 0x02, 0x01, 0x05,
+    ];
+
+    fn contains(haystack: &[u8], needle: &[u8]) -> bool {
+        haystack.windows(needle.len()).any(|w| w == needle)
+    }
+
+    #[test]
+    fn test_csr_includes_must_staple() {
+        let (csr, _) = AcmeManager::certificate_request("example.com", true).unwrap();
+        assert!(contains(&csr, MUST_STAPLE_DER));
+    }
+
+    #[test]
+    fn test_csr_without_must_staple() {
+        let (csr, _) = AcmeManager::certificate_request("example.com", false).unwrap();
+        assert!(!contains(&csr, &MUST_STAPLE_DER[..10]));
+    }
+}
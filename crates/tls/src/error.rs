@@ -21,4 +21,19 @@ pub enum TlsError {
 
     #[error("JSON error: {0}")]
     Json(#[from] serde_json::Error),
+
+    /// Stored certificate bundle is unreadable, e.g. truncated by a crash
+    #[error("Corrupt certificate data for {domain}: {reason}")]
+    CorruptCertificate { domain: String, reason: String },
+
+    /// Stored certificate and private key parse but don't belong together
+    #[error("Certificate and private key for {domain} do not match")]
+    KeyMismatch { domain: String },
+}
+
+impl TlsError {
+    /// Whether the stored bundle is unusable and should be issued again
+    pub fn is_corrupt_cert(&self) -> bool {
+        matches!(self, Self::CorruptCertificate { .. } | Self::KeyMismatch { .. })
+    }
 }
@@ -0,0 +1,158 @@
+//! Connection-level log of failed TLS handshakes
+//!
+//! A handshake that fails never becomes an HTTP request, so it never reaches
+//! the access log. Pingora's listener, the only layer that sees both the
+//! client address and the handshake error, reports failures through the
+//! `log` crate as `Downstream handshake error from <addr>: <error>`; the
+//! server turns those records into [`HandshakeFailure`]s, logs each one under
+//! the `avalon::tls` target and passes it to a hook that counts it in the
+//! metrics.
+
+use std::fmt;
+use std::net::{IpAddr, SocketAddr};
+use std::sync::Arc;
+use tracing::warn;
+
+/// Prefix of the listener's handshake error records
+const LISTENER_ERROR_PREFIX: &str = "Downstream handshake error from ";
+
+/// Why a handshake failed
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum HandshakeFailureReason {
+    /// SNI matched no certificate and `reject_unknown_sni` is set
+    UnknownSni,
+    /// No certificate was served, either none matched or the certificate
+    /// callback refused the handshake
+    NoCertificate,
+    /// Must-Staple certificate without a fresh OCSP staple
+    MissingStaple,
+    /// The certificate or key couldn't be installed on the connection
+    CertificateSetup,
+    /// Client and server share no TLS version
+    ProtocolVersion,
+    /// Client certificate missing where required, or not verified
+    ClientCertificate,
+    /// Any other handshake error
+    Other,
+}
+
+impl HandshakeFailureReason {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::UnknownSni => "unknown_sni",
+            Self::NoCertificate => "no_certificate",
+            Self::MissingStaple => "missing_ocsp_staple",
+            Self::CertificateSetup => "certificate_setup",
+            Self::ProtocolVersion => "protocol_version",
+            Self::ClientCertificate => "client_certificate",
+            Self::Other => "handshake_error",
+        }
+    }
+
+    /// Classify a handshake error by the OpenSSL reason it carries
+    pub fn classify(error: &str) -> Self {
+        const PROTOCOL_VERSION: &[&str] = &[
+            "unsupported protocol",
+            "wrong version number",
+            "version too low",
+            "no protocols available",
+            "alert protocol version",
+        ];
+        const CLIENT_CERTIFICATE: &[&str] = &[
+            "peer did not return a certificate",
+            "certificate required",
+            "certificate verify failed",
+            "alert unknown ca",
+            "alert bad certificate",
+        ];
+        const NO_CERTIFICATE: &[&str] = &["no certificate assigned", "no shared cipher", "cert cb error"];
+
+        let error = error.to_ascii_lowercase();
+        let matches = |reasons: &[&str]| reasons.iter().any(|r| error.contains(r));
+        if matches(PROTOCOL_VERSION) {
+            Self::ProtocolVersion
+        } else if matches(CLIENT_CERTIFICATE) {
+            Self::ClientCertificate
+        } else if matches(NO_CERTIFICATE) {
+            Self::NoCertificate
+        } else {
+            Self::Other
+        }
+    }
+}
+
+impl fmt::Display for HandshakeFailureReason {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+/// One failed handshake
+#[derive(Debug, Clone)]
+pub struct HandshakeFailure {
+    /// Client address; `None` for Unix socket listeners
+    pub client_ip: Option<IpAddr>,
+    pub reason: HandshakeFailureReason,
+    /// Underlying error
+    pub detail: String,
+}
+
+impl HandshakeFailure {
+    /// Parse one of the listener's `Downstream handshake error from ...` records
+    pub fn from_listener_error(message: &str) -> Option<Self> {
+        let (addr, error) = message.strip_prefix(LISTENER_ERROR_PREFIX)?.split_once(": ")?;
+        Some(Self {
+            client_ip: addr.parse::<SocketAddr>().ok().map(|addr| addr.ip()),
+            reason: HandshakeFailureReason::classify(error),
+            detail: error.to_string(),
+        })
+    }
+}
+
+/// Called for every failed handshake
+pub type HandshakeFailureHook = Arc<dyn Fn(&HandshakeFailure) + Send + Sync>;
+
+/// Write the connection-level log entry for a failed handshake
+pub fn log_handshake_failure(failure: &HandshakeFailure) {
+    warn!(
+        target: "avalon::tls",
+        client_ip = failure.client_ip.map(|ip| ip.to_string()).as_deref().unwrap_or("-"),
+        reason = %failure.reason,
+        detail = %failure.detail,
+        "TLS handshake failed"
+    );
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_listener_error_parsed() {
+        let failure = HandshakeFailure::from_listener_error(
+            "Downstream handshake error from 203.0.113.7:51234: TLSHandshakeFailure context: \
+             error:0A000102:SSL routines:tls_early_post_process_client_hello:unsupported protocol",
+        )
+        .unwrap();
+        assert_eq!(failure.client_ip, Some("203.0.113.7".parse().unwrap()));
+        assert_eq!(failure.reason, HandshakeFailureReason::ProtocolVersion);
+
+        let failure = HandshakeFailure::from_listener_error(
+            "Downstream handshake error from [2001:db8::1]:443: error:0A0000C7:SSL routines:\
+             tls_process_client_certificate:peer did not return a certificate",
+        )
+        .unwrap();
+        assert_eq!(failure.client_ip, Some("2001:db8::1".parse().unwrap()));
+        assert_eq!(failure.reason, HandshakeFailureReason::ClientCertificate);
+
+        assert!(HandshakeFailure::from_listener_error("H2 handshake error broken pipe").is_none());
+    }
+
+    #[test]
+    fn test_classify_handshake_errors() {
+        assert_eq!(HandshakeFailureReason::classify("SSL routines::wrong version number"), HandshakeFailureReason::ProtocolVersion);
+        assert_eq!(HandshakeFailureReason::classify("SSL routines::certificate verify failed"), HandshakeFailureReason::ClientCertificate);
+        assert_eq!(HandshakeFailureReason::classify("SSL routines::no shared cipher"), HandshakeFailureReason::NoCertificate);
+        assert_eq!(HandshakeFailureReason::classify("Connection reset by peer"), HandshakeFailureReason::Other);
+    }
+}
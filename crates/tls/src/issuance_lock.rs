@@ -0,0 +1,189 @@
+//! Cluster-wide lock around ACME issuance
+//!
+//! With shared certificate storage every node may decide to obtain the same
+//! certificate at once and run into CA rate limits. The lock lives in the
+//! storage backend, so only one node talks to the CA per domain; the others
+//! wait for it to finish and pick up the stored result.
+
+use crate::error::TlsError;
+use crate::storage::{CertBundle, CertStorage};
+use chrono::{DateTime, Utc};
+use std::future::Future;
+use std::time::{Duration, Instant};
+use tracing::{debug, info, warn};
+
+/// Default lock lifetime; must outlast a full issuance attempt
+pub const DEFAULT_LOCK_TTL: Duration = Duration::from_secs(360);
+
+/// How often waiting nodes check the lock and storage
+const DEFAULT_POLL_INTERVAL: Duration = Duration::from_secs(2);
+
+/// Per-domain issuance lock held in certificate storage
+pub struct IssuanceLock {
+    owner: String,
+    ttl: Duration,
+    poll_interval: Duration,
+}
+
+impl IssuanceLock {
+    /// Create a lock identified by `owner`, which must be unique per node
+    pub fn new(owner: impl Into<String>) -> Self {
+        Self {
+            owner: owner.into(),
+            ttl: DEFAULT_LOCK_TTL,
+            poll_interval: DEFAULT_POLL_INTERVAL,
+        }
+    }
+
+    /// Override the lock TTL and polling interval
+    pub fn with_timing(mut self, ttl: Duration, poll_interval: Duration) -> Self {
+        self.ttl = ttl;
+        self.poll_interval = poll_interval;
+        self
+    }
+
+    /// A lock owner id unique to this process
+    pub fn process_owner_id() -> String {
+        let host = std::env::var("HOSTNAME").unwrap_or_else(|_| "avalon".to_string());
+        let nanos = Utc::now().timestamp_nanos_opt().unwrap_or_default();
+        format!("{}-{}-{:x}", host, std::process::id(), nanos)
+    }
+
+    /// Run `issue` for a domain while holding the lock
+    ///
+    /// If another node holds the lock, waits for it to release or expire.
+    /// A certificate stored by another node after this call started is
+    /// returned instead of issuing again.
+    pub async fn run<F, Fut>(&self, storage: &CertStorage, domain: &str, issue: F) -> Result<CertBundle, TlsError>
+    where
+        F: FnOnce() -> Fut,
+        Fut: Future<Output = Result<CertBundle, TlsError>>,
+    {
+        let started = Utc::now();
+        // A crashed holder's lock expires after one TTL
+        let deadline = Instant::now() + self.ttl * 2;
+
+        while !storage.try_lock(domain, &self.owner, self.ttl).await? {
+            if let Some(bundle) = fresh_cert(storage, domain, started).await? {
+                info!(domain = %domain, "Certificate issued by another node");
+                storage.write_pem_files(&bundle).await?;
+                return Ok(bundle);
+            }
+            if Instant::now() >= deadline {
+                return Err(TlsError::Acme(format!(
+                    "Timed out waiting for issuance lock on {}",
+                    domain
+                )));
+            }
+            debug!(domain = %domain, "Issuance in progress on another node, waiting");
+            tokio::time::sleep(self.poll_interval).await;
+        }
+
+        // Another node may have finished right before we took the lock
+        let result = match fresh_cert(storage, domain, started).await {
+            Ok(Some(bundle)) => storage.write_pem_files(&bundle).await.map(|_| bundle),
+            Ok(None) => issue().await,
+            Err(e) => Err(e),
+        };
+
+        if let Err(e) = storage.unlock(domain, &self.owner).await {
+            warn!(domain = %domain, error = %e, "Failed to release issuance lock");
+        }
+
+        result
+    }
+}
+
+/// A stored certificate created at or after `since`
+async fn fresh_cert(
+    storage: &CertStorage,
+    domain: &str,
+    since: DateTime<Utc>,
+) -> Result<Option<CertBundle>, TlsError> {
+    // A corrupt bundle must not block issuing its replacement
+    Ok(storage
+        .load_cert_or_missing(domain)
+        .await?
+        .filter(|bundle| bundle.created_at >= since))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::storage_backend::{MemoryBackend, StorageBackend};
+    use std::sync::Arc;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use tempfile::TempDir;
+
+    /// Two nodes sharing one backend, each with its own local PEM directory
+    async fn make_node(backend: Arc<dyn StorageBackend>) -> (TempDir, CertStorage) {
+        let temp_dir = TempDir::new().unwrap();
+        let storage = CertStorage::with_backend(temp_dir.path(), backend).await.unwrap();
+        (temp_dir, storage)
+    }
+
+    async fn fake_issue(storage: &CertStorage, issued: &AtomicUsize, domain: &str) -> Result<CertBundle, TlsError> {
+        issued.fetch_add(1, Ordering::SeqCst);
+        tokio::time::sleep(Duration::from_millis(100)).await;
+        let bundle = crate::self_signed::generate_self_signed(domain, 90)?;
+        storage.store_cert(&bundle).await?;
+        storage.write_pem_files(&bundle).await?;
+        Ok(bundle)
+    }
+
+    fn make_lock(owner: &str) -> IssuanceLock {
+        IssuanceLock::new(owner).with_timing(Duration::from_secs(5), Duration::from_millis(10))
+    }
+
+    #[tokio::test]
+    async fn test_concurrent_obtain_issues_once() {
+        let backend: Arc<dyn StorageBackend> = Arc::new(MemoryBackend::new());
+        let (dir_a, node_a) = make_node(backend.clone()).await;
+        let (dir_b, node_b) = make_node(backend).await;
+        let issued = AtomicUsize::new(0);
+
+        let lock_a = make_lock("node-a");
+        let lock_b = make_lock("node-b");
+        let (a, b) = tokio::join!(
+            lock_a.run(&node_a, "example.com", || fake_issue(&node_a, &issued, "example.com")),
+            lock_b.run(&node_b, "example.com", || fake_issue(&node_b, &issued, "example.com")),
+        );
+
+        assert_eq!(issued.load(Ordering::SeqCst), 1);
+        assert_eq!(a.unwrap().certificate_pem, b.unwrap().certificate_pem);
+
+        // Both nodes end up with PEM files for their listeners
+        assert!(dir_a.path().join("certs/example.com.crt").exists());
+        assert!(dir_b.path().join("certs/example.com.crt").exists());
+    }
+
+    #[tokio::test]
+    async fn test_lock_released_after_failure() {
+        let backend: Arc<dyn StorageBackend> = Arc::new(MemoryBackend::new());
+        let (_dir, storage) = make_node(backend).await;
+
+        let result = make_lock("node-a")
+            .run(&storage, "example.com", || async { Err(TlsError::Acme("boom".to_string())) })
+            .await;
+        assert!(result.is_err());
+
+        assert!(storage.try_lock("example.com", "node-b", Duration::from_secs(5)).await.unwrap());
+    }
+
+    #[tokio::test]
+    async fn test_expired_lock_is_taken_over() {
+        let backend: Arc<dyn StorageBackend> = Arc::new(MemoryBackend::new());
+        let (_dir, storage) = make_node(backend).await;
+        let issued = AtomicUsize::new(0);
+
+        // A node crashed while holding the lock
+        assert!(storage.try_lock("example.com", "crashed", Duration::from_millis(20)).await.unwrap());
+
+        let bundle = make_lock("node-a")
+            .run(&storage, "example.com", || fake_issue(&storage, &issued, "example.com"))
+            .await
+            .unwrap();
+        assert_eq!(bundle.domain, "example.com");
+        assert_eq!(issued.load(Ordering::SeqCst), 1);
+    }
+}
@@ -4,25 +4,38 @@
 //! (Let's Encrypt) and TLS certificate management.
 
 pub mod acme;
+pub mod client_auth;
 pub mod cloudflare;
 pub mod error;
+pub mod handshake_log;
+pub mod issuance_lock;
 pub mod listener;
+pub mod ocsp;
 pub mod provider;
+pub mod redis_storage;
 pub mod renewal;
+pub mod s3_storage;
 pub mod self_signed;
 pub mod sni;
 pub mod storage;
+pub mod storage_backend;
 
 pub use acme::{AcmeManager, ChallengeTokens};
+pub use client_auth::request_client_certs;
 pub use error::TlsError;
+pub use handshake_log::{HandshakeFailure, HandshakeFailureHook, HandshakeFailureReason};
+pub use issuance_lock::IssuanceLock;
 pub use listener::{SniTlsSettings, load_all_domain_certs};
 pub use provider::{load_certs_from_storage, CertResolver};
+pub use redis_storage::RedisBackend;
 pub use renewal::{RenewalScheduler, shutdown_channel};
+pub use s3_storage::{S3Backend, S3Settings};
 pub use sni::{SniResolver, load_all_certs};
 pub use storage::{
     CertStorage, DiscoveredCert, auto_select_certificate, discover_certificates,
     find_best_cert_for_domain,
 };
+pub use storage_backend::{FileBackend, MemoryBackend, StorageBackend};
 
 // ============================================================================
 // ACME Certificate Authority URLs
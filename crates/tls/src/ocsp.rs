@@ -0,0 +1,182 @@
+//! OCSP stapling and the OCSP Must-Staple certificate extension
+//!
+//! Certificates carrying the TLS Feature extension with `status_request`
+//! (RFC 7633) must always be served with a valid OCSP response. avalon
+//! fetches staples from the issuer's responder and refuses to serve such a
+//! certificate while it has no fresh staple, rather than letting clients
+//! hard-fail on a missing one.
+
+use crate::error::TlsError;
+use chrono::{DateTime, NaiveDateTime, Utc};
+use openssl::hash::MessageDigest;
+use openssl::ocsp::{OcspCertId, OcspCertStatus, OcspFlag, OcspRequest, OcspResponse, OcspResponseStatus};
+use openssl::stack::Stack;
+use openssl::x509::store::X509StoreBuilder;
+use openssl::x509::verify::X509VerifyFlags;
+use openssl::x509::X509;
+use std::time::Duration;
+
+/// TLS Feature extension (RFC 7633)
+pub const TLS_FEATURE_OID: &[u64] = &[1, 3, 6, 1, 5, 5, 7, 1, 24];
+
+/// Extension value: `SEQUENCE { INTEGER 5 }` (status_request)
+const MUST_STAPLE_VALUE: &[u8] = &[0x30, 0x03, 0x02, 0x01, 0x05];
+
+/// Timeout for a single OCSP responder request
+const OCSP_REQUEST_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Allowed clock skew when checking response validity, in seconds
+const OCSP_CLOCK_SKEW_SECS: u32 = 300;
+
+/// Extension requesting the Must-Staple flag in a CSR
+pub fn must_staple_extension() -> rcgen::CustomExtension {
+    rcgen::CustomExtension::from_oid_content(TLS_FEATURE_OID, MUST_STAPLE_VALUE.to_vec())
+}
+
+/// Whether a certificate carries the OCSP Must-Staple extension
+pub fn has_must_staple(cert: &X509) -> bool {
+    let oid = TLS_FEATURE_OID.iter().map(|n| n.to_string()).collect::<Vec<_>>().join(".");
+    let Ok(der) = cert.to_der() else {
+        return false;
+    };
+    match x509_parser::parse_x509_certificate(&der) {
+        Ok((_, parsed)) => parsed.extensions().iter().any(|ext| ext.oid.to_id_string() == oid),
+        Err(_) => false,
+    }
+}
+
+/// A verified OCSP response for one certificate
+#[derive(Debug, Clone)]
+pub struct OcspStaple {
+    /// DER-encoded response sent in the handshake
+    pub der: Vec<u8>,
+    /// Time after which the response must not be served
+    pub next_update: DateTime<Utc>,
+}
+
+impl OcspStaple {
+    /// Whether the response can still be stapled
+    pub fn is_fresh(&self) -> bool {
+        Utc::now() < self.next_update
+    }
+}
+
+/// Fetch and verify an OCSP response for `cert` from its issuer's responder
+pub async fn fetch_staple(cert: &X509, issuer: &X509) -> Result<OcspStaple, TlsError> {
+    let responder = cert
+        .ocsp_responders()
+        .ok()
+        .and_then(|urls| urls.iter().next().map(|url| url.to_string()))
+        .ok_or_else(|| TlsError::CertificateError("Certificate has no OCSP responder".to_string()))?;
+
+    let id = OcspCertId::from_cert(MessageDigest::sha1(), cert, issuer).map_err(ocsp_error)?;
+    let mut request = OcspRequest::new().map_err(ocsp_error)?;
+    request.add_id(id).map_err(ocsp_error)?;
+    let body = request.to_der().map_err(ocsp_error)?;
+
+    let response = reqwest::Client::new()
+        .post(&responder)
+        .header("Content-Type", "application/ocsp-request")
+        .timeout(OCSP_REQUEST_TIMEOUT)
+        .body(body)
+        .send()
+        .await
+        .map_err(|e| TlsError::CertificateError(format!("OCSP request to {} failed: {}", responder, e)))?;
+    if !response.status().is_success() {
+        return Err(TlsError::CertificateError(format!(
+            "OCSP responder {} returned {}",
+            responder,
+            response.status()
+        )));
+    }
+    let der = response
+        .bytes()
+        .await
+        .map_err(|e| TlsError::CertificateError(format!("Failed to read OCSP response: {}", e)))?;
+
+    verify_response(&der, cert, issuer)
+}
+
+/// Check that a response is signed for `cert`, reports it good and is current
+pub fn verify_response(der: &[u8], cert: &X509, issuer: &X509) -> Result<OcspStaple, TlsError> {
+    let response = OcspResponse::from_der(der).map_err(ocsp_error)?;
+    if response.status() != OcspResponseStatus::SUCCESSFUL {
+        return Err(TlsError::CertificateError("OCSP responder did not return a response".to_string()));
+    }
+    let basic = response.basic().map_err(ocsp_error)?;
+
+    // The responder is either the issuer itself or a responder it delegated to
+    let mut certs = Stack::new().map_err(ocsp_error)?;
+    certs.push(issuer.to_owned()).map_err(ocsp_error)?;
+    let mut store = X509StoreBuilder::new().map_err(ocsp_error)?;
+    store.add_cert(issuer.to_owned()).map_err(ocsp_error)?;
+    store.set_flags(X509VerifyFlags::PARTIAL_CHAIN).map_err(ocsp_error)?;
+    basic
+        .verify(&certs, &store.build(), OcspFlag::TRUST_OTHER)
+        .map_err(ocsp_error)?;
+
+    let id = OcspCertId::from_cert(MessageDigest::sha1(), cert, issuer).map_err(ocsp_error)?;
+    let status = basic
+        .find_status(&id)
+        .ok_or_else(|| TlsError::CertificateError("OCSP response does not cover the certificate".to_string()))?;
+    if status.status != OcspCertStatus::GOOD {
+        return Err(TlsError::CertificateError(format!(
+            "OCSP status is {}",
+            if status.status == OcspCertStatus::REVOKED { "revoked" } else { "unknown" }
+        )));
+    }
+    status
+        .check_validity(OCSP_CLOCK_SKEW_SECS, None)
+        .map_err(ocsp_error)?;
+
+    let next_update = parse_asn1_time(&status.next_update.to_string())?;
+    Ok(OcspStaple { der: der.to_vec(), next_update })
+}
+
+/// Parse OpenSSL's printed time format, e.g. `Mar  4 12:00:00 2024 GMT`
+fn parse_asn1_time(value: &str) -> Result<DateTime<Utc>, TlsError> {
+    NaiveDateTime::parse_from_str(value, "%b %e %H:%M:%S %Y GMT")
+        .map(|t| t.and_utc())
+        .map_err(|e| TlsError::CertificateError(format!("Invalid OCSP time {:?}: {}", value, e)))
+}
+
+fn ocsp_error(e: openssl::error::ErrorStack) -> TlsError {
+    TlsError::CertificateError(format!("OCSP: {}", e))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rcgen::{CertificateParams, KeyPair};
+
+    fn self_signed(must_staple: bool) -> X509 {
+        let mut params = CertificateParams::new(vec!["example.com".to_string()]).unwrap();
+        if must_staple {
+            params.custom_extensions.push(must_staple_extension());
+        }
+        let key_pair = KeyPair::generate().unwrap();
+        let cert = params.self_signed(&key_pair).unwrap();
+        X509::from_pem(cert.pem().as_bytes()).unwrap()
+    }
+
+    #[test]
+    fn test_has_must_staple() {
+        assert!(has_must_staple(&self_signed(true)));
+        assert!(!has_must_staple(&self_signed(false)));
+    }
+
+    #[test]
+    fn test_parse_asn1_time() {
+        let time = parse_asn1_time("Mar  4 12:00:00 2024 GMT").unwrap();
+        assert_eq!(time.to_rfc3339(), "2024-03-04T12:00:00+00:00");
+        assert!(parse_asn1_time("not a time").is_err());
+    }
+
+    #[test]
+    fn test_staple_freshness() {
+        let fresh = OcspStaple { der: vec![0], next_update: Utc::now() + chrono::Duration::hours(1) };
+        let stale = OcspStaple { der: vec![0], next_update: Utc::now() - chrono::Duration::hours(1) };
+        assert!(fresh.is_fresh());
+        assert!(!stale.is_fresh());
+    }
+}
@@ -0,0 +1,330 @@
+//! Redis certificate storage backend
+//!
+//! Speaks the RESP protocol directly over TCP. Certificate operations are
+//! rare (startup, renewal), so each operation uses a fresh connection.
+
+use crate::error::TlsError;
+use crate::storage_backend::StorageBackend;
+use async_trait::async_trait;
+use std::future::Future;
+use std::pin::Pin;
+use std::time::Duration;
+use tokio::io::{AsyncBufRead, AsyncBufReadExt, AsyncReadExt, AsyncWriteExt, BufReader};
+use tokio::net::TcpStream;
+use tracing::debug;
+
+/// Parsed Redis connection URL
+#[derive(Debug, Clone, PartialEq)]
+struct RedisUrl {
+    address: String,
+    password: Option<String>,
+    db: u32,
+}
+
+impl RedisUrl {
+    /// Parse `redis://[[user]:password@]host[:port][/db]`
+    fn parse(url: &str) -> Result<Self, TlsError> {
+        let rest = url
+            .strip_prefix("redis://")
+            .ok_or_else(|| TlsError::Storage(format!("Unsupported Redis URL: {}", url)))?;
+
+        let (auth, rest) = match rest.rsplit_once('@') {
+            Some((auth, rest)) => (Some(auth), rest),
+            None => (None, rest),
+        };
+        let password = auth
+            .map(|a| a.split_once(':').map(|(_, p)| p).unwrap_or(a))
+            .filter(|p| !p.is_empty())
+            .map(|p| p.to_string());
+
+        let (host, db) = match rest.split_once('/') {
+            Some((host, db)) if !db.is_empty() => (
+                host,
+                db.parse()
+                    .map_err(|_| TlsError::Storage(format!("Invalid Redis database: {}", db)))?,
+            ),
+            Some((host, _)) => (host, 0),
+            None => (rest, 0),
+        };
+
+        let address = if host.contains(':') {
+            host.to_string()
+        } else {
+            format!("{}:6379", host)
+        };
+
+        Ok(Self { address, password, db })
+    }
+}
+
+/// A RESP reply
+#[derive(Debug, Clone, PartialEq)]
+enum RespValue {
+    Simple(String),
+    Error(String),
+    Integer(i64),
+    Bulk(Option<Vec<u8>>),
+    Array(Vec<RespValue>),
+}
+
+/// Encode a command as a RESP array of bulk strings
+fn encode_command(args: &[&[u8]]) -> Vec<u8> {
+    let mut out = format!("*{}\r\n", args.len()).into_bytes();
+    for arg in args {
+        out.extend_from_slice(format!("${}\r\n", arg.len()).as_bytes());
+        out.extend_from_slice(arg);
+        out.extend_from_slice(b"\r\n");
+    }
+    out
+}
+
+/// Delete a key only if it still holds the caller's token
+const UNLOCK_SCRIPT: &str =
+    "if redis.call('get', KEYS[1]) == ARGV[1] then return redis.call('del', KEYS[1]) else return 0 end";
+
+/// Read one RESP value
+fn read_value<'a, R>(reader: &'a mut R) -> Pin<Box<dyn Future<Output = Result<RespValue, TlsError>> + Send + 'a>>
+where
+    R: AsyncBufRead + Unpin + Send,
+{
+    Box::pin(async move {
+        let mut line = String::new();
+        if reader.read_line(&mut line).await? == 0 {
+            return Err(TlsError::Storage("Redis closed the connection".to_string()));
+        }
+        let line = line.trim_end_matches("\r\n");
+        let (kind, payload) = line.split_at(line.len().min(1));
+        let parse_len = |s: &str| {
+            s.parse::<i64>()
+                .map_err(|_| TlsError::Storage(format!("Invalid RESP length: {}", s)))
+        };
+
+        match kind {
+            "+" => Ok(RespValue::Simple(payload.to_string())),
+            "-" => Ok(RespValue::Error(payload.to_string())),
+            ":" => Ok(RespValue::Integer(parse_len(payload)?)),
+            "$" => {
+                let len = parse_len(payload)?;
+                if len < 0 {
+                    return Ok(RespValue::Bulk(None));
+                }
+                let mut data = vec![0u8; len as usize + 2];
+                reader.read_exact(&mut data).await?;
+                data.truncate(len as usize);
+                Ok(RespValue::Bulk(Some(data)))
+            }
+            "*" => {
+                let len = parse_len(payload)?;
+                let mut items = Vec::with_capacity(len.max(0) as usize);
+                for _ in 0..len.max(0) {
+                    items.push(read_value(reader).await?);
+                }
+                Ok(RespValue::Array(items))
+            }
+            _ => Err(TlsError::Storage(format!("Unexpected RESP reply: {}", line))),
+        }
+    })
+}
+
+/// Redis-backed certificate storage
+pub struct RedisBackend {
+    url: RedisUrl,
+    prefix: String,
+}
+
+impl RedisBackend {
+    /// Create a backend for a `redis://` URL; keys are stored as `{prefix}:{key}`
+    pub fn new(url: &str, prefix: &str) -> Result<Self, TlsError> {
+        Ok(Self {
+            url: RedisUrl::parse(url)?,
+            prefix: prefix.trim_end_matches(':').to_string(),
+        })
+    }
+
+    fn full_key(&self, key: &str) -> String {
+        format!("{}:{}", self.prefix, key)
+    }
+
+    async fn connect(&self) -> Result<BufReader<TcpStream>, TlsError> {
+        let stream = TcpStream::connect(&self.url.address).await?;
+        let mut conn = BufReader::new(stream);
+
+        if let Some(password) = &self.url.password {
+            Self::call(&mut conn, &[b"AUTH", password.as_bytes()]).await?;
+        }
+        if self.url.db != 0 {
+            let db = self.url.db.to_string();
+            Self::call(&mut conn, &[b"SELECT", db.as_bytes()]).await?;
+        }
+
+        Ok(conn)
+    }
+
+    async fn call(conn: &mut BufReader<TcpStream>, args: &[&[u8]]) -> Result<RespValue, TlsError> {
+        conn.get_mut().write_all(&encode_command(args)).await?;
+        match read_value(conn).await? {
+            RespValue::Error(e) => Err(TlsError::Storage(format!("Redis error: {}", e))),
+            value => Ok(value),
+        }
+    }
+}
+
+#[async_trait]
+impl StorageBackend for RedisBackend {
+    async fn load(&self, key: &str) -> Result<Option<Vec<u8>>, TlsError> {
+        let mut conn = self.connect().await?;
+        match Self::call(&mut conn, &[b"GET", self.full_key(key).as_bytes()]).await? {
+            RespValue::Bulk(data) => Ok(data),
+            other => Err(TlsError::Storage(format!("Unexpected GET reply: {:?}", other))),
+        }
+    }
+
+    async fn store(&self, key: &str, data: &[u8]) -> Result<(), TlsError> {
+        let mut conn = self.connect().await?;
+        Self::call(&mut conn, &[b"SET", self.full_key(key).as_bytes(), data]).await?;
+        debug!(key = %key, "Stored key in Redis");
+        Ok(())
+    }
+
+    async fn delete(&self, key: &str) -> Result<(), TlsError> {
+        let mut conn = self.connect().await?;
+        Self::call(&mut conn, &[b"DEL", self.full_key(key).as_bytes()]).await?;
+        Ok(())
+    }
+
+    async fn list(&self, prefix: &str) -> Result<Vec<String>, TlsError> {
+        let mut conn = self.connect().await?;
+        let pattern = format!("{}*", self.full_key(prefix));
+        let namespace = format!("{}:", self.prefix);
+        let mut cursor = "0".to_string();
+        let mut keys = Vec::new();
+
+        loop {
+            let reply = Self::call(
+                &mut conn,
+                &[b"SCAN", cursor.as_bytes(), b"MATCH", pattern.as_bytes(), b"COUNT", b"100"],
+            )
+            .await?;
+
+            let (next, batch) = match reply {
+                RespValue::Array(mut parts) if parts.len() == 2 => {
+                    let batch = parts.pop();
+                    (parts.pop(), batch)
+                }
+                other => return Err(TlsError::Storage(format!("Unexpected SCAN reply: {:?}", other))),
+            };
+
+            if let Some(RespValue::Array(items)) = batch {
+                for item in items {
+                    if let RespValue::Bulk(Some(data)) = item {
+                        let key = String::from_utf8_lossy(&data);
+                        if let Some(key) = key.strip_prefix(&namespace) {
+                            keys.push(key.to_string());
+                        }
+                    }
+                }
+            }
+
+            cursor = match next {
+                Some(RespValue::Bulk(Some(data))) => String::from_utf8_lossy(&data).to_string(),
+                _ => "0".to_string(),
+            };
+            if cursor == "0" {
+                break;
+            }
+        }
+
+        Ok(keys)
+    }
+
+    async fn try_lock(&self, key: &str, owner: &str, ttl: Duration) -> Result<bool, TlsError> {
+        let mut conn = self.connect().await?;
+        let full_key = self.full_key(key);
+        let ttl_ms = ttl.as_millis().max(1).to_string();
+
+        // SET NX PX gives atomic acquisition with server-side expiry
+        let reply = Self::call(
+            &mut conn,
+            &[b"SET", full_key.as_bytes(), owner.as_bytes(), b"NX", b"PX", ttl_ms.as_bytes()],
+        )
+        .await?;
+        if matches!(reply, RespValue::Simple(_)) {
+            return Ok(true);
+        }
+
+        // Already ours: extend it
+        match Self::call(&mut conn, &[b"GET", full_key.as_bytes()]).await? {
+            RespValue::Bulk(Some(holder)) if holder == owner.as_bytes() => {
+                Self::call(&mut conn, &[b"PEXPIRE", full_key.as_bytes(), ttl_ms.as_bytes()]).await?;
+                Ok(true)
+            }
+            _ => Ok(false),
+        }
+    }
+
+    async fn unlock(&self, key: &str, owner: &str) -> Result<(), TlsError> {
+        let mut conn = self.connect().await?;
+        Self::call(
+            &mut conn,
+            &[b"EVAL", UNLOCK_SCRIPT.as_bytes(), b"1", self.full_key(key).as_bytes(), owner.as_bytes()],
+        )
+        .await?;
+        Ok(())
+    }
+
+    fn name(&self) -> &'static str {
+        "redis"
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_url() {
+        assert_eq!(
+            RedisUrl::parse("redis://127.0.0.1").unwrap(),
+            RedisUrl { address: "127.0.0.1:6379".to_string(), password: None, db: 0 }
+        );
+        assert_eq!(
+            RedisUrl::parse("redis://:secret@redis.internal:6380/2").unwrap(),
+            RedisUrl { address: "redis.internal:6380".to_string(), password: Some("secret".to_string()), db: 2 }
+        );
+        assert!(RedisUrl::parse("http://127.0.0.1").is_err());
+    }
+
+    #[test]
+    fn test_encode_command() {
+        assert_eq!(
+            encode_command(&[b"GET", b"avalon:certs/a.json"]),
+            b"*2\r\n$3\r\nGET\r\n$19\r\navalon:certs/a.json\r\n".to_vec()
+        );
+    }
+
+    #[tokio::test]
+    async fn test_read_values() {
+        let mut reader = BufReader::new(&b"+OK\r\n$5\r\nhello\r\n$-1\r\n:3\r\n-ERR nope\r\n"[..]);
+        assert_eq!(read_value(&mut reader).await.unwrap(), RespValue::Simple("OK".to_string()));
+        assert_eq!(read_value(&mut reader).await.unwrap(), RespValue::Bulk(Some(b"hello".to_vec())));
+        assert_eq!(read_value(&mut reader).await.unwrap(), RespValue::Bulk(None));
+        assert_eq!(read_value(&mut reader).await.unwrap(), RespValue::Integer(3));
+        assert_eq!(read_value(&mut reader).await.unwrap(), RespValue::Error("ERR nope".to_string()));
+    }
+
+    #[tokio::test]
+    async fn test_read_scan_reply() {
+        let data = b"*2\r\n$1\r\n0\r\n*2\r\n$11\r\navalon:a.js\r\n$11\r\navalon:b.js\r\n";
+        let mut reader = BufReader::new(&data[..]);
+        assert_eq!(
+            read_value(&mut reader).await.unwrap(),
+            RespValue::Array(vec![
+                RespValue::Bulk(Some(b"0".to_vec())),
+                RespValue::Array(vec![
+                    RespValue::Bulk(Some(b"avalon:a.js".to_vec())),
+                    RespValue::Bulk(Some(b"avalon:b.js".to_vec())),
+                ]),
+            ])
+        );
+    }
+}
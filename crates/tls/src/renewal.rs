@@ -101,7 +101,8 @@ impl RenewalScheduler {
         debug!("Checking certificates for renewal");
 
         for domain in &self.domains {
-            match self.storage.load_cert(domain).await {
+            // A corrupt bundle comes back as missing and is obtained again below
+            match self.storage.load_cert_or_missing(domain).await {
                 Ok(Some(bundle)) => {
                     if bundle.expires_within_days(self.renewal_days) {
                         let days_left = (bundle.expires_at - chrono::Utc::now()).num_days();
@@ -157,6 +158,11 @@ impl RenewalScheduler {
                 }
             }
         }
+
+        // OCSP responses expire long before certificates do
+        if let Some(resolver) = &self.sni_resolver {
+            resolver.refresh_staples().await;
+        }
     }
 
     /// Reload a certificate into the SNI resolver after renewal
@@ -174,6 +180,11 @@ impl RenewalScheduler {
                     bundle.private_key_pem.as_bytes(),
                 ) {
                     Ok(pair) => {
+                        if pair.must_staple {
+                            if let Err(e) = pair.refresh_staple().await {
+                                warn!(domain = %domain, error = %e, "Failed to fetch OCSP staple for renewed certificate");
+                            }
+                        }
                         resolver.add_cert(domain, pair);
                         info!(domain = %domain, "Certificate hot-reloaded into SNI resolver");
                     }
@@ -0,0 +1,582 @@
+//! S3-compatible certificate storage backend
+//!
+//! Works with AWS S3 and compatible services (MinIO, R2, ...). Requests are
+//! signed with AWS Signature Version 4.
+
+use crate::error::TlsError;
+use crate::storage_backend::{LockRecord, StorageBackend};
+use async_trait::async_trait;
+use chrono::{DateTime, Utc};
+use hmac::{Hmac, Mac};
+use sha2::{Digest, Sha256};
+use std::time::Duration;
+use tracing::debug;
+
+type HmacSha256 = Hmac<Sha256>;
+
+/// Settings for an S3-compatible bucket
+#[derive(Debug, Clone)]
+pub struct S3Settings {
+    /// Endpoint URL, e.g. `https://s3.us-east-1.amazonaws.com`
+    pub endpoint: String,
+    pub bucket: String,
+    pub region: String,
+    /// Object key prefix
+    pub prefix: String,
+    pub access_key: String,
+    pub secret_key: String,
+    /// Use `endpoint/bucket/key` instead of `bucket.endpoint/key`
+    pub path_style: bool,
+}
+
+/// S3-backed certificate storage
+pub struct S3Backend {
+    settings: S3Settings,
+    client: reqwest::Client,
+}
+
+impl S3Backend {
+    pub fn new(settings: S3Settings) -> Self {
+        Self {
+            settings,
+            client: reqwest::Client::new(),
+        }
+    }
+
+    fn object_key(&self, key: &str) -> String {
+        let prefix = self.settings.prefix.trim_matches('/');
+        if prefix.is_empty() {
+            key.to_string()
+        } else {
+            format!("{}/{}", prefix, key)
+        }
+    }
+
+    /// Host and path for a request, honoring the addressing style
+    fn host_and_path(&self, object_key: Option<&str>) -> (String, String, String) {
+        let endpoint = self.settings.endpoint.trim_end_matches('/');
+        let (scheme, host) = endpoint.split_once("://").unwrap_or(("https", endpoint));
+        let key_path = object_key.map(|k| format!("/{}", uri_encode(k, false))).unwrap_or_default();
+
+        if self.settings.path_style {
+            (scheme.to_string(), host.to_string(), format!("/{}{}", self.settings.bucket, key_path))
+        } else {
+            let path = if key_path.is_empty() { "/".to_string() } else { key_path };
+            (scheme.to_string(), format!("{}.{}", self.settings.bucket, host), path)
+        }
+    }
+
+    /// Load an object with its ETag
+    async fn load_with_etag(&self, object_key: &str) -> Result<Option<(Vec<u8>, String)>, TlsError> {
+        let response = self.send(reqwest::Method::GET, Some(object_key), &[], &[], Vec::new()).await?;
+
+        match response.status() {
+            reqwest::StatusCode::NOT_FOUND => Ok(None),
+            status if status.is_success() => {
+                let etag = response
+                    .headers()
+                    .get(reqwest::header::ETAG)
+                    .and_then(|v| v.to_str().ok())
+                    .ok_or_else(|| TlsError::Storage(format!("S3 GET {} returned no ETag", object_key)))?
+                    .to_string();
+                let body = response
+                    .bytes()
+                    .await
+                    .map_err(|e| TlsError::Storage(format!("S3 read failed: {}", e)))?;
+                Ok(Some((body.to_vec(), etag)))
+            }
+            status => Err(TlsError::Storage(format!("S3 GET {} returned {}", object_key, status))),
+        }
+    }
+
+    /// Write a lock record under `condition`; false if the condition failed
+    async fn put_lock(&self, object_key: &str, condition: (&str, &str), record: &[u8]) -> Result<bool, TlsError> {
+        let response = self
+            .send(reqwest::Method::PUT, Some(object_key), &[], &[condition], record.to_vec())
+            .await?;
+
+        match response.status() {
+            status if status.is_success() => Ok(true),
+            // Taken or replaced by another owner in the meantime
+            reqwest::StatusCode::PRECONDITION_FAILED
+            | reqwest::StatusCode::CONFLICT
+            | reqwest::StatusCode::NOT_FOUND => Ok(false),
+            status => Err(TlsError::Storage(format!("S3 lock PUT {} returned {}", object_key, status))),
+        }
+    }
+
+    async fn send(
+        &self,
+        method: reqwest::Method,
+        object_key: Option<&str>,
+        query: &[(&str, &str)],
+        headers: &[(&str, &str)],
+        body: Vec<u8>,
+    ) -> Result<reqwest::Response, TlsError> {
+        let (scheme, host, path) = self.host_and_path(object_key);
+        let now = Utc::now();
+        let payload_hash = hex(&Sha256::digest(&body));
+
+        let mut query: Vec<(String, String)> = query
+            .iter()
+            .map(|(k, v)| (uri_encode(k, true), uri_encode(v, true)))
+            .collect();
+        query.sort();
+        let query_string = query
+            .iter()
+            .map(|(k, v)| format!("{}={}", k, v))
+            .collect::<Vec<_>>()
+            .join("&");
+
+        let authorization = sign_request(
+            &self.settings,
+            method.as_str(),
+            &host,
+            &path,
+            &query_string,
+            &payload_hash,
+            now,
+        );
+
+        let url = if query_string.is_empty() {
+            format!("{}://{}{}", scheme, host, path)
+        } else {
+            format!("{}://{}{}?{}", scheme, host, path, query_string)
+        };
+
+        let mut request = self.client.request(method, url);
+        for (name, value) in headers {
+            request = request.header(*name, *value);
+        }
+
+        request
+            .header("x-amz-date", amz_date(now))
+            .header("x-amz-content-sha256", payload_hash)
+            .header("authorization", authorization)
+            .body(body)
+            .send()
+            .await
+            .map_err(|e| TlsError::Storage(format!("S3 request failed: {}", e)))
+    }
+}
+
+#[async_trait]
+impl StorageBackend for S3Backend {
+    async fn load(&self, key: &str) -> Result<Option<Vec<u8>>, TlsError> {
+        let object_key = self.object_key(key);
+        let response = self.send(reqwest::Method::GET, Some(&object_key), &[], &[], Vec::new()).await?;
+
+        match response.status() {
+            reqwest::StatusCode::NOT_FOUND => Ok(None),
+            status if status.is_success() => {
+                let body = response
+                    .bytes()
+                    .await
+                    .map_err(|e| TlsError::Storage(format!("S3 read failed: {}", e)))?;
+                Ok(Some(body.to_vec()))
+            }
+            status => Err(TlsError::Storage(format!("S3 GET {} returned {}", object_key, status))),
+        }
+    }
+
+    async fn store(&self, key: &str, data: &[u8]) -> Result<(), TlsError> {
+        let object_key = self.object_key(key);
+        let response = self.send(reqwest::Method::PUT, Some(&object_key), &[], &[], data.to_vec()).await?;
+
+        if !response.status().is_success() {
+            return Err(TlsError::Storage(format!(
+                "S3 PUT {} returned {}",
+                object_key,
+                response.status()
+            )));
+        }
+        debug!(key = %object_key, "Stored object in S3");
+        Ok(())
+    }
+
+    async fn delete(&self, key: &str) -> Result<(), TlsError> {
+        let object_key = self.object_key(key);
+        let response = self.send(reqwest::Method::DELETE, Some(&object_key), &[], &[], Vec::new()).await?;
+
+        let status = response.status();
+        if !status.is_success() && status != reqwest::StatusCode::NOT_FOUND {
+            return Err(TlsError::Storage(format!("S3 DELETE {} returned {}", object_key, status)));
+        }
+        Ok(())
+    }
+
+    async fn list(&self, prefix: &str) -> Result<Vec<String>, TlsError> {
+        let full_prefix = self.object_key(prefix);
+        let strip = self.object_key("");
+        let mut keys = Vec::new();
+        let mut continuation: Option<String> = None;
+
+        loop {
+            let mut query = vec![("list-type", "2"), ("prefix", full_prefix.as_str())];
+            if let Some(token) = &continuation {
+                query.push(("continuation-token", token.as_str()));
+            }
+
+            let response = self.send(reqwest::Method::GET, None, &query, &[], Vec::new()).await?;
+            if !response.status().is_success() {
+                return Err(TlsError::Storage(format!("S3 list returned {}", response.status())));
+            }
+            let body = response
+                .text()
+                .await
+                .map_err(|e| TlsError::Storage(format!("S3 read failed: {}", e)))?;
+
+            let (batch, next) = parse_list_response(&body);
+            keys.extend(
+                batch
+                    .into_iter()
+                    .map(|k| k.strip_prefix(&strip).map(|s| s.to_string()).unwrap_or(k)),
+            );
+
+            match next {
+                Some(token) => continuation = Some(token),
+                None => break,
+            }
+        }
+
+        Ok(keys)
+    }
+
+    async fn try_lock(&self, key: &str, owner: &str, ttl: Duration) -> Result<bool, TlsError> {
+        let object_key = self.object_key(key);
+        let record = LockRecord::new(owner, ttl).encode();
+
+        // Conditional create; on conflict only a stale or own lock is replaced,
+        // and only if it is still the object that was read (If-Match)
+        for _ in 0..2 {
+            if self.put_lock(&object_key, ("if-none-match", "*"), &record).await? {
+                return Ok(true);
+            }
+
+            let Some((current, etag)) = self.load_with_etag(&object_key).await? else {
+                continue;
+            };
+            if LockRecord::data_blocks(&current, owner) {
+                return Ok(false);
+            }
+            return self.put_lock(&object_key, ("if-match", &etag), &record).await;
+        }
+
+        Ok(false)
+    }
+
+    async fn unlock(&self, key: &str, owner: &str) -> Result<(), TlsError> {
+        let existing = self.load(key).await?.and_then(|d| LockRecord::decode(&d));
+        if existing.is_some_and(|lock| lock.owner == owner) {
+            self.delete(key).await?;
+        }
+        Ok(())
+    }
+
+    fn name(&self) -> &'static str {
+        "s3"
+    }
+}
+
+/// Extract object keys and the continuation token from a ListObjectsV2 response
+fn parse_list_response(xml: &str) -> (Vec<String>, Option<String>) {
+    let keys = xml_values(xml, "Key").into_iter().map(|k| xml_unescape(&k)).collect();
+    let truncated = xml_values(xml, "IsTruncated").first().map(|v| v == "true").unwrap_or(false);
+    let next = if truncated {
+        xml_values(xml, "NextContinuationToken").into_iter().next().map(|t| xml_unescape(&t))
+    } else {
+        None
+    };
+    (keys, next)
+}
+
+fn xml_values(xml: &str, tag: &str) -> Vec<String> {
+    let open = format!("<{}>", tag);
+    let close = format!("</{}>", tag);
+    let mut values = Vec::new();
+    let mut rest = xml;
+
+    while let Some(start) = rest.find(&open) {
+        rest = &rest[start + open.len()..];
+        match rest.find(&close) {
+            Some(end) => {
+                values.push(rest[..end].to_string());
+                rest = &rest[end + close.len()..];
+            }
+            None => break,
+        }
+    }
+
+    values
+}
+
+fn xml_unescape(s: &str) -> String {
+    s.replace("&lt;", "<")
+        .replace("&gt;", ">")
+        .replace("&quot;", "\"")
+        .replace("&apos;", "'")
+        .replace("&amp;", "&")
+}
+
+fn amz_date(now: DateTime<Utc>) -> String {
+    now.format("%Y%m%dT%H%M%SZ").to_string()
+}
+
+/// Build the SigV4 `Authorization` header for a request
+fn sign_request(
+    settings: &S3Settings,
+    method: &str,
+    host: &str,
+    path: &str,
+    query_string: &str,
+    payload_hash: &str,
+    now: DateTime<Utc>,
+) -> String {
+    let date = now.format("%Y%m%d").to_string();
+    let timestamp = amz_date(now);
+    let scope = format!("{}/{}/s3/aws4_request", date, settings.region);
+    let signed_headers = "host;x-amz-content-sha256;x-amz-date";
+
+    let canonical_request = format!(
+        "{}\n{}\n{}\nhost:{}\nx-amz-content-sha256:{}\nx-amz-date:{}\n\n{}\n{}",
+        method, path, query_string, host, payload_hash, timestamp, signed_headers, payload_hash
+    );
+    let string_to_sign = format!(
+        "AWS4-HMAC-SHA256\n{}\n{}\n{}",
+        timestamp,
+        scope,
+        hex(&Sha256::digest(canonical_request.as_bytes()))
+    );
+
+    let key = signing_key(&settings.secret_key, &date, &settings.region, "s3");
+    let signature = hex(&hmac(&key, string_to_sign.as_bytes()));
+
+    format!(
+        "AWS4-HMAC-SHA256 Credential={}/{}, SignedHeaders={}, Signature={}",
+        settings.access_key, scope, signed_headers, signature
+    )
+}
+
+fn signing_key(secret: &str, date: &str, region: &str, service: &str) -> Vec<u8> {
+    let k_date = hmac(format!("AWS4{}", secret).as_bytes(), date.as_bytes());
+    let k_region = hmac(&k_date, region.as_bytes());
+    let k_service = hmac(&k_region, service.as_bytes());
+    hmac(&k_service, b"aws4_request")
+}
+
+fn hmac(key: &[u8], data: &[u8]) -> Vec<u8> {
+    let mut mac = HmacSha256::new_from_slice(key).expect("HMAC accepts keys of any length");
+    mac.update(data);
+    mac.finalize().into_bytes().to_vec()
+}
+
+fn hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+/// URI-encode per SigV4 rules; `/` is kept in paths but encoded in query values
+fn uri_encode(s: &str, encode_slash: bool) -> String {
+    let mut out = String::with_capacity(s.len());
+    for b in s.bytes() {
+        match b {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'_' | b'.' | b'~' => out.push(b as char),
+            b'/' if !encode_slash => out.push('/'),
+            _ => out.push_str(&format!("%{:02X}", b)),
+        }
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::Arc;
+
+    fn make_backend(path_style: bool) -> S3Backend {
+        S3Backend::new(S3Settings {
+            endpoint: "http://minio:9000".to_string(),
+            bucket: "certs".to_string(),
+            region: "us-east-1".to_string(),
+            prefix: "avalon".to_string(),
+            access_key: "AKIDEXAMPLE".to_string(),
+            secret_key: "wJalrXUtnFEMI/K7MDENG+bPxRfiCYEXAMPLEKEY".to_string(),
+            path_style,
+        })
+    }
+
+    #[test]
+    fn test_signing_key() {
+        // Example from the AWS Signature Version 4 documentation
+        let key = signing_key("wJalrXUtnFEMI/K7MDENG+bPxRfiCYEXAMPLEKEY", "20120215", "us-east-1", "iam");
+        assert_eq!(hex(&key), "f4780e2d9f65fa895f9c67b32ce1baf0b0d8a43505a000a1a9e090d414db404d");
+    }
+
+    #[test]
+    fn test_addressing_style() {
+        let backend = make_backend(true);
+        let key = backend.object_key("certs/example.com.json");
+        assert_eq!(key, "avalon/certs/example.com.json");
+        assert_eq!(
+            backend.host_and_path(Some(&key)),
+            ("http".to_string(), "minio:9000".to_string(), "/certs/avalon/certs/example.com.json".to_string())
+        );
+
+        let backend = make_backend(false);
+        assert_eq!(
+            backend.host_and_path(None),
+            ("http".to_string(), "certs.minio:9000".to_string(), "/".to_string())
+        );
+    }
+
+    #[test]
+    fn test_authorization_header() {
+        let backend = make_backend(true);
+        let now = DateTime::parse_from_rfc3339("2024-01-02T03:04:05Z").unwrap().with_timezone(&Utc);
+        let auth = sign_request(&backend.settings, "GET", "minio:9000", "/certs/a", "", &hex(&Sha256::digest(b"")), now);
+
+        assert!(auth.starts_with("AWS4-HMAC-SHA256 Credential=AKIDEXAMPLE/20240102/us-east-1/s3/aws4_request, "));
+        assert!(auth.contains("SignedHeaders=host;x-amz-content-sha256;x-amz-date"));
+        // Signing is deterministic for the same request and time
+        let again = sign_request(&backend.settings, "GET", "minio:9000", "/certs/a", "", &hex(&Sha256::digest(b"")), now);
+        assert_eq!(auth, again);
+    }
+
+    #[test]
+    fn test_parse_list_response() {
+        let xml = r#"<?xml version="1.0" encoding="UTF-8"?>
+<ListBucketResult>
+  <IsTruncated>true</IsTruncated>
+  <Contents><Key>avalon/certs/a.com.json</Key></Contents>
+  <Contents><Key>avalon/certs/b&amp;c.json</Key></Contents>
+  <NextContinuationToken>token123</NextContinuationToken>
+</ListBucketResult>"#;
+
+        let (keys, next) = parse_list_response(xml);
+        assert_eq!(keys, vec!["avalon/certs/a.com.json", "avalon/certs/b&c.json"]);
+        assert_eq!(next, Some("token123".to_string()));
+
+        let (_, next) = parse_list_response("<IsTruncated>false</IsTruncated>");
+        assert_eq!(next, None);
+    }
+
+    /// Minimal S3 stand-in holding a single object, honoring If-None-Match and If-Match
+    async fn fake_s3(initial: Option<&[u8]>) -> String {
+        use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let endpoint = format!("http://{}", listener.local_addr().unwrap());
+        let object = Arc::new(parking_lot::Mutex::new(initial.map(|data| (data.to_vec(), 1u64))));
+        tokio::spawn(async move {
+            loop {
+                let (mut stream, _) = listener.accept().await.unwrap();
+                let object = object.clone();
+                tokio::spawn(async move {
+                    let mut request = Vec::new();
+                    let mut buf = [0u8; 4096];
+                    let head_end = loop {
+                        let n = stream.read(&mut buf).await.unwrap();
+                        if n == 0 {
+                            return;
+                        }
+                        request.extend_from_slice(&buf[..n]);
+                        if let Some(pos) = request.windows(4).position(|w| w == b"\r\n\r\n") {
+                            break pos + 4;
+                        }
+                    };
+                    let head = String::from_utf8_lossy(&request[..head_end]).to_lowercase();
+                    let header = |name: &str| {
+                        let prefix = format!("{}: ", name);
+                        head.lines().find_map(|l| l.strip_prefix(&prefix)).map(|v| v.trim().to_string())
+                    };
+                    let length: usize = header("content-length").and_then(|v| v.parse().ok()).unwrap_or(0);
+                    while request.len() < head_end + length {
+                        let n = stream.read(&mut buf).await.unwrap();
+                        request.extend_from_slice(&buf[..n]);
+                    }
+                    let body = request[head_end..head_end + length].to_vec();
+
+                    let (status, etag, response_body) = {
+                        let mut object = object.lock();
+                        let current_etag = object.as_ref().map(|(_, version)| format!("\"{}\"", version));
+                        if head.starts_with("get ") {
+                            match &*object {
+                                Some((data, _)) => ("200 OK", current_etag, data.clone()),
+                                None => ("404 Not Found", None, Vec::new()),
+                            }
+                        } else {
+                            let allowed = match (header("if-none-match"), header("if-match")) {
+                                (Some(_), _) => object.is_none(),
+                                (_, Some(tag)) => current_etag.as_deref() == Some(tag.as_str()),
+                                _ => true,
+                            };
+                            if allowed {
+                                let version = object.as_ref().map_or(1, |(_, version)| version + 1);
+                                *object = Some((body, version));
+                                ("200 OK", None, Vec::new())
+                            } else {
+                                ("412 Precondition Failed", None, Vec::new())
+                            }
+                        }
+                    };
+
+                    let etag = etag.map(|tag| format!("ETag: {}\r\n", tag)).unwrap_or_default();
+                    let head = format!(
+                        "HTTP/1.1 {}\r\n{}Content-Length: {}\r\nConnection: close\r\n\r\n",
+                        status,
+                        etag,
+                        response_body.len()
+                    );
+                    let _ = stream.write_all(head.as_bytes()).await;
+                    let _ = stream.write_all(&response_body).await;
+                });
+            }
+        });
+        endpoint
+    }
+
+    async fn backend_for(initial: Option<&[u8]>) -> S3Backend {
+        let mut settings = make_backend(true).settings;
+        settings.endpoint = fake_s3(initial).await;
+        S3Backend::new(settings)
+    }
+
+    #[tokio::test]
+    async fn test_stale_lock_taken_over_once() {
+        let stale = LockRecord {
+            owner: "crashed".to_string(),
+            expires_at: Utc::now() - chrono::Duration::seconds(1),
+        };
+        let backend = Arc::new(backend_for(Some(&stale.encode())).await);
+
+        // Every contender sees the same stale lock; If-Match lets only one replace it
+        let contenders: Vec<_> = (0..8)
+            .map(|i| {
+                let backend = backend.clone();
+                tokio::spawn(async move {
+                    backend.try_lock("locks/a.lock", &format!("node-{}", i), Duration::from_secs(60)).await.unwrap()
+                })
+            })
+            .collect();
+        let mut winners = 0;
+        for contender in contenders {
+            winners += contender.await.unwrap() as usize;
+        }
+        assert_eq!(winners, 1);
+    }
+
+    #[tokio::test]
+    async fn test_unreadable_lock_is_held() {
+        let backend = backend_for(Some(b"")).await;
+        assert!(!backend.try_lock("locks/a.lock", "node-1", Duration::from_secs(60)).await.unwrap());
+
+        let backend = backend_for(None).await;
+        assert!(backend.try_lock("locks/a.lock", "node-1", Duration::from_secs(60)).await.unwrap());
+        assert!(!backend.try_lock("locks/a.lock", "node-2", Duration::from_secs(60)).await.unwrap());
+    }
+
+    #[test]
+    fn test_uri_encode() {
+        assert_eq!(uri_encode("certs/a b.json", false), "certs/a%20b.json");
+        assert_eq!(uri_encode("certs/", true), "certs%2F");
+    }
+}
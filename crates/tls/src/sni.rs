@@ -2,6 +2,8 @@
 //!
 //! This module provides SNI-based certificate selection for Pingora's TLS listeners.
 
+use crate::handshake_log::HandshakeFailureReason;
+use crate::ocsp::{fetch_staple, has_must_staple, OcspStaple};
 use crate::storage::CertStorage;
 use async_trait::async_trait;
 use parking_lot::RwLock;
@@ -21,14 +23,45 @@ pub struct CertKeyPair {
     pub cert: X509,
     pub key: PKey<openssl::pkey::Private>,
     pub chain: Vec<X509>,
+    /// Certificate carries the OCSP Must-Staple extension
+    pub must_staple: bool,
+    /// Latest OCSP response, shared with resolver clones held by listeners
+    pub ocsp_staple: Arc<RwLock<Option<OcspStaple>>>,
+}
+
+impl CertKeyPair {
+    /// DER of the current OCSP response, if one is fresh
+    pub fn current_staple(&self) -> Option<Vec<u8>> {
+        self.ocsp_staple.read().as_ref().filter(|s| s.is_fresh()).map(|s| s.der.clone())
+    }
+
+    /// Must-Staple certificates are only served with a fresh staple
+    pub fn is_servable(&self) -> bool {
+        !self.must_staple || self.current_staple().is_some()
+    }
+
+    /// Fetch a new OCSP response from the issuer's responder
+    pub async fn refresh_staple(&self) -> Result<(), crate::error::TlsError> {
+        let issuer = self.chain.first().ok_or_else(|| {
+            crate::error::TlsError::CertificateError("No issuer certificate in chain".to_string())
+        })?;
+        let staple = fetch_staple(&self.cert, issuer).await?;
+        *self.ocsp_staple.write() = Some(staple);
+        Ok(())
+    }
 }
 
 /// SNI-based certificate resolver for OpenSSL
+///
+/// Clones share their certificates, so certificates added to the resolver
+/// after its clones were handed to the TLS listeners are served right away.
 pub struct SniResolver {
     /// Map of domain -> certificate/key pair
-    certs: RwLock<HashMap<String, Arc<CertKeyPair>>>,
+    certs: Arc<RwLock<HashMap<String, Arc<CertKeyPair>>>>,
     /// Default certificate if no SNI match
-    default: RwLock<Option<Arc<CertKeyPair>>>,
+    default: Arc<RwLock<Option<Arc<CertKeyPair>>>>,
+    /// Fail handshakes whose SNI matches no certificate instead of using the default
+    reject_unknown_sni: bool,
 }
 
 impl std::fmt::Debug for SniResolver {
@@ -43,19 +76,32 @@ impl SniResolver {
     /// Create a new SNI resolver
     pub fn new() -> Self {
         Self {
-            certs: RwLock::new(HashMap::new()),
-            default: RwLock::new(None),
+            certs: Arc::new(RwLock::new(HashMap::new())),
+            default: Arc::new(RwLock::new(None)),
+            reject_unknown_sni: false,
         }
     }
 
-    /// Add a certificate for a domain
+    /// Refuse handshakes for server names without a certificate
+    pub fn with_reject_unknown_sni(mut self, reject: bool) -> Self {
+        self.reject_unknown_sni = reject;
+        self
+    }
+
+    /// Add a certificate for a domain, replacing the one it had
     pub fn add_cert(&self, domain: &str, pair: Arc<CertKeyPair>) {
         let mut certs = self.certs.write();
-        certs.insert(domain.to_string(), pair.clone());
-
-        // Set first cert as default if none set
-        if self.default.read().is_none() {
-            *self.default.write() = Some(pair);
+        let replaced = certs.insert(domain.to_string(), pair.clone());
+
+        // Set first cert as default if none set, and keep the default current
+        let mut default = self.default.write();
+        let is_default = match (&*default, &replaced) {
+            (None, _) => true,
+            (Some(current), Some(replaced)) => Arc::ptr_eq(current, replaced),
+            _ => false,
+        };
+        if is_default {
+            *default = Some(pair);
         }
 
         debug!(domain = %domain, "Added certificate for SNI");
@@ -72,6 +118,11 @@ impl SniResolver {
         self.certs.read().len()
     }
 
+    /// Certificate loaded for exactly `domain`
+    pub fn certificate(&self, domain: &str) -> Option<Arc<CertKeyPair>> {
+        self.certs.read().get(domain).cloned()
+    }
+
     /// Load certificate from PEM files
     pub fn load_from_files(
         cert_path: &Path,
@@ -99,17 +150,50 @@ impl SniResolver {
             .skip(1) // Skip the main cert
             .collect();
 
-        Ok(Arc::new(CertKeyPair { cert, key, chain }))
+        let must_staple = has_must_staple(&cert);
+        Ok(Arc::new(CertKeyPair {
+            cert,
+            key,
+            chain,
+            must_staple,
+            ocsp_staple: Arc::new(RwLock::new(None)),
+        }))
+    }
+
+    /// Refresh OCSP staples of all Must-Staple certificates
+    pub async fn refresh_staples(&self) {
+        let mut pairs: Vec<(String, Arc<CertKeyPair>)> = self
+            .certs
+            .read()
+            .iter()
+            .filter(|(_, pair)| pair.must_staple)
+            .map(|(domain, pair)| (domain.clone(), pair.clone()))
+            .collect();
+        if let Some(default) = self.default.read().clone().filter(|p| p.must_staple) {
+            pairs.push(("default".to_string(), default));
+        }
+
+        for (domain, pair) in pairs {
+            match pair.refresh_staple().await {
+                Ok(()) => debug!(domain = %domain, "Refreshed OCSP staple"),
+                Err(e) if pair.is_servable() => {
+                    warn!(domain = %domain, error = %e, "Failed to refresh OCSP staple, keeping current one")
+                }
+                Err(e) => {
+                    warn!(domain = %domain, error = %e, "No OCSP staple for Must-Staple certificate, it will not be served")
+                }
+            }
+        }
     }
 
     /// Resolve certificate for a given SNI hostname
-    fn resolve(&self, sni: &str) -> Option<Arc<CertKeyPair>> {
+    fn resolve(&self, sni: &str) -> Result<Arc<CertKeyPair>, HandshakeFailureReason> {
         let certs = self.certs.read();
 
         // Try exact match
         if let Some(pair) = certs.get(sni) {
             debug!(sni = %sni, "Resolved certificate for SNI (exact match)");
-            return Some(pair.clone());
+            return Ok(pair.clone());
         }
 
         // Try wildcard match (e.g., *.example.com)
@@ -117,12 +201,49 @@ impl SniResolver {
             let wildcard = format!("*{}", &sni[dot_pos..]);
             if let Some(pair) = certs.get(&wildcard) {
                 debug!(sni = %sni, wildcard = %wildcard, "Resolved wildcard certificate");
-                return Some(pair.clone());
+                return Ok(pair.clone());
             }
         }
 
+        if self.reject_unknown_sni {
+            return Err(HandshakeFailureReason::UnknownSni);
+        }
         warn!(sni = %sni, "No certificate found for SNI, using default");
-        self.default.read().clone()
+        self.default.read().clone().ok_or(HandshakeFailureReason::NoCertificate)
+    }
+
+    /// Pick the certificate for a handshake, checking it can be served
+    fn select(&self, sni: Option<&str>) -> Result<Arc<CertKeyPair>, HandshakeFailureReason> {
+        let pair = match sni {
+            Some(hostname) => {
+                debug!(sni = %hostname, "TLS handshake with SNI");
+                self.resolve(hostname)?
+            }
+            None => {
+                debug!("TLS handshake without SNI, using default");
+                self.default.read().clone().ok_or(HandshakeFailureReason::NoCertificate)?
+            }
+        };
+
+        // Fail closed: clients reject a Must-Staple certificate without a staple anyway
+        if !pair.is_servable() {
+            return Err(HandshakeFailureReason::MissingStaple);
+        }
+        Ok(pair)
+    }
+
+    /// Log why the certificate callback refused a handshake
+    ///
+    /// The callback only sees the TLS session, not the socket; the failure
+    /// itself, with the client address, is logged by the listener.
+    fn handshake_refused(&self, sni: Option<&str>, reason: HandshakeFailureReason, detail: Option<String>) {
+        warn!(
+            target: "avalon::tls",
+            sni = sni.unwrap_or("-"),
+            reason = %reason,
+            detail = detail.as_deref().unwrap_or(""),
+            "Refusing TLS handshake"
+        );
     }
 }
 
@@ -135,8 +256,9 @@ impl Default for SniResolver {
 impl Clone for SniResolver {
     fn clone(&self) -> Self {
         Self {
-            certs: RwLock::new(self.certs.read().clone()),
-            default: RwLock::new(self.default.read().clone()),
+            certs: self.certs.clone(),
+            default: self.default.clone(),
+            reject_unknown_sni: self.reject_unknown_sni,
         }
     }
 }
@@ -145,43 +267,43 @@ impl Clone for SniResolver {
 impl TlsAccept for SniResolver {
     async fn certificate_callback(&self, ssl: &mut TlsRef) {
         // Get SNI from the SSL connection
-        let sni = ssl.servername(openssl::ssl::NameType::HOST_NAME);
+        let sni = ssl.servername(openssl::ssl::NameType::HOST_NAME).map(str::to_string);
 
-        let pair = match sni {
-            Some(hostname) => {
-                debug!(sni = %hostname, "TLS handshake with SNI");
-                self.resolve(hostname)
-            }
-            None => {
-                debug!("TLS handshake without SNI, using default");
-                self.default.read().clone()
+        // Returning without a certificate fails the handshake
+        let pair = match self.select(sni.as_deref()) {
+            Ok(pair) => pair,
+            Err(reason) => {
+                self.handshake_refused(sni.as_deref(), reason, None);
+                return;
             }
         };
 
-        if let Some(pair) = pair {
-            // Set the certificate
-            if let Err(e) = ssl_use_certificate(ssl, &pair.cert) {
-                warn!(error = %e, "Failed to set certificate");
-                return;
-            }
+        // Set the certificate
+        if let Err(e) = ssl_use_certificate(ssl, &pair.cert) {
+            self.handshake_refused(sni.as_deref(), HandshakeFailureReason::CertificateSetup, Some(e.to_string()));
+            return;
+        }
 
-            // Set the private key
-            if let Err(e) = ssl_use_private_key(ssl, &pair.key) {
-                warn!(error = %e, "Failed to set private key");
-                return;
-            }
+        // Set the private key
+        if let Err(e) = ssl_use_private_key(ssl, &pair.key) {
+            self.handshake_refused(sni.as_deref(), HandshakeFailureReason::CertificateSetup, Some(e.to_string()));
+            return;
+        }
 
-            // Set certificate chain if present
-            for chain_cert in &pair.chain {
-                if let Err(e) = ssl.add_chain_cert(chain_cert.clone()) {
-                    warn!(error = %e, "Failed to add chain certificate");
-                }
+        if let Some(staple) = pair.current_staple() {
+            if let Err(e) = ssl.set_ocsp_status(&staple) {
+                warn!(error = %e, "Failed to set OCSP staple");
             }
+        }
 
-            debug!("Certificate set successfully");
-        } else {
-            warn!("No certificate available for TLS handshake");
+        // Set certificate chain if present
+        for chain_cert in &pair.chain {
+            if let Err(e) = ssl.add_chain_cert(chain_cert.clone()) {
+                warn!(error = %e, "Failed to add chain certificate");
+            }
         }
+
+        debug!("Certificate set successfully");
     }
 }
 
@@ -194,7 +316,11 @@ pub async fn load_all_certs(
 ) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
     for domain in domains {
         // Try loading from ACME storage first
-        if let Ok(Some(bundle)) = storage.load_cert(domain).await {
+        let stored = storage.load_cert(domain).await.unwrap_or_else(|e| {
+            warn!(domain = %domain, error = %e, "Failed to load ACME certificate");
+            None
+        });
+        if let Some(bundle) = stored {
             match SniResolver::load_from_pem(
                 bundle.certificate_pem.as_bytes(),
                 bundle.private_key_pem.as_bytes(),
@@ -259,4 +385,90 @@ mod tests {
         let resolver = SniResolver::new();
         assert_eq!(resolver.domain_count(), 0);
     }
+
+    fn test_pair(must_staple: bool) -> Arc<CertKeyPair> {
+        let mut params = rcgen::CertificateParams::new(vec!["example.com".to_string()]).unwrap();
+        if must_staple {
+            params.custom_extensions.push(crate::ocsp::must_staple_extension());
+        }
+        let key_pair = rcgen::KeyPair::generate().unwrap();
+        let cert = params.self_signed(&key_pair).unwrap();
+        SniResolver::load_from_pem(cert.pem().as_bytes(), key_pair.serialize_pem().as_bytes()).unwrap()
+    }
+
+    #[test]
+    fn test_must_staple_cert_requires_staple() {
+        let pair = test_pair(true);
+        assert!(pair.must_staple);
+        assert!(!pair.is_servable());
+
+        *pair.ocsp_staple.write() = Some(OcspStaple {
+            der: vec![0x30],
+            next_update: chrono::Utc::now() - chrono::Duration::minutes(1),
+        });
+        assert!(!pair.is_servable());
+
+        *pair.ocsp_staple.write() = Some(OcspStaple {
+            der: vec![0x30],
+            next_update: chrono::Utc::now() + chrono::Duration::days(1),
+        });
+        assert!(pair.is_servable());
+        assert_eq!(pair.current_staple(), Some(vec![0x30]));
+    }
+
+    #[test]
+    fn test_unknown_sni_rejected() {
+        let resolver = SniResolver::new().with_reject_unknown_sni(true);
+        resolver.add_cert("example.com", test_pair(false));
+
+        let clone = resolver.clone();
+        assert!(clone.select(Some("example.com")).is_ok());
+        assert_eq!(clone.select(Some("unknown.example.net")).unwrap_err(), HandshakeFailureReason::UnknownSni);
+    }
+
+    #[test]
+    fn test_clones_serve_replaced_certificate() {
+        let resolver = SniResolver::new();
+        resolver.add_cert("example.com", test_pair(false));
+        let listener = resolver.clone();
+
+        let renewed = test_pair(false);
+        resolver.add_cert("example.com", renewed.clone());
+        resolver.add_cert("other.example.com", test_pair(false));
+
+        assert!(Arc::ptr_eq(&listener.select(Some("example.com")).unwrap(), &renewed));
+        // The renewed certificate also replaces the default it was
+        assert!(Arc::ptr_eq(&listener.select(None).unwrap(), &renewed));
+        assert_eq!(listener.domain_count(), 2);
+    }
+
+    #[test]
+    fn test_unknown_sni_falls_back_to_default() {
+        let resolver = SniResolver::new();
+        resolver.add_cert("example.com", test_pair(false));
+        assert!(resolver.select(Some("unknown.example.net")).is_ok());
+        assert!(resolver.select(None).is_ok());
+
+        assert_eq!(
+            SniResolver::new().select(None).unwrap_err(),
+            HandshakeFailureReason::NoCertificate
+        );
+    }
+
+    #[test]
+    fn test_missing_staple_fails_handshake() {
+        let resolver = SniResolver::new();
+        resolver.add_cert("example.com", test_pair(true));
+        assert_eq!(
+            resolver.select(Some("example.com")).unwrap_err(),
+            HandshakeFailureReason::MissingStaple
+        );
+    }
+
+    #[test]
+    fn test_regular_cert_served_without_staple() {
+        let pair = test_pair(false);
+        assert!(!pair.must_staple);
+        assert!(pair.is_servable());
+    }
 }
@@ -1,10 +1,15 @@
 //! Certificate storage
 
 use crate::error::TlsError;
+use crate::storage_backend::{FileBackend, StorageBackend};
 use chrono::{DateTime, Utc};
+use openssl::pkey::PKey;
+use openssl::x509::X509;
 use serde::{Deserialize, Serialize};
 use sha2::{Digest, Sha256};
 use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::Duration;
 use tokio::fs;
 use tracing::{debug, info, warn};
 use x509_parser::prelude::*;
@@ -30,6 +35,25 @@ impl CertBundle {
         let threshold = Utc::now() + chrono::Duration::days(days);
         self.expires_at <= threshold
     }
+
+    /// Check that the certificate and key parse and form a key pair
+    pub fn validate(&self) -> Result<(), TlsError> {
+        let corrupt = |reason: String| TlsError::CorruptCertificate {
+            domain: self.domain.clone(),
+            reason,
+        };
+        let cert = X509::from_pem(self.certificate_pem.as_bytes())
+            .map_err(|e| corrupt(format!("invalid certificate PEM: {}", e)))?;
+        let key = PKey::private_key_from_pem(self.private_key_pem.as_bytes())
+            .map_err(|e| corrupt(format!("invalid private key PEM: {}", e)))?;
+        let public_key = cert
+            .public_key()
+            .map_err(|e| corrupt(format!("unreadable certificate public key: {}", e)))?;
+        if !public_key.public_eq(&key) {
+            return Err(TlsError::KeyMismatch { domain: self.domain.clone() });
+        }
+        Ok(())
+    }
 }
 
 /// ACME account information
@@ -41,113 +65,135 @@ pub struct AcmeAccount {
     pub created_at: DateTime<Utc>,
 }
 
-/// Certificate storage backend
+/// Certificate storage
+///
+/// Bundles and accounts live in a `StorageBackend`; PEM files for the TLS
+/// listeners are always written below the local `base_path`.
 pub struct CertStorage {
     base_path: PathBuf,
+    backend: Arc<dyn StorageBackend>,
 }
 
 impl CertStorage {
-    /// Create a new certificate storage
+    /// Create a new certificate storage backed by files under `base_path`
     pub async fn new<P: AsRef<Path>>(base_path: P) -> Result<Self, TlsError> {
+        let backend = Arc::new(FileBackend::new(base_path.as_ref()));
+        Self::with_backend(base_path, backend).await
+    }
+
+    /// Create a certificate storage using a custom backend (e.g. Redis or S3)
+    pub async fn with_backend<P: AsRef<Path>>(
+        base_path: P,
+        backend: Arc<dyn StorageBackend>,
+    ) -> Result<Self, TlsError> {
         let base_path = base_path.as_ref().to_path_buf();
         fs::create_dir_all(&base_path).await?;
         fs::create_dir_all(base_path.join("certs")).await?;
         fs::create_dir_all(base_path.join("accounts")).await?;
 
-        info!(path = ?base_path, "Certificate storage initialized");
-        Ok(Self { base_path })
+        info!(path = ?base_path, backend = backend.name(), "Certificate storage initialized");
+        Ok(Self { base_path, backend })
     }
 
     /// Store a certificate bundle
     pub async fn store_cert(&self, bundle: &CertBundle) -> Result<(), TlsError> {
-        let path = self.cert_path(&bundle.domain);
+        let key = cert_key(&bundle.domain);
         let content = serde_json::to_string_pretty(bundle)?;
-        fs::write(&path, content).await?;
-        debug!(domain = %bundle.domain, path = ?path, "Certificate stored");
+        self.backend.store(&key, content.as_bytes()).await?;
+        debug!(domain = %bundle.domain, key = %key, "Certificate stored");
         Ok(())
     }
 
     /// Load a certificate bundle
     pub async fn load_cert(&self, domain: &str) -> Result<Option<CertBundle>, TlsError> {
-        let path = self.cert_path(domain);
-        if !path.exists() {
-            return Ok(None);
-        }
+        let key = cert_key(domain);
+        let content = match self.backend.load(&key).await? {
+            Some(content) => content,
+            None => return Ok(None),
+        };
 
-        let content = fs::read_to_string(&path).await?;
-        let bundle: CertBundle = serde_json::from_str(&content)?;
+        let bundle: CertBundle = serde_json::from_slice(&content).map_err(|e| TlsError::CorruptCertificate {
+            domain: domain.to_string(),
+            reason: e.to_string(),
+        })?;
 
         if bundle.is_expired() {
             debug!(domain = %domain, "Certificate expired, removing");
-            let _ = fs::remove_file(&path).await;
+            let _ = self.backend.delete(&key).await;
             return Ok(None);
         }
 
+        bundle.validate()?;
         Ok(Some(bundle))
     }
 
+    /// Load a certificate bundle, reporting corrupt stored data as missing
+    ///
+    /// Callers then take their usual path of obtaining a new certificate
+    /// instead of serving none. The stored data is left alone: it may be a
+    /// write from another instance caught halfway, and the replacement
+    /// overwrites it anyway.
+    pub async fn load_cert_or_missing(&self, domain: &str) -> Result<Option<CertBundle>, TlsError> {
+        match self.load_cert(domain).await {
+            Err(e) if e.is_corrupt_cert() => {
+                warn!(domain = %domain, error = %e, "Stored certificate is corrupt, treating it as missing");
+                Ok(None)
+            }
+            result => result,
+        }
+    }
+
     /// Delete a certificate
     pub async fn delete_cert(&self, domain: &str) -> Result<(), TlsError> {
-        let path = self.cert_path(domain);
-        if path.exists() {
-            fs::remove_file(&path).await?;
-            debug!(domain = %domain, "Certificate deleted");
-        }
+        self.backend.delete(&cert_key(domain)).await?;
+        debug!(domain = %domain, "Certificate deleted");
         Ok(())
     }
 
     /// List all stored domains
     pub async fn list_domains(&self) -> Result<Vec<String>, TlsError> {
-        let certs_dir = self.base_path.join("certs");
-        let mut domains = Vec::new();
+        let keys = self.backend.list("certs/").await?;
 
-        let mut entries = fs::read_dir(&certs_dir).await?;
-        while let Some(entry) = entries.next_entry().await? {
-            if let Some(name) = entry.file_name().to_str() {
-                if name.ends_with(".json") {
-                    domains.push(name.trim_end_matches(".json").to_string());
-                }
-            }
-        }
-
-        Ok(domains)
+        Ok(keys
+            .iter()
+            .filter_map(|key| key.strip_prefix("certs/")?.strip_suffix(".json"))
+            .map(|domain| domain.to_string())
+            .collect())
     }
 
     /// Store ACME account
     pub async fn store_account(&self, account: &AcmeAccount) -> Result<(), TlsError> {
-        let path = self.account_path(&account.email);
         let content = serde_json::to_string_pretty(account)?;
-        fs::write(&path, content).await?;
+        self.backend.store(&account_key(&account.email), content.as_bytes()).await?;
         debug!(email = %account.email, "ACME account stored");
         Ok(())
     }
 
     /// Load ACME account
     pub async fn load_account(&self, email: &str) -> Result<Option<AcmeAccount>, TlsError> {
-        let path = self.account_path(email);
-        if !path.exists() {
-            return Ok(None);
+        match self.backend.load(&account_key(email)).await? {
+            Some(content) => Ok(Some(serde_json::from_slice(&content)?)),
+            None => Ok(None),
         }
+    }
+
+    /// Try to take the issuance lock for a domain
+    pub async fn try_lock(&self, domain: &str, owner: &str, ttl: Duration) -> Result<bool, TlsError> {
+        self.backend.try_lock(&lock_key(domain), owner, ttl).await
+    }
 
-        let content = fs::read_to_string(&path).await?;
-        let account: AcmeAccount = serde_json::from_str(&content)?;
-        Ok(Some(account))
+    /// Release the issuance lock for a domain
+    pub async fn unlock(&self, domain: &str, owner: &str) -> Result<(), TlsError> {
+        self.backend.unlock(&lock_key(domain), owner).await
     }
 
-    /// Get certificate file path
+    /// Get certificate file path (used by the file backend)
     pub fn cert_path(&self, domain: &str) -> PathBuf {
         self.base_path
             .join("certs")
             .join(format!("{}.json", sanitize_domain(domain)))
     }
 
-    /// Get account file path
-    fn account_path(&self, email: &str) -> PathBuf {
-        self.base_path
-            .join("accounts")
-            .join(format!("{}.json", simple_hash(email)))
-    }
-
     /// Get the PEM file paths for a domain
     pub fn get_pem_paths(&self, domain: &str) -> (PathBuf, PathBuf) {
         let sanitized = sanitize_domain(domain);
@@ -178,6 +224,21 @@ impl CertStorage {
     }
 }
 
+/// Backend key of a certificate bundle
+fn cert_key(domain: &str) -> String {
+    format!("certs/{}.json", sanitize_domain(domain))
+}
+
+/// Backend key of an ACME account
+fn account_key(email: &str) -> String {
+    format!("accounts/{}.json", simple_hash(email))
+}
+
+/// Backend key of a domain's issuance lock
+fn lock_key(domain: &str) -> String {
+    format!("locks/{}.lock", sanitize_domain(domain))
+}
+
 /// Sanitize domain name for use as filename
 fn sanitize_domain(domain: &str) -> String {
     domain
@@ -453,6 +514,7 @@ fn simple_hash(s: &str) -> String {
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::self_signed::generate_self_signed;
     use tempfile::TempDir;
 
     #[test]
@@ -555,13 +617,7 @@ mod tests {
         let temp_dir = TempDir::new().unwrap();
         let storage = CertStorage::new(temp_dir.path()).await.unwrap();
 
-        let bundle = CertBundle {
-            domain: "example.com".to_string(),
-            certificate_pem: "cert".to_string(),
-            private_key_pem: "key".to_string(),
-            expires_at: Utc::now() + chrono::Duration::days(90),
-            created_at: Utc::now(),
-        };
+        let bundle = generate_self_signed("example.com", 90).unwrap();
 
         storage.store_cert(&bundle).await.unwrap();
         let loaded = storage.load_cert("example.com").await.unwrap().unwrap();
@@ -675,6 +731,29 @@ mod tests {
         assert_eq!(loaded.account_url, account.account_url);
     }
 
+    #[tokio::test]
+    async fn test_memory_backend_round_trip() {
+        use crate::storage_backend::MemoryBackend;
+
+        let temp_dir = TempDir::new().unwrap();
+        let backend = Arc::new(MemoryBackend::new());
+        let storage = CertStorage::with_backend(temp_dir.path(), backend.clone()).await.unwrap();
+
+        let bundle = generate_self_signed("shared.example.com", 90).unwrap();
+        storage.store_cert(&bundle).await.unwrap();
+
+        // A second instance sharing the backend sees the same certificate
+        let other = CertStorage::with_backend(temp_dir.path(), backend.clone()).await.unwrap();
+        let loaded = other.load_cert("shared.example.com").await.unwrap().unwrap();
+        assert_eq!(loaded.certificate_pem, bundle.certificate_pem);
+        assert_eq!(loaded.expires_at, bundle.expires_at);
+        assert_eq!(other.list_domains().await.unwrap(), vec!["shared.example.com"]);
+
+        // Nothing was written to the local certs directory besides the backend
+        assert!(!storage.cert_path("shared.example.com").exists());
+        assert!(backend.load("certs/shared.example.com.json").await.unwrap().is_some());
+    }
+
     #[tokio::test]
     async fn test_load_nonexistent_account() {
         let temp_dir = TempDir::new().unwrap();
@@ -683,4 +762,64 @@ mod tests {
         let result = storage.load_account("nonexistent@example.com").await.unwrap();
         assert!(result.is_none());
     }
+
+    #[tokio::test]
+    async fn test_truncated_cert_file_is_corrupt() {
+        let temp_dir = TempDir::new().unwrap();
+        let storage = CertStorage::new(temp_dir.path()).await.unwrap();
+
+        storage.store_cert(&generate_self_signed("example.com", 90).unwrap()).await.unwrap();
+        let path = storage.cert_path("example.com");
+        let content = std::fs::read(&path).unwrap();
+        std::fs::write(&path, &content[..content.len() / 2]).unwrap();
+
+        let err = storage.load_cert("example.com").await.unwrap_err();
+        assert!(matches!(&err, TlsError::CorruptCertificate { domain, .. } if domain == "example.com"), "{}", err);
+        assert!(err.is_corrupt_cert());
+    }
+
+    #[tokio::test]
+    async fn test_truncated_pem_is_corrupt() {
+        let temp_dir = TempDir::new().unwrap();
+        let storage = CertStorage::new(temp_dir.path()).await.unwrap();
+
+        let mut bundle = generate_self_signed("example.com", 90).unwrap();
+        bundle.certificate_pem.truncate(bundle.certificate_pem.len() / 2);
+        storage.store_cert(&bundle).await.unwrap();
+
+        let err = storage.load_cert("example.com").await.unwrap_err();
+        assert!(matches!(err, TlsError::CorruptCertificate { .. }), "{}", err);
+    }
+
+    #[tokio::test]
+    async fn test_mismatched_key_is_rejected() {
+        let temp_dir = TempDir::new().unwrap();
+        let storage = CertStorage::new(temp_dir.path()).await.unwrap();
+
+        let mut bundle = generate_self_signed("example.com", 90).unwrap();
+        bundle.private_key_pem = generate_self_signed("example.com", 90).unwrap().private_key_pem;
+        storage.store_cert(&bundle).await.unwrap();
+
+        let err = storage.load_cert("example.com").await.unwrap_err();
+        assert!(matches!(err, TlsError::KeyMismatch { .. }), "{}", err);
+        assert!(err.is_corrupt_cert());
+    }
+
+    #[tokio::test]
+    async fn test_corrupt_cert_missing_for_reissue() {
+        let temp_dir = TempDir::new().unwrap();
+        let storage = CertStorage::new(temp_dir.path()).await.unwrap();
+
+        storage.store_cert(&generate_self_signed("example.com", 90).unwrap()).await.unwrap();
+        let path = storage.cert_path("example.com");
+        std::fs::write(&path, b"{\"domain\": \"example.com\", \"certif").unwrap();
+
+        // Reported as missing, which sends renewal down the obtain path
+        assert!(storage.load_cert_or_missing("example.com").await.unwrap().is_none());
+        assert!(path.exists());
+
+        // A valid bundle is returned as-is
+        storage.store_cert(&generate_self_signed("example.com", 90).unwrap()).await.unwrap();
+        assert!(storage.load_cert_or_missing("example.com").await.unwrap().is_some());
+    }
 }
@@ -0,0 +1,426 @@
+//! Pluggable key-value backends for certificate storage
+//!
+//! `CertStorage` serializes certificate bundles and ACME accounts and hands
+//! them to a backend under keys like `certs/example.com.json`. The file
+//! backend keeps the historical on-disk layout; shared backends (Redis, S3)
+//! let several avalon instances see the same certificates.
+
+use crate::error::TlsError;
+use async_trait::async_trait;
+use chrono::{DateTime, Utc};
+use dashmap::DashMap;
+use dashmap::mapref::entry::Entry;
+use serde::{Deserialize, Serialize};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::Duration;
+use tokio::fs;
+use tokio::io::AsyncWriteExt;
+
+/// Key-value store for serialized certificates and accounts
+#[async_trait]
+pub trait StorageBackend: Send + Sync {
+    /// Load the value stored under a key
+    async fn load(&self, key: &str) -> Result<Option<Vec<u8>>, TlsError>;
+
+    /// Store a value, replacing any existing one
+    async fn store(&self, key: &str, data: &[u8]) -> Result<(), TlsError>;
+
+    /// Delete a key; deleting a missing key is not an error
+    async fn delete(&self, key: &str) -> Result<(), TlsError>;
+
+    /// List keys starting with a prefix (returned without the backend's own namespace)
+    async fn list(&self, prefix: &str) -> Result<Vec<String>, TlsError>;
+
+    /// Atomically take a lock unless another owner holds an unexpired one
+    ///
+    /// Re-acquiring a lock already held by `owner` succeeds and extends it.
+    async fn try_lock(&self, key: &str, owner: &str, ttl: Duration) -> Result<bool, TlsError>;
+
+    /// Release a lock if it is still held by `owner`
+    async fn unlock(&self, key: &str, owner: &str) -> Result<(), TlsError>;
+
+    /// Backend name for logging
+    fn name(&self) -> &'static str;
+}
+
+/// Lock contents for backends without native key expiry
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub(crate) struct LockRecord {
+    pub owner: String,
+    pub expires_at: DateTime<Utc>,
+}
+
+impl LockRecord {
+    pub fn new(owner: &str, ttl: Duration) -> Self {
+        let ttl = chrono::Duration::from_std(ttl).unwrap_or_else(|_| chrono::Duration::days(365));
+        Self {
+            owner: owner.to_string(),
+            expires_at: Utc::now() + ttl,
+        }
+    }
+
+    pub fn encode(&self) -> Vec<u8> {
+        serde_json::to_vec(self).unwrap_or_default()
+    }
+
+    /// Parse a stored lock
+    pub fn decode(data: &[u8]) -> Option<Self> {
+        serde_json::from_slice(data).ok()
+    }
+
+    /// Whether this lock keeps `owner` out
+    pub fn blocks(&self, owner: &str) -> bool {
+        self.owner != owner && self.expires_at > Utc::now()
+    }
+
+    /// Whether stored lock data keeps `owner` out
+    ///
+    /// Data that doesn't parse, empty included, counts as held: it can't be
+    /// told apart from another owner's lock.
+    pub fn data_blocks(data: &[u8], owner: &str) -> bool {
+        Self::decode(data).is_none_or(|lock| lock.blocks(owner))
+    }
+}
+
+/// Backend storing each key as a file below a base directory
+pub struct FileBackend {
+    base_path: PathBuf,
+}
+
+impl FileBackend {
+    pub fn new<P: AsRef<Path>>(base_path: P) -> Self {
+        Self {
+            base_path: base_path.as_ref().to_path_buf(),
+        }
+    }
+
+    fn path(&self, key: &str) -> PathBuf {
+        self.base_path.join(key)
+    }
+
+    /// A new temporary path, for files renamed or linked into place
+    ///
+    /// Temporary files live in their own directory under the base path, out
+    /// of every listing but on the same filesystem as the keys.
+    async fn temp_path(&self) -> Result<PathBuf, TlsError> {
+        static NEXT: AtomicU64 = AtomicU64::new(0);
+        let dir = self.base_path.join(".tmp");
+        fs::create_dir_all(&dir).await?;
+        Ok(dir.join(format!("{}-{}", std::process::id(), NEXT.fetch_add(1, Ordering::Relaxed))))
+    }
+
+    /// Write `data` to a new temporary file
+    async fn write_temp(&self, data: &[u8]) -> Result<PathBuf, TlsError> {
+        let path = self.temp_path().await?;
+        let mut file = fs::File::create(&path).await?;
+        file.write_all(data).await?;
+        file.sync_all().await?;
+        Ok(path)
+    }
+}
+
+#[async_trait]
+impl StorageBackend for FileBackend {
+    async fn load(&self, key: &str) -> Result<Option<Vec<u8>>, TlsError> {
+        match fs::read(self.path(key)).await {
+            Ok(data) => Ok(Some(data)),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(None),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    async fn store(&self, key: &str, data: &[u8]) -> Result<(), TlsError> {
+        let path = self.path(key);
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent).await?;
+        }
+
+        // Readers see the old value or the new one, never a partial write
+        let temp = self.write_temp(data).await?;
+        if let Err(e) = fs::rename(&temp, &path).await {
+            let _ = fs::remove_file(&temp).await;
+            return Err(e.into());
+        }
+        Ok(())
+    }
+
+    async fn delete(&self, key: &str) -> Result<(), TlsError> {
+        match fs::remove_file(self.path(key)).await {
+            Ok(()) => Ok(()),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(()),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    async fn list(&self, prefix: &str) -> Result<Vec<String>, TlsError> {
+        // Keys are "dir/name"; list the directory part of the prefix
+        let (dir, name_prefix) = match prefix.rsplit_once('/') {
+            Some((dir, name)) => (format!("{}/", dir), name),
+            None => (String::new(), prefix),
+        };
+
+        let mut entries = match fs::read_dir(self.base_path.join(&dir)).await {
+            Ok(entries) => entries,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(Vec::new()),
+            Err(e) => return Err(e.into()),
+        };
+
+        let mut keys = Vec::new();
+        while let Some(entry) = entries.next_entry().await? {
+            if !entry.file_type().await?.is_file() {
+                continue;
+            }
+            if let Some(name) = entry.file_name().to_str() {
+                if name.starts_with(name_prefix) {
+                    keys.push(format!("{}{}", dir, name));
+                }
+            }
+        }
+
+        Ok(keys)
+    }
+
+    async fn try_lock(&self, key: &str, owner: &str, ttl: Duration) -> Result<bool, TlsError> {
+        let path = self.path(key);
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent).await?;
+        }
+
+        // The record is complete before it appears under the key
+        let record = self.write_temp(&LockRecord::new(owner, ttl).encode()).await?;
+        let result = self.take_lock(&path, &record, owner).await;
+        let _ = fs::remove_file(&record).await;
+        result
+    }
+
+    async fn unlock(&self, key: &str, owner: &str) -> Result<(), TlsError> {
+        let existing = self.load(key).await?.and_then(|d| LockRecord::decode(&d));
+        if existing.is_some_and(|lock| lock.owner == owner) {
+            self.delete(key).await?;
+        }
+        Ok(())
+    }
+
+    fn name(&self) -> &'static str {
+        "file"
+    }
+}
+
+impl FileBackend {
+    /// Link `record` in as the lock at `path` unless another owner's live lock is there
+    async fn take_lock(&self, path: &Path, record: &Path, owner: &str) -> Result<bool, TlsError> {
+        for _ in 0..2 {
+            // Linking fails if the lock exists, so only one contender creates it
+            match fs::hard_link(record, path).await {
+                Ok(()) => return Ok(true),
+                Err(e) if e.kind() == std::io::ErrorKind::AlreadyExists => {}
+                Err(e) => return Err(e.into()),
+            }
+
+            let current = match fs::read(path).await {
+                Ok(data) => data,
+                Err(e) if e.kind() == std::io::ErrorKind::NotFound => continue,
+                Err(e) => return Err(e.into()),
+            };
+            if LockRecord::data_blocks(&current, owner) {
+                return Ok(false);
+            }
+
+            // Move the stale (or own) lock aside; only one contender gets it.
+            // If another contender's new lock was moved instead, put it back.
+            let aside = self.temp_path().await?;
+            match fs::rename(path, &aside).await {
+                Ok(()) => {}
+                Err(e) if e.kind() == std::io::ErrorKind::NotFound => continue,
+                Err(e) => return Err(e.into()),
+            }
+            let moved = fs::read(&aside).await?;
+            if moved != current {
+                let _ = fs::hard_link(&aside, path).await;
+                let _ = fs::remove_file(&aside).await;
+                return Ok(false);
+            }
+            fs::remove_file(&aside).await?;
+        }
+
+        Ok(false)
+    }
+}
+
+/// In-process backend, useful for tests and single-run tooling
+#[derive(Default)]
+pub struct MemoryBackend {
+    entries: DashMap<String, Vec<u8>>,
+}
+
+impl MemoryBackend {
+    pub fn new() -> Self {
+        Self::default()
+    }
+}
+
+#[async_trait]
+impl StorageBackend for MemoryBackend {
+    async fn load(&self, key: &str) -> Result<Option<Vec<u8>>, TlsError> {
+        Ok(self.entries.get(key).map(|v| v.clone()))
+    }
+
+    async fn store(&self, key: &str, data: &[u8]) -> Result<(), TlsError> {
+        self.entries.insert(key.to_string(), data.to_vec());
+        Ok(())
+    }
+
+    async fn delete(&self, key: &str) -> Result<(), TlsError> {
+        self.entries.remove(key);
+        Ok(())
+    }
+
+    async fn list(&self, prefix: &str) -> Result<Vec<String>, TlsError> {
+        Ok(self
+            .entries
+            .iter()
+            .filter(|e| e.key().starts_with(prefix))
+            .map(|e| e.key().clone())
+            .collect())
+    }
+
+    async fn try_lock(&self, key: &str, owner: &str, ttl: Duration) -> Result<bool, TlsError> {
+        let record = LockRecord::new(owner, ttl).encode();
+        match self.entries.entry(key.to_string()) {
+            Entry::Occupied(mut entry) => {
+                if LockRecord::data_blocks(entry.get(), owner) {
+                    return Ok(false);
+                }
+                entry.insert(record);
+            }
+            Entry::Vacant(entry) => {
+                entry.insert(record);
+            }
+        }
+        Ok(true)
+    }
+
+    async fn unlock(&self, key: &str, owner: &str) -> Result<(), TlsError> {
+        self.entries
+            .remove_if(key, |_, data| LockRecord::decode(data).is_some_and(|lock| lock.owner == owner));
+        Ok(())
+    }
+
+    fn name(&self) -> &'static str {
+        "memory"
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::Arc;
+    use tempfile::TempDir;
+
+    async fn exercise_backend(backend: &dyn StorageBackend) {
+        assert!(backend.load("certs/a.com.json").await.unwrap().is_none());
+
+        backend.store("certs/a.com.json", b"first").await.unwrap();
+        backend.store("certs/b.com.json", b"second").await.unwrap();
+        backend.store("accounts/x.json", b"account").await.unwrap();
+
+        assert_eq!(backend.load("certs/a.com.json").await.unwrap(), Some(b"first".to_vec()));
+
+        backend.store("certs/a.com.json", b"replaced").await.unwrap();
+        assert_eq!(backend.load("certs/a.com.json").await.unwrap(), Some(b"replaced".to_vec()));
+
+        let mut keys = backend.list("certs/").await.unwrap();
+        keys.sort();
+        assert_eq!(keys, vec!["certs/a.com.json", "certs/b.com.json"]);
+
+        backend.delete("certs/a.com.json").await.unwrap();
+        backend.delete("certs/missing.json").await.unwrap();
+        assert!(backend.load("certs/a.com.json").await.unwrap().is_none());
+        assert_eq!(backend.list("certs/").await.unwrap(), vec!["certs/b.com.json"]);
+    }
+
+    async fn exercise_lock(backend: &dyn StorageBackend) {
+        let ttl = Duration::from_secs(60);
+        assert!(backend.try_lock("locks/a.lock", "node-1", ttl).await.unwrap());
+        assert!(!backend.try_lock("locks/a.lock", "node-2", ttl).await.unwrap());
+        assert!(backend.try_lock("locks/a.lock", "node-1", ttl).await.unwrap());
+
+        // Only the owner can release
+        backend.unlock("locks/a.lock", "node-2").await.unwrap();
+        assert!(!backend.try_lock("locks/a.lock", "node-2", ttl).await.unwrap());
+        backend.unlock("locks/a.lock", "node-1").await.unwrap();
+        assert!(backend.try_lock("locks/a.lock", "node-2", ttl).await.unwrap());
+
+        // Expired locks are taken over
+        assert!(backend.try_lock("locks/b.lock", "crashed", Duration::from_millis(1)).await.unwrap());
+        tokio::time::sleep(Duration::from_millis(10)).await;
+        assert!(backend.try_lock("locks/b.lock", "node-1", ttl).await.unwrap());
+
+        // A lock that can't be read is held by someone
+        backend.store("locks/c.lock", b"").await.unwrap();
+        assert!(!backend.try_lock("locks/c.lock", "node-1", ttl).await.unwrap());
+        backend.store("locks/c.lock", b"{\"owner\":").await.unwrap();
+        assert!(!backend.try_lock("locks/c.lock", "node-1", ttl).await.unwrap());
+    }
+
+    #[tokio::test]
+    async fn test_memory_backend() {
+        exercise_backend(&MemoryBackend::new()).await;
+    }
+
+    #[tokio::test]
+    async fn test_memory_backend_lock() {
+        exercise_lock(&MemoryBackend::new()).await;
+    }
+
+    #[tokio::test]
+    async fn test_file_backend_lock() {
+        let temp_dir = TempDir::new().unwrap();
+        exercise_lock(&FileBackend::new(temp_dir.path())).await;
+    }
+
+    #[tokio::test]
+    async fn test_file_backend_stale_lock_taken_over_once() {
+        let temp_dir = TempDir::new().unwrap();
+        let backend = Arc::new(FileBackend::new(temp_dir.path()));
+        assert!(backend.try_lock("locks/a.lock", "crashed", Duration::from_millis(1)).await.unwrap());
+        tokio::time::sleep(Duration::from_millis(10)).await;
+
+        // Many contenders find the same stale lock; exactly one takes it
+        let contenders: Vec<_> = (0..16)
+            .map(|i| {
+                let backend = backend.clone();
+                tokio::spawn(async move {
+                    backend.try_lock("locks/a.lock", &format!("node-{}", i), Duration::from_secs(60)).await.unwrap()
+                })
+            })
+            .collect();
+        let mut winners = 0;
+        for contender in contenders {
+            winners += contender.await.unwrap() as usize;
+        }
+        assert_eq!(winners, 1);
+        assert_eq!(std::fs::read_dir(temp_dir.path().join(".tmp")).unwrap().count(), 0);
+    }
+
+    #[tokio::test]
+    async fn test_file_backend() {
+        let temp_dir = TempDir::new().unwrap();
+        let backend = FileBackend::new(temp_dir.path());
+        exercise_backend(&backend).await;
+
+        // Keeps the historical layout on disk
+        assert!(temp_dir.path().join("certs/b.com.json").exists());
+
+        // Values are renamed into place, leaving no temporary files behind
+        assert_eq!(std::fs::read_dir(temp_dir.path().join(".tmp")).unwrap().count(), 0);
+    }
+
+    #[tokio::test]
+    async fn test_file_backend_list_missing_dir() {
+        let temp_dir = TempDir::new().unwrap();
+        let backend = FileBackend::new(temp_dir.path().join("nope"));
+        assert!(backend.list("certs/").await.unwrap().is_empty());
+    }
+}
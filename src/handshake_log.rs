@@ -0,0 +1,213 @@
+//! Failed TLS handshakes, as reported by Pingora's listener
+//!
+//! Pingora runs the handshake in its listener, which knows the client
+//! address but only reports failures through the `log` crate. This layer
+//! picks those records out of the events bridged into `tracing` and turns
+//! them into connection-level [`HandshakeFailure`] entries.
+
+use std::fmt;
+use tls::handshake_log::log_handshake_failure;
+use tls::{HandshakeFailure, HandshakeFailureHook};
+use tracing::field::{Field, Visit};
+use tracing::{Event, Subscriber};
+use tracing_subscriber::layer::{Context, Layer};
+
+/// Target of the listener's log records
+const LISTENER_TARGET: &str = "pingora_core::services::listening";
+
+/// Logs every failed handshake with its client address and calls `hook`
+pub struct HandshakeFailureLayer {
+    hook: HandshakeFailureHook,
+}
+
+impl HandshakeFailureLayer {
+    pub fn new(hook: HandshakeFailureHook) -> Self {
+        Self { hook }
+    }
+}
+
+impl<S: Subscriber> Layer<S> for HandshakeFailureLayer {
+    fn on_event(&self, event: &Event<'_>, _ctx: Context<'_, S>) {
+        // Records bridged from `log` all share the "log" target
+        if event.metadata().target() != "log" {
+            return;
+        }
+        let mut record = LogRecord::default();
+        event.record(&mut record);
+        if record.target.as_deref() != Some(LISTENER_TARGET) {
+            return;
+        }
+        if let Some(failure) = record.message.as_deref().and_then(HandshakeFailure::from_listener_error) {
+            log_handshake_failure(&failure);
+            (self.hook)(&failure);
+        }
+    }
+}
+
+/// Original target and message of a record bridged from `log`
+#[derive(Default)]
+struct LogRecord {
+    target: Option<String>,
+    message: Option<String>,
+}
+
+impl Visit for LogRecord {
+    fn record_str(&mut self, field: &Field, value: &str) {
+        if field.name() == "log.target" {
+            self.target = Some(value.to_string());
+        }
+    }
+
+    fn record_debug(&mut self, field: &Field, value: &dyn fmt::Debug) {
+        if field.name() == "message" {
+            self.message = Some(format!("{:?}", value));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use config::Config;
+    use pingora_core::listeners::tls::TlsSettings;
+    use pingora_core::tls::pkey::PKey;
+    use pingora_core::tls::ssl::{SslConnector, SslConnectorBuilder, SslMethod, SslVerifyMode, SslVersion};
+    use pingora_core::tls::x509::X509;
+    use proxy::AvalonProxy;
+    use std::net::SocketAddr;
+    use std::path::Path;
+    use std::sync::{Arc, Mutex};
+    use std::time::Duration;
+    use tls::HandshakeFailureReason;
+    use tracing_subscriber::layer::SubscriberExt;
+    use tracing_subscriber::util::SubscriberInitExt;
+
+    const CONFIG: &str = r#"
+[tls]
+acme_enabled = false
+
+[[servers]]
+name = "web"
+listen = [":443"]
+
+[[servers.routes]]
+[servers.routes.handle]
+type = "static_response"
+body = "ok"
+"#;
+
+    /// Write a self-signed certificate and key for `domain` into `dir`
+    fn write_cert(dir: &Path, domain: &str) -> (String, String) {
+        let bundle = tls::self_signed::generate_self_signed(domain, 30).unwrap();
+        let cert = dir.join(format!("{}.pem", domain));
+        let key = dir.join(format!("{}.key", domain));
+        std::fs::write(&cert, &bundle.certificate_pem).unwrap();
+        std::fs::write(&key, &bundle.private_key_pem).unwrap();
+        (cert.display().to_string(), key.display().to_string())
+    }
+
+    async fn serve(dir: &Path, settings: TlsSettings) -> (SocketAddr, tokio::sync::watch::Sender<bool>) {
+        use pingora_core::services::Service as _;
+
+        let path = dir.join("avalon.toml");
+        std::fs::write(&path, CONFIG).unwrap();
+        let proxy = AvalonProxy::new(Config::load(&path).unwrap(), Arc::new(Default::default())).unwrap();
+
+        let addr = std::net::TcpListener::bind("127.0.0.1:0").unwrap().local_addr().unwrap();
+        let mut service = pingora_proxy::http_proxy_service(&Arc::new(Default::default()), proxy);
+        service.add_tls_with_settings(&addr.to_string(), None, settings);
+        let (shutdown, watch) = tokio::sync::watch::channel(false);
+        tokio::spawn(async move { service.start_service(None, watch, 1).await });
+
+        for _ in 0..100 {
+            if tokio::net::TcpStream::connect(addr).await.is_ok() {
+                break;
+            }
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+        (addr, shutdown)
+    }
+
+    /// Handshake with `addr` from a blocking OpenSSL client set up by `configure`
+    async fn handshake(
+        addr: SocketAddr,
+        configure: impl FnOnce(&mut SslConnectorBuilder) + Send + 'static,
+    ) -> bool {
+        tokio::task::spawn_blocking(move || {
+            let mut builder = SslConnector::builder(SslMethod::tls()).unwrap();
+            builder.set_verify(SslVerifyMode::NONE);
+            configure(&mut builder);
+            let stream = std::net::TcpStream::connect(addr).unwrap();
+            builder.build().connect("localhost", stream).is_ok()
+        })
+        .await
+        .unwrap()
+    }
+
+    async fn recorded_failure(failures: &Mutex<Vec<HandshakeFailure>>) -> HandshakeFailure {
+        for _ in 0..100 {
+            if let Some(failure) = failures.lock().unwrap().first() {
+                return failure.clone();
+            }
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+        panic!("no handshake failure recorded");
+    }
+
+    fn record_failures() -> (tracing::subscriber::DefaultGuard, Arc<Mutex<Vec<HandshakeFailure>>>) {
+        let failures = Arc::new(Mutex::new(Vec::new()));
+        let recorded = failures.clone();
+        let layer = HandshakeFailureLayer::new(Arc::new(move |failure: &HandshakeFailure| recorded.lock().unwrap().push(failure.clone())));
+        (tracing_subscriber::registry().with(layer).set_default(), failures)
+    }
+
+    #[tokio::test]
+    async fn test_protocol_version_failure_logged_with_client_ip() {
+        let dir = std::env::temp_dir().join(format!("avalon-handshake-version-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let (_guard, failures) = record_failures();
+
+        let (cert, key) = write_cert(&dir, "localhost");
+        let mut settings = TlsSettings::intermediate(&cert, &key).unwrap();
+        settings.set_min_proto_version(Some(SslVersion::TLS1_3)).unwrap();
+        let (addr, _shutdown) = serve(&dir, settings).await;
+
+        let connected = handshake(addr, |client| client.set_max_proto_version(Some(SslVersion::TLS1_2)).unwrap()).await;
+        assert!(!connected, "a TLS 1.2 client must not reach a TLS 1.3 only listener");
+
+        let failure = recorded_failure(&failures).await;
+        assert_eq!(failure.client_ip, Some("127.0.0.1".parse().unwrap()));
+        assert_eq!(failure.reason, HandshakeFailureReason::ProtocolVersion);
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+
+    #[tokio::test]
+    async fn test_untrusted_client_certificate_logged_with_client_ip() {
+        let dir = std::env::temp_dir().join(format!("avalon-handshake-client-cert-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let (_guard, failures) = record_failures();
+
+        let (cert, key) = write_cert(&dir, "localhost");
+        let (ca, _) = write_cert(&dir, "clients.example.com");
+        let mut settings = TlsSettings::intermediate(&cert, &key).unwrap();
+        tls::request_client_certs(&mut settings, Path::new(&ca)).unwrap();
+        let (addr, _shutdown) = serve(&dir, settings).await;
+
+        // Signed by nobody the listener trusts
+        let (client_cert, client_key) = write_cert(&dir, "client.example.net");
+        // With TLS 1.3 the client can finish its side before the listener
+        // rejects the certificate, so only the listener's verdict counts
+        handshake(addr, move |client| {
+            let cert = X509::from_pem(&std::fs::read(&client_cert).unwrap()).unwrap();
+            let key = PKey::private_key_from_pem(&std::fs::read(&client_key).unwrap()).unwrap();
+            client.set_certificate(&cert).unwrap();
+            client.set_private_key(&key).unwrap();
+        })
+        .await;
+
+        let failure = recorded_failure(&failures).await;
+        assert_eq!(failure.client_ip, Some("127.0.0.1".parse().unwrap()));
+        assert_eq!(failure.reason, HandshakeFailureReason::ClientCertificate);
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+}
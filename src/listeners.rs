@@ -0,0 +1,450 @@
+//! Listeners added and removed by config reloads
+//!
+//! Pingora binds its listeners once at startup, so a reloaded config can't
+//! hand it a new address. Plain HTTP addresses that appear in a reloaded
+//! config are bound here instead and served by the proxy with their
+//! server's HTTP/2 settings; when an address added this way disappears
+//! again it stops accepting and its connections drain.
+//!
+//! Pingora can't close one of its own listeners either, so each one bound
+//! at startup is gated: once a reload removes its address, new connections
+//! are closed as soon as they're accepted and open ones drain, and listing
+//! the address again reopens it. Changes only a restart can apply (new TLS
+//! listeners, an address switching between TLS and plain HTTP, `unix:` and
+//! `fd:` addresses) are logged.
+
+use crate::socket_activation::parse_fd_address;
+use async_trait::async_trait;
+use config::{Config, Http2Config};
+use pingora_core::apps::ServerApp;
+use pingora_core::protocols::l4::stream::Stream;
+use pingora_core::protocols::{GetSocketDigest, SocketDigest};
+use pingora_core::server::ShutdownWatch;
+use std::collections::HashMap;
+use std::io;
+use std::os::unix::io::AsRawFd;
+use std::sync::Arc;
+use tokio::net::TcpListener;
+use tokio::sync::watch;
+use tracing::{debug, error, info, warn};
+
+/// A TCP listen address of a config
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Listener {
+    /// Address to bind, e.g. `0.0.0.0:8080`
+    pub addr: String,
+    pub tls: bool,
+    /// HTTP/2 settings of the server listening here
+    pub http2: Http2Config,
+}
+
+/// Listener differences between two configs
+#[derive(Debug, Default)]
+pub struct ListenerChanges {
+    /// Listeners to start; a TLS one only starts if it was bound at startup
+    pub added: Vec<Listener>,
+    /// Listeners no longer in the config
+    pub removed: Vec<Listener>,
+    /// Changes that only take effect after a restart
+    pub restart_required: Vec<String>,
+}
+
+impl ListenerChanges {
+    /// Compare the listeners of the running config with a reloaded one
+    pub fn between(old: &Config, new: &Config) -> Self {
+        let before = tcp_listeners(old);
+        let after = tcp_listeners(new);
+        let mut changes = Self::default();
+
+        for listener in &after {
+            match before.iter().find(|l| l.addr == listener.addr) {
+                Some(previous) if previous.tls != listener.tls => changes
+                    .restart_required
+                    .push(format!("{} switches between TLS and plain HTTP", listener.addr)),
+                Some(_) => {}
+                None => changes.added.push(listener.clone()),
+            }
+        }
+        changes.removed = before
+            .into_iter()
+            .filter(|listener| !after.iter().any(|l| l.addr == listener.addr))
+            .collect();
+
+        let (before, after) = (other_listeners(old), other_listeners(new));
+        for listen in after.iter().filter(|l| !before.contains(l)) {
+            changes.restart_required.push(format!("new listener {}", listen));
+        }
+        for listen in before.iter().filter(|l| !after.contains(l)) {
+            changes.restart_required.push(format!("removed listener {}", listen));
+        }
+        changes
+    }
+}
+
+/// Address a listen entry is bound to, as `main` binds it
+pub fn bind_address(listen: &str) -> String {
+    let addr = listen
+        .strip_prefix("https://")
+        .or_else(|| listen.strip_prefix("http://"))
+        .unwrap_or(listen);
+    if addr.starts_with(':') {
+        format!("0.0.0.0{}", addr)
+    } else {
+        addr.to_string()
+    }
+}
+
+fn is_tcp(listen: &str) -> bool {
+    config::unix_socket_path(listen).is_none() && parse_fd_address(listen).is_none()
+}
+
+fn tcp_listeners(config: &Config) -> Vec<Listener> {
+    let mut listeners: Vec<Listener> = Vec::new();
+    for server in &config.servers {
+        for listen in server.listen.iter().filter(|l| is_tcp(l)) {
+            let listener = Listener {
+                addr: bind_address(listen),
+                tls: crate::is_tls_address(listen),
+                http2: server.http2.clone(),
+            };
+            if !listeners.iter().any(|l| l.addr == listener.addr) {
+                listeners.push(listener);
+            }
+        }
+    }
+    listeners
+}
+
+/// `unix:` and `fd:` listen addresses
+fn other_listeners(config: &Config) -> Vec<&String> {
+    config.servers.iter().flat_map(|s| &s.listen).filter(|l| !is_tcp(l)).collect()
+}
+
+/// Builds the app serving a listener, given its server's HTTP/2 settings
+pub type AppFactory<A> = Box<dyn Fn(&Http2Config) -> A + Send + Sync>;
+
+/// A listener Pingora bound at startup
+struct StartupListener {
+    tls: bool,
+    /// Whether its address is gone from the config
+    closed: watch::Sender<bool>,
+}
+
+/// Listeners started and stopped by config reloads
+pub struct DynamicListeners<A> {
+    new_app: AppFactory<A>,
+    /// Stop signal of each listener bound on reload, by address
+    running: HashMap<String, watch::Sender<bool>>,
+    /// Listeners bound at startup, by address
+    startup: HashMap<String, StartupListener>,
+}
+
+impl<A: ServerApp + Send + Sync + 'static> DynamicListeners<A> {
+    /// Listeners serving connections with apps built by `new_app`
+    pub fn new(new_app: impl Fn(&Http2Config) -> A + Send + Sync + 'static) -> Self {
+        Self {
+            new_app: Box::new(new_app),
+            running: HashMap::new(),
+            startup: HashMap::new(),
+        }
+    }
+
+    /// Track a listener Pingora binds at `addr`, serving it with `app`
+    ///
+    /// The returned app is what Pingora should serve; it closes connections
+    /// while a reload has the address removed.
+    pub fn gate_startup<B>(&mut self, addr: &str, tls: bool, app: B) -> Gated<B> {
+        let (closed, gate) = watch::channel(false);
+        self.startup.insert(addr.to_string(), StartupListener { tls, closed });
+        Gated { app: Arc::new(app), closed: gate }
+    }
+
+    /// Start and stop listeners as a reload requires
+    pub async fn apply(&mut self, changes: &ListenerChanges) {
+        for change in &changes.restart_required {
+            warn!(change = %change, "Listener change needs a restart to take effect");
+        }
+
+        for listener in &changes.removed {
+            if let Some(stop) = self.running.remove(&listener.addr) {
+                let _ = stop.send(true);
+                info!(address = %listener.addr, "Stopped listening, draining connections");
+            } else if let Some(startup) = self.startup.get(&listener.addr) {
+                startup.closed.send_replace(true);
+                info!(address = %listener.addr, "Stopped serving listener bound at startup, draining connections");
+            }
+        }
+
+        for listener in &changes.added {
+            if let Some(startup) = self.startup.get(&listener.addr) {
+                if startup.tls != listener.tls {
+                    warn!(address = %listener.addr, "Listener switches between TLS and plain HTTP, needs a restart to take effect");
+                } else {
+                    startup.closed.send_replace(false);
+                    info!(address = %listener.addr, "Serving listener bound at startup again");
+                }
+                continue;
+            }
+            if listener.tls {
+                warn!(address = %listener.addr, "New TLS listener needs a restart to take effect");
+                continue;
+            }
+            match self.add(listener).await {
+                Ok(()) => info!(address = %listener.addr, "Listening (HTTP, added on reload)"),
+                Err(e) => error!(address = %listener.addr, error = %e, "Failed to add listener"),
+            }
+        }
+    }
+
+    async fn add(&mut self, listener: &Listener) -> io::Result<()> {
+        let tcp = TcpListener::bind(&listener.addr).await?;
+        let app = Arc::new((self.new_app)(&listener.http2));
+        let (stop, stopped) = watch::channel(false);
+        tokio::spawn(accept(tcp, app, stopped));
+        self.running.insert(listener.addr.clone(), stop);
+        Ok(())
+    }
+}
+
+/// Serve connections on `listener` until told to stop
+///
+/// The stop signal doubles as the connections' shutdown watch, so they
+/// finish their current request and aren't kept alive.
+async fn accept<A>(listener: TcpListener, app: Arc<A>, mut stopped: watch::Receiver<bool>)
+where
+    A: ServerApp + Send + Sync + 'static,
+{
+    loop {
+        tokio::select! {
+            accepted = listener.accept() => match accepted {
+                Ok((tcp, peer)) => {
+                    debug!(peer = %peer, "Accepted connection on reloaded listener");
+                    let fd = tcp.as_raw_fd();
+                    let mut stream = Stream::from(tcp);
+                    stream.set_socket_digest(SocketDigest::from_raw_fd(fd));
+                    let app = app.clone();
+                    let shutdown = stopped.clone();
+                    // HTTP apps keep the connection alive themselves and never hand it back
+                    tokio::spawn(async move { app.process_new(Box::new(stream), &shutdown).await });
+                }
+                Err(e) => warn!(error = %e, "Failed to accept connection"),
+            },
+            _ = stopped.changed() => break,
+        }
+    }
+}
+
+/// App of a listener bound at startup, see [`DynamicListeners::gate_startup`]
+pub struct Gated<A> {
+    app: Arc<A>,
+    closed: watch::Receiver<bool>,
+}
+
+#[async_trait]
+impl<A: ServerApp + Send + Sync + 'static> ServerApp for Gated<A> {
+    async fn process_new(self: &Arc<Self>, stream: Stream, shutdown: &ShutdownWatch) -> Option<Stream> {
+        if *self.closed.borrow() {
+            debug!("Closing connection to a removed listener");
+            return None;
+        }
+
+        // Connections drain when the address is removed as they do on shutdown
+        let (drain, draining) = watch::channel(*shutdown.borrow());
+        let (mut shutdown, mut closed) = (shutdown.clone(), self.closed.clone());
+        let forward = tokio::spawn(async move {
+            tokio::select! {
+                Ok(_) = shutdown.wait_for(|stopping| *stopping) => {}
+                Ok(_) = closed.wait_for(|closed| *closed) => {}
+                else => return,
+            }
+            let _ = drain.send(true);
+        });
+        let reused = self.app.process_new(stream, &draining).await;
+        forward.abort();
+        reused
+    }
+
+    async fn cleanup(&self) {
+        self.app.cleanup().await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pingora::prelude::*;
+    use proxy::AvalonProxy;
+    use std::time::{Duration, Instant};
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+    use tokio::net::TcpStream;
+
+    fn load(dir: &std::path::Path, toml: &str) -> Config {
+        let path = dir.join("avalon.toml");
+        std::fs::write(&path, toml).unwrap();
+        Config::load(&path).unwrap()
+    }
+
+    fn server(name: &str, port: u16, body: &str) -> String {
+        format!(
+            r#"
+[[servers]]
+name = "{name}"
+listen = ["127.0.0.1:{port}"]
+
+[[servers.routes]]
+[servers.routes.handle]
+type = "static_response"
+body = "{body}"
+"#
+        )
+    }
+
+    fn free_port() -> u16 {
+        std::net::TcpListener::bind("127.0.0.1:0").unwrap().local_addr().unwrap().port()
+    }
+
+    async fn get(port: u16) -> io::Result<String> {
+        let mut stream = TcpStream::connect(("127.0.0.1", port)).await?;
+        stream
+            .write_all(b"GET / HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\n\r\n")
+            .await?;
+        let mut response = String::new();
+        stream.read_to_string(&mut response).await?;
+        Ok(response)
+    }
+
+    #[test]
+    fn test_listener_changes() {
+        let dir = std::env::temp_dir().join(format!("avalon-listeners-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let tls = "[tls]\nacme_enabled = false\n";
+        let old = load(&dir, &format!("{}{}", tls, server("web", 8080, "old")));
+        let new = load(
+            &dir,
+            &format!(
+                "{}{}{}\n[[servers]]\nname = \"secure\"\nlisten = [\":443\"]\n",
+                tls,
+                server("web", 8081, "new"),
+                server("api", 9090, "api")
+            ),
+        );
+
+        let changes = ListenerChanges::between(&old, &new);
+        assert_eq!(
+            changes.added.iter().map(|l| (l.addr.as_str(), l.tls)).collect::<Vec<_>>(),
+            vec![("127.0.0.1:8081", false), ("127.0.0.1:9090", false), ("0.0.0.0:443", true)]
+        );
+        assert_eq!(changes.removed.iter().map(|l| l.addr.as_str()).collect::<Vec<_>>(), vec!["127.0.0.1:8080"]);
+        assert!(changes.restart_required.is_empty());
+
+        let unchanged = ListenerChanges::between(&new, &new);
+        assert!(unchanged.added.is_empty() && unchanged.removed.is_empty());
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn test_tls_switch_needs_restart() {
+        let dir = std::env::temp_dir().join(format!("avalon-listeners-tls-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let tls = "[tls]\nacme_enabled = false\n";
+        let plain = load(&dir, &format!("{}[[servers]]\nname = \"web\"\nlisten = [\":8443\"]\n", tls));
+        let secure = load(&dir, &format!("{}[[servers]]\nname = \"web\"\nlisten = [\"https://:8443\"]\n", tls));
+
+        let changes = ListenerChanges::between(&plain, &secure);
+        assert!(changes.added.is_empty());
+        assert!(changes.removed.is_empty());
+        assert_eq!(changes.restart_required, vec!["0.0.0.0:8443 switches between TLS and plain HTTP"]);
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+
+    #[tokio::test]
+    async fn test_server_added_on_reload_serves_requests() {
+        let dir = std::env::temp_dir().join(format!("avalon-listeners-reload-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let (running_port, added_port) = (free_port(), free_port());
+        let tls = "[tls]\nacme_enabled = false\n";
+        let old = load(&dir, &format!("{}{}", tls, server("web", running_port, "web")));
+        let new = load(
+            &dir,
+            &format!("{}{}{}", tls, server("web", running_port, "web"), server("added", added_port, "added on reload")),
+        );
+
+        let server = Server::new(None).unwrap();
+        let proxy = AvalonProxy::new(old.clone(), Arc::new(Default::default())).unwrap();
+        let conf = server.configuration.clone();
+        let app_proxy = proxy.clone();
+        let mut listeners = DynamicListeners::new(move |http2| {
+            let mut app = pingora_proxy::http_proxy(&conf, app_proxy.clone());
+            app.h2_options = Some(proxy::http2::h2_options(http2));
+            app
+        });
+
+        proxy.reload_config(new.clone()).unwrap();
+        let changes = ListenerChanges::between(&old, &new);
+        assert_eq!(changes.added.iter().map(|l| l.addr.clone()).collect::<Vec<_>>(), vec![format!("127.0.0.1:{}", added_port)]);
+        listeners.apply(&changes).await;
+
+        let response = get(added_port).await.unwrap();
+        assert!(response.starts_with("HTTP/1.1 200"), "{}", response);
+        assert!(response.ends_with("added on reload"), "{}", response);
+
+        // Reloading the old config again stops the added listener
+        proxy.reload_config(old.clone()).unwrap();
+        listeners.apply(&ListenerChanges::between(&new, &old)).await;
+        let deadline = Instant::now() + Duration::from_secs(5);
+        while get(added_port).await.is_ok() {
+            assert!(Instant::now() < deadline, "listener still accepting");
+            tokio::time::sleep(Duration::from_millis(20)).await;
+        }
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+
+    #[tokio::test]
+    async fn test_startup_listener_removed_and_listed_again() {
+        use pingora_core::services::Service as _;
+
+        let dir = std::env::temp_dir().join(format!("avalon-listeners-startup-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let (kept_port, removed_port) = (free_port(), free_port());
+        let tls = "[tls]\nacme_enabled = false\n";
+        let old = load(
+            &dir,
+            &format!("{}{}{}", tls, server("kept", kept_port, "kept"), server("removed", removed_port, "bound at startup")),
+        );
+        let new = load(&dir, &format!("{}{}", tls, server("kept", kept_port, "kept")));
+
+        let server = Server::new(None).unwrap();
+        let proxy = AvalonProxy::new(old.clone(), Arc::new(Default::default())).unwrap();
+        let conf = server.configuration.clone();
+        let app_proxy = proxy.clone();
+        let mut listeners = DynamicListeners::new(move |_: &Http2Config| pingora_proxy::http_proxy(&conf, app_proxy.clone()));
+
+        // Bound by Pingora, as main does at startup
+        let addr = format!("127.0.0.1:{}", removed_port);
+        let app = pingora_proxy::http_proxy(&server.configuration, proxy.clone());
+        let mut service = pingora_core::services::listening::Service::new("startup".to_string(), listeners.gate_startup(&addr, false, app));
+        service.add_tcp(&addr);
+        let (_shutdown, watch) = watch::channel(false);
+        tokio::spawn(async move { service.start_service(None, watch, 1).await });
+        let deadline = Instant::now() + Duration::from_secs(5);
+        while get(removed_port).await.is_err() {
+            assert!(Instant::now() < deadline, "startup listener not accepting");
+            tokio::time::sleep(Duration::from_millis(20)).await;
+        }
+        assert!(get(removed_port).await.unwrap().ends_with("bound at startup"));
+
+        proxy.reload_config(new.clone()).unwrap();
+        listeners.apply(&ListenerChanges::between(&old, &new)).await;
+        assert_eq!(get(removed_port).await.unwrap_or_default(), "");
+
+        // Listing it again reopens the listener instead of binding the address twice
+        proxy.reload_config(old.clone()).unwrap();
+        let changes = ListenerChanges::between(&new, &old);
+        assert_eq!(changes.added.iter().map(|l| l.addr.clone()).collect::<Vec<_>>(), vec![addr]);
+        listeners.apply(&changes).await;
+        assert!(listeners.running.is_empty());
+        let response = get(removed_port).await.unwrap();
+        assert!(response.ends_with("bound at startup"), "{}", response);
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+}
@@ -4,25 +4,41 @@
 //! reverse proxying with automatic HTTPS via Let's Encrypt.
 
 use anyhow::{Context, Result};
-use config::{Config, HandlerConfig};
-use proxy::{AvalonProxy, HealthCheckConfig, HealthChecker, wait_for_connections_drain};
+use config::{Config, ExportFormat, HandlerConfig, Http2Config, StorageConfig};
+use proxy::{AvalonProxy, ConnectionGuard, ConnectionLimits, ConnectionTracker, HealthCheckConfig, HealthChecker, metrics, run_preflight};
 use tls::{
-    AcmeManager, CertStorage, RenewalScheduler, SniResolver, auto_select_certificate,
-    get_acme_ca_name, load_all_certs, resolve_acme_ca, shutdown_channel,
+    AcmeManager, CertStorage, HandshakeFailure, RedisBackend, RenewalScheduler, S3Backend, S3Settings, SniResolver,
+    StorageBackend, auto_select_certificate, get_acme_ca_name, load_all_certs, resolve_acme_ca,
+    shutdown_channel,
 };
 use clap::{Parser, Subcommand};
 use notify::{RecommendedWatcher, RecursiveMode, Watcher, Config as NotifyConfig};
 use pingora::prelude::*;
 use pingora_core::listeners::tls::TlsSettings;
-use pingora_proxy::http_proxy_service;
+use pingora_core::server::configuration::Opt;
+use pingora_core::server::RunArgs;
+use pingora_core::services::listening::Service;
+use pingora_proxy::http_proxy;
+use handshake_log::HandshakeFailureLayer;
+use listeners::{bind_address, DynamicListeners, ListenerChanges};
+use shutdown::ShutdownSignals;
+use socket_activation::ActivatedListener;
+use std::collections::HashMap;
 use std::path::PathBuf;
 use std::sync::Arc;
 use std::sync::mpsc::channel;
 use std::time::Duration;
 use tracing::{info, warn, error, Level};
 use tracing_subscriber::FmtSubscriber;
+use tracing_subscriber::layer::SubscriberExt;
+use tracing_subscriber::util::SubscriberInitExt;
 
+mod handshake_log;
+mod listeners;
+mod shutdown;
+mod socket_activation;
 mod telemetry;
+mod unix_socket;
 
 #[derive(Parser)]
 #[command(name = "avalon")]
@@ -62,6 +78,20 @@ enum Commands {
     Validate {
         #[arg(short, long, default_value = "caddy.toml")]
         config: PathBuf,
+        /// Also check upstream reachability, certificate files and DNS for TLS domains
+        #[arg(long, alias = "check-upstreams")]
+        strict: bool,
+    },
+    /// Print the fully resolved configuration, with all defaults filled in
+    Export {
+        #[arg(short, long, default_value = "caddy.toml")]
+        config: PathBuf,
+        /// Output format: toml or json
+        #[arg(short, long, default_value = "toml")]
+        format: String,
+        /// Replace passwords, tokens and other secrets with a placeholder
+        #[arg(long)]
+        redact: bool,
     },
 }
 
@@ -83,20 +113,24 @@ fn main() -> Result<()> {
         _ => Level::INFO,
     };
 
-    let subscriber = FmtSubscriber::builder()
+    // Also bridges `log` records, which is how Pingora reports failed handshakes
+    FmtSubscriber::builder()
         .with_max_level(level)
         .with_target(false)
-        .finish();
-
-    tracing::subscriber::set_global_default(subscriber)
+        .finish()
+        .with(HandshakeFailureLayer::new(Arc::new(|failure: &HandshakeFailure| {
+            metrics().tls_errors.inc(failure.reason.as_str())
+        })))
+        .try_init()
         .context("Failed to set tracing subscriber")?;
 
     match cli.command {
-        Some(Commands::Validate { config }) => validate_config(config),
+        Some(Commands::Validate { config, strict }) => validate_config(config, strict),
         Some(Commands::Run { config, watch }) => run_server(config, watch),
+        Some(Commands::Export { config, format, redact }) => export_config(config, &format, redact),
         None => {
             if cli.test {
-                validate_config(cli.config)
+                validate_config(cli.config, false)
             } else {
                 run_server(cli.config, cli.watch)
             }
@@ -104,6 +138,35 @@ fn main() -> Result<()> {
     }
 }
 
+/// Build a shared certificate storage backend, or None for the local directory
+fn storage_backend(storage: &StorageConfig) -> Result<Option<Arc<dyn StorageBackend>>> {
+    match storage {
+        StorageConfig::File => Ok(None),
+        StorageConfig::Redis(redis) => {
+            let backend = RedisBackend::new(&redis.url, &redis.prefix)?;
+            Ok(Some(Arc::new(backend)))
+        }
+        StorageConfig::S3(s3) => {
+            let access_key = s3.access_key.clone()
+                .or_else(|| std::env::var("AWS_ACCESS_KEY_ID").ok())
+                .context("S3 storage requires access_key or AWS_ACCESS_KEY_ID")?;
+            let secret_key = s3.secret_key.clone()
+                .or_else(|| std::env::var("AWS_SECRET_ACCESS_KEY").ok())
+                .context("S3 storage requires secret_key or AWS_SECRET_ACCESS_KEY")?;
+
+            Ok(Some(Arc::new(S3Backend::new(S3Settings {
+                endpoint: s3.endpoint.clone(),
+                bucket: s3.bucket.clone(),
+                region: s3.region.clone(),
+                prefix: s3.prefix.clone(),
+                access_key,
+                secret_key,
+                path_style: s3.path_style,
+            }))))
+        }
+    }
+}
+
 #[allow(unreachable_code)]
 fn run_server(config_path: PathBuf, watch_config: bool) -> Result<()> {
     info!("Starting avalon");
@@ -119,8 +182,18 @@ fn run_server(config_path: PathBuf, watch_config: bool) -> Result<()> {
 
     // Initialize certificate storage
     let rt = tokio::runtime::Runtime::new()?;
+
+    // Export "otlp" access logs; the batch exporter runs on this runtime
+    let access_log_provider = {
+        let _enter = rt.enter();
+        telemetry::init_access_log_export(&config)
+    };
+    let backend = storage_backend(&config.tls.storage)?;
     let storage = rt.block_on(async {
-        CertStorage::new(&config.tls.storage_path).await
+        match backend {
+            Some(backend) => CertStorage::with_backend(&config.tls.storage_path, backend).await,
+            None => CertStorage::new(&config.tls.storage_path).await,
+        }
     }).context("Failed to initialize certificate storage")?;
     let storage = Arc::new(storage);
 
@@ -134,7 +207,8 @@ fn run_server(config_path: PathBuf, watch_config: bool) -> Result<()> {
         acme_ca,
         config.tls.email.clone(),
         storage.clone(),
-    );
+    )
+    .with_must_staple(config.tls.must_staple);
 
     // Check if we have valid certificates (don't obtain yet - server needs to be running first)
     let mut needs_cert = Vec::new();
@@ -149,6 +223,10 @@ fn run_server(config_path: PathBuf, watch_config: bool) -> Result<()> {
                         Ok(Some(bundle)) if !bundle.expires_within_days(30) => {
                             info!(domain = %domain, "Certificate valid");
                         }
+                        Err(e) if e.is_corrupt_cert() => {
+                            warn!(domain = %domain, error = %e, "Stored certificate is corrupt, re-obtaining");
+                            needs_cert.push(domain.clone());
+                        }
                         _ => {
                             info!(domain = %domain, "Certificate needed");
                             needs_cert.push(domain.clone());
@@ -159,17 +237,34 @@ fn run_server(config_path: PathBuf, watch_config: bool) -> Result<()> {
         }
     }
 
-    // Create Pingora server
-    let mut server = Server::new(None).context("Failed to create Pingora server")?;
-    server.bootstrap();
+    // Adopt socket-activated listeners ("fd:N") passed by systemd
+    let activated = socket_activated_listeners(&config)?;
 
-    // Create proxy service
-    let proxy = AvalonProxy::new(config.clone(), acme_manager.challenge_tokens())
-        .context("Failed to create proxy")?;
+    // Create Pingora server; inherited sockets arrive through its upgrade path
+    let opt = (!activated.is_empty()).then(|| Opt { upgrade: true, ..Default::default() });
+    let mut server = Server::new(opt).context("Failed to create Pingora server")?;
+    if let Some(conf) = Arc::get_mut(&mut server.configuration) {
+        shutdown::configure_server(conf, &config.global);
+    }
+    if !activated.is_empty() {
+        let upgrade_sock = PathBuf::from(&server.configuration.upgrade_sock);
+        let listeners: Vec<_> = activated.values().cloned().collect();
+        std::thread::spawn(move || {
+            if let Err(e) = socket_activation::send_to_upgrade_socket(&upgrade_sock, &listeners) {
+                error!(error = %e, "Failed to hand over socket-activated listeners");
+            }
+        });
+    }
+    server.bootstrap();
 
     // Setup SNI resolver for multi-domain TLS support
     let domains = config.get_tls_domains();
-    let sni_resolver = Arc::new(SniResolver::new());
+    let sni_resolver = Arc::new(SniResolver::new().with_reject_unknown_sni(config.tls.reject_unknown_sni));
+
+    // Create proxy service
+    let proxy = AvalonProxy::new(config.clone(), acme_manager.challenge_tokens())
+        .context("Failed to create proxy")?
+        .with_certificates(sni_resolver.clone(), storage.clone());
 
     info!(domains = ?domains, storage_path = ?config.tls.storage_path, "Setting up SNI resolver");
     if !domains.is_empty() {
@@ -178,28 +273,67 @@ fn run_server(config_path: PathBuf, watch_config: bool) -> Result<()> {
             if let Err(e) = load_all_certs(&sni_resolver, &storage, &domains, &config.tls.storage_path).await {
                 warn!(error = %e, "Failed to load some certificates for SNI");
             }
+            sni_resolver.refresh_staples().await;
         });
         info!(loaded_count = sni_resolver.domain_count(), "SNI certificates loaded");
     }
 
-    // Add listeners
+    // Add listeners; header timeouts and the connection cap apply to all of them
+    let limits = ConnectionLimits::from_config(&config.global);
+    let connections = Arc::new(ConnectionTracker::new());
+
+    // Builds the apps serving listen addresses added by config reloads
+    let reload_conf = server.configuration.clone();
+    let reload_proxy = proxy.clone();
+    let reload_limits = limits.clone();
+    let reload_connections = connections.clone();
+    let mut listeners = DynamicListeners::new(move |http2: &Http2Config| {
+        let mut app = http_proxy(&reload_conf, reload_proxy.clone());
+        app.h2_options = Some(proxy::http2::h2_options(http2));
+        ConnectionGuard::new(app, reload_limits.clone(), reload_connections.clone())
+    });
+
+    let mut unix_sockets = Vec::new();
     for server_config in &config.servers {
         for listen_addr in &server_config.listen {
-            let mut service = http_proxy_service(&server.configuration, proxy.clone());
+            let mut app = http_proxy(&server.configuration, proxy.clone());
+            app.h2_options = Some(proxy::http2::h2_options(&server_config.http2));
+            // Reloads close the listener while its address is gone from the config
+            let app = listeners.gate_startup(
+                &bind_address(listen_addr),
+                is_tls_address(listen_addr),
+                ConnectionGuard::new(app, limits.clone(), connections.clone()),
+            );
+            let mut service = Service::new("Pingora HTTP Proxy Service".to_string(), app);
+
+            if let Some(path) = config::unix_socket_path(listen_addr) {
+                unix_socket::add_unix_listener(&mut service, path, server_config.unix_socket_mode);
+                unix_sockets.push(path.to_path_buf());
+                info!(path = ?path, server = %server_config.name, "Listening (HTTP on Unix socket)");
+                server.add_service(service);
+                continue;
+            }
 
-            let addr = if listen_addr.starts_with(':') {
+            let addr = if let Some(listener) = activated.get(listen_addr) {
+                listener.addr.to_string()
+            } else if listen_addr.starts_with(':') {
                 format!("0.0.0.0{}", listen_addr)
             } else {
                 listen_addr.clone()
             };
 
             // Check for TLS listener
-            if is_tls_address(listen_addr) {
+            if is_tls_address(&addr) {
                 // Use SNI-based TLS if we have multiple domains or if callbacks are preferred
                 if sni_resolver.domain_count() > 0 {
                     // Use SNI callback for multi-certificate support
                     match TlsSettings::with_callbacks(Box::new(sni_resolver.as_ref().clone())) {
-                        Ok(tls_settings) => {
+                        Ok(mut tls_settings) => {
+                            // Send the staple set by the certificate callback when the client asks for one
+                            if let Err(e) = tls_settings.set_status_callback(|ssl| Ok(ssl.ocsp_status().is_some())) {
+                                warn!(address = %addr, error = %e, "Failed to enable OCSP stapling");
+                            }
+                            request_client_certs(&mut tls_settings, &addr, &config);
                             service.add_tls_with_settings(&addr, None, tls_settings);
                             info!(
                                 address = %addr,
@@ -226,9 +360,13 @@ fn run_server(config_path: PathBuf, watch_config: bool) -> Result<()> {
         }
     }
 
+
     // Start health checkers
     start_health_checkers(&config, &proxy);
 
+    // Push metrics to StatsD when configured
+    proxy::statsd::start(&config.global.metrics);
+
     // Create shutdown channel for graceful shutdown
     let (shutdown_tx, shutdown_rx) = shutdown_channel();
 
@@ -288,45 +426,75 @@ fn run_server(config_path: PathBuf, watch_config: bool) -> Result<()> {
         });
     }
 
-    // Setup signal handler for graceful shutdown
+    // SIGTERM and SIGINT shut down gracefully, see `shutdown`
+    let lifecycle = proxy.lifecycle();
     let shutdown_tx_clone = shutdown_tx.clone();
+    // Notify other components (renewal scheduler, etc.) to stop
+    lifecycle.on_shutdown(move || {
+        let _ = shutdown_tx_clone.send(true);
+    });
+    let pre_drain_delay = Duration::from_secs(config.global.pre_drain_delay);
     let grace_period = Duration::from_secs(config.global.grace_period);
-    ctrlc::set_handler(move || {
-        info!("Received shutdown signal, initiating graceful shutdown...");
+    let drain_timeout = Duration::from_secs(config.global.drain_timeout());
+    let shutdown_signals = ShutdownSignals::new(lifecycle, pre_drain_delay, move || {
+        // Wait for connections to drain, closing any left at the drain timeout
+        connections.drain(grace_period, drain_timeout);
 
-        // Notify other components (renewal scheduler, etc.) to stop
-        let _ = shutdown_tx_clone.send(true);
+        // Exit once drained rather than waiting out Pingora's own grace period
+        info!("Shutdown complete");
+        std::process::exit(0);
+    })
+    .with_socket_files(unix_sockets);
 
-        // Wait for active connections to drain (with timeout)
-        info!(grace_period_secs = grace_period.as_secs(), "Waiting for connections to drain...");
-        if wait_for_connections_drain(grace_period) {
-            info!("All connections drained successfully");
-        } else {
-            warn!("Grace period expired, some connections may be terminated");
+    // Reopen access log files on SIGHUP so logrotate can rename them
+    rt.spawn(async {
+        use tokio::signal::unix::{signal, SignalKind};
+
+        let mut hangup = match signal(SignalKind::hangup()) {
+            Ok(hangup) => hangup,
+            Err(e) => {
+                warn!(error = %e, "Failed to install SIGHUP handler, access logs won't be reopened");
+                return;
+            }
+        };
+        while hangup.recv().await.is_some() {
+            info!("Received SIGHUP, reopening access logs");
+            proxy::access_log::reopen_all();
         }
+    });
 
-        // Exit the process after draining (Pingora will also handle its own cleanup)
-        info!("Shutdown complete");
-        std::process::exit(0);
-    }).ok();
+    // Reload certificates renewed out of band on SIGUSR1, leaving routes as they are
+    let proxy_for_certs = proxy.clone();
+    rt.spawn(async move {
+        use tokio::signal::unix::{signal, SignalKind};
+
+        let mut usr1 = match signal(SignalKind::user_defined1()) {
+            Ok(usr1) => usr1,
+            Err(e) => {
+                warn!(error = %e, "Failed to install SIGUSR1 handler, certificates won't be reloaded on signal");
+                return;
+            }
+        };
+        while usr1.recv().await.is_some() {
+            info!("Received SIGUSR1, reloading TLS certificates");
+            if let Err(e) = proxy_for_certs.reload_certs().await {
+                error!(error = %e, "Failed to reload TLS certificates");
+            }
+        }
+    });
 
     // Store telemetry provider to keep it alive for the duration of the server
     // It will be automatically shut down when the process exits
     let _telemetry_guard = telemetry_provider;
+    let _access_log_guard = access_log_provider;
 
     // Start config file watcher if enabled
     if watch_config {
         let proxy_for_reload = proxy.clone();
         let config_path_for_watch = config_path.clone();
-        let sni_resolver_for_watch = sni_resolver.clone();
-        let storage_for_watch = storage.clone();
+        let config_for_watch = config.clone();
         std::thread::spawn(move || {
-            if let Err(e) = start_config_watcher(
-                config_path_for_watch,
-                proxy_for_reload,
-                sni_resolver_for_watch,
-                storage_for_watch,
-            ) {
+            if let Err(e) = start_config_watcher(config_path_for_watch, proxy_for_reload, config_for_watch, listeners) {
                 error!(error = %e, "Config watcher failed");
             }
         });
@@ -334,11 +502,33 @@ fn run_server(config_path: PathBuf, watch_config: bool) -> Result<()> {
     }
 
     info!("avalon started successfully");
-    server.run_forever();
+    server.run(RunArgs { shutdown_signal: Box::new(shutdown_signals) });
 
     Ok(())
 }
 
+/// Resolve `fd:N` listen addresses to the inherited sockets they name
+fn socket_activated_listeners(config: &Config) -> Result<HashMap<String, ActivatedListener>> {
+    let inherited = socket_activation::inherited_fds();
+    let mut activated = HashMap::new();
+
+    for listen_addr in config.servers.iter().flat_map(|s| &s.listen) {
+        let Some(fd) = socket_activation::parse_fd_address(listen_addr) else {
+            continue;
+        };
+        if !inherited.contains(&fd) {
+            warn!(fd = fd, "Listener fd was not passed via LISTEN_FDS");
+        }
+
+        let listener = socket_activation::adopt_fd(fd)
+            .with_context(|| format!("Invalid socket-activated listener {}", listen_addr))?;
+        info!(fd = fd, address = %listener.addr, "Adopting socket-activated listener");
+        activated.insert(listen_addr.clone(), listener);
+    }
+
+    Ok(activated)
+}
+
 fn is_tls_address(addr: &str) -> bool {
     addr.contains(":443") || addr.starts_with("https://")
 }
@@ -359,8 +549,10 @@ fn fallback_single_cert_tls<A>(
         let cert_str = cert_path.to_str().unwrap_or("");
         let key_str = key_path.to_str().unwrap_or("");
 
-        match service.add_tls(addr, cert_str, key_str) {
-            Ok(_) => {
+        match TlsSettings::intermediate(cert_str, key_str) {
+            Ok(mut tls_settings) => {
+                request_client_certs(&mut tls_settings, addr, config);
+                service.add_tls_with_settings(addr, None, tls_settings);
                 info!(address = %addr, domain = %first_domain, "Listening (HTTPS)");
             }
             Err(e) => {
@@ -376,6 +568,18 @@ fn fallback_single_cert_tls<A>(
     }
 }
 
+/// Verify client certificates on a TLS listener when `tls.client_ca` is set
+///
+/// On failure the listener still serves TLS, but no connection carries a
+/// client certificate, so `match.client_cert` routes stay unreachable.
+fn request_client_certs(tls_settings: &mut TlsSettings, addr: &str, config: &Config) {
+    if let Some(client_ca) = &config.tls.client_ca {
+        if let Err(e) = tls::request_client_certs(tls_settings, client_ca) {
+            error!(address = %addr, client_ca = ?client_ca, error = %e, "Failed to enable client certificate verification");
+        }
+    }
+}
+
 fn get_tls_cert_paths(
     tls_config: &config::TlsConfig,
     domain: &str,
@@ -477,7 +681,7 @@ fn start_health_checkers(config: &Config, proxy: &AvalonProxy) {
     }
 }
 
-fn validate_config(config_path: PathBuf) -> Result<()> {
+fn validate_config(config_path: PathBuf, strict: bool) -> Result<()> {
     let config = Config::load(&config_path)
         .with_context(|| format!("Failed to load config from {:?}", config_path))?;
 
@@ -496,15 +700,48 @@ fn validate_config(config_path: PathBuf) -> Result<()> {
         println!("  TLS domains: {:?}", domains);
     }
 
+    if strict {
+        println!("Running pre-flight checks...");
+        let checks = run_preflight(&config, Duration::from_secs(3));
+        let failed = checks.iter().filter(|c| !c.passed()).count();
+
+        for check in &checks {
+            match &check.error {
+                None => println!("  ok    {}", check.subject),
+                Some(e) => println!("  FAIL  {}: {}", check.subject, e),
+            }
+        }
+
+        if failed > 0 {
+            anyhow::bail!("{} of {} pre-flight checks failed", failed, checks.len());
+        }
+    }
+
     Ok(())
 }
 
-fn start_config_watcher(
+fn export_config(config_path: PathBuf, format: &str, redact: bool) -> Result<()> {
+    let format = match format.to_ascii_lowercase().as_str() {
+        "toml" => ExportFormat::Toml,
+        "json" => ExportFormat::Json,
+        other => anyhow::bail!("Unknown export format '{}', expected toml or json", other),
+    };
+    let config = Config::load(&config_path)
+        .with_context(|| format!("Failed to load config from {:?}", config_path))?;
+
+    println!("{}", config.export(format, redact).context("Failed to export config")?);
+    Ok(())
+}
+
+fn start_config_watcher<A>(
     config_path: PathBuf,
     proxy: AvalonProxy,
-    sni_resolver: Arc<SniResolver>,
-    storage: Arc<CertStorage>,
-) -> Result<()> {
+    mut current: Config,
+    mut listeners: DynamicListeners<A>,
+) -> Result<()>
+where
+    A: pingora_core::apps::ServerApp + Send + Sync + 'static,
+{
     use notify::event::{EventKind, ModifyKind};
 
     let (tx, rx) = channel();
@@ -523,7 +760,7 @@ fn start_config_watcher(
     let mut last_reload = std::time::Instant::now();
     let debounce_duration = Duration::from_millis(500);
 
-    // Create a tokio runtime for async cert loading
+    // Create a tokio runtime for async cert loading and reloaded listeners
     let rt = tokio::runtime::Runtime::new()
         .context("Failed to create tokio runtime for config watcher")?;
 
@@ -553,7 +790,12 @@ fn start_config_watcher(
                         Ok(new_config) => {
                             // Reload proxy configuration
                             match proxy.reload_config(new_config.clone()) {
-                                Ok(_) => info!("Proxy configuration reloaded successfully"),
+                                Ok(_) => {
+                                    info!("Proxy configuration reloaded successfully");
+                                    let changes = ListenerChanges::between(&current, &new_config);
+                                    rt.block_on(listeners.apply(&changes));
+                                    current = new_config.clone();
+                                }
                                 Err(e) => error!(error = %e, "Failed to apply new configuration"),
                             }
 
@@ -561,9 +803,9 @@ fn start_config_watcher(
                             let domains = new_config.get_tls_domains();
                             if !domains.is_empty() {
                                 info!(domains = ?domains, "Reloading TLS certificates...");
-                                rt.block_on(async {
-                                    reload_certificates(&sni_resolver, &storage, &domains).await;
-                                });
+                                if let Err(e) = rt.block_on(proxy.reload_certs()) {
+                                    error!(error = %e, "Failed to reload TLS certificates");
+                                }
                             }
                         }
                         Err(e) => {
@@ -584,36 +826,3 @@ fn start_config_watcher(
 
     Ok(())
 }
-
-/// Reload certificates from storage into the SNI resolver
-async fn reload_certificates(
-    sni_resolver: &SniResolver,
-    storage: &CertStorage,
-    domains: &[String],
-) {
-    for domain in domains {
-        match storage.load_cert(domain).await {
-            Ok(Some(bundle)) => {
-                match SniResolver::load_from_pem(
-                    bundle.certificate_pem.as_bytes(),
-                    bundle.private_key_pem.as_bytes(),
-                ) {
-                    Ok(pair) => {
-                        sni_resolver.add_cert(domain, pair);
-                        info!(domain = %domain, "Certificate reloaded");
-                    }
-                    Err(e) => {
-                        error!(domain = %domain, error = %e, "Failed to parse certificate");
-                    }
-                }
-            }
-            Ok(None) => {
-                warn!(domain = %domain, "No certificate found in storage");
-            }
-            Err(e) => {
-                error!(domain = %domain, error = %e, "Failed to load certificate");
-            }
-        }
-    }
-    info!("TLS certificates reload completed");
-}
@@ -0,0 +1,137 @@
+//! Shutdown signal handling
+//!
+//! Takes the place of Pingora's own signal watcher so that SIGTERM, the
+//! signal orchestrators send, goes through the same lame-duck phase as
+//! SIGINT: `/ready` answers 503 for `pre_drain_delay` while requests are
+//! still served, then Pingora stops accepting connections, the `unix:`
+//! socket files are removed and the drain runs on its own thread. SIGQUIT
+//! keeps Pingora's graceful upgrade, which hands the listening sockets (and
+//! with them the socket files) to a new process.
+//!
+//! Pingora's own grace period is lined up with `drain_timeout`, so it
+//! neither cuts a drain short nor outlasts it.
+
+use async_trait::async_trait;
+use config::GlobalConfig;
+use pingora_core::server::configuration::ServerConf;
+use pingora_core::server::{ShutdownSignal, ShutdownSignalWatch};
+use proxy::Lifecycle;
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::signal::unix::{signal, SignalKind};
+use tracing::info;
+
+type Drain = Arc<dyn Fn() + Send + Sync>;
+
+/// Seconds Pingora gives its runtimes to stop after the grace period
+const RUNTIME_SHUTDOWN_TIMEOUT: u64 = 1;
+
+/// Set Pingora's shutdown timing from the drain settings
+///
+/// Pingora waits `grace_period_seconds` after it stops accepting, then
+/// drops whatever is still running. The drain exits the process once
+/// connections are gone and closes the rest at `drain_timeout`, so that is
+/// also the longest Pingora waits, including after a graceful upgrade.
+pub fn configure_server(conf: &mut ServerConf, global: &GlobalConfig) {
+    conf.grace_period_seconds = Some(global.drain_timeout());
+    conf.graceful_shutdown_timeout_seconds = Some(RUNTIME_SHUTDOWN_TIMEOUT);
+}
+
+/// Shutdown signal watcher passed to `Server::run`
+pub struct ShutdownSignals {
+    lifecycle: Arc<Lifecycle>,
+    pre_drain_delay: Duration,
+    drain: Drain,
+    socket_files: Vec<PathBuf>,
+}
+
+impl ShutdownSignals {
+    /// `drain` runs once Pingora has been told to stop accepting connections
+    pub fn new(lifecycle: Arc<Lifecycle>, pre_drain_delay: Duration, drain: impl Fn() + Send + Sync + 'static) -> Self {
+        Self { lifecycle, pre_drain_delay, drain: Arc::new(drain), socket_files: Vec::new() }
+    }
+
+    /// Remove the socket files of `unix:` listeners when they stop accepting
+    pub fn with_socket_files(mut self, paths: Vec<PathBuf>) -> Self {
+        self.socket_files = paths;
+        self
+    }
+}
+
+#[async_trait]
+impl ShutdownSignalWatch for ShutdownSignals {
+    async fn recv(&self) -> ShutdownSignal {
+        let mut terminate = signal(SignalKind::terminate()).expect("Failed to install SIGTERM handler");
+        let mut interrupt = signal(SignalKind::interrupt()).expect("Failed to install SIGINT handler");
+        let mut quit = signal(SignalKind::quit()).expect("Failed to install SIGQUIT handler");
+
+        tokio::select! {
+            _ = terminate.recv() => info!("Received SIGTERM, initiating graceful shutdown..."),
+            _ = interrupt.recv() => info!("Received SIGINT, initiating graceful shutdown..."),
+            _ = quit.recv() => {
+                info!("Received SIGQUIT, handing listeners over to the new process");
+                return ShutdownSignal::GracefulUpgrade;
+            }
+        }
+
+        // Report not ready while still serving
+        let lifecycle = self.lifecycle.clone();
+        let pre_drain_delay = self.pre_drain_delay;
+        let _ = tokio::task::spawn_blocking(move || lifecycle.shutdown(pre_drain_delay, || ())).await;
+
+        // Pingora stops accepting and ends keep-alive once this returns
+        crate::unix_socket::remove_socket_files(&self.socket_files);
+        let drain = self.drain.clone();
+        std::thread::spawn(move || drain());
+        ShutdownSignal::GracefulTerminate
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::mpsc;
+    use std::time::Instant;
+
+    #[test]
+    fn test_pingora_waits_out_the_drain_timeout() {
+        let global = GlobalConfig { grace_period: 10, drain_timeout: Some(45), ..Default::default() };
+        let mut conf = ServerConf::default();
+        configure_server(&mut conf, &global);
+        assert_eq!(conf.grace_period_seconds, Some(45));
+        assert_eq!(conf.graceful_shutdown_timeout_seconds, Some(RUNTIME_SHUTDOWN_TIMEOUT));
+    }
+
+    #[tokio::test]
+    async fn test_sigterm_reports_not_ready_then_drains() {
+        let socket = std::env::temp_dir().join(format!("avalon-shutdown-{}.sock", std::process::id()));
+        drop(std::os::unix::net::UnixListener::bind(&socket).unwrap());
+
+        let lifecycle = Arc::new(Lifecycle::new());
+        let (drained_tx, drained_rx) = mpsc::channel();
+        let signals = ShutdownSignals::new(lifecycle.clone(), Duration::from_millis(300), move || {
+            drained_tx.send(Instant::now()).unwrap();
+        })
+        .with_socket_files(vec![socket.clone()]);
+        let watching = tokio::spawn(async move { signals.recv().await });
+
+        // Give the watcher time to install its handlers before signalling ourselves
+        tokio::time::sleep(Duration::from_millis(100)).await;
+        assert!(!lifecycle.is_shutting_down());
+        let sent = Instant::now();
+        unsafe { libc::kill(libc::getpid(), libc::SIGTERM) };
+
+        // Not ready at once, but no draining during the pre-drain delay
+        tokio::time::sleep(Duration::from_millis(100)).await;
+        assert!(lifecycle.is_shutting_down());
+        assert!(drained_rx.try_recv().is_err());
+        assert!(socket.exists());
+
+        let signal = tokio::time::timeout(Duration::from_secs(5), watching).await.unwrap().unwrap();
+        assert!(matches!(signal, ShutdownSignal::GracefulTerminate));
+        let drained_at = drained_rx.recv_timeout(Duration::from_secs(2)).unwrap();
+        assert!(drained_at.duration_since(sent) >= Duration::from_millis(300));
+        assert!(!socket.exists());
+    }
+}
@@ -0,0 +1,232 @@
+//! systemd socket activation
+//!
+//! A listen address of `fd:N` adopts a listening socket passed by systemd
+//! (`LISTEN_PID`/`LISTEN_FDS`) instead of binding a new one. Pingora binds
+//! listeners itself, so inherited sockets are handed to it through its
+//! graceful-upgrade channel: avalon starts in upgrade mode and sends the
+//! sockets, keyed by their bound address, to the upgrade socket.
+
+use anyhow::{Context, Result, bail};
+use std::io;
+use std::mem::ManuallyDrop;
+use std::net::{SocketAddr, TcpListener};
+use std::os::unix::io::{AsRawFd, FromRawFd, RawFd};
+use std::os::unix::net::UnixStream;
+use std::path::Path;
+use std::time::{Duration, Instant};
+use tracing::{debug, info};
+
+/// First descriptor passed by systemd (SD_LISTEN_FDS_START)
+const LISTEN_FDS_START: RawFd = 3;
+
+/// How long to wait for Pingora to open the upgrade socket
+const HANDOFF_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// An inherited listening socket
+#[derive(Debug, Clone, PartialEq)]
+pub struct ActivatedListener {
+    pub fd: RawFd,
+    /// Address the socket is bound to, used as the Pingora listener key
+    pub addr: SocketAddr,
+}
+
+/// Parse an `fd:N` listen address
+pub fn parse_fd_address(listen: &str) -> Option<RawFd> {
+    listen.strip_prefix("fd:")?.trim().parse().ok()
+}
+
+/// Descriptors systemd passed to this process
+///
+/// Empty unless `LISTEN_PID` names this process. The variables are cleared
+/// so child processes don't try to adopt the same sockets.
+pub fn inherited_fds() -> Vec<RawFd> {
+    let pid_matches = std::env::var("LISTEN_PID")
+        .ok()
+        .and_then(|pid| pid.parse::<u32>().ok())
+        .is_some_and(|pid| pid == std::process::id());
+    let count = std::env::var("LISTEN_FDS")
+        .ok()
+        .and_then(|n| n.parse::<RawFd>().ok())
+        .unwrap_or(0);
+
+    std::env::remove_var("LISTEN_PID");
+    std::env::remove_var("LISTEN_FDS");
+    std::env::remove_var("LISTEN_FDNAMES");
+
+    if !pid_matches {
+        return Vec::new();
+    }
+    (LISTEN_FDS_START..LISTEN_FDS_START + count.max(0)).collect()
+}
+
+/// Validate that `fd` is a listening TCP socket and return its address
+pub fn adopt_fd(fd: RawFd) -> Result<ActivatedListener> {
+    if getsockopt_int(fd, libc::SO_TYPE).with_context(|| format!("fd {} is not a socket", fd))?
+        != libc::SOCK_STREAM
+    {
+        bail!("fd {} is not a stream socket", fd);
+    }
+    if getsockopt_int(fd, libc::SO_ACCEPTCONN)? == 0 {
+        bail!("fd {} is not a listening socket", fd);
+    }
+
+    // Borrow the descriptor without taking ownership
+    let listener = ManuallyDrop::new(unsafe { TcpListener::from_raw_fd(fd) });
+    let addr = listener
+        .local_addr()
+        .with_context(|| format!("fd {} is not a TCP socket", fd))?;
+
+    Ok(ActivatedListener { fd, addr })
+}
+
+fn getsockopt_int(fd: RawFd, option: libc::c_int) -> io::Result<libc::c_int> {
+    let mut value: libc::c_int = 0;
+    let mut len = std::mem::size_of::<libc::c_int>() as libc::socklen_t;
+    let ret = unsafe {
+        libc::getsockopt(
+            fd,
+            libc::SOL_SOCKET,
+            option,
+            &mut value as *mut libc::c_int as *mut libc::c_void,
+            &mut len,
+        )
+    };
+    if ret < 0 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(value)
+}
+
+/// Hand inherited sockets to Pingora through its upgrade socket
+///
+/// Pingora expects the bound addresses as a space-separated payload with the
+/// descriptors attached, the same message an old process sends during a
+/// graceful upgrade. Blocks until Pingora accepts the connection.
+pub fn send_to_upgrade_socket(path: &Path, listeners: &[ActivatedListener]) -> Result<()> {
+    let deadline = Instant::now() + HANDOFF_TIMEOUT;
+    let stream = loop {
+        match UnixStream::connect(path) {
+            Ok(stream) => break stream,
+            Err(e) if Instant::now() < deadline => {
+                debug!(path = ?path, error = %e, "Waiting for upgrade socket");
+                std::thread::sleep(Duration::from_millis(100));
+            }
+            Err(e) => return Err(e).with_context(|| format!("Failed to connect to {:?}", path)),
+        }
+    };
+
+    let payload = listeners
+        .iter()
+        .map(|l| l.addr.to_string())
+        .collect::<Vec<_>>()
+        .join(" ");
+    let fds: Vec<RawFd> = listeners.iter().map(|l| l.fd).collect();
+    send_fds(&stream, payload.as_bytes(), &fds).context("Failed to pass sockets to Pingora")?;
+
+    info!(count = fds.len(), "Handed socket-activated listeners to Pingora");
+    Ok(())
+}
+
+fn send_fds(stream: &UnixStream, payload: &[u8], fds: &[RawFd]) -> io::Result<()> {
+    let fds_len = std::mem::size_of_val(fds) as libc::c_uint;
+    let mut control = vec![0u8; unsafe { libc::CMSG_SPACE(fds_len) } as usize];
+    let mut iov = libc::iovec {
+        iov_base: payload.as_ptr() as *mut libc::c_void,
+        iov_len: payload.len(),
+    };
+
+    let mut msg: libc::msghdr = unsafe { std::mem::zeroed() };
+    msg.msg_iov = &mut iov;
+    msg.msg_iovlen = 1;
+    msg.msg_control = control.as_mut_ptr() as *mut libc::c_void;
+    msg.msg_controllen = control.len() as _;
+
+    unsafe {
+        let cmsg = libc::CMSG_FIRSTHDR(&msg);
+        (*cmsg).cmsg_level = libc::SOL_SOCKET;
+        (*cmsg).cmsg_type = libc::SCM_RIGHTS;
+        (*cmsg).cmsg_len = libc::CMSG_LEN(fds_len) as _;
+        std::ptr::copy_nonoverlapping(fds.as_ptr(), libc::CMSG_DATA(cmsg) as *mut RawFd, fds.len());
+
+        if libc::sendmsg(stream.as_raw_fd(), &msg, 0) < 0 {
+            return Err(io::Error::last_os_error());
+        }
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::os::unix::net::UnixListener;
+
+    #[test]
+    fn test_parse_fd_address() {
+        assert_eq!(parse_fd_address("fd:3"), Some(3));
+        assert_eq!(parse_fd_address("fd:x"), None);
+        assert_eq!(parse_fd_address(":8080"), None);
+    }
+
+    #[test]
+    fn test_adopt_listening_socket() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let adopted = adopt_fd(listener.as_raw_fd()).unwrap();
+
+        assert_eq!(adopted.fd, listener.as_raw_fd());
+        assert_eq!(adopted.addr, listener.local_addr().unwrap());
+    }
+
+    #[test]
+    fn test_reject_non_listening_socket() {
+        let udp = std::net::UdpSocket::bind("127.0.0.1:0").unwrap();
+        assert!(adopt_fd(udp.as_raw_fd()).is_err());
+
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let client = std::net::TcpStream::connect(listener.local_addr().unwrap()).unwrap();
+        assert!(adopt_fd(client.as_raw_fd()).is_err());
+    }
+
+    #[test]
+    fn test_handoff_passes_socket() {
+        let path = std::env::temp_dir().join(format!("avalon-upgrade-{}.sock", std::process::id()));
+        let _ = std::fs::remove_file(&path);
+        let receiver = UnixListener::bind(&path).unwrap();
+
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let adopted = adopt_fd(listener.as_raw_fd()).unwrap();
+        let expected = adopted.clone();
+        let send_path = path.clone();
+        let sender = std::thread::spawn(move || send_to_upgrade_socket(&send_path, &[adopted]));
+
+        let (stream, _) = receiver.accept().unwrap();
+        sender.join().unwrap().unwrap();
+
+        // Receive the payload and descriptor like Pingora does
+        let mut payload = [0u8; 256];
+        let mut iov = libc::iovec {
+            iov_base: payload.as_mut_ptr() as *mut libc::c_void,
+            iov_len: payload.len(),
+        };
+        let mut control = vec![0u8; unsafe { libc::CMSG_SPACE(std::mem::size_of::<RawFd>() as libc::c_uint) } as usize];
+        let mut msg: libc::msghdr = unsafe { std::mem::zeroed() };
+        msg.msg_iov = &mut iov;
+        msg.msg_iovlen = 1;
+        msg.msg_control = control.as_mut_ptr() as *mut libc::c_void;
+        msg.msg_controllen = control.len() as _;
+
+        let received = unsafe { libc::recvmsg(stream.as_raw_fd(), &mut msg, 0) };
+        assert!(received > 0);
+        assert_eq!(std::str::from_utf8(&payload[..received as usize]).unwrap(), expected.addr.to_string());
+
+        let fd = unsafe {
+            let cmsg = libc::CMSG_FIRSTHDR(&msg);
+            assert!(!cmsg.is_null());
+            *(libc::CMSG_DATA(cmsg) as *const RawFd)
+        };
+        let received_listener = unsafe { TcpListener::from_raw_fd(fd) };
+        assert_eq!(received_listener.local_addr().unwrap(), expected.addr);
+
+        let _ = std::fs::remove_file(&path);
+    }
+}
@@ -2,11 +2,13 @@
 //!
 //! Provides distributed tracing support via OpenTelemetry protocol (OTLP).
 //! When enabled, traces are exported to an OTLP collector (e.g., Jaeger, Tempo).
+//! Access logs targeting `"otlp"` are exported to the same endpoint.
 
-use config::TracingConfig;
+use config::{Config, TracingConfig};
 use opentelemetry::trace::TracerProvider;
 use opentelemetry_otlp::WithExportConfig;
 use opentelemetry_sdk::{
+    logs::LoggerProvider,
     trace::{Sampler, TracerProvider as SdkTracerProvider},
     Resource,
 };
@@ -80,6 +82,40 @@ pub fn init_telemetry(config: &TracingConfig) -> Option<SdkTracerProvider> {
     Some(provider)
 }
 
+/// Export access logs over OTLP when any access log targets `"otlp"`
+///
+/// Uses the tracing endpoint and service name, whether or not tracing itself
+/// is enabled. Must be called within a Tokio runtime.
+pub fn init_access_log_export(config: &Config) -> Option<LoggerProvider> {
+    if !config.uses_otlp_access_log() {
+        return None;
+    }
+
+    let tracing_config = &config.global.tracing;
+    let provider = match opentelemetry_otlp::new_pipeline()
+        .logging()
+        .with_resource(Resource::new(vec![
+            opentelemetry::KeyValue::new("service.name", tracing_config.service_name.clone()),
+        ]))
+        .with_exporter(
+            opentelemetry_otlp::new_exporter()
+                .tonic()
+                .with_endpoint(&tracing_config.otlp_endpoint),
+        )
+        .install_batch(opentelemetry_sdk::runtime::Tokio)
+    {
+        Ok(provider) => provider,
+        Err(e) => {
+            tracing::error!(error = %e, "Failed to create OTLP access log exporter");
+            return None;
+        }
+    };
+
+    proxy::otlp_log::install(&provider);
+    info!(endpoint = %tracing_config.otlp_endpoint, "Exporting access logs over OTLP");
+    Some(provider)
+}
+
 /// Shutdown OpenTelemetry and flush pending traces
 #[allow(dead_code)]
 pub fn shutdown_telemetry(provider: Option<SdkTracerProvider>) {
@@ -104,6 +140,12 @@ mod tests {
         assert!(provider.is_none());
     }
 
+    #[test]
+    fn test_access_log_export_off_without_otlp_target() {
+        let config = Config::default();
+        assert!(init_access_log_export(&config).is_none());
+    }
+
     #[test]
     fn test_config_defaults() {
         let config = TracingConfig::default();
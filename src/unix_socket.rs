@@ -0,0 +1,128 @@
+//! HTTP listeners on Unix domain sockets
+//!
+//! A `unix:/run/avalon.sock` listen address serves HTTP on a socket file, for
+//! a front proxy such as nginx or a sidecar on the same host. A file left
+//! behind by an earlier run that nothing accepts connections on is removed
+//! before binding, and the socket files are removed again when the listeners
+//! stop at shutdown (see `shutdown`); a graceful upgrade leaves them to the
+//! new process.
+
+use pingora_core::apps::ServerApp;
+use pingora_core::services::listening::Service;
+use std::io;
+use std::os::unix::fs::PermissionsExt;
+use std::os::unix::net::UnixStream;
+use std::path::{Path, PathBuf};
+use tracing::{info, warn};
+
+/// Listen on the socket file `path`, created with permissions `mode` when set
+pub fn add_unix_listener<A>(service: &mut Service<A>, path: &Path, mode: Option<u32>)
+where
+    A: ServerApp + Send + Sync + 'static,
+{
+    remove_stale_socket(path);
+    let permissions = mode.map(std::fs::Permissions::from_mode);
+    service.add_uds(&path.to_string_lossy(), permissions);
+}
+
+/// Remove a socket file no running server accepts connections on
+fn remove_stale_socket(path: &Path) {
+    if !path.exists() {
+        return;
+    }
+    match UnixStream::connect(path) {
+        // Binding fails and reports it
+        Ok(_) => warn!(path = ?path, "Unix socket is already in use"),
+        Err(e) if e.kind() == io::ErrorKind::ConnectionRefused => match std::fs::remove_file(path) {
+            Ok(()) => info!(path = ?path, "Removed stale Unix socket"),
+            Err(e) => warn!(path = ?path, error = %e, "Failed to remove stale Unix socket"),
+        },
+        Err(_) => {}
+    }
+}
+
+/// Remove the socket files of `unix:` listeners
+pub fn remove_socket_files(paths: &[PathBuf]) {
+    for path in paths {
+        match std::fs::remove_file(path) {
+            Ok(()) => info!(path = ?path, "Removed Unix socket"),
+            Err(e) if e.kind() == io::ErrorKind::NotFound => {}
+            Err(e) => warn!(path = ?path, error = %e, "Failed to remove Unix socket"),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use config::Config;
+    use pingora::prelude::*;
+    use proxy::AvalonProxy;
+    use std::io::{Read, Write};
+    use std::sync::Arc;
+    use std::time::{Duration, Instant};
+
+    #[test]
+    fn test_serve_request_over_unix_socket() {
+        let dir = std::env::temp_dir().join(format!("avalon-uds-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let socket = dir.join("avalon.sock");
+        let config_path = dir.join("avalon.toml");
+        let toml = format!(
+            r#"
+[tls]
+acme_enabled = false
+
+[[servers]]
+name = "sidecar"
+listen = ["unix:{}"]
+unix_socket_mode = 0o660
+
+[[servers.routes]]
+[servers.routes.handle]
+type = "static_response"
+body = "over uds"
+"#,
+            socket.display()
+        );
+        std::fs::write(&config_path, toml).unwrap();
+        let config = Config::load(&config_path).unwrap();
+
+        // A file left by an earlier run doesn't block binding
+        drop(std::os::unix::net::UnixListener::bind(&socket).unwrap());
+
+        let mut server = Server::new(None).unwrap();
+        server.bootstrap();
+        let proxy = AvalonProxy::new(config.clone(), Arc::new(Default::default())).unwrap();
+        let mut service = Service::new(
+            "Unix socket test".to_string(),
+            pingora_proxy::http_proxy(&server.configuration, proxy),
+        );
+        add_unix_listener(&mut service, &socket, config.servers[0].unix_socket_mode);
+        server.add_service(service);
+        std::thread::spawn(move || server.run_forever());
+
+        let deadline = Instant::now() + Duration::from_secs(10);
+        let mut stream = loop {
+            match UnixStream::connect(&socket) {
+                Ok(stream) => break stream,
+                Err(e) if Instant::now() > deadline => panic!("server never listened: {}", e),
+                Err(_) => std::thread::sleep(Duration::from_millis(50)),
+            }
+        };
+        stream
+            .write_all(b"GET / HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\n\r\n")
+            .unwrap();
+        let mut response = String::new();
+        stream.read_to_string(&mut response).unwrap();
+        assert!(response.starts_with("HTTP/1.1 200"), "{}", response);
+        assert!(response.ends_with("over uds"), "{}", response);
+
+        let mode = std::fs::metadata(&socket).unwrap().permissions().mode() & 0o777;
+        assert_eq!(mode, 0o660);
+
+        remove_socket_files(&[socket.clone()]);
+        assert!(!socket.exists());
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+}